@@ -16,10 +16,26 @@ fn main() {
     env::var("NSS_CACERT_STORE_PATH").expect("NSS_CACERT_STORE_PATH must be set");
     env::var("NIX_VERSION").expect("NIX_VERSION must be set");
 
+    // Metadata written beside the tarball by flake.nix, reported by `nix-installer info`, empty if
+    // the tarball came from elsewhere
+    let tarball_dir = Path::new(&tarball_path)
+        .parent()
+        .expect("NIX_TARBALL_PATH must be a file");
+    let sha256_path = tarball_dir.join("nix.tar.zst.sha256");
+    let sha256 = std::fs::read_to_string(&sha256_path).unwrap_or_default();
+    let sha256 = sha256.split_whitespace().next().unwrap_or_default();
+    println!("cargo:rustc-env=NIX_TARBALL_SHA256={sha256}");
+    let features_path = tarball_dir.join("experimental-features");
+    let features = std::fs::read_to_string(&features_path).unwrap_or_default();
+    let features = features.split_whitespace().collect::<Vec<_>>().join(",");
+    println!("cargo:rustc-env=NIX_EXPERIMENTAL_FEATURES={features}");
+
     // Tell cargo to rerun if any of these change
     println!("cargo:rerun-if-env-changed=NIX_TARBALL_PATH");
     println!("cargo:rerun-if-env-changed=NIX_STORE_PATH");
     println!("cargo:rerun-if-env-changed=NSS_CACERT_STORE_PATH");
     println!("cargo:rerun-if-env-changed=NIX_VERSION");
     println!("cargo:rerun-if-changed={tarball_path}");
+    println!("cargo:rerun-if-changed={}", sha256_path.display());
+    println!("cargo:rerun-if-changed={}", features_path.display());
 }
@@ -50,9 +50,12 @@
       # Build the nix binary tarball and recompress with zstd
       # This is similar to nix's packaging/binary-tarball.nix but outputs zstd
       nixTarballZstd =
-        { pkgs, system }:
+        {
+          pkgs,
+          system,
+          nixPkg ? nix.packages.${system}.nix,
+        }:
         let
-          nixPkg = nix.packages.${system}.nix;
           cacertPkg = pkgs.cacert;
           installerClosureInfo = pkgs.buildPackages.closureInfo {
             rootPaths = [
@@ -63,7 +66,10 @@
         in
         pkgs.runCommand "nix-tarball-zstd-${nixPkg.version}"
           {
-            nativeBuildInputs = [ pkgs.zstd ];
+            nativeBuildInputs = [
+              pkgs.zstd
+              pkgs.jq
+            ];
             # Export these so they can be read without IFD
             passthru = {
               inherit nixPkg cacertPkg;
@@ -93,20 +99,26 @@
               $TMPDIR/reginfo \
               $(cat ${installerClosureInfo}/store-paths) \
               | zstd -19 -T0 -o $out/nix.tar.zst
+
+            # Reported by `nix-installer info`
+            sha256sum $out/nix.tar.zst | cut -d' ' -f1 > $out/nix.tar.zst.sha256
+            ${nixPkg}/bin/nix __dump-xp-features | jq -r 'keys[]' > $out/experimental-features \
+              || touch $out/experimental-features
           '';
 
-      # Shared crane build setup - returns { package, clippy, cargoArtifacts }
+      # Shared crane build setup - returns { package, clippy, clippy-all-features, cargoArtifacts }
       mkCraneBuilds =
         {
           pkgs,
           stdenv,
           buildPackages,
           extraRustFlags ? "",
+          nixPkg ? nix.packages.${stdenv.hostPlatform.system}.nix,
         }:
         let
           craneLib = crane.mkLib pkgs;
           tarballPkg = nixTarballZstd {
-            inherit pkgs;
+            inherit pkgs nixPkg;
             system = stdenv.hostPlatform.system;
           };
           # Get paths directly from passthru - no IFD!
@@ -175,6 +187,16 @@
               cargoClippyExtraArgs = "--all-targets -- -D warnings";
             }
           );
+
+          # The `tui` and `async` features are off by default, so are only built here
+          clippy-all-features = craneLib.cargoClippy (
+            sharedAttrs
+            // {
+              inherit cargoArtifacts;
+              pname = "nix-installer-all-features";
+              cargoClippyExtraArgs = "--all-targets --all-features -- -D warnings";
+            }
+          );
         };
 
       installerPackage =
@@ -183,6 +205,7 @@
           stdenv,
           buildPackages,
           extraRustFlags ? "",
+          nixPkg ? nix.packages.${stdenv.hostPlatform.system}.nix,
         }:
         (mkCraneBuilds {
           inherit
@@ -190,6 +213,7 @@
             stdenv
             buildPackages
             extraRustFlags
+            nixPkg
             ;
         }).package;
     in
@@ -288,7 +312,7 @@
               touch $out
             '';
 
-          inherit (craneBuilds) clippy;
+          inherit (craneBuilds) clippy clippy-all-features;
         }
       );
 
@@ -300,6 +324,18 @@
         // nixpkgs.lib.optionalAttrs (system == "x86_64-linux") {
           inherit (pkgs) nix-installer-static;
           default = pkgs.nix-installer-static;
+
+          # 32-bit ARM (such as the Raspberry Pi) is cross compiled, with Nix's own cross build of Nix
+          nix-installer-armv7l-linux =
+            pkgs.pkgsCross.armv7l-hf-multiplatform.pkgsStatic.callPackage installerPackage
+              {
+                nixPkg = nix.packages.${system}."nix-everything-armv7l-unknown-linux-gnueabihf";
+              };
+          nix-installer-armv6l-linux =
+            pkgs.pkgsCross.raspberryPi.pkgsStatic.callPackage installerPackage
+              {
+                nixPkg = nix.packages.${system}."nix-everything-armv6l-unknown-linux-gnueabihf";
+              };
         }
         // nixpkgs.lib.optionalAttrs (system == "aarch64-linux") {
           inherit (pkgs) nix-installer-static;
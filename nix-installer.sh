@@ -200,7 +200,7 @@ get_architecture() {
 
   local _current_exe
   case "$_ostype" in
-  Linux)
+  Linux | Android)
     _current_exe=$(get_current_exe)
     _ostype=linux
     ;;
@@ -221,6 +221,14 @@ get_architecture() {
     _cputype=aarch64
     ;;
 
+  armv7l | armv8l)
+    _cputype=armv7l
+    ;;
+
+  armv6l)
+    _cputype=armv6l
+    ;;
+
   x86_64 | x86-64 | x64 | amd64)
     _cputype=x86_64
     ;;
@@ -6,8 +6,8 @@ use target_lexicon::OperatingSystem;
 use tracing::{Span, span};
 
 use crate::action::{ActionError, ActionErrorKind};
-use crate::execute_command;
-use crate::util::which;
+use crate::os::probe;
+use crate::os::users::user_backend;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
 
@@ -38,19 +38,7 @@ impl AddUserToGroup {
             gid,
         };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => (),
-            _ => {
-                if !(which("addgroup").is_some() || which("gpasswd").is_some()) {
-                    return Err(Self::error(ActionErrorKind::MissingAddUserToGroupCommand));
-                }
-                if !(which("delgroup").is_some() || which("gpasswd").is_some()) {
-                    return Err(Self::error(
-                        ActionErrorKind::MissingRemoveUserFromGroupCommand,
-                    ));
-                }
-            },
-        }
+        let backend = user_backend().map_err(Self::error)?;
 
         // Ensure user does not exists
         if let Some(user) = User::from_name(name.as_str())
@@ -87,7 +75,6 @@ impl AddUserToGroup {
                         .output()
                         .map_err(|e| ActionErrorKind::command(&command, e))
                         .map_err(Self::error)?;
-                    let stderr = String::from_utf8_lossy(&output.stderr);
                     match output.status.code() {
                         Some(0) => {
                             // yes {user} is a member of {groupname}
@@ -100,8 +87,13 @@ impl AddUserToGroup {
                             return Ok(StatefulAction::completed(this));
                         },
                         // 64 is the exit code for "Group not found" or "Unable to find the user
-                        // record", so we have to disambiguate by checking stderr for this string
-                        Some(64) if stderr.contains("Group not found") => {
+                        // record", so we have to disambiguate by looking the group up, as the
+                        // message is translated
+                        Some(64)
+                            if probe::user_in_group(&this.name, &this.groupname)
+                                .map_err(Self::error)?
+                                .is_none() =>
+                        {
                             tracing::trace!(
                                 "Will add user `{}` to newly created group `{}`",
                                 this.name,
@@ -129,16 +121,10 @@ impl AddUserToGroup {
                     };
                 },
                 _ => {
-                    let output = execute_command(
-                        Command::new("groups")
-                            .arg(&this.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                    let output_str = String::from_utf8(output.stdout).map_err(Self::error)?;
-                    let user_in_group = output_str.split(' ').any(|v| v == this.groupname);
+                    let user_in_group =
+                        probe::user_in_group(&this.name, &this.groupname).map_err(Self::error)?;
 
-                    if user_in_group {
+                    if user_in_group == Some(true) {
                         tracing::debug!(
                             "Adding user `{}` to group `{}` already complete",
                             this.name,
@@ -149,6 +135,12 @@ impl AddUserToGroup {
                 },
             }
         }
+        backend
+            .check_creatable(&format!(
+                "Membership of `{}` in group `{}`",
+                this.name, this.groupname
+            ))
+            .map_err(Self::error)?;
 
         Ok(StatefulAction::uncompleted(this))
     }
@@ -188,54 +180,9 @@ impl Action for AddUserToGroup {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        use target_lexicon::OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .args([
-                            ".",
-                            "-append",
-                            &format!("/Groups/{}", self.groupname),
-                            "GroupMembership",
-                        ])
-                        .arg(&self.name)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .map_err(Self::error)?;
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .args(["-o", "edit"])
-                        .arg("-a")
-                        .arg(&self.name)
-                        .arg(&self.groupname)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which("gpasswd").is_some() {
-                    execute_command(
-                        Command::new("gpasswd")
-                            .args(["-a"])
-                            .args([&self.name, &self.groupname])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else if which("addgroup").is_some() {
-                    execute_command(
-                        Command::new("addgroup")
-                            .args([&self.name, &self.groupname])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(Self::error(
-                        ActionErrorKind::MissingAddUserToGroupCommand,
-                    )));
-                }
-            },
-        }
+        user_backend()
+            .and_then(|backend| backend.add_user_to_group(&self.name, &self.groupname))
+            .map_err(Self::error)?;
 
         Ok(())
     }
@@ -261,40 +208,9 @@ impl Action for AddUserToGroup {
             gid: _,
         } = self;
 
-        use target_lexicon::OperatingSystem;
-        match target_lexicon::OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .args([".", "-delete", &format!("/Groups/{groupname}"), "users"])
-                        .arg(name)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which("gpasswd").is_some() {
-                    execute_command(
-                        Command::new("gpasswd")
-                            .args(["-d"])
-                            .args([&name.to_string(), &groupname.to_string()])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else if which("delgroup").is_some() {
-                    execute_command(
-                        Command::new("delgroup")
-                            .args([name, groupname])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(
-                        ActionErrorKind::MissingRemoveUserFromGroupCommand,
-                    ));
-                }
-            },
-        };
+        user_backend()
+            .and_then(|backend| backend.remove_user_from_group(name, groupname))
+            .map_err(Self::error)?;
 
         Ok(())
     }
@@ -287,7 +287,7 @@ impl Action for CreateDirectory {
 }
 
 // There are cleaner ways of doing this (eg `systemctl status $PATH`) however we need a widely supported way.
-fn path_is_mountpoint(path: &Path) -> Result<bool, ActionErrorKind> {
+pub(crate) fn path_is_mountpoint(path: &Path) -> Result<bool, ActionErrorKind> {
     let path_str = match path.to_str() {
         Some(path_str) => path_str,
         None => return Err(ActionErrorKind::PathNoneString(path.to_path_buf())),
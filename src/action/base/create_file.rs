@@ -2,15 +2,15 @@ use nix::unistd::{Group, User, chown};
 use tracing::{Span, span};
 
 use std::{
-    fs::{File, OpenOptions},
-    io::{Read, Write},
-    os::{unix::fs::MetadataExt, unix::fs::OpenOptionsExt, unix::fs::PermissionsExt},
+    fs::File,
+    io::Read,
+    os::{unix::fs::MetadataExt, unix::fs::PermissionsExt},
     path::{Path, PathBuf},
 };
 
 use crate::{
     action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
-    util::OnMissing,
+    util::{DurableFile, OnMissing},
 };
 
 /** Create a file at the given location with the provided `buf`,
@@ -178,21 +178,8 @@ impl Action for CreateFile {
             span.record("buf", &self.buf);
         }
 
-        let mut options = OpenOptions::new();
-        options.create_new(true).write(true).read(true);
-
-        if let Some(mode) = self.mode {
-            options.mode(mode);
-        }
-
-        let mut file = options
-            .open(&self.path)
-            .map_err(|e| ActionErrorKind::Open(self.path.to_owned(), e))
-            .map_err(Self::error)?;
-
-        file.write_all(self.buf.as_bytes())
-            .map_err(|e| ActionErrorKind::Write(self.path.to_owned(), e))
-            .map_err(Self::error)?;
+        let mut file = DurableFile::create(&self.path).map_err(Self::error)?;
+        file.write_all(self.buf.as_bytes()).map_err(Self::error)?;
 
         let gid = if let Some(ref group) = self.group {
             Some(
@@ -218,9 +205,17 @@ impl Action for CreateFile {
         } else {
             None
         };
-        chown(&self.path, uid, gid)
+        // Change ownership _before_ applying mode, to ensure that if
+        // a file needs to be setuid it will never be setuid for the
+        // wrong user
+        chown(file.temp_path(), uid, gid)
             .map_err(|e| ActionErrorKind::Chown(self.path.clone(), e))
             .map_err(Self::error)?;
+        file.set_mode(self.mode.unwrap_or(0o644))
+            .map_err(Self::error)?;
+
+        // Fails if something else created the file since planning, like `create_new` would
+        file.persist_new().map_err(Self::error)?;
 
         Ok(())
     }
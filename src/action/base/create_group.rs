@@ -1,11 +1,8 @@
 use nix::unistd::Group;
-use std::process::Command;
-use target_lexicon::OperatingSystem;
 use tracing::{Span, span};
 
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
-use crate::execute_command;
-use crate::util::which;
+use crate::os::users::user_backend;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
 
@@ -27,17 +24,7 @@ impl CreateGroup {
             gid,
         };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => (),
-            _ => {
-                if !(which("groupadd").is_some() || which("addgroup").is_some()) {
-                    return Err(Self::error(ActionErrorKind::MissingGroupCreationCommand));
-                }
-                if !(which("groupdel").is_some() || which("delgroup").is_some()) {
-                    return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
-                }
-            },
-        }
+        let backend = user_backend().map_err(Self::error)?;
 
         // Ensure group does not exists
         if let Some(group) = Group::from_name(name.as_str())
@@ -55,6 +42,10 @@ impl CreateGroup {
             tracing::debug!("Creating group `{}` already complete", this.name);
             return Ok(StatefulAction::completed(this));
         }
+        backend
+            .check_creatable(&format!("Group `{name}` (GID {gid})"))
+            .map_err(Self::error)?;
+
         Ok(StatefulAction::uncompleted(this))
     }
 }
@@ -90,44 +81,9 @@ impl Action for CreateGroup {
     fn execute(&mut self) -> Result<(), ActionError> {
         let Self { name, gid } = self;
 
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .args([
-                            "-o",
-                            "create",
-                            "-r",
-                            "Nix build group for nix-daemon",
-                            "-i",
-                            &format!("{gid}"),
-                            name,
-                        ])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which("groupadd").is_some() {
-                    execute_command(
-                        Command::new("groupadd")
-                            .args(["-g", &gid.to_string(), "--system", name])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else if which("addgroup").is_some() {
-                    execute_command(
-                        Command::new("addgroup")
-                            .args(["-g", &gid.to_string(), "--system", name])
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingGroupCreationCommand));
-                }
-            },
-        };
+        user_backend()
+            .and_then(|backend| backend.create_group(name, *gid))
+            .map_err(Self::error)?;
 
         Ok(())
     }
@@ -146,36 +102,9 @@ impl Action for CreateGroup {
     fn revert(&mut self) -> Result<(), ActionError> {
         let Self { name, gid: _ } = self;
 
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .args([".", "-delete", &format!("/Groups/{name}")])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if which("groupdel").is_some() {
-                    execute_command(
-                        Command::new("groupdel")
-                            .arg(name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else if which("delgroup").is_some() {
-                    execute_command(
-                        Command::new("delgroup")
-                            .arg(name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
-                }
-            },
-        };
+        user_backend()
+            .and_then(|backend| backend.delete_group(name))
+            .map_err(Self::error)?;
 
         Ok(())
     }
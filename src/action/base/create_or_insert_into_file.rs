@@ -2,13 +2,12 @@ use nix::unistd::{Group, User, chown};
 
 use crate::{
     action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
-    util::OnMissing,
+    util::{DurableFile, OnMissing},
 };
-use rand::Rng;
 use std::{
     fs::{File, OpenOptions},
-    io::{Read, Seek, SeekFrom, Write},
-    os::{unix::fs::MetadataExt, unix::fs::OpenOptionsExt, unix::prelude::PermissionsExt},
+    io::Read,
+    os::{unix::fs::MetadataExt, unix::prelude::PermissionsExt},
     path::{Path, PathBuf},
 };
 use tracing::{Span, span};
@@ -192,9 +191,6 @@ impl Action for CreateOrInsertIntoFile {
             Err(e) => return Err(Self::error(ActionErrorKind::Open(path.to_owned(), e))),
         };
 
-        // Create a temporary file in the same directory as the one
-        // that the final file goes in, so that we can rename it
-        // atomically
         let parent_dir = path.parent().expect("File must be in a directory");
         if !parent_dir.exists() {
             std::fs::create_dir_all(parent_dir)
@@ -202,49 +198,20 @@ impl Action for CreateOrInsertIntoFile {
                 .map_err(Self::error)?;
         }
 
-        let mut temp_file_path = parent_dir.to_owned();
-        {
-            let mut rng = rand::rng();
-            temp_file_path.push(format!("nix-installer-tmp.{}", rng.random::<u32>()));
-        }
-        let mut temp_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            // If the file is created, ensure that it has harmless
-            // permissions regardless of whether the mode will be
-            // changed later (if we ever create setuid executables,
-            // they should only become setuid once they are owned by
-            // the appropriate user)
-            .mode(0o600)
-            .open(&temp_file_path)
-
-            .map_err(|e| {
-                ActionErrorKind::Open(temp_file_path.clone(), e)
-            }).map_err(Self::error)?;
+        // Write to a temporary file beside the final one, so it is replaced atomically
+        let mut temp_file = DurableFile::create(path).map_err(Self::error)?;
 
         if *position == Position::End {
             if let Some(ref mut orig_file) = orig_file {
-                std::io::copy(orig_file, &mut temp_file)
-                    .map_err(|e| {
-                        ActionErrorKind::Copy(path.to_owned(), temp_file_path.to_owned(), e)
-                    })
-                    .map_err(Self::error)?;
+                temp_file.copy_from(orig_file).map_err(Self::error)?;
             }
         }
 
-        temp_file
-            .write_all(buf.as_bytes())
-            .map_err(|e| ActionErrorKind::Write(temp_file_path.clone(), e))
-            .map_err(Self::error)?;
+        temp_file.write_all(buf.as_bytes()).map_err(Self::error)?;
 
         if *position == Position::Beginning {
             if let Some(ref mut orig_file) = orig_file {
-                std::io::copy(orig_file, &mut temp_file)
-                    .map_err(|e| {
-                        ActionErrorKind::Copy(path.to_owned(), temp_file_path.to_owned(), e)
-                    })
-                    .map_err(Self::error)?;
+                temp_file.copy_from(orig_file).map_err(Self::error)?;
             }
         }
 
@@ -276,14 +243,12 @@ impl Action for CreateOrInsertIntoFile {
         // Change ownership _before_ applying mode, to ensure that if
         // a file needs to be setuid it will never be setuid for the
         // wrong user
-        chown(&temp_file_path, uid, gid)
+        chown(temp_file.temp_path(), uid, gid)
             .map_err(|e| ActionErrorKind::Chown(path.clone(), e))
             .map_err(Self::error)?;
 
         if let Some(mode) = mode {
-            std::fs::set_permissions(&temp_file_path, PermissionsExt::from_mode(*mode))
-                .map_err(|e| ActionErrorKind::SetPermissions(*mode, path.to_owned(), e))
-                .map_err(Self::error)?;
+            temp_file.set_mode(*mode).map_err(Self::error)?;
         } else if let Some(original_file) = orig_file {
             let original_file_mode = original_file
                 .metadata()
@@ -291,17 +256,12 @@ impl Action for CreateOrInsertIntoFile {
                 .map_err(Self::error)?
                 .permissions()
                 .mode();
-            std::fs::set_permissions(
-                &temp_file_path,
-                PermissionsExt::from_mode(original_file_mode),
-            )
-            .map_err(|e| ActionErrorKind::SetPermissions(original_file_mode, path.to_owned(), e))
-            .map_err(Self::error)?;
+            temp_file
+                .set_mode(original_file_mode)
+                .map_err(Self::error)?;
         }
 
-        std::fs::rename(&temp_file_path, &path)
-            .map_err(|e| ActionErrorKind::Rename(path.to_owned(), temp_file_path.to_owned(), e))
-            .map_err(Self::error)?;
+        temp_file.persist().map_err(Self::error)?;
 
         Ok(())
     }
@@ -340,8 +300,6 @@ impl Action for CreateOrInsertIntoFile {
         }
 
         let mut file = OpenOptions::new()
-            .create(false)
-            .write(true)
             .read(true)
             .open(&path)
             .map_err(|e| ActionErrorKind::Open(path.to_owned(), e))
@@ -362,18 +320,7 @@ impl Action for CreateOrInsertIntoFile {
                 .map_err(|e| ActionErrorKind::Remove(path.to_owned(), e))
                 .map_err(Self::error)?;
         } else {
-            file.seek(SeekFrom::Start(0))
-                .map_err(|e| ActionErrorKind::Seek(path.to_owned(), e))
-                .map_err(Self::error)?;
-            file.set_len(0)
-                .map_err(|e| ActionErrorKind::Truncate(path.to_owned(), e))
-                .map_err(Self::error)?;
-            file.write_all(file_contents.as_bytes())
-                .map_err(|e| ActionErrorKind::Write(path.to_owned(), e))
-                .map_err(Self::error)?;
-            file.flush()
-                .map_err(|e| ActionErrorKind::Flush(path.to_owned(), e))
-                .map_err(Self::error)?;
+            crate::util::write_atomic(path, file_contents).map_err(Self::error)?;
         }
         Ok(())
     }
@@ -1,27 +1,19 @@
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
-use std::{
-    os::unix::fs::PermissionsExt,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use nix_config_parser::NixConfig;
-use rand::Rng;
 use tracing::{Span, span};
 
 use crate::{
     action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
-    util::OnMissing,
+    backup::FileBackup,
+    nix_conf::NixConfValue,
+    sensitive,
+    util::{DurableFile, OnMissing},
 };
 
 pub(crate) const TRUSTED_USERS_CONF_NAME: &str = "trusted-users";
 pub(crate) const EXPERIMENTAL_FEATURES_CONF_NAME: &str = "experimental-features";
 pub(crate) const EXTRA_EXPERIMENTAL_FEATURES_CONF_NAME: &str = "extra-experimental-features";
-/// The `nix.conf` configuration names that are safe to merge.
-// FIXME(@cole-h): make configurable by downstream users?
-// NOTE(cole-h): evaluate if any additions here need to be handled in PlaceNixConfiguration::setup_extra_config
-const MERGEABLE_CONF_NAMES: &[&str] = &[EXPERIMENTAL_FEATURES_CONF_NAME];
 const NIX_CONF_MODE: u32 = 0o644;
 const NIX_CONF_COMMENT_CHAR: char = '#';
 
@@ -50,13 +42,19 @@ impl From<CreateOrMergeNixConfigError> for ActionErrorKind {
 }
 
 /// Create or merge an existing `nix.conf` at the specified path.
+///
+/// Secret settings, such as `access-tokens`, are redacted in receipts and logs. An existing file
+/// is backed up before it is rewritten, and restored when reverting.
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_or_merge_nix_config")]
 pub struct CreateOrMergeNixConfig {
     pub(crate) path: PathBuf,
+    #[serde(serialize_with = "serialize_redacted_nix_config")]
     pending_nix_config: NixConfig,
     header: String,
     footer: Option<String>,
+    #[serde(default)]
+    backup: Option<FileBackup>,
 }
 
 impl CreateOrMergeNixConfig {
@@ -74,6 +72,7 @@ impl CreateOrMergeNixConfig {
             pending_nix_config,
             header,
             footer,
+            backup: None,
         };
 
         if this.path.exists() {
@@ -100,6 +99,22 @@ impl CreateOrMergeNixConfig {
         Ok(StatefulAction::uncompleted(this))
     }
 
+    /// The pending settings as `name="value"`, with secrets redacted
+    fn describe_pending(&self, separator: &str) -> String {
+        self.pending_nix_config
+            .settings()
+            .iter()
+            .map(|(k, v)| {
+                if sensitive::is_sensitive_setting(k, v) {
+                    format!("{k}=\"{}\"", sensitive::REDACTED)
+                } else {
+                    format!("{k}=\"{v}\"")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
     fn merge_pending_and_existing_nix_config(
         pending_nix_config: &NixConfig,
         existing_nix_config: &NixConfig,
@@ -111,29 +126,22 @@ impl CreateOrMergeNixConfig {
         for (pending_conf_name, pending_conf_value) in pending_nix_config.settings() {
             if let Some(existing_conf_value) = existing_nix_config.settings().get(pending_conf_name)
             {
-                let pending_conf_value = pending_conf_value.split(' ').collect::<Vec<_>>();
-                let existing_conf_value = existing_conf_value.split(' ').collect::<Vec<_>>();
+                let pending_conf_value = NixConfValue::parse(pending_conf_name, pending_conf_value);
+                let existing_conf_value =
+                    NixConfValue::parse(pending_conf_name, existing_conf_value);
 
-                if pending_conf_value
-                    .iter()
-                    .all(|e| existing_conf_value.contains(e))
-                {
+                if existing_conf_value.contains(&pending_conf_value) {
                     // If _all_ the values we want are present in the existing config,
                     // merged_nix_config will be empty and this will be marked as completed. We
                     // don't return early here because there may be more config options to
                     // check.
-                } else if MERGEABLE_CONF_NAMES.contains(&pending_conf_name.as_str()) {
-                    let mut merged_conf_value =
-                        Vec::with_capacity(pending_conf_value.len() + existing_conf_value.len());
-                    merged_conf_value.extend(pending_conf_value);
-                    merged_conf_value.extend(existing_conf_value);
-                    merged_conf_value.dedup();
-                    let merged_conf_value = merged_conf_value.join(" ");
-                    let merged_conf_value = merged_conf_value.trim();
-
+                } else if let Some(merged_conf_value) =
+                    pending_conf_value.merge(&existing_conf_value)
+                {
+                    // Lists (like `experimental-features` or `substituters`) are merged as sets
                     merged_nix_config
                         .settings_mut()
-                        .insert(pending_conf_name.to_owned(), merged_conf_value.to_owned());
+                        .insert(pending_conf_name.to_owned(), merged_conf_value.to_string());
                 } else {
                     unmergeable_config_names.push(pending_conf_name.to_owned());
                 }
@@ -217,12 +225,25 @@ impl CreateOrMergeNixConfig {
             }
         };
 
-        crate::util::write_atomic(path, &lines.join("\n")).map_err(Self::error)?;
+        crate::util::write_atomic(path, lines.join("\n")).map_err(Self::error)?;
 
         Ok(parse_ret)
     }
 }
 
+fn serialize_redacted_nix_config<S: serde::Serializer>(
+    nix_config: &NixConfig,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut redacted = nix_config.clone();
+    for (name, value) in redacted.settings_mut().iter_mut() {
+        if sensitive::is_sensitive_setting(name, value) {
+            *value = sensitive::REDACTED.to_string();
+        }
+    }
+    serde::Serialize::serialize(&redacted, serializer)
+}
+
 #[typetag::serde(name = "create_or_merge_nix_config")]
 impl Action for CreateOrMergeNixConfig {
     fn action_tag() -> ActionTag {
@@ -245,15 +266,7 @@ impl Action for CreateOrMergeNixConfig {
         );
 
         if tracing::enabled!(tracing::Level::TRACE) {
-            span.record(
-                "pending_nix_config",
-                self.pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}=\"{v}\""))
-                    .collect::<Vec<_>>()
-                    .join(","),
-            );
+            span.record("pending_nix_config", self.describe_pending(","));
         }
         span
     }
@@ -263,13 +276,7 @@ impl Action for CreateOrMergeNixConfig {
             self.tracing_synopsis(),
             vec![format!(
                 "Added settings: {settings}",
-                settings = self
-                    .pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}=\"{v}\""))
-                    .collect::<Vec<_>>()
-                    .join(", "),
+                settings = self.describe_pending(", "),
             )],
         )]
     }
@@ -278,41 +285,24 @@ impl Action for CreateOrMergeNixConfig {
     fn execute(&mut self) -> Result<(), ActionError> {
         if tracing::enabled!(tracing::Level::TRACE) {
             let span = tracing::Span::current();
-            span.record(
-                "pending_nix_config",
-                self.pending_nix_config
-                    .settings()
-                    .iter()
-                    .map(|(k, v)| format!("{k}='{v}'"))
-                    .collect::<Vec<_>>()
-                    .join(" "),
-            );
+            span.record("pending_nix_config", self.describe_pending(" "));
         }
 
-        // Create a temporary file in the same directory as the one
-        // that the final file goes in, so that we can rename it
-        // atomically
-        let parent_dir = self.path.parent().expect("File must be in a directory");
-        let mut temp_file_path = parent_dir.to_owned();
+        if let Some((name, _)) = self
+            .pending_nix_config
+            .settings()
+            .iter()
+            .find(|(_, value)| value.as_str() == sensitive::REDACTED)
         {
-            let mut rng = rand::rng();
-            temp_file_path.push(format!("nix-installer-tmp.{}", rng.random::<u32>()));
+            return Err(Self::error(ActionErrorKind::RedactedSecret(name.clone())));
+        }
+
+        if self.backup.is_none() {
+            self.backup = FileBackup::create(&self.path).map_err(Self::error)?;
         }
-        let mut temp_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            // If the file is created, ensure that it has harmless
-            // permissions regardless of whether the mode will be
-            // changed later (if we ever create setuid executables,
-            // they should only become setuid once they are owned by
-            // the appropriate user)
-            .mode(0o600)
-            .open(&temp_file_path)
-
-            .map_err(|e| {
-                Self::error(ActionErrorKind::Open(temp_file_path.clone(), e))
-            })?;
+
+        // Write to a temporary file beside the final one, so it is replaced atomically
+        let mut temp_file = DurableFile::create(&self.path).map_err(Self::error)?;
 
         let (mut merged_nix_config, mut existing_nix_config) = if self.path.exists() {
             let (merged_nix_config, existing_nix_config) = Self::validate_nix_config_against_path(
@@ -472,33 +462,21 @@ impl Action for CreateOrMergeNixConfig {
 
         temp_file
             .write_all(new_config.as_bytes())
-            .map_err(|e| Self::error(ActionErrorKind::Write(temp_file_path.clone(), e)))?;
-        std::fs::set_permissions(&temp_file_path, PermissionsExt::from_mode(NIX_CONF_MODE))
-            .map_err(|e| {
-                Self::error(ActionErrorKind::SetPermissions(
-                    NIX_CONF_MODE,
-                    self.path.to_owned(),
-                    e,
-                ))
-            })?;
-        temp_file
-            .sync_all()
-            .map_err(|e| Self::error(ActionErrorKind::Sync(temp_file_path.clone(), e)))?;
-        std::fs::rename(&temp_file_path, &self.path).map_err(|e| {
-            Self::error(ActionErrorKind::Rename(
-                temp_file_path.to_owned(),
-                self.path.to_owned(),
-                e,
-            ))
-        })?;
+            .map_err(Self::error)?;
+        temp_file.set_mode(NIX_CONF_MODE).map_err(Self::error)?;
+        temp_file.persist().map_err(Self::error)?;
 
         Ok(())
     }
 
     fn revert_description(&self) -> Vec<ActionDescription> {
+        let explanation = match &self.backup {
+            Some(backup) => format!("Restore the original from `{}`", backup.backup.display()),
+            None => format!("Delete file `{}`", self.path.display()),
+        };
         vec![ActionDescription::new(
             format!("Delete file `{}`", self.path.display()),
-            vec![format!("Delete file `{}`", self.path.display())],
+            vec![explanation],
         )]
     }
 
@@ -506,6 +484,9 @@ impl Action for CreateOrMergeNixConfig {
     fn revert(&mut self) -> Result<(), ActionError> {
         crate::util::remove_file(&self.path, OnMissing::Ignore)
             .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.to_owned(), e)))?;
+        if let Some(backup) = self.backup.take() {
+            backup.restore().map_err(Self::error)?;
+        }
 
         Ok(())
     }
@@ -516,6 +497,7 @@ mod test {
     use super::*;
     use color_eyre::eyre::eyre;
     use std::fs::write;
+    use std::os::unix::fs::PermissionsExt;
 
     #[test]
     fn creates_and_deletes_file() -> eyre::Result<()> {
@@ -548,6 +530,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn redacts_secrets_in_the_receipt() -> eyre::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let test_file = temp_dir.path().join("redacts_secrets_in_the_receipt");
+        let mut nix_config = NixConfig::new();
+        nix_config
+            .settings_mut()
+            .insert("access-tokens".into(), "github.com=ghp_secret".into());
+        nix_config
+            .settings_mut()
+            .insert("sandbox".into(), "true".into());
+        let mut action = CreateOrMergeNixConfig::plan(
+            &test_file,
+            nix_config,
+            "# Generated by".to_string(),
+            None,
+        )?;
+
+        let receipt = serde_json::to_string(&action)?;
+        assert!(!receipt.contains("ghp_secret"));
+        assert!(!format!("{:?}", action.describe_execute()).contains("ghp_secret"));
+
+        let mut read_back: StatefulAction<CreateOrMergeNixConfig> = serde_json::from_str(&receipt)?;
+        assert!(read_back.try_execute().is_err());
+        assert!(!test_file.exists());
+
+        action.try_execute()?;
+        assert!(std::fs::read_to_string(&test_file)?.contains("ghp_secret"));
+
+        Ok(())
+    }
+
     #[test]
     fn creates_and_deletes_file_even_if_edited() -> eyre::Result<()> {
         let temp_dir = tempfile::TempDir::new()?;
@@ -651,7 +665,11 @@ mod test {
 
         action.try_revert()?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            "experimental-features = flakes\nwarn-dirty = true\n",
+            "The original file should have been restored"
+        );
 
         Ok(())
     }
@@ -738,7 +756,11 @@ mod test {
 
         action.try_revert()?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            "# test 2\n# test\nexperimental-features = flakes # some inline comment about experimental-features\n# the following line should be warn-dirty = true\nwarn-dirty = true # this is an inline comment\n# this is an ungrouped comment\n# this too",
+            "The original file should have been restored"
+        );
 
         Ok(())
     }
@@ -771,7 +793,11 @@ mod test {
 
         action.try_revert()?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            " a = b\n c = d# lol\n# e = f",
+            "The original file should have been restored"
+        );
 
         Ok(())
     }
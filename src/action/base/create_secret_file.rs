@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::backup::FileBackup;
+use crate::sensitive::SensitiveString;
+use crate::util::{DurableFile, OnMissing};
+
+/**
+Create a file holding a secret, such as credentials
+
+Unlike [`CreateFile`](crate::action::base::CreateFile), the content is a [`SensitiveString`], so it
+is redacted in the receipt and logs. Uninstalling only needs the path, but a plan read back from a
+receipt cannot be executed again. A different file replaced with `force` is backed up, and restored
+when reverting.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_secret_file")]
+pub struct CreateSecretFile {
+    pub(crate) path: PathBuf,
+    mode: u32,
+    content: SensitiveString,
+    #[serde(default)]
+    backup: Option<FileBackup>,
+}
+
+impl CreateSecretFile {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        path: impl AsRef<Path>,
+        mode: u32,
+        content: SensitiveString,
+        force: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            path: path.as_ref().to_path_buf(),
+            mode,
+            content,
+            backup: None,
+        };
+
+        if this.path.exists() {
+            let existing = std::fs::read_to_string(&this.path)
+                .map_err(|e| Self::error(ActionErrorKind::Read(this.path.clone(), e)))?;
+            if existing == this.content.expose() {
+                tracing::debug!("Creating file `{}` already complete", this.path.display());
+                return Ok(StatefulAction::completed(this));
+            }
+            if !force {
+                return Err(Self::error(ActionErrorKind::DifferentContent(this.path)));
+            }
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[typetag::serde(name = "create_secret_file")]
+impl Action for CreateSecretFile {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_secret_file")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Create secret file `{}`", self.path.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_secret_file",
+            path = tracing::field::display(self.path.display()),
+            mode = tracing::field::display(format!("{:#o}", self.mode)),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        if self.content.is_redacted() {
+            return Err(Self::error(ActionErrorKind::RedactedSecret(
+                self.path.display().to_string(),
+            )));
+        }
+
+        if self.backup.is_none() {
+            self.backup = FileBackup::create(&self.path).map_err(Self::error)?;
+        }
+
+        // The mode is set before the secret is written, so it is never readable by others
+        let mut temp_file = DurableFile::create(&self.path).map_err(Self::error)?;
+        temp_file.set_mode(self.mode).map_err(Self::error)?;
+        temp_file
+            .write_all(self.content.expose().as_bytes())
+            .map_err(Self::error)?;
+        temp_file.persist().map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Delete file `{}`", self.path.display()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        crate::util::remove_file(&self.path, OnMissing::Ignore)
+            .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.clone(), e)))?;
+        if let Some(backup) = self.backup.take() {
+            backup.restore().map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn creates_private_file_and_redacts_it() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("netrc");
+        let content = "machine cache.example.com password hunter2\n";
+
+        let mut action = CreateSecretFile::plan(&path, 0o600, content.into(), false)?;
+        let receipt = serde_json::to_string(&action)?;
+        assert!(!receipt.contains("hunter2"));
+
+        action.try_execute()?;
+        assert_eq!(std::fs::read_to_string(&path)?, content);
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        assert!(CreateSecretFile::plan(&path, 0o600, "other".into(), false).is_err());
+
+        let mut read_back: StatefulAction<CreateSecretFile> = serde_json::from_str(&receipt)?;
+        std::fs::remove_file(&path)?;
+        assert!(read_back.try_execute().is_err());
+
+        action.try_revert()?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
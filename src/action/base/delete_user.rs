@@ -1,12 +1,8 @@
 use nix::unistd::User;
-use std::process::Command;
-use target_lexicon::OperatingSystem;
 use tracing::{Span, span};
 
-use crate::action::base::create_user::delete_user_macos;
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
-use crate::execute_command;
-use crate::util::which;
+use crate::os::users::user_backend;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
 
@@ -24,14 +20,7 @@ impl DeleteUser {
     pub fn plan(name: String) -> Result<StatefulAction<Self>, ActionError> {
         let this = Self { name: name.clone() };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => (),
-            _ => {
-                if !(which("userdel").is_some() || which("deluser").is_some()) {
-                    return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                }
-            },
-        }
+        user_backend().map_err(Self::error)?;
 
         // Ensure user exists
         let _ = User::from_name(name.as_str())
@@ -74,30 +63,9 @@ impl Action for DeleteUser {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
-                delete_user_macos(&self.name).map_err(Self::error)?;
-            },
-            _ => {
-                if which("userdel").is_some() {
-                    execute_command(
-                        Command::new("userdel")
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else if which("deluser").is_some() {
-                    execute_command(
-                        Command::new("deluser")
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                }
-            },
-        };
+        user_backend()
+            .and_then(|backend| backend.delete_user(&self.name))
+            .map_err(Self::error)?;
 
         Ok(())
     }
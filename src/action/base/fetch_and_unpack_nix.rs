@@ -1,27 +1,93 @@
 use std::io::Cursor;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
 
 use tracing::{Span, span};
 
 use crate::{
-    action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
-    settings::{EMBEDDED_NIX_TARBALL, NIX_VERSION},
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionEstimate, ActionTag,
+        StatefulAction,
+    },
+    execute_command,
+    progress::ProgressReader,
+    settings::{EMBEDDED_NIX_TARBALL, NIX_VERSION, NixSource, embedded_nix_unpacked_size},
     util::OnMissing,
 };
 
+/// The magic bytes starting a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
 /**
-Unpack the embedded Nix tarball to the destination directory
+Unpack the embedded Nix tarball (or one fetched from a URL) to the destination directory
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "fetch_and_unpack_nix")]
 pub struct FetchAndUnpackNix {
     dest: PathBuf,
+    #[serde(default)]
+    source: NixSource,
+    /// The CA bundle `curl` verifies a fetched tarball with, instead of its own
+    #[serde(default)]
+    ssl_cert_file: Option<PathBuf>,
 }
 
 impl FetchAndUnpackNix {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(dest: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
-        Ok(Self { dest }.into())
+    pub fn plan(
+        dest: PathBuf,
+        source: NixSource,
+        ssl_cert_file: Option<PathBuf>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        if let NixSource::Tarball { url, .. } = &source {
+            match url.scheme() {
+                "file" | "http" | "https" => (),
+                _ => {
+                    return Err(Self::error(UnpackError::UnsupportedScheme(url.clone())));
+                },
+            }
+        }
+
+        Ok(Self {
+            dest,
+            source,
+            ssl_cert_file,
+        }
+        .into())
+    }
+
+    /// Read a `file://` tarball, or download an `http(s)://` one with `curl` (which respects the proxy settings)
+    fn fetch(
+        url: &url::Url,
+        download: &Path,
+        ssl_cert_file: Option<&Path>,
+    ) -> Result<Vec<u8>, ActionErrorKind> {
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|()| UnpackError::UnsupportedScheme(url.clone()))?;
+            return std::fs::read(&path).map_err(|e| ActionErrorKind::Read(path, e));
+        }
+
+        let mut command = Command::new("curl");
+        command.args(["--fail", "--silent", "--show-error", "--location"]);
+        // The whole bundle, so the intermediates in it are trusted too
+        if let Some(ssl_cert_file) = ssl_cert_file {
+            command.arg("--cacert").arg(ssl_cert_file);
+        }
+        execute_command(
+            command
+                .arg("--output")
+                .arg(download)
+                .arg(url.as_str())
+                .stdin(std::process::Stdio::null()),
+        )?;
+        let tarball =
+            std::fs::read(download).map_err(|e| ActionErrorKind::Read(download.into(), e))?;
+        crate::util::remove_file(download, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(download.into(), e))?;
+        Ok(tarball)
     }
 }
 
@@ -32,11 +98,19 @@ impl Action for FetchAndUnpackNix {
     }
 
     fn tracing_synopsis(&self) -> String {
-        format!(
-            "Unpack embedded Nix {} to `{}`",
-            NIX_VERSION.trim(),
-            self.dest.display()
-        )
+        match &self.source {
+            NixSource::Embedded => format!(
+                "Unpack embedded Nix {} to `{}`",
+                NIX_VERSION.trim(),
+                self.dest.display()
+            ),
+            NixSource::Tarball {
+                url, distribution, ..
+            } => format!(
+                "Fetch `{url}` and unpack {distribution} to `{}`",
+                self.dest.display()
+            ),
+        }
     }
 
     fn tracing_span(&self) -> Span {
@@ -47,27 +121,74 @@ impl Action for FetchAndUnpackNix {
         )
     }
 
+    fn execute_estimate(&self) -> ActionEstimate {
+        if !matches!(self.source, NixSource::Embedded) {
+            // The size of a fetched tarball isn't known until it is fetched
+            return ActionEstimate::default();
+        }
+        // The unpacked tarball is a good approximation of the size of the store
+        let unpacked_bytes = embedded_nix_unpacked_size();
+        // Assume unpacking manages roughly 100 MiB/s
+        let duration = Duration::from_secs_f64(unpacked_bytes as f64 / (100.0 * 1024.0 * 1024.0));
+        ActionEstimate::new(unpacked_bytes, duration)
+    }
+
     fn execute_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        tracing::trace!("Unpacking embedded tar.zst");
-
         // Remove destination if it exists (from a previous failed install)
         if self.dest.exists() {
             crate::util::remove_dir_all(&self.dest, OnMissing::Ignore)
                 .map_err(|e| Self::error(ActionErrorKind::Remove(self.dest.clone(), e)))?;
         }
 
+        let tarball = match &self.source {
+            NixSource::Embedded => {
+                tracing::trace!("Unpacking embedded tar.zst");
+                std::borrow::Cow::Borrowed(EMBEDDED_NIX_TARBALL)
+            },
+            NixSource::Tarball { url, .. } => {
+                tracing::trace!(%url, "Fetching tarball");
+                let download = self.dest.with_extension("tarball");
+                std::borrow::Cow::Owned(
+                    Self::fetch(url, &download, self.ssl_cert_file.as_deref())
+                        .map_err(Self::error)?,
+                )
+            },
+        };
+
+        if !tarball.starts_with(&ZSTD_MAGIC) {
+            // Leave other compressions (like the `.tar.xz` of upstream releases) to `tar`
+            let archive = self.dest.with_extension("tar");
+            crate::util::write_atomic(&archive, &tarball).map_err(Self::error)?;
+            std::fs::create_dir_all(&self.dest)
+                .map_err(|e| ActionErrorKind::CreateDirectory(self.dest.clone(), e))
+                .map_err(Self::error)?;
+            execute_command(
+                Command::new("tar")
+                    .arg("-xf")
+                    .arg(&archive)
+                    .arg("-C")
+                    .arg(&self.dest)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+            crate::util::remove_file(&archive, OnMissing::Ignore)
+                .map_err(|e| Self::error(ActionErrorKind::Remove(archive, e)))?;
+            return Ok(());
+        }
+
         // Decompress zstd
-        let zstd_reader = Cursor::new(EMBEDDED_NIX_TARBALL);
+        let zstd_reader = Cursor::new(tarball);
         let tar_data =
             zstd::decode_all(zstd_reader).map_err(|e| Self::error(UnpackError::Zstd(e)))?;
 
         // Unpack tar
-        let mut archive = tar::Archive::new(Cursor::new(tar_data));
+        let tar_len = tar_data.len() as u64;
+        let mut archive = tar::Archive::new(ProgressReader::new(Cursor::new(tar_data), tar_len));
         archive.set_preserve_permissions(true);
         archive.set_preserve_mtime(true);
         archive.set_unpack_xattrs(true);
@@ -95,6 +216,8 @@ pub enum UnpackError {
     Zstd(#[source] std::io::Error),
     #[error("Tar extraction error")]
     Unarchive(#[source] std::io::Error),
+    #[error("Unsupported tarball URL `{0}`, use an `https://` or `file://` URL")]
+    UnsupportedScheme(url::Url),
 }
 
 impl From<UnpackError> for ActionErrorKind {
@@ -0,0 +1,148 @@
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::execute_command;
+
+/**
+Run a site-specific command, such as enrolling the machine in monitoring, and optionally another
+to undo it on revert
+
+The installer's planners never produce this action: it is for appending to a plan file consumed by
+`install`, so organizations can add their own steps without forking the crate. The command is run
+directly, not through a shell.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "generic_command")]
+pub struct GenericCommand {
+    description: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    revert_command: Option<String>,
+    #[serde(default)]
+    revert_args: Vec<String>,
+}
+
+impl GenericCommand {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        description: impl Into<String>,
+        command: impl Into<String>,
+        args: Vec<String>,
+        revert: Option<(String, Vec<String>)>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let (revert_command, revert_args) = match revert {
+            Some((command, args)) => (Some(command), args),
+            None => (None, vec![]),
+        };
+
+        Ok(Self {
+            description: description.into(),
+            command: command.into(),
+            args,
+            revert_command,
+            revert_args,
+        }
+        .into())
+    }
+}
+
+/// The command line as it would be typed, for descriptions
+fn command_line(command: &str, args: &[String]) -> String {
+    std::iter::once(command)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[typetag::serde(name = "generic_command")]
+impl Action for GenericCommand {
+    fn action_tag() -> ActionTag {
+        ActionTag("generic_command")
+    }
+    fn tracing_synopsis(&self) -> String {
+        self.description.clone()
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "generic_command",
+            command = self.command,
+            args = ?self.args,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!("Run `{}`", command_line(&self.command, &self.args))],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new(&self.command)
+                .args(&self.args)
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        match &self.revert_command {
+            Some(revert_command) => vec![ActionDescription::new(
+                format!("Undo: {}", self.description),
+                vec![format!(
+                    "Run `{}`",
+                    command_line(revert_command, &self.revert_args)
+                )],
+            )],
+            None => vec![],
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        if let Some(revert_command) = &self.revert_command {
+            execute_command(
+                Command::new(revert_command)
+                    .args(&self.revert_args)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::{Action, ActionState, StatefulAction};
+
+    #[test]
+    fn deserializes_from_plan_file() {
+        let action: StatefulAction<Box<dyn Action>> = serde_json::from_value(serde_json::json!({
+            "action": {
+                "action_name": "generic_command",
+                "description": "Enroll in monitoring",
+                "command": "/usr/local/bin/enroll",
+                "args": ["--site", "lab"],
+            },
+            "state": "Uncompleted",
+        }))
+        .unwrap();
+
+        assert_eq!(action.state, ActionState::Uncompleted);
+        assert_eq!(action.action.tracing_synopsis(), "Enroll in monitoring");
+        // Without a revert command there is nothing to undo
+        assert!(action.action.revert_description().is_empty());
+    }
+}
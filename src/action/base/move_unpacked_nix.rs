@@ -8,6 +8,7 @@ use walkdir::WalkDir;
 
 use crate::{
     action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
+    settings::Distribution,
     util::OnMissing,
 };
 
@@ -20,13 +21,22 @@ Move an unpacked Nix at `src` to `/nix`
 #[serde(tag = "action_name", rename = "mount_unpacked_nix")]
 pub struct MoveUnpackedNix {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    distribution: Distribution,
 }
 
 impl MoveUnpackedNix {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
+    pub fn plan(
+        unpacked_path: PathBuf,
+        distribution: Distribution,
+    ) -> Result<StatefulAction<Self>, ActionError> {
         // Note: Do NOT try to check for the src/dest since the installer creates those
-        Ok(Self { unpacked_path }.into())
+        Ok(Self {
+            unpacked_path,
+            distribution,
+        }
+        .into())
     }
 }
 
@@ -60,10 +70,13 @@ impl Action for MoveUnpackedNix {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { unpacked_path } = self;
+        let Self {
+            unpacked_path,
+            distribution,
+        } = self;
 
-        // This is the `nix-$VERSION` folder which unpacks from the tarball, not a nix derivation
-        let found_nix_paths = glob::glob(&format!("{}/nix-*", unpacked_path.display()))
+        // This is the `nix-$VERSION` (or `lix-$VERSION`) folder which unpacks from the tarball, not a nix derivation
+        let found_nix_paths = glob::glob(&format!("{}/{distribution}-*", unpacked_path.display()))
             .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| Self::error(MoveUnpackedNixError::from(e)))?;
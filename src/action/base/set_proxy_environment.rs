@@ -0,0 +1,67 @@
+use tracing::{Span, span};
+
+use crate::action::{Action, ActionDescription, ActionTag};
+use crate::action::{ActionError, StatefulAction};
+use crate::proxy::Proxy;
+
+/** Set the proxy variables `curl` and Nix read, for the commands run after it, does nothing on revert.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "set_proxy_environment")]
+pub struct SetProxyEnvironment {
+    proxy: Proxy,
+}
+
+impl SetProxyEnvironment {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub(crate) fn plan(proxy: Proxy) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self { proxy }.into())
+    }
+}
+
+#[typetag::serde(name = "set_proxy_environment")]
+impl Action for SetProxyEnvironment {
+    fn action_tag() -> ActionTag {
+        ActionTag("set_proxy_environment")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Use the proxy `{}` for the commands run while installing",
+            crate::sensitive::redact_url(&self.proxy.url)
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "set_proxy_environment")
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Nix has no `nix.conf` setting for a proxy, `curl` and Nix read it from `http_proxy`, `https_proxy` and `all_proxy`".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        // Not `crate::set_env`, which logs the value, and the proxy may have credentials
+        for (key, value) in self.proxy.environment() {
+            tracing::trace!(key, "Setting env");
+            // SAFETY: Actions execute one at a time, before any concurrent access to the environment
+            unsafe { std::env::set_var(key, value) };
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        Ok(())
+    }
+}
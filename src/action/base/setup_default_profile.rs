@@ -6,7 +6,7 @@ use crate::{
     action::{ActionError, ActionErrorKind, ActionTag, StatefulAction},
     profile::WriteToDefaultProfile,
     set_env,
-    settings::{NIX_STORE_PATH, NIX_VERSION, NSS_CACERT_STORE_PATH},
+    settings::{Distribution, NIX_VERSION, NixSource, StorePaths},
 };
 
 use tracing::{Span, span};
@@ -20,12 +20,21 @@ Setup the default Nix profile with `nss-cacert` and `nix` itself.
 #[serde(tag = "action_name", rename = "setup_default_profile")]
 pub struct SetupDefaultProfile {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    source: NixSource,
 }
 
 impl SetupDefaultProfile {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
-        Ok(Self { unpacked_path }.into())
+    pub fn plan(
+        unpacked_path: PathBuf,
+        source: NixSource,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self {
+            unpacked_path,
+            source,
+        }
+        .into())
     }
 }
 
@@ -52,20 +61,17 @@ impl Action for SetupDefaultProfile {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        let nix_pkg = PathBuf::from(NIX_STORE_PATH.trim());
-        let nss_ca_cert_pkg = PathBuf::from(NSS_CACERT_STORE_PATH.trim());
-
-        // Find the unpacked nix directory (nix-VERSION-SYSTEM)
-        let nix_version = NIX_VERSION.trim();
+        // Find the unpacked nix directory (nix-VERSION-SYSTEM, or lix-VERSION-SYSTEM)
+        let distribution = self.source.distribution();
+        let prefix = match self.source {
+            NixSource::Embedded => format!("nix-{}", NIX_VERSION.trim()),
+            NixSource::Tarball { .. } => format!("{distribution}-"),
+        };
         let found_nix_paths: Vec<_> = std::fs::read_dir(&self.unpacked_path)
             .map_err(|e| ActionErrorKind::ReadDir(self.unpacked_path.clone(), e))
             .map_err(Self::error)?
             .filter_map(Result::ok)
-            .filter(|e| {
-                e.file_name()
-                    .to_string_lossy()
-                    .starts_with(&format!("nix-{nix_version}"))
-            })
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
             .collect();
 
         if found_nix_paths.len() != 1 {
@@ -78,6 +84,28 @@ impl Action for SetupDefaultProfile {
             .map_err(|e| ActionErrorKind::Read(reginfo_path.to_path_buf(), e))
             .map_err(Self::error)?;
 
+        let StorePaths {
+            nix: nix_pkg,
+            nss_cacert: nss_ca_cert_pkg,
+        } = match &self.source {
+            NixSource::Embedded => StorePaths::embedded(),
+            NixSource::Tarball {
+                store_paths: Some(store_paths),
+                ..
+            } => store_paths.clone(),
+            NixSource::Tarball {
+                store_paths: None, ..
+            } => {
+                let version = tarball_version(&found_nix_path, distribution)
+                    .ok_or_else(|| Self::error(ActionErrorKind::MalformedBinaryTarball))?;
+                StorePaths::from_reginfo(&String::from_utf8_lossy(&reginfo), distribution, version)
+                    .ok_or_else(|| {
+                        Self::error(SetupDefaultProfileError::StorePathsNotFound(distribution))
+                    })?
+            },
+        };
+        tracing::debug!(nix = %nix_pkg.display(), nss_cacert = %nss_ca_cert_pkg.display(), "Installing store paths");
+
         let mut load_db_command = Command::new(nix_pkg.join("bin/nix-store"));
         load_db_command.arg("--load-db");
         load_db_command.stdin(std::process::Stdio::piped());
@@ -127,7 +155,7 @@ impl Action for SetupDefaultProfile {
             nix_store_path: &nix_pkg,
             nss_ca_cert_path: &nss_ca_cert_pkg,
 
-            profile: std::path::Path::new("/nix/var/nix/profiles/default"),
+            profile: std::path::Path::new(crate::settings::DEFAULT_PROFILE),
             pkgs: &[&nix_pkg, &nss_ca_cert_pkg],
         };
         profile
@@ -160,12 +188,27 @@ impl Action for SetupDefaultProfile {
     }
 }
 
+/// The version in the name of an unpacked tarball's `{distribution}-{version}-{system}` directory
+pub(crate) fn tarball_version(
+    unpacked: &std::path::Path,
+    distribution: Distribution,
+) -> Option<&str> {
+    let name = unpacked.file_name()?.to_str()?;
+    let rest = name.strip_prefix(&format!("{distribution}-"))?;
+    rest.split_once('-').map(|(version, _system)| version)
+}
+
 #[non_exhaustive]
 #[derive(Debug, thiserror::Error)]
 pub enum SetupDefaultProfileError {
     #[error("No root home found to place channel configuration in")]
     NoRootHome,
 
+    #[error(
+        "Could not find the `{0}` and `nss-cacert` store paths in the tarball, pass them with `--store-paths-json`"
+    )]
+    StorePathsNotFound(Distribution),
+
     #[error(transparent)]
     NixProfile(#[from] crate::profile::Error),
 }
@@ -0,0 +1,486 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::CreateFile;
+use crate::action::macos::DARWIN_LAUNCHD_DOMAIN;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::settings::{CommonSettings, DEFAULT_PROFILE, InitSystem, MaintenanceSchedule};
+
+const GC_OLDER_THAN: &str = "30d";
+const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+const LAUNCHD_DAEMON_DIR: &str = "/Library/LaunchDaemons";
+
+/// A periodic job keeping the Nix store in shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MaintenanceJob {
+    /// `nix-collect-garbage --delete-older-than 30d`
+    Gc,
+    /// `nix-store --optimise`, hard linking identical files in the store
+    Optimise,
+}
+
+impl MaintenanceJob {
+    /// The name of the systemd units, and the `org.nixos.` `launchd` label
+    fn name(&self) -> &'static str {
+        match self {
+            MaintenanceJob::Gc => "nix-gc",
+            MaintenanceJob::Optimise => "nix-optimise",
+        }
+    }
+
+    fn description(&self) -> String {
+        match self {
+            MaintenanceJob::Gc => format!("Collect Nix garbage older than {GC_OLDER_THAN}"),
+            MaintenanceJob::Optimise => "Optimise the Nix store".to_string(),
+        }
+    }
+
+    fn program_arguments(&self) -> Vec<String> {
+        match self {
+            MaintenanceJob::Gc => vec![
+                format!("{DEFAULT_PROFILE}/bin/nix-collect-garbage"),
+                "--delete-older-than".to_string(),
+                GC_OLDER_THAN.to_string(),
+            ],
+            MaintenanceJob::Optimise => vec![
+                format!("{DEFAULT_PROFILE}/bin/nix-store"),
+                "--optimise".to_string(),
+            ],
+        }
+    }
+
+    fn systemd_timer_name(&self) -> String {
+        format!("{}.timer", self.name())
+    }
+
+    fn launchd_label(&self) -> String {
+        format!("org.nixos.{}", self.name())
+    }
+
+    fn launchd_plist_dest(&self) -> PathBuf {
+        PathBuf::from(LAUNCHD_DAEMON_DIR).join(format!("{}.plist", self.launchd_label()))
+    }
+}
+
+/**
+Periodically collect garbage in, and optimise, the Nix store
+
+Installs a systemd timer or `launchd` job for each, running `nix-collect-garbage --delete-older-than 30d`
+or `nix-store --optimise` on the given schedule, since few users remember to do it themselves.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_auto_maintenance")]
+pub struct ConfigureAutoMaintenance {
+    init: InitSystem,
+    gc_schedule: Option<MaintenanceSchedule>,
+    #[serde(default)]
+    optimise_schedule: Option<MaintenanceSchedule>,
+    create_units: Vec<StatefulAction<CreateFile>>,
+}
+
+impl ConfigureAutoMaintenance {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        init: InitSystem,
+        settings: &CommonSettings,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            init,
+            gc_schedule: settings.enable_auto_gc,
+            optimise_schedule: settings.enable_auto_optimise,
+            create_units: Vec::new(),
+        };
+
+        let mut create_units = Vec::new();
+        for (job, schedule) in this.jobs() {
+            match init {
+                InitSystem::Systemd => {
+                    create_units.push(
+                        CreateFile::plan(
+                            Path::new(SYSTEMD_UNIT_DIR).join(format!("{}.service", job.name())),
+                            None,
+                            None,
+                            0o0644,
+                            systemd_service(job),
+                            settings.force,
+                        )
+                        .map_err(Self::error)?,
+                    );
+                    create_units.push(
+                        CreateFile::plan(
+                            Path::new(SYSTEMD_UNIT_DIR).join(job.systemd_timer_name()),
+                            None,
+                            None,
+                            0o0644,
+                            systemd_timer(job, schedule),
+                            settings.force,
+                        )
+                        .map_err(Self::error)?,
+                    );
+                },
+                InitSystem::Launchd => create_units.push(
+                    CreateFile::plan(
+                        job.launchd_plist_dest(),
+                        None,
+                        None,
+                        0o0644,
+                        launchd_plist(job, schedule),
+                        settings.force,
+                    )
+                    .map_err(Self::error)?,
+                ),
+                InitSystem::None
+                | InitSystem::Openrc
+                | InitSystem::SysVInit
+                | InitSystem::Runit
+                | InitSystem::S6
+                | InitSystem::RcD => {
+                    return Err(Self::error(
+                        ActionErrorKind::AutoMaintenanceUnsupportedInit(init),
+                    ));
+                },
+            }
+        }
+
+        Ok(Self {
+            create_units,
+            ..this
+        }
+        .into())
+    }
+
+    fn jobs(&self) -> Vec<(MaintenanceJob, MaintenanceSchedule)> {
+        let mut jobs = Vec::new();
+        if let Some(schedule) = self.gc_schedule {
+            jobs.push((MaintenanceJob::Gc, schedule));
+        }
+        if let Some(schedule) = self.optimise_schedule {
+            jobs.push((MaintenanceJob::Optimise, schedule));
+        }
+        jobs
+    }
+}
+
+fn systemd_service(job: MaintenanceJob) -> String {
+    format!(
+        "\
+        [Unit]\n\
+        Description={description}\n\
+        After=nix-daemon.socket\n\
+        \n\
+        [Service]\n\
+        Type=oneshot\n\
+        ExecStart={exec_start}\n\
+        ",
+        description = job.description(),
+        exec_start = job.program_arguments().join(" "),
+    )
+}
+
+fn systemd_timer(job: MaintenanceJob, schedule: MaintenanceSchedule) -> String {
+    format!(
+        "\
+        [Unit]\n\
+        Description={description} {schedule}\n\
+        \n\
+        [Timer]\n\
+        OnCalendar={schedule}\n\
+        Persistent=true\n\
+        RandomizedDelaySec=30min\n\
+        \n\
+        [Install]\n\
+        WantedBy=timers.target\n\
+        ",
+        description = job.description(),
+    )
+}
+
+fn launchd_plist(job: MaintenanceJob, schedule: MaintenanceSchedule) -> String {
+    let program_arguments = job
+        .program_arguments()
+        .iter()
+        .map(|argument| format!("    <string>{argument}</string>\n"))
+        .collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{program_arguments}  </array>
+  <key>StartCalendarInterval</key>
+  {calendar_interval}
+  <key>LowPriorityIO</key>
+  <true/>
+  <key>StandardErrorPath</key>
+  <string>/var/log/{name}.log</string>
+</dict>
+</plist>
+"#,
+        label = job.launchd_label(),
+        name = job.name(),
+        calendar_interval = launchd_calendar_interval(schedule),
+    )
+}
+
+/// `launchd` runs jobs missed while asleep on wake, like systemd's `Persistent=true`
+fn launchd_calendar_interval(schedule: MaintenanceSchedule) -> &'static str {
+    match schedule {
+        MaintenanceSchedule::Daily => {
+            "<dict>\n    <key>Hour</key>\n    <integer>3</integer>\n    <key>Minute</key>\n    <integer>15</integer>\n  </dict>"
+        },
+        MaintenanceSchedule::Weekly => {
+            "<dict>\n    <key>Weekday</key>\n    <integer>0</integer>\n    <key>Hour</key>\n    <integer>3</integer>\n    <key>Minute</key>\n    <integer>15</integer>\n  </dict>"
+        },
+        MaintenanceSchedule::Monthly => {
+            "<dict>\n    <key>Day</key>\n    <integer>1</integer>\n    <key>Hour</key>\n    <integer>3</integer>\n    <key>Minute</key>\n    <integer>15</integer>\n  </dict>"
+        },
+    }
+}
+
+#[typetag::serde(name = "configure_auto_maintenance")]
+impl Action for ConfigureAutoMaintenance {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_auto_maintenance")
+    }
+    fn tracing_synopsis(&self) -> String {
+        let jobs = self
+            .jobs()
+            .iter()
+            .map(|(job, schedule)| match job {
+                MaintenanceJob::Gc => format!("collect Nix garbage {schedule}"),
+                MaintenanceJob::Optimise => format!("optimise the Nix store {schedule}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" and ");
+        format!("Configure {init} to {jobs}", init = self.init)
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_auto_maintenance",
+            init = %self.init,
+            gc_schedule = self.gc_schedule.map(tracing::field::display),
+            optimise_schedule = self.optimise_schedule.map(tracing::field::display),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        for create_unit in &self.create_units {
+            explanation.push(format!("Create `{}`", create_unit.inner().path.display()));
+        }
+        for (job, _) in self.jobs() {
+            match self.init {
+                InitSystem::Systemd => explanation.push(format!(
+                    "Run `systemctl enable --now {}`",
+                    job.systemd_timer_name()
+                )),
+                InitSystem::Launchd => explanation.push(format!(
+                    "Run `launchctl bootstrap {DARWIN_LAUNCHD_DOMAIN} {}`",
+                    job.launchd_plist_dest().display()
+                )),
+                InitSystem::None
+                | InitSystem::Openrc
+                | InitSystem::SysVInit
+                | InitSystem::Runit
+                | InitSystem::S6
+                | InitSystem::RcD => (),
+            }
+        }
+
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        for create_unit in self.create_units.iter_mut() {
+            create_unit.try_execute().map_err(Self::error)?;
+        }
+
+        if self.init == InitSystem::Systemd {
+            execute_command(
+                Command::new("systemctl")
+                    .arg("daemon-reload")
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        for (job, _) in self.jobs() {
+            match self.init {
+                InitSystem::Systemd => {
+                    execute_command(
+                        Command::new("systemctl")
+                            .args(["enable", "--now", &job.systemd_timer_name()])
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .map_err(Self::error)?;
+                },
+                InitSystem::Launchd => {
+                    crate::action::macos::retry_bootstrap(
+                        DARWIN_LAUNCHD_DOMAIN,
+                        &job.launchd_label(),
+                        &job.launchd_plist_dest(),
+                    )
+                    .map_err(Self::error)?;
+                },
+                InitSystem::None
+                | InitSystem::Openrc
+                | InitSystem::SysVInit
+                | InitSystem::Runit
+                | InitSystem::S6
+                | InitSystem::RcD => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        for (job, _) in self.jobs() {
+            match self.init {
+                InitSystem::Systemd => explanation.push(format!(
+                    "Run `systemctl disable --now {}`",
+                    job.systemd_timer_name()
+                )),
+                InitSystem::Launchd => explanation.push(format!(
+                    "Run `launchctl bootout {DARWIN_LAUNCHD_DOMAIN}/{}`",
+                    job.launchd_label()
+                )),
+                InitSystem::None
+                | InitSystem::Openrc
+                | InitSystem::SysVInit
+                | InitSystem::Runit
+                | InitSystem::S6
+                | InitSystem::RcD => (),
+            }
+        }
+        for create_unit in &self.create_units {
+            explanation.push(format!("Delete `{}`", create_unit.inner().path.display()));
+        }
+
+        vec![ActionDescription::new(
+            format!(
+                "Remove the {init} jobs maintaining the Nix store",
+                init = self.init
+            ),
+            explanation,
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        for (job, _) in self.jobs() {
+            match self.init {
+                InitSystem::Systemd => {
+                    if let Err(err) = execute_command(
+                        Command::new("systemctl")
+                            .args(["disable", "--now", &job.systemd_timer_name()])
+                            .stdin(std::process::Stdio::null()),
+                    ) {
+                        errors.push(Self::error(err));
+                    }
+                },
+                InitSystem::Launchd => {
+                    if let Err(err) = crate::action::macos::retry_bootout(
+                        DARWIN_LAUNCHD_DOMAIN,
+                        &job.launchd_label(),
+                    ) {
+                        errors.push(Self::error(err));
+                    }
+                },
+                InitSystem::None
+                | InitSystem::Openrc
+                | InitSystem::SysVInit
+                | InitSystem::Runit
+                | InitSystem::S6
+                | InitSystem::RcD => (),
+            }
+        }
+
+        for create_unit in self.create_units.iter_mut().rev() {
+            if let Err(err) = create_unit.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        if self.init == InitSystem::Systemd
+            && let Err(err) = execute_command(
+                Command::new("systemctl")
+                    .arg("daemon-reload")
+                    .stdin(std::process::Stdio::null()),
+            )
+        {
+            errors.push(Self::error(err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn launchd_plist_parses() -> Result<(), Box<dyn std::error::Error>> {
+        for schedule in [
+            MaintenanceSchedule::Daily,
+            MaintenanceSchedule::Weekly,
+            MaintenanceSchedule::Monthly,
+        ] {
+            let plist: plist::Dictionary =
+                plist::from_bytes(launchd_plist(MaintenanceJob::Gc, schedule).as_bytes())?;
+            let interval = plist
+                .get("StartCalendarInterval")
+                .and_then(|interval| interval.as_dictionary())
+                .ok_or("missing StartCalendarInterval")?;
+            assert_eq!(
+                interval.contains_key("Weekday"),
+                schedule == MaintenanceSchedule::Weekly
+            );
+        }
+
+        let plist: plist::Dictionary = plist::from_bytes(
+            launchd_plist(MaintenanceJob::Optimise, MaintenanceSchedule::Weekly).as_bytes(),
+        )?;
+        let arguments = plist
+            .get("ProgramArguments")
+            .and_then(|arguments| arguments.as_array())
+            .ok_or("missing ProgramArguments")?;
+        assert_eq!(arguments.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn systemd_units_use_the_schedule() {
+        let timer = systemd_timer(MaintenanceJob::Optimise, MaintenanceSchedule::Monthly);
+        assert!(timer.contains("OnCalendar=monthly\n"));
+        let service = systemd_service(MaintenanceJob::Optimise);
+        assert!(service.contains(&format!(
+            "ExecStart={DEFAULT_PROFILE}/bin/nix-store --optimise\n"
+        )));
+    }
+}
@@ -0,0 +1,514 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+use url::Url;
+
+use crate::action::base::{CreateFile, CreateSecretFile};
+use crate::action::common::place_nix_configuration::NIX_CONF_FOLDER;
+use crate::action::macos::DARWIN_LAUNCHD_DOMAIN;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::backup::FileBackup;
+use crate::execute_command;
+use crate::sensitive::SensitiveString;
+use crate::settings::{CommonSettings, InitSystem};
+use crate::util::OnMissing;
+
+const REFRESH_SCRIPT_DEST: &str = "/etc/nix/nix-config-refresh.sh";
+/// The URLs to fetch, which may hold tokens, so only `root` may read them
+const REFRESH_URLS_DEST: &str = "/etc/nix/nix-config-refresh.urls";
+const SYSTEMD_SERVICE_DEST: &str = "/etc/systemd/system/nix-config-refresh.service";
+const SYSTEMD_TIMER_NAME: &str = "nix-config-refresh.timer";
+const SYSTEMD_TIMER_DEST: &str = "/etc/systemd/system/nix-config-refresh.timer";
+const LAUNCHD_SERVICE_NAME: &str = "org.nixos.nix-config-refresh";
+const LAUNCHD_SERVICE_DEST: &str = "/Library/LaunchDaemons/org.nixos.nix-config-refresh.plist";
+
+/**
+Periodically re-fetch an enterprise-managed `/etc/nix/registry.json` and `/etc/nix/netrc`
+
+Installs a refresh script along with a systemd timer or `launchd` job which runs it. The URLs, which
+often carry a token, are kept out of the world readable script and the receipt, in a file only
+`root` can read. Fetches use
+`ssl_cert_file` as the CA bundle and, for mTLS, `config_refresh_client_cert` as the client
+certificate. A `registry.json` or `netrc` which was already there is backed up before the first
+fetch replaces it, and restored on revert.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_config_refresh")]
+pub struct ConfigureConfigRefresh {
+    init: InitSystem,
+    create_urls: StatefulAction<CreateSecretFile>,
+    create_script: StatefulAction<CreateFile>,
+    create_units: Vec<StatefulAction<CreateFile>>,
+    refreshed_files: Vec<PathBuf>,
+    #[serde(default)]
+    backups: Vec<FileBackup>,
+}
+
+impl ConfigureConfigRefresh {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        init: InitSystem,
+        settings: &CommonSettings,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let mut fetches = Vec::new();
+        if let Some(registry_url) = &settings.registry_url {
+            fetches.push((
+                registry_url,
+                PathBuf::from(NIX_CONF_FOLDER).join("registry.json"),
+                0o644,
+            ));
+        }
+        if let Some(netrc_url) = &settings.netrc_url {
+            fetches.push((
+                netrc_url,
+                PathBuf::from(NIX_CONF_FOLDER).join("netrc"),
+                0o600,
+            ));
+        }
+
+        let ca_bundle = settings
+            .ca_bundle()
+            .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+        let create_urls = CreateSecretFile::plan(
+            REFRESH_URLS_DEST,
+            0o600,
+            refresh_urls(&fetches),
+            settings.force,
+        )
+        .map_err(Self::error)?;
+        let script = refresh_script(
+            ca_bundle.as_deref(),
+            settings.config_refresh_client_cert.as_deref(),
+        );
+        let create_script = CreateFile::plan(
+            REFRESH_SCRIPT_DEST,
+            None,
+            None,
+            0o0755,
+            script,
+            settings.force,
+        )
+        .map_err(Self::error)?;
+
+        let interval = settings.config_refresh_interval;
+        let create_units = match init {
+            InitSystem::Systemd => vec![
+                CreateFile::plan(
+                    SYSTEMD_SERVICE_DEST,
+                    None,
+                    None,
+                    0o0644,
+                    format!(
+                        "\
+                        [Unit]\n\
+                        Description=Refresh the managed Nix flake registry and netrc\n\
+                        Wants=network-online.target\n\
+                        After=network-online.target\n\
+                        \n\
+                        [Service]\n\
+                        Type=oneshot\n\
+                        ExecStart={REFRESH_SCRIPT_DEST}\n\
+                    "
+                    ),
+                    settings.force,
+                )
+                .map_err(Self::error)?,
+                CreateFile::plan(
+                    SYSTEMD_TIMER_DEST,
+                    None,
+                    None,
+                    0o0644,
+                    format!(
+                        "\
+                        [Unit]\n\
+                        Description=Periodically refresh the managed Nix flake registry and netrc\n\
+                        \n\
+                        [Timer]\n\
+                        OnBootSec=1min\n\
+                        OnUnitActiveSec={interval}s\n\
+                        \n\
+                        [Install]\n\
+                        WantedBy=timers.target\n\
+                    "
+                    ),
+                    settings.force,
+                )
+                .map_err(Self::error)?,
+            ],
+            InitSystem::Launchd => vec![
+                CreateFile::plan(
+                    LAUNCHD_SERVICE_DEST,
+                    None,
+                    None,
+                    0o0644,
+                    format!(
+                        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{LAUNCHD_SERVICE_NAME}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>/bin/sh</string>
+    <string>{REFRESH_SCRIPT_DEST}</string>
+  </array>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>StartInterval</key>
+  <integer>{interval}</integer>
+  <key>StandardErrorPath</key>
+  <string>/var/log/nix-config-refresh.log</string>
+</dict>
+</plist>
+"#
+                    ),
+                    settings.force,
+                )
+                .map_err(Self::error)?,
+            ],
+            InitSystem::None
+            | InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => {
+                return Err(Self::error(ActionErrorKind::ConfigRefreshUnsupportedInit(
+                    init,
+                )));
+            },
+        };
+
+        Ok(Self {
+            init,
+            create_urls,
+            create_script,
+            create_units,
+            refreshed_files: fetches.into_iter().map(|(_, dest, _)| dest).collect(),
+            backups: vec![],
+        }
+        .into())
+    }
+}
+
+#[typetag::serde(name = "configure_config_refresh")]
+impl Action for ConfigureConfigRefresh {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_config_refresh")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Configure {init} to periodically refresh {files}",
+            init = self.init,
+            files = self
+                .refreshed_files
+                .iter()
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<_>>()
+                .join(" and "),
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_config_refresh",
+            init = %self.init,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        for path in &self.refreshed_files {
+            if path.exists() {
+                explanation.push(format!(
+                    "Back up the existing `{}` before replacing it",
+                    path.display()
+                ));
+            }
+        }
+        explanation.push(format!(
+            "Create `{}` listing the URLs to fetch, readable only by `root`",
+            self.create_urls.inner().path.display()
+        ));
+        explanation.push(format!(
+            "Create `{}` which fetches the managed files",
+            self.create_script.inner().path.display()
+        ));
+        for create_unit in &self.create_units {
+            explanation.push(format!("Create `{}`", create_unit.inner().path.display()));
+        }
+        match self.init {
+            InitSystem::Systemd => {
+                explanation.push(format!("Run `systemctl enable --now {SYSTEMD_TIMER_NAME}`"))
+            },
+            InitSystem::Launchd => explanation.push(format!(
+                "Run `launchctl bootstrap {DARWIN_LAUNCHD_DOMAIN} {LAUNCHD_SERVICE_DEST}`"
+            )),
+            InitSystem::None
+            | InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => (),
+        }
+
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        // `/etc/nix` may not exist yet, such as with `--skip-nix-conf`
+        std::fs::create_dir_all(NIX_CONF_FOLDER)
+            .map_err(|e| ActionErrorKind::CreateDirectory(PathBuf::from(NIX_CONF_FOLDER), e))
+            .map_err(Self::error)?;
+        // The first fetch replaces them as soon as the job is started
+        for path in &self.refreshed_files {
+            if self.backups.iter().any(|backup| backup.original == *path) {
+                continue;
+            }
+            if let Some(backup) = FileBackup::create(path).map_err(Self::error)? {
+                self.backups.push(backup);
+            }
+        }
+        self.create_urls.try_execute().map_err(Self::error)?;
+        self.create_script.try_execute().map_err(Self::error)?;
+        for create_unit in self.create_units.iter_mut() {
+            create_unit.try_execute().map_err(Self::error)?;
+        }
+
+        match self.init {
+            InitSystem::Systemd => {
+                execute_command(
+                    Command::new("systemctl")
+                        .arg("daemon-reload")
+                        .stdin(std::process::Stdio::null()),
+                )
+                .map_err(Self::error)?;
+                // An `OnBootSec` already in the past elapses immediately, fetching the files now
+                execute_command(
+                    Command::new("systemctl")
+                        .args(["enable", "--now", SYSTEMD_TIMER_NAME])
+                        .stdin(std::process::Stdio::null()),
+                )
+                .map_err(Self::error)?;
+            },
+            InitSystem::Launchd => {
+                crate::action::macos::retry_bootstrap(
+                    DARWIN_LAUNCHD_DOMAIN,
+                    LAUNCHD_SERVICE_NAME,
+                    Path::new(LAUNCHD_SERVICE_DEST),
+                )
+                .map_err(Self::error)?;
+            },
+            InitSystem::None
+            | InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => (),
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        match self.init {
+            InitSystem::Systemd => explanation.push(format!(
+                "Run `systemctl disable --now {SYSTEMD_TIMER_NAME}`"
+            )),
+            InitSystem::Launchd => explanation.push(format!(
+                "Run `launchctl bootout {DARWIN_LAUNCHD_DOMAIN}/{LAUNCHD_SERVICE_NAME}`"
+            )),
+            InitSystem::None
+            | InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => (),
+        }
+        for create_unit in &self.create_units {
+            explanation.push(format!("Delete `{}`", create_unit.inner().path.display()));
+        }
+        explanation.push(format!(
+            "Delete `{}`",
+            self.create_script.inner().path.display()
+        ));
+        explanation.push(format!(
+            "Delete `{}`",
+            self.create_urls.inner().path.display()
+        ));
+        for path in &self.refreshed_files {
+            if self.backups.iter().any(|backup| backup.original == *path) {
+                explanation.push(format!("Restore `{}` from its backup", path.display()));
+            } else {
+                explanation.push(format!("Delete `{}`", path.display()));
+            }
+        }
+
+        vec![ActionDescription::new(
+            format!(
+                "Remove the {init} job refreshing the managed Nix configuration",
+                init = self.init
+            ),
+            explanation,
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        match self.init {
+            InitSystem::Systemd => {
+                if let Err(err) = execute_command(
+                    Command::new("systemctl")
+                        .args(["disable", "--now", SYSTEMD_TIMER_NAME])
+                        .stdin(std::process::Stdio::null()),
+                ) {
+                    errors.push(Self::error(err));
+                }
+            },
+            InitSystem::Launchd => {
+                if let Err(err) =
+                    crate::action::macos::retry_bootout(DARWIN_LAUNCHD_DOMAIN, LAUNCHD_SERVICE_NAME)
+                {
+                    errors.push(Self::error(err));
+                }
+            },
+            InitSystem::None
+            | InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => (),
+        }
+
+        for create_unit in self.create_units.iter_mut().rev() {
+            if let Err(err) = create_unit.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = self.create_script.try_revert() {
+            errors.push(err);
+        }
+        if let Err(err) = self.create_urls.try_revert() {
+            errors.push(err);
+        }
+
+        for path in &self.refreshed_files {
+            if let Err(err) = crate::util::remove_file(path, OnMissing::Ignore) {
+                errors.push(Self::error(ActionErrorKind::Remove(path.clone(), err)));
+            }
+        }
+        for backup in &self.backups {
+            if let Err(err) = backup.restore() {
+                errors.push(Self::error(err));
+            }
+        }
+
+        if self.init == InitSystem::Systemd
+            && let Err(err) = execute_command(
+                Command::new("systemctl")
+                    .arg("daemon-reload")
+                    .stdin(std::process::Stdio::null()),
+            )
+        {
+            errors.push(Self::error(err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r#"'\''"#))
+}
+
+/// Each fetch on a line of its own, as `URL DEST MODE` (a URL never contains whitespace)
+fn refresh_urls(fetches: &[(&Url, PathBuf, u32)]) -> SensitiveString {
+    fetches
+        .iter()
+        .map(|(url, dest, mode)| format!("{url} {} {mode:o}\n", dest.display()))
+        .collect::<String>()
+        .into()
+}
+
+fn refresh_script(ssl_cert_file: Option<&Path>, client_cert: Option<&Path>) -> String {
+    let mut curl_args = String::new();
+    if let Some(ssl_cert_file) = ssl_cert_file {
+        curl_args.push_str(&format!(
+            " --cacert {}",
+            shell_quote(&ssl_cert_file.display().to_string())
+        ));
+    }
+    if let Some(client_cert) = client_cert {
+        curl_args.push_str(&format!(
+            " --cert {}",
+            shell_quote(&client_cert.display().to_string())
+        ));
+    }
+
+    format!(
+        "\
+        #!/bin/sh\n\
+        # Managed by `nix-installer`, refreshes the managed Nix configuration\n\
+        set -eu\n\
+        \n\
+        fetch() {{\n\
+        \x20   tmp=\"$2.nix-config-refresh\"\n\
+        \x20   curl --fail --silent --show-error --location{curl_args} --output \"$tmp\" \"$1\"\n\
+        \x20   chmod \"$3\" \"$tmp\"\n\
+        \x20   mv -f \"$tmp\" \"$2\"\n\
+        }}\n\
+        \n\
+        while read -r url dest mode; do\n\
+        \x20   fetch \"$url\" \"$dest\" \"$mode\"\n\
+        done < {urls}\n\
+    ",
+        urls = shell_quote(REFRESH_URLS_DEST),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn refresh_script_fetches_with_certs() -> Result<(), Box<dyn std::error::Error>> {
+        let registry_url = Url::parse("https://nix.example.com/registry.json?token=hunter2")?;
+        let urls = refresh_urls(&[(
+            &registry_url,
+            PathBuf::from("/etc/nix/registry.json"),
+            0o644,
+        )]);
+        let script = refresh_script(
+            Some(Path::new("/etc/ssl/corp-ca.pem")),
+            Some(Path::new("/etc/ssl/it's-me.pem")),
+        );
+
+        assert!(script.contains("--cacert '/etc/ssl/corp-ca.pem'"));
+        assert!(script.contains(r#"--cert '/etc/ssl/it'\''s-me.pem'"#));
+        assert!(script.contains("done < '/etc/nix/nix-config-refresh.urls'\n"));
+        assert!(!script.contains("hunter2"));
+        assert_eq!(
+            urls.expose(),
+            "https://nix.example.com/registry.json?token=hunter2 /etc/nix/registry.json 644\n"
+        );
+        assert!(!serde_json::to_string(&urls)?.contains("hunter2"));
+        Ok(())
+    }
+}
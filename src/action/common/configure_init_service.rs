@@ -1,3 +1,4 @@
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -6,7 +7,15 @@ use tracing::{Span, span};
 
 use crate::action::macos::DARWIN_LAUNCHD_DOMAIN;
 use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::backup::FileBackup;
+use crate::daemon_unit::{
+    DaemonLimits, DaemonUnitOverride, SystemdDropIn, caffeinate_launchd_plist, merge_launchd_plist,
+    systemd_environment,
+};
 use crate::execute_command;
+use crate::os::probe;
+use crate::prompt::{self, PromptAnswer, PromptRequest};
+use crate::proxy::Proxy;
 use crate::util::which;
 
 use crate::action::{Action, ActionDescription};
@@ -15,6 +24,68 @@ use crate::util::OnMissing;
 
 const TMPFILES_SRC: &str = "/nix/var/nix/profiles/default/lib/tmpfiles.d/nix-daemon.conf";
 const TMPFILES_DEST: &str = "/etc/tmpfiles.d/nix-daemon.conf";
+const DROP_IN_DIR: &str = "/etc/systemd/system/nix-daemon.service.d";
+const HARDENING_DROP_IN_DEST: &str =
+    "/etc/systemd/system/nix-daemon.service.d/nix-installer-hardening.conf";
+const PROXY_DROP_IN_DEST: &str =
+    "/etc/systemd/system/nix-daemon.service.d/nix-installer-proxy.conf";
+const LIMITS_DROP_IN_DEST: &str =
+    "/etc/systemd/system/nix-daemon.service.d/nix-installer-limits.conf";
+/// Sorted after the installer's own drop-ins, so `--daemon-unit-override` wins over them
+const OVERRIDE_DROP_IN_DEST: &str = "/etc/systemd/system/nix-daemon.service.d/user-override.conf";
+/// The drop-ins the installer writes, which are replaced (or removed) when it runs again
+const INSTALLER_DROP_INS: &[&str] = &[
+    HARDENING_DROP_IN_DEST,
+    PROXY_DROP_IN_DEST,
+    LIMITS_DROP_IN_DEST,
+    OVERRIDE_DROP_IN_DEST,
+];
+/// Sandboxing the daemon is known to tolerate
+///
+/// `LockPersonality=` is left out because Nix switches personality to build for `i686-linux`
+/// on `x86_64-linux`, and `ProtectControlGroups=` because of the `cgroups` experimental feature.
+const HARDENING: &[(&str, &str)] = &[
+    ("ProtectSystem", "strict"),
+    (
+        "ReadWritePaths",
+        "/nix -/root/.cache/nix -/root/.local/share/nix -/root/.local/state/nix",
+    ),
+    ("ProtectHome", "read-only"),
+    ("PrivateTmp", "yes"),
+    ("ProtectKernelModules", "yes"),
+    ("ProtectKernelLogs", "yes"),
+    ("ProtectClock", "yes"),
+    ("RestrictRealtime", "yes"),
+];
+const OPENRC_RUNLEVEL: &str = "default";
+/// The `rc.conf` variable enabling an `rc.d` service named `service_name`
+fn rcvar(service_name: &str) -> String {
+    format!("{service_name}_enable")
+}
+const SUPERVISOR_WAIT_ATTEMPTS: usize = 100;
+
+/// Where runit or s6 service definitions live, and the scan directory they are linked into to run them
+///
+/// Void Linux keeps runit services in `/etc/sv` and runs those in `/var/service`, Artix in `/etc/runit/sv` and `/run/runit/service`.
+pub(crate) fn supervision_dirs(init: InitSystem) -> (PathBuf, PathBuf) {
+    let first_existing = |candidates: [&str; 2]| {
+        candidates
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_dir())
+            .unwrap_or_else(|| PathBuf::from(candidates[1]))
+    };
+    match init {
+        InitSystem::S6 => (
+            PathBuf::from("/etc/s6/sv"),
+            first_existing(["/run/service", "/service"]),
+        ),
+        _ => (
+            first_existing(["/etc/runit/sv", "/etc/sv"]),
+            first_existing(["/run/runit/service", "/var/service"]),
+        ),
+    }
+}
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct SocketFile {
@@ -41,8 +112,7 @@ impl UnitSrc {
             UnitSrc::Literal(content) => {
                 tracing::trace!(src = %content, dest = %dest.display(), "Writing");
 
-                std::fs::write(dest, content)
-                    .map_err(|e| ActionErrorKind::Write(dest.to_path_buf(), e))?;
+                crate::util::write_atomic(dest, content)?;
             },
         }
 
@@ -63,50 +133,204 @@ pub struct ConfigureInitService {
     service_name: Option<String>,
     service_dest: Option<PathBuf>,
     socket_files: Vec<SocketFile>,
+    /// Add a drop-in sandboxing the daemon (systemd only)
+    #[serde(default)]
+    harden_daemon: bool,
+    /// Put this proxy in the daemon's environment (systemd and launchd only)
+    #[serde(default)]
+    proxy: Option<Proxy>,
+    /// Limit the daemon's niceness, CPU weight, I/O class and memory (systemd and launchd only)
+    #[serde(default)]
+    limits: Option<DaemonLimits>,
+    /// Run the daemon under `caffeinate`, keeping the Mac awake (launchd only)
+    #[serde(default)]
+    prevent_sleep: bool,
+    /// A drop-in, or plist keys, from `--daemon-unit-override` (systemd and launchd only)
+    #[serde(default)]
+    unit_override: Option<DaemonUnitOverride>,
+    /// The conflicting units or scripts which were replaced, restored when reverting
+    #[serde(default)]
+    backups: Vec<FileBackup>,
 }
 
 impl ConfigureInitService {
+    /// Sandbox the daemon with a drop-in, if the init supports it
+    pub fn harden_daemon(&mut self) {
+        if self.init == InitSystem::Systemd {
+            self.harden_daemon = true;
+        } else {
+            tracing::warn!(init = %self.init, "Only the systemd daemon can be hardened, continuing without");
+        }
+    }
+
+    /// Have the daemon use `proxy`, if the init supports setting its environment
+    pub(crate) fn use_proxy(&mut self, proxy: Proxy) {
+        if matches!(self.init, InitSystem::Systemd | InitSystem::Launchd) {
+            self.proxy = Some(proxy);
+        } else {
+            tracing::warn!(init = %self.init, "Only the systemd and launchd daemons can be given a proxy, continuing without");
+        }
+    }
+
+    /// Limit the daemon's resources, if the init supports it
+    pub(crate) fn limit_daemon(&mut self, limits: DaemonLimits) {
+        if matches!(self.init, InitSystem::Systemd | InitSystem::Launchd) {
+            self.limits = Some(limits);
+        } else {
+            tracing::warn!(init = %self.init, "Only the systemd and launchd daemons can have their resources limited, continuing without");
+        }
+    }
+
+    /// Keep the machine awake while the daemon runs, if the init supports it
+    pub(crate) fn prevent_sleep(&mut self) {
+        if self.init == InitSystem::Launchd {
+            self.prevent_sleep = true;
+        } else {
+            tracing::warn!(init = %self.init, "Only the launchd daemon can keep the machine awake, continuing without");
+        }
+    }
+
+    /// Merge the drop-in (or plist keys) at `path` into the daemon's service, if the init supports it
+    pub(crate) fn override_unit(&mut self, path: &Path) -> Result<(), ActionError> {
+        let unit_override = DaemonUnitOverride::read(path).map_err(Self::error)?;
+        match self.init {
+            InitSystem::Systemd => unit_override
+                .check_systemd()
+                .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?,
+            InitSystem::Launchd => {
+                unit_override
+                    .launchd_keys()
+                    .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+            },
+            _ => {
+                tracing::warn!(init = %self.init, "Only the systemd and launchd daemons can be overridden, continuing without");
+                return Ok(());
+            },
+        }
+        self.unit_override = Some(unit_override);
+        Ok(())
+    }
+
     pub(crate) fn check_if_systemd_unit_exists(
         src: &UnitSrc,
         dest: &Path,
     ) -> Result<(), ActionErrorKind> {
-        // TODO: once we have a way to communicate interaction between the library and the cli,
-        // interactively ask for permission to remove the file
-
         // NOTE: Check if the unit file already exists...
         let unit_dest = PathBuf::from(dest);
         if unit_dest.exists() {
-            match src {
-                UnitSrc::Path(unit_src) => {
-                    if unit_dest.is_symlink() {
-                        let link_dest = std::fs::read_link(&unit_dest)
-                            .map_err(|e| ActionErrorKind::ReadSymlink(unit_dest.clone(), e))?;
-                        if link_dest != *unit_src {
-                            return Err(ActionErrorKind::SymlinkExists(unit_dest));
-                        }
-                    } else {
-                        return Err(ActionErrorKind::FileExists(unit_dest));
+            match Self::check_existing_unit(src, unit_dest) {
+                Ok(()) => (),
+                Err(
+                    err @ (ActionErrorKind::SymlinkExists(_)
+                    | ActionErrorKind::FileExists(_)
+                    | ActionErrorKind::DifferentContent(_)),
+                ) => Self::confirm_replace(dest, err)?,
+                Err(err) => return Err(err),
+            }
+        }
+        // NOTE: ...and if there are any overrides in the most well-known places for systemd,
+        // other than the installer's own drop-ins from an earlier run
+        let dest_d = PathBuf::from(format!("{dest}.d", dest = dest.display()));
+        if dest_d.exists() {
+            for entry in std::fs::read_dir(&dest_d)
+                .map_err(|e| ActionErrorKind::ReadDir(dest_d.clone(), e))?
+            {
+                let entry = entry.map_err(|e| ActionErrorKind::ReadDir(dest_d.clone(), e))?;
+                if !INSTALLER_DROP_INS
+                    .iter()
+                    .any(|drop_in| entry.path() == Path::new(drop_in))
+                {
+                    return Err(ActionErrorKind::DirExists(dest_d));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_existing_unit(src: &UnitSrc, unit_dest: PathBuf) -> Result<(), ActionErrorKind> {
+        match src {
+            UnitSrc::Path(unit_src) => {
+                if unit_dest.is_symlink() {
+                    let link_dest = std::fs::read_link(&unit_dest)
+                        .map_err(|e| ActionErrorKind::ReadSymlink(unit_dest.clone(), e))?;
+                    if link_dest != *unit_src {
+                        return Err(ActionErrorKind::SymlinkExists(unit_dest));
                     }
-                },
-                UnitSrc::Literal(content) => {
-                    if unit_dest.is_symlink() {
-                        return Err(ActionErrorKind::FileExists(unit_dest));
-                    } else {
-                        let actual_content = std::fs::read_to_string(&unit_dest)
-                            .map_err(|e| ActionErrorKind::Read(unit_dest.clone(), e))?;
-                        if *content != actual_content {
-                            return Err(ActionErrorKind::DifferentContent(unit_dest));
-                        }
+                } else {
+                    return Err(ActionErrorKind::FileExists(unit_dest));
+                }
+            },
+            UnitSrc::Literal(content) => {
+                if unit_dest.is_symlink() {
+                    return Err(ActionErrorKind::FileExists(unit_dest));
+                } else {
+                    let actual_content = std::fs::read_to_string(&unit_dest)
+                        .map_err(|e| ActionErrorKind::Read(unit_dest.clone(), e))?;
+                    if *content != actual_content {
+                        return Err(ActionErrorKind::DifferentContent(unit_dest));
                     }
-                },
-            }
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Ask whether the conflicting `dest` may be replaced, returning `err` if not
+    fn confirm_replace(dest: &Path, err: ActionErrorKind) -> Result<(), ActionErrorKind> {
+        let request = PromptRequest::ReplaceFile {
+            path: dest.to_path_buf(),
+            reason: err.to_string(),
+        };
+        match prompt::ask(request) {
+            PromptAnswer::Yes => {
+                tracing::debug!(dest = %dest.display(), "Replacing conflicting file");
+                Ok(())
+            },
+            PromptAnswer::No => Err(err),
         }
-        // NOTE: ...and if there are any overrides in the most well-known places for systemd
-        let dest_d = format!("{dest}.d", dest = dest.display());
-        if Path::new(&dest_d).exists() {
-            return Err(ActionErrorKind::DirExists(PathBuf::from(dest_d)));
+    }
+
+    /// Back up `dest` if it exists and isn't already the unit from `src`, before replacing it
+    fn backup_replaced_unit(
+        src: &UnitSrc,
+        dest: &Path,
+        backups: &mut Vec<FileBackup>,
+    ) -> Result<(), ActionErrorKind> {
+        if (dest.exists() || dest.is_symlink())
+            && Self::check_existing_unit(src, dest.to_path_buf()).is_err()
+        {
+            backups.extend(FileBackup::create(dest)?);
         }
+        Ok(())
+    }
 
+    /// Write an OpenRC or SysVinit script to `service_dest`, confirming before replacing a different one
+    fn place_init_script(
+        service_src: &UnitSrc,
+        service_dest: &Path,
+        backups: &mut Vec<FileBackup>,
+    ) -> Result<(), ActionErrorKind> {
+        if let UnitSrc::Literal(content) = service_src
+            && service_dest.exists()
+        {
+            let actual_content = std::fs::read_to_string(service_dest)
+                .map_err(|e| ActionErrorKind::Read(service_dest.to_path_buf(), e))?;
+            if *content != actual_content {
+                Self::confirm_replace(
+                    service_dest,
+                    ActionErrorKind::DifferentContent(service_dest.to_path_buf()),
+                )?;
+                backups.extend(FileBackup::create(service_dest)?);
+            }
+        }
+
+        crate::util::remove_file(service_dest, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(service_dest.into(), e))?;
+        service_src.place(service_dest)?;
+        std::fs::set_permissions(service_dest, PermissionsExt::from_mode(0o755))
+            .map_err(|e| ActionErrorKind::SetPermissions(0o755, service_dest.to_path_buf(), e))?;
         Ok(())
     }
 
@@ -138,6 +362,33 @@ impl ConfigureInitService {
                     return Err(Self::error(ActionErrorKind::SystemdMissing));
                 }
             },
+            InitSystem::Openrc => {
+                if which("openrc").is_none() || which("rc-update").is_none() {
+                    return Err(Self::error(ActionErrorKind::OpenrcMissing));
+                }
+            },
+            InitSystem::SysVInit => {
+                if which("update-rc.d").is_none() && which("chkconfig").is_none() {
+                    return Err(Self::error(ActionErrorKind::SysVInitMissing));
+                }
+            },
+            InitSystem::Runit => {
+                if which("sv").is_none() {
+                    return Err(Self::error(ActionErrorKind::SupervisorMissing("sv")));
+                }
+            },
+            InitSystem::S6 => {
+                for command in ["s6-svc", "s6-svscanctl"] {
+                    if which(command).is_none() {
+                        return Err(Self::error(ActionErrorKind::SupervisorMissing(command)));
+                    }
+                }
+            },
+            InitSystem::RcD => {
+                if which("sysrc").is_none() || which("service").is_none() {
+                    return Err(Self::error(ActionErrorKind::RcdMissing));
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init system
             },
@@ -150,6 +401,12 @@ impl ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            harden_daemon: false,
+            proxy: None,
+            limits: None,
+            prevent_sleep: false,
+            unit_override: None,
+            backups: vec![],
         }
         .into())
     }
@@ -166,6 +423,13 @@ impl Action for ConfigureInitService {
             InitSystem::Launchd => {
                 "Configure Nix daemon related settings with launchctl".to_string()
             },
+            InitSystem::Openrc => "Configure Nix daemon related settings with OpenRC".to_string(),
+            InitSystem::SysVInit => {
+                "Configure Nix daemon related settings with SysVinit".to_string()
+            },
+            InitSystem::Runit => "Configure Nix daemon related settings with runit".to_string(),
+            InitSystem::S6 => "Configure Nix daemon related settings with s6".to_string(),
+            InitSystem::RcD => "Configure Nix daemon related settings with rc.d".to_string(),
             InitSystem::None => "Leave the Nix daemon unconfigured".to_string(),
         }
     }
@@ -217,6 +481,27 @@ impl Action for ConfigureInitService {
                         },
                     }
                 }
+                if self.harden_daemon {
+                    explanation.push(format!(
+                        "Create `{HARDENING_DROP_IN_DEST}` to sandbox the daemon"
+                    ));
+                }
+                if self.proxy.is_some() {
+                    explanation.push(format!(
+                        "Create `{PROXY_DROP_IN_DEST}` to give the daemon the proxy"
+                    ));
+                }
+                if self.limits.is_some() {
+                    explanation.push(format!(
+                        "Create `{LIMITS_DROP_IN_DEST}` to limit the daemon's resources"
+                    ));
+                }
+                if let Some(unit_override) = &self.unit_override {
+                    explanation.push(format!(
+                        "Create `{OVERRIDE_DROP_IN_DEST}` from `{}`",
+                        unit_override.path.display()
+                    ));
+                }
                 explanation.push("Run `systemctl daemon-reload`".to_string());
 
                 if self.start_daemon {
@@ -247,6 +532,28 @@ impl Action for ConfigureInitService {
                         ),
                     });
                 }
+                if self.proxy.is_some() {
+                    explanation.push(
+                        "Add the proxy to the `EnvironmentVariables` of the daemon".to_string(),
+                    );
+                }
+                if self.limits.is_some() {
+                    explanation.push(
+                        "Set the daemon's `Nice` and `LowPriorityIO` in its plist".to_string(),
+                    );
+                }
+                if self.prevent_sleep {
+                    explanation.push(
+                        "Run the daemon under `caffeinate -i -s`, so the Mac doesn't sleep while it runs"
+                            .to_string(),
+                    );
+                }
+                if let Some(unit_override) = &self.unit_override {
+                    explanation.push(format!(
+                        "Merge the keys in `{}` into the daemon's plist",
+                        unit_override.path.display()
+                    ));
+                }
 
                 if self.start_daemon {
                     explanation.push(format!(
@@ -259,6 +566,91 @@ impl Action for ConfigureInitService {
                 }
                 vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
             },
+            InitSystem::Openrc => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for OpenRC");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for OpenRC");
+                let mut explanation = vec![
+                    format!("Create `{}`", service_dest.display()),
+                    format!("Run `rc-update add {service_name} {OPENRC_RUNLEVEL}`"),
+                ];
+                if self.start_daemon {
+                    explanation.push(format!("Run `rc-service {service_name} start`"));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
+            InitSystem::SysVInit => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for SysVinit");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for SysVinit");
+                let mut explanation = vec![
+                    format!("Create `{}`", service_dest.display()),
+                    format!(
+                        "Run `update-rc.d {service_name} defaults` (or `chkconfig --add {service_name}`)"
+                    ),
+                ];
+                if self.start_daemon {
+                    explanation.push(format!("Run `{} start`", service_dest.display()));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
+            InitSystem::Runit | InitSystem::S6 => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for runit and s6");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for runit and s6");
+                let (_, scan_dir) = supervision_dirs(self.init);
+                let mut explanation = vec![format!("Create `{}`", service_dest.display())];
+                if !self.start_daemon {
+                    explanation.push(
+                        "Create a `down` file beside it, so the supervisor leaves the daemon stopped"
+                            .to_string(),
+                    );
+                }
+                explanation.push(format!(
+                    "Link the service into `{}` for the supervisor to run",
+                    scan_dir.display()
+                ));
+                if self.start_daemon {
+                    explanation.push(match self.init {
+                        InitSystem::Runit => format!("Run `sv up {service_name}`"),
+                        _ => format!("Run `s6-svc -u {}`", scan_dir.join(service_name).display()),
+                    });
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
+            InitSystem::RcD => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for rc.d");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for rc.d");
+                let mut explanation = vec![
+                    format!("Create `{}`", service_dest.display()),
+                    format!("Run `sysrc {}=YES`", rcvar(service_name)),
+                ];
+                if self.start_daemon {
+                    explanation.push(format!("Run `service {service_name} start`"));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
             InitSystem::None => (),
         }
         vec
@@ -273,6 +665,12 @@ impl Action for ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            harden_daemon,
+            proxy,
+            limits,
+            prevent_sleep,
+            unit_override,
+            backups,
         } = self;
 
         match init {
@@ -289,23 +687,36 @@ impl Action for ConfigureInitService {
                     match service_src {
                         UnitSrc::Path(src) => {
                             tracing::trace!(src = %src.display(), dest = %service_dest.display(), "Copying");
-                            std::fs::copy(&src, service_dest).map_err(|e| {
-                                Self::error(ActionErrorKind::Copy(
-                                    src.clone(),
-                                    PathBuf::from(service_dest),
-                                    e,
-                                ))
-                            })?;
+                            let content = std::fs::read(&src)
+                                .map_err(|e| ActionErrorKind::Read(src.clone(), e))
+                                .map_err(Self::error)?;
+                            crate::util::write_atomic(service_dest, content)
+                                .map_err(Self::error)?;
                         },
                         UnitSrc::Literal(content) => {
                             tracing::trace!(src = %content, dest = %service_dest.display(), "Writing");
 
-                            std::fs::write(service_dest, content)
-                                .map_err(|e| ActionErrorKind::Write(service_dest.clone(), e))
+                            crate::util::write_atomic(service_dest, content)
                                 .map_err(Self::error)?;
                         },
                     }
                 }
+                if let Some(proxy) = proxy {
+                    add_launchd_environment(service_dest, proxy).map_err(Self::error)?;
+                }
+                if let Some(limits) = limits {
+                    merge_launchd_plist(service_dest, limits.launchd_keys())
+                        .map_err(Self::error)?;
+                }
+                if *prevent_sleep {
+                    caffeinate_launchd_plist(service_dest).map_err(Self::error)?;
+                }
+                if let Some(unit_override) = unit_override {
+                    let keys = unit_override
+                        .launchd_keys()
+                        .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+                    merge_launchd_plist(service_dest, keys).map_err(Self::error)?;
+                }
 
                 crate::action::macos::retry_bootstrap(domain, service, service_dest)
                     .map_err(Self::error)?;
@@ -378,12 +789,11 @@ impl Action for ConfigureInitService {
                 )
                 .map_err(Self::error)?;
 
-                // TODO: once we have a way to communicate interaction between the library and the
-                // cli, interactively ask for permission to remove the file
-
                 if let Some(service_src) = service_src.as_ref() {
                     Self::check_if_systemd_unit_exists(service_src, service_dest)
                         .map_err(Self::error)?;
+                    Self::backup_replaced_unit(service_src, service_dest, backups)
+                        .map_err(Self::error)?;
 
                     crate::util::remove_file(service_dest, OnMissing::Ignore)
                         .map_err(|e| ActionErrorKind::Remove(service_dest.into(), e))
@@ -394,6 +804,7 @@ impl Action for ConfigureInitService {
 
                 for SocketFile { src, dest, .. } in socket_files.iter() {
                     Self::check_if_systemd_unit_exists(src, dest).map_err(Self::error)?;
+                    Self::backup_replaced_unit(src, dest, backups).map_err(Self::error)?;
                     crate::util::remove_file(dest, OnMissing::Ignore)
                         .map_err(|e| ActionErrorKind::Remove(dest.into(), e))
                         .map_err(Self::error)?;
@@ -414,13 +825,76 @@ impl Action for ConfigureInitService {
                         UnitSrc::Literal(content) => {
                             tracing::trace!(src = %content, dest = %dest.display(), "Writing");
 
-                            std::fs::write(dest, content)
-                                .map_err(|e| ActionErrorKind::Write(dest.clone(), e))
-                                .map_err(Self::error)?;
+                            crate::util::write_atomic(dest, content).map_err(Self::error)?;
                         },
                     }
                 }
 
+                // Drop-ins an earlier run wrote for settings since turned off
+                for (wanted, drop_in) in [
+                    (*harden_daemon, HARDENING_DROP_IN_DEST),
+                    (proxy.is_some(), PROXY_DROP_IN_DEST),
+                    (limits.is_some(), LIMITS_DROP_IN_DEST),
+                    (unit_override.is_some(), OVERRIDE_DROP_IN_DEST),
+                ] {
+                    if !wanted {
+                        crate::util::remove_file(Path::new(drop_in), OnMissing::Ignore)
+                            .map_err(|e| ActionErrorKind::Remove(PathBuf::from(drop_in), e))
+                            .map_err(Self::error)?;
+                    }
+                }
+
+                if *harden_daemon {
+                    std::fs::create_dir_all(DROP_IN_DIR)
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(DROP_IN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    crate::util::write_atomic(
+                        Path::new(HARDENING_DROP_IN_DEST),
+                        hardening_drop_in().render(),
+                    )
+                    .map_err(Self::error)?;
+                }
+
+                if let Some(proxy) = proxy {
+                    std::fs::create_dir_all(DROP_IN_DIR)
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(DROP_IN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    let dest = Path::new(PROXY_DROP_IN_DEST);
+                    crate::util::write_atomic(dest, proxy_drop_in(proxy).render())
+                        .map_err(Self::error)?;
+                    restrict_if_secret(dest, proxy).map_err(Self::error)?;
+                }
+
+                if let Some(limits) = limits {
+                    std::fs::create_dir_all(DROP_IN_DIR)
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(DROP_IN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    crate::util::write_atomic(
+                        Path::new(LIMITS_DROP_IN_DEST),
+                        limits.systemd_drop_in().render(),
+                    )
+                    .map_err(Self::error)?;
+                }
+
+                if let Some(unit_override) = unit_override {
+                    std::fs::create_dir_all(DROP_IN_DIR)
+                        .map_err(|e| {
+                            ActionErrorKind::CreateDirectory(PathBuf::from(DROP_IN_DIR), e)
+                        })
+                        .map_err(Self::error)?;
+                    crate::util::write_atomic(
+                        Path::new(OVERRIDE_DROP_IN_DEST),
+                        &unit_override.content,
+                    )
+                    .map_err(Self::error)?;
+                }
+
                 if *start_daemon {
                     execute_command(
                         Command::new("systemctl")
@@ -451,6 +925,170 @@ impl Action for ConfigureInitService {
                     }
                 }
             },
+            InitSystem::Openrc => {
+                let service_src = service_src
+                    .as_ref()
+                    .expect("service_src should be defined for OpenRC");
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for OpenRC");
+                let service_name = service_name
+                    .as_ref()
+                    .expect("service_name should be defined for OpenRC");
+
+                Self::place_init_script(service_src, service_dest, backups).map_err(Self::error)?;
+
+                execute_command(
+                    Command::new("rc-update")
+                        .args(["add", service_name, OPENRC_RUNLEVEL])
+                        .stdin(std::process::Stdio::null()),
+                )
+                .map_err(Self::error)?;
+
+                if *start_daemon {
+                    execute_command(
+                        Command::new("rc-service")
+                            .args([service_name, "start"])
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .map_err(Self::error)?;
+                }
+            },
+            InitSystem::SysVInit => {
+                let service_src = service_src
+                    .as_ref()
+                    .expect("service_src should be defined for SysVinit");
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for SysVinit");
+                let service_name = service_name
+                    .as_ref()
+                    .expect("service_name should be defined for SysVinit");
+
+                Self::place_init_script(service_src, service_dest, backups).map_err(Self::error)?;
+
+                let mut command = match which("update-rc.d") {
+                    Some(update_rc_d) => {
+                        let mut command = Command::new(update_rc_d);
+                        command.args([service_name, "defaults"]);
+                        command
+                    },
+                    None => {
+                        let mut command = Command::new("chkconfig");
+                        command.args(["--add", service_name]);
+                        command
+                    },
+                };
+                execute_command(command.stdin(std::process::Stdio::null())).map_err(Self::error)?;
+
+                if *start_daemon {
+                    execute_command(
+                        Command::new(service_dest)
+                            .arg("start")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .map_err(Self::error)?;
+                }
+            },
+            InitSystem::Runit | InitSystem::S6 => {
+                let service_src = service_src
+                    .as_ref()
+                    .expect("service_src should be defined for runit and s6");
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for runit and s6");
+                let service_name = service_name
+                    .as_ref()
+                    .expect("service_name should be defined for runit and s6");
+                let service_dir = service_dest
+                    .parent()
+                    .expect("service_dest should be inside a service directory");
+
+                std::fs::create_dir_all(service_dir)
+                    .map_err(|e| ActionErrorKind::CreateDirectory(service_dir.to_path_buf(), e))
+                    .map_err(Self::error)?;
+                Self::place_init_script(service_src, service_dest, backups).map_err(Self::error)?;
+
+                // Both supervisors leave a service with a `down` file stopped until asked to start it,
+                // including after a reboot
+                let down = service_dir.join("down");
+                if !*start_daemon {
+                    crate::util::write_atomic(&down, "").map_err(Self::error)?;
+                }
+
+                let (_, scan_dir) = supervision_dirs(*init);
+                let link = scan_dir.join(service_name);
+                crate::util::remove_file(&link, OnMissing::Ignore)
+                    .map_err(|e| ActionErrorKind::Remove(link.clone(), e))
+                    .map_err(Self::error)?;
+                std::os::unix::fs::symlink(service_dir, &link)
+                    .map_err(|e| {
+                        ActionErrorKind::Symlink(service_dir.to_path_buf(), link.clone(), e)
+                    })
+                    .map_err(Self::error)?;
+                if *init == InitSystem::S6 {
+                    // s6-svscan only notices new services when asked to rescan
+                    execute_command(
+                        Command::new("s6-svscanctl")
+                            .arg("-a")
+                            .arg(&scan_dir)
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .map_err(Self::error)?;
+                }
+
+                if *start_daemon {
+                    crate::util::remove_file(&down, OnMissing::Ignore)
+                        .map_err(|e| ActionErrorKind::Remove(down.clone(), e))
+                        .map_err(Self::error)?;
+
+                    let mut command = match init {
+                        InitSystem::Runit => {
+                            wait_for_supervisor(&link, "ok").map_err(Self::error)?;
+                            let mut command = Command::new("sv");
+                            command.arg("up").arg(&link);
+                            command
+                        },
+                        _ => {
+                            wait_for_supervisor(&link, "control").map_err(Self::error)?;
+                            let mut command = Command::new("s6-svc");
+                            command.arg("-u").arg(&link);
+                            command
+                        },
+                    };
+                    execute_command(command.stdin(std::process::Stdio::null()))
+                        .map_err(Self::error)?;
+                }
+            },
+            InitSystem::RcD => {
+                let service_src = service_src
+                    .as_ref()
+                    .expect("service_src should be defined for rc.d");
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for rc.d");
+                let service_name = service_name
+                    .as_ref()
+                    .expect("service_name should be defined for rc.d");
+
+                Self::place_init_script(service_src, service_dest, backups).map_err(Self::error)?;
+
+                execute_command(
+                    Command::new("sysrc")
+                        .arg(format!("{}=YES", rcvar(service_name)))
+                        .stdin(std::process::Stdio::null()),
+                )
+                .map_err(Self::error)?;
+
+                if *start_daemon {
+                    execute_command(
+                        Command::new("service")
+                            .args([service_name, "start"])
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .map_err(Self::error)?;
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init system
             },
@@ -469,6 +1107,18 @@ impl Action for ConfigureInitService {
                 }
 
                 steps.push("Run `systemctl disable nix-daemon.service`".to_string());
+                if self.harden_daemon {
+                    steps.push(format!("Remove `{HARDENING_DROP_IN_DEST}`"));
+                }
+                if self.proxy.is_some() {
+                    steps.push(format!("Remove `{PROXY_DROP_IN_DEST}`"));
+                }
+                if self.limits.is_some() {
+                    steps.push(format!("Remove `{LIMITS_DROP_IN_DEST}`"));
+                }
+                if self.unit_override.is_some() {
+                    steps.push(format!("Remove `{OVERRIDE_DROP_IN_DEST}`"));
+                }
                 steps.push("Run `systemd-tempfiles --remove --prefix=/nix/var/nix`".to_string());
                 steps.push("Run `systemctl daemon-reload`".to_string());
 
@@ -488,6 +1138,86 @@ impl Action for ConfigureInitService {
                     )],
                 )]
             },
+            InitSystem::Openrc => {
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for OpenRC");
+                vec![ActionDescription::new(
+                    "Unconfigure Nix daemon related settings with OpenRC".to_string(),
+                    vec![
+                        format!("Run `rc-service {service_name} stop`"),
+                        format!("Run `rc-update del {service_name} {OPENRC_RUNLEVEL}`"),
+                    ],
+                )]
+            },
+            InitSystem::SysVInit => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for SysVinit");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for SysVinit");
+                vec![ActionDescription::new(
+                    "Unconfigure Nix daemon related settings with SysVinit".to_string(),
+                    vec![
+                        format!("Run `{} stop`", service_dest.display()),
+                        format!(
+                            "Run `update-rc.d -f {service_name} remove` (or `chkconfig --del {service_name}`)"
+                        ),
+                    ],
+                )]
+            },
+            InitSystem::Runit | InitSystem::S6 => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for runit and s6");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for runit and s6");
+                let (_, scan_dir) = supervision_dirs(self.init);
+                let link = scan_dir.join(service_name);
+                vec![ActionDescription::new(
+                    format!(
+                        "Unconfigure Nix daemon related settings with {}",
+                        match self.init {
+                            InitSystem::Runit => "runit",
+                            _ => "s6",
+                        }
+                    ),
+                    vec![
+                        match self.init {
+                            InitSystem::Runit => format!("Run `sv down {service_name}`"),
+                            _ => format!("Run `s6-svc -d {}`", link.display()),
+                        },
+                        format!("Remove `{}`", link.display()),
+                        format!(
+                            "Remove `{}`",
+                            service_dest
+                                .parent()
+                                .expect("service_dest should be inside a service directory")
+                                .display()
+                        ),
+                    ],
+                )]
+            },
+            InitSystem::RcD => {
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be defined for rc.d");
+                vec![ActionDescription::new(
+                    "Unconfigure Nix daemon related settings with rc.d".to_string(),
+                    vec![
+                        format!("Run `service {service_name} onestop`"),
+                        format!("Run `sysrc -x {}`", rcvar(service_name)),
+                    ],
+                )]
+            },
             InitSystem::None => Vec::new(),
         }
     }
@@ -585,6 +1315,25 @@ impl Action for ConfigureInitService {
                     errors.push(err);
                 }
 
+                for (added, drop_in) in [
+                    (self.harden_daemon, HARDENING_DROP_IN_DEST),
+                    (self.proxy.is_some(), PROXY_DROP_IN_DEST),
+                    (self.limits.is_some(), LIMITS_DROP_IN_DEST),
+                    (self.unit_override.is_some(), OVERRIDE_DROP_IN_DEST),
+                ] {
+                    if !added {
+                        continue;
+                    }
+                    if let Err(err) =
+                        crate::util::remove_file(Path::new(drop_in), OnMissing::Ignore)
+                            .map_err(|e| ActionErrorKind::Remove(PathBuf::from(drop_in), e))
+                    {
+                        errors.push(err);
+                    }
+                    // Only if nothing else was dropped in beside it
+                    let _ = std::fs::remove_dir(DROP_IN_DIR);
+                }
+
                 if let Err(err) =
                     crate::util::remove_file(Path::new(TMPFILES_DEST), OnMissing::Ignore)
                         .map_err(|e| ActionErrorKind::Remove(PathBuf::from(TMPFILES_DEST), e))
@@ -600,6 +1349,163 @@ impl Action for ConfigureInitService {
                     errors.push(err);
                 }
             },
+            InitSystem::Openrc => {
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be set for OpenRC");
+
+                // `rc-service` exits non-zero if the service is already stopped, so only stop it if it's running
+                let is_started = Command::new("rc-service")
+                    .args([service_name.as_str(), "status"])
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success());
+                if is_started
+                    && let Err(err) = execute_command(
+                        Command::new("rc-service")
+                            .args([service_name.as_str(), "stop"])
+                            .stdin(std::process::Stdio::null()),
+                    )
+                {
+                    errors.push(err);
+                }
+
+                if let Err(err) = execute_command(
+                    Command::new("rc-update")
+                        .args(["del", service_name.as_str(), OPENRC_RUNLEVEL])
+                        .stdin(std::process::Stdio::null()),
+                ) {
+                    errors.push(err);
+                }
+            },
+            InitSystem::SysVInit => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be set for SysVinit");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be set for SysVinit");
+
+                // The script's `stop` succeeds even if the daemon isn't running
+                if service_dest.exists()
+                    && let Err(err) = execute_command(
+                        Command::new(service_dest)
+                            .arg("stop")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                {
+                    errors.push(err);
+                }
+
+                // `-f` as the script may already be gone
+                let mut command = match which("update-rc.d") {
+                    Some(update_rc_d) => {
+                        let mut command = Command::new(update_rc_d);
+                        command.args(["-f", service_name.as_str(), "remove"]);
+                        command
+                    },
+                    None => {
+                        let mut command = Command::new("chkconfig");
+                        command.args(["--del", service_name.as_str()]);
+                        command
+                    },
+                };
+                if let Err(err) = execute_command(command.stdin(std::process::Stdio::null())) {
+                    errors.push(err);
+                }
+            },
+            InitSystem::Runit | InitSystem::S6 => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be set for runit and s6");
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be set for runit and s6");
+                let (_, scan_dir) = supervision_dirs(self.init);
+                let link = scan_dir.join(service_name);
+
+                if link.is_symlink() {
+                    let mut command = match self.init {
+                        InitSystem::Runit => {
+                            let mut command = Command::new("sv");
+                            command.arg("down").arg(&link);
+                            command
+                        },
+                        _ => {
+                            let mut command = Command::new("s6-svc");
+                            command.arg("-d").arg(&link);
+                            command
+                        },
+                    };
+                    if let Err(err) = execute_command(command.stdin(std::process::Stdio::null())) {
+                        errors.push(err);
+                    }
+                    if let Err(err) = crate::util::remove_file(&link, OnMissing::Ignore)
+                        .map_err(|e| ActionErrorKind::Remove(link.clone(), e))
+                    {
+                        errors.push(err);
+                    }
+                    // Have s6-svscan stop supervising the removed service
+                    if self.init == InitSystem::S6
+                        && let Err(err) = execute_command(
+                            Command::new("s6-svscanctl")
+                                .arg("-an")
+                                .arg(&scan_dir)
+                                .stdin(std::process::Stdio::null()),
+                        )
+                    {
+                        errors.push(err);
+                    }
+                }
+
+                // Includes the `supervise` directory the supervisor kept its state in
+                if let Some(service_dir) = service_dest.parent()
+                    && service_dir.exists()
+                    && let Err(err) = std::fs::remove_dir_all(service_dir)
+                        .map_err(|e| ActionErrorKind::Remove(service_dir.to_path_buf(), e))
+                {
+                    errors.push(err);
+                }
+            },
+            InitSystem::RcD => {
+                let service_name = self
+                    .service_name
+                    .as_ref()
+                    .expect("service_name should be set for rc.d");
+
+                // `onestatus` and `onestop` work whether or not the service is still enabled
+                let is_started = Command::new("service")
+                    .args([service_name.as_str(), "onestatus"])
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success());
+                if is_started
+                    && let Err(err) = execute_command(
+                        Command::new("service")
+                            .args([service_name.as_str(), "onestop"])
+                            .stdin(std::process::Stdio::null()),
+                    )
+                {
+                    errors.push(err);
+                }
+
+                if let Err(err) = execute_command(
+                    Command::new("sysrc")
+                        .args(["-x", &rcvar(service_name)])
+                        .stdin(std::process::Stdio::null()),
+                ) {
+                    errors.push(err);
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init
             },
@@ -621,6 +1527,12 @@ impl Action for ConfigureInitService {
             }
         }
 
+        for backup in self.backups.iter() {
+            if let Err(err) = backup.restore() {
+                errors.push(err);
+            }
+        }
+
         if errors.is_empty() {
             Ok(())
         } else if errors.len() == 1 {
@@ -697,35 +1609,69 @@ fn disable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
     }
 }
 
-fn is_active(unit: &str) -> Result<bool, ActionErrorKind> {
-    let mut command = Command::new("systemctl");
-    command.arg("is-active");
-    command.arg(unit);
-    let output = command
-        .output()
-        .map_err(|e| ActionErrorKind::command(&command, e))?;
-    if String::from_utf8(output.stdout)?.starts_with("active") {
-        tracing::trace!(%unit, "Is active");
-        Ok(true)
-    } else {
-        tracing::trace!(%unit, "Is not active");
-        Ok(false)
+/// Wait for runit or s6 to start supervising `service_dir`, signalled by `supervise/{ready}` appearing
+fn wait_for_supervisor(service_dir: &Path, ready: &str) -> Result<(), ActionErrorKind> {
+    let ready = service_dir.join("supervise").join(ready);
+    for _ in 0..SUPERVISOR_WAIT_ATTEMPTS {
+        if ready.exists() {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    Err(ActionErrorKind::SupervisorTimeout(
+        service_dir.to_path_buf(),
+    ))
+}
+
+fn is_active(unit: &str) -> Result<bool, ActionErrorKind> {
+    let is_active = probe::systemd_unit_state(unit)?.is_active();
+    tracing::trace!(%unit, is_active, "Checked if unit is active");
+    Ok(is_active)
 }
 
 fn is_enabled(unit: &str) -> Result<bool, ActionErrorKind> {
-    let mut command = Command::new("systemctl");
-    command.arg("is-enabled");
-    command.arg(unit);
-    let output = command
-        .output()
-        .map_err(|e| ActionErrorKind::command(&command, e))?;
-    let stdout = String::from_utf8(output.stdout)?;
-    if stdout.starts_with("enabled") || stdout.starts_with("linked") {
-        tracing::trace!(%unit, "Is enabled");
-        Ok(true)
-    } else {
-        tracing::trace!(%unit, "Is not enabled");
-        Ok(false)
+    let is_enabled = probe::systemd_unit_state(unit)?.is_enabled();
+    tracing::trace!(%unit, is_enabled, "Checked if unit is enabled");
+    Ok(is_enabled)
+}
+
+fn hardening_drop_in() -> SystemdDropIn {
+    HARDENING
+        .iter()
+        .fold(SystemdDropIn::default(), |drop_in, (key, value)| {
+            drop_in.entry("Service", *key, *value)
+        })
+}
+
+/// A drop-in setting the variables `proxy` is read from in the daemon's environment
+fn proxy_drop_in(proxy: &Proxy) -> SystemdDropIn {
+    // Percent-encoded credentials have `%`s, which `systemd_environment` escapes
+    proxy
+        .environment()
+        .into_iter()
+        .fold(SystemdDropIn::default(), |drop_in, (key, value)| {
+            drop_in.entry("Service", "Environment", systemd_environment(key, &value))
+        })
+}
+
+/// Add the variables `proxy` is read from to the `EnvironmentVariables` of the plist at `dest`
+fn add_launchd_environment(dest: &Path, proxy: &Proxy) -> Result<(), ActionErrorKind> {
+    let environment = proxy
+        .environment()
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), plist::Value::String(value)))
+        .collect::<plist::Dictionary>();
+    let mut keys = plist::Dictionary::new();
+    keys.insert("EnvironmentVariables".into(), environment.into());
+    merge_launchd_plist(dest, keys)?;
+    restrict_if_secret(dest, proxy)
+}
+
+/// Only let root read `path` if the proxy in it has credentials
+fn restrict_if_secret(path: &Path, proxy: &Proxy) -> Result<(), ActionErrorKind> {
+    if crate::sensitive::url_has_secret(&proxy.url) {
+        std::fs::set_permissions(path, PermissionsExt::from_mode(0o600))
+            .map_err(|e| ActionErrorKind::SetPermissions(0o600, path.to_path_buf(), e))?;
     }
+    Ok(())
 }
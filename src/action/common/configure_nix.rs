@@ -6,11 +6,12 @@ use crate::{
         base::SetupDefaultProfile,
         common::{ConfigureShellProfile, PlaceNixConfiguration},
     },
+    plan::check_cancelled,
     planner::ShellProfileLocations,
     settings::{CommonSettings, SCRATCH_DIR},
 };
 
-use crate::action::common::SetupChannels;
+use crate::action::common::{SetupChannels, SetupNixNext};
 
 use tracing::{Span, span};
 
@@ -24,6 +25,8 @@ pub struct ConfigureNix {
     configure_shell_profile: Option<StatefulAction<ConfigureShellProfile>>,
     place_nix_configuration: Option<StatefulAction<PlaceNixConfiguration>>,
     setup_channels: Option<StatefulAction<SetupChannels>>,
+    #[serde(default)]
+    setup_nix_next: Option<StatefulAction<SetupNixNext>>,
 }
 
 impl ConfigureNix {
@@ -32,11 +35,17 @@ impl ConfigureNix {
         shell_profile_locations: ShellProfileLocations,
         settings: &CommonSettings,
     ) -> Result<StatefulAction<Self>, ActionError> {
+        let source = settings
+            .nix_source()
+            .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
         let setup_default_profile =
-            SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR)).map_err(Self::error)?;
+            SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR), source).map_err(Self::error)?;
 
         let configure_shell_profile = if settings.modify_profile {
-            Some(ConfigureShellProfile::plan(shell_profile_locations).map_err(Self::error)?)
+            Some(
+                ConfigureShellProfile::plan(shell_profile_locations, settings.shell_profile_scope)
+                    .map_err(Self::error)?,
+            )
         } else {
             None
         };
@@ -44,31 +53,70 @@ impl ConfigureNix {
         let place_nix_configuration = if settings.skip_nix_conf {
             None
         } else {
-            Some(
-                PlaceNixConfiguration::plan(
-                    settings.nix_build_group_name.clone(),
-                    settings.ssl_cert_file.clone(),
-                    settings.extra_conf.clone(),
-                    settings.force,
-                )
-                .map_err(Self::error)?,
-            )
+            Some(PlaceNixConfiguration::plan(settings).map_err(Self::error)?)
         };
 
         let setup_channels = if settings.add_channel {
-            Some(SetupChannels::plan().map_err(Self::error)?)
+            Some(SetupChannels::plan(settings.offline).map_err(Self::error)?)
         } else {
             None
         };
 
+        let setup_nix_next = match &settings.nix_next {
+            Some(store_path) => Some(SetupNixNext::plan(store_path).map_err(Self::error)?),
+            None => None,
+        };
+
         Ok(Self {
             place_nix_configuration,
             setup_default_profile,
             configure_shell_profile,
             setup_channels,
+            setup_nix_next,
         }
         .into())
     }
+
+    /// The tags of the optional steps which [`ConfigureNix::revert_step`] can revert on their own
+    pub(crate) fn step_tags(&self) -> Vec<&'static str> {
+        let mut tags = vec![];
+        if self.configure_shell_profile.is_some() {
+            tags.push(ConfigureShellProfile::action_tag().0);
+        }
+        if self.place_nix_configuration.is_some() {
+            tags.push(PlaceNixConfiguration::action_tag().0);
+        }
+        if self.setup_channels.is_some() {
+            tags.push(SetupChannels::action_tag().0);
+        }
+        if self.setup_nix_next.is_some() {
+            tags.push(SetupNixNext::action_tag().0);
+        }
+        tags
+    }
+
+    /// Revert only the optional step tagged `tag` (if planned), dropping it once reverted
+    pub(crate) fn revert_step(&mut self, tag: &str) -> Result<(), ActionError> {
+        fn revert<A: Action>(step: &mut Option<StatefulAction<A>>) -> Result<(), ActionError> {
+            if let Some(action) = step {
+                action.try_revert()?;
+            }
+            *step = None;
+            Ok(())
+        }
+
+        match tag {
+            tag if tag == ConfigureShellProfile::action_tag().0 => {
+                revert(&mut self.configure_shell_profile)
+            },
+            tag if tag == PlaceNixConfiguration::action_tag().0 => {
+                revert(&mut self.place_nix_configuration)
+            },
+            tag if tag == SetupChannels::action_tag().0 => revert(&mut self.setup_channels),
+            tag if tag == SetupNixNext::action_tag().0 => revert(&mut self.setup_nix_next),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[typetag::serde(name = "configure_nix")]
@@ -90,6 +138,7 @@ impl Action for ConfigureNix {
             place_nix_configuration,
             configure_shell_profile,
             setup_channels,
+            setup_nix_next,
         } = &self;
 
         let mut buf = setup_default_profile.describe_execute();
@@ -99,6 +148,9 @@ impl Action for ConfigureNix {
         if let Some(setup_channels) = setup_channels {
             buf.append(&mut setup_channels.describe_execute());
         }
+        if let Some(setup_nix_next) = setup_nix_next {
+            buf.append(&mut setup_nix_next.describe_execute());
+        }
         if let Some(configure_shell_profile) = configure_shell_profile {
             buf.append(&mut configure_shell_profile.describe_execute());
         }
@@ -112,6 +164,7 @@ impl Action for ConfigureNix {
             place_nix_configuration,
             configure_shell_profile,
             setup_channels,
+            setup_nix_next,
         } = self;
 
         let setup_default_profile_span = tracing::Span::current().clone();
@@ -121,9 +174,11 @@ impl Action for ConfigureNix {
 
         if let Some(place_nix_configuration) = place_nix_configuration {
             place_nix_configuration.try_execute().map_err(Self::error)?;
+            check_cancelled().map_err(Self::error)?;
         }
         setup_default_profile.try_execute().map_err(Self::error)?;
         if let Some(configure_shell_profile) = configure_shell_profile {
+            check_cancelled().map_err(Self::error)?;
             configure_shell_profile.try_execute().map_err(Self::error)?;
         }
 
@@ -132,9 +187,15 @@ impl Action for ConfigureNix {
         // Presumably there are conflicts with nix commands run in
         // setup_default_profile.
         if let Some(setup_channels) = setup_channels {
+            check_cancelled().map_err(Self::error)?;
             setup_channels.try_execute().map_err(Self::error)?;
         }
 
+        if let Some(setup_nix_next) = setup_nix_next {
+            check_cancelled().map_err(Self::error)?;
+            setup_nix_next.try_execute().map_err(Self::error)?;
+        }
+
         Ok(())
     }
 
@@ -144,6 +205,7 @@ impl Action for ConfigureNix {
             place_nix_configuration,
             configure_shell_profile,
             setup_channels,
+            setup_nix_next,
         } = &self;
 
         let mut buf = Vec::default();
@@ -157,6 +219,9 @@ impl Action for ConfigureNix {
         if let Some(setup_channels) = setup_channels {
             buf.append(&mut setup_channels.describe_revert());
         }
+        if let Some(setup_nix_next) = setup_nix_next {
+            buf.append(&mut setup_nix_next.describe_revert());
+        }
 
         buf
     }
@@ -164,6 +229,11 @@ impl Action for ConfigureNix {
     #[tracing::instrument(level = "debug", skip_all)]
     fn revert(&mut self) -> Result<(), ActionError> {
         let mut errors = vec![];
+        if let Some(setup_nix_next) = &mut self.setup_nix_next
+            && let Err(err) = setup_nix_next.try_revert()
+        {
+            errors.push(err);
+        }
         if let Some(configure_shell_profile) = &mut self.configure_shell_profile {
             if let Err(err) = configure_shell_profile.try_revert() {
                 errors.push(err);
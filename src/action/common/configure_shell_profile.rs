@@ -3,13 +3,23 @@ use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
 };
 use crate::planner::ShellProfileLocations;
+use crate::settings::{DEFAULT_PROFILE, ShellProfileScope};
 
-use nix::unistd::User;
+use nix::unistd::{AccessFlags, Group, User};
 use std::path::{Path, PathBuf};
 use tracing::{Span, span};
 
 const PROFILE_NIX_FILE_SHELL: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh";
-const PROFILE_NIX_FILE_FISH: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.fish";
+pub(crate) const PROFILE_NIX_FILE_FISH: &str =
+    "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.fish";
+
+/// Prepended to the block loading Nix in profiles which didn't exist, so their origin is clear
+const CREATED_PROFILE_HEADER: &str = "\
+# Created by https://github.com/NixOS/nix-installer to load Nix, as this file didn't exist.
+# Uninstalling Nix removes the block below, and this file too if nothing else was added to it.
+";
+
+const ETC_SHELLS: &str = "/etc/shells";
 
 /**
 Configure any detected shell profiles to include Nix support
@@ -24,9 +34,14 @@ pub struct ConfigureShellProfile {
 
 impl ConfigureShellProfile {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(locations: ShellProfileLocations) -> Result<StatefulAction<Self>, ActionError> {
+    pub fn plan(
+        locations: ShellProfileLocations,
+        scope: ShellProfileScope,
+    ) -> Result<StatefulAction<Self>, ActionError> {
         let mut create_or_insert_files = Vec::default();
         let mut create_directories = Vec::default();
+        // The shells whose profiles will load Nix, to check the login shell against
+        let mut configured_shells = Vec::default();
 
         let shell_buf = format!(
             "\n\
@@ -39,33 +54,6 @@ impl ConfigureShellProfile {
             inde = "    ", // indent
         );
 
-        for profile_target in locations.bash.iter().chain(locations.zsh.iter()) {
-            let profile_target_path = Path::new(profile_target);
-            if let Some(parent) = profile_target_path.parent() {
-                // Some tools (eg `nix-darwin`) create symlinks to these files, don't write to them if that's the case.
-                if !profile_target_path.is_symlink() {
-                    if !parent.exists() {
-                        create_directories.push(
-                            CreateDirectory::plan(parent, None, None, 0o0755, false)
-                                .map_err(Self::error)?,
-                        );
-                    }
-
-                    create_or_insert_files.push(
-                        CreateOrInsertIntoFile::plan(
-                            profile_target_path,
-                            None,
-                            None,
-                            0o644,
-                            shell_buf.to_string(),
-                            create_or_insert_into_file::Position::Beginning,
-                        )
-                        .map_err(Self::error)?,
-                    );
-                }
-            }
-        }
-
         let fish_buf = format!(
             "\n\
             # Nix\n\
@@ -77,57 +65,216 @@ impl ConfigureShellProfile {
             inde = "    ", // indent
         );
 
-        for fish_prefix in &locations.fish.confd_prefixes {
-            let fish_prefix_path = PathBuf::from(fish_prefix);
+        // Nushell can't source `nix-daemon.sh`, so set what it would
+        let nushell_buf = format!(
+            "\n\
+            # Nix\n\
+            if ('{DEFAULT_PROFILE}' | path exists) {{\n\
+            {inde}$env.NIX_PROFILES = $'{DEFAULT_PROFILE} ($env.HOME)/.nix-profile'\n\
+            {inde}$env.NIX_SSL_CERT_FILE = ($env.NIX_SSL_CERT_FILE? | default '{DEFAULT_PROFILE}/etc/ssl/certs/ca-bundle.crt')\n\
+            {inde}$env.PATH = ($env.PATH | split row (char esep) | prepend [$'($env.HOME)/.nix-profile/bin' '{DEFAULT_PROFILE}/bin'] | uniq)\n\
+            }}\n\
+            # End Nix\n\
+        \n",
+            inde = "    ", // indent
+        );
+
+        let xonsh_buf = format!(
+            "\n\
+            # Nix\n\
+            if p'{PROFILE_NIX_FILE_SHELL}'.exists():\n\
+            {inde}source-bash '{PROFILE_NIX_FILE_SHELL}'\n\
+            # End Nix\n\
+        \n",
+            inde = "    ", // indent
+        );
+
+        if scope.includes_system() {
+            for profile_target in locations.bash.iter().chain(locations.zsh.iter()) {
+                let profile_target_path = Path::new(profile_target);
+                if let Some(parent) = profile_target_path.parent() {
+                    // Some tools (eg `nix-darwin`) create symlinks to these files, don't write to them if that's the case.
+                    if !profile_target_path.is_symlink() {
+                        if !parent.exists() {
+                            create_directories.push(
+                                CreateDirectory::plan(parent, None, None, 0o0755, false)
+                                    .map_err(Self::error)?,
+                            );
+                        }
+
+                        create_or_insert_files.push(
+                            CreateOrInsertIntoFile::plan(
+                                profile_target_path,
+                                None,
+                                None,
+                                0o644,
+                                profile_buf(profile_target_path, &shell_buf)
+                                    .map_err(Self::error)?,
+                                create_or_insert_into_file::Position::Beginning,
+                            )
+                            .map_err(Self::error)?,
+                        );
+                        if locations.zsh.contains(profile_target) {
+                            configured_shells.push("zsh");
+                        } else {
+                            configured_shells.extend(["bash", "sh"]);
+                        }
+                    }
+                }
+            }
+
+            for fish_prefix in &locations.fish.confd_prefixes {
+                let fish_prefix_path = PathBuf::from(fish_prefix);
+
+                if !fish_prefix_path.exists() {
+                    // If the prefix doesn't exist, don't create the `conf.d/nix.fish`
+                    continue;
+                }
+
+                let mut profile_target = fish_prefix_path;
+                profile_target.push(locations.fish.confd_suffix.clone());
 
-            if !fish_prefix_path.exists() {
-                // If the prefix doesn't exist, don't create the `conf.d/nix.fish`
-                continue;
+                // Some tools (eg `nix-darwin`) create symlinks to these files, don't write to them if that's the case.
+                if !profile_target.is_symlink() {
+                    if let Some(conf_d) = profile_target.parent() {
+                        create_directories
+                            .push(CreateDirectory::plan(conf_d, None, None, 0o755, false)?);
+                    }
+
+                    let buf = profile_buf(&profile_target, &fish_buf).map_err(Self::error)?;
+                    create_or_insert_files.push(CreateOrInsertIntoFile::plan(
+                        profile_target,
+                        None,
+                        None,
+                        0o644,
+                        buf,
+                        create_or_insert_into_file::Position::Beginning,
+                    )?);
+                    configured_shells.push("fish");
+                }
             }
+            for fish_prefix in &locations.fish.vendor_confd_prefixes {
+                let fish_prefix_path = PathBuf::from(fish_prefix);
+
+                if !fish_prefix_path.exists() {
+                    // If the prefix doesn't exist, don't create the `conf.d/nix.fish`
+                    continue;
+                }
 
-            let mut profile_target = fish_prefix_path;
-            profile_target.push(locations.fish.confd_suffix.clone());
+                let mut profile_target = fish_prefix_path;
+                profile_target.push(locations.fish.vendor_confd_suffix.clone());
 
-            // Some tools (eg `nix-darwin`) create symlinks to these files, don't write to them if that's the case.
-            if !profile_target.is_symlink() {
                 if let Some(conf_d) = profile_target.parent() {
                     create_directories
                         .push(CreateDirectory::plan(conf_d, None, None, 0o755, false)?);
                 }
 
+                let buf = profile_buf(&profile_target, &fish_buf).map_err(Self::error)?;
                 create_or_insert_files.push(CreateOrInsertIntoFile::plan(
                     profile_target,
                     None,
                     None,
                     0o644,
-                    fish_buf.to_string(),
+                    buf,
                     create_or_insert_into_file::Position::Beginning,
                 )?);
+                configured_shells.push("fish");
             }
-        }
-        for fish_prefix in &locations.fish.vendor_confd_prefixes {
-            let fish_prefix_path = PathBuf::from(fish_prefix);
 
-            if !fish_prefix_path.exists() {
-                // If the prefix doesn't exist, don't create the `conf.d/nix.fish`
-                continue;
-            }
+            for (shell, confd, buf) in [
+                ("nu", &locations.nushell, &nushell_buf),
+                ("xonsh", &locations.xonsh, &xonsh_buf),
+            ] {
+                for prefix in &confd.prefixes {
+                    if !prefix.exists() {
+                        // If the shell isn't installed here, don't create the file
+                        continue;
+                    }
 
-            let mut profile_target = fish_prefix_path;
-            profile_target.push(locations.fish.vendor_confd_suffix.clone());
+                    let profile_target = prefix.join(&confd.suffix);
+                    if profile_target.is_symlink() {
+                        continue;
+                    }
+                    if let Some(parent) = profile_target.parent() {
+                        if !parent.exists() {
+                            create_directories
+                                .push(CreateDirectory::plan(parent, None, None, 0o755, false)?);
+                        }
+                    }
 
-            if let Some(conf_d) = profile_target.parent() {
-                create_directories.push(CreateDirectory::plan(conf_d, None, None, 0o755, false)?);
+                    let buf = profile_buf(&profile_target, buf).map_err(Self::error)?;
+                    create_or_insert_files.push(CreateOrInsertIntoFile::plan(
+                        profile_target,
+                        None,
+                        None,
+                        0o644,
+                        buf,
+                        create_or_insert_into_file::Position::Beginning,
+                    )?);
+                    configured_shells.push(shell);
+                }
             }
+        }
 
-            create_or_insert_files.push(CreateOrInsertIntoFile::plan(
-                profile_target,
-                None,
-                None,
-                0o644,
-                fish_buf.to_string(),
-                create_or_insert_into_file::Position::Beginning,
-            )?);
+        if scope.includes_user() {
+            match sudo_user().map_err(Self::error)? {
+                Some((user, group)) => {
+                    let home = user.dir.clone();
+                    // Existing rc files, or for shells with a config directory, its snippet files
+                    let user_profiles = [
+                        ("bash", home.join(".bashrc"), &shell_buf, None),
+                        ("zsh", home.join(".zshrc"), &shell_buf, None),
+                        (
+                            "fish",
+                            home.join(".config/fish/conf.d/nix.fish"),
+                            &fish_buf,
+                            Some(home.join(".config/fish")),
+                        ),
+                        (
+                            "nu",
+                            home.join(".config/nushell/env.nu"),
+                            &nushell_buf,
+                            Some(home.join(".config/nushell")),
+                        ),
+                        ("xonsh", home.join(".xonshrc"), &xonsh_buf, None),
+                    ];
+                    for (shell, profile_target, buf, config_dir) in user_profiles {
+                        let in_use = match &config_dir {
+                            Some(config_dir) => config_dir.is_dir(),
+                            None => profile_target.is_file(),
+                        };
+                        // Home manager and friends link these from the store
+                        if !in_use || profile_target.is_symlink() {
+                            continue;
+                        }
+                        if let Some(parent) = profile_target.parent() {
+                            if !parent.exists() {
+                                create_directories.push(CreateDirectory::plan(
+                                    parent,
+                                    user.name.clone(),
+                                    group.clone(),
+                                    0o755,
+                                    false,
+                                )?);
+                            }
+                        }
+
+                        let buf = profile_buf(&profile_target, buf).map_err(Self::error)?;
+                        create_or_insert_files.push(CreateOrInsertIntoFile::plan(
+                            profile_target,
+                            user.name.clone(),
+                            group.clone(),
+                            None,
+                            buf,
+                            create_or_insert_into_file::Position::Beginning,
+                        )?);
+                        configured_shells.push(shell);
+                    }
+                },
+                None => tracing::warn!(
+                    "Not running through `sudo`, so there are no user shell profiles to configure"
+                ),
+            }
         }
 
         // If the `$GITHUB_PATH` environment exists, we're almost certainly running on Github
@@ -154,6 +301,8 @@ impl ConfigureShellProfile {
             )?);
         }
 
+        check_login_shell(&configured_shells);
+
         Ok(Self {
             locations,
             create_directories,
@@ -163,6 +312,97 @@ impl ConfigureShellProfile {
     }
 }
 
+/// The block to insert into `profile`, after checking it can be added to
+///
+/// The profile must be UTF-8 and writable. One which doesn't exist is created, with a
+/// [`CREATED_PROFILE_HEADER`].
+fn profile_buf(profile: &Path, buf: &str) -> Result<String, ActionErrorKind> {
+    let contents = match std::fs::read(profile) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(format!("{CREATED_PROFILE_HEADER}{buf}"));
+        },
+        Err(e) => return Err(ActionErrorKind::Read(profile.to_path_buf(), e)),
+    };
+
+    if std::str::from_utf8(&contents).is_err() {
+        return Err(ConfigureShellProfileError::NotUtf8(profile.to_path_buf()).into());
+    }
+    if contents.windows(2).any(|pair| pair == b"\r\n") {
+        tracing::warn!(
+            "`{}` has Windows (CRLF) line endings, which shells may choke on",
+            profile.display()
+        );
+    }
+    nix::unistd::access(profile, AccessFlags::W_OK)
+        .map_err(|e| ConfigureShellProfileError::NotWritable(profile.to_path_buf(), e))?;
+
+    Ok(buf.to_string())
+}
+
+/// Warn if the login shell of the user installing won't load Nix, or isn't in `/etc/shells`
+fn check_login_shell(configured_shells: &[&str]) {
+    let user = match sudo_user() {
+        Ok(Some((user, _))) => user,
+        _ => match User::from_uid(nix::unistd::getuid()) {
+            Ok(Some(user)) => user,
+            _ => return,
+        },
+    };
+    let shell = user.shell;
+    if shell.as_os_str().is_empty() {
+        return;
+    }
+
+    if let Ok(etc_shells) = std::fs::read_to_string(ETC_SHELLS) {
+        if !is_listed_shell(&etc_shells, &shell) {
+            tracing::warn!(
+                "`{}`'s login shell `{}` isn't listed in `{ETC_SHELLS}`, so programs like `chsh` may treat it as invalid",
+                user.name,
+                shell.display()
+            );
+        }
+    }
+
+    let Some(name) = shell.file_name().and_then(|name| name.to_str()) else {
+        return;
+    };
+    let known_shells = ["sh", "bash", "zsh", "fish", "nu", "xonsh"];
+    if known_shells.contains(&name) && !configured_shells.contains(&name) {
+        tracing::warn!(
+            "No `{name}` profile will be configured, so `{}`'s login shell `{}` won't load Nix; source `{PROFILE_NIX_FILE_SHELL}` from its configuration, or use `--shell-profile-scope user` or `both`",
+            user.name,
+            shell.display()
+        );
+    }
+}
+
+/// If `shell` is one of the lines of `/etc/shells`
+fn is_listed_shell(etc_shells: &str, shell: &Path) -> bool {
+    etc_shells
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .any(|line| Path::new(line) == shell)
+}
+
+/// The user who ran the installer through `sudo`, and the name of their primary group
+fn sudo_user() -> Result<Option<(User, String)>, ActionErrorKind> {
+    let Ok(name) = std::env::var("SUDO_USER") else {
+        return Ok(None);
+    };
+    let Some(user) =
+        User::from_name(&name).map_err(|e| ActionErrorKind::GettingUserId(name.clone(), e))?
+    else {
+        return Ok(None);
+    };
+    let group = Group::from_gid(user.gid)
+        .map_err(|e| ActionErrorKind::GettingGroupId(user.gid.to_string(), e))?
+        .map(|group| group.name)
+        .ok_or_else(|| ActionErrorKind::NoGroup(user.gid.to_string()))?;
+    Ok(Some((user, group)))
+}
+
 #[typetag::serde(name = "configure_shell_profile")]
 impl Action for ConfigureShellProfile {
     fn action_tag() -> ActionTag {
@@ -243,3 +483,54 @@ impl Action for ConfigureShellProfile {
         }
     }
 }
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigureShellProfileError {
+    #[error(
+        "Shell profile `{0}` isn't UTF-8 text, so Nix can't be added to it; convert it (for example with `iconv`) or use `--no-modify-profile`"
+    )]
+    NotUtf8(PathBuf),
+    #[error(
+        "Shell profile `{0}` isn't writable, so Nix can't be added to it; check whether it is immutable or on a read-only filesystem, or use `--no-modify-profile`"
+    )]
+    NotWritable(PathBuf, #[source] nix::errno::Errno),
+}
+
+impl From<ConfigureShellProfileError> for ActionErrorKind {
+    fn from(val: ConfigureShellProfileError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_profiles_are_documented_and_binary_ones_refused()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let zshrc = temp_dir.path().join("zshrc");
+
+        let buf = profile_buf(&zshrc, "\n# Nix\n# End Nix\n")?;
+        assert!(buf.starts_with(CREATED_PROFILE_HEADER));
+
+        std::fs::write(&zshrc, "export EDITOR=vi\n")?;
+        assert_eq!(
+            profile_buf(&zshrc, "\n# Nix\n# End Nix\n")?,
+            "\n# Nix\n# End Nix\n"
+        );
+
+        std::fs::write(&zshrc, b"\xff\xfe# UTF-16\n")?;
+        assert!(profile_buf(&zshrc, "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn login_shell_listed_in_etc_shells() {
+        let etc_shells = "# /etc/shells: valid login shells\n/bin/sh\n/usr/bin/zsh\n";
+        assert!(is_listed_shell(etc_shells, Path::new("/usr/bin/zsh")));
+        assert!(!is_listed_shell(etc_shells, Path::new("/usr/bin/fish")));
+    }
+}
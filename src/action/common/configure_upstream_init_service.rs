@@ -1,11 +1,13 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tracing::{Span, span};
 
 use crate::action::{ActionError, ActionTag, StatefulAction};
 
-use crate::action::common::configure_init_service::{SocketFile, UnitSrc};
+use crate::action::common::configure_init_service::{SocketFile, UnitSrc, supervision_dirs};
 use crate::action::{Action, ActionDescription, common::ConfigureInitService};
+use crate::daemon_unit::DaemonLimits;
+use crate::proxy::Proxy;
 use crate::settings::InitSystem;
 
 // Linux
@@ -18,6 +20,116 @@ const DARWIN_NIX_DAEMON_SOURCE: &str =
 pub(crate) const DARWIN_NIX_DAEMON_DEST: &str = "/Library/LaunchDaemons/org.nixos.nix-daemon.plist";
 const DARWIN_LAUNCHD_SERVICE_NAME: &str = "org.nixos.nix-daemon";
 
+// OpenRC
+const OPENRC_SERVICE_DEST: &str = "/etc/init.d/nix-daemon";
+const OPENRC_SERVICE_NAME: &str = "nix-daemon";
+// Nix doesn't ship an OpenRC service, so we provide our own
+const OPENRC_SERVICE_CONTENT: &str = "\
+#!/sbin/openrc-run
+
+description=\"Nix multi-user support daemon\"
+command=\"/nix/var/nix/profiles/default/bin/nix-daemon\"
+command_background=\"yes\"
+pidfile=\"/run/${RC_SVCNAME}.pid\"
+
+depend() {
+\tneed localmount
+\tafter firewall
+}
+";
+
+// SysVinit
+const SYSVINIT_SERVICE_DEST: &str = "/etc/init.d/nix-daemon";
+const SYSVINIT_SERVICE_NAME: &str = "nix-daemon";
+// Nix doesn't ship an init script either, this one is understood by both `update-rc.d` (LSB
+// headers) and `chkconfig`
+const SYSVINIT_SERVICE_CONTENT: &str = r#"#!/bin/sh
+### BEGIN INIT INFO
+# Provides:          nix-daemon
+# Required-Start:    $local_fs $remote_fs
+# Required-Stop:     $local_fs $remote_fs
+# Default-Start:     2 3 4 5
+# Default-Stop:      0 1 6
+# Short-Description: Nix multi-user support daemon
+### END INIT INFO
+# chkconfig: 2345 20 80
+# description: Nix multi-user support daemon
+
+DAEMON=/nix/var/nix/profiles/default/bin/nix-daemon
+PIDFILE=/var/run/nix-daemon.pid
+
+is_running() {
+	[ -f "$PIDFILE" ] && kill -0 "$(cat "$PIDFILE")" 2>/dev/null
+}
+
+case "$1" in
+	start)
+		is_running && exit 0
+		echo "Starting nix-daemon"
+		"$DAEMON" --daemon </dev/null >/dev/null 2>&1 &
+		echo $! > "$PIDFILE"
+		;;
+	stop)
+		if is_running; then
+			echo "Stopping nix-daemon"
+			kill "$(cat "$PIDFILE")"
+		fi
+		rm -f "$PIDFILE"
+		;;
+	restart|force-reload)
+		"$0" stop
+		"$0" start
+		;;
+	status)
+		if is_running; then
+			echo "nix-daemon is running"
+		else
+			echo "nix-daemon is not running"
+			exit 3
+		fi
+		;;
+	*)
+		echo "Usage: $0 {start|stop|restart|force-reload|status}" >&2
+		exit 3
+		;;
+esac
+"#;
+
+// runit and s6
+const SUPERVISED_SERVICE_NAME: &str = "nix-daemon";
+// Both supervisors run `run` in the foreground and restart it when it exits
+const SUPERVISED_SERVICE_CONTENT: &str = "\
+#!/bin/sh
+exec /nix/var/nix/profiles/default/bin/nix-daemon 2>&1
+";
+
+// rc.d (FreeBSD)
+const RCD_SERVICE_DEST: &str = "/usr/local/etc/rc.d/nix_daemon";
+const RCD_SERVICE_NAME: &str = "nix_daemon";
+// `daemon(8)` backgrounds and restarts the Nix daemon, sending its output to syslog
+const RCD_SERVICE_CONTENT: &str = r#"#!/bin/sh
+
+# PROVIDE: nix_daemon
+# REQUIRE: LOGIN FILESYSTEMS
+# KEYWORD: shutdown
+
+. /etc/rc.subr
+
+name="nix_daemon"
+desc="Nix multi-user support daemon"
+rcvar="nix_daemon_enable"
+
+load_rc_config $name
+
+: ${nix_daemon_enable:="NO"}
+
+pidfile="/var/run/${name}.pid"
+command="/usr/sbin/daemon"
+command_args="-r -S -T nix-daemon -P ${pidfile} /nix/var/nix/profiles/default/bin/nix-daemon"
+
+run_rc_command "$1"
+"#;
+
 /**
 Configure the init to run the Nix daemon
 */
@@ -29,43 +141,98 @@ pub struct ConfigureUpstreamInitService {
 
 impl ConfigureUpstreamInitService {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(init: InitSystem, start_daemon: bool) -> Result<StatefulAction<Self>, ActionError> {
+    pub fn plan(
+        init: InitSystem,
+        start_daemon: bool,
+        harden_daemon: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
         let service_src: Option<UnitSrc> = match init {
             InitSystem::Launchd => Some(UnitSrc::Path(DARWIN_NIX_DAEMON_SOURCE.into())),
             InitSystem::Systemd => Some(UnitSrc::Path(SERVICE_SRC.into())),
+            InitSystem::Openrc => Some(UnitSrc::Literal(OPENRC_SERVICE_CONTENT.into())),
+            InitSystem::SysVInit => Some(UnitSrc::Literal(SYSVINIT_SERVICE_CONTENT.into())),
+            InitSystem::RcD => Some(UnitSrc::Literal(RCD_SERVICE_CONTENT.into())),
+            InitSystem::Runit | InitSystem::S6 => {
+                Some(UnitSrc::Literal(SUPERVISED_SERVICE_CONTENT.into()))
+            },
             InitSystem::None => None,
         };
         let service_dest: Option<PathBuf> = match init {
             InitSystem::Launchd => Some(DARWIN_NIX_DAEMON_DEST.into()),
             InitSystem::Systemd => Some(SERVICE_DEST.into()),
+            InitSystem::Openrc => Some(OPENRC_SERVICE_DEST.into()),
+            InitSystem::SysVInit => Some(SYSVINIT_SERVICE_DEST.into()),
+            InitSystem::RcD => Some(RCD_SERVICE_DEST.into()),
+            InitSystem::Runit | InitSystem::S6 => Some(
+                supervision_dirs(init)
+                    .0
+                    .join(SUPERVISED_SERVICE_NAME)
+                    .join("run"),
+            ),
             InitSystem::None => None,
         };
         let service_name: Option<String> = match init {
             InitSystem::Launchd => Some(DARWIN_LAUNCHD_SERVICE_NAME.into()),
+            InitSystem::Openrc => Some(OPENRC_SERVICE_NAME.into()),
+            InitSystem::SysVInit => Some(SYSVINIT_SERVICE_NAME.into()),
+            InitSystem::RcD => Some(RCD_SERVICE_NAME.into()),
+            InitSystem::Runit | InitSystem::S6 => Some(SUPERVISED_SERVICE_NAME.into()),
             _ => None,
         };
-
-        let configure_init_service = ConfigureInitService::plan(
-            init,
-            start_daemon,
-            service_src,
-            service_dest,
-            service_name,
-            vec![SocketFile {
+        // Only systemd has socket activation, elsewhere the daemon is started directly
+        let socket_files = match init {
+            InitSystem::Openrc
+            | InitSystem::SysVInit
+            | InitSystem::Runit
+            | InitSystem::S6
+            | InitSystem::RcD => vec![],
+            _ => vec![SocketFile {
                 name: "nix-daemon.socket".into(),
                 src: UnitSrc::Path(
                     "/nix/var/nix/profiles/default/lib/systemd/system/nix-daemon.socket".into(),
                 ),
                 dest: "/etc/systemd/system/nix-daemon.socket".into(),
             }],
+        };
+
+        let mut configure_init_service = ConfigureInitService::plan(
+            init,
+            start_daemon,
+            service_src,
+            service_dest,
+            service_name,
+            socket_files,
         )
         .map_err(Self::error)?;
+        if harden_daemon {
+            configure_init_service.action.harden_daemon();
+        }
 
         Ok(Self {
             configure_init_service,
         }
         .into())
     }
+
+    /// Have the daemon use `proxy`, see [`ConfigureInitService`]
+    pub(crate) fn use_proxy(&mut self, proxy: Proxy) {
+        self.configure_init_service.action.use_proxy(proxy);
+    }
+
+    /// Limit the daemon's resources, see [`ConfigureInitService`]
+    pub(crate) fn limit_daemon(&mut self, limits: DaemonLimits) {
+        self.configure_init_service.action.limit_daemon(limits);
+    }
+
+    /// Keep the machine awake while the daemon runs, see [`ConfigureInitService`]
+    pub(crate) fn prevent_sleep(&mut self) {
+        self.configure_init_service.action.prevent_sleep();
+    }
+
+    /// Merge the drop-in (or plist keys) at `path` into the daemon's service, see [`ConfigureInitService`]
+    pub(crate) fn override_unit(&mut self, path: &Path) -> Result<(), ActionError> {
+        self.configure_init_service.action.override_unit(path)
+    }
 }
 
 #[typetag::serde(name = "create_upstream_init_service")]
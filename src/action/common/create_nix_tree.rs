@@ -201,3 +201,68 @@ fn ensure_nix_var_ownership() -> Result<(), ActionErrorKind> {
     }
     Ok(())
 }
+
+/// The per-user directories skipped by [`ensure_nix_var_ownership`]
+const PER_USER_PATHS: &[&str] = &[
+    "/nix/var/nix/profiles/per-user",
+    "/nix/var/nix/gcroots/per-user",
+];
+
+/// Nix refuses to use a per-user profile directory its user doesn't own, which happens when one
+/// was created by root (such as from `sudo nix-env`).
+///
+/// This function re-owns root-owned paths under each `per-user/<name>` to `<name>`, if that user
+/// exists and isn't root.
+pub(crate) fn ensure_per_user_ownership() -> Result<(), ActionErrorKind> {
+    for per_user in PER_USER_PATHS {
+        let entries = match std::fs::read_dir(per_user) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(ActionErrorKind::Read(per_user.into(), e)),
+        };
+
+        for entry in entries {
+            let entry = entry.map_err(|e| ActionErrorKind::Read(per_user.into(), e))?;
+            let Some(name) = entry.file_name().to_str().map(ToString::to_string) else {
+                continue;
+            };
+            let user = match nix::unistd::User::from_name(&name) {
+                Ok(Some(user)) if !user.uid.is_root() => user,
+                Ok(_) => continue,
+                Err(e) => return Err(ActionErrorKind::GettingUserId(name, e)),
+            };
+
+            let root_owned = walkdir::WalkDir::new(entry.path())
+                .follow_links(false)
+                .same_file_system(true)
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        tracing::warn!(%e, "Failed to get entry in {per_user}");
+                        None
+                    },
+                })
+                .filter(|entry| entry.metadata().is_ok_and(|metadata| metadata.uid() == 0));
+            for entry in root_owned {
+                tracing::debug!(
+                    path = %entry.path().to_string_lossy(),
+                    "Re-owning path to `{name}`"
+                );
+
+                if let Err(e) = std::os::unix::fs::lchown(
+                    entry.path(),
+                    Some(user.uid.as_raw()),
+                    Some(user.gid.as_raw()),
+                ) {
+                    tracing::warn!(
+                        path = %entry.path().to_string_lossy(),
+                        %e,
+                        "Failed to set the owner to `{name}`"
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
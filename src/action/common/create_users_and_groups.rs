@@ -1,12 +1,19 @@
 use crate::{
     action::{
-        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionEstimate, ActionState,
+        ActionTag, StatefulAction,
         base::{AddUserToGroup, CreateGroup, CreateUser},
+        linux::create_sysusers_build_users::check_homed_conflicts,
     },
-    settings::CommonSettings,
+    plan::check_cancelled,
+    settings::{CommonSettings, IdConflict},
 };
+use nix::unistd::{Gid, Group, Uid, User};
 use tracing::{Span, span};
 
+/// How many UID ranges above the configured one are tried when looking for a free range
+const RENUMBER_ATTEMPTS: u32 = 64;
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_users_and_group")]
 pub struct CreateUsersAndGroups {
@@ -22,20 +29,81 @@ pub struct CreateUsersAndGroups {
 
 impl CreateUsersAndGroups {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(settings: CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+    pub fn plan(mut settings: CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+        check_homed_conflicts(&settings).map_err(Self::error)?;
+
+        let id_conflict = settings.id_conflict_strategy();
+        settings.nix_build_group_id =
+            resolve_group_id(&settings, id_conflict, &HostUsers).map_err(Self::error)?;
+
+        // The UID and primary GID of each build user
+        let mut user_ids = (1..=settings.nix_build_user_count)
+            .map(|index| {
+                (
+                    settings.nix_build_user_id_base + index,
+                    settings.nix_build_group_id,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Users left by an interrupted install are fine, so long as they match the plan
+        let conflicts = find_conflicts(&settings, settings.nix_build_user_id_base, &HostUsers);
+        if !conflicts.is_empty() {
+            let suggested_base = find_free_base(&settings, &HostUsers);
+            let renumberable = conflicts
+                .iter()
+                .all(|conflict| !matches!(conflict, BuildUserConflict::GidMismatch { .. }));
+            match (id_conflict, suggested_base) {
+                (IdConflict::Shift, Some(base)) if renumberable => {
+                    tracing::warn!(
+                        "Existing users clash with build users at UID {}-{}, using UID {}-{} instead",
+                        settings.nix_build_user_id_base + 1,
+                        settings.nix_build_user_id_base + settings.nix_build_user_count,
+                        base + 1,
+                        base + settings.nix_build_user_count,
+                    );
+                    settings.nix_build_user_id_base = base;
+                    user_ids = (1..=settings.nix_build_user_count)
+                        .map(|index| (base + index, settings.nix_build_group_id))
+                        .collect();
+                },
+                (IdConflict::Adopt, _) => {
+                    user_ids = adopted_user_ids(&settings, &HostUsers).map_err(|conflicts| {
+                        Self::error(ActionErrorKind::BuildUserConflicts {
+                            conflicts,
+                            suggested_base: None,
+                            count: settings.nix_build_user_count,
+                        })
+                    })?;
+                    tracing::warn!(
+                        "Existing users clash with build users at UID {}-{}, keeping their existing UIDs",
+                        settings.nix_build_user_id_base + 1,
+                        settings.nix_build_user_id_base + settings.nix_build_user_count,
+                    );
+                },
+                _ => {
+                    return Err(Self::error(ActionErrorKind::BuildUserConflicts {
+                        conflicts,
+                        suggested_base: suggested_base.filter(|_| renumberable),
+                        count: settings.nix_build_user_count,
+                    }));
+                },
+            }
+        }
+
         let create_group = CreateGroup::plan(
             settings.nix_build_group_name.clone(),
             settings.nix_build_group_id,
         )?;
         let mut create_users = Vec::with_capacity(settings.nix_build_user_count as usize);
         let mut add_users_to_groups = Vec::with_capacity(settings.nix_build_user_count as usize);
-        for index in 1..=settings.nix_build_user_count {
+        for (index, (uid, gid)) in (1..=settings.nix_build_user_count).zip(user_ids) {
             create_users.push(
                 CreateUser::plan(
                     format!("{}{index}", settings.nix_build_user_prefix),
-                    settings.nix_build_user_id_base + index,
+                    uid,
                     settings.nix_build_group_name.clone(),
-                    settings.nix_build_group_id,
+                    gid,
                     format!("Nix build user {index}"),
                     true,
                 )
@@ -44,7 +112,7 @@ impl CreateUsersAndGroups {
             add_users_to_groups.push(
                 AddUserToGroup::plan(
                     format!("{}{index}", settings.nix_build_user_prefix),
-                    settings.nix_build_user_id_base + index,
+                    uid,
                     settings.nix_build_group_name.clone(),
                     settings.nix_build_group_id,
                 )
@@ -63,6 +131,66 @@ impl CreateUsersAndGroups {
         }
         .into())
     }
+
+    /// Plan the recorded group and users again, so any which went missing (such as after a macOS
+    /// update) are recreated and the rest are left alone
+    ///
+    /// Existing users or a group whose UID or GID no longer match the receipt are an error.
+    pub(crate) fn replan(&self) -> Result<StatefulAction<Self>, ActionError> {
+        let create_group =
+            CreateGroup::plan(self.nix_build_group_name.clone(), self.nix_build_group_id)?;
+        let mut create_users = Vec::with_capacity(self.create_users.len());
+        for create_user in &self.create_users {
+            let user = &create_user.action;
+            create_users.push(
+                CreateUser::plan(
+                    user.name.clone(),
+                    user.uid,
+                    user.groupname.clone(),
+                    user.gid,
+                    user.comment.clone(),
+                    true,
+                )
+                .map_err(Self::error)?,
+            );
+        }
+        let mut add_users_to_groups = Vec::with_capacity(self.add_users_to_groups.len());
+        for add_user_to_group in &self.add_users_to_groups {
+            let membership = &add_user_to_group.action;
+            add_users_to_groups.push(
+                AddUserToGroup::plan(
+                    membership.name.clone(),
+                    membership.uid,
+                    membership.groupname.clone(),
+                    membership.gid,
+                )
+                .map_err(Self::error)?,
+            );
+        }
+
+        let completed = create_group.state == ActionState::Completed
+            && create_users
+                .iter()
+                .all(|create_user| create_user.state == ActionState::Completed)
+            && add_users_to_groups
+                .iter()
+                .all(|add_user_to_group| add_user_to_group.state == ActionState::Completed);
+        let this = Self {
+            nix_build_group_name: self.nix_build_group_name.clone(),
+            nix_build_group_id: self.nix_build_group_id,
+            nix_build_user_count: self.nix_build_user_count,
+            nix_build_user_prefix: self.nix_build_user_prefix.clone(),
+            nix_build_user_id_base: self.nix_build_user_id_base,
+            create_group,
+            create_users,
+            add_users_to_groups,
+        };
+        if completed {
+            Ok(StatefulAction::completed(this))
+        } else {
+            Ok(StatefulAction::uncompleted(this))
+        }
+    }
 }
 
 #[typetag::serde(name = "create_users_and_group")]
@@ -95,6 +223,13 @@ impl Action for CreateUsersAndGroups {
         )
     }
 
+    fn execute_estimate(&self) -> ActionEstimate {
+        self.create_users
+            .iter()
+            .map(|create_user| create_user.estimate_execute())
+            .sum()
+    }
+
     fn execute_description(&self) -> Vec<ActionDescription> {
         let Self {
             nix_build_user_count: _,
@@ -148,17 +283,20 @@ impl Action for CreateUsersAndGroups {
 
         // Create group
         create_group.try_execute()?;
+        check_cancelled().map_err(Self::error)?;
 
         // Mac is apparently not threadsafe here...
         use target_lexicon::OperatingSystem;
         match OperatingSystem::host() {
             OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
                 for create_user in create_users.iter_mut() {
+                    check_cancelled().map_err(Self::error)?;
                     create_user.try_execute().map_err(Self::error)?;
                 }
             },
             _ => {
                 for create_user in create_users.iter_mut() {
+                    check_cancelled().map_err(Self::error)?;
                     create_user.try_execute().map_err(Self::error)?;
                 }
                 // While we may be tempted to do something like this, it can break on many older OSes like Ubuntu 18.04:
@@ -197,6 +335,7 @@ impl Action for CreateUsersAndGroups {
         };
 
         for add_user_to_group in add_users_to_groups.iter_mut() {
+            check_cancelled().map_err(Self::error)?;
             add_user_to_group.try_execute().map_err(Self::error)?;
         }
 
@@ -281,3 +420,390 @@ impl Action for CreateUsersAndGroups {
         }
     }
 }
+
+/// A planned build user which clashes with an existing user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildUserConflict {
+    /// A user with the planned name exists, with a different UID
+    UidMismatch {
+        name: String,
+        existing_uid: u32,
+        planned_uid: u32,
+    },
+    /// A user with the planned name exists, with a different primary group
+    GidMismatch {
+        name: String,
+        existing_gid: u32,
+        planned_gid: u32,
+    },
+    /// The planned UID belongs to a user with a different name
+    UidTaken {
+        name: String,
+        uid: u32,
+        owner: String,
+    },
+}
+
+impl std::fmt::Display for BuildUserConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildUserConflict::UidMismatch {
+                name,
+                existing_uid,
+                planned_uid,
+            } => write!(
+                f,
+                "User `{name}` exists with UID {existing_uid}, not {planned_uid}"
+            ),
+            BuildUserConflict::GidMismatch {
+                name,
+                existing_gid,
+                planned_gid,
+            } => write!(
+                f,
+                "User `{name}` exists with GID {existing_gid}, not {planned_gid}"
+            ),
+            BuildUserConflict::UidTaken { name, uid, owner } => {
+                write!(f, "UID {uid} planned for `{name}` belongs to `{owner}`")
+            },
+        }
+    }
+}
+
+/// Looks up existing users and groups, so conflicts can be found against fixtures in tests
+pub(crate) trait UserLookup {
+    /// The UID and primary GID of the user `name`
+    fn by_name(&self, name: &str) -> Option<(u32, u32)>;
+    /// The name of the user with `uid`
+    fn by_uid(&self, uid: u32) -> Option<String>;
+    /// The GID of the group `name`
+    fn group_by_name(&self, name: &str) -> Option<u32>;
+    /// The name of the group with `gid`
+    fn group_by_gid(&self, gid: u32) -> Option<String>;
+}
+
+struct HostUsers;
+
+impl UserLookup for HostUsers {
+    fn by_name(&self, name: &str) -> Option<(u32, u32)> {
+        User::from_name(name)
+            .ok()
+            .flatten()
+            .map(|user| (user.uid.as_raw(), user.gid.as_raw()))
+    }
+
+    fn by_uid(&self, uid: u32) -> Option<String> {
+        User::from_uid(Uid::from_raw(uid))
+            .ok()
+            .flatten()
+            .map(|user| user.name)
+    }
+
+    fn group_by_name(&self, name: &str) -> Option<u32> {
+        Group::from_name(name)
+            .ok()
+            .flatten()
+            .map(|group| group.gid.as_raw())
+    }
+
+    fn group_by_gid(&self, gid: u32) -> Option<String> {
+        Group::from_gid(Gid::from_raw(gid))
+            .ok()
+            .flatten()
+            .map(|group| group.name)
+    }
+}
+
+/// The GID for the build group, adopting an existing group's or shifting to a free one
+///
+/// An existing group with a different GID is left for [`CreateGroup`] to report, unless adopted.
+pub(crate) fn resolve_group_id(
+    settings: &CommonSettings,
+    id_conflict: IdConflict,
+    users: &impl UserLookup,
+) -> Result<u32, ActionErrorKind> {
+    let name = &settings.nix_build_group_name;
+    let planned_gid = settings.nix_build_group_id;
+    if let Some(existing_gid) = users.group_by_name(name) {
+        if existing_gid != planned_gid && id_conflict == IdConflict::Adopt {
+            tracing::warn!(
+                "Group `{name}` exists with GID {existing_gid}, not {planned_gid}, keeping it"
+            );
+            return Ok(existing_gid);
+        }
+        return Ok(planned_gid);
+    }
+
+    let Some(owner) = users.group_by_gid(planned_gid) else {
+        return Ok(planned_gid);
+    };
+    if id_conflict != IdConflict::Shift {
+        return Err(ActionErrorKind::GroupGidTaken(
+            name.clone(),
+            planned_gid,
+            owner,
+        ));
+    }
+    let free_gid = (1..=RENUMBER_ATTEMPTS)
+        .filter_map(|offset| planned_gid.checked_add(offset))
+        .find(|gid| users.group_by_gid(*gid).is_none())
+        .ok_or_else(|| ActionErrorKind::GroupGidTaken(name.clone(), planned_gid, owner))?;
+    tracing::warn!("GID {planned_gid} belongs to another group, using GID {free_gid} instead");
+    Ok(free_gid)
+}
+
+/// The UID and primary GID of each build user, keeping those of existing users
+///
+/// Fails with the clashes which can't be adopted, where a planned UID belongs to another user.
+pub(crate) fn adopted_user_ids(
+    settings: &CommonSettings,
+    users: &impl UserLookup,
+) -> Result<Vec<(u32, u32)>, Vec<BuildUserConflict>> {
+    let mut ids = vec![];
+    let mut conflicts = vec![];
+    for index in 1..=settings.nix_build_user_count {
+        let name = format!("{}{index}", settings.nix_build_user_prefix);
+        if let Some(existing) = users.by_name(&name) {
+            ids.push(existing);
+            continue;
+        }
+        let planned_uid = settings.nix_build_user_id_base + index;
+        match users.by_uid(planned_uid) {
+            Some(owner) => conflicts.push(BuildUserConflict::UidTaken {
+                name,
+                uid: planned_uid,
+                owner,
+            }),
+            None => ids.push((planned_uid, settings.nix_build_group_id)),
+        }
+    }
+    if conflicts.is_empty() {
+        Ok(ids)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Every clash between existing users and build users numbered from `base`
+pub(crate) fn find_conflicts(
+    settings: &CommonSettings,
+    base: u32,
+    users: &impl UserLookup,
+) -> Vec<BuildUserConflict> {
+    let mut conflicts = vec![];
+    for index in 1..=settings.nix_build_user_count {
+        let name = format!("{}{index}", settings.nix_build_user_prefix);
+        let planned_uid = base + index;
+        match users.by_name(&name) {
+            Some((existing_uid, existing_gid)) => {
+                if existing_uid != planned_uid {
+                    conflicts.push(BuildUserConflict::UidMismatch {
+                        name: name.clone(),
+                        existing_uid,
+                        planned_uid,
+                    });
+                }
+                if existing_gid != settings.nix_build_group_id {
+                    conflicts.push(BuildUserConflict::GidMismatch {
+                        name,
+                        existing_gid,
+                        planned_gid: settings.nix_build_group_id,
+                    });
+                }
+            },
+            None => {
+                if let Some(owner) = users.by_uid(planned_uid) {
+                    conflicts.push(BuildUserConflict::UidTaken {
+                        name,
+                        uid: planned_uid,
+                        owner,
+                    });
+                }
+            },
+        }
+    }
+    conflicts
+}
+
+/// A UID base for which no build user clashes with an existing user
+///
+/// The base existing build users were created with is preferred, so they are kept, then ranges
+/// above the configured base are tried.
+pub(crate) fn find_free_base(settings: &CommonSettings, users: &impl UserLookup) -> Option<u32> {
+    let count = settings.nix_build_user_count;
+    let existing_base = (1..=count).find_map(|index| {
+        let (uid, _) = users.by_name(&format!("{}{index}", settings.nix_build_user_prefix))?;
+        uid.checked_sub(index)
+    });
+    existing_base
+        .into_iter()
+        .chain((1..=RENUMBER_ATTEMPTS).filter_map(|attempt| {
+            settings
+                .nix_build_user_id_base
+                .checked_add(attempt.checked_mul(count)?)
+        }))
+        .find(|base| {
+            find_conflicts(settings, *base, users)
+                .iter()
+                .all(|conflict| matches!(conflict, BuildUserConflict::GidMismatch { .. }))
+        })
+}
+
+pub(crate) fn conflict_report(
+    conflicts: &[BuildUserConflict],
+    suggested_base: Option<u32>,
+    count: u32,
+) -> String {
+    let mut report = String::from("Existing users clash with the planned build users:\n");
+    for conflict in conflicts {
+        report.push_str(&format!("* {conflict}\n"));
+    }
+    match suggested_base {
+        Some(base) => report.push_str(&format!(
+            "UID {}-{} are free, pass `--id-conflict shift` (or `--nix-build-user-id-base {base}`) to use them, or `--id-conflict adopt` to keep existing build users as they are",
+            base + 1,
+            base + count
+        )),
+        None => report.push_str(
+            "Remove or rename the existing users, or pass a different `--nix-build-user-prefix`",
+        ),
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    struct Fixture(HashMap<String, (u32, u32)>);
+
+    impl UserLookup for Fixture {
+        fn by_name(&self, name: &str) -> Option<(u32, u32)> {
+            self.0.get(name).copied()
+        }
+
+        fn by_uid(&self, uid: u32) -> Option<String> {
+            self.0
+                .iter()
+                .find(|(_, (existing_uid, _))| *existing_uid == uid)
+                .map(|(name, _)| name.clone())
+        }
+
+        // Groups share the map, with the GID as their "UID"
+        fn group_by_name(&self, name: &str) -> Option<u32> {
+            self.by_name(name).map(|(gid, _)| gid)
+        }
+
+        fn group_by_gid(&self, gid: u32) -> Option<String> {
+            self.by_uid(gid)
+        }
+    }
+
+    fn settings() -> CommonSettings {
+        let mut settings = CommonSettings::try_default().unwrap();
+        settings.nix_build_user_prefix = "nixbld".into();
+        settings.nix_build_user_id_base = 30000;
+        settings.nix_build_user_count = 4;
+        settings.nix_build_group_id = 30000;
+        settings
+    }
+
+    #[test]
+    fn partially_existing_users_do_not_conflict() {
+        let users = Fixture(HashMap::from([
+            ("nixbld1".to_string(), (30001, 30000)),
+            ("nixbld2".to_string(), (30002, 30000)),
+        ]));
+        assert_eq!(find_conflicts(&settings(), 30000, &users), vec![]);
+    }
+
+    #[test]
+    fn conflicts_are_all_reported_and_renumbered() {
+        let users = Fixture(HashMap::from([
+            ("nixbld1".to_string(), (40001, 30000)),
+            ("nixbld2".to_string(), (40002, 30000)),
+            ("alice".to_string(), (30003, 100)),
+        ]));
+        let settings = settings();
+        assert_eq!(
+            find_conflicts(&settings, 30000, &users),
+            vec![
+                BuildUserConflict::UidMismatch {
+                    name: "nixbld1".into(),
+                    existing_uid: 40001,
+                    planned_uid: 30001,
+                },
+                BuildUserConflict::UidMismatch {
+                    name: "nixbld2".into(),
+                    existing_uid: 40002,
+                    planned_uid: 30002,
+                },
+                BuildUserConflict::UidTaken {
+                    name: "nixbld3".into(),
+                    uid: 30003,
+                    owner: "alice".into(),
+                },
+            ]
+        );
+        // The existing users' range is kept
+        assert_eq!(find_free_base(&settings, &users), Some(40000));
+    }
+
+    #[test]
+    fn taken_uids_are_skipped() {
+        let users = Fixture(HashMap::from([
+            ("alice".to_string(), (30002, 100)),
+            ("bob".to_string(), (30005, 100)),
+        ]));
+        assert_eq!(find_free_base(&settings(), &users), Some(30008));
+    }
+
+    #[test]
+    fn existing_users_and_groups_are_adopted() {
+        let users = Fixture(HashMap::from([
+            ("nixbld".to_string(), (350, 0)),
+            ("nixbld1".to_string(), (40001, 20)),
+            ("alice".to_string(), (30003, 100)),
+        ]));
+        let mut settings = settings();
+        assert_eq!(
+            resolve_group_id(&settings, IdConflict::Adopt, &users).unwrap(),
+            350
+        );
+        assert_eq!(
+            resolve_group_id(&settings, IdConflict::Error, &users).unwrap(),
+            30000
+        );
+
+        settings.nix_build_group_id = 350;
+        assert_eq!(
+            adopted_user_ids(&settings, &users),
+            Err(vec![BuildUserConflict::UidTaken {
+                name: "nixbld3".into(),
+                uid: 30003,
+                owner: "alice".into(),
+            }])
+        );
+        settings.nix_build_user_count = 2;
+        assert_eq!(
+            adopted_user_ids(&settings, &users),
+            Ok(vec![(40001, 20), (30002, 350)])
+        );
+    }
+
+    #[test]
+    fn taken_gids_are_shifted() {
+        let users = Fixture(HashMap::from([
+            ("staff".to_string(), (30000, 0)),
+            ("admin".to_string(), (30001, 0)),
+        ]));
+        let settings = settings();
+        assert!(resolve_group_id(&settings, IdConflict::Error, &users).is_err());
+        assert_eq!(
+            resolve_group_id(&settings, IdConflict::Shift, &users).unwrap(),
+            30002
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::base::CreateDirectory;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::settings::DEFAULT_DAEMON_SOCKET;
+
+/**
+Make the Nix daemon reachable at additional socket paths, by linking them to the default socket
+
+The daemon keeps listening only on the default socket, so this works whichever init system starts
+it; a link dangles until the daemon first starts.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "link_daemon_sockets")]
+pub struct LinkDaemonSockets {
+    sockets: Vec<PathBuf>,
+    create_directories: Vec<StatefulAction<CreateDirectory>>,
+}
+
+impl LinkDaemonSockets {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(sockets: Vec<PathBuf>) -> Result<StatefulAction<Self>, ActionError> {
+        let mut create_directories: Vec<StatefulAction<CreateDirectory>> = Vec::new();
+        let mut all_linked = true;
+        for socket in &sockets {
+            if !socket.is_absolute() || socket == Path::new(DEFAULT_DAEMON_SOCKET) {
+                return Err(Self::error(LinkDaemonSocketsError::InvalidSocket(
+                    socket.clone(),
+                )));
+            }
+
+            if socket.is_symlink() {
+                let link_dest = std::fs::read_link(socket)
+                    .map_err(|e| Self::error(ActionErrorKind::ReadSymlink(socket.clone(), e)))?;
+                if link_dest != Path::new(DEFAULT_DAEMON_SOCKET) {
+                    return Err(Self::error(ActionErrorKind::SymlinkExists(socket.clone())));
+                }
+                continue;
+            } else if socket.exists() {
+                return Err(Self::error(ActionErrorKind::FileExists(socket.clone())));
+            }
+            all_linked = false;
+
+            if let Some(parent) = socket.parent() {
+                let already_planned = create_directories
+                    .iter()
+                    .any(|create_directory| create_directory.action.path == parent);
+                if !parent.exists() && !already_planned {
+                    create_directories.push(
+                        CreateDirectory::plan(parent, None, None, 0o0755, false)
+                            .map_err(Self::error)?,
+                    );
+                }
+            }
+        }
+
+        let this = Self {
+            sockets,
+            create_directories,
+        };
+        if all_linked {
+            tracing::debug!("Linking the daemon sockets already complete");
+            return Ok(StatefulAction::completed(this));
+        }
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[typetag::serde(name = "link_daemon_sockets")]
+impl Action for LinkDaemonSockets {
+    fn action_tag() -> ActionTag {
+        ActionTag("link_daemon_sockets")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Link {} to the Nix daemon socket",
+            self.sockets
+                .iter()
+                .map(|socket| format!("`{}`", socket.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "link_daemon_sockets",
+            sockets = ?self.sockets,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!(
+                "Clients connecting to these paths reach the daemon listening on `{DEFAULT_DAEMON_SOCKET}`"
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        for create_directory in &mut self.create_directories {
+            create_directory.try_execute().map_err(Self::error)?;
+        }
+
+        for socket in &self.sockets {
+            if socket.is_symlink() {
+                continue;
+            }
+            std::os::unix::fs::symlink(DEFAULT_DAEMON_SOCKET, socket)
+                .map_err(|e| {
+                    ActionErrorKind::Symlink(DEFAULT_DAEMON_SOCKET.into(), socket.clone(), e)
+                })
+                .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove the links to the Nix daemon socket from {}",
+                self.sockets
+                    .iter()
+                    .map(|socket| format!("`{}`", socket.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        for socket in &self.sockets {
+            if socket.is_symlink() {
+                if let Err(e) = std::fs::remove_file(socket) {
+                    errors.push(Self::error(ActionErrorKind::Remove(socket.clone(), e)));
+                }
+            }
+        }
+        for create_directory in self.create_directories.iter_mut().rev() {
+            if let Err(err) = create_directory.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum LinkDaemonSocketsError {
+    #[error(
+        "The extra daemon socket `{0}` must be an absolute path other than `{DEFAULT_DAEMON_SOCKET}`"
+    )]
+    InvalidSocket(PathBuf),
+}
+
+impl From<LinkDaemonSocketsError> for ActionErrorKind {
+    fn from(val: LinkDaemonSocketsError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
@@ -1,5 +1,7 @@
 //! [`Action`](crate::action::Action)s which only call other base plugins
 
+pub(crate) mod configure_auto_maintenance;
+pub(crate) mod configure_config_refresh;
 pub(crate) mod configure_init_service;
 pub(crate) mod configure_nix;
 pub(crate) mod configure_shell_profile;
@@ -7,17 +9,33 @@ pub(crate) mod configure_upstream_init_service;
 pub(crate) mod create_nix_tree;
 pub(crate) mod create_users_and_groups;
 pub(crate) mod delete_users;
+pub(crate) mod link_daemon_sockets;
+pub(crate) mod place_access_tokens;
+pub(crate) mod place_netrc_configuration;
 pub(crate) mod place_nix_configuration;
+pub(crate) mod provision_chroot_store;
 pub(crate) mod provision_nix;
+pub(crate) mod restore_store;
+pub(crate) mod run_hook;
 pub(crate) mod setup_channels;
+pub(crate) mod setup_nix_next;
 
+pub use configure_auto_maintenance::ConfigureAutoMaintenance;
+pub use configure_config_refresh::ConfigureConfigRefresh;
 pub use configure_init_service::{ConfigureInitService, ConfigureNixDaemonServiceError};
 pub use configure_nix::ConfigureNix;
 pub use configure_shell_profile::ConfigureShellProfile;
 pub use configure_upstream_init_service::ConfigureUpstreamInitService;
 pub use create_nix_tree::CreateNixTree;
-pub use create_users_and_groups::CreateUsersAndGroups;
+pub use create_users_and_groups::{BuildUserConflict, CreateUsersAndGroups};
 pub use delete_users::DeleteUsersInGroup;
+pub use link_daemon_sockets::{LinkDaemonSockets, LinkDaemonSocketsError};
+pub use place_access_tokens::PlaceAccessTokens;
+pub use place_netrc_configuration::PlaceNetrcConfiguration;
 pub use place_nix_configuration::PlaceNixConfiguration;
+pub use provision_chroot_store::{ProvisionChrootStore, ProvisionChrootStoreError};
 pub use provision_nix::ProvisionNix;
+pub use restore_store::RestoreStore;
+pub use run_hook::{HookStage, RunHook, RunHookError};
 pub use setup_channels::SetupChannels;
+pub use setup_nix_next::SetupNixNext;
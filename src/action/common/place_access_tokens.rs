@@ -0,0 +1,157 @@
+use tracing::{Span, span};
+
+use crate::action::base::{CreateDirectory, CreateSecretFile};
+use crate::action::common::place_nix_configuration::NIX_CONF;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::sensitive::SensitiveString;
+use crate::settings::CommonSettings;
+
+pub const NIX_CONF_D: &str = "/etc/nix/nix.conf.d";
+pub const ACCESS_TOKENS_CONF: &str = "/etc/nix/nix.conf.d/tokens.conf";
+/// The `nix.conf` line including [`ACCESS_TOKENS_CONF`]
+pub(crate) const ACCESS_TOKENS_INCLUDE: &str = "!include nix.conf.d/tokens.conf";
+const ACCESS_TOKENS_CONF_NAME: &str = "access-tokens";
+/// Only root (and so the daemon) may read the tokens, Nix skips the include for other users
+const ACCESS_TOKENS_MODE: u32 = 0o600;
+
+/**
+Write `access-tokens` (used when fetching flakes from GitHub or GitLab) to a root-only include of
+`/etc/nix/nix.conf`, rather than to `nix.conf` itself, which anyone can read
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "place_access_tokens")]
+pub struct PlaceAccessTokens {
+    create_directory: StatefulAction<CreateDirectory>,
+    create_file: StatefulAction<CreateSecretFile>,
+}
+
+impl PlaceAccessTokens {
+    /// Plan from `--github-token` and `--access-tokens`, if either was given
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(settings: &CommonSettings) -> Result<Option<StatefulAction<Self>>, ActionError> {
+        let Some(access_tokens) = access_tokens(settings) else {
+            return Ok(None);
+        };
+
+        let create_directory =
+            CreateDirectory::plan(NIX_CONF_D, None, None, 0o0755, false).map_err(Self::error)?;
+        let content = SensitiveString::new(format!(
+            "# Generated by https://github.com/NixOS/nix-installer\n{ACCESS_TOKENS_CONF_NAME} = {}\n",
+            access_tokens.expose()
+        ));
+        let create_file = CreateSecretFile::plan(
+            ACCESS_TOKENS_CONF,
+            ACCESS_TOKENS_MODE,
+            content,
+            settings.force,
+        )
+        .map_err(Self::error)?;
+
+        Ok(Some(
+            Self {
+                create_directory,
+                create_file,
+            }
+            .into(),
+        ))
+    }
+}
+
+/// The `access-tokens` value from `--github-token` and `--access-tokens`
+fn access_tokens(settings: &CommonSettings) -> Option<SensitiveString> {
+    let mut tokens = vec![];
+    if let Some(github_token) = &settings.github_token {
+        tokens.push(format!("github.com={}", github_token.expose()));
+    }
+    if let Some(access_tokens) = &settings.access_tokens {
+        tokens.extend(
+            access_tokens
+                .expose()
+                .split_whitespace()
+                .map(ToString::to_string),
+        );
+    }
+    (!tokens.is_empty()).then(|| SensitiveString::new(tokens.join(" ")))
+}
+
+#[typetag::serde(name = "place_access_tokens")]
+impl Action for PlaceAccessTokens {
+    fn action_tag() -> ActionTag {
+        ActionTag("place_access_tokens")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Place the flake fetching access tokens in `{ACCESS_TOKENS_CONF}`")
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "place_access_tokens",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Include it from `{NIX_CONF}`"),
+                "Only readable by root, so the tokens don't leak to other users".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_directory.try_execute().map_err(Self::error)?;
+        self.create_file.try_execute().map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove `{ACCESS_TOKENS_CONF}`"),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+        if let Err(err) = self.create_file.try_revert() {
+            errors.push(err);
+        }
+        if let Err(err) = self.create_directory.try_revert() {
+            errors.push(err);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_token_joins_access_tokens() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = CommonSettings::try_default()?;
+        assert!(access_tokens(&settings).is_none());
+
+        settings.github_token = Some("ghp_abc".into());
+        settings.access_tokens = Some("gitlab.com=PAT:glpat-def".into());
+        assert_eq!(
+            access_tokens(&settings).unwrap().expose(),
+            "github.com=ghp_abc gitlab.com=PAT:glpat-def"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use tracing::{Span, span};
+
+use crate::action::base::CreateSecretFile;
+use crate::action::common::place_nix_configuration::CUSTOM_NIX_CONF;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::sensitive::SensitiveString;
+use crate::settings::CommonSettings;
+
+pub const NETRC_FILE: &str = "/etc/nix/netrc";
+pub(crate) const NETRC_FILE_CONF_NAME: &str = "netrc-file";
+/// Only root (and so the daemon) may read the credentials
+const NETRC_MODE: u32 = 0o600;
+
+/**
+Install the credentials for private binary caches as `/etc/nix/netrc`
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "place_netrc_configuration")]
+pub struct PlaceNetrcConfiguration {
+    create_file: StatefulAction<CreateSecretFile>,
+}
+
+impl PlaceNetrcConfiguration {
+    /// Plan from `--netrc-file` or `--netrc-content`, if either was given
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(settings: &CommonSettings) -> Result<Option<StatefulAction<Self>>, ActionError> {
+        let content = match (&settings.netrc_file, &settings.netrc_content) {
+            (Some(netrc_file), _) => SensitiveString::new(
+                std::fs::read_to_string(netrc_file)
+                    .map_err(|e| Self::error(ActionErrorKind::Read(netrc_file.clone(), e)))?,
+            ),
+            (None, Some(netrc_content)) => netrc_content.clone(),
+            (None, None) => return Ok(None),
+        };
+        let create_file = CreateSecretFile::plan(NETRC_FILE, NETRC_MODE, content, settings.force)
+            .map_err(Self::error)?;
+
+        Ok(Some(Self { create_file }.into()))
+    }
+}
+
+#[typetag::serde(name = "place_netrc_configuration")]
+impl Action for PlaceNetrcConfiguration {
+    fn action_tag() -> ActionTag {
+        ActionTag("place_netrc_configuration")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Place the binary cache credentials in `{NETRC_FILE}`")
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "place_netrc_configuration",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Only readable by root, as the Nix daemon fetches from private caches".to_string(),
+                format!("Set `{NETRC_FILE_CONF_NAME}` in `{CUSTOM_NIX_CONF}` to it"),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_file.try_execute().map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove `{NETRC_FILE}`"),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        self.create_file.try_revert().map_err(Self::error)?;
+
+        Ok(())
+    }
+}
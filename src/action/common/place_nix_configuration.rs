@@ -1,19 +1,32 @@
 use tracing::{Span, span};
 
+use crate::action::base::create_or_insert_into_file::Position;
 use crate::action::base::create_or_merge_nix_config::{
     CreateOrMergeNixConfigError, EXPERIMENTAL_FEATURES_CONF_NAME,
     EXTRA_EXPERIMENTAL_FEATURES_CONF_NAME, TRUSTED_USERS_CONF_NAME,
 };
-use crate::action::base::{CreateDirectory, CreateOrMergeNixConfig};
+use crate::action::base::{CreateDirectory, CreateOrInsertIntoFile, CreateOrMergeNixConfig};
+use crate::action::common::place_access_tokens::{ACCESS_TOKENS_INCLUDE, NIX_CONF_D};
+use crate::action::common::place_netrc_configuration::{NETRC_FILE, NETRC_FILE_CONF_NAME};
+use crate::action::common::{PlaceAccessTokens, PlaceNetrcConfiguration};
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
 };
-use crate::settings::UrlOrPathOrString;
+use crate::nix_conf;
+use crate::settings::{CommonSettings, NIX_VERSION, NixSource, UrlOrPathOrString};
+use semver::Version;
 use std::path::PathBuf;
+use url::Url;
 
 pub const NIX_CONF_FOLDER: &str = "/etc/nix";
 pub const NIX_CONF: &str = "/etc/nix/nix.conf";
-const CUSTOM_NIX_CONF: &str = "/etc/nix/nix.custom.conf";
+pub(crate) const CUSTOM_NIX_CONF: &str = "/etc/nix/nix.custom.conf";
+/// The installer's own settings, when Nix supports including them from `nix.conf`
+pub const INSTALLER_NIX_CONF: &str = "/etc/nix/nix.conf.d/installer.conf";
+const INSTALLER_NIX_CONF_INCLUDE: &str = "!include nix.conf.d/installer.conf";
+
+/// The first Nix release which reads `include` directives in `nix.conf`
+const NIX_CONF_INCLUDE_MIN_VERSION: Version = Version::new(2, 1, 0);
 
 const NIX_CONFIG_HEADER: &str = r#"# Generated by https://github.com/NixOS/nix-installer
 # See `/nix/nix-installer --version` for the version details.
@@ -21,12 +34,23 @@ const NIX_CONFIG_HEADER: &str = r#"# Generated by https://github.com/NixOS/nix-i
 
 const NIX_CONFIG_FOOTER: &str = "!include nix.custom.conf";
 
+const INSTALLER_NIX_CONFIG_HEADER: &str = r#"# Generated by https://github.com/NixOS/nix-installer
+# Replaced when reinstalling Nix, so change these settings in `/etc/nix/nix.conf` instead.
+"#;
+
+const NIX_CONFIG_USER_NOTE: &str = "# Settings below override the ones included above, and are kept when reinstalling or uninstalling Nix.";
+
 pub(crate) const CUSTOM_NIX_CONFIG_HEADER: &str = r#"# Written by https://github.com/NixOS/nix-installer
 # The contents below are based on options specified at installation time.
 "#;
 
 /**
 Place the `/etc/nix/nix.conf` file
+
+When the Nix being installed supports it, the installer's settings go in
+`/etc/nix/nix.conf.d/installer.conf` instead, and only a block of `!include`s is added to the top of
+`nix.conf`. Anything else in `nix.conf` is the user's, so it overrides the installer's settings, and
+is kept when reinstalling or uninstalling.
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "place_nix_configuration")]
@@ -34,47 +58,118 @@ pub struct PlaceNixConfiguration {
     create_directory: StatefulAction<CreateDirectory>,
     create_or_merge_standard_nix_config: Option<StatefulAction<CreateOrMergeNixConfig>>,
     create_or_merge_custom_nix_config: StatefulAction<CreateOrMergeNixConfig>,
+    #[serde(default)]
+    create_nix_conf_d: Option<StatefulAction<CreateDirectory>>,
+    #[serde(default)]
+    insert_nix_conf_includes: Option<StatefulAction<CreateOrInsertIntoFile>>,
+    #[serde(default)]
+    place_netrc_configuration: Option<StatefulAction<PlaceNetrcConfiguration>>,
+    #[serde(default)]
+    place_access_tokens: Option<StatefulAction<PlaceAccessTokens>>,
 }
 
 impl PlaceNixConfiguration {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(
-        nix_build_group_name: String,
-        ssl_cert_file: Option<PathBuf>,
-        extra_conf: Vec<UrlOrPathOrString>,
-        force: bool,
-    ) -> Result<StatefulAction<Self>, ActionError> {
-        let extra_conf = Self::parse_extra_conf(extra_conf)?;
+    pub fn plan(settings: &CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+        let mut extra_conf = Self::parse_extra_conf(settings.extra_conf.clone())?;
+        Self::merge_binary_caches(
+            &mut extra_conf,
+            &settings.substituters,
+            &settings.trusted_public_keys,
+        );
+        Self::merge_setting_flags(&mut extra_conf, settings)?;
+
+        let place_netrc_configuration =
+            PlaceNetrcConfiguration::plan(settings).map_err(Self::error)?;
+        if place_netrc_configuration.is_some() {
+            nix_conf::merge_into(&mut extra_conf, NETRC_FILE_CONF_NAME, NETRC_FILE).map_err(
+                |existing| {
+                    Self::error(PlaceNixConfigurationError::ConflictingSetting {
+                        name: NETRC_FILE_CONF_NAME.to_string(),
+                        value: NETRC_FILE.to_string(),
+                        existing,
+                    })
+                },
+            )?;
+        }
 
-        let configured_ssl_cert_file = ssl_cert_file;
+        let place_access_tokens = PlaceAccessTokens::plan(settings).map_err(Self::error)?;
+        let mut footer = NIX_CONFIG_FOOTER.to_string();
+        if place_access_tokens.is_some() {
+            footer.push('\n');
+            footer.push_str(ACCESS_TOKENS_INCLUDE);
+        }
 
-        let maybe_trusted_users = extra_conf.settings().get(TRUSTED_USERS_CONF_NAME);
-        let standard_nix_config = Some(Self::setup_standard_config(maybe_trusted_users)?);
+        // The exported keychain bundle doesn't exist yet, only a given file must already
+        let configured_ssl_cert_file = match &settings.ssl_cert_file {
+            Some(ssl_cert_file) => {
+                let ssl_cert_file = ssl_cert_file.canonicalize().map_err(|e| {
+                    Self::error(ActionErrorKind::Canonicalize(ssl_cert_file.to_owned(), e))
+                })?;
+                // Nix is given the whole bundle, so every certificate in it must be usable
+                let certificates = crate::ssl_cert::read_ssl_cert(&ssl_cert_file)
+                    .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+                tracing::debug!(
+                    ssl_cert_file = %ssl_cert_file.display(),
+                    certificates = certificates.len(),
+                    "Read SSL cert file"
+                );
+                Some(ssl_cert_file)
+            },
+            None => settings
+                .ca_bundle()
+                .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?,
+        };
+
+        let maybe_trusted_users = extra_conf.settings().get(TRUSTED_USERS_CONF_NAME).cloned();
+        let nix_conf_d = supports_nix_conf_include(installed_nix_version(settings).as_ref());
+        let create_directory =
+            CreateDirectory::plan(NIX_CONF_FOLDER, None, None, 0o0755, settings.force)
+                .map_err(Self::error)?;
+
+        let (create_nix_conf_d, insert_nix_conf_includes, create_or_merge_standard_nix_config) =
+            if nix_conf_d {
+                let create_nix_conf_d =
+                    CreateDirectory::plan(NIX_CONF_D, None, None, 0o0755, settings.force)
+                        .map_err(Self::error)?;
+                let insert_nix_conf_includes = CreateOrInsertIntoFile::plan(
+                    NIX_CONF,
+                    None,
+                    None,
+                    0o0644,
+                    nix_conf_includes(&footer, maybe_trusted_users.as_ref()),
+                    Position::Beginning,
+                )
+                .map_err(Self::error)?;
+                let create_or_merge_installer_nix_config = CreateOrMergeNixConfig::plan(
+                    INSTALLER_NIX_CONF,
+                    Self::setup_standard_config(None)?,
+                    INSTALLER_NIX_CONFIG_HEADER.to_string(),
+                    None,
+                )
+                .map_err(Self::error)?;
+                (
+                    Some(create_nix_conf_d),
+                    Some(insert_nix_conf_includes),
+                    create_or_merge_installer_nix_config,
+                )
+            } else {
+                let create_or_merge_standard_nix_config = CreateOrMergeNixConfig::plan(
+                    NIX_CONF,
+                    Self::setup_standard_config(maybe_trusted_users.as_ref())?,
+                    NIX_CONFIG_HEADER.to_string(),
+                    Some(footer),
+                )
+                .map_err(Self::error)?;
+                (None, None, create_or_merge_standard_nix_config)
+            };
 
         let custom_nix_config = Self::setup_extra_config(
             extra_conf,
-            nix_build_group_name,
+            settings.nix_build_group_name.clone(),
             configured_ssl_cert_file.as_ref(),
         )?;
 
-        let create_directory = CreateDirectory::plan(NIX_CONF_FOLDER, None, None, 0o0755, force)
-            .map_err(Self::error)?;
-
-        let create_or_merge_standard_nix_config =
-            if let Some(standard_nix_config) = standard_nix_config {
-                Some(
-                    CreateOrMergeNixConfig::plan(
-                        NIX_CONF,
-                        standard_nix_config,
-                        NIX_CONFIG_HEADER.to_string(),
-                        Some(NIX_CONFIG_FOOTER.to_string()),
-                    )
-                    .map_err(Self::error)?,
-                )
-            } else {
-                None
-            };
-
         let create_or_merge_custom_nix_config = CreateOrMergeNixConfig::plan(
             CUSTOM_NIX_CONF,
             custom_nix_config,
@@ -85,8 +180,12 @@ impl PlaceNixConfiguration {
 
         Ok(Self {
             create_directory,
-            create_or_merge_standard_nix_config,
+            create_or_merge_standard_nix_config: Some(create_or_merge_standard_nix_config),
             create_or_merge_custom_nix_config,
+            create_nix_conf_d,
+            insert_nix_conf_includes,
+            place_netrc_configuration,
+            place_access_tokens,
         }
         .into())
     }
@@ -143,7 +242,7 @@ impl PlaceNixConfiguration {
         Ok(nix_config)
     }
 
-    fn parse_extra_conf(
+    pub(crate) fn parse_extra_conf(
         extra_conf: Vec<UrlOrPathOrString>,
     ) -> Result<nix_config_parser::NixConfig, ActionError> {
         let mut extra_conf_text = vec![];
@@ -175,6 +274,103 @@ impl PlaceNixConfiguration {
         Ok(nix_config)
     }
 
+    /// Add `--substituter`s and `--trusted-public-key`s to what `--extra-conf` set
+    ///
+    /// If `--extra-conf` replaced `substituters` (or `trusted-public-keys`) they are appended to
+    /// it, otherwise they go in the `extra-` variant so `cache.nixos.org` keeps working.
+    fn merge_binary_caches(
+        extra_conf: &mut nix_config_parser::NixConfig,
+        substituters: &[Url],
+        trusted_public_keys: &[String],
+    ) {
+        // `Url` adds a `/` to bare hosts, which Nix doesn't write
+        let substituters = substituters
+            .iter()
+            .map(|url| url.as_str().trim_end_matches('/'))
+            .collect::<Vec<_>>();
+
+        for (name, values) in [
+            ("substituters", substituters.join(" ")),
+            ("trusted-public-keys", trusted_public_keys.join(" ")),
+        ] {
+            if values.is_empty() {
+                continue;
+            }
+            let key = if extra_conf.settings().contains_key(name) {
+                name.to_string()
+            } else {
+                format!("extra-{name}")
+            };
+            // Lists always merge
+            let _ = nix_conf::merge_into(extra_conf, &key, &values);
+        }
+    }
+
+    /// Add the settings with their own flags (like `--max-jobs`) to what `--extra-conf` set
+    fn merge_setting_flags(
+        extra_conf: &mut nix_config_parser::NixConfig,
+        settings: &CommonSettings,
+    ) -> Result<(), ActionError> {
+        let mut flags = vec![];
+        if !settings.experimental_features.is_empty() {
+            flags.push((
+                EXTRA_EXPERIMENTAL_FEATURES_CONF_NAME,
+                settings.experimental_features.join(" "),
+            ));
+        }
+        if let Some(max_jobs) = &settings.max_jobs {
+            flags.push(("max-jobs", max_jobs.clone()));
+        }
+        if !settings.trusted_users.is_empty() {
+            Self::check_users_exist(&settings.trusted_users, &settings.nix_build_group_name)?;
+            // Setting `trusted-users` replaces Nix's default of `root`, which should stay trusted
+            let mut trusted_users = settings.trusted_users.clone();
+            if !extra_conf.settings().contains_key(TRUSTED_USERS_CONF_NAME) {
+                trusted_users.insert(0, String::from("root"));
+            }
+            flags.push((TRUSTED_USERS_CONF_NAME, trusted_users.join(" ")));
+        }
+        if !settings.allowed_users.is_empty() {
+            Self::check_users_exist(&settings.allowed_users, &settings.nix_build_group_name)?;
+            flags.push(("allowed-users", settings.allowed_users.join(" ")));
+        }
+
+        for (name, value) in flags {
+            nix_conf::merge_into(extra_conf, name, &value).map_err(|existing| {
+                Self::error(PlaceNixConfigurationError::ConflictingSetting {
+                    name: name.to_string(),
+                    value,
+                    existing,
+                })
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check the users (and `@group`s) in `--trusted-users` or `--allowed-users` exist
+    ///
+    /// The Nix build group doesn't exist yet, and `*` means everyone.
+    fn check_users_exist(users: &[String], nix_build_group_name: &str) -> Result<(), ActionError> {
+        for user in users {
+            let exists = match user.strip_prefix('@') {
+                Some(group) if group == nix_build_group_name => true,
+                Some(group) => nix::unistd::Group::from_name(group)
+                    .map_err(|e| Self::error(ActionErrorKind::GettingGroupId(group.into(), e)))?
+                    .is_some(),
+                None if user == "*" => true,
+                None => nix::unistd::User::from_name(user)
+                    .map_err(|e| Self::error(ActionErrorKind::GettingUserId(user.into(), e)))?
+                    .is_some(),
+            };
+            if !exists {
+                return Err(Self::error(PlaceNixConfigurationError::NoSuchUser(
+                    user.clone(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
     fn setup_extra_config(
         mut extra_conf: nix_config_parser::NixConfig,
         nix_build_group_name: String,
@@ -187,12 +383,9 @@ impl PlaceNixConfiguration {
         }
 
         if let Some(ssl_cert_file) = ssl_cert_file {
-            let ssl_cert_file_canonical = ssl_cert_file.canonicalize().map_err(|e| {
-                Self::error(ActionErrorKind::Canonicalize(ssl_cert_file.to_owned(), e))
-            })?;
             settings.insert(
                 "ssl-cert-file".to_string(),
-                ssl_cert_file_canonical.display().to_string(),
+                ssl_cert_file.display().to_string(),
             );
         }
 
@@ -222,6 +415,49 @@ impl PlaceNixConfiguration {
     }
 }
 
+/// The version of the Nix being installed, if it can be told
+///
+/// A `--tarball-url` only has one when `--store-paths-json` names its `{distribution}-{version}`.
+fn installed_nix_version(settings: &CommonSettings) -> Option<Version> {
+    let version = match settings.nix_source().ok()? {
+        NixSource::Embedded => NIX_VERSION.trim().to_string(),
+        NixSource::Tarball {
+            distribution,
+            store_paths,
+            ..
+        } => {
+            let store_paths = store_paths?;
+            let name = store_paths.nix.file_name()?.to_str()?;
+            let (_, version) = name.split_once(&format!("-{distribution}-"))?;
+            version.to_string()
+        },
+    };
+    Version::parse(&version).ok()
+}
+
+/// If the installer's settings can go in [`INSTALLER_NIX_CONF`], included from `nix.conf`
+///
+/// `nix.custom.conf` is already included, so a Nix of unknown version is assumed to support it.
+fn supports_nix_conf_include(version: Option<&Version>) -> bool {
+    version.is_none_or(|version| *version >= NIX_CONF_INCLUDE_MIN_VERSION)
+}
+
+/// The block added to the top of `nix.conf`, including the installer's settings
+fn nix_conf_includes(footer: &str, trusted_users: Option<&String>) -> String {
+    let mut buf = NIX_CONFIG_HEADER.to_string();
+    buf.push_str(INSTALLER_NIX_CONF_INCLUDE);
+    buf.push('\n');
+    buf.push_str(footer);
+    buf.push('\n');
+    // Cachix only reads `trusted-users` from `nix.conf` itself, see `setup_standard_config`
+    if let Some(trusted_users) = trusted_users {
+        buf.push_str(&format!("{TRUSTED_USERS_CONF_NAME} = {trusted_users}\n"));
+    }
+    buf.push_str(NIX_CONFIG_USER_NOTE);
+    buf.push_str("\n\n");
+    buf
+}
+
 #[typetag::serde(name = "place_nix_configuration")]
 impl Action for PlaceNixConfiguration {
     fn action_tag() -> ActionTag {
@@ -244,6 +480,16 @@ impl Action for PlaceNixConfiguration {
         if let Some(val) = self.create_directory.describe_execute().first() {
             explanation.push(val.description.clone())
         }
+        if let Some(ref create_nix_conf_d) = self.create_nix_conf_d {
+            if let Some(val) = create_nix_conf_d.describe_execute().first() {
+                explanation.push(val.description.clone())
+            }
+        }
+        if self.insert_nix_conf_includes.is_some() {
+            explanation.push(format!(
+                "Include it from the top of `{NIX_CONF}`, so settings added below override it"
+            ));
+        }
         if let Some(ref standard_config) = self.create_or_merge_standard_nix_config {
             for val in standard_config.describe_execute().iter() {
                 explanation.push(val.description.clone())
@@ -256,6 +502,16 @@ impl Action for PlaceNixConfiguration {
         {
             explanation.push(val.description.clone())
         }
+        if let Some(ref netrc_config) = self.place_netrc_configuration {
+            for val in netrc_config.describe_execute().iter() {
+                explanation.push(val.description.clone())
+            }
+        }
+        if let Some(ref access_tokens) = self.place_access_tokens {
+            for val in access_tokens.describe_execute().iter() {
+                explanation.push(val.description.clone())
+            }
+        }
 
         vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
@@ -263,9 +519,21 @@ impl Action for PlaceNixConfiguration {
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
         self.create_directory.try_execute().map_err(Self::error)?;
+        if let Some(ref mut netrc_config) = self.place_netrc_configuration {
+            netrc_config.try_execute().map_err(Self::error)?;
+        }
+        if let Some(ref mut create_nix_conf_d) = self.create_nix_conf_d {
+            create_nix_conf_d.try_execute().map_err(Self::error)?;
+        }
+        if let Some(ref mut access_tokens) = self.place_access_tokens {
+            access_tokens.try_execute().map_err(Self::error)?;
+        }
         if let Some(ref mut standard_config) = self.create_or_merge_standard_nix_config {
             standard_config.try_execute().map_err(Self::error)?;
         }
+        if let Some(ref mut nix_conf_includes) = self.insert_nix_conf_includes {
+            nix_conf_includes.try_execute().map_err(Self::error)?;
+        }
 
         self.create_or_merge_custom_nix_config
             .try_execute()
@@ -277,10 +545,16 @@ impl Action for PlaceNixConfiguration {
     fn revert_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
             format!("Remove the Nix configuration in `{NIX_CONF}`"),
-            vec![
-                "This file is read by the Nix daemon to set its configuration options at runtime."
-                    .to_string(),
-            ],
+            if self.insert_nix_conf_includes.is_some() {
+                vec![format!(
+                    "Settings added to `{NIX_CONF}` after installing are kept, only the installer's are removed"
+                )]
+            } else {
+                vec![
+                    "This file is read by the Nix daemon to set its configuration options at runtime."
+                        .to_string(),
+                ]
+            },
         )]
     }
 
@@ -291,12 +565,36 @@ impl Action for PlaceNixConfiguration {
             errors.push(err);
         }
 
+        // Only the installer's block is removed from `nix.conf`, the user's settings are kept
+        if let Some(ref mut nix_conf_includes) = self.insert_nix_conf_includes {
+            if let Err(err) = nix_conf_includes.try_revert() {
+                errors.push(err);
+            }
+        }
+
         if let Some(ref mut standard_config) = self.create_or_merge_standard_nix_config {
             if let Err(err) = standard_config.try_revert() {
                 errors.push(err);
             }
         }
 
+        // Before the access tokens, which remove `nix.conf.d` once it is empty
+        if let Some(ref mut create_nix_conf_d) = self.create_nix_conf_d {
+            if let Err(err) = create_nix_conf_d.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Some(ref mut access_tokens) = self.place_access_tokens {
+            if let Err(err) = access_tokens.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Some(ref mut netrc_config) = self.place_netrc_configuration {
+            if let Err(err) = netrc_config.try_revert() {
+                errors.push(err);
+            }
+        }
+
         if let Err(err) = self.create_directory.try_revert() {
             errors.push(err);
         }
@@ -319,6 +617,18 @@ impl Action for PlaceNixConfiguration {
 pub enum PlaceNixConfigurationError {
     #[error("HTTP/HTTPS URLs are not supported for extra-conf; use a local file path instead: {0}")]
     HttpUrlNotSupported(String),
+    #[error(
+        "`{name} = {value}` from its installer flag conflicts with `{name} = {existing}` from `--extra-conf`"
+    )]
+    ConflictingSetting {
+        name: String,
+        value: String,
+        existing: String,
+    },
+    #[error(
+        "`{0}` is not an existing user (or `@group`), so it can't be added to `trusted-users` or `allowed-users`"
+    )]
+    NoSuchUser(String),
 }
 
 impl From<PlaceNixConfigurationError> for ActionErrorKind {
@@ -362,6 +672,94 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn binary_caches_from_flags() -> eyre::Result<()> {
+        let mut extra_conf =
+            PlaceNixConfiguration::parse_extra_conf(vec![UrlOrPathOrString::String(
+                String::from("substituters = https://cache.nixos.org https://a.example.com"),
+            )])?;
+
+        PlaceNixConfiguration::merge_binary_caches(
+            &mut extra_conf,
+            &[
+                Url::parse("https://a.example.com")?,
+                Url::parse("s3://b-cache?region=eu-west-1")?,
+            ],
+            &[String::from("b-cache-1:foobar")],
+        );
+
+        assert_eq!(
+            extra_conf
+                .settings()
+                .get("substituters")
+                .map(String::as_str),
+            Some("https://cache.nixos.org https://a.example.com s3://b-cache?region=eu-west-1"),
+            "Flags are appended to substituters set with `--extra-conf`, once"
+        );
+        assert_eq!(
+            extra_conf
+                .settings()
+                .get("extra-trusted-public-keys")
+                .map(String::as_str),
+            Some("b-cache-1:foobar"),
+            "Keys don't replace the default trusted-public-keys"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn setting_flags_merge_with_extra_conf() -> eyre::Result<()> {
+        let mut settings = CommonSettings::try_default()?;
+        settings.experimental_features = vec![String::from("ca-derivations")];
+        settings.max_jobs = Some(String::from("4"));
+
+        let mut extra_conf =
+            PlaceNixConfiguration::parse_extra_conf(vec![UrlOrPathOrString::String(
+                String::from("extra-experimental-features = recursive-nix\nmax-jobs = 4"),
+            )])?;
+        PlaceNixConfiguration::merge_setting_flags(&mut extra_conf, &settings)?;
+        assert_eq!(
+            extra_conf
+                .settings()
+                .get(EXTRA_EXPERIMENTAL_FEATURES_CONF_NAME)
+                .map(String::as_str),
+            Some("recursive-nix ca-derivations")
+        );
+
+        settings.max_jobs = Some(String::from("8"));
+        assert!(
+            PlaceNixConfiguration::merge_setting_flags(&mut extra_conf, &settings).is_err(),
+            "A flag can't silently override a different `--extra-conf` value"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn trusted_users_keep_root_and_must_exist() -> eyre::Result<()> {
+        let mut settings = CommonSettings::try_default()?;
+        settings.trusted_users = vec![String::from("@nixbld")];
+
+        let mut extra_conf = nix_config_parser::NixConfig::new();
+        PlaceNixConfiguration::merge_setting_flags(&mut extra_conf, &settings)?;
+        assert_eq!(
+            extra_conf
+                .settings()
+                .get(TRUSTED_USERS_CONF_NAME)
+                .map(String::as_str),
+            Some("root @nixbld")
+        );
+
+        settings.allowed_users = vec![String::from("nix-installer-no-such-user")];
+        assert!(
+            PlaceNixConfiguration::merge_setting_flags(&mut extra_conf, &settings).is_err(),
+            "Users which don't exist are rejected"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn experimental_features() -> eyre::Result<()> {
         let nix_conf_dir = tempfile::tempdir()?;
@@ -424,6 +822,10 @@ mod tests {
                 None,
             )
             .map_err(PlaceNixConfiguration::error)?,
+            create_nix_conf_d: None,
+            insert_nix_conf_includes: None,
+            place_netrc_configuration: None,
+            place_access_tokens: None,
         });
 
         place_nix_configuration
@@ -499,6 +901,10 @@ mod tests {
                 None,
             )
             .map_err(PlaceNixConfiguration::error)?,
+            create_nix_conf_d: None,
+            insert_nix_conf_includes: None,
+            place_netrc_configuration: None,
+            place_access_tokens: None,
         });
 
         place_nix_configuration
@@ -519,4 +925,80 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn user_edits_to_nix_conf_survive_uninstall() -> eyre::Result<()> {
+        assert!(supports_nix_conf_include(None));
+        assert!(supports_nix_conf_include(Some(&Version::new(2, 24, 10))));
+        assert!(!supports_nix_conf_include(Some(&Version::new(2, 0, 4))));
+
+        let nix_conf_dir = tempfile::tempdir()?;
+        let nix_conf_path = nix_conf_dir.path().join("nix.conf");
+        let nix_conf_d_path = nix_conf_dir.path().join("nix.conf.d");
+        std::fs::write(&nix_conf_path, "max-jobs = 2\n")?;
+
+        let mut place_nix_configuration = StatefulAction::uncompleted(PlaceNixConfiguration {
+            create_directory: StatefulAction::completed(CreateDirectory {
+                path: nix_conf_dir.path().to_owned(),
+                user: None,
+                group: None,
+                mode: None,
+                is_mountpoint: false,
+                force_prune_on_revert: false,
+            }),
+            create_or_merge_standard_nix_config: Some(
+                CreateOrMergeNixConfig::plan(
+                    nix_conf_d_path.join("installer.conf"),
+                    PlaceNixConfiguration::setup_standard_config(None)?,
+                    INSTALLER_NIX_CONFIG_HEADER.to_string(),
+                    None,
+                )
+                .map_err(PlaceNixConfiguration::error)?,
+            ),
+            create_or_merge_custom_nix_config: CreateOrMergeNixConfig::plan(
+                nix_conf_dir.path().join("nix.custom.conf"),
+                nix_config_parser::NixConfig::new(),
+                CUSTOM_NIX_CONFIG_HEADER.to_string(),
+                None,
+            )
+            .map_err(PlaceNixConfiguration::error)?,
+            create_nix_conf_d: Some(CreateDirectory::plan(
+                &nix_conf_d_path,
+                None,
+                None,
+                0o0755,
+                false,
+            )?),
+            insert_nix_conf_includes: Some(CreateOrInsertIntoFile::plan(
+                &nix_conf_path,
+                None,
+                None,
+                0o0644,
+                nix_conf_includes(NIX_CONFIG_FOOTER, None),
+                Position::Beginning,
+            )?),
+            place_netrc_configuration: None,
+            place_access_tokens: None,
+        });
+
+        place_nix_configuration.try_execute()?;
+        let nix_conf = std::fs::read_to_string(&nix_conf_path)?;
+        assert!(nix_conf.starts_with(NIX_CONFIG_HEADER));
+        assert!(nix_conf.contains(INSTALLER_NIX_CONF_INCLUDE));
+        assert!(
+            nix_conf.ends_with("max-jobs = 2\n"),
+            "The user's settings come last, so they win"
+        );
+        let installer_conf = std::fs::read_to_string(nix_conf_d_path.join("installer.conf"))?;
+        assert!(installer_conf.contains("max-jobs = auto"));
+
+        std::fs::write(&nix_conf_path, format!("{nix_conf}sandbox = relaxed\n"))?;
+        place_nix_configuration.try_revert()?;
+        assert_eq!(
+            std::fs::read_to_string(&nix_conf_path)?,
+            "max-jobs = 2\nsandbox = relaxed\n"
+        );
+        assert!(!nix_conf_d_path.exists());
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::FetchAndUnpackNix;
+use crate::action::base::setup_default_profile::tarball_version;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionEstimate, ActionTag,
+    StatefulAction,
+};
+use crate::settings::{CommonSettings, NixSource, StorePaths};
+use crate::util::{OnMissing, which};
+
+/**
+Provision Nix into a secondary store under `prefix`, as `{prefix}/nix/store`, without touching the
+host's `/nix`
+
+The store paths are registered with the host's `nix-store --store {prefix}`, so the prefix works as
+a [local chroot store](https://nix.dev/manual/nix/latest/store/types/local-store) or as the root of
+a container. Its default profile points at the Nix package.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "provision_chroot_store")]
+pub struct ProvisionChrootStore {
+    prefix: PathBuf,
+    source: NixSource,
+    fetch_nix: StatefulAction<FetchAndUnpackNix>,
+}
+
+impl ProvisionChrootStore {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        prefix: impl AsRef<Path>,
+        settings: &CommonSettings,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let prefix = prefix.as_ref().to_path_buf();
+        if !prefix.is_absolute() || prefix == Path::new("/") {
+            return Err(Self::error(ProvisionChrootStoreError::InvalidPrefix(
+                prefix,
+            )));
+        }
+        let store = prefix.join("nix/store");
+        if store.exists() {
+            return Err(Self::error(ActionErrorKind::DirExists(store)));
+        }
+        if which("nix-store").is_none() {
+            return Err(Self::error(ProvisionChrootStoreError::NoHostNix));
+        }
+
+        let source = settings
+            .nix_source()
+            .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+        let fetch_nix = FetchAndUnpackNix::plan(
+            Self::scratch_dir(&prefix),
+            source.clone(),
+            settings.ssl_cert_file.clone(),
+        )?;
+
+        Ok(Self {
+            prefix,
+            source,
+            fetch_nix,
+        }
+        .into())
+    }
+
+    /// Unpacked inside the prefix, so the store paths can be renamed into place
+    fn scratch_dir(prefix: &Path) -> PathBuf {
+        prefix.join("nix/temp-install-dir")
+    }
+}
+
+#[typetag::serde(name = "provision_chroot_store")]
+impl Action for ProvisionChrootStore {
+    fn action_tag() -> ActionTag {
+        ActionTag("provision_chroot_store")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Provision Nix into a store in `{}`",
+            self.prefix.join("nix").display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "provision_chroot_store",
+            prefix = tracing::field::display(self.prefix.display()),
+        )
+    }
+
+    fn execute_estimate(&self) -> ActionEstimate {
+        self.fetch_nix.estimate_execute()
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut buf = self.fetch_nix.describe_execute();
+        buf.push(ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Move the unpacked store paths into `{}`",
+                    self.prefix.join("nix/store").display()
+                ),
+                format!(
+                    "Register them with `nix-store --store {} --load-db`",
+                    self.prefix.display()
+                ),
+                format!(
+                    "Point `{}` at the Nix package",
+                    self.prefix.join("nix/var/nix/profiles/default").display()
+                ),
+            ],
+        ));
+        buf
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.fetch_nix.try_execute().map_err(Self::error)?;
+
+        let scratch_dir = Self::scratch_dir(&self.prefix);
+        // The unpacked `nix-store` expects its dependencies in `/nix/store`, so use the host's
+        populate_store(&self.prefix.join("nix"), &scratch_dir, &self.source, |_| {
+            let mut load_db_command = Command::new("nix-store");
+            load_db_command.arg("--store").arg(&self.prefix);
+            load_db_command
+        })
+        .map_err(Self::error)?;
+
+        crate::util::remove_dir_all(&scratch_dir, OnMissing::Ignore)
+            .map_err(|e| Self::error(ActionErrorKind::Remove(scratch_dir, e)))?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove the Nix store in `{}`",
+                self.prefix.join("nix").display()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let nix = self.prefix.join("nix");
+        crate::util::remove_dir_all(&nix, OnMissing::Ignore)
+            .map_err(|e| Self::error(ActionErrorKind::Remove(nix, e)))?;
+
+        Ok(())
+    }
+}
+
+/// Move the Nix unpacked into `scratch_dir` into the store in `nix_dir` (what is `/nix` to that
+/// store), register its paths with `nix-store --load-db`, and point the default profile at it
+///
+/// `load_db` is given the Nix package's store path, and returns the `nix-store` command to add
+/// `--load-db` to.
+pub(crate) fn populate_store(
+    nix_dir: &Path,
+    scratch_dir: &Path,
+    source: &NixSource,
+    load_db: impl FnOnce(&Path) -> Command,
+) -> Result<(), ActionErrorKind> {
+    let distribution = source.distribution();
+    let found_nix_paths: Vec<_> = std::fs::read_dir(scratch_dir)
+        .map_err(|e| ActionErrorKind::ReadDir(scratch_dir.to_path_buf(), e))?
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .starts_with(&format!("{distribution}-"))
+        })
+        .collect();
+    if found_nix_paths.len() != 1 {
+        return Err(ActionErrorKind::MalformedBinaryTarball);
+    }
+    let found_nix_path = found_nix_paths.into_iter().next().unwrap().path();
+
+    let src_store = found_nix_path.join("store");
+    let dest_store = nix_dir.join("store");
+    std::fs::create_dir_all(&dest_store)
+        .map_err(|e| ActionErrorKind::CreateDirectory(dest_store.clone(), e))?;
+    let entries = std::fs::read_dir(&src_store)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| ActionErrorKind::ReadDir(src_store.clone(), e))?;
+    for entry in entries {
+        let entry_dest = dest_store.join(entry.file_name());
+        std::fs::rename(entry.path(), &entry_dest)
+            .map_err(|e| ActionErrorKind::Rename(entry.path(), entry_dest, e))?;
+    }
+
+    let reginfo_path = found_nix_path.join(".reginfo");
+    let reginfo =
+        std::fs::read(&reginfo_path).map_err(|e| ActionErrorKind::Read(reginfo_path.clone(), e))?;
+
+    let nix_pkg = match source {
+        NixSource::Embedded => StorePaths::embedded().nix,
+        NixSource::Tarball {
+            store_paths: Some(store_paths),
+            ..
+        } => store_paths.nix.clone(),
+        NixSource::Tarball {
+            store_paths: None, ..
+        } => {
+            let version = tarball_version(&found_nix_path, distribution)
+                .ok_or(ActionErrorKind::MalformedBinaryTarball)?;
+            StorePaths::from_reginfo(&String::from_utf8_lossy(&reginfo), distribution, version)
+                .ok_or(ActionErrorKind::MalformedBinaryTarball)?
+                .nix
+        },
+    };
+
+    let mut load_db_command = load_db(&nix_pkg);
+    load_db_command
+        .arg("--load-db")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    tracing::trace!(
+        "Executing `{:?}` with stdin from `{}`",
+        load_db_command,
+        reginfo_path.display()
+    );
+    let mut handle = load_db_command
+        .spawn()
+        .map_err(|e| ActionErrorKind::command(&load_db_command, e))?;
+    let mut stdin = handle.stdin.take().unwrap();
+    stdin
+        .write_all(&reginfo)
+        .and_then(|()| stdin.flush())
+        .map_err(|e| ActionErrorKind::Write(PathBuf::from("/dev/stdin"), e))?;
+    drop(stdin);
+    let output = handle
+        .wait_with_output()
+        .map_err(|e| ActionErrorKind::command(&load_db_command, e))?;
+    if !output.status.success() {
+        return Err(ActionErrorKind::command_output(&load_db_command, output));
+    }
+
+    // Profiles are GC roots, so this also keeps the Nix package alive
+    let profiles = nix_dir.join("var/nix/profiles");
+    std::fs::create_dir_all(&profiles)
+        .map_err(|e| ActionErrorKind::CreateDirectory(profiles.clone(), e))?;
+    for (target, link) in [
+        (nix_pkg.as_path(), profiles.join("default-1-link")),
+        (Path::new("default-1-link"), profiles.join("default")),
+    ] {
+        std::os::unix::fs::symlink(target, &link)
+            .map_err(|e| ActionErrorKind::Symlink(target.to_path_buf(), link, e))?;
+    }
+
+    Ok(())
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ProvisionChrootStoreError {
+    #[error("The store prefix `{0}` must be an absolute path other than `/`")]
+    InvalidPrefix(PathBuf),
+    #[error(
+        "`nix-store` was not found, the host's Nix is needed to register the store paths under the prefix"
+    )]
+    NoHostNix,
+}
+
+impl From<ProvisionChrootStoreError> for ActionErrorKind {
+    fn from(val: ProvisionChrootStoreError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
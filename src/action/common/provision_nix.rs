@@ -3,9 +3,11 @@ use tracing::{Span, span};
 use super::CreateNixTree;
 use crate::{
     action::{
-        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
-        base::{FetchAndUnpackNix, MoveUnpackedNix},
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionEstimate, ActionTag,
+        StatefulAction,
+        base::{FetchAndUnpackNix, MoveUnpackedNix, SetProxyEnvironment},
     },
+    plan::check_cancelled,
     settings::{CommonSettings, SCRATCH_DIR},
 };
 use std::os::unix::fs::MetadataExt as _;
@@ -19,8 +21,11 @@ Place Nix and it's requirements onto the target
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "provision_nix")]
 pub struct ProvisionNix {
-    nix_store_gid: u32,
+    pub(crate) nix_store_gid: u32,
 
+    /// With `--proxy`, set before fetching Nix so every later command uses it too
+    #[serde(default)]
+    pub(crate) set_proxy: Option<StatefulAction<SetProxyEnvironment>>,
     pub(crate) fetch_nix: StatefulAction<FetchAndUnpackNix>,
     pub(crate) create_nix_tree: StatefulAction<CreateNixTree>,
     pub(crate) move_unpacked_nix: StatefulAction<MoveUnpackedNix>,
@@ -29,13 +34,26 @@ pub struct ProvisionNix {
 impl ProvisionNix {
     #[tracing::instrument(level = "debug", skip_all)]
     pub fn plan(settings: &CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
-        let fetch_nix = FetchAndUnpackNix::plan(PathBuf::from(SCRATCH_DIR))?;
+        let source = settings
+            .nix_source()
+            .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+        let set_proxy = settings
+            .proxy_for_install()
+            .map(SetProxyEnvironment::plan)
+            .transpose()?;
+        let fetch_nix = FetchAndUnpackNix::plan(
+            PathBuf::from(SCRATCH_DIR),
+            source.clone(),
+            settings.ssl_cert_file.clone(),
+        )?;
 
         let create_nix_tree = CreateNixTree::plan().map_err(Self::error)?;
         let move_unpacked_nix =
-            MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR)).map_err(Self::error)?;
+            MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR), source.distribution())
+                .map_err(Self::error)?;
         Ok(Self {
             nix_store_gid: settings.nix_build_group_id,
+            set_proxy,
             fetch_nix,
             create_nix_tree,
             move_unpacked_nix,
@@ -57,8 +75,13 @@ impl Action for ProvisionNix {
         span!(tracing::Level::DEBUG, "provision_nix",)
     }
 
+    fn execute_estimate(&self) -> ActionEstimate {
+        self.fetch_nix.estimate_execute() + self.move_unpacked_nix.estimate_execute()
+    }
+
     fn execute_description(&self) -> Vec<ActionDescription> {
         let Self {
+            set_proxy,
             fetch_nix,
             create_nix_tree,
             move_unpacked_nix,
@@ -66,6 +89,9 @@ impl Action for ProvisionNix {
         } = &self;
 
         let mut buf = Vec::default();
+        if let Some(set_proxy) = set_proxy {
+            buf.append(&mut set_proxy.describe_execute());
+        }
         buf.append(&mut fetch_nix.describe_execute());
 
         buf.append(&mut create_nix_tree.describe_execute());
@@ -84,11 +110,17 @@ impl Action for ProvisionNix {
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
         // Execute sequentially (no async parallelism needed)
+        if let Some(set_proxy) = &mut self.set_proxy {
+            set_proxy.try_execute().map_err(Self::error)?;
+        }
         self.fetch_nix.try_execute().map_err(Self::error)?;
+        check_cancelled().map_err(Self::error)?;
 
         self.create_nix_tree.try_execute().map_err(Self::error)?;
+        check_cancelled().map_err(Self::error)?;
 
         self.move_unpacked_nix.try_execute().map_err(Self::error)?;
+        check_cancelled().map_err(Self::error)?;
 
         ensure_nix_store_group(self.nix_store_gid).map_err(Self::error)?;
 
@@ -97,6 +129,7 @@ impl Action for ProvisionNix {
 
     fn revert_description(&self) -> Vec<ActionDescription> {
         let Self {
+            set_proxy: _,
             fetch_nix,
             create_nix_tree,
             move_unpacked_nix,
@@ -138,7 +171,7 @@ impl Action for ProvisionNix {
 
 /// Everything under /nix/store should be group-owned by the nix_build_group_id.
 /// This function walks /nix/store and makes sure that is true.
-fn ensure_nix_store_group(nix_store_gid: u32) -> Result<(), ActionErrorKind> {
+pub(crate) fn ensure_nix_store_group(nix_store_gid: u32) -> Result<(), ActionErrorKind> {
     let entryiter = walkdir::WalkDir::new(NIX_STORE_LOCATION)
         .follow_links(false)
         .same_file_system(true)
@@ -182,7 +215,9 @@ fn ensure_nix_store_group(nix_store_gid: u32) -> Result<(), ActionErrorKind> {
             Some((entry, metadata))
         });
 
+    // Paths which already have the right group are skipped, so stopping part way is safe to resume
     for (entry, _metadata) in entryiter {
+        check_cancelled()?;
         tracing::debug!(
             path = %entry.path().to_string_lossy(),
             "Re-owning path's group to {nix_store_gid}"
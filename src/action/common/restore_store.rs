@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::execute_command;
+use crate::store_export::{STORE_PATHS_FILE, check_export, nix_command, open_store_paths};
+
+/**
+Import a store exported by `nix-installer uninstall --backup-store` into the new install
+
+Only the store paths are imported, not the profiles or GC roots which referred to them.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "restore_store")]
+pub struct RestoreStore {
+    src: PathBuf,
+}
+
+impl RestoreStore {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(src: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let src = check_export(src.as_ref()).map_err(Self::error)?;
+
+        Ok(Self { src }.into())
+    }
+}
+
+#[typetag::serde(name = "restore_store")]
+impl Action for RestoreStore {
+    fn action_tag() -> ActionTag {
+        ActionTag("restore_store")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Restore the store exported to `{}`", self.src.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "restore_store",
+            src = %self.src.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Run `nix copy --from file://{} --no-check-sigs --stdin` with the paths in `{STORE_PATHS_FILE}`",
+                    self.src.display()
+                ),
+                "Profiles and GC roots are not restored, so the paths are kept only until the next garbage collection".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        // The paths were exported from a store which trusted them, unsigned paths built locally included
+        execute_command(
+            nix_command()
+                .args(["copy", "--no-check-sigs", "--stdin", "--from"])
+                .arg(format!("file://{}", self.src.display()))
+                .stdin(open_store_paths(&self.src).map_err(Self::error)?),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        // The restored paths are removed along with the store
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plan_requires_an_export() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        assert!(RestoreStore::plan(dir.path()).is_err());
+
+        std::fs::write(dir.path().join(STORE_PATHS_FILE), "")?;
+        std::fs::write(dir.path().join("nix-cache-info"), "StoreDir: /nix/store\n")?;
+        let action = RestoreStore::plan(dir.path())?;
+        assert_eq!(action.action.src, dir.path().canonicalize()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::plan::RECEIPT_FILE_NAME;
+
+/// When a [`RunHook`] runs its hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookStage {
+    PreInstall,
+    PostInstall,
+    PostUninstall,
+}
+
+impl std::fmt::Display for HookStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookStage::PreInstall => write!(f, "pre-install"),
+            HookStage::PostInstall => write!(f, "post-install"),
+            HookStage::PostUninstall => write!(f, "post-uninstall"),
+        }
+    }
+}
+
+/**
+Run a user-supplied hook, such as fleet tooling reporting the install, with variables describing
+the plan in its environment
+
+A post-uninstall hook does nothing on execute and runs on revert, so it belongs at the start of a
+plan. Its state in the receipt records whether the install got far enough for it to be needed.
+
+The hook gets:
+* `NIX_INSTALLER_HOOK`: `pre-install`, `post-install`, or `post-uninstall`
+* `NIX_INSTALLER_PLANNER`: the planner, such as `linux`
+* `NIX_INSTALLER_VERSION`: the version of `nix-installer` running it
+* `NIX_INSTALLER_RECEIPT`: where the install receipt is written
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "run_hook")]
+pub struct RunHook {
+    stage: HookStage,
+    hook: PathBuf,
+    planner: String,
+    state_dir: PathBuf,
+    /// Receipts from before `--receipt-path` only record the state directory the receipt is in
+    #[serde(default)]
+    receipt: Option<PathBuf>,
+}
+
+impl RunHook {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        stage: HookStage,
+        hook: impl AsRef<Path>,
+        planner: impl Into<String>,
+        state_dir: impl AsRef<Path>,
+        receipt: impl AsRef<Path>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let hook = hook.as_ref().to_path_buf();
+        if !hook.is_file() {
+            return Err(Self::error(RunHookError::NotFound(stage, hook)));
+        }
+
+        Ok(Self {
+            stage,
+            hook,
+            planner: planner.into(),
+            state_dir: state_dir.as_ref().to_path_buf(),
+            receipt: Some(receipt.as_ref().to_path_buf()),
+        }
+        .into())
+    }
+
+    fn run(&self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new(&self.hook)
+                .env("NIX_INSTALLER_HOOK", self.stage.to_string())
+                .env("NIX_INSTALLER_PLANNER", &self.planner)
+                .env("NIX_INSTALLER_VERSION", env!("CARGO_PKG_VERSION"))
+                .env(
+                    "NIX_INSTALLER_RECEIPT",
+                    self.receipt
+                        .clone()
+                        .unwrap_or_else(|| self.state_dir.join(RECEIPT_FILE_NAME)),
+                )
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+}
+
+#[typetag::serde(name = "run_hook")]
+impl Action for RunHook {
+    fn action_tag() -> ActionTag {
+        ActionTag("run_hook")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Run the {} hook `{}`", self.stage, self.hook.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "run_hook",
+            stage = %self.stage,
+            hook = tracing::field::display(self.hook.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        match self.stage {
+            HookStage::PreInstall | HookStage::PostInstall => {
+                vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+            },
+            HookStage::PostUninstall => vec![ActionDescription::new(
+                format!(
+                    "Record the post-uninstall hook `{}` in the receipt",
+                    self.hook.display()
+                ),
+                vec![],
+            )],
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        match self.stage {
+            HookStage::PreInstall | HookStage::PostInstall => self.run(),
+            HookStage::PostUninstall => Ok(()),
+        }
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        match self.stage {
+            HookStage::PreInstall | HookStage::PostInstall => vec![],
+            HookStage::PostUninstall => {
+                vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+            },
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        match self.stage {
+            HookStage::PreInstall | HookStage::PostInstall => Ok(()),
+            HookStage::PostUninstall => self.run(),
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum RunHookError {
+    #[error("The {0} hook `{1}` does not exist")]
+    NotFound(HookStage, PathBuf),
+}
+
+impl From<RunHookError> for ActionErrorKind {
+    fn from(val: RunHookError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use crate::{
     action::{ActionError, ActionErrorKind, ActionTag, StatefulAction},
     execute_command,
-    settings::{NIX_STORE_PATH, NSS_CACERT_STORE_PATH},
+    settings::DEFAULT_PROFILE,
 };
 
 use std::process::Command;
@@ -19,6 +19,9 @@ Setup the default system channel with nixpkgs-unstable.
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct SetupChannels {
     create_file: StatefulAction<CreateFile>,
+    /// Only place the channel configuration, `nix-channel --update` can be run once online
+    #[serde(default)]
+    offline: bool,
 }
 
 impl SetupChannels {
@@ -39,7 +42,7 @@ impl SetupChannels {
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan() -> Result<StatefulAction<Self>, ActionError> {
+    pub fn plan(offline: bool) -> Result<StatefulAction<Self>, ActionError> {
         let create_file = CreateFile::plan(
             Self::get_root_home()
                 .map_err(Self::error)?
@@ -50,7 +53,11 @@ impl SetupChannels {
             "https://nixos.org/channels/nixpkgs-unstable nixpkgs\n".to_string(),
             false,
         )?;
-        Ok(Self { create_file }.into())
+        Ok(Self {
+            create_file,
+            offline,
+        }
+        .into())
     }
 }
 
@@ -74,7 +81,13 @@ impl Action for SetupChannels {
             explanation.push(val.description.clone())
         }
 
-        explanation.push("Run `nix-channel --update nixpkgs`".to_string());
+        if self.offline {
+            explanation.push(
+                "Skip `nix-channel --update nixpkgs` while offline, run it once online".to_string(),
+            );
+        } else {
+            explanation.push("Run `nix-channel --update nixpkgs`".to_string());
+        }
 
         vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
@@ -84,19 +97,24 @@ impl Action for SetupChannels {
         // Place channel configuration
         self.create_file.try_execute()?;
 
-        let nix_pkg = PathBuf::from(NIX_STORE_PATH.trim());
-        let nss_ca_cert_pkg = PathBuf::from(NSS_CACERT_STORE_PATH.trim());
+        if self.offline {
+            tracing::info!("Offline, so run `nix-channel --update nixpkgs` later to fetch nixpkgs");
+            return Ok(());
+        }
+
+        // Set up by `SetupDefaultProfile`, whichever tarball Nix came from
+        let default_profile = PathBuf::from(DEFAULT_PROFILE);
 
         // Update nixpkgs channel
         execute_command(
-            Command::new(nix_pkg.join("bin/nix-channel"))
+            Command::new(default_profile.join("bin/nix-channel"))
                 .arg("--update")
                 .arg("nixpkgs")
                 .stdin(std::process::Stdio::null())
                 .env("HOME", Self::get_root_home().map_err(Self::error)?)
                 .env(
                     "NIX_SSL_CERT_FILE",
-                    nss_ca_cert_pkg.join("etc/ssl/certs/ca-bundle.crt"),
+                    default_profile.join("etc/ssl/certs/ca-bundle.crt"),
                 ), /* We could rely on setup_default_profile setting this
                    environment variable, but add this just to be explicit. */
         )
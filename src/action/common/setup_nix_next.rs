@@ -0,0 +1,192 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::{
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        base::{CreateDirectory, CreateFile},
+    },
+    execute_command,
+    settings::DEFAULT_PROFILE,
+    util::OnMissing,
+};
+
+pub const NIX_NEXT_PROFILE: &str = "/nix/var/nix/profiles/nix-next";
+pub const NIX_NEXT_WRAPPER_DIR: &str = "/usr/local/bin";
+pub const NIX_NEXT_WRAPPER: &str = "/usr/local/bin/nix-next";
+
+/**
+Install an additional Nix into the `nix-next` profile, along with a `nix-next` wrapper to run it
+
+This lets an administrator trial a new Nix against the running daemon before making it the default.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "setup_nix_next")]
+pub struct SetupNixNext {
+    store_path: PathBuf,
+    create_wrapper_dir: StatefulAction<CreateDirectory>,
+    create_wrapper: StatefulAction<CreateFile>,
+}
+
+impl SetupNixNext {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(store_path: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let store_path = store_path.as_ref().to_path_buf();
+        if store_path.parent() != Some(Path::new("/nix/store")) {
+            return Err(Self::error(SetupNixNextError::NotAStorePath(store_path)));
+        }
+
+        let create_wrapper_dir =
+            CreateDirectory::plan(NIX_NEXT_WRAPPER_DIR, None, None, 0o755, false)?;
+        let create_wrapper = CreateFile::plan(
+            NIX_NEXT_WRAPPER,
+            None,
+            None,
+            0o755,
+            format!(
+                "\
+                #!/bin/sh\n\
+                # Created by nix-installer, runs the Nix installed in `{NIX_NEXT_PROFILE}`\n\
+                exec {NIX_NEXT_PROFILE}/bin/nix \"$@\"\n\
+                "
+            ),
+            false,
+        )?;
+
+        Ok(Self {
+            store_path,
+            create_wrapper_dir,
+            create_wrapper,
+        }
+        .into())
+    }
+}
+
+#[typetag::serde(name = "setup_nix_next")]
+impl Action for SetupNixNext {
+    fn action_tag() -> ActionTag {
+        ActionTag("setup_nix_next")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Install `{}` into the `nix-next` profile",
+            self.store_path.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "setup_nix_next",
+            store_path = %self.store_path.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Substitute `{}` and install it into `{NIX_NEXT_PROFILE}`, leaving the default profile untouched",
+                    self.store_path.display()
+                ),
+                format!("Create `{NIX_NEXT_WRAPPER}` to run it"),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        // Set up by `SetupDefaultProfile`, whichever tarball Nix came from
+        let default_profile = PathBuf::from(DEFAULT_PROFILE);
+
+        execute_command(
+            Command::new(default_profile.join("bin/nix-env"))
+                .arg("--profile")
+                .arg(NIX_NEXT_PROFILE)
+                .arg("--install")
+                .arg(&self.store_path)
+                .stdin(std::process::Stdio::null())
+                .env(
+                    "NIX_SSL_CERT_FILE",
+                    default_profile.join("etc/ssl/certs/ca-bundle.crt"),
+                ),
+        )
+        .map_err(Self::error)?;
+
+        self.create_wrapper_dir.try_execute()?;
+        self.create_wrapper.try_execute()?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            "Remove the `nix-next` profile".to_string(),
+            vec![format!(
+                "Remove `{NIX_NEXT_WRAPPER}` and the `{NIX_NEXT_PROFILE}` profile links"
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let Err(err) = self.create_wrapper.try_revert() {
+            errors.push(err);
+        }
+        if let Err(err) = self.create_wrapper_dir.try_revert() {
+            errors.push(err);
+        }
+
+        // The profile and its generations (`nix-next-1-link`, ...) are symlinks, removing them lets the store paths be garbage collected
+        let profiles_dir = Path::new(NIX_NEXT_PROFILE)
+            .parent()
+            .expect("The profile has a parent directory");
+        if let Ok(entries) = std::fs::read_dir(profiles_dir) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                let is_generation = file_name
+                    .strip_prefix("nix-next-")
+                    .and_then(|rest| rest.strip_suffix("-link"))
+                    .is_some_and(|generation| generation.chars().all(|c| c.is_ascii_digit()));
+                if (file_name == "nix-next" || is_generation)
+                    && let Err(err) = crate::util::remove_file(&entry.path(), OnMissing::Ignore)
+                        .map_err(|e| ActionErrorKind::Remove(entry.path(), e))
+                {
+                    errors.push(Self::error(err));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum SetupNixNextError {
+    #[error(
+        "`{0}` is not a Nix store path, pass a path like `/nix/store/...-nix-2.34.0` to `--nix-next`"
+    )]
+    NotAStorePath(PathBuf),
+}
+
+impl From<SetupNixNextError> for ActionErrorKind {
+    fn from(val: SetupNixNextError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
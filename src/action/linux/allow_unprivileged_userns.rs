@@ -0,0 +1,183 @@
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::CreateFile;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::settings::UsernsWorkaround;
+
+pub const USERNS_SYSCTL_PATH: &str = "/etc/sysctl.d/60-nix-userns.conf";
+pub const USERNS_APPARMOR_PROFILE_PATH: &str = "/etc/apparmor.d/nix";
+
+const USERNS_SYSCTL_CONTENT: &str = "\
+# Generated by https://github.com/NixOS/nix-installer
+# Nix's build sandbox creates user namespaces
+kernel.apparmor_restrict_unprivileged_userns = 0
+";
+
+const USERNS_APPARMOR_PROFILE_CONTENT: &str = "\
+# Generated by https://github.com/NixOS/nix-installer
+# Nix's build sandbox creates user namespaces
+abi <abi/4.0>,
+include <tunables/global>
+
+profile nix /nix/store/*/bin/nix flags=(unconfined) {
+  userns,
+
+  include if exists <local/nix>
+}
+";
+
+/**
+Let Nix create user namespaces where AppArmor restricts unprivileged ones, as on Ubuntu 24.04
+
+Either lifts the restriction with a `sysctl.d` drop-in, or adds an AppArmor profile allowing only
+Nix, like those Ubuntu ships for browsers.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "allow_unprivileged_userns")]
+pub struct AllowUnprivilegedUserns {
+    workaround: UsernsWorkaround,
+    create_file: StatefulAction<CreateFile>,
+}
+
+impl AllowUnprivilegedUserns {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(workaround: UsernsWorkaround) -> Result<StatefulAction<Self>, ActionError> {
+        let (path, content) = match workaround {
+            UsernsWorkaround::Sysctl => (USERNS_SYSCTL_PATH, USERNS_SYSCTL_CONTENT),
+            UsernsWorkaround::Apparmor => (
+                USERNS_APPARMOR_PROFILE_PATH,
+                USERNS_APPARMOR_PROFILE_CONTENT,
+            ),
+        };
+        let create_file = CreateFile::plan(path, None, None, 0o0644, content.to_string(), false)
+            .map_err(Self::error)?;
+
+        Ok(Self {
+            workaround,
+            create_file,
+        }
+        .into())
+    }
+
+    fn path(&self) -> &'static str {
+        match self.workaround {
+            UsernsWorkaround::Sysctl => USERNS_SYSCTL_PATH,
+            UsernsWorkaround::Apparmor => USERNS_APPARMOR_PROFILE_PATH,
+        }
+    }
+}
+
+#[typetag::serde(name = "allow_unprivileged_userns")]
+impl Action for AllowUnprivilegedUserns {
+    fn action_tag() -> ActionTag {
+        ActionTag("allow_unprivileged_userns")
+    }
+    fn tracing_synopsis(&self) -> String {
+        match self.workaround {
+            UsernsWorkaround::Sysctl => {
+                "Lift the AppArmor restriction on unprivileged user namespaces".to_string()
+            },
+            UsernsWorkaround::Apparmor => {
+                "Allow Nix to create user namespaces with an AppArmor profile".to_string()
+            },
+        }
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "allow_unprivileged_userns",
+            workaround = %self.workaround,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let apply = match self.workaround {
+            UsernsWorkaround::Sysctl => format!("Apply it with `sysctl -p {USERNS_SYSCTL_PATH}`"),
+            UsernsWorkaround::Apparmor => {
+                format!("Load it with `apparmor_parser -r {USERNS_APPARMOR_PROFILE_PATH}`")
+            },
+        };
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Create `{}`", self.path()),
+                apply,
+                "Without user namespaces, sandboxed builds fail in `clone()`".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_file.try_execute().map_err(Self::error)?;
+
+        let mut command = match self.workaround {
+            UsernsWorkaround::Sysctl => {
+                let mut command = Command::new("sysctl");
+                command.args(["-p", USERNS_SYSCTL_PATH]);
+                command
+            },
+            UsernsWorkaround::Apparmor => {
+                let mut command = Command::new("apparmor_parser");
+                command.args(["-r", USERNS_APPARMOR_PROFILE_PATH]);
+                command
+            },
+        };
+        execute_command(command.stdin(std::process::Stdio::null())).map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove `{}`", self.path()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        // The profile has to be unloaded while its file still exists
+        if self.workaround == UsernsWorkaround::Apparmor {
+            if let Err(err) = execute_command(
+                Command::new("apparmor_parser")
+                    .args(["-R", USERNS_APPARMOR_PROFILE_PATH])
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+        }
+        if let Err(err) = self.create_file.try_revert() {
+            errors.push(err);
+        }
+        // Reapplying the remaining configuration restores the distribution's restriction
+        if self.workaround == UsernsWorkaround::Sysctl {
+            if let Err(err) = execute_command(
+                Command::new("sysctl")
+                    .arg("--system")
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
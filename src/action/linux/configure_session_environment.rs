@@ -0,0 +1,183 @@
+use std::path::Path;
+
+use tracing::{Span, span};
+
+use crate::action::base::{
+    CreateDirectory, CreateFile, CreateOrInsertIntoFile, create_or_insert_into_file,
+};
+use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::settings::{DEFAULT_PROFILE, InitSystem};
+
+pub const ENVIRONMENT_D_DIR: &str = "/etc/environment.d";
+pub const ENVIRONMENT_D_PATH: &str = "/etc/environment.d/nix.conf";
+pub const ETC_ENVIRONMENT_PATH: &str = "/etc/environment";
+
+/**
+Make Nix available to graphical sessions and systemd user services, which don't source shell profiles
+
+With systemd this writes `/etc/environment.d/nix.conf`, which `systemd --user` (and so most
+desktop sessions) read. Otherwise `/etc/environment` is used, which is read by `pam_env` but
+doesn't expand variables, so only `NIX_SSL_CERT_FILE` can be set there.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_session_environment")]
+pub struct ConfigureSessionEnvironment {
+    create_directory: Option<StatefulAction<CreateDirectory>>,
+    create_environment_d: Option<StatefulAction<CreateFile>>,
+    insert_into_etc_environment: Option<StatefulAction<CreateOrInsertIntoFile>>,
+}
+
+impl ConfigureSessionEnvironment {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(init: InitSystem, force: bool) -> Result<StatefulAction<Self>, ActionError> {
+        let this = if init == InitSystem::Systemd {
+            let create_directory = if Path::new(ENVIRONMENT_D_DIR).exists() {
+                None
+            } else {
+                Some(
+                    CreateDirectory::plan(ENVIRONMENT_D_DIR, None, None, 0o0755, false)
+                        .map_err(Self::error)?,
+                )
+            };
+            let create_environment_d = CreateFile::plan(
+                ENVIRONMENT_D_PATH,
+                None,
+                None,
+                0o0644,
+                environment_d_conf(),
+                force,
+            )
+            .map_err(Self::error)?;
+            Self {
+                create_directory,
+                create_environment_d: Some(create_environment_d),
+                insert_into_etc_environment: None,
+            }
+        } else {
+            let insert_into_etc_environment = CreateOrInsertIntoFile::plan(
+                ETC_ENVIRONMENT_PATH,
+                None,
+                None,
+                0o0644,
+                format!("NIX_SSL_CERT_FILE={DEFAULT_PROFILE}/etc/ssl/certs/ca-bundle.crt\n"),
+                create_or_insert_into_file::Position::End,
+            )
+            .map_err(Self::error)?;
+            Self {
+                create_directory: None,
+                create_environment_d: None,
+                insert_into_etc_environment: Some(insert_into_etc_environment),
+            }
+        };
+
+        Ok(this.into())
+    }
+
+    fn path(&self) -> &'static str {
+        if self.create_environment_d.is_some() {
+            ENVIRONMENT_D_PATH
+        } else {
+            ETC_ENVIRONMENT_PATH
+        }
+    }
+}
+
+/// `environment.d(5)` expands `${VAR}` and `${VAR:-default}`, so Nix can be added to what's there
+fn environment_d_conf() -> String {
+    format!(
+        "# Generated by https://github.com/NixOS/nix-installer\n\
+        # Makes Nix available to graphical sessions and systemd user services\n\
+        NIX_PROFILES=\"{DEFAULT_PROFILE} ${{HOME}}/.nix-profile\"\n\
+        NIX_SSL_CERT_FILE=${{NIX_SSL_CERT_FILE:-{DEFAULT_PROFILE}/etc/ssl/certs/ca-bundle.crt}}\n\
+        PATH=${{HOME}}/.nix-profile/bin:{DEFAULT_PROFILE}/bin:${{PATH}}\n\
+        XDG_DATA_DIRS=${{XDG_DATA_DIRS:-/usr/local/share:/usr/share}}:${{HOME}}/.nix-profile/share:{DEFAULT_PROFILE}/share\n"
+    )
+}
+
+#[typetag::serde(name = "configure_session_environment")]
+impl Action for ConfigureSessionEnvironment {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_session_environment")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Add Nix to the session environment in `{}`", self.path())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_session_environment",
+            path = self.path(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Graphical sessions and systemd user services don't source shell profiles, so would not find Nix otherwise".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        if let Some(create_directory) = &mut self.create_directory {
+            create_directory.try_execute().map_err(Self::error)?;
+        }
+        if let Some(create_environment_d) = &mut self.create_environment_d {
+            create_environment_d.try_execute().map_err(Self::error)?;
+        }
+        if let Some(insert_into_etc_environment) = &mut self.insert_into_etc_environment {
+            insert_into_etc_environment
+                .try_execute()
+                .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove Nix from the session environment in `{}`",
+                self.path()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let Some(insert_into_etc_environment) = &mut self.insert_into_etc_environment {
+            if let Err(err) = insert_into_etc_environment.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Some(create_environment_d) = &mut self.create_environment_d {
+            if let Err(err) = create_environment_d.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Some(create_directory) = &mut self.create_directory {
+            if let Err(err) = create_directory.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(
+                crate::action::ActionErrorKind::MultipleChildren(errors),
+            ))
+        }
+    }
+}
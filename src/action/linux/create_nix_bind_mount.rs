@@ -0,0 +1,294 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::create_directory::path_is_mountpoint;
+use crate::action::base::{
+    CreateDirectory, CreateFile, CreateOrInsertIntoFile, create_or_insert_into_file,
+};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::settings::InitSystem;
+
+const NIX_DIR: &str = "/nix";
+pub const NIX_MOUNT_UNIT: &str = "nix.mount";
+pub const NIX_MOUNT_UNIT_PATH: &str = "/etc/systemd/system/nix.mount";
+pub const FSTAB_PATH: &str = "/etc/fstab";
+
+/**
+Keep the Nix store on another filesystem, such as `/var/lib/nix`, by bind mounting it on `/nix`
+
+Unlike a symlink, Nix sees a real `/nix`, so no `nix.conf` changes are needed. With systemd the
+mount is persisted by a `nix.mount` unit, otherwise by an `/etc/fstab` entry.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_nix_bind_mount")]
+pub struct CreateNixBindMount {
+    backing_dir: PathBuf,
+    create_backing_dir: StatefulAction<CreateDirectory>,
+    create_mount_unit: Option<StatefulAction<CreateFile>>,
+    insert_fstab_entry: Option<StatefulAction<CreateOrInsertIntoFile>>,
+}
+
+impl CreateNixBindMount {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        backing_dir: impl AsRef<Path>,
+        init: InitSystem,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let backing_dir = backing_dir.as_ref().to_path_buf();
+        if !backing_dir.is_absolute() || backing_dir.starts_with(NIX_DIR) {
+            return Err(Self::error(CreateNixBindMountError::InvalidBackingDir(
+                backing_dir,
+            )));
+        }
+
+        if Path::new(NIX_DIR).exists()
+            && path_is_mountpoint(Path::new(NIX_DIR)).map_err(Self::error)?
+        {
+            return Err(Self::error(CreateNixBindMountError::AlreadyMounted));
+        }
+
+        let create_backing_dir =
+            CreateDirectory::plan(&backing_dir, None, None, 0o0755, true).map_err(Self::error)?;
+
+        let (create_mount_unit, insert_fstab_entry) = if init == InitSystem::Systemd {
+            let create_mount_unit = CreateFile::plan(
+                NIX_MOUNT_UNIT_PATH,
+                None,
+                None,
+                0o0644,
+                mount_unit(&backing_dir),
+                false,
+            )
+            .map_err(Self::error)?;
+            (Some(create_mount_unit), None)
+        } else {
+            let insert_fstab_entry = CreateOrInsertIntoFile::plan(
+                FSTAB_PATH,
+                None,
+                None,
+                0o0644,
+                fstab_entry(&backing_dir),
+                create_or_insert_into_file::Position::End,
+            )
+            .map_err(Self::error)?;
+            (None, Some(insert_fstab_entry))
+        };
+
+        Ok(Self {
+            backing_dir,
+            create_backing_dir,
+            create_mount_unit,
+            insert_fstab_entry,
+        }
+        .into())
+    }
+
+    fn persisted_in(&self) -> &'static str {
+        if self.create_mount_unit.is_some() {
+            NIX_MOUNT_UNIT_PATH
+        } else {
+            FSTAB_PATH
+        }
+    }
+}
+
+fn mount_unit(backing_dir: &Path) -> String {
+    format!(
+        "\
+        # Generated by https://github.com/NixOS/nix-installer\n\
+        [Unit]\n\
+        Description=Mount `{backing_dir}` on `/nix`\n\
+        RequiresMountsFor={backing_dir}\n\
+        \n\
+        [Mount]\n\
+        What={backing_dir}\n\
+        Where=/nix\n\
+        Type=none\n\
+        Options=bind\n\
+        \n\
+        [Install]\n\
+        WantedBy=local-fs.target\n\
+        ",
+        backing_dir = backing_dir.display(),
+    )
+}
+
+/// `fstab(5)` separates fields with whitespace, so spaces in the path are escaped as `\040`
+fn fstab_entry(backing_dir: &Path) -> String {
+    format!(
+        "{} /nix none bind 0 0\n",
+        backing_dir.display().to_string().replace(' ', "\\040")
+    )
+}
+
+#[typetag::serde(name = "create_nix_bind_mount")]
+impl Action for CreateNixBindMount {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_nix_bind_mount")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Bind mount `{}` on `{NIX_DIR}`", self.backing_dir.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_nix_bind_mount",
+            backing_dir = tracing::field::display(self.backing_dir.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Create the directory `{}`", self.backing_dir.display()),
+                format!("Persist the mount in `{}`", self.persisted_in()),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_backing_dir.try_execute().map_err(Self::error)?;
+
+        if let Some(create_mount_unit) = &mut self.create_mount_unit {
+            create_mount_unit.try_execute().map_err(Self::error)?;
+            execute_command(
+                Command::new("systemctl")
+                    .arg("daemon-reload")
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+            execute_command(
+                Command::new("systemctl")
+                    .args(["enable", "--now", NIX_MOUNT_UNIT])
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        if let Some(insert_fstab_entry) = &mut self.insert_fstab_entry {
+            insert_fstab_entry.try_execute().map_err(Self::error)?;
+            execute_command(
+                Command::new("mount")
+                    .arg("--bind")
+                    .arg(&self.backing_dir)
+                    .arg(NIX_DIR)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Unmount `{NIX_DIR}` and remove `{}`",
+                self.backing_dir.display()
+            ),
+            vec![format!("Remove the mount from `{}`", self.persisted_in())],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let Some(create_mount_unit) = &mut self.create_mount_unit {
+            // Stopping the unit unmounts `/nix`
+            if let Err(err) = execute_command(
+                Command::new("systemctl")
+                    .args(["disable", "--now", NIX_MOUNT_UNIT])
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+            if let Err(err) = create_mount_unit.try_revert() {
+                errors.push(err);
+            }
+            if let Err(err) = execute_command(
+                Command::new("systemctl")
+                    .arg("daemon-reload")
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+        }
+
+        if let Some(insert_fstab_entry) = &mut self.insert_fstab_entry {
+            if let Err(err) = execute_command(
+                Command::new("umount")
+                    .arg(NIX_DIR)
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+            if let Err(err) = insert_fstab_entry.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        // Only remove the store once it's unmounted, so nothing is left holding it
+        if errors.is_empty() {
+            if let Err(err) = self.create_backing_dir.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum CreateNixBindMountError {
+    #[error(
+        "The backing directory `{0}` must be an absolute path outside of `/nix`, such as `/var/lib/nix`"
+    )]
+    InvalidBackingDir(PathBuf),
+    #[error("`/nix` is already a mountpoint, install without `--nix-backing-dir` to use it as-is")]
+    AlreadyMounted,
+}
+
+impl From<CreateNixBindMountError> for ActionErrorKind {
+    fn from(val: CreateNixBindMountError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{fstab_entry, mount_unit};
+
+    #[test]
+    fn bind_mount_persistence() {
+        assert_eq!(
+            fstab_entry(Path::new("/srv/nix store")),
+            "/srv/nix\\040store /nix none bind 0 0\n"
+        );
+
+        let unit = mount_unit(Path::new("/var/lib/nix"));
+        assert!(unit.contains("What=/var/lib/nix\n"));
+        assert!(unit.contains("Where=/nix\n"));
+        assert!(unit.contains("Options=bind\n"));
+    }
+}
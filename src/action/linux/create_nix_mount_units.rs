@@ -0,0 +1,223 @@
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::CreateFile;
+use crate::action::linux::create_nix_bind_mount::{NIX_MOUNT_UNIT, NIX_MOUNT_UNIT_PATH};
+use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::execute_command;
+use crate::settings::Filesystem;
+
+pub const NIX_AUTOMOUNT_UNIT: &str = "nix.automount";
+pub const NIX_AUTOMOUNT_UNIT_PATH: &str = "/etc/systemd/system/nix.automount";
+
+/**
+Mount a dedicated device on `/nix` with a `nix.mount` unit, and optionally a `nix.automount` unit
+
+This replaces hand-editing `/etc/fstab` before installing; the units are removed on revert.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_nix_mount_units")]
+pub struct CreateNixMountUnits {
+    what: String,
+    create_mount_unit: StatefulAction<CreateFile>,
+    create_automount_unit: Option<StatefulAction<CreateFile>>,
+}
+
+impl CreateNixMountUnits {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        what: impl AsRef<str>,
+        filesystem: Filesystem,
+        automount: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let what = what.as_ref().to_string();
+        let create_mount_unit = CreateFile::plan(
+            NIX_MOUNT_UNIT_PATH,
+            None,
+            None,
+            0o0644,
+            mount_unit(&what, filesystem, automount),
+            false,
+        )
+        .map_err(Self::error)?;
+        let create_automount_unit = if automount {
+            Some(
+                CreateFile::plan(
+                    NIX_AUTOMOUNT_UNIT_PATH,
+                    None,
+                    None,
+                    0o0644,
+                    automount_unit(),
+                    false,
+                )
+                .map_err(Self::error)?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            what,
+            create_mount_unit,
+            create_automount_unit,
+        }
+        .into())
+    }
+
+    /// The unit which is enabled, and so the one to start and stop
+    fn unit(&self) -> &'static str {
+        if self.create_automount_unit.is_some() {
+            NIX_AUTOMOUNT_UNIT
+        } else {
+            NIX_MOUNT_UNIT
+        }
+    }
+}
+
+/// With an automount, the mount is pulled in by it rather than installed itself
+fn mount_unit(what: &str, filesystem: Filesystem, automount: bool) -> String {
+    let install = if automount {
+        String::new()
+    } else {
+        "\n[Install]\nWantedBy=local-fs.target\n".to_string()
+    };
+    format!(
+        "\
+        # Generated by https://github.com/NixOS/nix-installer\n\
+        [Unit]\n\
+        Description=Mount `{what}` on `/nix`\n\
+        \n\
+        [Mount]\n\
+        What={what}\n\
+        Where=/nix\n\
+        Type={filesystem}\n\
+        Options=defaults\n\
+        {install}",
+    )
+}
+
+fn automount_unit() -> String {
+    "\
+    # Generated by https://github.com/NixOS/nix-installer\n\
+    [Unit]\n\
+    Description=Automount `/nix`\n\
+    \n\
+    [Automount]\n\
+    Where=/nix\n\
+    \n\
+    [Install]\n\
+    WantedBy=local-fs.target\n\
+    "
+    .to_string()
+}
+
+#[typetag::serde(name = "create_nix_mount_units")]
+impl Action for CreateNixMountUnits {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_nix_mount_units")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Mount `{}` on `/nix` with `{}`", self.what, self.unit())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_nix_mount_units",
+            what = %self.what,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![format!("Create `{NIX_MOUNT_UNIT_PATH}`")];
+        if self.create_automount_unit.is_some() {
+            explanation.push(format!("Create `{NIX_AUTOMOUNT_UNIT_PATH}`"));
+        }
+        explanation.push(format!("Enable (and start) `{}`", self.unit()));
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_mount_unit.try_execute().map_err(Self::error)?;
+        if let Some(create_automount_unit) = &mut self.create_automount_unit {
+            create_automount_unit.try_execute().map_err(Self::error)?;
+        }
+
+        execute_command(
+            Command::new("systemctl")
+                .arg("daemon-reload")
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+        execute_command(
+            Command::new("systemctl")
+                .args(["enable", "--now", self.unit()])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Unmount `/nix` and remove `{}`", self.unit()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let Err(err) = execute_command(
+            Command::new("systemctl")
+                .args(["disable", "--now", self.unit()])
+                .stdin(std::process::Stdio::null()),
+        ) {
+            errors.push(Self::error(err));
+        }
+        // Stopping an automount leaves an already triggered mount in place
+        if self.create_automount_unit.is_some() {
+            if let Err(err) = execute_command(
+                Command::new("systemctl")
+                    .args(["stop", NIX_MOUNT_UNIT])
+                    .stdin(std::process::Stdio::null()),
+            ) {
+                errors.push(Self::error(err));
+            }
+        }
+
+        if let Some(create_automount_unit) = &mut self.create_automount_unit {
+            if let Err(err) = create_automount_unit.try_revert() {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = self.create_mount_unit.try_revert() {
+            errors.push(err);
+        }
+
+        if let Err(err) = execute_command(
+            Command::new("systemctl")
+                .arg("daemon-reload")
+                .stdin(std::process::Stdio::null()),
+        ) {
+            errors.push(Self::error(err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(
+                crate::action::ActionErrorKind::MultipleChildren(errors),
+            ))
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::{CreateDirectory, CreateFile};
+use crate::action::common::configure_shell_profile::PROFILE_NIX_FILE_FISH;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::util::which;
+
+pub const NIX_SYSEXT_DIR: &str = "/var/lib/extensions/nix";
+pub const NIX_SYSEXT_UNIT: &str = "nix-sysext.service";
+pub const NIX_SYSEXT_UNIT_PATH: &str = "/etc/systemd/system/nix-sysext.service";
+
+const UNIT_SRC_DIR: &str = "/nix/var/nix/profiles/default/lib/systemd/system";
+const UNITS: &[&str] = &["nix-daemon.service", "nix-daemon.socket"];
+
+/**
+Package the `nix-daemon` units and the fish profile into a systemd-sysext extension, merged over
+`/usr` on boot by a `nix-sysext.service` unit
+
+SteamOS updates replace the read-only `/usr` and can drop files in `/etc`, but extensions in
+`/var/lib/extensions` are merged over whichever image is booted. The extension accepts any OS
+version (`ID=_any`), so it keeps applying after an update.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_steamos_sysext")]
+pub struct CreateSteamosSysext {
+    create_directory: StatefulAction<CreateDirectory>,
+    create_merge_unit: StatefulAction<CreateFile>,
+}
+
+impl CreateSteamosSysext {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan() -> Result<StatefulAction<Self>, ActionError> {
+        if which("systemd-sysext").is_none() {
+            return Err(Self::error(ActionErrorKind::MissingSteamosBinary(
+                "systemd-sysext".into(),
+            )));
+        }
+        let create_directory =
+            CreateDirectory::plan(NIX_SYSEXT_DIR, None, None, 0o0755, true).map_err(Self::error)?;
+        let create_merge_unit = CreateFile::plan(
+            NIX_SYSEXT_UNIT_PATH,
+            None,
+            None,
+            0o0644,
+            merge_unit(),
+            false,
+        )
+        .map_err(Self::error)?;
+
+        Ok(Self {
+            create_directory,
+            create_merge_unit,
+        }
+        .into())
+    }
+
+    /// The files of the extension, relative to its root, and their contents
+    fn files() -> Vec<(PathBuf, String)> {
+        vec![
+            (
+                PathBuf::from("usr/lib/extension-release.d/extension-release.nix"),
+                "ID=_any\n".to_string(),
+            ),
+            (
+                PathBuf::from("usr/share/fish/vendor_conf.d/nix.fish"),
+                format!(
+                    "\
+                    # Nix\n\
+                    if test -e '{PROFILE_NIX_FILE_FISH}'\n\
+                    {inde}. '{PROFILE_NIX_FILE_FISH}'\n\
+                    end\n\
+                    # End Nix\n\
+                    ",
+                    inde = "    ", // indent
+                ),
+            ),
+        ]
+    }
+}
+
+fn merge_unit() -> String {
+    format!(
+        "\
+        # Generated by https://github.com/NixOS/nix-installer\n\
+        [Unit]\n\
+        Description=Merge the Nix system extension in `{NIX_SYSEXT_DIR}`\n\
+        After=nix.mount\n\
+        Requires=nix.mount\n\
+        DefaultDependencies=no\n\
+        \n\
+        [Service]\n\
+        Type=oneshot\n\
+        RemainAfterExit=yes\n\
+        ExecStart=/usr/bin/systemd-sysext refresh\n\
+        ExecStart=/usr/bin/systemctl daemon-reload\n\
+        ExecStart=/usr/bin/systemctl restart --no-block nix-daemon.socket\n\
+        \n\
+        [Install]\n\
+        WantedBy=sysinit.target\n\
+        "
+    )
+}
+
+#[typetag::serde(name = "create_steamos_sysext")]
+impl Action for CreateSteamosSysext {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_steamos_sysext")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Create a systemd-sysext extension for Nix in `{NIX_SYSEXT_DIR}`")
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(tracing::Level::DEBUG, "create_steamos_sysext",)
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Link {} into the extension's `/usr/lib/systemd/system`",
+                    UNITS
+                        .iter()
+                        .map(|unit| format!("`{unit}`"))
+                        .collect::<Vec<_>>()
+                        .join(" and ")
+                ),
+                "Add a fish `vendor_conf.d` profile to the extension".to_string(),
+                format!("Create and enable `{NIX_SYSEXT_UNIT_PATH}` to merge it on boot"),
+                "SteamOS updates replace `/usr`, but the extension is merged over each new image"
+                    .to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_directory.try_execute().map_err(Self::error)?;
+
+        let root = Path::new(NIX_SYSEXT_DIR);
+        for (relative, buf) in Self::files() {
+            let path = root.join(relative);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| Self::error(ActionErrorKind::CreateDirectory(parent.into(), e)))?;
+            }
+            std::fs::write(&path, buf)
+                .map_err(|e| Self::error(ActionErrorKind::Write(path.clone(), e)))?;
+        }
+
+        let unit_dir = root.join("usr/lib/systemd/system");
+        std::fs::create_dir_all(&unit_dir)
+            .map_err(|e| Self::error(ActionErrorKind::CreateDirectory(unit_dir.clone(), e)))?;
+        for unit in UNITS {
+            let src = Path::new(UNIT_SRC_DIR).join(unit);
+            let dest = unit_dir.join(unit);
+            if dest.is_symlink() {
+                continue;
+            }
+            std::os::unix::fs::symlink(&src, &dest)
+                .map_err(|e| Self::error(ActionErrorKind::Symlink(src, dest, e)))?;
+        }
+
+        self.create_merge_unit.try_execute().map_err(Self::error)?;
+
+        execute_command(
+            Command::new("systemctl")
+                .arg("daemon-reload")
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+        execute_command(
+            Command::new("systemctl")
+                .args(["enable", "--now", NIX_SYSEXT_UNIT])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Unmerge and remove the Nix system extension in `{NIX_SYSEXT_DIR}`"),
+            vec![format!("Disable and remove `{NIX_SYSEXT_UNIT_PATH}`")],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let Err(err) = execute_command(
+            Command::new("systemctl")
+                .args(["disable", NIX_SYSEXT_UNIT])
+                .stdin(std::process::Stdio::null()),
+        ) {
+            errors.push(Self::error(err));
+        }
+        if let Err(err) = self.create_merge_unit.try_revert() {
+            errors.push(err);
+        }
+        if let Err(err) = self.create_directory.try_revert() {
+            errors.push(err);
+        }
+
+        // Merges the remaining extensions, if any, without this one
+        if let Err(err) = execute_command(
+            Command::new("systemd-sysext")
+                .arg("refresh")
+                .stdin(std::process::Stdio::null()),
+        ) {
+            errors.push(Self::error(err));
+        }
+        if let Err(err) = execute_command(
+            Command::new("systemctl")
+                .arg("daemon-reload")
+                .stdin(std::process::Stdio::null()),
+        ) {
+            errors.push(Self::error(err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::path::Path;
+use std::process::Command;
+
+use nix::unistd::{Group, User};
+use tracing::{Span, span};
+
+use crate::action::base::CreateFile;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::settings::CommonSettings;
+use crate::util::which;
+
+pub const SYSUSERS_FRAGMENT_PATH: &str = "/etc/sysusers.d/nix.conf";
+pub const TMPFILES_FRAGMENT_PATH: &str = "/etc/tmpfiles.d/nix.conf";
+
+/**
+Create the Nix build users and group from a `sysusers.d` fragment applied with `systemd-sysusers`,
+instead of `useradd` and friends
+
+The fragments stay in place, so the users (and, from the `tmpfiles.d` fragment, the home directory
+`sysusers.d` leaves to others) are recreated at boot if `/etc` or `/var` is reset, as on stateless
+and image based systems.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_sysusers_build_users")]
+pub struct CreateSysusersBuildUsers {
+    nix_build_group_name: String,
+    nix_build_group_id: u32,
+    nix_build_user_count: u32,
+    nix_build_user_prefix: String,
+    nix_build_user_id_base: u32,
+    create_fragment: StatefulAction<CreateFile>,
+    #[serde(default)]
+    create_tmpfiles_fragment: Option<StatefulAction<CreateFile>>,
+}
+
+impl CreateSysusersBuildUsers {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(settings: &CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+        if which("systemd-sysusers").is_none() {
+            return Err(Self::error(ActionErrorKind::MissingSysusersCommand));
+        }
+        // `systemd-sysusers` never removes users, so uninstalling still needs these
+        if !(which("userdel").is_some() || which("deluser").is_some()) {
+            return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
+        }
+        if !(which("groupdel").is_some() || which("delgroup").is_some()) {
+            return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
+        }
+        check_homed_conflicts(settings).map_err(Self::error)?;
+
+        let create_fragment = CreateFile::plan(
+            SYSUSERS_FRAGMENT_PATH,
+            None,
+            None,
+            0o0644,
+            sysusers_fragment(settings),
+            settings.force,
+        )?;
+        let create_tmpfiles_fragment = CreateFile::plan(
+            TMPFILES_FRAGMENT_PATH,
+            None,
+            None,
+            0o0644,
+            TMPFILES_FRAGMENT.to_string(),
+            settings.force,
+        )?;
+
+        let this = Self {
+            nix_build_group_name: settings.nix_build_group_name.clone(),
+            nix_build_group_id: settings.nix_build_group_id,
+            nix_build_user_count: settings.nix_build_user_count,
+            nix_build_user_prefix: settings.nix_build_user_prefix.clone(),
+            nix_build_user_id_base: settings.nix_build_user_id_base,
+            create_fragment,
+            create_tmpfiles_fragment: Some(create_tmpfiles_fragment),
+        };
+
+        if this.create_fragment.state == crate::action::ActionState::Completed
+            && this
+                .create_tmpfiles_fragment
+                .as_ref()
+                .is_some_and(|fragment| fragment.state == crate::action::ActionState::Completed)
+            && this.users_and_group_exist().map_err(Self::error)?
+        {
+            tracing::debug!("Creating build users with `systemd-sysusers` already complete");
+            return Ok(StatefulAction::completed(this));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    /// The `sysusers.d` fragment, as installs before it was renamed wrote another
+    pub(crate) fn fragment_path(&self) -> &Path {
+        &self.create_fragment.action.path
+    }
+
+    fn user_names(&self) -> impl Iterator<Item = String> + '_ {
+        (1..=self.nix_build_user_count)
+            .map(|index| format!("{}{index}", self.nix_build_user_prefix))
+    }
+
+    fn users_and_group_exist(&self) -> Result<bool, ActionErrorKind> {
+        if Group::from_name(&self.nix_build_group_name)
+            .map_err(|e| ActionErrorKind::GettingGroupId(self.nix_build_group_name.clone(), e))?
+            .is_none()
+        {
+            return Ok(false);
+        }
+        for name in self.user_names() {
+            if User::from_name(&name)
+                .map_err(|e| ActionErrorKind::GettingUserId(name.clone(), e))?
+                .is_none()
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[typetag::serde(name = "create_sysusers_build_users")]
+impl Action for CreateSysusersBuildUsers {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_sysusers_build_users")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Create build users (UID {}-{}) and group (GID {}) with `systemd-sysusers`",
+            self.nix_build_user_id_base + 1,
+            self.nix_build_user_id_base + self.nix_build_user_count,
+            self.nix_build_group_id
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_sysusers_build_users",
+            nix_build_user_count = self.nix_build_user_count,
+            nix_build_group_name = self.nix_build_group_name,
+            nix_build_group_id = self.nix_build_group_id,
+            nix_build_user_prefix = self.nix_build_user_prefix,
+            nix_build_user_id_base = self.nix_build_user_id_base,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "The Nix daemon requires system users (and a group they share) which it can act as in order to build".to_string(),
+                format!("Write `{SYSUSERS_FRAGMENT_PATH}` describing the users and group, then apply it with `systemd-sysusers`"),
+                format!("Write `{TMPFILES_FRAGMENT_PATH}` for their home directory, then apply it with `systemd-tmpfiles`"),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_fragment.try_execute().map_err(Self::error)?;
+
+        execute_command(
+            Command::new("systemd-sysusers")
+                .arg(SYSUSERS_FRAGMENT_PATH)
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        if let Some(create_tmpfiles_fragment) = &mut self.create_tmpfiles_fragment {
+            create_tmpfiles_fragment
+                .try_execute()
+                .map_err(Self::error)?;
+            execute_command(
+                Command::new("systemd-tmpfiles")
+                    .args(["--create", TMPFILES_FRAGMENT_PATH])
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            "Remove Nix users and group".to_string(),
+            vec![
+                format!("Remove `{SYSUSERS_FRAGMENT_PATH}` and `{TMPFILES_FRAGMENT_PATH}`"),
+                format!(
+                    "Delete users `{}1` through `{}{}` and group `{}`",
+                    self.nix_build_user_prefix,
+                    self.nix_build_user_prefix,
+                    self.nix_build_user_count,
+                    self.nix_build_group_name
+                ),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        // Removed first, so the users are not recreated at boot if deleting them fails
+        if let Err(err) = self.create_fragment.try_revert() {
+            errors.push(err);
+        }
+        // `/var/empty` is left, other users likely share it
+        if let Some(create_tmpfiles_fragment) = &mut self.create_tmpfiles_fragment {
+            if let Err(err) = create_tmpfiles_fragment.try_revert() {
+                errors.push(err);
+            }
+        }
+
+        for name in self.user_names().collect::<Vec<_>>() {
+            if let Err(err) = delete(&name, ["userdel", "deluser"])
+                .map_err(|e| e.unwrap_or(ActionErrorKind::MissingUserDeletionCommand))
+            {
+                errors.push(Self::error(err));
+            }
+        }
+
+        if let Err(err) = delete(&self.nix_build_group_name, ["groupdel", "delgroup"])
+            .map_err(|e| e.unwrap_or(ActionErrorKind::MissingGroupDeletionCommand))
+        {
+            errors.push(Self::error(err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
+
+/// Delete a user or group with the first of `commands` found in `PATH`, `Err(None)` if none were found
+fn delete(name: &str, commands: [&str; 2]) -> Result<(), Option<ActionErrorKind>> {
+    let command = commands
+        .into_iter()
+        .find(|command| which(command).is_some())
+        .ok_or(None)?;
+    execute_command(
+        Command::new(command)
+            .arg(name)
+            .stdin(std::process::Stdio::null()),
+    )
+    .map_err(Some)?;
+    Ok(())
+}
+
+/// The home directory of the build users, which `systemd-sysusers` doesn't create
+const TMPFILES_FRAGMENT: &str = "\
+# Created by nix-installer, see tmpfiles.d(5)
+d /var/empty 0555 root root -
+";
+
+fn sysusers_fragment(settings: &CommonSettings) -> String {
+    let CommonSettings {
+        nix_build_group_name: group,
+        nix_build_group_id: gid,
+        nix_build_user_prefix: prefix,
+        nix_build_user_id_base: uid_base,
+        nix_build_user_count: count,
+        ..
+    } = settings;
+
+    let mut buf = format!(
+        "# Created by nix-installer, see sysusers.d(5)\n\
+        g {group} {gid}\n"
+    );
+    for index in 1..=*count {
+        let uid = uid_base + index;
+        buf.push_str(&format!(
+            "u {prefix}{index} {uid}:{gid} \"Nix build user {index}\" /var/empty /sbin/nologin\n\
+            m {prefix}{index} {group}\n"
+        ));
+    }
+    buf
+}
+
+/// Users managed by `systemd-homed`, as `(name, uid)`
+fn homed_users() -> Vec<(String, u32)> {
+    if which("homectl").is_none() {
+        return vec![];
+    }
+    let output = match Command::new("homectl")
+        .args(["list", "--no-legend", "--no-pager"])
+        .stdin(std::process::Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        // `systemd-homed` is installed but not running, so it manages nobody
+        _ => return vec![],
+    };
+    parse_homectl_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_homectl_list(output: &str) -> Vec<(String, u32)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next()?;
+            let uid = columns.next()?.parse().ok()?;
+            Some((name.to_string(), uid))
+        })
+        .collect()
+}
+
+/// Refuse to create build users which would collide with users managed by `systemd-homed`
+///
+/// Homed users are only resolvable while they are logged in, so a free name or UID does not mean it is unused.
+pub(crate) fn check_homed_conflicts(settings: &CommonSettings) -> Result<(), ActionErrorKind> {
+    let build_users = (1..=settings.nix_build_user_count)
+        .map(|index| {
+            (
+                format!("{}{index}", settings.nix_build_user_prefix),
+                settings.nix_build_user_id_base + index,
+            )
+        })
+        .collect::<Vec<_>>();
+    for (homed_name, homed_uid) in homed_users() {
+        if let Some((name, uid)) = build_users
+            .iter()
+            .find(|(name, uid)| *name == homed_name || *uid == homed_uid)
+        {
+            return Err(ActionErrorKind::HomedUserConflict {
+                name: name.clone(),
+                uid: *uid,
+                homed_name,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether `systemd-sysusers` can be used to create users on this system
+pub(crate) fn sysusers_available() -> bool {
+    which("systemd-sysusers").is_some() && Path::new("/run/systemd/system").exists()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fragment_lists_group_then_users() {
+        let mut settings = CommonSettings::try_default().unwrap();
+        settings.nix_build_group_name = "nixbld".into();
+        settings.nix_build_group_id = 30000;
+        settings.nix_build_user_prefix = "nixbld".into();
+        settings.nix_build_user_id_base = 30000;
+        settings.nix_build_user_count = 2;
+
+        assert_eq!(
+            sysusers_fragment(&settings),
+            "\
+            # Created by nix-installer, see sysusers.d(5)\n\
+            g nixbld 30000\n\
+            u nixbld1 30001:30000 \"Nix build user 1\" /var/empty /sbin/nologin\n\
+            m nixbld1 nixbld\n\
+            u nixbld2 30002:30000 \"Nix build user 2\" /var/empty /sbin/nologin\n\
+            m nixbld2 nixbld\n\
+            "
+        );
+    }
+
+    #[test]
+    fn parses_homectl_list() {
+        let output = "alice 60001 60001 active Alice /home/alice /bin/bash\nbob 60002 60002 inactive \"Bob\" /home/bob /bin/zsh\n";
+        assert_eq!(
+            parse_homectl_list(output),
+            vec![("alice".to_string(), 60001), ("bob".to_string(), 60002)]
+        );
+    }
+}
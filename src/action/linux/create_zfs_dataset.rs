@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::execute_command;
+use crate::util::which;
+
+use crate::action::{Action, ActionDescription};
+
+/**
+Create a ZFS dataset mounted at the given path (typically `/nix`)
+
+Datasets which already exist with the expected mountpoint are adopted and left alone on revert.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_zfs_dataset")]
+pub struct CreateZfsDataset {
+    dataset: String,
+    mountpoint: PathBuf,
+}
+
+impl CreateZfsDataset {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        dataset: impl AsRef<str>,
+        mountpoint: impl AsRef<Path>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            dataset: dataset.as_ref().to_string(),
+            mountpoint: mountpoint.as_ref().to_path_buf(),
+        };
+
+        if which("zfs").is_none() {
+            return Err(Self::error(ActionErrorKind::MissingZfsBinary));
+        }
+
+        let mut command = Command::new("zfs");
+        command.args(["get", "-H", "-o", "value", "mountpoint", &this.dataset]);
+        command.stdin(std::process::Stdio::null());
+        let output = command
+            .output()
+            .map_err(|e| Self::error(ActionErrorKind::command(&command, e)))?;
+
+        if !output.status.success() {
+            // The dataset does not exist yet
+            return Ok(StatefulAction::uncompleted(this));
+        }
+
+        let existing_mountpoint = String::from_utf8(output.stdout).map_err(Self::error)?;
+        let existing_mountpoint = existing_mountpoint.trim();
+        if Path::new(existing_mountpoint) != this.mountpoint {
+            return Err(Self::error(ActionErrorKind::ZfsDatasetMountpointMismatch(
+                this.dataset,
+                existing_mountpoint.to_string(),
+                this.mountpoint,
+            )));
+        }
+
+        tracing::debug!(
+            "ZFS dataset `{}` already mounted at `{}`, not creating or destroying it",
+            this.dataset,
+            this.mountpoint.display()
+        );
+        Ok(StatefulAction::skipped(this))
+    }
+}
+
+#[typetag::serde(name = "create_zfs_dataset")]
+impl Action for CreateZfsDataset {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_zfs_dataset")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Create a ZFS dataset `{}` mounted at `{}`",
+            self.dataset,
+            self.mountpoint.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_zfs_dataset",
+            dataset = %self.dataset,
+            mountpoint = %self.mountpoint.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                "Automatic snapshots are disabled on the dataset (`com.sun:auto-snapshot=false`), the Nix store is reproducible and can be large".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        let Self {
+            dataset,
+            mountpoint,
+        } = self;
+
+        execute_command(
+            Command::new("zfs")
+                .args([
+                    "create",
+                    "-o",
+                    &format!("mountpoint={}", mountpoint.display()),
+                    "-o",
+                    "com.sun:auto-snapshot=false",
+                    dataset,
+                ])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Destroy the ZFS dataset `{}` mounted at `{}`",
+                self.dataset,
+                self.mountpoint.display()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new("zfs")
+                .args(["destroy", "-r", &self.dataset])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::execute_command;
+use crate::settings::Filesystem;
+use crate::util::which;
+
+pub const NIX_DEVICE_LABEL: &str = "nix";
+
+/**
+Format a block device (such as `/dev/vdb1`) labeled `nix`, to hold the Nix store
+
+Devices which already have a filesystem of the expected type labeled `nix` are adopted and left
+alone on revert; any other filesystem is refused rather than overwritten.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "format_nix_device")]
+pub struct FormatNixDevice {
+    device: PathBuf,
+    filesystem: Filesystem,
+}
+
+impl FormatNixDevice {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        device: impl AsRef<Path>,
+        filesystem: Filesystem,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            device: device.as_ref().to_path_buf(),
+            filesystem,
+        };
+
+        if !this.device.exists() {
+            return Err(Self::error(FormatNixDeviceError::NoDevice(this.device)));
+        }
+        if which(this.mkfs()).is_none() {
+            return Err(Self::error(FormatNixDeviceError::MissingMkfs(this.mkfs())));
+        }
+
+        let mut command = Command::new("blkid");
+        command.args(["-o", "export"]);
+        command.arg(&this.device);
+        command.stdin(std::process::Stdio::null());
+        let output = command
+            .output()
+            .map_err(|e| Self::error(ActionErrorKind::command(&command, e)))?;
+        let stdout = String::from_utf8(output.stdout).map_err(Self::error)?;
+
+        match existing_filesystem(&stdout) {
+            // No filesystem (`blkid` exits with 2 then)
+            None => Ok(StatefulAction::uncompleted(this)),
+            Some((fs_type, label))
+                if fs_type == this.filesystem.to_string()
+                    && label.as_deref() == Some(NIX_DEVICE_LABEL) =>
+            {
+                tracing::debug!(
+                    "`{}` already has a {fs_type} filesystem labeled `{NIX_DEVICE_LABEL}`, not formatting or wiping it",
+                    this.device.display()
+                );
+                Ok(StatefulAction::skipped(this))
+            },
+            Some((fs_type, _)) => Err(Self::error(FormatNixDeviceError::HasFilesystem(
+                this.device,
+                fs_type,
+            ))),
+        }
+    }
+
+    fn mkfs(&self) -> String {
+        format!("mkfs.{}", self.filesystem)
+    }
+}
+
+/// The `TYPE` and `LABEL` from `blkid -o export`, if there is a filesystem
+fn existing_filesystem(blkid_export: &str) -> Option<(String, Option<String>)> {
+    let mut fs_type = None;
+    let mut label = None;
+    for line in blkid_export.lines() {
+        match line.split_once('=') {
+            Some(("TYPE", value)) => fs_type = Some(value.to_string()),
+            Some(("LABEL", value)) => label = Some(value.to_string()),
+            _ => (),
+        }
+    }
+    fs_type.map(|fs_type| (fs_type, label))
+}
+
+#[typetag::serde(name = "format_nix_device")]
+impl Action for FormatNixDevice {
+    fn action_tag() -> ActionTag {
+        ActionTag("format_nix_device")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Format `{}` as {} labeled `{NIX_DEVICE_LABEL}`",
+            self.device.display(),
+            self.filesystem
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "format_nix_device",
+            device = %self.device.display(),
+            filesystem = %self.filesystem,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![format!(
+                "The device is blank, so no data is lost; it can be found as `/dev/disk/by-label/{NIX_DEVICE_LABEL}`"
+            )],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new(self.mkfs())
+                .args(["-L", NIX_DEVICE_LABEL])
+                .arg(&self.device)
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        // Ensure `/dev/disk/by-label/nix` exists before it's mounted
+        execute_command(
+            Command::new("udevadm")
+                .arg("settle")
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Wipe the {} filesystem on `{}`",
+                self.filesystem,
+                self.device.display()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new("wipefs")
+                .arg("--all")
+                .arg(&self.device)
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum FormatNixDeviceError {
+    #[error("The device `{0}` does not exist")]
+    NoDevice(PathBuf),
+    #[error("Could not find `{0}` in PATH; formatting the device requires it")]
+    MissingMkfs(String),
+    #[error(
+        "`{0}` already has a {1} filesystem, if it holds nothing of value consider wiping it with `wipefs --all {0}`"
+    )]
+    HasFilesystem(PathBuf, String),
+}
+
+impl From<FormatNixDeviceError> for ActionErrorKind {
+    fn from(val: FormatNixDeviceError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::existing_filesystem;
+
+    #[test]
+    fn blkid_export() {
+        assert_eq!(existing_filesystem(""), None);
+        assert_eq!(
+            existing_filesystem("DEVNAME=/dev/vdb1\nLABEL=nix\nUUID=1234\nTYPE=ext4\n"),
+            Some(("ext4".into(), Some("nix".into())))
+        );
+        assert_eq!(
+            existing_filesystem("DEVNAME=/dev/vdb1\nUUID=1234\nTYPE=xfs\n"),
+            Some(("xfs".into(), None))
+        );
+    }
+}
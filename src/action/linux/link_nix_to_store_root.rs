@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::base::CreateDirectory;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+
+const NIX_DIR: &str = "/nix";
+
+/**
+Keep the Nix store on another filesystem, such as `/data/nix`, by making `/nix` a symlink to it
+
+Binary Nix tarballs are built for `/nix/store`, so the store keeps that path; `nix.conf` needs
+`allow-symlinked-store = true` for Nix to accept it.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "link_nix_to_store_root")]
+pub struct LinkNixToStoreRoot {
+    store_root: PathBuf,
+    create_store_root: StatefulAction<CreateDirectory>,
+}
+
+impl LinkNixToStoreRoot {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(store_root: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let store_root = store_root.as_ref().to_path_buf();
+        if !store_root.is_absolute() || store_root.starts_with(NIX_DIR) {
+            return Err(Self::error(LinkNixToStoreRootError::InvalidStoreRoot(
+                store_root,
+            )));
+        }
+
+        let create_store_root =
+            CreateDirectory::plan(&store_root, None, None, 0o0755, true).map_err(Self::error)?;
+        let this = Self {
+            store_root,
+            create_store_root,
+        };
+
+        let nix = Path::new(NIX_DIR);
+        if nix.is_symlink() {
+            let link_dest = std::fs::read_link(nix)
+                .map_err(|e| Self::error(ActionErrorKind::ReadSymlink(nix.to_path_buf(), e)))?;
+            if link_dest != this.store_root {
+                return Err(Self::error(ActionErrorKind::SymlinkExists(
+                    nix.to_path_buf(),
+                )));
+            }
+            tracing::debug!("`/nix` already links to `{}`", this.store_root.display());
+            return Ok(StatefulAction::completed(this));
+        } else if nix.exists() {
+            return Err(Self::error(ActionErrorKind::DirExists(nix.to_path_buf())));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[typetag::serde(name = "link_nix_to_store_root")]
+impl Action for LinkNixToStoreRoot {
+    fn action_tag() -> ActionTag {
+        ActionTag("link_nix_to_store_root")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Keep the Nix store in `{}`, linked from `{NIX_DIR}`",
+            self.store_root.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "link_nix_to_store_root",
+            store_root = tracing::field::display(self.store_root.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Create the directory `{}`", self.store_root.display()),
+                format!("Symlink `{NIX_DIR}` to `{}`", self.store_root.display()),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_store_root.try_execute().map_err(Self::error)?;
+
+        std::os::unix::fs::symlink(&self.store_root, NIX_DIR)
+            .map_err(|e| ActionErrorKind::Symlink(self.store_root.clone(), NIX_DIR.into(), e))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Remove the `{NIX_DIR}` symlink and `{}`",
+                self.store_root.display()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let nix = Path::new(NIX_DIR);
+        if nix.is_symlink() {
+            std::fs::remove_file(nix)
+                .map_err(|e| ActionErrorKind::Remove(nix.to_path_buf(), e))
+                .map_err(Self::error)?;
+        }
+
+        self.create_store_root.try_revert().map_err(Self::error)?;
+
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum LinkNixToStoreRootError {
+    #[error("The store root `{0}` must be an absolute path outside of `/nix`, such as `/data/nix`")]
+    InvalidStoreRoot(PathBuf),
+}
+
+impl From<LinkNixToStoreRootError> for ActionErrorKind {
+    fn from(val: LinkNixToStoreRootError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
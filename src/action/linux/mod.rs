@@ -1,10 +1,32 @@
+pub(crate) mod allow_unprivileged_userns;
+pub(crate) mod configure_session_environment;
+pub(crate) mod create_nix_bind_mount;
+pub(crate) mod create_nix_mount_units;
+pub(crate) mod create_steamos_sysext;
+pub(crate) mod create_sysusers_build_users;
+pub(crate) mod create_zfs_dataset;
 pub(crate) mod ensure_steamos_nix_directory;
+pub(crate) mod format_nix_device;
+pub(crate) mod link_nix_to_store_root;
+pub(crate) mod preset_systemd_unit;
+pub(crate) mod provision_proot_store;
 pub(crate) mod provision_selinux;
 pub(crate) mod revert_clean_steamos_nix_offload;
 pub(crate) mod start_systemd_unit;
 pub(crate) mod systemctl_daemon_reload;
 
+pub use allow_unprivileged_userns::AllowUnprivilegedUserns;
+pub use configure_session_environment::ConfigureSessionEnvironment;
+pub use create_nix_bind_mount::{CreateNixBindMount, CreateNixBindMountError};
+pub use create_nix_mount_units::CreateNixMountUnits;
+pub use create_steamos_sysext::CreateSteamosSysext;
+pub use create_sysusers_build_users::CreateSysusersBuildUsers;
+pub use create_zfs_dataset::CreateZfsDataset;
 pub use ensure_steamos_nix_directory::EnsureSteamosNixDirectory;
+pub use format_nix_device::{FormatNixDevice, FormatNixDeviceError};
+pub use link_nix_to_store_root::{LinkNixToStoreRoot, LinkNixToStoreRootError};
+pub use preset_systemd_unit::PresetSystemdUnit;
+pub use provision_proot_store::{ProvisionProotStore, ProvisionProotStoreError};
 pub use provision_selinux::ProvisionSelinux;
 pub use revert_clean_steamos_nix_offload::RevertCleanSteamosNixOffload;
 pub use start_systemd_unit::{StartSystemdUnit, StartSystemdUnitError};
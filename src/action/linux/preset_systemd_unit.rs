@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::util::OnMissing;
+
+pub const SYSTEMD_PRESET_DIR: &str = "/usr/lib/systemd/system-preset";
+pub const SYSTEMD_SYSTEM_DIR: &str = "/etc/systemd/system";
+
+/**
+Enable a systemd unit for the next boot without a running systemd, as when building an image
+
+A preset (in [`SYSTEMD_PRESET_DIR`]) keeps the unit enabled if the image runs `systemctl preset-all`,
+and a symlink in the `.wants` directory of the target wanting it enables it on the first boot.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "preset_systemd_unit")]
+pub struct PresetSystemdUnit {
+    unit: String,
+    wanted_by: String,
+    preset_path: PathBuf,
+    wants_path: PathBuf,
+}
+
+impl PresetSystemdUnit {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        unit: impl AsRef<str>,
+        wanted_by: impl AsRef<str>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let unit = unit.as_ref();
+        let wanted_by = wanted_by.as_ref();
+        let stem = unit.rsplit_once('.').map_or(unit, |(stem, _)| stem);
+        let this = Self {
+            unit: unit.to_string(),
+            wanted_by: wanted_by.to_string(),
+            preset_path: Path::new(SYSTEMD_PRESET_DIR).join(format!("80-{stem}.preset")),
+            wants_path: Path::new(SYSTEMD_SYSTEM_DIR)
+                .join(format!("{wanted_by}.wants"))
+                .join(unit),
+        };
+
+        let preset_done =
+            std::fs::read_to_string(&this.preset_path).is_ok_and(|preset| preset == this.preset());
+        let wants_done = std::fs::read_link(&this.wants_path).is_ok_and(|dest| dest == this.dest());
+        if preset_done && wants_done {
+            tracing::debug!("Presetting systemd unit `{unit}` already complete");
+            return Ok(StatefulAction::completed(this));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    fn preset(&self) -> String {
+        format!("enable {}\n", self.unit)
+    }
+
+    /// The unit placed in `/etc/systemd/system`, which the `.wants` symlink points to
+    fn dest(&self) -> PathBuf {
+        Path::new(SYSTEMD_SYSTEM_DIR).join(&self.unit)
+    }
+}
+
+#[typetag::serde(name = "preset_systemd_unit")]
+impl Action for PresetSystemdUnit {
+    fn action_tag() -> ActionTag {
+        ActionTag("preset_systemd_unit")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Enable the systemd unit `{}` for the next boot", self.unit)
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "preset_systemd_unit",
+            unit = %self.unit,
+            wanted_by = %self.wanted_by,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Create `{}`", self.preset_path.display()),
+                format!(
+                    "Symlink `{}` to `{}`",
+                    self.wants_path.display(),
+                    self.dest().display()
+                ),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        for dir in [&self.preset_path, &self.wants_path]
+            .into_iter()
+            .filter_map(|path| path.parent())
+        {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ActionErrorKind::CreateDirectory(dir.to_path_buf(), e))
+                .map_err(Self::error)?;
+        }
+
+        crate::util::write_atomic(&self.preset_path, self.preset()).map_err(Self::error)?;
+
+        crate::util::remove_file(&self.wants_path, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(self.wants_path.clone(), e))
+            .map_err(Self::error)?;
+        std::os::unix::fs::symlink(self.dest(), &self.wants_path)
+            .map_err(|e| ActionErrorKind::Symlink(self.dest(), self.wants_path.clone(), e))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Stop enabling the systemd unit `{}` for the next boot",
+                self.unit
+            ),
+            vec![
+                format!("Remove `{}`", self.preset_path.display()),
+                format!("Remove `{}`", self.wants_path.display()),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        for path in [&self.preset_path, &self.wants_path] {
+            if let Err(e) = crate::util::remove_file(path, OnMissing::Ignore) {
+                errors.push(Self::error(ActionErrorKind::Remove(path.clone(), e)));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+}
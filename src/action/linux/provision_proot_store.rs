@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::base::FetchAndUnpackNix;
+use crate::action::common::provision_chroot_store::populate_store;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionEstimate, ActionTag,
+    StatefulAction,
+};
+use crate::settings::{CommonSettings, NixSource};
+use crate::util::{OnMissing, which};
+
+/**
+Provision Nix into `store_root` without `root`, for use through [proot](https://proot-me.github.io)
+with `store_root` bound on `/nix`
+
+The unpacked `nix-store` registers the store paths itself, run under `proot -b {store_root}:/nix`
+so it finds its dependencies in `/nix/store`. The default profile points at the Nix package.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "provision_proot_store")]
+pub struct ProvisionProotStore {
+    store_root: PathBuf,
+    source: NixSource,
+    fetch_nix: StatefulAction<FetchAndUnpackNix>,
+}
+
+impl ProvisionProotStore {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        store_root: impl AsRef<Path>,
+        settings: &CommonSettings,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let store_root = store_root.as_ref().to_path_buf();
+        if !store_root.is_absolute() || store_root == Path::new("/") {
+            return Err(Self::error(ProvisionProotStoreError::InvalidStoreRoot(
+                store_root,
+            )));
+        }
+        let store = store_root.join("store");
+        if store.exists() {
+            return Err(Self::error(ActionErrorKind::DirExists(store)));
+        }
+        if which("proot").is_none() {
+            return Err(Self::error(ProvisionProotStoreError::NoProot));
+        }
+
+        let source = settings
+            .nix_source()
+            .map_err(|e| Self::error(ActionErrorKind::Custom(Box::new(e))))?;
+        let fetch_nix = FetchAndUnpackNix::plan(
+            Self::scratch_dir(&store_root),
+            source.clone(),
+            settings.ssl_cert_file.clone(),
+        )?;
+
+        Ok(Self {
+            store_root,
+            source,
+            fetch_nix,
+        }
+        .into())
+    }
+
+    /// Unpacked inside the store root, so the store paths can be renamed into place
+    fn scratch_dir(store_root: &Path) -> PathBuf {
+        store_root.join("temp-install-dir")
+    }
+}
+
+#[typetag::serde(name = "provision_proot_store")]
+impl Action for ProvisionProotStore {
+    fn action_tag() -> ActionTag {
+        ActionTag("provision_proot_store")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Provision Nix into a store in `{}`",
+            self.store_root.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "provision_proot_store",
+            store_root = tracing::field::display(self.store_root.display()),
+        )
+    }
+
+    fn execute_estimate(&self) -> ActionEstimate {
+        self.fetch_nix.estimate_execute()
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut buf = self.fetch_nix.describe_execute();
+        buf.push(ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Move the unpacked store paths into `{}`",
+                    self.store_root.join("store").display()
+                ),
+                format!(
+                    "Register them with `nix-store --load-db`, run with `proot -b {}:/nix`",
+                    self.store_root.display()
+                ),
+                format!(
+                    "Point `{}` at the Nix package",
+                    self.store_root.join("var/nix/profiles/default").display()
+                ),
+            ],
+        ));
+        buf
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        self.fetch_nix.try_execute().map_err(Self::error)?;
+
+        let scratch_dir = Self::scratch_dir(&self.store_root);
+        populate_store(&self.store_root, &scratch_dir, &self.source, |nix_pkg| {
+            let mut load_db_command = Command::new("proot");
+            load_db_command
+                .arg("-b")
+                .arg(format!("{}:/nix", self.store_root.display()))
+                .arg(nix_pkg.join("bin/nix-store"));
+            load_db_command
+        })
+        .map_err(Self::error)?;
+
+        crate::util::remove_dir_all(&scratch_dir, OnMissing::Ignore)
+            .map_err(|e| Self::error(ActionErrorKind::Remove(scratch_dir, e)))?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove the Nix store in `{}`", self.store_root.display()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        // Only what was provisioned, the store root may hold the receipt and `nix.conf` too
+        for dir in [
+            self.store_root.join("store"),
+            self.store_root.join("var"),
+            Self::scratch_dir(&self.store_root),
+        ] {
+            crate::util::remove_dir_all(&dir, OnMissing::Ignore)
+                .map_err(|e| Self::error(ActionErrorKind::Remove(dir, e)))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ProvisionProotStoreError {
+    #[error("The store root `{0}` must be an absolute path other than `/`")]
+    InvalidStoreRoot(PathBuf),
+    #[error("`proot` was not found, install it with `pkg install proot`")]
+    NoProot,
+}
+
+impl From<ProvisionProotStoreError> for ActionErrorKind {
+    fn from(val: ProvisionProotStoreError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
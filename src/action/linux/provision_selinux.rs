@@ -13,11 +13,14 @@ pub const SELINUX_POLICY_PP_CONTENT: &[u8] = include_bytes!("selinux/nix.pp");
 
 /**
 Provision the selinux/nix.pp for SELinux compatibility
+
+The module is only rebuilt when the installed one is missing or older, but the contexts of `/nix`
+are always restored.
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "provision_selinux")]
 pub struct ProvisionSelinux {
-    policy_path: PathBuf,
+    pub(crate) policy_path: PathBuf,
     policy_content: Vec<u8>,
 }
 
@@ -66,27 +69,48 @@ impl Action for ProvisionSelinux {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        if self.policy_path.exists() {
-            // Rebuild it.
-            remove_existing_policy(&self.policy_path).map_err(Self::error)?;
-        }
+        let embedded_version = policy_version(&self.policy_content);
+        let installed_version = std::fs::read(&self.policy_path)
+            .ok()
+            .and_then(|content| policy_version(&content));
+        let up_to_date = match (&installed_version, &embedded_version) {
+            (Some(installed), Some(embedded)) => {
+                !is_newer(embedded, installed) && module_installed().map_err(Self::error)?
+            },
+            _ => false,
+        };
 
-        if let Some(parent) = self.policy_path.parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| ActionErrorKind::CreateDirectory(parent.into(), e))
+        if up_to_date {
+            tracing::debug!(
+                installed_version,
+                "The installed SELinux policy for Nix is up to date"
+            );
+        } else {
+            if self.policy_path.exists() {
+                tracing::debug!(
+                    ?installed_version,
+                    ?embedded_version,
+                    "Rebuilding the SELinux policy for Nix"
+                );
+                remove_existing_policy(&self.policy_path).map_err(Self::error)?;
+            }
+
+            if let Some(parent) = self.policy_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| ActionErrorKind::CreateDirectory(parent.into(), e))
+                    .map_err(Self::error)?;
+            }
+
+            crate::util::write_atomic(&self.policy_path, &self.policy_content)
                 .map_err(Self::error)?;
-        }
 
-        std::fs::write(&self.policy_path, &self.policy_content)
-            .map_err(|e| ActionErrorKind::Write(self.policy_path.clone(), e))
+            execute_command(
+                Command::new("semodule")
+                    .arg("--install")
+                    .arg(&self.policy_path),
+            )
             .map_err(Self::error)?;
-
-        execute_command(
-            Command::new("semodule")
-                .arg("--install")
-                .arg(&self.policy_path),
-        )
-        .map_err(Self::error)?;
+        }
 
         execute_command(Command::new("restorecon").args(["-FR", "/nix"])).map_err(Self::error)?;
 
@@ -110,6 +134,52 @@ impl Action for ProvisionSelinux {
     }
 }
 
+/// The version of a policy package, such as `1.0` from `module nix 1.0;` in `nix.te`
+///
+/// A module package stores its name and then its version, each prefixed by their length as a
+/// little-endian `u32`.
+pub(crate) fn policy_version(policy_content: &[u8]) -> Option<String> {
+    const NAME: &[u8] = b"nix";
+    let mut needle = (NAME.len() as u32).to_le_bytes().to_vec();
+    needle.extend_from_slice(NAME);
+
+    let start = policy_content
+        .windows(needle.len())
+        .position(|window| window == needle)?
+        + needle.len();
+    let len_bytes = policy_content.get(start..start + 4)?;
+    let len = u32::from_le_bytes(len_bytes.try_into().ok()?) as usize;
+    let version = policy_content.get(start + 4..start + 4 + len)?;
+    let version = std::str::from_utf8(version).ok()?;
+    version
+        .split('.')
+        .all(|part| part.parse::<u32>().is_ok())
+        .then(|| version.to_string())
+}
+
+/// Compare dotted versions numerically, so `1.10` is newer than `1.9`
+fn is_newer(version: &str, than: &str) -> bool {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    parse(version) > parse(than)
+}
+
+/// Whether `semodule` lists a `nix` module as installed
+fn module_installed() -> Result<bool, ActionErrorKind> {
+    let output = execute_command(
+        Command::new("semodule")
+            .arg("--list-modules")
+            .stdin(std::process::Stdio::null()),
+    )?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some("nix")))
+}
+
 fn remove_existing_policy(policy_path: &Path) -> Result<(), ActionErrorKind> {
     execute_command(Command::new("semodule").arg("--remove").arg("nix"))?;
 
@@ -120,3 +190,25 @@ fn remove_existing_policy(policy_path: &Path) -> Result<(), ActionErrorKind> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SELINUX_POLICY_PP_CONTENT, is_newer, policy_version};
+
+    #[test]
+    fn embedded_policy_version() {
+        assert_eq!(
+            policy_version(SELINUX_POLICY_PP_CONTENT).as_deref(),
+            Some("1.0")
+        );
+        assert_eq!(policy_version(b"not a policy"), None);
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(is_newer("1.10", "1.9"));
+        assert!(is_newer("1.1", "1.0"));
+        assert!(!is_newer("1.0", "1.0"));
+        assert!(!is_newer("1.0", "1.0.1"));
+    }
+}
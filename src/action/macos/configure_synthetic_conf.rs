@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+use crate::util::OnMissing;
+
+pub const SYNTHETIC_CONF: &str = "/etc/synthetic.conf";
+
+/// The entry creating `/nix` as an empty directory, for the Nix Store volume to mount on
+const NIX_ENTRY: &str = "nix";
+
+/**
+Add the `nix` entry to `/etc/synthetic.conf`, keeping any other entries
+
+Each entry is a name, optionally followed by a **tab** and a symlink target. macOS silently ignores
+entries separated by spaces instead, so files with such entries are refused unless planned to
+repair them.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_synthetic_conf")]
+pub struct ConfigureSyntheticConf {
+    path: PathBuf,
+    repair_malformed: bool,
+}
+
+impl ConfigureSyntheticConf {
+    /// With `repair_malformed`, entries separated by spaces are rewritten with tabs, rather than
+    /// refused
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(repair_malformed: bool) -> Result<StatefulAction<Self>, ActionError> {
+        Self::plan_at(SYNTHETIC_CONF, repair_malformed)
+    }
+
+    fn plan_at(
+        path: impl AsRef<Path>,
+        repair_malformed: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let path = path.as_ref().to_path_buf();
+        let this = Self {
+            path,
+            repair_malformed,
+        };
+
+        let contents = match std::fs::read_to_string(&this.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Self::error(ActionErrorKind::Read(this.path, e))),
+        };
+
+        let malformed = malformed_entries(&contents);
+        if !malformed.is_empty() && !repair_malformed {
+            return Err(Self::error(SyntheticConfError::Malformed(
+                this.path, malformed,
+            )));
+        }
+        let contents = repair_entries(&contents);
+        if let Some(target) = nix_entry_target(&contents) {
+            return Err(Self::error(SyntheticConfError::NixIsSymlink(
+                this.path, target,
+            )));
+        }
+
+        if malformed.is_empty() && has_nix_entry(&contents) {
+            tracing::debug!("Configuring `{}` already complete", this.path.display());
+            return Ok(StatefulAction::completed(this));
+        }
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    fn read(&self) -> Result<String, ActionError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(Self::error(ActionErrorKind::Read(self.path.clone(), e))),
+        }
+    }
+}
+
+/// The entries which separate their name and target with spaces, or have trailing spaces
+fn malformed_entries(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter(|line| {
+            let name = line.split('\t').next().unwrap_or_default();
+            name.contains(' ')
+        })
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Rewrite malformed entries, separating the name from the target with a tab
+fn repair_entries(contents: &str) -> String {
+    let mut repaired = contents
+        .lines()
+        .map(|line| {
+            if malformed_entries(line).is_empty() {
+                return line.to_string();
+            }
+            let (name, rest) = line.split_once('\t').unwrap_or((line, ""));
+            let name = name.trim();
+            match name.split_once(char::is_whitespace) {
+                Some((name, target)) => {
+                    let target = format!("{}{rest}", target.trim_start());
+                    format!("{name}\t{target}")
+                },
+                None if rest.is_empty() => name.to_string(),
+                None => format!("{name}\t{rest}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if contents.ends_with('\n') {
+        repaired.push('\n');
+    }
+    repaired
+}
+
+fn has_nix_entry(contents: &str) -> bool {
+    contents.lines().any(|line| line == NIX_ENTRY)
+}
+
+/// Where an existing `nix` entry points, if it makes `/nix` a symlink rather than a directory
+fn nix_entry_target(contents: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (name, target) = line.split_once('\t')?;
+        (name == NIX_ENTRY).then(|| target.to_string())
+    })
+}
+
+fn with_nix_entry(contents: &str) -> String {
+    if has_nix_entry(contents) {
+        return contents.to_string();
+    }
+    let mut buf = contents.to_string();
+    if !buf.is_empty() && !buf.ends_with('\n') {
+        buf.push('\n');
+    }
+    // The newline is required otherwise it segfaults
+    buf.push_str(NIX_ENTRY);
+    buf.push('\n');
+    buf
+}
+
+fn without_nix_entry(contents: &str) -> String {
+    contents
+        .lines()
+        .filter(|line| *line != NIX_ENTRY)
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+#[typetag::serde(name = "configure_synthetic_conf")]
+impl Action for ConfigureSyntheticConf {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_synthetic_conf")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Add a `nix` entry to `{}`", self.path.display())
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_synthetic_conf",
+            path = tracing::field::display(self.path.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation =
+            vec!["It creates `/nix` at boot, for the Nix Store volume to mount on".to_string()];
+        if self.repair_malformed {
+            explanation.push("Entries separated by spaces are rewritten with tabs".to_string());
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        let mut contents = self.read()?;
+        if self.repair_malformed {
+            contents = repair_entries(&contents);
+        }
+        let contents = with_nix_entry(&contents);
+
+        crate::util::write_atomic(&self.path, contents).map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove the `nix` entry from `{}`", self.path.display()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        // The user already deleted it
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = without_nix_entry(&self.read()?);
+        if contents.trim().is_empty() {
+            crate::util::remove_file(&self.path, OnMissing::Ignore)
+                .map_err(|e| Self::error(ActionErrorKind::Remove(self.path.clone(), e)))?;
+        } else {
+            crate::util::write_atomic(&self.path, contents).map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum SyntheticConfError {
+    #[error(
+        "`{0}` has entries separated by spaces rather than a tab, which macOS ignores: {entries}; run `nix-installer repair synthetic-conf` to fix them",
+        entries = .1.iter().map(|entry| format!("`{entry}`")).collect::<Vec<_>>().join(", ")
+    )]
+    Malformed(PathBuf, Vec<String>),
+    #[error(
+        "`{0}` makes `/nix` a symlink to `{1}`, remove that entry so the Nix Store volume can be mounted on `/nix`"
+    )]
+    NixIsSymlink(PathBuf, String),
+}
+
+impl From<SyntheticConfError> for ActionErrorKind {
+    fn from(val: SyntheticConfError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_and_repairs_space_separated_entries() {
+        let contents = "# comment\nrun\tprivate/var/run\nopt  Users/me/opt\nnix \n";
+        assert_eq!(
+            malformed_entries(contents),
+            vec!["opt  Users/me/opt".to_string(), "nix ".to_string()]
+        );
+        let repaired = repair_entries(contents);
+        assert_eq!(
+            repaired,
+            "# comment\nrun\tprivate/var/run\nopt\tUsers/me/opt\nnix\n"
+        );
+        assert!(malformed_entries(&repaired).is_empty());
+    }
+
+    #[test]
+    fn adds_and_removes_only_the_nix_entry() {
+        let contents = "run\tprivate/var/run";
+        let added = with_nix_entry(contents);
+        assert_eq!(added, "run\tprivate/var/run\nnix\n");
+        assert_eq!(with_nix_entry(&added), added);
+        assert_eq!(without_nix_entry(&added), "run\tprivate/var/run\n");
+        assert_eq!(nix_entry_target("nix\tData/nix\n"), Some("Data/nix".into()));
+    }
+
+    #[test]
+    fn execute_and_revert() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("synthetic.conf");
+        std::fs::write(&path, "opt  Users/me/opt\n")?;
+
+        assert!(ConfigureSyntheticConf::plan_at(&path, false).is_err());
+
+        let mut action = ConfigureSyntheticConf::plan_at(&path, true)?;
+        action.try_execute()?;
+        assert_eq!(std::fs::read_to_string(&path)?, "opt\tUsers/me/opt\nnix\n");
+
+        action.try_revert()?;
+        assert_eq!(std::fs::read_to_string(&path)?, "opt\tUsers/me/opt\n");
+        Ok(())
+    }
+}
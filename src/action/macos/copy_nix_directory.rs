@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::execute_command;
+
+/// What the daemon must own, which a single user install left owned by its user
+const ROOT_OWNED: &[&str] = &["/nix/store", "/nix/var/nix/db"];
+
+/**
+Copy a `/nix` moved aside by [`MoveAsideNixDirectory`](super::MoveAsideNixDirectory) into the
+mounted Nix Store volume, keeping its store, database, profiles and GC roots
+
+The copy is removed along with the volume, so reverting does nothing.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "copy_nix_directory")]
+pub struct CopyNixDirectory {
+    src: PathBuf,
+}
+
+impl CopyNixDirectory {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(src: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self {
+            src: src.as_ref().to_path_buf(),
+        }
+        .into())
+    }
+}
+
+#[typetag::serde(name = "copy_nix_directory")]
+impl Action for CopyNixDirectory {
+    fn action_tag() -> ActionTag {
+        ActionTag("copy_nix_directory")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Copy `{}` into the Nix Store volume on `/nix`",
+            self.src.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "copy_nix_directory",
+            src = %self.src.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Run `ditto {} /nix`, keeping ownership, permissions and extended attributes",
+                    self.src.display()
+                ),
+                format!("Run `chown -R root {}`", ROOT_OWNED.join(" ")),
+                format!(
+                    "`{}` is kept, remove it once the migrated store works",
+                    self.src.display()
+                ),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        execute_command(
+            Command::new("/usr/bin/ditto")
+                .arg(&self.src)
+                .arg("/nix")
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        let root_owned = ROOT_OWNED
+            .iter()
+            .filter(|path| Path::new(path).exists())
+            .collect::<Vec<_>>();
+        if !root_owned.is_empty() {
+            execute_command(
+                Command::new("/usr/sbin/chown")
+                    .args(["-R", "root"])
+                    .args(root_owned)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        // The copy is removed along with the volume
+        Ok(())
+    }
+}
@@ -10,12 +10,46 @@ use crate::execute_command;
 use crate::action::{Action, ActionDescription};
 use crate::os::darwin::{DiskUtilApfsListOutput, DiskUtilInfoOutput};
 
+/// Optional role and size limits for a created APFS volume, as understood by `diskutil apfs addVolume`
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct ApfsVolumeOptions {
+    /// The APFS role(s) of the volume, such as `V`
+    pub role: Option<String>,
+    /// Space reserved for the volume within its container, such as `10g`
+    pub reserve: Option<String>,
+    /// The most space the volume may consume within its container, such as `100g`
+    pub quota: Option<String>,
+}
+
+impl ApfsVolumeOptions {
+    fn args(&self) -> Vec<String> {
+        let Self {
+            role,
+            reserve,
+            quota,
+        } = self;
+        let mut args = vec![];
+        if let Some(reserve) = reserve {
+            args.extend(["-reserve".to_string(), reserve.clone()]);
+        }
+        if let Some(quota) = quota {
+            args.extend(["-quota".to_string(), quota.clone()]);
+        }
+        if let Some(role) = role {
+            args.extend(["-role".to_string(), role.clone()]);
+        }
+        args
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_apfs_volume")]
 pub struct CreateApfsVolume {
     disk: PathBuf,
     name: String,
     case_sensitive: bool,
+    #[serde(default)]
+    options: ApfsVolumeOptions,
 }
 
 impl CreateApfsVolume {
@@ -24,6 +58,7 @@ impl CreateApfsVolume {
         disk: impl AsRef<Path>,
         name: String,
         case_sensitive: bool,
+        options: ApfsVolumeOptions,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let output =
             execute_command(Command::new("/usr/sbin/diskutil").args(["apfs", "list", "-plist"]))
@@ -38,6 +73,7 @@ impl CreateApfsVolume {
                         disk: disk.as_ref().to_path_buf(),
                         name,
                         case_sensitive,
+                        options,
                     }));
                 }
             }
@@ -47,6 +83,7 @@ impl CreateApfsVolume {
             disk: disk.as_ref().to_path_buf(),
             name,
             case_sensitive,
+            options,
         }))
     }
 }
@@ -75,7 +112,17 @@ impl Action for CreateApfsVolume {
     }
 
     fn execute_description(&self) -> Vec<ActionDescription> {
-        vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        let mut explanation = vec![];
+        if let Some(role) = &self.options.role {
+            explanation.push(format!("The volume has the APFS role `{role}`"));
+        }
+        if let Some(reserve) = &self.options.reserve {
+            explanation.push(format!("`{reserve}` of space is reserved for the volume"));
+        }
+        if let Some(quota) = &self.options.quota {
+            explanation.push(format!("The volume may use at most `{quota}` of space"));
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
@@ -84,6 +131,7 @@ impl Action for CreateApfsVolume {
             disk,
             name,
             case_sensitive,
+            options,
         } = self;
 
         execute_command(
@@ -98,8 +146,9 @@ impl Action for CreateApfsVolume {
                         "Case-sensitive APFS"
                     },
                     name,
-                    "-nomount",
                 ])
+                .args(options.args())
+                .arg("-nomount")
                 .stdin(std::process::Stdio::null()),
         )
         .map_err(Self::error)?;
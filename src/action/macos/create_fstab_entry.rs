@@ -151,7 +151,7 @@ impl Action for CreateFstabEntry {
             current_fstab_lines.push("");
         }
 
-        crate::util::write_atomic(fstab_path, &current_fstab_lines.join("\n"))
+        crate::util::write_atomic(fstab_path, current_fstab_lines.join("\n"))
             .map_err(Self::error)?;
 
         Ok(())
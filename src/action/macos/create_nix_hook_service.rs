@@ -1,6 +1,4 @@
 use serde::{Deserialize, Serialize};
-use std::fs::OpenOptions;
-use std::io::Write;
 use std::process::Command;
 use tracing::{Span, span};
 
@@ -127,17 +125,9 @@ impl Action for CreateNixHookService {
 
         let generated_plist = generate_plist(service_label).map_err(Self::error)?;
 
-        let mut options = OpenOptions::new();
-        options.create(true).write(true).read(true);
-
-        let mut file = options
-            .open(&path)
-            .map_err(|e| Self::error(ActionErrorKind::Open(path.to_owned(), e)))?;
-
         let mut buf = Vec::new();
         plist::to_writer_xml(&mut buf, &generated_plist).map_err(Self::error)?;
-        file.write_all(&buf)
-            .map_err(|e| Self::error(ActionErrorKind::Write(path.to_owned(), e)))?;
+        crate::util::write_atomic(path, buf).map_err(Self::error)?;
 
         Ok(())
     }
@@ -1,9 +1,8 @@
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
-    base::{CreateOrInsertIntoFile, create_or_insert_into_file},
     macos::{
-        BootstrapLaunchctlService, CreateApfsVolume, CreateSyntheticObjects, EnableOwnership,
-        EncryptApfsVolume, UnmountApfsVolume,
+        ApfsVolumeOptions, BootstrapLaunchctlService, ConfigureSyntheticConf, CreateApfsVolume,
+        CreateSyntheticObjects, EnableOwnership, EncryptApfsVolume, UnmountApfsVolume,
     },
 };
 use std::process::Command;
@@ -14,8 +13,8 @@ use std::{
 use tracing::{Span, span};
 
 use super::{
-    CreateVolumeService, DARWIN_LAUNCHD_DOMAIN, KickstartLaunchctlService,
-    create_fstab_entry::CreateFstabEntry,
+    CreateVolumeService, DARWIN_LAUNCHD_DOMAIN, DEFAULT_MOUNT_TIMEOUT_SECS,
+    KickstartLaunchctlService, create_fstab_entry::CreateFstabEntry,
 };
 
 pub const NIX_VOLUME_MOUNTD_DEST: &str = "/Library/LaunchDaemons/org.nixos.darwin-store.plist";
@@ -26,10 +25,12 @@ pub const NIX_VOLUME_MOUNTD_NAME: &str = "org.nixos.darwin-store";
 #[serde(tag = "action_name", rename = "create_nix_volume")]
 pub struct CreateNixVolume {
     disk: PathBuf,
-    name: String,
+    pub(crate) name: String,
     case_sensitive: bool,
-    encrypt: bool,
-    create_or_append_synthetic_conf: StatefulAction<CreateOrInsertIntoFile>,
+    pub(crate) encrypt: bool,
+    /// Receipts from before [`ConfigureSyntheticConf`] record a `create_or_insert_into_file`
+    #[serde(alias = "create_or_append_synthetic_conf")]
+    configure_synthetic_conf: StatefulAction<Box<dyn Action>>,
     create_synthetic_objects: StatefulAction<CreateSyntheticObjects>,
     pub(crate) unmount_volume: StatefulAction<UnmountApfsVolume>,
     pub(crate) create_volume: StatefulAction<CreateApfsVolume>,
@@ -39,6 +40,12 @@ pub struct CreateNixVolume {
     bootstrap_volume: StatefulAction<BootstrapLaunchctlService>,
     kickstart_launchctl_service: StatefulAction<KickstartLaunchctlService>,
     enable_ownership: StatefulAction<EnableOwnership>,
+    #[serde(default = "default_mount_timeout_secs")]
+    mount_timeout_secs: u64,
+}
+
+fn default_mount_timeout_secs() -> u64 {
+    DEFAULT_MOUNT_TIMEOUT_SECS
 }
 
 impl CreateNixVolume {
@@ -48,22 +55,20 @@ impl CreateNixVolume {
         name: String,
         case_sensitive: bool,
         encrypt: bool,
+        keychain_label: Option<String>,
+        volume_options: ApfsVolumeOptions,
+        mount_timeout_secs: u64,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let disk = disk.as_ref();
-        let create_or_append_synthetic_conf = CreateOrInsertIntoFile::plan(
-            "/etc/synthetic.conf",
-            None,
-            None,
-            None,
-            "nix\n".into(), /* The newline is required otherwise it segfaults */
-            create_or_insert_into_file::Position::End,
-        )
-        .map_err(Self::error)?;
+        let configure_synthetic_conf = ConfigureSyntheticConf::plan(false)
+            .map_err(Self::error)?
+            .boxed();
 
         let create_synthetic_objects = CreateSyntheticObjects::plan().map_err(Self::error)?;
 
         let create_volume =
-            CreateApfsVolume::plan(disk, name.clone(), case_sensitive).map_err(Self::error)?;
+            CreateApfsVolume::plan(disk, name.clone(), case_sensitive, volume_options)
+                .map_err(Self::error)?;
 
         let unmount_volume = if create_volume.state == crate::action::ActionState::Completed {
             UnmountApfsVolume::plan_skip_if_already_mounted_to_nix(disk, name.clone())
@@ -75,7 +80,12 @@ impl CreateNixVolume {
         let create_fstab_entry = CreateFstabEntry::plan(name.clone()).map_err(Self::error)?;
 
         let encrypt_volume = if encrypt {
-            Some(EncryptApfsVolume::plan(disk, &name, &create_volume)?)
+            Some(EncryptApfsVolume::plan(
+                disk,
+                &name,
+                keychain_label,
+                &create_volume,
+            )?)
         } else {
             None
         };
@@ -102,7 +112,7 @@ impl CreateNixVolume {
             name,
             case_sensitive,
             encrypt,
-            create_or_append_synthetic_conf,
+            configure_synthetic_conf,
             create_synthetic_objects,
             unmount_volume,
             create_volume,
@@ -112,6 +122,7 @@ impl CreateNixVolume {
             bootstrap_volume,
             kickstart_launchctl_service,
             enable_ownership,
+            mount_timeout_secs,
         }
         .into())
     }
@@ -142,7 +153,7 @@ impl Action for CreateNixVolume {
 
     fn execute_description(&self) -> Vec<ActionDescription> {
         let mut explanation = vec![
-            self.create_or_append_synthetic_conf.tracing_synopsis(),
+            self.configure_synthetic_conf.tracing_synopsis(),
             self.create_synthetic_objects.tracing_synopsis(),
             self.unmount_volume.tracing_synopsis(),
             self.create_volume.tracing_synopsis(),
@@ -160,7 +171,7 @@ impl Action for CreateNixVolume {
 
     #[tracing::instrument(level = "debug", skip_all)]
     fn execute(&mut self) -> Result<(), ActionError> {
-        self.create_or_append_synthetic_conf
+        self.configure_synthetic_conf
             .try_execute()
             .map_err(Self::error)?;
         self.create_synthetic_objects
@@ -207,7 +218,11 @@ impl Action for CreateNixVolume {
             .try_execute()
             .map_err(Self::error)?;
 
-        crate::action::macos::wait_for_nix_store_dir().map_err(Self::error)?;
+        crate::action::macos::wait_for_nix_store_volume(
+            &self.name,
+            Duration::from_secs(self.mount_timeout_secs),
+        )
+        .map_err(Self::error)?;
 
         self.enable_ownership.try_execute().map_err(Self::error)?;
 
@@ -216,7 +231,7 @@ impl Action for CreateNixVolume {
 
     fn revert_description(&self) -> Vec<ActionDescription> {
         let mut explanation = vec![
-            self.create_or_append_synthetic_conf.tracing_synopsis(),
+            self.configure_synthetic_conf.tracing_synopsis(),
             self.create_synthetic_objects.tracing_synopsis(),
             self.unmount_volume.tracing_synopsis(),
             self.create_volume.tracing_synopsis(),
@@ -287,7 +302,7 @@ impl Action for CreateNixVolume {
         }
 
         // Purposefully not reversed
-        if let Err(err) = self.create_or_append_synthetic_conf.try_revert() {
+        if let Err(err) = self.configure_synthetic_conf.try_revert() {
             errors.push(err);
         }
 
@@ -1,6 +1,4 @@
 use serde::{Deserialize, Serialize};
-use std::fs::OpenOptions;
-use std::io::Write;
 use std::process::Command;
 use tracing::{Span, span};
 
@@ -209,17 +207,9 @@ impl Action for CreateVolumeService {
         )
         .map_err(Self::error)?;
 
-        let mut options = OpenOptions::new();
-        options.create(true).write(true).read(true);
-
-        let mut file = options
-            .open(&path)
-            .map_err(|e| Self::error(ActionErrorKind::Open(path.to_owned(), e)))?;
-
         let mut buf = Vec::new();
         plist::to_writer_xml(&mut buf, &generated_plist).map_err(Self::error)?;
-        file.write_all(&buf)
-            .map_err(|e| Self::error(ActionErrorKind::Write(path.to_owned(), e)))?;
+        crate::util::write_atomic(path, buf).map_err(Self::error)?;
 
         Ok(())
     }
@@ -248,11 +238,27 @@ fn generate_mount_plist(
     mount_point: &Path,
     encrypt: bool,
 ) -> Result<LaunchctlMountPlist, ActionErrorKind> {
+    let mount_plist = LaunchctlMountPlist {
+        run_at_load: true,
+        label: mount_service_label.into(),
+        program_arguments: mount_command(apfs_volume_label, uuid, mount_point, encrypt),
+    };
+
+    Ok(mount_plist)
+}
+
+/// The command which mounts (unlocking with the keychain item, if encrypted) the volume
+pub(crate) fn mount_command(
+    apfs_volume_label: &str,
+    uuid: &str,
+    mount_point: &Path,
+    encrypt: bool,
+) -> Vec<String> {
     let apfs_volume_label_with_quotes = format!("\"{apfs_volume_label}\"");
     let nix_store_with_quotes = format!("\"{KEYCHAIN_NIX_STORE_SERVICE}\"");
     // The official Nix scripts uppercase the UUID, so we do as well for compatibility.
     let uuid_string = uuid.to_uppercase();
-    let mount_command = if encrypt {
+    if encrypt {
         let encrypted_command = format!(
             "/usr/bin/security find-generic-password -a {apfs_volume_label_with_quotes} -s {nix_store_with_quotes} -w | /usr/sbin/diskutil apfs unlockVolume {apfs_volume_label_with_quotes} -mountpoint {mount_point:?} -stdinpassphrase"
         );
@@ -265,15 +271,7 @@ fn generate_mount_plist(
             mount_point.display().to_string(),
             uuid_string,
         ]
-    };
-
-    let mount_plist = LaunchctlMountPlist {
-        run_at_load: true,
-        label: mount_service_label.into(),
-        program_arguments: mount_command,
-    };
-
-    Ok(mount_plist)
+    }
 }
 
 #[derive(Deserialize, Clone, Debug, Serialize, PartialEq)]
@@ -9,6 +9,7 @@ use crate::{
     },
     execute_command,
     os::darwin::DiskUtilApfsListOutput,
+    sensitive::SensitiveString,
 };
 use rand::Rng;
 use std::{
@@ -19,14 +20,24 @@ use tracing::{Span, span};
 
 use super::{CreateApfsVolume, KEYCHAIN_NIX_STORE_SERVICE};
 
+const SYSTEM_KEYCHAIN: &str = "/Library/Keychains/System.keychain";
+/// The service of the item holding a new password while the volume's is being changed, distinct so
+/// the volume's mount daemon never finds it
+const KEYCHAIN_ROTATING_SERVICE: &str = "Nix Store (rotating)";
+/// The exit status of `security` for `errSecItemNotFound`
+const SECURITY_ITEM_NOT_FOUND: i32 = 44;
+
 /**
-Encrypt an APFS volume
+Encrypt an APFS volume, with a random password kept in the System keychain
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "encrypt_apfs_volume")]
 pub struct EncryptApfsVolume {
     disk: PathBuf,
     name: String,
+    /// The label of the keychain item, receipts from before this was configurable used the default
+    #[serde(default)]
+    keychain_label: Option<String>,
 }
 
 impl EncryptApfsVolume {
@@ -34,20 +45,18 @@ impl EncryptApfsVolume {
     pub fn plan(
         disk: impl AsRef<Path>,
         name: impl AsRef<str>,
+        keychain_label: Option<String>,
         planned_create_apfs_volume: &StatefulAction<CreateApfsVolume>,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let name = name.as_ref().to_owned();
-        let disk = disk.as_ref().to_path_buf();
+        let this = Self {
+            disk: disk.as_ref().to_path_buf(),
+            name: name.as_ref().to_owned(),
+            keychain_label,
+        };
 
         let mut command = Command::new("/usr/bin/security");
-        command.args(["find-generic-password", "-a"]);
-        command.arg(&name);
-        command.arg("-s");
-        command.arg(KEYCHAIN_NIX_STORE_SERVICE);
-        command.arg("-l");
-        command.arg(format!("{} encryption password", disk.display()));
-        command.arg("-D");
-        command.arg("Encrypted volume password");
+        command.arg("find-generic-password");
+        command.args(this.keychain_item_args(KEYCHAIN_NIX_STORE_SERVICE));
         command.stdin(Stdio::null());
         command.stdout(Stdio::null());
         command.stderr(Stdio::null());
@@ -59,12 +68,14 @@ impl EncryptApfsVolume {
             // The user has a password matching what we would create.
             if planned_create_apfs_volume.state == ActionState::Completed {
                 // We detected a created volume already, and a password exists, so we can keep using that and skip doing anything
-                return Ok(StatefulAction::completed(Self { name, disk }));
+                return Ok(StatefulAction::completed(this));
             }
 
             // Ask the user to remove it
+            let keychain_label = this.keychain_label();
             return Err(Self::error(EncryptApfsVolumeError::ExistingPasswordFound(
-                name, disk,
+                this.name,
+                keychain_label,
             )));
         } else if planned_create_apfs_volume.state == ActionState::Completed {
             #[derive(serde::Deserialize)]
@@ -73,9 +84,10 @@ impl EncryptApfsVolume {
                 file_vault: bool,
             }
 
-            let output =
-                execute_command(Command::new("/usr/sbin/diskutil").args(["info", "-plist", &name]))
-                    .map_err(Self::error)?;
+            let output = execute_command(
+                Command::new("/usr/sbin/diskutil").args(["info", "-plist", &this.name]),
+            )
+            .map_err(Self::error)?;
 
             let parsed: DiskUtilDiskInfoOutput =
                 plist::from_bytes(&output.stdout).map_err(Self::error)?;
@@ -84,7 +96,7 @@ impl EncryptApfsVolume {
             // We won't be able to decrypt the volume.
             if parsed.file_vault {
                 return Err(Self::error(
-                    EncryptApfsVolumeError::MissingPasswordForExistingVolume(name, disk),
+                    EncryptApfsVolumeError::MissingPasswordForExistingVolume(this.name, this.disk),
                 ));
             }
         }
@@ -98,16 +110,219 @@ impl EncryptApfsVolume {
             plist::from_bytes(&output.stdout).map_err(Self::error)?;
         for container in parsed.containers {
             for volume in container.volumes {
-                if volume.name.as_ref() == Some(&name) && volume.file_vault.unwrap_or(false) {
-                    return Ok(StatefulAction::completed(Self { disk, name }));
+                if volume.name.as_ref() == Some(&this.name) && volume.file_vault.unwrap_or(false) {
+                    return Ok(StatefulAction::completed(this));
                 }
             }
         }
 
-        Ok(StatefulAction::uncompleted(Self { name, disk }))
+        Ok(StatefulAction::uncompleted(this))
+    }
+
+    fn keychain_label(&self) -> String {
+        self.keychain_label
+            .clone()
+            .unwrap_or_else(|| format!("{} encryption password", self.disk.display()))
+    }
+
+    /// The attributes identifying the volume's keychain item, or the one holding its new password
+    /// during a rotation
+    fn keychain_item_args(&self, service: &str) -> Vec<String> {
+        vec![
+            "-a".into(),
+            self.name.clone(),
+            "-s".into(),
+            service.into(),
+            "-l".into(),
+            self.keychain_label(),
+            "-D".into(),
+            "Encrypted volume password".into(),
+        ]
+    }
+
+    /// Store `password` in the System keychain, updating an existing item
+    fn add_keychain_password(
+        &self,
+        service: &str,
+        password: &SensitiveString,
+    ) -> Result<(), ActionError> {
+        let mut command = Command::new("/usr/bin/security");
+        command.arg("add-generic-password");
+        command.args(self.keychain_item_args(service));
+        command.args([
+            "-j",
+            &format!(
+                "Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}"
+            ),
+            "-w",
+            password.expose(),
+            "-T",
+            "/System/Library/CoreServices/APFSUserAgent",
+            "-T",
+            "/System/Library/CoreServices/CSUserAgent",
+            "-T",
+            "/usr/bin/security",
+            "-U",
+            SYSTEM_KEYCHAIN,
+        ]);
+        command.stdin(Stdio::null());
+        // Not `execute_command`, which would log the password with the command
+        let output = command.output().map_err(|e| {
+            Self::error(ActionErrorKind::command(
+                Command::new("/usr/bin/security").arg("add-generic-password"),
+                e,
+            ))
+        })?;
+        if !output.status.success() {
+            return Err(Self::error(EncryptApfsVolumeError::AddingPassword(
+                self.keychain_label(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delete every matching item from the System keychain, as there may be several
+    fn delete_keychain_passwords(&self, service: &str) -> Result<(), ActionError> {
+        loop {
+            let mut command = Command::new("/usr/bin/security");
+            command.arg("delete-generic-password");
+            command.args(self.keychain_item_args(service));
+            command.arg(SYSTEM_KEYCHAIN);
+            command.stdin(Stdio::null());
+            let output = command
+                .output()
+                .map_err(|e| Self::error(ActionErrorKind::command(&command, e)))?;
+            match output.status.code() {
+                Some(0) => continue,
+                Some(SECURITY_ITEM_NOT_FOUND) => return Ok(()),
+                _ => {
+                    return Err(Self::error(ActionErrorKind::command_output(
+                        &command, output,
+                    )));
+                },
+            }
+        }
+    }
+
+    /// Change the volume's password to a new random one, and update its keychain item
+    ///
+    /// The new password is stored in a separate item before the volume is changed, so if this is
+    /// interrupted the password which unlocks the volume is still in the keychain.
+    #[tracing::instrument(level = "debug", skip_all, fields(
+        disk = %self.disk.display(),
+    ))]
+    pub(crate) fn rotate_password(&self) -> Result<(), ActionError> {
+        let mut find = Command::new("/usr/bin/security");
+        find.arg("find-generic-password");
+        find.args(self.keychain_item_args(KEYCHAIN_NIX_STORE_SERVICE));
+        find.args(["-w", SYSTEM_KEYCHAIN]);
+        find.stdin(Stdio::null());
+        let output = find
+            .output()
+            .map_err(|e| Self::error(ActionErrorKind::command(&find, e)))?;
+        if !output.status.success() {
+            return Err(Self::error(
+                EncryptApfsVolumeError::MissingPasswordForExistingVolume(
+                    self.name.clone(),
+                    self.disk.clone(),
+                ),
+            ));
+        }
+        let old_password = SensitiveString::new(String::from_utf8_lossy(&output.stdout).trim());
+        let new_password = generate_password();
+
+        self.add_keychain_password(KEYCHAIN_ROTATING_SERVICE, &new_password)?;
+
+        let mut change = Command::new("/usr/sbin/diskutil");
+        change.args([
+            "apfs",
+            "changePassphrase",
+            self.name.as_str(),
+            "-user",
+            "disk",
+            "-oldStdinpassphrase",
+            "-newStdinpassphrase",
+        ]);
+        if let Err(err) = execute_with_stdin(
+            &mut change,
+            &SensitiveString::new(format!(
+                "{}\n{}\n",
+                old_password.expose(),
+                new_password.expose()
+            )),
+        ) {
+            // The volume still has its old password, so the new one is of no use
+            if let Err(delete_err) = self.delete_keychain_passwords(KEYCHAIN_ROTATING_SERVICE) {
+                tracing::warn!("Could not remove the unused new password: {delete_err}");
+            }
+            return Err(Self::error(err));
+        }
+
+        self.add_keychain_password(KEYCHAIN_NIX_STORE_SERVICE, &new_password)
+            .map_err(|_| {
+                Self::error(EncryptApfsVolumeError::RotationIncomplete(
+                    self.keychain_label(),
+                ))
+            })?;
+        self.delete_keychain_passwords(KEYCHAIN_ROTATING_SERVICE)?;
+
+        Ok(())
     }
 }
 
+/// A random password for the volume
+fn generate_password() -> SensitiveString {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                        abcdefghijklmnopqrstuvwxyz\
+                            0123456789)(*&^%$#@!~";
+    const PASSWORD_LEN: usize = 32;
+    let mut rng = rand::rng();
+
+    (0..PASSWORD_LEN)
+        .map(|_| {
+            let idx = rng.random_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect::<String>()
+        .into()
+}
+
+/// Run `command` with `input` on its stdin
+fn execute_with_stdin(
+    command: &mut Command,
+    input: &SensitiveString,
+) -> Result<(), ActionErrorKind> {
+    command.stdin(Stdio::piped());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    tracing::trace!(command = ?command, "Executing");
+    let mut child = command
+        .spawn()
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("child should have had a stdin handle");
+    stdin
+        .write_all(input.expose().as_bytes())
+        .map_err(|e| ActionErrorKind::Write("/dev/stdin".into(), e))?;
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    if !output.status.success() {
+        return Err(ActionErrorKind::command_output(command, output));
+    }
+    tracing::trace!(
+        command = ?command,
+        stderr = %String::from_utf8_lossy(&output.stderr),
+        stdout = %String::from_utf8_lossy(&output.stdout),
+        "Command success"
+    );
+    Ok(())
+}
+
 #[typetag::serde(name = "encrypt_apfs_volume")]
 impl Action for EncryptApfsVolume {
     fn action_tag() -> ActionTag {
@@ -137,23 +352,7 @@ impl Action for EncryptApfsVolume {
         disk = %self.disk.display(),
     ))]
     fn execute(&mut self) -> Result<(), ActionError> {
-        // Generate a random password.
-        let password: String = {
-            const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                                abcdefghijklmnopqrstuvwxyz\
-                                    0123456789)(*&^%$#@!~";
-            const PASSWORD_LEN: usize = 32;
-            let mut rng = rand::rng();
-
-            (0..PASSWORD_LEN)
-                .map(|_| {
-                    let idx = rng.random_range(0..CHARSET.len());
-                    CHARSET[idx] as char
-                })
-                .collect()
-        };
-
-        let disk_str = &self.disk.to_str().expect("Could not turn disk into string"); /* Should not reasonably ever fail */
+        let password = generate_password();
 
         let mut retry_tokens: usize = 60;
         loop {
@@ -180,85 +379,22 @@ impl Action for EncryptApfsVolume {
             std::thread::sleep(Duration::from_millis(500));
         }
 
-        // Add the password to the user keychain so they can unlock it later.
-        let mut cmd = Command::new("/usr/bin/security");
-        cmd.args([
-            "add-generic-password",
-            "-a",
-            self.name.as_str(),
-            "-s",
-            KEYCHAIN_NIX_STORE_SERVICE,
-            "-l",
-            format!("{} encryption password", disk_str).as_str(),
-            "-D",
-            "Encrypted volume password",
-            "-j",
-            format!("Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}")
-                .as_str(),
-            "-w",
-            password.as_str(),
-            "-T",
-            "/System/Library/CoreServices/APFSUserAgent",
-            "-T",
-            "/System/Library/CoreServices/CSUserAgent",
-            "-T",
-            "/usr/bin/security",
-        ]);
-
-        cmd.arg("/Library/Keychains/System.keychain");
-
-        // Add the password to the user keychain so they can unlock it later.
-        execute_command(&mut cmd).map_err(Self::error)?;
+        // Add the password to the System keychain so the volume can be unlocked at boot
+        self.add_keychain_password(KEYCHAIN_NIX_STORE_SERVICE, &password)?;
 
         // Encrypt the mounted volume
-        {
-            let mut command = Command::new("/usr/sbin/diskutil");
-            command.args([
+        execute_with_stdin(
+            Command::new("/usr/sbin/diskutil").args([
                 "apfs",
                 "encryptVolume",
                 self.name.as_str(),
                 "-user",
                 "disk",
                 "-stdinpassphrase",
-            ]);
-            command.stdin(Stdio::piped());
-            command.stdout(Stdio::piped());
-            command.stderr(Stdio::piped());
-            tracing::trace!(command = ?command, "Executing");
-            let mut child = command
-                .spawn()
-                .map_err(|e| ActionErrorKind::command(&command, e))
-                .map_err(Self::error)?;
-            let mut stdin = child
-                .stdin
-                .take()
-                .expect("child should have had a stdin handle");
-            stdin
-                .write_all(password.as_bytes())
-                .map_err(|e| ActionErrorKind::Write("/dev/stdin".into(), e))
-                .map_err(Self::error)?;
-            stdin
-                .write(b"\n")
-                .map_err(|e| ActionErrorKind::Write("/dev/stdin".into(), e))
-                .map_err(Self::error)?;
-            let output = child
-                .wait_with_output()
-                .map_err(|e| ActionErrorKind::command(&command, e))
-                .map_err(Self::error)?;
-            match output.status.success() {
-                true => {
-                    tracing::trace!(
-                        command = ?command,
-                        stderr = %String::from_utf8_lossy(&output.stderr),
-                        stdout = %String::from_utf8_lossy(&output.stdout),
-                        "Command success"
-                    );
-                },
-                false => Err(Self::error(ActionErrorKind::command_output(
-                    &command, output,
-                )))?,
-            }
-        }
+            ]),
+            &SensitiveString::new(format!("{}\n", password.expose())),
+        )
+        .map_err(Self::error)?;
 
         execute_command(
             Command::new("/usr/sbin/diskutil")
@@ -285,28 +421,9 @@ impl Action for EncryptApfsVolume {
         disk = %self.disk.display(),
     ))]
     fn revert(&mut self) -> Result<(), ActionError> {
-        let disk_str = self.disk.to_str().expect("Could not turn disk into string"); /* Should not reasonably ever fail */
-
-        // TODO: This seems very rough and unsafe
-        execute_command(
-            Command::new("/usr/bin/security").args([
-                "delete-generic-password",
-                "-a",
-                self.name.as_str(),
-                "-s",
-                KEYCHAIN_NIX_STORE_SERVICE,
-                "-l",
-                format!("{} encryption password", disk_str).as_str(),
-                "-D",
-                "Encrypted volume password",
-                "-j",
-                format!(
-                    "Added automatically by the Nix installer for use by {NIX_VOLUME_MOUNTD_DEST}"
-                )
-                .as_str(),
-            ]),
-        )
-        .map_err(Self::error)?;
+        // Including a new password left behind by an interrupted rotation
+        self.delete_keychain_passwords(KEYCHAIN_ROTATING_SERVICE)?;
+        self.delete_keychain_passwords(KEYCHAIN_NIX_STORE_SERVICE)?;
 
         Ok(())
     }
@@ -315,9 +432,9 @@ impl Action for EncryptApfsVolume {
 #[derive(thiserror::Error, Debug)]
 pub enum EncryptApfsVolumeError {
     #[error(
-        "The keychain has an existing password for a non-existing \"{0}\" volume on disk `{1}`, consider removing the password with `sudo security delete-generic-password  -a \"{0}\" -s \"Nix Store\" -l \"{1} encryption password\" -D \"Encrypted volume password\"`. Note that it's possible to have several passwords stored, so you may need to run this command several times until receiving the message `The specified item could not be found in the keychain.`"
+        "The keychain has an existing password for a non-existing \"{0}\" volume, consider removing the password with `sudo security delete-generic-password  -a \"{0}\" -s \"Nix Store\" -l \"{1}\" -D \"Encrypted volume password\"`. Note that it's possible to have several passwords stored, so you may need to run this command several times until receiving the message `The specified item could not be found in the keychain.`"
     )]
-    ExistingPasswordFound(String, PathBuf),
+    ExistingPasswordFound(String, String),
     #[error(
         "The keychain lacks a password for the already existing \"{0}\" volume on disk `{1}`, consider removing the volume with `diskutil apfs deleteVolume \"{0}\"` (if you receive error -69888, you may need to run `sudo launchctl bootout system/org.nixos.darwin-store` and `sudo launchctl bootout system/org.nixos.nix-daemon` first)"
     )]
@@ -326,6 +443,12 @@ pub enum EncryptApfsVolumeError {
         "The existing APFS volume \"{0}\" on disk `{1}` is not encrypted but it should be, consider removing the volume with `diskutil apfs deleteVolume \"{0}\"` (if you receive error -69888, you may need to run `sudo launchctl bootout system/org.nixos.darwin-store` and `sudo launchctl bootout system/org.nixos.nix-daemon` first)"
     )]
     ExistingVolumeNotEncrypted(String, PathBuf),
+    #[error("Adding the password \"{0}\" to the System keychain failed: {1}")]
+    AddingPassword(String, String),
+    #[error(
+        "The volume's password was changed, but updating the keychain item \"{0}\" failed, the new password is kept in the System keychain item for the \"Nix Store (rotating)\" service, copy it to \"{0}\" before rebooting"
+    )]
+    RotationIncomplete(String),
 }
 
 impl From<EncryptApfsVolumeError> for ActionErrorKind {
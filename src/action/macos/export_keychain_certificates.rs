@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{Span, span};
+
+use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::execute_command;
+use crate::util::OnMissing;
+
+use crate::action::{Action, ActionDescription};
+
+/// Where `--use-system-ca` exports the keychain certificates to, for `ssl-cert-file`
+pub const SYSTEM_CA_BUNDLE_DEST: &str = "/nix/var/nix/system-ca-bundle.pem";
+
+/// The keychains holding the roots macOS ships, and those added by the administrator (or an MDM profile)
+const KEYCHAINS: &[&str] = &[
+    "/System/Library/Keychains/SystemRootCertificates.keychain",
+    "/Library/Keychains/System.keychain",
+];
+
+/**
+Export the certificates in the System keychains to a PEM bundle Nix can use, so roots added for a
+corporate TLS intercepting proxy are trusted as they are by the rest of macOS
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "export_keychain_certificates")]
+pub struct ExportKeychainCertificates {
+    dest: PathBuf,
+}
+
+impl ExportKeychainCertificates {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(dest: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        Ok(Self {
+            dest: dest.as_ref().to_path_buf(),
+        }
+        .into())
+    }
+}
+
+#[typetag::serde(name = "export_keychain_certificates")]
+impl Action for ExportKeychainCertificates {
+    fn action_tag() -> ActionTag {
+        ActionTag("export_keychain_certificates")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Export the System keychain certificates to `{}`",
+            self.dest.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "export_keychain_certificates",
+            dest = %self.dest.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!("Run `security find-certificate -a -p {}`", KEYCHAINS.join(" ")),
+                "Nix reads certificates from a bundle, not the keychain, so this is refreshed only by reinstalling".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        let output = execute_command(
+            Command::new("/usr/bin/security")
+                .args(["find-certificate", "-a", "-p"])
+                .args(KEYCHAINS)
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+        if !String::from_utf8_lossy(&output.stdout).contains("-----BEGIN CERTIFICATE-----") {
+            return Err(Self::error(ActionErrorKind::Custom(
+                "The System keychains have no certificates to export".into(),
+            )));
+        }
+
+        if let Some(parent) = self.dest.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| ActionErrorKind::CreateDirectory(parent.to_path_buf(), e))
+                .map_err(Self::error)?;
+        }
+        crate::util::write_atomic(&self.dest, output.stdout).map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!("Remove `{}`", self.dest.display()),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        crate::util::remove_file(&self.dest, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(self.dest.clone(), e))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+}
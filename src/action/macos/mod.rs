@@ -3,6 +3,8 @@
 
 pub(crate) mod bootstrap_launchctl_service;
 pub(crate) mod configure_remote_building;
+pub(crate) mod configure_synthetic_conf;
+pub(crate) mod copy_nix_directory;
 pub(crate) mod create_apfs_volume;
 pub(crate) mod create_fstab_entry;
 pub(crate) mod create_nix_hook_service;
@@ -11,32 +13,43 @@ pub(crate) mod create_synthetic_objects;
 pub(crate) mod create_volume_service;
 pub(crate) mod enable_ownership;
 pub(crate) mod encrypt_apfs_volume;
+pub(crate) mod export_keychain_certificates;
 pub(crate) mod kickstart_launchctl_service;
+pub(crate) mod mount_nix_volume;
+pub(crate) mod move_aside_nix_directory;
 pub(crate) mod set_tmutil_exclusion;
 pub(crate) mod set_tmutil_exclusions;
 pub(crate) mod unmount_apfs_volume;
 
 use std::fs;
 use std::process::Command;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io::ErrorKind, path::Path};
 
 pub use bootstrap_launchctl_service::BootstrapLaunchctlService;
 pub use configure_remote_building::ConfigureRemoteBuilding;
-pub use create_apfs_volume::CreateApfsVolume;
+pub use configure_synthetic_conf::{ConfigureSyntheticConf, SyntheticConfError};
+pub use copy_nix_directory::CopyNixDirectory;
+pub use create_apfs_volume::{ApfsVolumeOptions, CreateApfsVolume};
 pub use create_nix_hook_service::CreateNixHookService;
-pub use create_nix_volume::{CreateNixVolume, NIX_VOLUME_MOUNTD_DEST};
+pub use create_nix_volume::{CreateNixVolume, NIX_VOLUME_MOUNTD_DEST, NIX_VOLUME_MOUNTD_NAME};
 pub use create_synthetic_objects::CreateSyntheticObjects;
 pub use create_volume_service::CreateVolumeService;
 pub use enable_ownership::{EnableOwnership, EnableOwnershipError};
 pub use encrypt_apfs_volume::EncryptApfsVolume;
+pub use export_keychain_certificates::{ExportKeychainCertificates, SYSTEM_CA_BUNDLE_DEST};
 pub use kickstart_launchctl_service::KickstartLaunchctlService;
+pub use mount_nix_volume::MountNixVolume;
+pub use move_aside_nix_directory::{
+    MIGRATED_NIX_DIR, MigrateNixDirectoryError, MoveAsideNixDirectory,
+};
 use serde::Deserialize;
 pub use set_tmutil_exclusion::SetTmutilExclusion;
 pub use set_tmutil_exclusions::SetTmutilExclusions;
 pub use unmount_apfs_volume::UnmountApfsVolume;
 
 use crate::execute_command;
+use crate::os::darwin::DiskUtilApfsListOutput;
 
 use super::ActionErrorKind;
 
@@ -111,30 +124,152 @@ pub(crate) fn service_is_disabled(domain: &str, service: &str) -> Result<bool, A
     Ok(is_disabled)
 }
 
-/// Waits for the Nix Store mountpoint to exist, up to `retry_tokens * 100ms` amount of time.
+/// How long to wait for the Nix Store volume to mount on `/nix` by default, before kickstarting
+/// its mount daemon and waiting as long again
+pub const DEFAULT_MOUNT_TIMEOUT_SECS: u64 = 15;
+
+/// Waits up to `timeout` for the Nix Store volume `volume_name` to be mounted on `/nix`
+///
+/// At boot the mount can race with FileVault unlocking the disk, so rather than failing, whatever
+/// blocks the mount is logged and the mount daemon is kickstarted before waiting again.
 #[tracing::instrument]
-pub(crate) fn wait_for_nix_store_dir() -> Result<(), ActionErrorKind> {
-    let mut retry_tokens: usize = 150;
+pub(crate) fn wait_for_nix_store_volume(
+    volume_name: &str,
+    timeout: Duration,
+) -> Result<(), ActionErrorKind> {
+    if wait_for_nix_store_dir(timeout)? {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "The `{volume_name}` volume was not mounted on `/nix` after {}s: {}; kickstarting `{NIX_VOLUME_MOUNTD_NAME}`",
+        timeout.as_secs(),
+        mount_blocker(volume_name)
+    );
+    if let Err(err) = execute_command(
+        Command::new("launchctl")
+            .args(["kickstart", "-k"])
+            .arg(format!("{DARWIN_LAUNCHD_DOMAIN}/{NIX_VOLUME_MOUNTD_NAME}"))
+            .stdin(std::process::Stdio::null()),
+    ) {
+        tracing::warn!("Could not kickstart `{NIX_VOLUME_MOUNTD_NAME}`: {err}");
+    }
+    if wait_for_nix_store_dir(timeout)? {
+        return Ok(());
+    }
+
+    Err(ActionErrorKind::NixVolumeMountTimeout {
+        name: volume_name.to_string(),
+        timeout_secs: timeout.as_secs() * 2,
+        blocker: mount_blocker(volume_name),
+    })
+}
+
+/// Waits up to `timeout` for the Nix Store mountpoint to exist, returning if it does
+fn wait_for_nix_store_dir(timeout: Duration) -> Result<bool, ActionErrorKind> {
+    let deadline = Instant::now() + timeout;
     loop {
         let mut command = Command::new("/usr/sbin/diskutil");
         command.args(["info", "/nix"]);
         command.stderr(std::process::Stdio::null());
         command.stdout(std::process::Stdio::null());
-        tracing::debug!(%retry_tokens, command = ?command, "Checking for Nix Store mount path existence");
-        let output = command
-            .output()
+        tracing::debug!(command = ?command, "Checking for Nix Store mount path existence");
+        let status = command
+            .status()
             .map_err(|e| ActionErrorKind::command(&command, e))?;
-        if output.status.success() {
-            break;
-        } else if retry_tokens == 0 {
-            Err(ActionErrorKind::command_output(&command, output))?;
-        } else {
-            retry_tokens = retry_tokens.saturating_sub(1);
+        if status.success() {
+            return Ok(true);
+        } else if Instant::now() >= deadline {
+            return Ok(false);
         }
         std::thread::sleep(Duration::from_millis(100));
     }
+}
 
-    Ok(())
+/// Why the Nix Store volume `volume_name` isn't mounted, as best as can be told
+fn mount_blocker(volume_name: &str) -> String {
+    let mut reasons = vec![];
+
+    match execute_command(
+        Command::new("/usr/sbin/diskutil")
+            .args(["apfs", "list", "-plist"])
+            .stdin(std::process::Stdio::null()),
+    )
+    .and_then(|output| Ok(plist::from_bytes::<DiskUtilApfsListOutput>(&output.stdout)?))
+    {
+        Ok(list) => {
+            let volume = list
+                .containers
+                .into_iter()
+                .flat_map(|container| container.volumes)
+                .find(|volume| volume.name.as_deref() == Some(volume_name));
+            match volume {
+                None => reasons.push("the volume does not exist".to_string()),
+                Some(volume) if volume.locked == Some(true) => reasons.push(
+                    "the volume is still locked, waiting for FileVault or its keychain password"
+                        .to_string(),
+                ),
+                Some(_) => (),
+            }
+        },
+        Err(err) => reasons.push(format!("could not list the APFS volumes ({err})")),
+    }
+
+    let service = format!("{DARWIN_LAUNCHD_DOMAIN}/{NIX_VOLUME_MOUNTD_NAME}");
+    match Command::new("launchctl")
+        .args(["print", &service])
+        .stdin(std::process::Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if let Some(last_exit) = stdout
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with("last exit code = "))
+                .filter(|line| *line != "last exit code = 0")
+            {
+                reasons.push(format!("`{NIX_VOLUME_MOUNTD_NAME}` failed ({last_exit})"));
+            }
+        },
+        _ => reasons.push(format!("`{NIX_VOLUME_MOUNTD_NAME}` is not loaded")),
+    }
+
+    // Disk Arbitration reports other processes dissenting from a mount here
+    let activity = diskutil_activity(Duration::from_secs(2));
+    let dissents = activity
+        .lines()
+        .filter(|line| line.contains("Dissent") || line.contains(volume_name))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    if let Some(last) = dissents.last() {
+        reasons.push(format!("`diskutil activity` reported `{last}`"));
+    }
+
+    if reasons.is_empty() {
+        "no blocking condition was found".to_string()
+    } else {
+        reasons.join(", ")
+    }
+}
+
+/// What `diskutil activity`, which runs until it is stopped, prints over `duration`
+fn diskutil_activity(duration: Duration) -> String {
+    let Ok(mut child) = Command::new("/usr/sbin/diskutil")
+        .arg("activity")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+    else {
+        return String::new();
+    };
+    std::thread::sleep(duration);
+    let _ = child.kill();
+    child
+        .wait_with_output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default()
 }
 
 /// Wait for `launchctl bootstrap {domain} {service_path}` to succeed up to `retry_tokens * 500ms` amount
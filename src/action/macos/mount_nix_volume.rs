@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::{Span, span};
+
+use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::execute_command;
+use crate::os::darwin::DiskUtilInfoOutput;
+
+use super::{
+    create_volume_service::mount_command, get_disk_info_for_label, wait_for_nix_store_volume,
+};
+use crate::action::{Action, ActionDescription};
+
+/**
+Mount an existing Nix Store APFS volume, unlocking it with the keychain item if it is encrypted
+
+Used to recover when the volume failed to mount at boot, so it is not recorded in receipts and
+reverting it does nothing.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "mount_nix_volume")]
+pub struct MountNixVolume {
+    name: String,
+    uuid: String,
+    mount_point: PathBuf,
+    encrypt: bool,
+    mount_timeout_secs: u64,
+}
+
+impl MountNixVolume {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(
+        name: impl AsRef<str>,
+        mount_point: impl AsRef<Path>,
+        mount_timeout_secs: u64,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let name = name.as_ref().to_string();
+        let mount_point = mount_point.as_ref().to_path_buf();
+
+        let disk_info = get_disk_info_for_label(&name)
+            .map_err(Self::error)?
+            .ok_or_else(|| Self::error(ActionErrorKind::NixVolumeNotFound(name.clone())))?;
+
+        let this = Self {
+            name,
+            uuid: disk_info.volume_uuid,
+            mount_point,
+            encrypt: disk_info.file_vault,
+            mount_timeout_secs,
+        };
+
+        let volume_info = DiskUtilInfoOutput::for_volume_name(&this.name).map_err(Self::error)?;
+        if volume_info.mount_point.as_deref() == Some(this.mount_point.as_path()) {
+            tracing::debug!(
+                "Volume `{}` is already mounted at `{}`",
+                this.name,
+                this.mount_point.display()
+            );
+            return Ok(StatefulAction::completed(this));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[typetag::serde(name = "mount_nix_volume")]
+impl Action for MountNixVolume {
+    fn action_tag() -> ActionTag {
+        ActionTag("mount_nix_volume")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Mount the `{}` APFS volume at `{}`",
+            self.name,
+            self.mount_point.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "mount_nix_volume",
+            name = %self.name,
+            mount_point = %self.mount_point.display(),
+            encrypt = self.encrypt,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        if self.encrypt {
+            explanation.push(
+                "The volume is unlocked with the password stored in the system keychain"
+                    .to_string(),
+            );
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        let Self {
+            name,
+            uuid,
+            mount_point,
+            encrypt,
+            mount_timeout_secs,
+        } = self;
+
+        let mount_command = mount_command(name, uuid, mount_point, *encrypt);
+        execute_command(
+            Command::new(&mount_command[0])
+                .args(&mount_command[1..])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(Self::error)?;
+
+        wait_for_nix_store_volume(name, Duration::from_secs(*mount_timeout_secs))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        Ok(())
+    }
+}
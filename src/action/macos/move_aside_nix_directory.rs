@@ -0,0 +1,192 @@
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use tracing::{Span, span};
+
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+};
+
+use super::DARWIN_LAUNCHD_DOMAIN;
+
+/// Where `--migrate-store` moves a directory based `/nix` to, while the Nix Store volume takes its place
+pub const MIGRATED_NIX_DIR: &str = "/nix.pre-volume";
+
+/// The daemon of the earlier install, running from the store being moved
+const NIX_DAEMON_SERVICE: &str = "org.nixos.nix-daemon";
+
+/**
+Move a `/nix` directory on the root filesystem, from an install before the Nix Store volume, aside
+so the volume can be mounted on `/nix`
+
+[`CopyNixDirectory`](super::CopyNixDirectory) copies it into the volume once it is mounted. The
+directory is kept, and moved back on revert if it is still there.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "move_aside_nix_directory")]
+pub struct MoveAsideNixDirectory {
+    dest: PathBuf,
+}
+
+impl MoveAsideNixDirectory {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn plan(dest: impl AsRef<Path>) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            dest: dest.as_ref().to_path_buf(),
+        };
+        let nix = Path::new("/nix");
+
+        let metadata = match nix.symlink_metadata() {
+            Ok(metadata) => metadata,
+            // An earlier attempt got this far, and left an empty `/nix` to mount on, or none at all
+            Err(_) if this.dest.is_dir() => {
+                tracing::debug!(
+                    "Moving `/nix` to `{}` already complete",
+                    this.dest.display()
+                );
+                return Ok(StatefulAction::completed(this));
+            },
+            Err(_) => return Err(Self::error(MigrateNixDirectoryError::NotFound)),
+        };
+        if !metadata.is_dir() {
+            return Err(Self::error(MigrateNixDirectoryError::NotDirectory));
+        }
+        let root = Path::new("/")
+            .metadata()
+            .map_err(|e| Self::error(ActionErrorKind::GettingMetadata("/".into(), e)))?;
+        let is_mounted = metadata.dev() != root.dev();
+        let is_empty = nix
+            .read_dir()
+            .map_err(|e| Self::error(ActionErrorKind::ReadDir(nix.to_path_buf(), e)))?
+            .next()
+            .is_none();
+        // Either the empty mount point is left, or the volume is already mounted on it
+        if (is_empty || is_mounted) && this.dest.is_dir() {
+            tracing::debug!(
+                "Moving `/nix` to `{}` already complete",
+                this.dest.display()
+            );
+            return Ok(StatefulAction::completed(this));
+        }
+        if is_mounted {
+            return Err(Self::error(MigrateNixDirectoryError::AlreadyMounted));
+        }
+        if this.dest.exists() {
+            return Err(Self::error(MigrateNixDirectoryError::DestExists(
+                this.dest.clone(),
+            )));
+        }
+        if !nix.join("store").is_dir() {
+            return Err(Self::error(MigrateNixDirectoryError::NoStore));
+        }
+
+        Ok(StatefulAction::uncompleted(this))
+    }
+}
+
+#[typetag::serde(name = "move_aside_nix_directory")]
+impl Action for MoveAsideNixDirectory {
+    fn action_tag() -> ActionTag {
+        ActionTag("move_aside_nix_directory")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Move the existing `/nix` directory to `{}`",
+            self.dest.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "move_aside_nix_directory",
+            dest = %self.dest.display(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                format!(
+                    "Run `launchctl bootout {DARWIN_LAUNCHD_DOMAIN}/{NIX_DAEMON_SERVICE}`, if the earlier install's daemon is running"
+                ),
+                "Leave an empty `/nix` to mount the Nix Store volume on".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(&mut self) -> Result<(), ActionError> {
+        super::retry_bootout(DARWIN_LAUNCHD_DOMAIN, NIX_DAEMON_SERVICE).map_err(Self::error)?;
+
+        std::fs::rename("/nix", &self.dest)
+            .map_err(|e| ActionErrorKind::Rename("/nix".into(), self.dest.clone(), e))
+            .map_err(Self::error)?;
+        std::fs::DirBuilder::new()
+            .mode(0o755)
+            .create("/nix")
+            .map_err(|e| ActionErrorKind::CreateDirectory("/nix".into(), e))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Move `{}` back to `/nix`, if it was not removed after migrating",
+                self.dest.display()
+            ),
+            vec![],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn revert(&mut self) -> Result<(), ActionError> {
+        if !self.dest.exists() {
+            tracing::debug!(
+                "`{}` was removed, not moving it back to `/nix`",
+                self.dest.display()
+            );
+            return Ok(());
+        }
+
+        // Only the empty mount point is removed, never a store
+        match std::fs::remove_dir("/nix") {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::Remove("/nix".into(), e)));
+            },
+        }
+        std::fs::rename(&self.dest, "/nix")
+            .map_err(|e| ActionErrorKind::Rename(self.dest.clone(), "/nix".into(), e))
+            .map_err(Self::error)?;
+
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateNixDirectoryError {
+    #[error("There is no `/nix` to migrate to the Nix Store volume")]
+    NotFound,
+    #[error("`/nix` is not a directory, so it can't be migrated to the Nix Store volume")]
+    NotDirectory,
+    #[error("`/nix` is already a volume of its own, so there is nothing to migrate")]
+    AlreadyMounted,
+    #[error("`/nix` has no store to migrate")]
+    NoStore,
+    #[error(
+        "`{0}` already exists, move it elsewhere (or remove it, if it is left from an earlier migration) before migrating `/nix`"
+    )]
+    DestExists(PathBuf),
+}
+
+impl From<MigrateNixDirectoryError> for ActionErrorKind {
+    fn from(val: MigrateNixDirectoryError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
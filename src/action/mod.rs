@@ -196,7 +196,7 @@ pub use stateful::{ActionState, StatefulAction};
 use std::{error::Error, os::unix::process::ExitStatusExt as _, process::Output};
 use tracing::Span;
 
-use crate::error::HasExpectedErrors;
+use crate::error::{ErrorCode, HasExpectedErrors};
 
 /// An action which can be reverted or completed, with an action state
 ///
@@ -242,6 +242,14 @@ pub trait Action: Send + Sync + std::fmt::Debug + dyn_clone::DynClone {
     ///
     /// This is called by [`InstallPlan::uninstall`](crate::InstallPlan::uninstall) through [`StatefulAction::try_revert`] which handles tracing as well as if the action needs to revert based on its `action_state`.
     fn revert(&mut self) -> Result<(), ActionError>;
+    /// A rough estimate of the disk space and time execution takes, if meaningful
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to sum [`StatefulAction::estimate_execute`] of those actions, not [`execute_estimate`][Action::execute_estimate].
+    ///
+    /// This is called by [`InstallPlan::describe_install`](crate::InstallPlan::describe_install) through [`StatefulAction::estimate_execute`] which will skip the action if it is completed.
+    fn execute_estimate(&self) -> ActionEstimate {
+        ActionEstimate::default()
+    }
 
     fn stateful(self) -> StatefulAction<Self>
     where
@@ -283,6 +291,69 @@ impl ActionDescription {
     }
 }
 
+/**
+A rough estimate of the resources an [`Action`] takes to execute, intended for humans to review
+*/
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ActionEstimate {
+    /// Disk space consumed, in bytes
+    pub disk_bytes: u64,
+    /// Time taken
+    pub duration: std::time::Duration,
+}
+
+impl ActionEstimate {
+    pub fn new(disk_bytes: u64, duration: std::time::Duration) -> Self {
+        Self {
+            disk_bytes,
+            duration,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::ops::Add for ActionEstimate {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            disk_bytes: self.disk_bytes.saturating_add(other.disk_bytes),
+            duration: self.duration.saturating_add(other.duration),
+        }
+    }
+}
+
+impl std::iter::Sum for ActionEstimate {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), std::ops::Add::add)
+    }
+}
+
+impl std::fmt::Display for ActionEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "~{} of disk, ~{}s",
+            format_bytes(self.disk_bytes),
+            self.duration.as_secs_f64().ceil() as u64
+        )
+    }
+}
+
+/// `bytes` in GiB (to a tenth), or in whole MiB if less than a GiB
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    const GIB: u64 = 1024 * MIB;
+    if bytes >= GIB {
+        format!("{:.1} GiB", bytes as f64 / GIB as f64)
+    } else {
+        format!("{} MiB", bytes.div_ceil(MIB))
+    }
+}
+
 /// A 'tag' name an action has that corresponds to the one we serialize in [`typetag]`
 pub struct ActionTag(pub &'static str);
 
@@ -355,6 +426,9 @@ pub enum ActionErrorKind {
     /// A child error
     #[error(transparent)]
     Child(Box<ActionError>),
+    /// The plan was cancelled part way through the action
+    #[error("Cancelled by user")]
+    Cancelled,
     /// Several errors
     #[error("Multiple child errors\n\n{}", .0.iter().map(|err| {
         if let Some(source) = err.source() {
@@ -455,12 +529,23 @@ pub enum ActionErrorKind {
     UserUidMismatch(String, u32, u32),
     #[error("User `{0}` existed but had a different gid ({1}) than planned ({2})")]
     UserGidMismatch(String, u32, u32),
+    #[error("{}", crate::action::common::create_users_and_groups::conflict_report(.conflicts, *.suggested_base, *.count))]
+    BuildUserConflicts {
+        conflicts: Vec<crate::action::common::BuildUserConflict>,
+        /// A UID base for which none of the build users clash, if one was found
+        suggested_base: Option<u32>,
+        count: u32,
+    },
     #[error("Getting user `{0}`")]
     NoUser(String),
     #[error("Getting gid for group `{0}`")]
     GettingGroupId(String, #[source] nix::errno::Errno),
     #[error("Group `{0}` existed but had a different gid ({1}) than planned ({2})")]
     GroupGidMismatch(String, u32, u32),
+    #[error(
+        "GID {1} planned for group `{0}` belongs to group `{2}`, pass `--id-conflict shift` to use a free GID instead"
+    )]
+    GroupGidTaken(String, u32, String),
     #[error("Getting group `{0}`")]
     NoGroup(String),
     #[error("Chowning path `{0}`")]
@@ -527,6 +612,13 @@ pub enum ActionErrorKind {
         "Could not find `{0}` in PATH; This action only works on SteamOS, which should have this present in PATH."
     )]
     MissingSteamosBinary(String),
+    #[error("Could not find `zfs` in PATH; creating a ZFS dataset requires the ZFS userland tools")]
+    MissingZfsBinary,
+    #[error(
+        "ZFS dataset `{0}` already exists with mountpoint `{1}` instead of `{planned}`, consider changing it with `zfs set mountpoint={planned} {0}`",
+        planned = .2.display()
+    )]
+    ZfsDatasetMountpointMismatch(String, String, std::path::PathBuf),
     #[error(
         "Could not find a supported command to create users in PATH; please install `useradd` or `adduser`"
     )]
@@ -558,8 +650,85 @@ pub enum ActionErrorKind {
         "
     )]
     SystemdMissing,
+    #[error(
+        "\
+        Could not detect OpenRC; you may be able to get up and running without OpenRC with `--init none`.\n\
+        See https://github.com/NixOS/nix-installer#without-systemd-linux-only for documentation on usage and drawbacks.\
+        "
+    )]
+    OpenrcMissing,
+    #[error(
+        "\
+        Could not find `update-rc.d` or `chkconfig` to register the SysVinit service; you may be able to get up and running without an init system with `--init none`.\n\
+        See https://github.com/NixOS/nix-installer#without-systemd-linux-only for documentation on usage and drawbacks.\
+        "
+    )]
+    SysVInitMissing,
+    #[error(
+        "\
+        Could not find `{0}` to supervise the Nix daemon; you may be able to get up and running without an init system with `--init none`.\n\
+        See https://github.com/NixOS/nix-installer#without-systemd-linux-only for documentation on usage and drawbacks.\
+        "
+    )]
+    SupervisorMissing(&'static str),
+    #[error(
+        "Could not find `sysrc` and `service` to register the `rc.d` service; you may be able to get up and running without an init system with `--init none`"
+    )]
+    RcdMissing,
+    #[error("The service supervisor did not pick up `{0}` in time, check that it is running")]
+    SupervisorTimeout(std::path::PathBuf),
+    #[error(
+        "Refreshing the managed registry or netrc requires `--init systemd` or `--init launchd`, not `--init {0}`"
+    )]
+    ConfigRefreshUnsupportedInit(crate::settings::InitSystem),
+    #[error(
+        "Automatic garbage collection and store optimisation require `--init systemd` or `--init launchd`, not `--init {0}`"
+    )]
+    AutoMaintenanceUnsupportedInit(crate::settings::InitSystem),
     #[error("`{command}` failed, message: {message}")]
     DiskUtilInfoError { command: String, message: String },
+    #[error(
+        "Could not find an APFS volume named `{0}`, was Nix installed with a different `--volume-label`?"
+    )]
+    NixVolumeNotFound(String),
+    #[error(
+        "The `{name}` APFS volume was not mounted on `/nix` after {timeout_secs}s: {blocker}; once it is unlocked, `nix-installer remount` mounts it, or pass a longer `--mount-timeout`"
+    )]
+    NixVolumeMountTimeout {
+        name: String,
+        timeout_secs: u64,
+        blocker: String,
+    },
+    #[error(
+        "Could not find `systemd-sysusers` in PATH; use `--user-management useradd` to create users with `useradd` or `adduser` instead"
+    )]
+    MissingSysusersCommand,
+    #[error(
+        "Build user `{name}` (UID {uid}) conflicts with `{homed_name}`, a user managed by `systemd-homed`; choose different build users with `--nix-build-user-prefix` or `--nix-build-user-id-base`"
+    )]
+    HomedUserConflict {
+        name: String,
+        uid: u32,
+        homed_name: String,
+    },
+    #[error(
+        "The secret in `{0}` was redacted when this plan was written, so it cannot be carried out; plan again with the secret instead of installing from a saved plan or receipt"
+    )]
+    RedactedSecret(String),
+    #[error(
+        "{0} doesn't exist, and can't be created locally as users come from a network directory (`{1}` in `/etc/nsswitch.conf`); create it in the directory, then run the installer again"
+    )]
+    NetworkDirectoryEntryMissing(String, String),
+    #[error(
+        "Command `{command}` was still running after the {} seconds allowed for each step (`--action-timeout`), so it was killed\nstdout: {stdout}\nstderr: {stderr}",
+        .timeout.as_secs()
+    )]
+    CommandTimedOut {
+        command: String,
+        timeout: std::time::Duration,
+        stdout: String,
+        stderr: String,
+    },
 }
 
 impl ActionErrorKind {
@@ -577,6 +746,54 @@ impl ActionErrorKind {
             output,
         }
     }
+
+    /// The [`ErrorCode`] of this error, if it is more specific than a failed action
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Child(child) => child.kind().error_code(),
+            Self::MultipleChildren(children) => {
+                children.iter().find_map(|child| child.kind().error_code())
+            },
+            Self::Multiple(kinds) => kinds.iter().find_map(|kind| kind.error_code()),
+            Self::Cancelled => Some(ErrorCode::Cancelled),
+            Self::CommandTimedOut { .. } => Some(ErrorCode::TimedOut),
+            Self::DifferentContent(_)
+            | Self::FileExists(_)
+            | Self::DirExists(_)
+            | Self::SymlinkExists(_)
+            | Self::PathUserMismatch(_, _, _)
+            | Self::PathGroupMismatch(_, _, _)
+            | Self::PathModeMismatch(_, _, _)
+            | Self::PathWasNotFile(_)
+            | Self::PathWasNotDirectory(_)
+            | Self::UserUidMismatch(_, _, _)
+            | Self::UserGidMismatch(_, _, _)
+            | Self::GroupGidMismatch(_, _, _)
+            | Self::BuildUserConflicts { .. }
+            | Self::GroupGidTaken(..)
+            | Self::HomedUserConflict { .. }
+            | Self::NetworkDirectoryEntryMissing(_, _)
+            | Self::ZfsDatasetMountpointMismatch(_, _, _) => Some(ErrorCode::Conflict),
+            Self::SystemdMissing
+            | Self::OpenrcMissing
+            | Self::SysVInitMissing
+            | Self::SupervisorMissing(_)
+            | Self::RcdMissing
+            | Self::ConfigRefreshUnsupportedInit(_)
+            | Self::AutoMaintenanceUnsupportedInit(_)
+            | Self::MissingSysusersCommand
+            | Self::MissingZfsBinary
+            | Self::MissingSteamosBinary(_)
+            | Self::MissingUserCreationCommand
+            | Self::MissingGroupCreationCommand
+            | Self::MissingAddUserToGroupCommand
+            | Self::MissingUserDeletionCommand
+            | Self::MissingGroupDeletionCommand
+            | Self::MissingRemoveUserFromGroupCommand => Some(ErrorCode::UnsupportedPlatform),
+            Self::RedactedSecret(_) => Some(ErrorCode::InvalidPlan),
+            _ => None,
+        }
+    }
 }
 
 impl HasExpectedErrors for ActionErrorKind {
@@ -586,6 +803,22 @@ impl HasExpectedErrors for ActionErrorKind {
             | Self::PathGroupMismatch(_, _, _)
             | Self::PathModeMismatch(_, _, _) => Some(Box::new(self)),
             Self::SystemdMissing => Some(Box::new(self)),
+            Self::OpenrcMissing => Some(Box::new(self)),
+            Self::SysVInitMissing => Some(Box::new(self)),
+            Self::SupervisorMissing(_) => Some(Box::new(self)),
+            Self::RcdMissing => Some(Box::new(self)),
+            Self::BuildUserConflicts { .. } | Self::GroupGidTaken(..) => Some(Box::new(self)),
+            Self::ConfigRefreshUnsupportedInit(_) | Self::AutoMaintenanceUnsupportedInit(_) => {
+                Some(Box::new(self))
+            },
+            Self::NixVolumeNotFound(_) | Self::NixVolumeMountTimeout { .. } => Some(Box::new(self)),
+            Self::MissingSysusersCommand | Self::HomedUserConflict { .. } => Some(Box::new(self)),
+            Self::RedactedSecret(_) => Some(Box::new(self)),
+            Self::NetworkDirectoryEntryMissing(_, _) => Some(Box::new(self)),
+            Self::CommandTimedOut { .. } => Some(Box::new(self)),
+            Self::MissingZfsBinary | Self::ZfsDatasetMountpointMismatch(_, _, _) => {
+                Some(Box::new(self))
+            },
             _ => None,
         }
     }
@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use tracing::Span;
 
-use super::{Action, ActionDescription, ActionError, ActionTag};
+use super::{Action, ActionDescription, ActionError, ActionEstimate, ActionTag};
+use crate::locale::translate_description;
+use crate::timeout::DeadlineGuard;
 
 /// A wrapper around an [`Action`](crate::action::Action) which tracks the [`ActionState`] and
 /// handles some tracing output
@@ -34,22 +36,39 @@ impl StatefulAction<Box<dyn Action>> {
     pub fn tracing_span(&self) -> Span {
         self.action.tracing_span()
     }
-    /// A description of what this action would do during execution
+    /// A description of what this action would do during execution, [translated](crate::locale)
     pub fn describe_execute(&self) -> Vec<ActionDescription> {
         match self.state {
             ActionState::Completed | ActionState::Skipped => {
                 vec![]
             },
-            _ => self.action.execute_description(),
+            _ => self
+                .action
+                .execute_description()
+                .into_iter()
+                .map(translate_description)
+                .collect(),
         }
     }
-    /// A description of what this action would do during revert
+    /// A rough estimate of the disk space and time execution takes, if not already completed
+    pub fn estimate_execute(&self) -> ActionEstimate {
+        match self.state {
+            ActionState::Completed | ActionState::Skipped => ActionEstimate::default(),
+            _ => self.action.execute_estimate(),
+        }
+    }
+    /// A description of what this action would do during revert, [translated](crate::locale)
     pub fn describe_revert(&self) -> Vec<ActionDescription> {
         match self.state {
             ActionState::Uncompleted | ActionState::Skipped => {
                 vec![]
             },
-            _ => self.action.revert_description(),
+            _ => self
+                .action
+                .revert_description()
+                .into_iter()
+                .map(translate_description)
+                .collect(),
         }
     }
     /// Perform any execution steps
@@ -72,6 +91,7 @@ impl StatefulAction<Box<dyn Action>> {
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Executing: {}", self.action.tracing_synopsis());
+                let _deadline = DeadlineGuard::start();
                 self.action.execute()?;
                 self.state = ActionState::Completed;
                 tracing::debug!("Completed: {}", self.action.tracing_synopsis());
@@ -99,6 +119,7 @@ impl StatefulAction<Box<dyn Action>> {
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Reverting: {}", self.action.tracing_synopsis());
+                let _deadline = DeadlineGuard::start();
                 self.action.revert()?;
                 tracing::debug!("Reverted: {}", self.action.tracing_synopsis());
                 self.state = ActionState::Uncompleted;
@@ -147,6 +168,13 @@ where
         }
         self.action.execute_description()
     }
+    /// A rough estimate of the disk space and time execution takes, if not already completed
+    pub fn estimate_execute(&self) -> ActionEstimate {
+        match self.state {
+            ActionState::Completed | ActionState::Skipped => ActionEstimate::default(),
+            _ => self.action.execute_estimate(),
+        }
+    }
     /// A description of what this action would do during revert
     pub fn describe_revert(&self) -> Vec<ActionDescription> {
         if self.state == ActionState::Uncompleted {
@@ -175,6 +203,7 @@ where
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Executing: {}", self.action.tracing_synopsis());
+                let _deadline = DeadlineGuard::start();
                 self.action.execute()?;
                 self.state = ActionState::Completed;
                 tracing::debug!("Completed: {}", self.action.tracing_synopsis());
@@ -203,6 +232,7 @@ where
             _ => {
                 self.state = ActionState::Progress;
                 tracing::debug!("Reverting: {}", self.action.tracing_synopsis());
+                let _deadline = DeadlineGuard::start();
                 self.action.revert()?;
                 tracing::debug!("Reverted: {}", self.action.tracing_synopsis());
                 self.state = ActionState::Uncompleted;
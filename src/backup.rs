@@ -0,0 +1,156 @@
+/*! Backups of the files the installer replaces
+
+When installing has to replace an existing file, like a conflicting `nix-daemon.service` or a
+`nix.conf` left by another install, the original is first copied to
+`/nix/.installer-backups/<timestamp>/` under its full path. The [`FileBackup`] is kept in the
+action, so it is recorded in the receipt, and uninstalling puts the original back.
+*/
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::action::ActionErrorKind;
+use crate::util::OnMissing;
+
+pub const BACKUP_DIR: &str = "/nix/.installer-backups";
+
+/// Backups may hold secrets, like a replaced `netrc`
+const BACKUP_DIR_MODE: u32 = 0o700;
+
+/// The backups of this run share one timestamped directory
+static RUN_BACKUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+fn run_backup_dir() -> &'static Path {
+    RUN_BACKUP_DIR.get_or_init(|| {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        // Tests must not write to `/nix`
+        let base = if cfg!(test) {
+            std::env::temp_dir().join(format!("nix-installer-backups-{}", std::process::id()))
+        } else {
+            PathBuf::from(BACKUP_DIR)
+        };
+        base.join(timestamp.to_string())
+    })
+}
+
+/// A file which was copied aside before being replaced
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileBackup {
+    pub original: PathBuf,
+    pub backup: PathBuf,
+}
+
+impl FileBackup {
+    /// Back up `path` before replacing it, if it exists
+    pub(crate) fn create(path: &Path) -> Result<Option<Self>, ActionErrorKind> {
+        Self::create_in(run_backup_dir(), path)
+    }
+
+    fn create_in(dir: &Path, path: &Path) -> Result<Option<Self>, ActionErrorKind> {
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ActionErrorKind::GettingMetadata(path.to_path_buf(), e)),
+        };
+
+        let backup = dir.join(path.strip_prefix("/").unwrap_or(path));
+        if let Some(parent) = backup.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| ActionErrorKind::CreateDirectory(parent.to_path_buf(), e))?;
+        }
+        std::fs::set_permissions(dir, PermissionsExt::from_mode(BACKUP_DIR_MODE))
+            .map_err(|e| ActionErrorKind::SetPermissions(BACKUP_DIR_MODE, dir.to_path_buf(), e))?;
+
+        copy_file_or_symlink(path, &backup, metadata.is_symlink())?;
+        tracing::debug!(
+            original = %path.display(),
+            backup = %backup.display(),
+            "Backed up file before replacing it"
+        );
+
+        Ok(Some(Self {
+            original: path.to_path_buf(),
+            backup,
+        }))
+    }
+
+    /// Put the original back, once what replaced it is removed
+    pub(crate) fn restore(&self) -> Result<(), ActionErrorKind> {
+        let metadata = match self.backup.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!(
+                    "The backup of `{}` at `{}` is gone, so it can't be restored",
+                    self.original.display(),
+                    self.backup.display()
+                );
+                return Ok(());
+            },
+            Err(e) => return Err(ActionErrorKind::GettingMetadata(self.backup.clone(), e)),
+        };
+
+        crate::util::remove_file(&self.original, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(self.original.clone(), e))?;
+        // The backup is on the Nix Store volume on macOS, so it is copied back rather than renamed
+        copy_file_or_symlink(&self.backup, &self.original, metadata.is_symlink())?;
+        crate::util::remove_file(&self.backup, OnMissing::Ignore)
+            .map_err(|e| ActionErrorKind::Remove(self.backup.clone(), e))?;
+        tracing::debug!(original = %self.original.display(), "Restored file from its backup");
+
+        Ok(())
+    }
+}
+
+/// Copy `src` to `dest`, keeping its mode, or recreate it if it is a symlink
+fn copy_file_or_symlink(src: &Path, dest: &Path, is_symlink: bool) -> Result<(), ActionErrorKind> {
+    if is_symlink {
+        let target =
+            std::fs::read_link(src).map_err(|e| ActionErrorKind::ReadSymlink(src.into(), e))?;
+        std::os::unix::fs::symlink(&target, dest)
+            .map_err(|e| ActionErrorKind::Symlink(target, dest.into(), e))?;
+    } else {
+        std::fs::copy(src, dest).map_err(|e| ActionErrorKind::Copy(src.into(), dest.into(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_up_and_restores_files_and_symlinks() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let backup_dir = temp_dir.path().join("backups");
+        let file = temp_dir.path().join("nix.conf");
+        let link = temp_dir.path().join("nix-daemon.service");
+        std::fs::write(&file, "sandbox = relaxed\n")?;
+        std::os::unix::fs::symlink("/etc/elsewhere.service", &link)?;
+
+        assert_eq!(
+            FileBackup::create_in(&backup_dir, &temp_dir.path().join("missing"))?,
+            None
+        );
+        let file_backup = FileBackup::create_in(&backup_dir, &file)?.unwrap();
+        let link_backup = FileBackup::create_in(&backup_dir, &link)?.unwrap();
+        assert!(file_backup.backup.starts_with(&backup_dir));
+
+        std::fs::write(&file, "max-jobs = auto\n")?;
+        std::fs::remove_file(&link)?;
+        file_backup.restore()?;
+        link_backup.restore()?;
+
+        assert_eq!(std::fs::read_to_string(&file)?, "sandbox = relaxed\n");
+        assert_eq!(
+            std::fs::read_link(&link)?,
+            Path::new("/etc/elsewhere.service")
+        );
+        assert!(!file_backup.backup.exists());
+        Ok(())
+    }
+}
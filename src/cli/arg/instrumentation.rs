@@ -1,11 +1,39 @@
 use eyre::WrapErr;
 use std::error::Error;
+use std::fs::File;
 use std::io::IsTerminal;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{
-    EnvFilter, filter::Directive, layer::SubscriberExt, util::SubscriberInitExt,
+    EnvFilter, Layer, filter::Directive, layer::SubscriberExt, util::SubscriberInitExt,
 };
 
+pub const DEFAULT_LOG_DIRECTORY: &str = "/var/log/nix-installer";
+const LOG_FILE_PREFIX: &str = "nix-installer-";
+const LOG_FILE_SUFFIX: &str = ".log";
+/// How many log files to keep in the log directory, including the current one
+const LOG_FILES_KEPT: usize = 10;
+
+/// Set while a full-screen interface owns the terminal, logs then only go to the log file
+static STDERR_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Stop (or resume) writing logs to stderr, see [`STDERR_PAUSED`]
+#[cfg(feature = "tui")]
+pub(crate) fn pause_stderr(paused: bool) {
+    STDERR_PAUSED.store(paused, Ordering::Relaxed);
+}
+
+fn stderr_writer() -> Box<dyn std::io::Write> {
+    match STDERR_PAUSED.load(Ordering::Relaxed) {
+        true => Box::new(std::io::sink()),
+        false => Box::new(std::io::stderr()),
+    }
+}
+
 #[derive(Clone, Default, Debug, clap::ValueEnum)]
 pub enum Logger {
     #[default]
@@ -27,6 +55,28 @@ impl std::fmt::Display for Logger {
     }
 }
 
+/// How `nix-installer` prints to the terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// Colors, and a spinner or progress bar redrawn in place for each step
+    Rich,
+    /// The same content without colors or redrawing, for screen readers and dumb terminals
+    Plain,
+    /// Like `plain`, but logs only warnings and errors unless `--verbose` is passed
+    Quiet,
+}
+
+impl std::fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            OutputMode::Rich => "rich",
+            OutputMode::Plain => "plain",
+            OutputMode::Quiet => "quiet",
+        };
+        write!(f, "{}", output)
+    }
+}
+
 #[derive(clap::Args, Debug, Default)]
 pub struct Instrumentation {
     /// Enable debug logs, -vv for trace
@@ -40,11 +90,38 @@ pub struct Instrumentation {
     /// See https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html#directives
     #[clap(long = "log-directive", global = true, env = "NIX_INSTALLER_LOG_DIRECTIVES", value_delimiter = ',', num_args = 0..)]
     pub log_directives: Vec<Directive>,
+    /// Where to write a detailed log of each run, including every executed command and its output, whatever the verbosity
+    #[clap(long, env = "NIX_INSTALLER_LOG_DIRECTORY", default_value = DEFAULT_LOG_DIRECTORY, global = true)]
+    pub log_directory: PathBuf,
+    /// Do not write a log file to `--log-directory`
+    #[clap(long, env = "NIX_INSTALLER_NO_LOG_FILE", global = true)]
+    pub no_log_file: bool,
+    /// How to print to the terminal (options are `rich`, `plain`, and `quiet`), `plain` by default if `NO_COLOR` is set or `TERM` is `dumb`
+    #[clap(long, value_enum, env = "NIX_INSTALLER_OUTPUT", global = true)]
+    pub output: Option<OutputMode>,
 }
 
 impl Instrumentation {
+    pub fn output_mode(&self) -> OutputMode {
+        if let Some(output) = self.output {
+            return output;
+        }
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let dumb_term = std::env::var_os("TERM").is_some_and(|v| v == "dumb");
+        match no_color || dumb_term {
+            true => OutputMode::Plain,
+            false => OutputMode::Rich,
+        }
+    }
+
+    /// Whether to print without colors or redrawing, see [`crate::style`]
+    pub fn is_plain(&self) -> bool {
+        self.output_mode() != OutputMode::Rich
+    }
+
     pub fn log_level(&self) -> String {
         match self.verbose {
+            0 if self.output_mode() == OutputMode::Quiet => "warn",
             0 => "info",
             1 => "debug",
             _ => "trace",
@@ -53,41 +130,118 @@ impl Instrumentation {
     }
 
     pub fn setup(&self) -> eyre::Result<()> {
+        crate::style::set_plain(self.is_plain());
+
         let filter_layer = self.filter_layer()?;
+        let log_file = self.open_log_file();
+        let file_layer = match &log_file {
+            Ok(Some((_, file))) => file.try_clone().ok().and_then(|f| self.fmt_layer_file(f)),
+            _ => None,
+        };
 
         let registry = tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(ErrorLayer::default());
+            .with(ErrorLayer::default())
+            .with(file_layer);
 
         match self.logger {
             Logger::Compact => {
                 let fmt_layer = self.fmt_layer_compact();
-                registry.with(fmt_layer).try_init()?
+                registry
+                    .with(fmt_layer.with_filter(filter_layer))
+                    .try_init()?
             },
             Logger::Full => {
                 let fmt_layer = self.fmt_layer_full();
-                registry.with(fmt_layer).try_init()?
+                registry
+                    .with(fmt_layer.with_filter(filter_layer))
+                    .try_init()?
             },
             Logger::Pretty => {
                 let fmt_layer = self.fmt_layer_pretty();
-                registry.with(fmt_layer).try_init()?
+                registry
+                    .with(fmt_layer.with_filter(filter_layer))
+                    .try_init()?
             },
             Logger::Json => {
                 let fmt_layer = self.fmt_layer_json();
-                registry.with(fmt_layer).try_init()?
+                registry
+                    .with(fmt_layer.with_filter(filter_layer))
+                    .try_init()?
+            },
+        }
+
+        match log_file {
+            Ok(Some((path, _))) => {
+                tracing::debug!("Writing a detailed log to `{}`", path.display())
             },
+            Ok(None) => (),
+            // Usually because the directory is only writable by root, as when running `plan`
+            Err(err) => tracing::debug!(
+                "Not writing a log file to `{}`: {err}",
+                self.log_directory.display()
+            ),
         }
 
         Ok(())
     }
 
+    /// Create a new log file in `--log-directory`, removing the oldest ones beyond [`LOG_FILES_KEPT`]
+    fn open_log_file(&self) -> std::io::Result<Option<(PathBuf, File)>> {
+        if self.no_log_file || self.log_directory.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        std::fs::create_dir_all(&self.log_directory)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self.log_directory.join(format!(
+            "{LOG_FILE_PREFIX}{timestamp}-{pid}{LOG_FILE_SUFFIX}",
+            pid = std::process::id()
+        ));
+        // Logs include command output, so only root may read them
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        rotate_log_files(&self.log_directory, LOG_FILES_KEPT)?;
+
+        Ok(Some((path, file)))
+    }
+
+    /// Writes everything `nix-installer` logs, up to `trace`, to `file`
+    fn fmt_layer_file<S>(&self, file: File) -> Option<impl tracing_subscriber::layer::Layer<S>>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        let filter = EnvFilter::try_new(format!(
+            "{}=trace",
+            env!("CARGO_PKG_NAME").replace('-', "_")
+        ))
+        .ok()?;
+        Some(
+            tracing_subscriber::fmt::Layer::new()
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .with_filter(filter),
+        )
+    }
+
+    /// Whether logs on stderr may be colored
+    fn ansi(&self) -> bool {
+        std::io::stderr().is_terminal() && !self.is_plain()
+    }
+
     pub fn fmt_layer_full<S>(&self) -> impl tracing_subscriber::layer::Layer<S>
     where
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
-            .with_writer(std::io::stderr)
+            .with_ansi(self.ansi())
+            .with_writer(stderr_writer)
     }
 
     pub fn fmt_layer_pretty<S>(&self) -> impl tracing_subscriber::layer::Layer<S>
@@ -95,8 +249,8 @@ impl Instrumentation {
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
-            .with_writer(std::io::stderr)
+            .with_ansi(self.ansi())
+            .with_writer(stderr_writer)
             .pretty()
     }
 
@@ -105,8 +259,8 @@ impl Instrumentation {
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
-            .with_writer(std::io::stderr)
+            .with_ansi(self.ansi())
+            .with_writer(stderr_writer)
             .json()
     }
 
@@ -115,8 +269,8 @@ impl Instrumentation {
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
-            .with_writer(std::io::stderr)
+            .with_ansi(self.ansi())
+            .with_writer(stderr_writer)
             .compact()
             .without_time()
             .with_target(false)
@@ -153,3 +307,67 @@ impl Instrumentation {
         Ok(filter_layer)
     }
 }
+
+/// The log files written to `directory`, oldest first
+pub(crate) fn log_files(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut log_files = std::fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(LOG_FILE_PREFIX) && name.ends_with(LOG_FILE_SUFFIX)
+                })
+        })
+        .collect::<Vec<_>>();
+    // The timestamps in the names have the same number of digits until 2286, so this is oldest first
+    log_files.sort();
+    Ok(log_files)
+}
+
+/// Remove the oldest log files in `directory`, keeping the `kept` most recent
+fn rotate_log_files(directory: &Path, kept: usize) -> std::io::Result<()> {
+    let log_files = log_files(directory)?;
+
+    let excess = log_files.len().saturating_sub(kept);
+    for path in log_files.into_iter().take(excess) {
+        std::fs::remove_file(&path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_keeps_the_most_recent_log_files() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        for name in [
+            "nix-installer-1700000000-1.log",
+            "nix-installer-1700000001-1.log",
+            "nix-installer-1700000002-1.log",
+            "unrelated.log",
+        ] {
+            std::fs::write(temp_dir.path().join(name), "")?;
+        }
+
+        rotate_log_files(temp_dir.path(), 2)?;
+
+        let mut remaining = std::fs::read_dir(temp_dir.path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "nix-installer-1700000001-1.log",
+                "nix-installer-1700000002-1.log",
+                "unrelated.log",
+            ]
+        );
+        Ok(())
+    }
+}
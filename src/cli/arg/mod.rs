@@ -1,2 +1,4 @@
 mod instrumentation;
-pub(crate) use instrumentation::Instrumentation;
+#[cfg(feature = "tui")]
+pub(crate) use instrumentation::pause_stderr;
+pub(crate) use instrumentation::{Instrumentation, log_files};
@@ -0,0 +1,104 @@
+/*! Reporting errors with their [`ErrorCode`], as text or JSON (`--error-format`) */
+
+use std::{error::Error, process::ExitCode, sync::OnceLock};
+
+use crate::style::Colorize;
+use crate::{ErrorCode, NixInstallerError};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl std::fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorFormat::Text => write!(f, "text"),
+            ErrorFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+pub(crate) fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+fn error_format() -> ErrorFormat {
+    ERROR_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Escalating to `root` with `sudo` failed
+#[derive(Debug, thiserror::Error)]
+#[error("`nix-installer` needs to run as `root`, and running it with `sudo` failed")]
+pub(crate) struct NeedsRoot(#[source] pub(crate) nix::Error);
+
+/// The [`ErrorCode`] of the first error in `error`'s chain which has one
+pub(crate) fn error_code(error: &eyre::Report) -> ErrorCode {
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(err) = cause.downcast_ref::<NixInstallerError>() {
+                Some(err.error_code())
+            } else if let Some(err) = cause.downcast_ref::<crate::planner::PlannerError>() {
+                Some(err.error_code())
+            } else if let Some(err) = cause.downcast_ref::<crate::action::ActionError>() {
+                Some(err.kind().error_code().unwrap_or(ErrorCode::ActionFailed))
+            } else {
+                cause.is::<NeedsRoot>().then_some(ErrorCode::NeedsRoot)
+            }
+        })
+        .unwrap_or(ErrorCode::Failure)
+}
+
+/// Print an error the user can act on, and return the exit code for `code`
+///
+/// As text, only the error's message is printed, without its causes or a backtrace.
+pub(crate) fn report_failure(error: &dyn Error, code: ErrorCode) -> ExitCode {
+    match error_format() {
+        ErrorFormat::Text => eprintln!("{}", error.red()),
+        ErrorFormat::Json => print_json(error, code),
+    }
+    ExitCode::from(code.exit_code())
+}
+
+/// Like [`report_failure`], for a failure which is a message rather than an error
+pub(crate) fn report_failure_message(message: impl Into<String>, code: ErrorCode) -> ExitCode {
+    #[derive(Debug, thiserror::Error)]
+    #[error("{0}")]
+    struct Message(String);
+
+    report_failure(&Message(message.into()), code)
+}
+
+/// Report an error which reached the top of the CLI, returning `None` if it should be left to `color-eyre`
+pub(crate) fn report_unhandled(error: &eyre::Report) -> Option<ExitCode> {
+    let code = error_code(error);
+    match error_format() {
+        ErrorFormat::Json => print_json(error.as_ref(), code),
+        ErrorFormat::Text if code == ErrorCode::Failure => return None,
+        ErrorFormat::Text => eprintln!("{error:?}"),
+    }
+    Some(ExitCode::from(code.exit_code()))
+}
+
+fn print_json(error: &dyn Error, code: ErrorCode) {
+    let mut causes = vec![];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    let report = serde_json::json!({
+        "error": {
+            "code": code,
+            "exit_code": code.exit_code(),
+            "message": error.to_string(),
+            "causes": causes,
+        }
+    });
+    eprintln!("{report}");
+}
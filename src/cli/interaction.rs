@@ -1,7 +1,11 @@
 use std::io::{BufRead, Write, stdin, stdout};
+use std::path::Path;
 
 use eyre::{WrapErr, eyre};
-use owo_colors::OwoColorize;
+
+use crate::locale::translate;
+use crate::prompt::{InteractionHandler, PromptAnswer, PromptAnswers, PromptRequest};
+use crate::style::Colorize;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PromptChoice {
@@ -23,7 +27,7 @@ pub(crate) fn prompt(
         {are_you_sure} ({yes}/{no}{maybe_explain}): \
     ",
         question = question.as_ref(),
-        are_you_sure = "Proceed?".bold(),
+        are_you_sure = translate("Proceed?").bold(),
         no = if default == PromptChoice::No {
             "[N]o"
         } else {
@@ -66,6 +70,57 @@ pub(crate) fn prompt(
     Ok(r)
 }
 
+/// Answers prompts raised during execution from pre-seeded `answers`, otherwise by asking on the
+/// terminal if `interactive`
+pub(crate) struct TerminalInteraction {
+    pub(crate) answers: PromptAnswers,
+    pub(crate) interactive: bool,
+}
+
+impl InteractionHandler for TerminalInteraction {
+    fn interact(&self, request: &PromptRequest) -> Option<PromptAnswer> {
+        if let Some(answer) = self.answers.answer(request) {
+            tracing::info!("{request}\nUsing the pre-seeded answer: {answer:?}");
+            return Some(answer);
+        }
+        if !self.interactive {
+            return None;
+        }
+        match self::prompt(
+            translate(&request.to_string()).yellow().to_string(),
+            PromptChoice::No,
+            true,
+        ) {
+            Ok(PromptChoice::Yes) => Some(PromptAnswer::Yes),
+            Ok(PromptChoice::No | PromptChoice::Explain) => Some(PromptAnswer::No),
+            Err(err) => {
+                tracing::warn!("Failed to prompt, using predeclared answer: {err:?}");
+                None
+            },
+        }
+    }
+}
+
+/// Read pre-seeded answers from the JSON object in `answers_file` (if any), then `answers`
+pub(crate) fn collect_answers(
+    answers_file: Option<&Path>,
+    answers: Vec<(String, PromptAnswer)>,
+) -> eyre::Result<PromptAnswers> {
+    let mut collected = match answers_file {
+        Some(path) => {
+            let buf = std::fs::read_to_string(path)
+                .with_context(|| format!("Reading answers file `{}`", path.display()))?;
+            serde_json::from_str(&buf)
+                .with_context(|| format!("Parsing answers file `{}`", path.display()))?
+        },
+        None => PromptAnswers::default(),
+    };
+    for (key, answer) in answers {
+        collected.insert(key, answer);
+    }
+    Ok(collected)
+}
+
 pub(crate) fn read_line() -> eyre::Result<String> {
     let stdin = stdin();
     let stdin = stdin.lock();
@@ -79,6 +134,6 @@ pub(crate) fn read_line() -> eyre::Result<String> {
 }
 
 pub(crate) fn clean_exit_with_message(message: impl AsRef<str>) -> ! {
-    eprintln!("{}", message.as_ref());
+    eprintln!("{}", translate(message.as_ref()));
     std::process::exit(0)
 }
@@ -3,12 +3,13 @@
 */
 
 pub(crate) mod arg;
+pub(crate) mod error;
 mod interaction;
+mod progress;
 pub(crate) mod subcommand;
 
 use clap::Parser;
 use eyre::WrapErr;
-use owo_colors::OwoColorize;
 use std::{
     ffi::CString,
     path::PathBuf,
@@ -17,10 +18,14 @@ use std::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::SystemTime,
 };
 use url::Url;
 
+use self::error::{ErrorFormat, NeedsRoot};
 use self::subcommand::NixInstallerSubcommand;
+use crate::InstallPlan;
+use crate::style::Colorize;
 
 pub use crate::plan::{CancelSignal, cancel_signal};
 
@@ -28,10 +33,12 @@ pub trait CommandExecute {
     fn execute(self) -> eyre::Result<ExitCode>;
 }
 
+// `NixInstallerCli::command()` (from `clap::CommandFactory`) describes the whole CLI without
+// executing anything, the `completions` and `manpages` subcommands are generated from it
 /**
 Experimental Nix Installer
 
-A WIP replacement for the shell-based Nix installer (TODO: better description)
+Install Nix, and uninstall it again, from a plan of reversible steps recorded in a receipt
 */
 #[derive(Debug, Parser)]
 #[clap(version)]
@@ -50,6 +57,16 @@ pub struct NixInstallerCli {
     )]
     pub ssl_cert_file: Option<PathBuf>,
 
+    /// How to print errors: as `text`, or as `json` with a stable `code` and `exit_code` for programs wrapping `nix-installer`
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = ErrorFormat::Text,
+        env = "NIX_INSTALLER_ERROR_FORMAT",
+        global = true
+    )]
+    pub error_format: ErrorFormat,
+
     #[clap(flatten)]
     pub instrumentation: arg::Instrumentation,
 
@@ -61,6 +78,7 @@ impl CommandExecute for NixInstallerCli {
     #[tracing::instrument(level = "trace", skip_all)]
     fn execute(self) -> eyre::Result<ExitCode> {
         let is_install_subcommand = matches!(self.subcommand, NixInstallerSubcommand::Install(_));
+        error::set_error_format(self.error_format);
 
         let ret = match self.subcommand {
             NixInstallerSubcommand::Plan(plan) => plan.execute(),
@@ -69,6 +87,17 @@ impl CommandExecute for NixInstallerCli {
             NixInstallerSubcommand::Repair(repair) => repair.execute(),
             NixInstallerSubcommand::Uninstall(revert) => revert.execute(),
             NixInstallerSubcommand::SplitReceipt(split_receipt) => split_receipt.execute(),
+            NixInstallerSubcommand::Adopt(adopt) => adopt.execute(),
+            NixInstallerSubcommand::Remount(remount) => remount.execute(),
+            NixInstallerSubcommand::Info(info) => info.execute(),
+            NixInstallerSubcommand::Status(status) => status.execute(),
+            NixInstallerSubcommand::BugReport(mut bug_report) => {
+                bug_report.log_directory = self.instrumentation.log_directory.clone();
+                bug_report.execute()
+            },
+            NixInstallerSubcommand::Reconfigure(reconfigure) => reconfigure.execute(),
+            NixInstallerSubcommand::Completions(completions) => completions.execute(),
+            NixInstallerSubcommand::Manpages(manpages) => manpages.execute(),
         };
 
         let maybe_cancelled = ret.as_ref().err().and_then(|err| {
@@ -83,8 +112,10 @@ impl CommandExecute for NixInstallerCli {
         });
 
         if let Some(cancelled) = maybe_cancelled {
-            eprintln!("{}", cancelled.red());
-            return Ok(ExitCode::FAILURE);
+            return Ok(error::report_failure(
+                cancelled,
+                crate::ErrorCode::Cancelled,
+            ));
         }
 
         let is_macos = matches!(
@@ -92,21 +123,17 @@ impl CommandExecute for NixInstallerCli {
             target_lexicon::OperatingSystem::MacOSX(_) | target_lexicon::OperatingSystem::Darwin(_)
         );
 
-        if is_install_subcommand && is_macos {
-            let is_ok_but_failed = ret.as_ref().is_ok_and(|code| code == &ExitCode::FAILURE);
-            let is_error = ret.as_ref().is_err();
-
-            if is_error || is_ok_but_failed {
-                // NOTE: If the error bubbled up, print it before we log the pkg suggestion
-                if let Err(ref err) = ret {
+        match ret {
+            Ok(code) => Ok(code),
+            Err(err) => match error::report_unhandled(&err) {
+                Some(code) => Ok(code),
+                None if is_install_subcommand && is_macos => {
                     eprintln!("{err:?}\n");
-                }
-
-                return Ok(ExitCode::FAILURE);
-            }
+                    Ok(ExitCode::FAILURE)
+                },
+                None => Err(err),
+            },
         }
-
-        ret
     }
 }
 
@@ -153,11 +180,11 @@ pub fn ensure_root() -> eyre::Result<()> {
                 // Rust logging/backtrace bits we use
                 "RUST_LOG" | "RUST_BACKTRACE" => true,
                 // CI
-                "GITHUB_PATH" => true,
+                "GITHUB_PATH" | "GITHUB_OUTPUT" | "GITHUB_ACTIONS" => true,
                 // Used for detecting what command to suggest for sourcing Nix
                 "SHELL" => true,
-                // Proxy settings (automatically picked up by Reqwest)
-                "HTTP_PROXY" | "http_proxy" | "HTTPS_PROXY" | "https_proxy" => true,
+                // Proxy settings, read by `curl` and Nix
+                key if crate::proxy::PROXY_ENV_VARS.contains(&key) => true,
                 // Our own environments
                 key if key.starts_with("NIX_INSTALLER") => true,
                 // Kept for backward compatibility with existing installations
@@ -185,8 +212,55 @@ pub fn ensure_root() -> eyre::Result<()> {
         }
 
         tracing::trace!("Execvp'ing `{sudo_cstring:?}` with args `{arg_vec_cstring:?}`");
-        nix::unistd::execvp(&sudo_cstring, &arg_vec_cstring)
-            .wrap_err("Executing `nix-installer` as `root` via `sudo`")?;
+        nix::unistd::execvp(&sudo_cstring, &arg_vec_cstring).map_err(NeedsRoot)?;
     }
     Ok(())
 }
+
+/// If `plan` may be reverted: installs with `--protect` need the hostname of this machine, from
+/// `confirm_hostname` or (unless `no_confirm`) typed in
+pub(crate) fn confirm_protected(
+    plan: &InstallPlan,
+    confirm_hostname: Option<String>,
+    no_confirm: bool,
+) -> eyre::Result<bool> {
+    if !plan.planner.protected() {
+        return Ok(true);
+    }
+    let hostname = nix::unistd::gethostname()
+        .wrap_err("Getting hostname")?
+        .to_string_lossy()
+        .into_owned();
+    let confirmed_hostname = match confirm_hostname {
+        Some(confirm_hostname) => Some(confirm_hostname),
+        None if !no_confirm => {
+            eprintln!(
+                "{}",
+                "This Nix install is protected, type the hostname of this machine to continue:"
+                    .yellow()
+            );
+            Some(interaction::read_line()?)
+        },
+        None => None,
+    };
+
+    Ok(confirmed_hostname.as_deref().map(str::trim) == Some(hostname.as_str()))
+}
+
+/// Copy the receipt of `plan` aside, as `receipt.pre-{operation}.{timestamp}.json`, before rewriting it
+pub(crate) fn back_up_receipt(plan: &InstallPlan, operation: &str) -> eyre::Result<PathBuf> {
+    let timestamp_millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis();
+
+    let receipt_location = plan.planner.receipt_path();
+    let mut old_receipt = receipt_location.clone();
+    old_receipt.set_extension(format!("pre-{operation}.{timestamp_millis}.json"));
+    std::fs::copy(&receipt_location, &old_receipt)
+        .wrap_err_with(|| format!("Backing up `{}`", receipt_location.display()))?;
+    tracing::info!(
+        "Backed up pre-{operation} receipt to {}",
+        old_receipt.display()
+    );
+    Ok(old_receipt)
+}
@@ -0,0 +1,212 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::progress::ProgressReporter;
+use crate::style::Colorize;
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const TICK: Duration = Duration::from_millis(100);
+const BAR_WIDTH: usize = 20;
+// Redrawing relies on the line not wrapping
+const MAX_SYNOPSIS_CHARS: usize = 60;
+
+/// Draws a spinner (or a progress bar, for steps reporting bytes) for the running step on stderr,
+/// and records how long each step took
+#[derive(Debug)]
+pub(crate) struct TerminalProgress {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    current: Option<CurrentStep>,
+    suspended: bool,
+    tick: usize,
+    finished: Vec<FinishedStep>,
+}
+
+#[derive(Debug)]
+struct CurrentStep {
+    step: usize,
+    total: usize,
+    synopsis: String,
+    started: Instant,
+    bytes: Option<(u64, u64)>,
+}
+
+#[derive(Debug)]
+struct FinishedStep {
+    synopsis: String,
+    elapsed: Duration,
+}
+
+impl TerminalProgress {
+    pub(crate) fn new() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let weak = Arc::downgrade(&state);
+        std::thread::spawn(move || tick(weak));
+        Self { state }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A table of how long each step took
+    pub(crate) fn summary(&self) -> Option<String> {
+        let state = self.lock();
+        if state.finished.is_empty() {
+            return None;
+        }
+        let total: Duration = state.finished.iter().map(|f| f.elapsed).sum();
+        let mut buf = String::from("Step timings:\n");
+        for finished in &state.finished {
+            buf.push_str(&format!(
+                "{:>8} {}\n",
+                format_duration(finished.elapsed),
+                truncate(&finished.synopsis)
+            ));
+        }
+        buf.push_str(&format!("{:>8} {}", format_duration(total), "total".bold()));
+        Some(buf)
+    }
+}
+
+impl ProgressReporter for TerminalProgress {
+    fn step_started(&self, step: usize, total: usize, synopsis: &str) {
+        let mut state = self.lock();
+        state.current = Some(CurrentStep {
+            step,
+            total,
+            synopsis: synopsis.to_string(),
+            started: Instant::now(),
+            bytes: None,
+        });
+        draw(&state);
+    }
+
+    fn step_bytes(&self, done: u64, total: u64) {
+        if let Some(current) = &mut self.lock().current {
+            current.bytes = Some((done, total));
+        }
+    }
+
+    fn step_finished(
+        &self,
+        step: usize,
+        total: usize,
+        synopsis: &str,
+        elapsed: Duration,
+        success: bool,
+    ) {
+        let mut state = self.lock();
+        state.current = None;
+        state.finished.push(FinishedStep {
+            synopsis: synopsis.to_string(),
+            elapsed,
+        });
+        let line = format!(
+            "[{step}/{total}] {} ({})",
+            truncate(synopsis),
+            format_duration(elapsed)
+        );
+        let mut stderr = std::io::stderr().lock();
+        let _ = match success {
+            true => writeln!(stderr, "\r\x1b[2K{} {line}", "✓".green()),
+            false => writeln!(stderr, "\r\x1b[2K{} {line}", "✗".red()),
+        };
+    }
+
+    fn suspend(&self) {
+        let mut state = self.lock();
+        state.suspended = true;
+        let _ = write!(std::io::stderr(), "\r\x1b[2K");
+    }
+
+    fn resume(&self) {
+        let mut state = self.lock();
+        state.suspended = false;
+        draw(&state);
+    }
+}
+
+impl Drop for TerminalProgress {
+    fn drop(&mut self) {
+        if self.lock().current.is_some() {
+            let _ = write!(std::io::stderr(), "\r\x1b[2K");
+        }
+    }
+}
+
+fn tick(state: Weak<Mutex<State>>) {
+    loop {
+        std::thread::sleep(TICK);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.tick = state.tick.wrapping_add(1);
+        draw(&state);
+    }
+}
+
+fn draw(state: &State) {
+    let Some(current) = &state.current else {
+        return;
+    };
+    if state.suspended {
+        return;
+    }
+
+    let spinner = SPINNER[state.tick % SPINNER.len()];
+    let progress = match current.bytes {
+        Some((done, total)) if total > 0 => {
+            let ratio = (done as f64 / total as f64).clamp(0.0, 1.0);
+            let filled = (ratio * BAR_WIDTH as f64) as usize;
+            format!(
+                " [{}{}] {:>3}%",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                (ratio * 100.0) as u8
+            )
+        },
+        _ => String::new(),
+    };
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(
+        stderr,
+        "\r\x1b[2K{} [{}/{}] {}{progress} ({})",
+        spinner.cyan(),
+        current.step,
+        current.total,
+        truncate(&current.synopsis),
+        format_duration(current.started.elapsed()),
+    );
+    let _ = stderr.flush();
+}
+
+fn truncate(synopsis: &str) -> String {
+    let first_line = synopsis.lines().next().unwrap_or_default();
+    if first_line.chars().count() <= MAX_SYNOPSIS_CHARS {
+        first_line.to_string()
+    } else {
+        let truncated: String = first_line.chars().take(MAX_SYNOPSIS_CHARS - 1).collect();
+        format!("{truncated}…")
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{secs:.1}s")
+    } else {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    }
+}
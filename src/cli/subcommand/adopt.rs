@@ -0,0 +1,211 @@
+use std::{path::Path, process::ExitCode};
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::{WrapErr, eyre};
+use nix::unistd::{Group, User};
+
+use crate::style::Colorize;
+use crate::{
+    BuiltinPlanner, InstallPlan,
+    cli::{
+        CommandExecute, ensure_root,
+        error::report_failure,
+        interaction::{self, PromptChoice},
+        subcommand::install::copy_self_to_nix_dir,
+    },
+    error::HasExpectedErrors,
+    plan::{current_version, find_receipt_at, required_extensions},
+    settings::CommonSettings,
+};
+
+/**
+Adopt a Nix install created by the upstream shell installer
+
+Probes the system for the artifacts of the shell installer (such as the build users and group) and
+records a receipt as though `nix-installer` had created them, so `nix-installer uninstall` can
+remove the install, or `nix-installer` can otherwise manage it.
+*/
+#[derive(Debug, Parser)]
+pub struct Adopt {
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub no_confirm: bool,
+
+    #[clap(subcommand)]
+    pub planner: Option<BuiltinPlanner>,
+}
+
+impl CommandExecute for Adopt {
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            planner,
+        } = self;
+
+        ensure_root()?;
+
+        let mut planner = match planner {
+            Some(planner) => planner,
+            None => BuiltinPlanner::try_default()?,
+        };
+
+        let settings = planner.common_settings();
+        if let Some(existing_receipt) =
+            find_receipt_at(settings.receipt_path.as_deref(), Some(&settings.state_dir))
+        {
+            eprintln!(
+                "{}",
+                format!(
+                    "Found an existing receipt at `{}`, this install is already managed by `nix-installer`",
+                    existing_receipt.display()
+                )
+                .red()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+
+        if !Path::new("/nix/var/nix").exists() {
+            eprintln!("{}", "No existing Nix install was found to adopt".red());
+            return Ok(ExitCode::FAILURE);
+        }
+
+        let settings = planner.common_settings_mut();
+        probe_build_users(settings)?;
+        // The actions are only ever reverted, existing files must not prevent planning them
+        settings.force = true;
+
+        let planner = planner.boxed();
+        let actions = match planner.plan() {
+            Ok(actions) => actions,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    return Ok(report_failure(&*expected, err.error_code()));
+                }
+                return Err(err)?;
+            },
+        };
+        let plan = mark_completed(InstallPlan {
+            version: current_version()?,
+            extensions: required_extensions(planner.as_ref(), &actions)?,
+            actions,
+            planner,
+            skip_interactive_self_test: false,
+            converging: false,
+            action_timeout: None,
+        })?;
+
+        let receipt_location = plan.planner.receipt_path();
+        if !no_confirm {
+            let brief_summary = format!(
+                "\
+                This will record a receipt at `{receipt}` for the existing Nix install, as though `nix-installer` had installed it.\n\
+                Afterwards, `nix-installer uninstall` will be able to remove it.\
+                ",
+                receipt = receipt_location.display(),
+            );
+            let mut currently_explaining = false;
+            loop {
+                let question = if currently_explaining {
+                    format!(
+                        "{brief_summary}\n\n{}",
+                        plan.describe_uninstall(true).map_err(|e| eyre!(e))?
+                    )
+                } else {
+                    brief_summary.clone()
+                };
+                match interaction::prompt(question, PromptChoice::Yes, currently_explaining)? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => currently_explaining = true,
+                    PromptChoice::No => {
+                        interaction::clean_exit_with_message("Okay, didn't do anything! Bye!")
+                    },
+                }
+            }
+        }
+
+        plan.write_receipt()?;
+        copy_self_to_nix_dir().wrap_err("Copying `nix-installer` to `/nix/nix-installer`")?;
+
+        println!(
+            "\
+            {success}\n\
+            To uninstall it, run `/nix/nix-installer uninstall --thorough`, which also removes the shell installer's shell profile snippets.\n\
+            ",
+            success = format!(
+                "Adopted the existing Nix install, its receipt is at `{}`",
+                receipt_location.display()
+            )
+            .green()
+            .bold(),
+        );
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Find the build users and group of the existing install, the shell installer may have used different IDs
+fn probe_build_users(settings: &mut CommonSettings) -> eyre::Result<()> {
+    let group = Group::from_name(&settings.nix_build_group_name)?.ok_or_else(|| {
+        eyre!(
+            "No `{}` group found, the existing install may not have been created by the shell installer",
+            settings.nix_build_group_name
+        )
+    })?;
+    settings.nix_build_group_id = group.gid.as_raw();
+
+    let prefixes = [settings.nix_build_user_prefix.as_str(), "_nixbld", "nixbld"];
+    let mut found = None;
+    for prefix in prefixes {
+        if let Some(first_user) = User::from_name(&format!("{prefix}1"))? {
+            found = Some((prefix.to_string(), first_user.uid.as_raw()));
+            break;
+        }
+    }
+
+    match found {
+        Some((prefix, first_uid)) => {
+            let mut count = 0;
+            while User::from_name(&format!("{prefix}{}", count + 1))?.is_some() {
+                count += 1;
+            }
+            tracing::debug!(%prefix, first_uid, count, "Found existing build users");
+            settings.nix_build_user_prefix = prefix;
+            settings.nix_build_user_id_base = first_uid.saturating_sub(1);
+            settings.nix_build_user_count = count;
+        },
+        None => {
+            tracing::debug!("No existing build users found");
+            settings.nix_build_user_count = 0;
+        },
+    }
+
+    Ok(())
+}
+
+/// Mark every action (and sub-action) which is not already done or skipped as completed, so it is reverted on uninstall
+fn mark_completed(plan: InstallPlan) -> eyre::Result<InstallPlan> {
+    fn mark(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if map.contains_key("action")
+                    && map.get("state").and_then(|state| state.as_str()) == Some("Uncompleted")
+                {
+                    map.insert("state".into(), "Completed".into());
+                }
+                map.values_mut().for_each(mark);
+            },
+            serde_json::Value::Array(values) => values.iter_mut().for_each(mark),
+            _ => (),
+        }
+    }
+
+    let mut value = serde_json::to_value(plan)?;
+    mark(&mut value);
+    Ok(serde_json::from_value(value)?)
+}
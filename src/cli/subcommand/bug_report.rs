@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{ArgAction, Parser};
+use eyre::WrapErr;
+use target_lexicon::OperatingSystem;
+
+use crate::cli::CommandExecute;
+use crate::cli::arg::log_files;
+use crate::cli::subcommand::info::EmbeddedNix;
+use crate::cli::subcommand::status::InstallStatus;
+use crate::os::probe;
+use crate::plan::find_receipt_at;
+use crate::sensitive::{redact_nix_conf, redact_text};
+use crate::style::Colorize;
+
+/// The directory the files are in, inside the archive
+const ARCHIVE_DIR: &str = "nix-installer-bug-report";
+const NIX_CONF: &str = "/etc/nix/nix.conf";
+
+/**
+Bundle what maintainers need to look into a problem into an archive to attach to an issue
+
+It holds the install receipt, the most recent logs, `nix-installer status` and `info`, the
+operating system (`uname`, `/etc/os-release` or `sw_vers`), the state of the Nix daemon and
+`/nix`, `/etc/nix/nix.conf`, and the self-test results. Secrets, such as access tokens and
+credentials in URLs, and the hostname are redacted, but review the archive before sharing it.
+*/
+#[derive(Debug, Parser)]
+pub struct BugReport {
+    /// Where to write the archive (a `.tar.zst`)
+    #[clap(long = "out-file", default_value = "nix-installer-bug-report.tar.zst")]
+    pub output: PathBuf,
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+    /// How many of the most recent logs to include
+    #[clap(long, default_value_t = 3)]
+    pub logs: usize,
+    /// Skip the self-test, which starts shells and builds with Nix
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub no_self_test: bool,
+    /// The `--log-directory` the logs are read from
+    #[clap(skip)]
+    pub(crate) log_directory: PathBuf,
+}
+
+impl CommandExecute for BugReport {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            output,
+            state_dir,
+            receipt_path,
+            logs,
+            no_self_test,
+            log_directory,
+        } = self;
+
+        let mut files = vec![
+            (
+                "info.json".to_string(),
+                serde_json::to_string_pretty(&EmbeddedNix::current())?,
+            ),
+            (
+                "status.json".to_string(),
+                serde_json::to_string_pretty(&InstallStatus::current(
+                    receipt_path.as_deref(),
+                    state_dir.as_deref(),
+                ))?,
+            ),
+        ];
+        if let Some(receipt) = find_receipt_at(receipt_path.as_deref(), state_dir.as_deref()) {
+            files.push(("receipt.json".to_string(), read(&receipt)));
+        }
+        if Path::new(NIX_CONF).exists() {
+            files.push(("nix.conf".to_string(), redact_nix_conf(&read(NIX_CONF))));
+        }
+        match log_files(&log_directory) {
+            Ok(log_files) => {
+                let skipped = log_files.len().saturating_sub(logs);
+                for log_file in log_files.into_iter().skip(skipped) {
+                    if let Some(name) = log_file.file_name() {
+                        files.push((format!("logs/{}", name.to_string_lossy()), read(&log_file)));
+                    }
+                }
+            },
+            Err(e) => tracing::warn!(
+                "Could not list the logs in `{}`, they are left out: {e}",
+                log_directory.display()
+            ),
+        }
+        files.extend(system_files());
+        if !no_self_test {
+            tracing::info!("Running the self-test");
+            files.push((
+                "self-test.json".to_string(),
+                serde_json::to_string_pretty(&crate::self_test::self_test_report(
+                    receipt_path.as_deref(),
+                ))?,
+            ));
+        }
+
+        let hostname = nix::unistd::gethostname()
+            .ok()
+            .and_then(|hostname| hostname.into_string().ok())
+            .filter(|hostname| !hostname.is_empty());
+        for (_, content) in &mut files {
+            *content = redact_text(content);
+            if let Some(hostname) = &hostname {
+                *content = content.replace(hostname, "<hostname>");
+            }
+        }
+
+        write_archive(&output, &files)
+            .wrap_err_with(|| format!("Writing `{}`", output.display()))?;
+        eprintln!(
+            "{}",
+            format!(
+                "Wrote `{}`, review it before attaching it to an issue",
+                output.display()
+            )
+            .green()
+        );
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The contents of `path`, or why it couldn't be read
+fn read(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    std::fs::read(path)
+        .map(|buf| String::from_utf8_lossy(&buf).into_owned())
+        .unwrap_or_else(|e| format!("Could not read `{}`: {e}\n", path.display()))
+}
+
+/// The operating system, and the state of the Nix daemon and `/nix`
+fn system_files() -> Vec<(String, String)> {
+    let mut files = vec![("uname.txt".to_string(), run(&[&["uname", "-a"]]))];
+    match OperatingSystem::host() {
+        OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
+            files.extend([
+                ("sw_vers.txt".to_string(), run(&[&["/usr/bin/sw_vers"]])),
+                (
+                    "diskutil.txt".to_string(),
+                    run(&[
+                        &["/usr/sbin/diskutil", "list"],
+                        &["/usr/sbin/diskutil", "info", "/nix"],
+                        &["/usr/sbin/diskutil", "apfs", "list"],
+                    ]),
+                ),
+                (
+                    "launchd.txt".to_string(),
+                    run(&[
+                        &["launchctl", "print", "system/org.nixos.nix-daemon"],
+                        &["launchctl", "print", "system/org.nixos.darwin-store"],
+                    ]),
+                ),
+            ]);
+        },
+        _ => {
+            files.extend([
+                ("os-release".to_string(), read("/etc/os-release")),
+                ("mounts.txt".to_string(), run(&[&["df", "-h", "/nix"]])),
+                (
+                    "systemd.txt".to_string(),
+                    run(&[
+                        &[
+                            "systemctl",
+                            "status",
+                            "--no-pager",
+                            "nix-daemon.service",
+                            "nix-daemon.socket",
+                        ],
+                        &[
+                            "journalctl",
+                            "--no-pager",
+                            "--lines=200",
+                            "--unit=nix-daemon.service",
+                        ],
+                    ]),
+                ),
+            ]);
+        },
+    }
+    files
+}
+
+/// A transcript of running each of `commands`, with their output and exit status
+fn run(commands: &[&[&str]]) -> String {
+    let mut transcript = String::new();
+    for command in commands {
+        let Some((program, args)) = command.split_first() else {
+            continue;
+        };
+        transcript.push_str(&format!("$ {}\n", command.join(" ")));
+        match probe::command(program).args(args).output() {
+            Ok(output) => {
+                transcript.push_str(&String::from_utf8_lossy(&output.stdout));
+                transcript.push_str(&String::from_utf8_lossy(&output.stderr));
+                if !output.status.success() {
+                    transcript.push_str(&format!("({})\n", output.status));
+                }
+            },
+            Err(e) => transcript.push_str(&format!("(could not run: {e})\n")),
+        }
+        transcript.push('\n');
+    }
+    transcript
+}
+
+fn write_archive(output: &Path, files: &[(String, String)]) -> std::io::Result<()> {
+    // The logs are only readable by root, so neither is the archive
+    let file = File::options()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o600)
+        .open(output)?;
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut archive = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    for (name, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        archive.append_data(
+            &mut header,
+            format!("{ARCHIVE_DIR}/{name}"),
+            content.as_bytes(),
+        )?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
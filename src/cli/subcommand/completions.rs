@@ -0,0 +1,68 @@
+use std::process::ExitCode;
+
+use clap::{CommandFactory, Parser, ValueEnum};
+
+use crate::cli::{CommandExecute, NixInstallerCli};
+
+/// The name completions are registered for, which is what the shells see on the `PATH`
+const BIN_NAME: &str = "nix-installer";
+
+/// Print a shell completion script for `nix-installer`, for packagers to install
+#[derive(Debug, Parser)]
+pub struct Completions {
+    /// The shell to complete `nix-installer` in
+    #[clap(value_enum)]
+    pub shell: CompletionShell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    /// Nushell
+    Nu,
+}
+
+impl CommandExecute for Completions {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let mut stdout = std::io::stdout();
+        write_completions(self.shell, &mut stdout);
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn write_completions(shell: CompletionShell, buf: &mut dyn std::io::Write) {
+    let mut command = NixInstallerCli::command();
+    match shell {
+        CompletionShell::Bash => {
+            clap_complete::generate(clap_complete::Shell::Bash, &mut command, BIN_NAME, buf)
+        },
+        CompletionShell::Zsh => {
+            clap_complete::generate(clap_complete::Shell::Zsh, &mut command, BIN_NAME, buf)
+        },
+        CompletionShell::Fish => {
+            clap_complete::generate(clap_complete::Shell::Fish, &mut command, BIN_NAME, buf)
+        },
+        CompletionShell::Nu => {
+            clap_complete::generate(clap_complete_nushell::Nushell, &mut command, BIN_NAME, buf)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn completes_subcommands() {
+        for shell in CompletionShell::value_variants() {
+            let mut buf = vec![];
+            write_completions(*shell, &mut buf);
+            let completions = String::from_utf8(buf).unwrap();
+            assert!(completions.contains("uninstall"), "{shell:?}");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::process::ExitCode;
+
+use clap::{ArgAction, Parser};
+
+use crate::style::Colorize;
+use crate::{cli::CommandExecute, settings};
+
+/// Show the version of Nix embedded in this installer, without installing it
+#[derive(Debug, Parser)]
+pub struct Info {
+    /// Output a JSON object, for scripts deciding whether an upgrade is needed
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub json: bool,
+}
+
+/// What `nix-installer info` reports, known when the installer was built
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct EmbeddedNix {
+    installer_version: &'static str,
+    nix_version: &'static str,
+    nix_store_path: &'static str,
+    nss_cacert_store_path: &'static str,
+    tarball_sha256: Option<&'static str>,
+    tarball_size: usize,
+    experimental_features: Option<Vec<&'static str>>,
+    target: String,
+}
+
+impl EmbeddedNix {
+    pub(crate) fn current() -> Self {
+        Self {
+            installer_version: env!("CARGO_PKG_VERSION"),
+            nix_version: settings::NIX_VERSION,
+            nix_store_path: settings::NIX_STORE_PATH,
+            nss_cacert_store_path: settings::NSS_CACERT_STORE_PATH,
+            tarball_sha256: Some(settings::NIX_TARBALL_SHA256).filter(|hash| !hash.is_empty()),
+            tarball_size: settings::EMBEDDED_NIX_TARBALL.len(),
+            experimental_features: Some(settings::NIX_EXPERIMENTAL_FEATURES)
+                .filter(|features| !features.is_empty())
+                .map(|features| features.split(',').collect()),
+            target: target_lexicon::HOST.to_string(),
+        }
+    }
+}
+
+impl CommandExecute for Info {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let info = EmbeddedNix::current();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&info)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let unknown = || "unknown".to_string();
+        println!(
+            "\
+            {installer} {installer_version} ({target})\n\
+            \n\
+            Embedded Nix {nix_version}\n\
+            \x20 Store path: {nix_store_path}\n\
+            \x20 Certificates: {nss_cacert_store_path}\n\
+            \x20 Tarball: {tarball_size} bytes, SHA-256 {tarball_sha256}\n\
+            \x20 Experimental features: {experimental_features}\
+            ",
+            installer = "nix-installer".bold(),
+            installer_version = info.installer_version,
+            target = info.target,
+            nix_version = info.nix_version.bold(),
+            nix_store_path = info.nix_store_path,
+            nss_cacert_store_path = info.nss_cacert_store_path,
+            tarball_size = info.tarball_size,
+            tarball_sha256 = info
+                .tarball_sha256
+                .map(String::from)
+                .unwrap_or_else(unknown),
+            experimental_features = info
+                .experimental_features
+                .map(|features| features.join(", "))
+                .unwrap_or_else(unknown),
+        );
+        Ok(ExitCode::SUCCESS)
+    }
+}
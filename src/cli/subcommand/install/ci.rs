@@ -0,0 +1,59 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, WrapErr};
+
+use crate::settings;
+
+/// Whether `--ci` was passed, or the installer is running in a GitHub Actions workflow
+pub(crate) fn enabled(ci: bool) -> bool {
+    ci || std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// Start a collapsible group of log lines, as GitHub Actions (and compatible runners) display them
+pub(crate) fn group(title: &str) {
+    println!("::group::{title}");
+}
+
+pub(crate) fn end_group() {
+    println!("::endgroup::");
+}
+
+/// The `key=value` outputs later steps can use, such as `steps.<id>.outputs.nix-version`
+pub(crate) fn outputs() -> Vec<(&'static str, String)> {
+    vec![
+        ("nix-version", settings::NIX_VERSION.to_string()),
+        ("nix-store-path", settings::NIX_STORE_PATH.to_string()),
+        (
+            "nix-bin",
+            Path::new(settings::NIX_STORE_PATH)
+                .join("bin")
+                .display()
+                .to_string(),
+        ),
+    ]
+}
+
+/// Append the [`outputs`] to `$GITHUB_OUTPUT`, or print them if it is unset
+pub(crate) fn write_outputs() -> eyre::Result<()> {
+    let outputs = outputs()
+        .into_iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect::<String>();
+
+    match std::env::var_os("GITHUB_OUTPUT").map(PathBuf::from) {
+        Some(path) => {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .wrap_err_with(|| format!("Opening `{}`", path.display()))?;
+            file.write_all(outputs.as_bytes())
+                .wrap_err_with(|| format!("Writing outputs to `{}`", path.display()))?;
+        },
+        None => print!("{outputs}"),
+    }
+    Ok(())
+}
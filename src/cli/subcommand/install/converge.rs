@@ -0,0 +1,109 @@
+use color_eyre::eyre::{self, WrapErr, eyre};
+
+use crate::{
+    BuiltinPlanner, InstallPlan,
+    action::{Action, ActionState, StatefulAction},
+};
+
+/**
+Plan the requested settings over an existing install, so only actions which differ from the
+receipt are executed
+
+Actions identical to one the receipt records are marked completed. The rest are planned against
+the system as-is, so their own checks decide what is left to do (such as only creating the
+additional build users). Returns `None` when nothing differs.
+*/
+pub(crate) fn plan(
+    existing_receipt: &InstallPlan,
+    planner: BuiltinPlanner,
+) -> eyre::Result<Option<InstallPlan>> {
+    let mut plan = planner.plan()?;
+
+    // Undoing what a setting did isn't something actions can do without reverting it all
+    let dropped = existing_receipt
+        .actions
+        .iter()
+        .filter(|recorded| {
+            !plan
+                .actions
+                .iter()
+                .any(|planned| planned.inner_typetag_name() == recorded.inner_typetag_name())
+        })
+        .map(|recorded| recorded.tracing_synopsis())
+        .collect::<Vec<_>>();
+    if !dropped.is_empty() {
+        return Err(eyre!(
+            "Converging cannot undo these steps of the existing install, uninstall and install again instead:\n{}",
+            dropped
+                .iter()
+                .map(|synopsis| format!("* {synopsis}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+    }
+
+    let recorded = existing_receipt
+        .actions
+        .iter()
+        .map(desired_state)
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let mut changed = false;
+    for action in plan.actions.iter_mut() {
+        if recorded.contains(&desired_state(action)?) {
+            action.state = ActionState::Completed;
+        } else if action.state != ActionState::Completed {
+            tracing::debug!("Converging `{}`", action.tracing_synopsis());
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return Ok(None);
+    }
+
+    plan.set_converging(true);
+    Ok(Some(plan))
+}
+
+/// The action as JSON without the states of it (or its children), which only reflect progress
+fn desired_state(action: &StatefulAction<Box<dyn Action>>) -> eyre::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(&action.action)
+        .wrap_err_with(|| format!("Serializing `{}`", action.tracing_synopsis()))?;
+    strip_states(&mut value);
+    Ok(value)
+}
+
+fn strip_states(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if map.contains_key("action") {
+                map.remove("state");
+            }
+            map.values_mut().for_each(strip_states);
+        },
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_states),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_states;
+
+    #[test]
+    fn states_are_ignored() {
+        let mut recorded = serde_json::json!({
+            "action_name": "create_users_and_group",
+            "create_group": { "action": { "name": "nixbld" }, "state": "Completed" },
+            "create_users": [{ "action": { "name": "nixbld1" }, "state": "Skipped" }],
+        });
+        let mut planned = serde_json::json!({
+            "action_name": "create_users_and_group",
+            "create_group": { "action": { "name": "nixbld" }, "state": "Uncompleted" },
+            "create_users": [{ "action": { "name": "nixbld1" }, "state": "Completed" }],
+        });
+        strip_states(&mut recorded);
+        strip_states(&mut planned);
+        assert_eq!(recorded, planned);
+    }
+}
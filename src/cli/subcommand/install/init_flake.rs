@@ -0,0 +1,116 @@
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, WrapErr};
+use nix::unistd::{Gid, Uid, chown};
+
+use crate::style::Colorize;
+
+const FLAKE_TEMPLATE: &str = r#"{
+  description = "A starter flake, created by nix-installer";
+
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+
+  outputs =
+    { self, nixpkgs }:
+    let
+      systems = [
+        "x86_64-linux"
+        "aarch64-linux"
+        "x86_64-darwin"
+        "aarch64-darwin"
+      ];
+      forAllSystems = f: nixpkgs.lib.genAttrs systems (system: f nixpkgs.legacyPackages.${system});
+    in
+    {
+      # Entered with `nix develop`, add the tools your project needs to `packages`
+      devShells = forAllSystems (pkgs: {
+        default = pkgs.mkShell {
+          packages = [ pkgs.hello ];
+        };
+      });
+
+      # Run with `nix fmt`
+      formatter = forAllSystems (pkgs: pkgs.nixfmt-tree);
+    };
+}
+"#;
+
+/// Write a starter `flake.nix` into `dir`, owned by the user who ran `sudo`, if any
+///
+/// An existing `flake.nix` is left alone, returning `None`.
+pub(crate) fn init_flake(dir: &Path) -> eyre::Result<Option<PathBuf>> {
+    let flake = dir.join("flake.nix");
+    if flake.exists() {
+        tracing::warn!(
+            "Not creating a starter flake, `{}` already exists",
+            flake.display()
+        );
+        return Ok(None);
+    }
+
+    let owner = invoking_user();
+    if !dir.exists() {
+        std::fs::create_dir_all(dir).wrap_err_with(|| format!("Creating `{}`", dir.display()))?;
+        chown_to(dir, owner)?;
+    }
+    std::fs::write(&flake, FLAKE_TEMPLATE)
+        .wrap_err_with(|| format!("Writing `{}`", flake.display()))?;
+    std::fs::set_permissions(&flake, PermissionsExt::from_mode(0o644))
+        .wrap_err_with(|| format!("Setting permissions of `{}`", flake.display()))?;
+    chown_to(&flake, owner)?;
+
+    Ok(Some(flake))
+}
+
+/// What to try with the flake written by [`init_flake`]
+pub(crate) fn next_steps(dir: &Path) -> String {
+    format!(
+        "\
+        A starter flake was created in `{dir}`, try it from a new shell:\n\
+        \x20 {cd}\n\
+        \x20 {develop}   # Enter a shell with the flake's packages\n\
+        \x20 {fmt}       # Format the flake\n\
+        ",
+        dir = dir.display(),
+        cd = format!("cd {}", dir.display()).bold(),
+        develop = "nix develop".bold(),
+        fmt = "nix fmt".bold(),
+    )
+}
+
+/// The user and group which ran the installer through `sudo`
+fn invoking_user() -> Option<(Uid, Gid)> {
+    let uid = std::env::var("SUDO_UID").ok()?.parse().ok()?;
+    let gid = std::env::var("SUDO_GID").ok()?.parse().ok()?;
+    Some((Uid::from_raw(uid), Gid::from_raw(gid)))
+}
+
+fn chown_to(path: &Path, owner: Option<(Uid, Gid)>) -> eyre::Result<()> {
+    if let Some((uid, gid)) = owner {
+        chown(path, Some(uid), Some(gid))
+            .wrap_err_with(|| format!("Changing ownership of `{}`", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_existing_flake() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("project");
+
+        let flake = init_flake(&dir)?.expect("A flake is created in an empty directory");
+        assert_eq!(std::fs::read_to_string(&flake)?, FLAKE_TEMPLATE);
+
+        std::fs::write(&flake, "{ }")?;
+        assert!(init_flake(&dir)?.is_none());
+        assert_eq!(std::fs::read_to_string(&flake)?, "{ }");
+        Ok(())
+    }
+}
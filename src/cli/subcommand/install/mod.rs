@@ -1,19 +1,33 @@
+mod ci;
+mod converge;
+mod init_flake;
+#[cfg(feature = "tui")]
+mod tui;
+
 use std::{
+    io::IsTerminal,
     os::unix::prelude::PermissionsExt,
     path::{Path, PathBuf},
     process::ExitCode,
+    sync::Arc,
+    time::Duration,
 };
 
+use crate::style::Colorize;
 use crate::{
-    BuiltinPlanner, InstallPlan, NixInstallerError,
+    BuiltinPlanner, ErrorCode, InstallPlan, NixInstallerError, OnFailure,
     cli::{
-        CommandExecute, ensure_root,
+        CommandExecute, back_up_receipt, ensure_root,
+        error::{report_failure, report_failure_message},
         interaction::{self, PromptChoice},
+        progress::TerminalProgress,
         setup_signal_handler,
         subcommand::split_receipt::{PHASE1_RECEIPT_LOCATION, PHASE2_RECEIPT_LOCATION},
     },
     error::HasExpectedErrors,
-    plan::RECEIPT_LOCATION,
+    locale::translate,
+    plan::find_receipt_at,
+    prompt::{InteractionHandler, PromptAnswer, PromptAnswers},
     settings::CommonSettings,
     util::OnMissing,
 };
@@ -22,7 +36,11 @@ use color_eyre::{
     Section,
     eyre::{WrapErr, eyre},
 };
-use owo_colors::OwoColorize;
+
+/// The pre-seeded answer to confirming the install plan
+const ANSWER_INSTALL: &str = "install";
+/// The pre-seeded answer to reverting a failed install
+const ANSWER_REVERT_ON_FAILURE: &str = "revert_on_failure";
 
 const EXISTING_INCOMPATIBLE_PLAN_GUIDANCE: &str = "\
     If you are trying to upgrade Nix, try running `sudo -i nix upgrade-nix` instead.\n\
@@ -63,7 +81,82 @@ pub struct Install {
     )]
     pub explain: bool,
 
-    /// A path to a non-default installer plan
+    /// What to do when a step of the installation fails: `stop` (and offer to revert), `rollback` the partial install, or `ignore` it and continue
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OnFailure::Stop,
+        env = "NIX_INSTALLER_ON_FAILURE",
+        global = true
+    )]
+    pub on_failure: OnFailure,
+
+    /// Kill a step's commands and fail it if it is still running after this many seconds, rather than waiting forever for a command which hangs
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        env = "NIX_INSTALLER_ACTION_TIMEOUT",
+        global = true
+    )]
+    pub action_timeout: Option<u64>,
+
+    /// After installing, create a starter `flake.nix` (with a development shell and formatter) in this directory, owned by the user running `sudo`
+    #[clap(
+        long,
+        value_name = "DIR",
+        env = "NIX_INSTALLER_INIT_FLAKE",
+        global = true
+    )]
+    pub init_flake: Option<PathBuf>,
+
+    /// Run non-interactively for CI, grouping the log output and reporting the installed Nix for later steps (implied when `GITHUB_ACTIONS=true`)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_CI",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub ci: bool,
+
+    /// When `nix-installer` already installed Nix, apply only what differs from the recorded settings (such as more build users or `--extra-conf`) and update the receipt
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_CONVERGE",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub converge: bool,
+
+    /// Pre-seed the answer to a decision as `KEY=yes` or `KEY=no`, so it is never prompted for, such as `install`, `revert_on_failure`, `replace_file` or `replace_file:PATH` (can be repeated)
+    #[clap(
+        long = "answer",
+        value_name = "KEY=ANSWER",
+        value_parser = PromptAnswers::parse_pair,
+        action = ArgAction::Append,
+        env = "NIX_INSTALLER_ANSWERS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub answers: Vec<(String, PromptAnswer)>,
+
+    /// A JSON object of pre-seeded answers, such as `{"install": "yes", "replace_file": "no"}`, which `--answer` overrides
+    #[clap(long, env = "NIX_INSTALLER_ANSWERS_FILE", global = true)]
+    pub answers_file: Option<PathBuf>,
+
+    /// Choose the planner's options, review the plan and watch each step install in a full-screen wizard
+    #[cfg(feature = "tui")]
+    #[clap(
+        long,
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        conflicts_with_all = ["no_confirm", "ci"],
+        global = true
+    )]
+    pub interactive_tui: bool,
+
+    /// A path to a non-default installer plan, such as one from `nix-installer plan`, where `${env:NAME}` and `${env:NAME:-default}` are substituted from the environment
     #[clap(env = "NIX_INSTALLER_PLAN")]
     pub plan: Option<PathBuf>,
 
@@ -80,22 +173,47 @@ impl CommandExecute for Install {
             planner: maybe_planner,
             settings,
             explain,
+            on_failure,
+            action_timeout,
+            init_flake,
+            ci,
+            converge,
+            answers,
+            answers_file,
+            #[cfg(feature = "tui")]
+            interactive_tui,
         } = self;
+        #[cfg(not(feature = "tui"))]
+        let interactive_tui = false;
+
+        let ci = ci::enabled(ci);
+        let no_confirm = no_confirm || ci;
+        let answers = interaction::collect_answers(answers_file.as_deref(), answers)?;
+
+        // Planners which install without `root` (like `termux`) run as the user
+        let rootless = match (&plan, &maybe_planner) {
+            (None, Some(planner)) => !planner.requires_root(),
+            (None, None) => crate::planner::detect::in_termux(),
+            (Some(_), _) => false,
+        };
+        if !rootless {
+            ensure_root()?;
+        }
 
-        ensure_root()?;
-
-        let existing_receipt: Option<InstallPlan> = match Path::new(RECEIPT_LOCATION).exists() {
-            true => {
+        let existing_receipt_location =
+            find_receipt_at(settings.receipt_path.as_deref(), Some(&settings.state_dir));
+        let existing_receipt: Option<InstallPlan> = match &existing_receipt_location {
+            Some(receipt_location) => {
                 tracing::trace!("Reading existing receipt");
                 let install_plan_string =
-                    std::fs::read_to_string(RECEIPT_LOCATION).wrap_err("Reading plan")?;
+                    std::fs::read_to_string(receipt_location).wrap_err("Reading plan")?;
                 Some(
                     serde_json::from_str(&install_plan_string).wrap_err_with(|| {
-                        format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`. Try running `/nix/nix-installer uninstall`, then installing again.")
+                        format!("Unable to parse existing receipt `{}`, it may be from an incompatible version of `nix-installer`. Try running `/nix/nix-installer uninstall`, then installing again.", receipt_location.display())
                     })?,
                 )
             },
-            false => None,
+            None => None,
         };
 
         let uninstall_command = match Path::new("/nix/nix-installer").exists() {
@@ -110,96 +228,227 @@ impl CommandExecute for Install {
                 "`--plan` conflicts with passing a planner, a planner creates plans, so passing an existing plan doesn't make sense"
             ));
         }
+        // Checked here rather than by `clap`, as the planner subcommands `--interactive-tui` reaches have no `plan`
+        if plan.is_some() && interactive_tui {
+            return Err(eyre!(
+                "`--interactive-tui` conflicts with passing a plan, the wizard plans the install itself"
+            ));
+        }
 
         let mut install_plan = if let Some(plan_path) = plan {
-            let install_plan_string =
-                std::fs::read_to_string(&plan_path).wrap_err("Reading plan")?;
-            serde_json::from_str(&install_plan_string)?
+            match InstallPlan::from_plan_file(&plan_path, false) {
+                Ok(plan) => plan,
+                Err(err) => {
+                    if let Some(expected) = err.expected() {
+                        return Ok(report_failure(&*expected, err.error_code()));
+                    }
+                    return Err(err)?;
+                },
+            }
         } else {
             let planner = match maybe_planner {
                 Some(planner) => planner,
                 None => BuiltinPlanner::from_common_settings(settings.clone())
                     .map_err(|e| eyre::eyre!(e))?,
             };
+            #[cfg(feature = "tui")]
+            let planner = match interactive_tui {
+                true => match tui::choose_options(planner)? {
+                    Some(planner) => planner,
+                    None => interaction::clean_exit_with_message(
+                        "Okay, not continuing with the installation. Bye!",
+                    ),
+                },
+                false => planner,
+            };
 
             if let Some(existing_receipt) = existing_receipt {
+                let receipt_location = existing_receipt_location
+                    .as_deref()
+                    .map(|location| location.display().to_string())
+                    .unwrap_or_default();
                 if let Err(e) = existing_receipt.check_compatible() {
-                    eprintln!(
-                        "{}",
-                        format!("\
+                    return Ok(report_failure_message(
+                        format!(
+                            "\
                             {e}\n\
                             \n\
-                            Found existing plan in `{RECEIPT_LOCATION}` which was created by a version incompatible `nix-installer`.\n\
+                            Found existing plan in `{receipt_location}` which was created by a version incompatible `nix-installer`.\n\
                             {EXISTING_INCOMPATIBLE_PLAN_GUIDANCE}\n\
-                        ").red()
-                        );
-                    return Ok(ExitCode::FAILURE);
+                        "
+                        ),
+                        ErrorCode::IncompatibleVersion,
+                    ));
                 }
 
                 if existing_receipt.planner.typetag_name() != planner.typetag_name() {
-                    eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
-                    return Ok(ExitCode::FAILURE);
+                    return Ok(report_failure_message(
+                        format!(
+                            "Found existing plan in `{receipt_location}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`"
+                        ),
+                        ErrorCode::AlreadyInstalled,
+                    ));
                 }
 
-                if existing_receipt.planner.settings().map_err(|e| eyre!(e))?
+                if converge {
+                    match converge::plan(&existing_receipt, planner)? {
+                        Some(plan) => {
+                            back_up_receipt(&existing_receipt, "converge")?;
+                            plan
+                        },
+                        None => {
+                            eprintln!(
+                                "{}",
+                                format!("Found existing plan in `{receipt_location}`, which already matches the requested settings").green()
+                            );
+                            return Ok(ExitCode::SUCCESS);
+                        },
+                    }
+                } else if existing_receipt.planner.settings().map_err(|e| eyre!(e))?
                     != planner.settings().map_err(|e| eyre!(e))?
                 {
-                    eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
-                    return Ok(ExitCode::FAILURE);
+                    return Ok(report_failure_message(
+                        format!(
+                            "Found existing plan in `{receipt_location}` which used different planner settings, try `--converge` to apply the new settings, or uninstalling the existing install with `{uninstall_command}`"
+                        ),
+                        ErrorCode::AlreadyInstalled,
+                    ));
+                } else {
+                    eprintln!("{}", format!("Found existing plan in `{receipt_location}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").red());
+                    return Ok(ExitCode::SUCCESS);
+                }
+            } else {
+                let res = planner.plan();
+                match res {
+                    Ok(plan) => plan,
+                    Err(err) => {
+                        if let Some(expected) = err.expected() {
+                            return Ok(report_failure(&*expected, err.error_code()));
+                        }
+                        return Err(err)?;
+                    },
                 }
-
-                eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").red());
-                return Ok(ExitCode::SUCCESS);
-            }
-
-            let res = planner.plan();
-            match res {
-                Ok(plan) => plan,
-                Err(err) => {
-                    if let Some(expected) = err.expected() {
-                        eprintln!("{}", expected.red());
-                        return Ok(ExitCode::FAILURE);
-                    }
-                    return Err(err)?;
-                },
             }
         };
 
         if let Err(err) = install_plan.pre_install_check() {
             if let Some(expected) = err.expected() {
-                eprintln!("{}", expected.red());
-                return Ok(ExitCode::FAILURE);
+                return Ok(report_failure(&*expected, err.error_code()));
             }
             Err(err)?
         }
 
-        if !no_confirm {
-            let mut currently_explaining = explain;
-            loop {
-                match interaction::prompt(
-                    install_plan
-                        .describe_install(currently_explaining)
-                        .map_err(|e| eyre!(e))?,
-                    PromptChoice::Yes,
-                    currently_explaining,
-                )? {
-                    PromptChoice::Yes => break,
-                    PromptChoice::Explain => currently_explaining = true,
-                    PromptChoice::No => interaction::clean_exit_with_message(
+        // Shells reading the user's rc files are flaky to test without a terminal
+        install_plan.set_skip_interactive_self_test(ci);
+        install_plan.set_action_timeout(action_timeout.map(Duration::from_secs));
+
+        match answers.get(ANSWER_INSTALL) {
+            _ if no_confirm => (),
+            #[cfg(feature = "tui")]
+            _ if interactive_tui => {
+                if !tui::review(&install_plan)? {
+                    interaction::clean_exit_with_message(
                         "Okay, not continuing with the installation. Bye!",
-                    ),
+                    )
                 }
-            }
+            },
+            Some(PromptAnswer::Yes) => (),
+            Some(PromptAnswer::No) => interaction::clean_exit_with_message(
+                "Okay, not continuing with the installation, as pre-seeded. Bye!",
+            ),
+            None => {
+                let mut currently_explaining = explain;
+                loop {
+                    match interaction::prompt(
+                        install_plan
+                            .describe_install(currently_explaining)
+                            .map_err(|e| eyre!(e))?,
+                        PromptChoice::Yes,
+                        currently_explaining,
+                    )? {
+                        PromptChoice::Yes => break,
+                        PromptChoice::Explain => currently_explaining = true,
+                        PromptChoice::No => interaction::clean_exit_with_message(
+                            "Okay, not continuing with the installation. Bye!",
+                        ),
+                    }
+                }
+            },
         }
 
         let cancel_signal = setup_signal_handler();
+        // With `--no-confirm`, prompts raised during execution use their pre-seeded or predeclared answers
+        let interaction_handler = (!no_confirm || !answers.is_empty()).then(|| {
+            Arc::new(interaction::TerminalInteraction {
+                answers: answers.clone(),
+                interactive: !no_confirm,
+            }) as Arc<dyn InteractionHandler>
+        });
+
+        // Without a terminal to draw on, or with plain output, each step is logged instead
+        let progress = (std::io::stderr().is_terminal()
+            && !crate::style::is_plain()
+            && !ci
+            && !interactive_tui)
+            .then(|| Arc::new(TerminalProgress::new()));
+        let reporter = progress
+            .clone()
+            .map(|progress| progress as Arc<dyn crate::progress::ProgressReporter>);
+        #[cfg(feature = "tui")]
+        let wizard_progress = match interactive_tui {
+            true => Some(Arc::new(tui::WizardProgress::new(
+                &install_plan,
+                cancel_signal.clone(),
+            )?)),
+            false => None,
+        };
+        #[cfg(feature = "tui")]
+        let reporter = reporter.or_else(|| {
+            wizard_progress
+                .clone()
+                .map(|progress| progress as Arc<dyn crate::progress::ProgressReporter>)
+        });
+
+        if ci {
+            ci::group("Installing Nix");
+        }
+        let res = install_plan.install_with_progress(
+            Some(cancel_signal.clone()),
+            interaction_handler,
+            reporter,
+            on_failure,
+        );
+        if ci {
+            ci::end_group();
+        }
+        #[cfg(feature = "tui")]
+        if let Some(wizard_progress) = wizard_progress {
+            wizard_progress.finish(res.as_ref().map(|_| ()))?;
+        }
 
-        match install_plan.install(Some(cancel_signal.clone())) {
+        match res {
+            Err(err @ NixInstallerError::RolledBack(_)) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                } else {
+                    let error = eyre!(err).wrap_err("Install failure");
+                    tracing::error!("{:?}", error);
+                }
+                eprintln!(
+                    "{}",
+                    "Partial Nix install was rolled back successfully!".bold()
+                );
+                return Ok(ExitCode::from(ErrorCode::RolledBack.exit_code()));
+            },
+            Err(err @ NixInstallerError::RollbackFailed(_, _)) => {
+                return Err(eyre!(err).wrap_err("Install failure"))?;
+            },
             Err(err) => {
                 // Attempt to copy self to the store if possible, but since the install failed, this might not work, that's ok.
                 copy_self_to_nix_dir().ok();
 
-                if !no_confirm {
+                let revert_answer = answers.get(ANSWER_REVERT_ON_FAILURE);
+                if !no_confirm || revert_answer == Some(PromptAnswer::Yes) {
                     let mut was_expected = false;
                     if let Some(expected) = err.expected() {
                         was_expected = true;
@@ -216,22 +465,32 @@ impl CommandExecute for Install {
                         tracing::error!("{:?}", error);
                     };
 
-                    eprintln!("{}", "Installation failure, offering to revert...".red());
-                    let mut currently_explaining = explain;
-                    loop {
-                        match interaction::prompt(
-                            install_plan
-                                .describe_uninstall(currently_explaining)
-                                .map_err(|e| eyre!(e))?,
-                            PromptChoice::Yes,
-                            currently_explaining,
-                        )? {
-                            PromptChoice::Yes => break,
-                            PromptChoice::Explain => currently_explaining = true,
-                            PromptChoice::No => interaction::clean_exit_with_message(
-                                "Okay, didn't do anything! Bye!",
-                            ),
-                        }
+                    match revert_answer {
+                        Some(PromptAnswer::Yes) => {
+                            eprintln!("{}", "Installation failure, reverting...".red())
+                        },
+                        Some(PromptAnswer::No) => interaction::clean_exit_with_message(
+                            "Installation failure, not reverting, as pre-seeded. Bye!",
+                        ),
+                        None => {
+                            eprintln!("{}", "Installation failure, offering to revert...".red());
+                            let mut currently_explaining = explain;
+                            loop {
+                                match interaction::prompt(
+                                    install_plan
+                                        .describe_uninstall(currently_explaining)
+                                        .map_err(|e| eyre!(e))?,
+                                    PromptChoice::Yes,
+                                    currently_explaining,
+                                )? {
+                                    PromptChoice::Yes => break,
+                                    PromptChoice::Explain => currently_explaining = true,
+                                    PromptChoice::No => interaction::clean_exit_with_message(
+                                        "Okay, didn't do anything! Bye!",
+                                    ),
+                                }
+                            }
+                        },
                     }
                     let res = install_plan.uninstall(Some(cancel_signal));
 
@@ -245,12 +504,10 @@ impl CommandExecute for Install {
                         },
                         Err(err) => {
                             if let Some(expected) = err.expected() {
-                                eprintln!("{}", expected.red());
-                                return Ok(ExitCode::FAILURE);
+                                return Ok(report_failure(&*expected, err.error_code()));
                             }
                             if matches!(err, NixInstallerError::Cancelled) {
-                                eprintln!("{}", err.red());
-                                return Ok(ExitCode::FAILURE);
+                                return Ok(report_failure(&err, err.error_code()));
                             }
                             return Err(err)?;
                         },
@@ -266,12 +523,10 @@ impl CommandExecute for Install {
                     }
                 } else {
                     if let Some(expected) = err.expected() {
-                        eprintln!("{}", expected.red());
-                        return Ok(ExitCode::FAILURE);
+                        return Ok(report_failure(&*expected, err.error_code()));
                     }
                     if matches!(err, NixInstallerError::Cancelled) {
-                        eprintln!("{}", err.red());
-                        return Ok(ExitCode::FAILURE);
+                        return Ok(report_failure(&err, err.error_code()));
                     }
 
                     let error = eyre!(err).wrap_err("Install failure");
@@ -279,8 +534,10 @@ impl CommandExecute for Install {
                 }
             },
             Ok(_) => {
-                copy_self_to_nix_dir()
-                    .wrap_err("Copying `nix-installer` to `/nix/nix-installer`")?;
+                if install_plan.planner.requires_root() {
+                    copy_self_to_nix_dir()
+                        .wrap_err("Copying `nix-installer` to `/nix/nix-installer`")?;
+                }
 
                 let phase1_receipt_path = Path::new(PHASE1_RECEIPT_LOCATION);
                 if phase1_receipt_path.exists() {
@@ -300,19 +557,39 @@ impl CommandExecute for Install {
                         .wrap_err_with(|| format!("Failed to remove uninstall phase 2 receipt at {PHASE2_RECEIPT_LOCATION}"))?;
                 }
 
+                if let Some(summary) = progress.as_ref().and_then(|progress| progress.summary()) {
+                    println!("{summary}\n");
+                }
+
                 println!(
                     "\
                     {success}\n\
                     To get started using Nix, open a new shell or run `{shell_reminder}`\n\
                     ",
-                    success = "Nix was installed successfully!".green().bold(),
+                    success = translate("Nix was installed successfully!").green().bold(),
                     shell_reminder = match std::env::var("SHELL") {
+                        // Without `root`, Nix is only reachable through proot
+                        _ if !install_plan.planner.requires_root() =>
+                            crate::planner::termux::ENTER_COMMAND.bold(),
                         Ok(val) if val.contains("fish") =>
                             ". /nix/var/nix/profiles/default/etc/profile.d/nix-daemon.fish".bold(),
                         Ok(_) | Err(_) =>
                             ". /nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh".bold(),
                     },
                 );
+
+                if let Some(dir) = init_flake {
+                    // The install succeeded, so a failure here is reported without failing it
+                    match init_flake::init_flake(&dir) {
+                        Ok(Some(_)) => println!("{}", init_flake::next_steps(&dir)),
+                        Ok(None) => (),
+                        Err(err) => tracing::warn!("Could not create a starter flake: {err:?}"),
+                    }
+                }
+
+                if ci {
+                    ci::write_outputs().wrap_err("Writing CI outputs")?;
+                }
             },
         }
 
@@ -321,7 +598,7 @@ impl CommandExecute for Install {
 }
 
 #[tracing::instrument(level = "debug")]
-fn copy_self_to_nix_dir() -> Result<(), std::io::Error> {
+pub(crate) fn copy_self_to_nix_dir() -> Result<(), std::io::Error> {
     let path = std::env::current_exe()?;
     std::fs::copy(path, "/nix/nix-installer")?;
     std::fs::set_permissions("/nix/nix-installer", PermissionsExt::from_mode(0o0755))?;
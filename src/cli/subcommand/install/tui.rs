@@ -0,0 +1,763 @@
+/*! The full-screen wizard of `nix-installer install --interactive-tui`
+
+The wizard lets the user change the planner's options, reviews the plan (with each step's
+explanation a keypress away), draws each step as it executes, then summarizes how it went.
+Each screen is its own terminal session, so anything printed between them (such as a failed
+pre-install check) lands on the normal terminal.
+*/
+
+use std::{
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use eyre::WrapErr;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Wrap},
+};
+
+use crate::{
+    BuiltinPlanner, InstallPlan, NixInstallerError,
+    action::{ActionDescription, ActionEstimate},
+    cli::arg::pause_stderr,
+    locale::translate,
+    plan::CancelSignal,
+    progress::ProgressReporter,
+    settings::{CommonSettings, MaintenanceSchedule, ShellProfileScope},
+};
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const TICK: Duration = Duration::from_millis(100);
+
+/// Take over the terminal (in the alternate screen and raw mode), keeping logs off it
+fn enter() -> std::io::Result<DefaultTerminal> {
+    pause_stderr(true);
+    ratatui::try_init().inspect_err(|_| pause_stderr(false))
+}
+
+/// Give the terminal back, see [`enter`]
+fn leave() {
+    ratatui::restore();
+    pause_stderr(false);
+}
+
+/// A terminal taken over with [`enter`], given back when dropped
+struct Session(DefaultTerminal);
+
+impl Session {
+    fn start() -> eyre::Result<Self> {
+        enter()
+            .map(Self)
+            .wrap_err("Starting the terminal interface")
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        leave();
+    }
+}
+
+/// Let the user change the planner's options, `None` if they quit instead
+pub(super) fn choose_options(mut planner: BuiltinPlanner) -> eyre::Result<Option<BuiltinPlanner>> {
+    let mut session = Session::start()?;
+    let mut selected = 0;
+
+    loop {
+        session.0.draw(|frame| {
+            draw_options(
+                frame,
+                planner.typetag_name(),
+                planner.common_settings(),
+                selected,
+            )
+        })?;
+
+        let Some(key) = read_key()? else {
+            continue;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                selected = (selected + 1).min(Choice::ALL.len() - 1)
+            },
+            KeyCode::Right | KeyCode::Char(' ') | KeyCode::Char('l') => {
+                Choice::ALL[selected].change(planner.common_settings_mut(), true)
+            },
+            KeyCode::Left | KeyCode::Char('h') => {
+                Choice::ALL[selected].change(planner.common_settings_mut(), false)
+            },
+            KeyCode::Enter => return Ok(Some(planner)),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(None);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Show the plan, expanding the explanation of any step, and whether the user chose to install it
+pub(super) fn review(install_plan: &InstallPlan) -> eyre::Result<bool> {
+    let steps: Vec<ActionDescription> = install_plan
+        .actions
+        .iter()
+        .flat_map(|action| action.describe_execute())
+        .collect();
+    let estimate: ActionEstimate = install_plan
+        .actions
+        .iter()
+        .map(|action| action.estimate_execute())
+        .sum();
+    let mut expanded = vec![false; steps.len()];
+    let mut list_state = ListState::default().with_selected(Some(0));
+
+    let mut session = Session::start()?;
+    loop {
+        session.0.draw(|frame| {
+            draw_review(
+                frame,
+                install_plan.planner.typetag_name(),
+                &steps,
+                &expanded,
+                &estimate,
+                &mut list_state,
+            )
+        })?;
+
+        let Some(key) = read_key()? else {
+            continue;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => list_state.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => list_state.select_next(),
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                if let Some(expanded) = list_state
+                    .selected()
+                    .and_then(|selected| expanded.get_mut(selected))
+                {
+                    *expanded = !*expanded;
+                }
+            },
+            KeyCode::Char('e') => {
+                let expand = expanded.iter().any(|expanded| !expanded);
+                expanded.iter_mut().for_each(|expanded| *expanded = expand);
+            },
+            KeyCode::Char('y') | KeyCode::Char('i') => return Ok(true),
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('n') => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// The next key pressed, if the next event is one
+fn read_key() -> eyre::Result<Option<KeyEvent>> {
+    match event::read().wrap_err("Reading from the terminal")? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => Ok(Some(key)),
+        _ => Ok(None),
+    }
+}
+
+/// A setting of the planner's [`CommonSettings`] which the wizard can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    ModifyProfile,
+    ShellProfileScope,
+    BuildUserCount,
+    AddChannel,
+    AutoGc,
+    AutoOptimise,
+}
+
+impl Choice {
+    const ALL: [Choice; 6] = [
+        Choice::ModifyProfile,
+        Choice::ShellProfileScope,
+        Choice::BuildUserCount,
+        Choice::AddChannel,
+        Choice::AutoGc,
+        Choice::AutoOptimise,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Choice::ModifyProfile => "Load Nix in shell profiles",
+            Choice::ShellProfileScope => "Shell profiles to modify",
+            Choice::BuildUserCount => "Build users",
+            Choice::AddChannel => "Add a `nixpkgs` channel",
+            Choice::AutoGc => "Collect garbage",
+            Choice::AutoOptimise => "Optimise the store",
+        }
+    }
+
+    fn explanation(self) -> &'static str {
+        match self {
+            Choice::ModifyProfile => {
+                "Add Nix to the `PATH` of new shells, by sourcing Nix's profile script from the shell profiles (`--no-modify-profile` turns this off)."
+            },
+            Choice::ShellProfileScope => {
+                "Which shell profiles are modified: the `system`-wide ones (such as `/etc/bashrc`), those of the `user` running `sudo` (such as `~/.bashrc`), or `both` (`--shell-profile-scope`)."
+            },
+            Choice::BuildUserCount => {
+                "How many build users the Nix daemon runs builds as, which bounds how many builds run at once (`--nix-build-user-count`)."
+            },
+            Choice::AddChannel => {
+                "Subscribe `root` to the `nixpkgs-unstable` channel, for tools which still use `<nixpkgs>` (`--add-channel`)."
+            },
+            Choice::AutoGc => {
+                "Collect garbage older than 30 days on a schedule, with a systemd timer or `launchd` job (`--enable-auto-gc`)."
+            },
+            Choice::AutoOptimise => {
+                "Hard link identical files in the Nix store on a schedule, with a systemd timer or `launchd` job (`--enable-auto-optimise`)."
+            },
+        }
+    }
+
+    fn value(self, settings: &CommonSettings) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+        let schedule = |schedule: Option<MaintenanceSchedule>| match schedule {
+            Some(schedule) => schedule.to_string(),
+            None => "never".to_string(),
+        };
+        match self {
+            Choice::ModifyProfile => yes_no(settings.modify_profile),
+            Choice::ShellProfileScope => match settings.shell_profile_scope {
+                ShellProfileScope::System => "system".to_string(),
+                ShellProfileScope::User => "user".to_string(),
+                ShellProfileScope::Both => "both".to_string(),
+            },
+            Choice::BuildUserCount => settings.nix_build_user_count.to_string(),
+            Choice::AddChannel => yes_no(settings.add_channel),
+            Choice::AutoGc => schedule(settings.enable_auto_gc),
+            Choice::AutoOptimise => schedule(settings.enable_auto_optimise),
+        }
+    }
+
+    /// Step the setting to its next value, or its previous one unless `forward`
+    fn change(self, settings: &mut CommonSettings, forward: bool) {
+        match self {
+            Choice::ModifyProfile => settings.modify_profile = !settings.modify_profile,
+            Choice::ShellProfileScope => {
+                settings.shell_profile_scope = cycle(
+                    &[
+                        ShellProfileScope::System,
+                        ShellProfileScope::User,
+                        ShellProfileScope::Both,
+                    ],
+                    settings.shell_profile_scope,
+                    forward,
+                )
+            },
+            Choice::BuildUserCount => {
+                settings.nix_build_user_count = match forward {
+                    true => settings.nix_build_user_count.saturating_add(1),
+                    false => settings.nix_build_user_count.saturating_sub(1).max(1),
+                }
+            },
+            Choice::AddChannel => settings.add_channel = !settings.add_channel,
+            Choice::AutoGc => {
+                settings.enable_auto_gc = cycle(&SCHEDULES, settings.enable_auto_gc, forward)
+            },
+            Choice::AutoOptimise => {
+                settings.enable_auto_optimise =
+                    cycle(&SCHEDULES, settings.enable_auto_optimise, forward)
+            },
+        }
+    }
+}
+
+const SCHEDULES: [Option<MaintenanceSchedule>; 4] = [
+    None,
+    Some(MaintenanceSchedule::Daily),
+    Some(MaintenanceSchedule::Weekly),
+    Some(MaintenanceSchedule::Monthly),
+];
+
+/// The value after (or before, unless `forward`) `current` in `values`, wrapping around
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, forward: bool) -> T {
+    let index = values
+        .iter()
+        .position(|value| *value == current)
+        .unwrap_or_default();
+    let next = match forward {
+        true => (index + 1) % values.len(),
+        false => (index + values.len() - 1) % values.len(),
+    };
+    values[next]
+}
+
+fn draw_options(frame: &mut Frame, planner: &str, settings: &CommonSettings, selected: usize) {
+    let [header, body, explanation, footer] = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Min(Choice::ALL.len() as u16 + 2),
+        Constraint::Length(4),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            "Install Nix".bold(),
+            format!(" with the `{planner}` planner").into(),
+        ])),
+        header,
+    );
+
+    let items = Choice::ALL.iter().map(|choice| {
+        ListItem::new(Line::from(vec![
+            format!("{:<28}", choice.label()).into(),
+            choice.value(settings).cyan(),
+        ]))
+    });
+    let mut list_state = ListState::default().with_selected(Some(selected));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title(" Options "))
+            .highlight_style(Style::new().reversed())
+            .highlight_symbol("> "),
+        body,
+        &mut list_state,
+    );
+
+    frame.render_widget(
+        Paragraph::new(Choice::ALL[selected].explanation())
+            .wrap(Wrap { trim: true })
+            .dim(),
+        explanation,
+    );
+    draw_keys(
+        frame,
+        footer,
+        &[
+            ("↑/↓", "select"),
+            ("←/→", "change"),
+            ("enter", "review the plan"),
+            ("q", "quit"),
+        ],
+    );
+}
+
+fn draw_review(
+    frame: &mut Frame,
+    planner: &str,
+    steps: &[ActionDescription],
+    expanded: &[bool],
+    estimate: &ActionEstimate,
+    list_state: &mut ListState,
+) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let mut title = vec![
+        "Nix install plan".bold(),
+        format!(" from the `{planner}` planner, {} steps", steps.len()).into(),
+    ];
+    if !estimate.is_empty() {
+        title.push(format!(" (estimated cost: {estimate})").dim());
+    }
+    frame.render_widget(Paragraph::new(Line::from(title)), header);
+
+    let items = steps.iter().zip(expanded).map(|(step, expanded)| {
+        let marker = if *expanded { "▾ " } else { "▸ " };
+        let mut lines = vec![Line::from(vec![
+            marker.dim(),
+            step.description.clone().into(),
+        ])];
+        if *expanded {
+            lines.extend(
+                step.explanation
+                    .iter()
+                    .map(|line| Line::from(format!("    {line}")).dim()),
+            );
+        }
+        ListItem::new(Text::from(lines))
+    });
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title(" Planned actions "))
+            .highlight_style(Style::new().bold()),
+        body,
+        list_state,
+    );
+
+    draw_keys(
+        frame,
+        footer,
+        &[
+            ("↑/↓", "select"),
+            ("enter", "explain"),
+            ("e", "explain all"),
+            ("y", "install"),
+            ("q", "quit"),
+        ],
+    );
+}
+
+fn draw_keys(frame: &mut Frame, area: Rect, keys: &[(&str, &str)]) {
+    let mut spans = vec![];
+    for (key, action) in keys {
+        spans.push(Span::from(*key).bold());
+        spans.push(format!(" {action}  ").into());
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+/// Draws each step of the plan as it executes, then a summary once [`finish`][WizardProgress::finish]ed
+///
+/// Raw mode stops `Ctrl+C` raising `SIGINT`, so pressing it (or `q`) sets the cancel signal instead.
+pub(super) struct WizardProgress {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    terminal: Option<DefaultTerminal>,
+    planner: &'static str,
+    steps: Vec<Step>,
+    /// The bytes the running step has processed, of its total
+    bytes: Option<(u64, u64)>,
+    tick: usize,
+    suspended: bool,
+    cancelling: bool,
+    outcome: Option<Result<(), String>>,
+}
+
+struct Step {
+    synopsis: String,
+    status: StepStatus,
+}
+
+#[derive(Clone, Copy)]
+enum StepStatus {
+    Pending,
+    Running(Instant),
+    Done(Duration),
+    Failed(Duration),
+}
+
+impl std::fmt::Debug for WizardProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WizardProgress").finish_non_exhaustive()
+    }
+}
+
+impl WizardProgress {
+    pub(super) fn new(
+        install_plan: &InstallPlan,
+        cancel_signal: CancelSignal,
+    ) -> eyre::Result<Self> {
+        let terminal = enter().wrap_err("Starting the terminal interface")?;
+        let state = Arc::new(Mutex::new(State {
+            terminal: Some(terminal),
+            planner: install_plan.planner.typetag_name(),
+            steps: install_plan
+                .actions
+                .iter()
+                .map(|action| Step {
+                    synopsis: translate(&action.tracing_synopsis()).into_owned(),
+                    status: StepStatus::Pending,
+                })
+                .collect(),
+            bytes: None,
+            tick: 0,
+            suspended: false,
+            cancelling: false,
+            outcome: None,
+        }));
+        let weak = Arc::downgrade(&state);
+        std::thread::spawn(move || tick(weak, cancel_signal));
+        Ok(Self { state })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Summarize how the install went, then wait for a key and restore the terminal
+    pub(super) fn finish(&self, result: Result<(), &NixInstallerError>) -> eyre::Result<()> {
+        {
+            let mut state = self.lock();
+            state.outcome = Some(result.map_err(|err| err.to_string()));
+            draw(&mut state);
+        }
+        while read_key()?.is_none() {}
+        if self.lock().terminal.take().is_some() {
+            leave();
+        }
+        Ok(())
+    }
+}
+
+impl ProgressReporter for WizardProgress {
+    fn step_started(&self, step: usize, total: usize, synopsis: &str) {
+        let mut state = self.lock();
+        let len = total.max(state.steps.len());
+        state.steps.resize_with(len, || Step {
+            synopsis: String::new(),
+            status: StepStatus::Pending,
+        });
+        state.steps[step - 1] = Step {
+            synopsis: synopsis.to_string(),
+            status: StepStatus::Running(Instant::now()),
+        };
+        state.bytes = None;
+        draw(&mut state);
+    }
+
+    fn step_bytes(&self, done: u64, total: u64) {
+        self.lock().bytes = Some((done, total));
+    }
+
+    fn step_finished(
+        &self,
+        step: usize,
+        _total: usize,
+        _synopsis: &str,
+        elapsed: Duration,
+        success: bool,
+    ) {
+        let mut state = self.lock();
+        if let Some(finished) = state.steps.get_mut(step - 1) {
+            finished.status = match success {
+                true => StepStatus::Done(elapsed),
+                false => StepStatus::Failed(elapsed),
+            };
+        }
+        state.bytes = None;
+        draw(&mut state);
+    }
+
+    fn suspend(&self) {
+        let mut state = self.lock();
+        state.suspended = true;
+        if state.terminal.take().is_some() {
+            leave();
+        }
+    }
+
+    fn resume(&self) {
+        let mut state = self.lock();
+        state.suspended = false;
+        match enter() {
+            Ok(terminal) => state.terminal = Some(terminal),
+            Err(err) => tracing::warn!("Could not restart the terminal interface: {err}"),
+        }
+        draw(&mut state);
+    }
+}
+
+impl Drop for WizardProgress {
+    fn drop(&mut self) {
+        if self.lock().terminal.take().is_some() {
+            leave();
+        }
+    }
+}
+
+fn tick(weak: Weak<Mutex<State>>, cancel_signal: Arc<AtomicBool>) {
+    loop {
+        let listening = {
+            let Some(state) = weak.upgrade() else {
+                return;
+            };
+            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+            // Prompts read the terminal while suspended, and the summary waits for a key itself
+            !state.suspended && state.outcome.is_none()
+        };
+
+        let key = match listening {
+            true => match event::poll(TICK) {
+                Ok(true) => read_key().ok().flatten(),
+                Ok(false) => None,
+                Err(_) => None,
+            },
+            false => {
+                std::thread::sleep(TICK);
+                None
+            },
+        };
+
+        let Some(state) = weak.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = key {
+            let cancel = match key.code {
+                KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+                KeyCode::Char('q') => true,
+                _ => false,
+            };
+            if cancel {
+                tracing::warn!("Cancelling the installation");
+                cancel_signal.store(true, Ordering::Relaxed);
+                state.cancelling = true;
+            }
+        }
+        state.tick = state.tick.wrapping_add(1);
+        draw(&mut state);
+    }
+}
+
+fn draw(state: &mut State) {
+    if state.suspended {
+        return;
+    }
+    let State {
+        terminal,
+        planner,
+        steps,
+        bytes,
+        tick,
+        cancelling,
+        outcome,
+        ..
+    } = state;
+    let Some(terminal) = terminal else {
+        return;
+    };
+    let _ = terminal.draw(|frame| {
+        let [header, gauge, body, footer] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let title = match outcome {
+            None => Line::from(vec![
+                "Installing Nix".bold(),
+                format!(" with the `{planner}` planner").into(),
+            ]),
+            Some(Ok(())) => Line::from("Nix was installed successfully!".green().bold()),
+            Some(Err(_)) => Line::from("The installation failed".red().bold()),
+        };
+        frame.render_widget(Paragraph::new(title), header);
+
+        let finished = steps
+            .iter()
+            .filter(|step| matches!(step.status, StepStatus::Done(_)))
+            .count();
+        let (ratio, label) = match bytes {
+            Some((done, total)) if *total > 0 && outcome.is_none() => {
+                let ratio = (*done as f64 / *total as f64).clamp(0.0, 1.0);
+                (ratio, format!("{:.0}% of this step", ratio * 100.0))
+            },
+            _ => (
+                finished as f64 / steps.len().max(1) as f64,
+                format!("{finished}/{} steps", steps.len()),
+            ),
+        };
+        frame.render_widget(
+            Gauge::default()
+                .ratio(ratio)
+                .label(label)
+                .gauge_style(Style::new().green()),
+            gauge,
+        );
+
+        let current = steps
+            .iter()
+            .rposition(|step| !matches!(step.status, StepStatus::Pending));
+        let spinner = SPINNER[*tick % SPINNER.len()];
+        let items = steps.iter().map(|step| {
+            let line = match step.status {
+                StepStatus::Pending => Line::from(format!("  {}", step.synopsis)).dim(),
+                StepStatus::Running(started) => Line::from(vec![
+                    format!("{spinner} ").cyan(),
+                    step.synopsis.clone().into(),
+                    format!(" ({})", format_duration(started.elapsed())).dim(),
+                ]),
+                StepStatus::Done(elapsed) => Line::from(vec![
+                    "✓ ".green(),
+                    step.synopsis.clone().into(),
+                    format!(" ({})", format_duration(elapsed)).dim(),
+                ]),
+                StepStatus::Failed(elapsed) => Line::from(vec![
+                    "✗ ".red(),
+                    step.synopsis.clone().red(),
+                    format!(" ({})", format_duration(elapsed)).dim(),
+                ]),
+            };
+            ListItem::new(line)
+        });
+        let mut list_state = ListState::default().with_selected(current);
+        frame.render_stateful_widget(
+            List::new(items).block(Block::bordered().title(" Steps ")),
+            body,
+            &mut list_state,
+        );
+
+        let status = match outcome {
+            Some(Ok(())) => {
+                let total: Duration = steps
+                    .iter()
+                    .filter_map(|step| match step.status {
+                        StepStatus::Done(elapsed) | StepStatus::Failed(elapsed) => Some(elapsed),
+                        _ => None,
+                    })
+                    .sum();
+                Line::from(format!(
+                    "Took {}, press any key to continue",
+                    format_duration(total)
+                ))
+            },
+            Some(Err(err)) => Line::from(vec![
+                err.lines().next().unwrap_or_default().to_string().red(),
+                ", press any key to continue".into(),
+            ]),
+            None if *cancelling => Line::from("Cancelling after this step...".yellow()),
+            None => Line::from(vec!["q".bold(), " cancel".into()]),
+        };
+        frame.render_widget(Paragraph::new(status), footer);
+    });
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{secs:.1}s")
+    } else {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn choices_cycle_through_values() {
+        let mut settings = CommonSettings::try_default().unwrap();
+        settings.enable_auto_gc = None;
+
+        Choice::AutoGc.change(&mut settings, false);
+        assert_eq!(settings.enable_auto_gc, Some(MaintenanceSchedule::Monthly));
+        Choice::AutoGc.change(&mut settings, true);
+        assert_eq!(settings.enable_auto_gc, None);
+
+        settings.nix_build_user_count = 1;
+        Choice::BuildUserCount.change(&mut settings, false);
+        assert_eq!(settings.nix_build_user_count, 1);
+        Choice::BuildUserCount.change(&mut settings, true);
+        assert_eq!(Choice::BuildUserCount.value(&settings), "2");
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{CommandFactory, Parser};
+use eyre::WrapErr;
+
+use crate::cli::{CommandExecute, NixInstallerCli};
+
+/// Write man pages for `nix-installer` and each of its subcommands, for packagers to install
+#[derive(Debug, Parser)]
+pub struct Manpages {
+    /// The directory to write the pages into, as `nix-installer.1`, `nix-installer-install.1`, and so on
+    #[clap(long, value_name = "DIR")]
+    pub out_dir: PathBuf,
+}
+
+impl CommandExecute for Manpages {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { out_dir } = self;
+
+        std::fs::create_dir_all(&out_dir)
+            .wrap_err_with(|| format!("Creating `{}`", out_dir.display()))?;
+        clap_mangen::generate_to(NixInstallerCli::command(), &out_dir)
+            .wrap_err_with(|| format!("Writing man pages into `{}`", out_dir.display()))?;
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -1,15 +1,31 @@
+mod adopt;
+mod bug_report;
+mod completions;
+pub(crate) mod info;
 mod install;
+mod manpages;
 mod plan;
-mod repair;
+mod reconfigure;
+mod remount;
+pub(crate) mod repair;
 mod self_test;
 mod split_receipt;
+pub(crate) mod status;
 mod uninstall;
 
+use adopt::Adopt;
+use bug_report::BugReport;
+use completions::Completions;
+use info::Info;
 use install::Install;
+use manpages::Manpages;
 use plan::Plan;
+use reconfigure::Reconfigure;
+use remount::Remount;
 use repair::Repair;
 use self_test::SelfTest;
 use split_receipt::SplitReceipt;
+use status::Status;
 use uninstall::Uninstall;
 
 #[allow(clippy::large_enum_variant)]
@@ -21,4 +37,12 @@ pub enum NixInstallerSubcommand {
     SelfTest(SelfTest),
     Plan(Plan),
     SplitReceipt(SplitReceipt),
+    Adopt(Adopt),
+    Remount(Remount),
+    Info(Info),
+    Status(Status),
+    BugReport(BugReport),
+    Reconfigure(Reconfigure),
+    Completions(Completions),
+    Manpages(Manpages),
 }
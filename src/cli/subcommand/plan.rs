@@ -1,22 +1,31 @@
 use std::{path::PathBuf, process::ExitCode};
 
-use crate::{BuiltinPlanner, cli::ensure_root, error::HasExpectedErrors};
-use clap::Parser;
+use crate::style::Colorize;
+use crate::{
+    ActionChange, BuiltinPlanner, InstallPlan, PlanDiff,
+    cli::{ensure_root, error::report_failure},
+    error::HasExpectedErrors,
+    plan::find_receipt_at,
+};
+use clap::{ArgAction, Parser};
 
 use eyre::WrapErr;
-use owo_colors::OwoColorize;
 
 use crate::cli::CommandExecute;
 
 /**
 Emit a JSON install plan that can be manually edited before execution
 
-Primarily intended for development, debugging, and handling install cases.
+Plans can be executed with `nix-installer install <PLAN>`, and checked beforehand with
+`nix-installer plan validate <PLAN>`, or compared with the current install with
+`nix-installer plan diff`. String values in a plan may contain `${env:NAME}` or
+`${env:NAME:-default}`, which are substituted from the environment of the machine reading it.
 */
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Plan {
     #[clap(subcommand)]
-    pub planner: Option<BuiltinPlanner>,
+    pub subcommand: Option<PlanSubcommand>,
     /// Where to write the generated plan (in JSON format)
     #[clap(
         long = "out-file",
@@ -26,10 +35,26 @@ pub struct Plan {
     pub output: PathBuf,
 }
 
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, clap::Subcommand)]
+pub enum PlanSubcommand {
+    Validate(Validate),
+    Diff(Diff),
+    #[command(flatten)]
+    Planner(BuiltinPlanner),
+}
+
 impl CommandExecute for Plan {
     #[tracing::instrument(level = "debug", skip_all, fields())]
     fn execute(self) -> eyre::Result<ExitCode> {
-        let Self { planner, output } = self;
+        let Self { subcommand, output } = self;
+
+        let planner = match subcommand {
+            Some(PlanSubcommand::Validate(validate)) => return validate.execute(),
+            Some(PlanSubcommand::Diff(diff)) => return diff.execute(),
+            Some(PlanSubcommand::Planner(planner)) => Some(planner),
+            None => None,
+        };
 
         ensure_root()?;
 
@@ -44,8 +69,7 @@ impl CommandExecute for Plan {
             Ok(plan) => plan,
             Err(err) => {
                 if let Some(expected) = err.expected() {
-                    eprintln!("{}", expected.red());
-                    return Ok(ExitCode::FAILURE);
+                    return Ok(report_failure(&*expected, err.error_code()));
                 }
                 return Err(err)?;
             },
@@ -57,3 +81,146 @@ impl CommandExecute for Plan {
         Ok(ExitCode::SUCCESS)
     }
 }
+
+/**
+Check that a plan can be read and executed by this version of `nix-installer`, without changing the system
+
+The plan is parsed with `${env:...}` templates substituted from the current environment, and the
+changes it would make are described.
+*/
+#[derive(Debug, Parser)]
+pub struct Validate {
+    /// The plan to validate
+    pub plan: PathBuf,
+    /// Fail if the plan has fields this version of `nix-installer` does not know, instead of ignoring them
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub strict: bool,
+    /// Describe every setting of the plan, not only configured ones
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub explain: bool,
+}
+
+impl CommandExecute for Validate {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            plan,
+            strict,
+            explain,
+        } = self;
+
+        let install_plan = match InstallPlan::from_plan_file(&plan, strict) {
+            Ok(install_plan) => install_plan,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    return Ok(report_failure(&*expected, err.error_code()));
+                }
+                return Err(err)?;
+            },
+        };
+
+        println!("{}", install_plan.describe_install(explain)?);
+        eprintln!("{}", format!("Plan `{}` is valid", plan.display()).green());
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/**
+Compare the plan from the given settings with the receipt of the current install
+
+Lists the actions which would be added (`+`), removed (`-`), or changed (`~`), like
+`terraform plan`. Nothing is changed, to apply the plan uninstall and install again, or use
+`nix-installer reconfigure` for the settings it supports. Exits with 2 if there are differences.
+*/
+#[derive(Debug, Parser)]
+pub struct Diff {
+    /// The receipt to compare with, instead of the current install's
+    #[clap(long)]
+    pub receipt: Option<PathBuf>,
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub planner: Option<BuiltinPlanner>,
+}
+
+impl CommandExecute for Diff {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            receipt,
+            receipt_path,
+            planner,
+        } = self;
+
+        ensure_root()?;
+
+        let Some(receipt) = receipt.or_else(|| find_receipt_at(receipt_path.as_deref(), None))
+        else {
+            eprintln!("{}", "No install receipt found, is Nix installed?".red());
+            return Ok(ExitCode::FAILURE);
+        };
+        let installed = InstallPlan::from_plan_file(&receipt, false)?;
+
+        let planner = match planner {
+            Some(planner) => planner,
+            None => BuiltinPlanner::try_default()?,
+        };
+        let proposed = match planner.plan() {
+            Ok(plan) => plan,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    return Ok(report_failure(&*expected, err.error_code()));
+                }
+                return Err(err)?;
+            },
+        };
+
+        let diff = installed.diff(&proposed)?;
+        if diff.is_empty() {
+            println!("{}", "No differences from the installed plan".green());
+            return Ok(ExitCode::SUCCESS);
+        }
+        print_diff(&diff);
+
+        Ok(ExitCode::from(2))
+    }
+}
+
+fn print_diff(diff: &PlanDiff) {
+    if let Some((installed, proposed)) = &diff.planner {
+        println!("{} planner: {installed} -> {proposed}", "~".yellow());
+    }
+    if !diff.settings.is_empty() {
+        println!("Settings:");
+        let show = |value: &Option<serde_json::Value>| match value {
+            Some(value) => value.to_string(),
+            None => "(unset)".to_string(),
+        };
+        for change in &diff.settings {
+            println!(
+                "  {} {}: {} -> {}",
+                "~".yellow(),
+                change.name.bold(),
+                show(&change.installed),
+                show(&change.proposed)
+            );
+        }
+    }
+    if !diff.actions.is_empty() {
+        println!("Actions:");
+        for change in &diff.actions {
+            match change {
+                ActionChange::Added(synopsis) => println!("  {} {synopsis}", "+".green()),
+                ActionChange::Removed(synopsis) => println!("  {} {synopsis}", "-".red()),
+                ActionChange::Changed(synopsis, fields) => {
+                    println!("  {} {synopsis}", "~".yellow());
+                    for field in fields {
+                        println!("      {}", field.dimmed());
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::{WrapErr, eyre};
+use nix_config_parser::NixConfig;
+
+use crate::action::ActionState;
+use crate::action::base::{AddUserToGroup, CreateUser};
+use crate::action::common::CreateUsersAndGroups;
+use crate::action::common::place_nix_configuration::{
+    CUSTOM_NIX_CONF, INSTALLER_NIX_CONF, NIX_CONF,
+};
+use crate::action::linux::CreateSysusersBuildUsers;
+use crate::cli::interaction::{self, PromptChoice};
+use crate::cli::subcommand::repair::{find_action_in_receipt, get_existing_receipt};
+use crate::cli::{CommandExecute, back_up_receipt, ensure_root};
+use crate::planner::Planner;
+use crate::style::Colorize;
+
+/**
+Change the settings of an existing install, without reinstalling
+
+The install receipt is updated to match, so uninstalling undoes the changes too.
+*/
+#[derive(Debug, Parser)]
+pub struct Reconfigure {
+    /// Run without requiring explicit user confirmation
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub no_confirm: bool,
+
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+
+    /// The number of build users to have, creating or deleting them as needed
+    #[clap(long, alias = "daemon-user-count", value_name = "COUNT")]
+    pub nix_build_user_count: u32,
+}
+
+impl CommandExecute for Reconfigure {
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            state_dir,
+            receipt_path,
+            nix_build_user_count,
+        } = self;
+
+        ensure_root()?;
+
+        if nix_build_user_count == 0 {
+            return Err(eyre!(
+                "At least one build user is needed, reinstall with `--nix-build-user-count 0` to build as `root` instead"
+            ));
+        }
+
+        let Some(mut receipt) = get_existing_receipt(receipt_path.as_deref(), state_dir.as_deref())
+        else {
+            return Err(eyre!(
+                "`reconfigure` needs the install receipt, which could not be found or parsed by this version of the installer"
+            ));
+        };
+        let Some((idx, mut create_users_and_groups)) =
+            find_action_in_receipt::<CreateUsersAndGroups>(&receipt)?
+        else {
+            if find_action_in_receipt::<CreateSysusersBuildUsers>(&receipt)?.is_some() {
+                return Err(eyre!(
+                    "The build users of this install are created by `systemd-sysusers`, changing their number isn't supported yet"
+                ));
+            }
+            return Err(eyre!("The receipt does not record any build users"));
+        };
+
+        let current_count = create_users_and_groups.nix_build_user_count;
+        if current_count == nix_build_user_count {
+            println!("There are already {current_count} build users, nothing to do");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if !no_confirm {
+            let summary = if nix_build_user_count > current_count {
+                format!(
+                    "Will create {} more build users, for {nix_build_user_count} in total, and update the receipt",
+                    nix_build_user_count - current_count
+                )
+            } else {
+                format!(
+                    "Will delete the last {} build users, leaving {nix_build_user_count}, and update the receipt",
+                    current_count - nix_build_user_count
+                )
+            };
+            loop {
+                match interaction::prompt(&summary, PromptChoice::Yes, true)? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::No => interaction::clean_exit_with_message(
+                        "Okay, not reconfiguring anything. Bye!",
+                    ),
+                    PromptChoice::Explain => (),
+                }
+            }
+        }
+
+        let CreateUsersAndGroups {
+            nix_build_group_name,
+            nix_build_group_id,
+            nix_build_user_prefix,
+            nix_build_user_id_base,
+            create_users,
+            add_users_to_groups,
+            ..
+        } = &mut create_users_and_groups;
+
+        if nix_build_user_count > current_count {
+            for index in (current_count + 1)..=nix_build_user_count {
+                let name = format!("{nix_build_user_prefix}{index}");
+                let uid = *nix_build_user_id_base + index;
+                let mut create_user = CreateUser::plan(
+                    name.clone(),
+                    uid,
+                    nix_build_group_name.clone(),
+                    *nix_build_group_id,
+                    format!("Nix build user {index}"),
+                    true,
+                )?;
+                let mut add_user_to_group = AddUserToGroup::plan(
+                    name,
+                    uid,
+                    nix_build_group_name.clone(),
+                    *nix_build_group_id,
+                )?;
+                create_user.try_execute()?;
+                add_user_to_group.try_execute()?;
+                create_user.state = ActionState::Completed;
+                add_user_to_group.state = ActionState::Completed;
+                create_users.push(create_user);
+                add_users_to_groups.push(add_user_to_group);
+            }
+        } else {
+            tracing::warn!(
+                "Builds running as the deleted users will fail, consider stopping the Nix daemon first"
+            );
+            // The receipt may list users in any order, so delete by name
+            let removed = ((nix_build_user_count + 1)..=current_count)
+                .map(|index| format!("{nix_build_user_prefix}{index}"))
+                .collect::<Vec<_>>();
+            for add_user_to_group in add_users_to_groups
+                .iter_mut()
+                .filter(|action| removed.contains(&action.action.name))
+            {
+                add_user_to_group.try_revert()?;
+            }
+            for create_user in create_users
+                .iter_mut()
+                .filter(|action| removed.contains(&action.action.name))
+            {
+                create_user.try_revert()?;
+            }
+            add_users_to_groups.retain(|action| !removed.contains(&action.action.name));
+            create_users.retain(|action| !removed.contains(&action.action.name));
+        }
+        create_users_and_groups.nix_build_user_count = nix_build_user_count;
+
+        warn_about_max_jobs(nix_build_user_count);
+
+        receipt.actions[idx].action = Box::new(create_users_and_groups);
+        let mut planner =
+            serde_json::to_value(&receipt.planner).wrap_err("Serializing the receipt's planner")?;
+        if let Some(settings) = planner
+            .get_mut("settings")
+            .and_then(|settings| settings.as_object_mut())
+        {
+            settings.insert(
+                "nix_build_user_count".to_string(),
+                nix_build_user_count.into(),
+            );
+        }
+        receipt.planner = serde_json::from_value::<Box<dyn Planner>>(planner)
+            .wrap_err("Updating the receipt's planner")?;
+
+        back_up_receipt(&receipt, "reconfigure")?;
+        receipt.write_receipt()?;
+
+        println!(
+            "{}",
+            format!("There are now {nix_build_user_count} build users").green()
+        );
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Nix needs a build user per concurrent build, so a `max-jobs` above the count leaves builds waiting
+fn warn_about_max_jobs(nix_build_user_count: u32) {
+    // `nix.custom.conf` is included at the end of `nix.conf`, so its setting wins, and the
+    // installer's own settings are overridden by both
+    let max_jobs = [INSTALLER_NIX_CONF, NIX_CONF, CUSTOM_NIX_CONF]
+        .into_iter()
+        .filter_map(|path| NixConfig::parse_file(Path::new(path)).ok())
+        .filter_map(|nix_config| nix_config.settings().get("max-jobs").cloned())
+        .next_back()
+        .and_then(|max_jobs| max_jobs.parse::<u32>().ok());
+    if let Some(max_jobs) = max_jobs.filter(|max_jobs| *max_jobs > nix_build_user_count) {
+        tracing::warn!(
+            "`max-jobs` is {max_jobs} in `{NIX_CONF}`, but there are only {nix_build_user_count} build users to run them"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::Parser;
+use target_lexicon::OperatingSystem;
+
+use crate::style::Colorize;
+use crate::{
+    ErrorCode, InstallPlan,
+    action::{
+        ActionState,
+        macos::{DEFAULT_MOUNT_TIMEOUT_SECS, MountNixVolume},
+    },
+    cli::{CommandExecute, ensure_root, error::report_failure},
+    error::HasExpectedErrors,
+    plan::find_receipt_at,
+};
+
+const DEFAULT_VOLUME_LABEL: &str = "Nix Store";
+
+/**
+Mount the Nix Store volume, if it failed to mount at boot (macOS only)
+
+An encrypted volume is unlocked with the password stored in the system keychain.
+*/
+#[derive(Debug, Parser)]
+pub struct Remount {
+    /// The label of the Nix Store APFS volume (default: from the install receipt, or `Nix Store`)
+    #[clap(long, env = "NIX_INSTALLER_VOLUME_LABEL")]
+    pub volume_label: Option<String>,
+
+    /// Seconds to wait for the volume to mount, before kickstarting its mount daemon and waiting as long again
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MOUNT_TIMEOUT_SECS,
+        env = "NIX_INSTALLER_MOUNT_TIMEOUT",
+        value_name = "SECONDS"
+    )]
+    pub mount_timeout: u64,
+
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+}
+
+impl CommandExecute for Remount {
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            volume_label,
+            mount_timeout,
+            receipt_path,
+        } = self;
+
+        if !matches!(
+            OperatingSystem::host(),
+            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_)
+        ) {
+            eprintln!("{}", "`remount` is only needed on macOS".red());
+            return Ok(ExitCode::FAILURE);
+        }
+
+        ensure_root()?;
+
+        let volume_label = volume_label
+            .or_else(|| volume_label_from_receipt(receipt_path.as_deref()))
+            .unwrap_or_else(|| DEFAULT_VOLUME_LABEL.to_string());
+
+        let mut mount_nix_volume = match MountNixVolume::plan(&volume_label, "/nix", mount_timeout)
+        {
+            Ok(mount_nix_volume) => mount_nix_volume,
+            Err(err) => {
+                if let Some(expected) = err.kind().expected() {
+                    return Ok(report_failure(
+                        &*expected,
+                        err.kind().error_code().unwrap_or(ErrorCode::ActionFailed),
+                    ));
+                }
+                return Err(err)?;
+            },
+        };
+
+        if mount_nix_volume.state == ActionState::Completed {
+            println!("The `{volume_label}` volume is already mounted at `/nix`");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        tracing::info!("{}", mount_nix_volume.tracing_synopsis());
+        mount_nix_volume.try_execute()?;
+
+        println!(
+            "{}",
+            format!("Mounted the `{volume_label}` volume at `/nix`")
+                .green()
+                .bold()
+        );
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn volume_label_from_receipt(receipt_path: Option<&Path>) -> Option<String> {
+    let receipt = find_receipt_at(receipt_path, None)?;
+    let plan: InstallPlan = serde_json::from_str(&std::fs::read_to_string(receipt).ok()?).ok()?;
+    let settings = plan.planner.settings().ok()?;
+    settings
+        .get("volume_label")?
+        .as_str()
+        .map(ToString::to_string)
+}
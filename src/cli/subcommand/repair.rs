@@ -1,20 +1,36 @@
 use std::io::IsTerminal as _;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::time::SystemTime;
 
 use clap::{ArgAction, Parser, Subcommand};
 use eyre::Context as _;
+use nix::unistd::{Gid, Group};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::process::Command;
 use target_lexicon::OperatingSystem;
 
 use crate::action::base::{AddUserToGroup, CreateGroup, CreateUser};
-use crate::action::common::{ConfigureShellProfile, CreateUsersAndGroups};
+use crate::action::common::configure_upstream_init_service::DARWIN_NIX_DAEMON_DEST;
+use crate::action::common::create_nix_tree::ensure_per_user_ownership;
+use crate::action::common::provision_nix::ensure_nix_store_group;
+use crate::action::common::{
+    ConfigureShellProfile, ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionNix,
+};
+use crate::action::linux::ProvisionSelinux;
+use crate::action::linux::create_sysusers_build_users::CreateSysusersBuildUsers;
+use crate::action::linux::provision_selinux::SELINUX_POLICY_PP_CONTENT;
+use crate::action::macos::create_nix_volume::NIX_VOLUME_MOUNTD_NAME;
+use crate::action::macos::{
+    BootstrapLaunchctlService, ConfigureSyntheticConf, CreateNixVolume, CreateSyntheticObjects,
+    CreateVolumeService, NIX_VOLUME_MOUNTD_DEST,
+};
 use crate::action::{Action, ActionState, StatefulAction};
 use crate::cli::interaction::PromptChoice;
-use crate::cli::{CommandExecute, ensure_root};
-use crate::plan::RECEIPT_LOCATION;
+use crate::cli::{CommandExecute, back_up_receipt, ensure_root};
+use crate::plan::find_receipt_at;
 use crate::planner::{PlannerError, ShellProfileLocations};
+use crate::settings::{InitSystem, ShellProfileScope};
 use crate::{InstallPlan, execute_command};
 
 /// The base UID that we temporarily move build users to while migrating macOS to the new range.
@@ -37,6 +53,19 @@ pub struct Repair {
     )]
     pub no_confirm: bool,
 
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR", global = true)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(
+        long,
+        value_name = "PATH",
+        env = "NIX_INSTALLER_RECEIPT_PATH",
+        global = true
+    )]
+    pub receipt_path: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<RepairKind>,
 }
@@ -107,6 +136,27 @@ pub enum RepairKind {
         )]
         move_existing_users: bool,
     },
+    /// Recreate the build users and group recorded in the install receipt, such as after a macOS
+    /// update deleted them.
+    ///
+    /// Users which still exist must have the recorded UID and GID. On macOS, `/etc/synthetic.conf`,
+    /// the Nix Store volume's mount daemon and the Nix daemon's LaunchDaemon are restored too.
+    Users,
+    /// Re-own `/nix/store` to the build group recorded in the install receipt, such as after its
+    /// GID changed.
+    ///
+    /// Per-user profile and GC root directories which are owned by root are also given back to
+    /// their users.
+    StoreOwnership,
+    /// Reinstall the SELinux policy for Nix if this installer's is newer, and restore the
+    /// contexts of `/nix`, such as after a relabel or an OS upgrade left the store mislabeled.
+    Selinux,
+    /// Change the password of the encrypted Nix Store volume on macOS to a new random one, and
+    /// update the System keychain item which unlocks it at boot.
+    RotateVolumeKey,
+    /// Restore the `nix` entry in macOS's `/etc/synthetic.conf`, rewriting entries separated by
+    /// spaces (which macOS ignores) with tabs, and create `/nix` from it.
+    SyntheticConf,
 }
 
 impl Repair {
@@ -135,6 +185,8 @@ impl CommandExecute for Repair {
                 ..
             } => {
                 let maybe_users_and_groups_from_receipt = maybe_users_and_groups_from_receipt(
+                    self.receipt_path.as_deref(),
+                    self.state_dir.as_deref(),
                     nix_build_user_prefix,
                     nix_build_user_count,
                     nix_build_group_name,
@@ -155,6 +207,36 @@ impl CommandExecute for Repair {
                 );
                 (!self.no_confirm, brief_summary)
             },
+            RepairKind::Users => (
+                !self.no_confirm,
+                String::from(
+                    "Will recreate any of the build users and group recorded in the receipt which are missing",
+                ),
+            ),
+            RepairKind::StoreOwnership => (
+                !self.no_confirm,
+                String::from(
+                    "Will re-own `/nix/store` to the build group recorded in the receipt, and per-user profiles to their users",
+                ),
+            ),
+            RepairKind::Selinux => (
+                !self.no_confirm,
+                String::from(
+                    "Will update the SELinux policy for Nix if needed, and restore the contexts of `/nix`",
+                ),
+            ),
+            RepairKind::RotateVolumeKey => (
+                !self.no_confirm,
+                String::from(
+                    "Will change the password of the Nix Store volume, and the System keychain item holding it",
+                ),
+            ),
+            RepairKind::SyntheticConf => (
+                !self.no_confirm,
+                String::from(
+                    "Will fix the entries of `/etc/synthetic.conf`, ensuring it creates `/nix`",
+                ),
+            ),
         };
 
         if prompt_before_repairing {
@@ -178,9 +260,12 @@ impl CommandExecute for Repair {
         // TODO(cole-h): if we add another repair command, make this whole thing more generic
         let updated_receipt = match command.clone() {
             RepairKind::Hooks => {
-                let reconfigure = ConfigureShellProfile::plan(ShellProfileLocations::default())
-                    .map_err(PlannerError::Action)?
-                    .boxed();
+                let reconfigure = ConfigureShellProfile::plan(
+                    ShellProfileLocations::default(),
+                    ShellProfileScope::System,
+                )
+                .map_err(PlannerError::Action)?
+                .boxed();
                 repair_actions.push(reconfigure);
 
                 match OperatingSystem::host() {
@@ -222,6 +307,8 @@ impl CommandExecute for Repair {
                 let user_base = crate::settings::default_nix_build_user_id_base();
 
                 let maybe_users_and_groups_from_receipt = maybe_users_and_groups_from_receipt(
+                    self.receipt_path.as_deref(),
+                    self.state_dir.as_deref(),
                     &nix_build_user_prefix,
                     nix_build_user_count,
                     &nix_build_group_name,
@@ -237,7 +324,7 @@ impl CommandExecute for Repair {
                 if receipt_action_idx_create_group.is_none() {
                     tracing::warn!(
                         "Unable to find {} in receipt (receipt didn't exist or is unable to be \
-                        parsed by this version of the installer). Your receipt at {RECEIPT_LOCATION} \
+                        parsed by this version of the installer). Your receipt \
                         will not reflect the changed UIDs, but the users will still be relocated \
                         to the new Sequoia-compatible UID range, starting at {user_base}, and \
                         uninstallation will continue to work as normal, even if the UIDs do not match.",
@@ -400,6 +487,158 @@ impl CommandExecute for Repair {
 
                 maybe_updated_receipt
             },
+            RepairKind::Users => {
+                let Some(receipt) =
+                    get_existing_receipt(self.receipt_path.as_deref(), self.state_dir.as_deref())
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The `users` repair command needs the install receipt, which could not be \
+                        found or parsed by this version of the installer"
+                    ));
+                };
+
+                if let Some((_, create_users_and_groups)) =
+                    find_action_in_receipt::<CreateUsersAndGroups>(&receipt)?
+                {
+                    repair_actions.push(
+                        create_users_and_groups
+                            .replan()
+                            .map_err(PlannerError::Action)?
+                            .boxed(),
+                    );
+                } else if let Some((_, create_sysusers_build_users)) =
+                    find_action_in_receipt::<CreateSysusersBuildUsers>(&receipt)?
+                {
+                    // `systemd-sysusers` only creates what's missing
+                    let fragment_path = create_sysusers_build_users.fragment_path();
+                    if !fragment_path.exists() {
+                        return Err(color_eyre::eyre::eyre!(
+                            "`{}` is missing, reinstall Nix to recreate it",
+                            fragment_path.display()
+                        ));
+                    }
+                    execute_command(
+                        Command::new("systemd-sysusers")
+                            .arg(fragment_path)
+                            .stdin(std::process::Stdio::null()),
+                    )?;
+                } else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The receipt does not record any build users to recreate"
+                    ));
+                }
+
+                if matches!(
+                    OperatingSystem::host(),
+                    OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_)
+                ) {
+                    repair_actions.extend(plan_macos_mount_repair(&receipt)?);
+                }
+
+                None
+            },
+            RepairKind::StoreOwnership => {
+                let Some(receipt) =
+                    get_existing_receipt(self.receipt_path.as_deref(), self.state_dir.as_deref())
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The `store-ownership` repair command needs the install receipt, which \
+                        could not be found or parsed by this version of the installer"
+                    ));
+                };
+                let Some((_, provision_nix)) = find_action_in_receipt::<ProvisionNix>(&receipt)?
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The receipt does not record the group owning `/nix/store`"
+                    ));
+                };
+
+                let nix_store_gid = provision_nix.nix_store_gid;
+                if Group::from_gid(Gid::from_raw(nix_store_gid))?.is_none() {
+                    tracing::warn!(
+                        "No group has the GID {nix_store_gid} recorded in the receipt, consider \
+                        running `nix-installer repair users` first"
+                    );
+                }
+
+                tracing::info!("Re-owning `/nix/store` to GID {nix_store_gid}");
+                ensure_nix_store_group(nix_store_gid)?;
+                ensure_per_user_ownership()?;
+
+                None
+            },
+            RepairKind::Selinux => {
+                let Some(mut receipt) =
+                    get_existing_receipt(self.receipt_path.as_deref(), self.state_dir.as_deref())
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The `selinux` repair command needs the install receipt, which could not \
+                        be found or parsed by this version of the installer"
+                    ));
+                };
+                let Some((idx, provision_selinux)) =
+                    find_action_in_receipt::<ProvisionSelinux>(&receipt)?
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The receipt does not record an SELinux policy for Nix"
+                    ));
+                };
+
+                let provision_selinux = ProvisionSelinux::plan(
+                    provision_selinux.policy_path,
+                    SELINUX_POLICY_PP_CONTENT,
+                )
+                .map_err(PlannerError::Action)?;
+                repair_actions.push(provision_selinux.clone().boxed());
+
+                // So uninstalling removes the policy which is installed now
+                receipt.actions[idx] = StatefulAction::completed(provision_selinux.action).boxed();
+                Some(receipt)
+            },
+            RepairKind::RotateVolumeKey => {
+                let Some(receipt) =
+                    get_existing_receipt(self.receipt_path.as_deref(), self.state_dir.as_deref())
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The `rotate-volume-key` repair command needs the install receipt, which \
+                        could not be found or parsed by this version of the installer"
+                    ));
+                };
+                let Some(encrypt_volume) = find_action_in_receipt::<CreateNixVolume>(&receipt)?
+                    .and_then(|(_, create_nix_volume)| create_nix_volume.encrypt_volume)
+                else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The receipt does not record an encrypted Nix Store volume"
+                    ));
+                };
+
+                encrypt_volume.action.rotate_password()?;
+
+                // The keychain item keeps its label, so the receipt is unchanged
+                None
+            },
+            RepairKind::SyntheticConf => {
+                if !matches!(
+                    OperatingSystem::host(),
+                    OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_)
+                ) {
+                    return Err(color_eyre::eyre::eyre!(
+                        "The `synthetic-conf` repair command is only for macOS"
+                    ));
+                }
+                repair_actions.push(
+                    ConfigureSyntheticConf::plan(true)
+                        .map_err(PlannerError::Action)?
+                        .boxed(),
+                );
+                repair_actions.push(
+                    CreateSyntheticObjects::plan()
+                        .map_err(PlannerError::Action)?
+                        .boxed(),
+                );
+
+                None
+            },
         };
 
         for mut action in repair_actions {
@@ -411,14 +650,7 @@ impl CommandExecute for Repair {
         }
 
         if let Some(updated_receipt) = updated_receipt {
-            let timestamp_millis = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_millis();
-
-            let mut old_receipt = std::path::PathBuf::from(RECEIPT_LOCATION);
-            old_receipt.set_extension(format!("pre-repair.{timestamp_millis}.json"));
-            std::fs::copy(RECEIPT_LOCATION, &old_receipt)?;
-            tracing::info!("Backed up pre-repair receipt to {}", old_receipt.display());
+            back_up_receipt(&updated_receipt, "repair")?;
 
             updated_receipt.write_receipt()?;
             tracing::info!("Wrote updated receipt");
@@ -458,11 +690,14 @@ where
 }
 
 #[tracing::instrument]
-fn get_existing_receipt() -> Option<InstallPlan> {
-    match std::path::Path::new(RECEIPT_LOCATION).exists() {
-        true => {
-            tracing::debug!("Reading existing receipt");
-            let install_plan_string = std::fs::read_to_string(RECEIPT_LOCATION).ok();
+pub(crate) fn get_existing_receipt(
+    receipt_path: Option<&Path>,
+    state_dir: Option<&Path>,
+) -> Option<InstallPlan> {
+    match find_receipt_at(receipt_path, state_dir) {
+        Some(receipt_location) => {
+            tracing::debug!("Reading existing receipt at {}", receipt_location.display());
+            let install_plan_string = std::fs::read_to_string(receipt_location).ok();
 
             match install_plan_string {
                 Some(s) => match serde_json::from_str::<InstallPlan>(s.as_str()) {
@@ -481,10 +716,74 @@ fn get_existing_receipt() -> Option<InstallPlan> {
                 _ => None,
             }
         },
-        false => None,
+        None => None,
     }
 }
 
+/// Extract the first action of type `A` from the receipt, with its index
+#[tracing::instrument(skip_all)]
+pub(crate) fn find_action_in_receipt<A: Action + DeserializeOwned>(
+    receipt: &InstallPlan,
+) -> color_eyre::Result<Option<(usize, A)>> {
+    let wanted_tag = A::action_tag().0;
+    for (idx, stateful_action) in receipt.actions.iter().enumerate() {
+        if stateful_action.inner_typetag_name() != wanted_tag {
+            continue;
+        }
+        // NOTE: See `find_users_and_groups` for why this round-trips
+        let action_json = serde_json::to_string(&stateful_action.action)
+            .with_context(|| format!("round-tripping {wanted_tag} json to extract real type"))?;
+        let action: A = serde_json::from_str(&action_json)
+            .with_context(|| format!("round-tripping {wanted_tag} json to extract real type"))?;
+        return Ok(Some((idx, action)));
+    }
+    Ok(None)
+}
+
+/// Plan restoring what mounts the Nix Store volume on `/nix` and starts the Nix daemon, which
+/// macOS updates have been known to remove
+fn plan_macos_mount_repair(
+    receipt: &InstallPlan,
+) -> color_eyre::Result<Vec<StatefulAction<Box<dyn Action>>>> {
+    let mut actions = vec![
+        ConfigureSyntheticConf::plan(false)
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        CreateSyntheticObjects::plan()
+            .map_err(PlannerError::Action)?
+            .boxed(),
+    ];
+
+    if let Some((_, create_nix_volume)) = find_action_in_receipt::<CreateNixVolume>(receipt)? {
+        actions.push(
+            CreateVolumeService::plan(
+                NIX_VOLUME_MOUNTD_DEST,
+                NIX_VOLUME_MOUNTD_NAME,
+                create_nix_volume.name,
+                "/nix",
+                create_nix_volume.encrypt,
+            )
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        );
+        actions.push(
+            BootstrapLaunchctlService::plan(NIX_VOLUME_MOUNTD_NAME, NIX_VOLUME_MOUNTD_DEST)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+    }
+
+    if !Path::new(DARWIN_NIX_DAEMON_DEST).exists() {
+        actions.push(
+            ConfigureUpstreamInitService::plan(InitSystem::Launchd, true, false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+    }
+
+    Ok(actions)
+}
+
 #[tracing::instrument(skip_all)]
 fn find_users_and_groups(
     existing_receipt: Option<InstallPlan>,
@@ -545,11 +844,13 @@ struct UsersAndGroupsMeta {
 }
 
 fn maybe_users_and_groups_from_receipt(
+    receipt_path: Option<&Path>,
+    state_dir: Option<&Path>,
     nix_build_user_prefix: &str,
     nix_build_user_count: u32,
     nix_build_group_name: &str,
 ) -> eyre::Result<UsersAndGroupsMeta> {
-    let existing_receipt = get_existing_receipt();
+    let existing_receipt = get_existing_receipt(receipt_path, state_dir);
     let maybe_create_users_and_groups_idx_action = find_users_and_groups(existing_receipt)?;
 
     match maybe_create_users_and_groups_idx_action {
@@ -1,24 +1,43 @@
-use std::process::ExitCode;
+use std::{path::PathBuf, process::ExitCode};
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
 
 use crate::{NixInstallerError, cli::CommandExecute};
 
 /// Run a self test of Nix to ensure that an install is working
 #[derive(Debug, Parser)]
-pub struct SelfTest {}
+pub struct SelfTest {
+    /// Output a JSON report with the outcome of each test, for CI
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub json: bool,
+
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+}
 
 impl CommandExecute for SelfTest {
     #[tracing::instrument(level = "debug", skip_all, fields())]
     fn execute(self) -> eyre::Result<ExitCode> {
-        crate::self_test::self_test().map_err(NixInstallerError::SelfTest)?;
+        if self.json {
+            let report = crate::self_test::self_test_report(self.receipt_path.as_deref());
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(if report.passed {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+
+        crate::self_test::self_test(self.receipt_path.as_deref())
+            .map_err(NixInstallerError::SelfTest)?;
 
         tracing::info!(
             shells = ?crate::self_test::Shell::discover()
                 .iter()
                 .map(|v| v.executable())
                 .collect::<Vec<_>>(),
-            "Successfully tested Nix install in all discovered shells."
+            "Successfully tested Nix install in all discovered shells, the daemon, sandboxed builds and flakes."
         );
         Ok(ExitCode::SUCCESS)
     }
@@ -1,15 +1,21 @@
-use std::{path::PathBuf, process::ExitCode, time::SystemTime};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::SystemTime,
+};
 
+use crate::style::Colorize;
 use crate::{
-    InstallPlan,
-    action::{Action, ActionState, StatefulAction},
-    cli::{ensure_root, interaction::PromptChoice},
-    plan::RECEIPT_LOCATION,
+    ErrorCode, InstallPlan,
+    action::{Action, ActionState, StatefulAction, common::ConfigureNix},
+    cli::{
+        confirm_protected, ensure_root, error::report_failure_message, interaction::PromptChoice,
+    },
+    plan::find_receipt_at,
 };
 use clap::{ArgAction, Parser};
 use color_eyre::eyre::WrapErr;
-use eyre::ContextCompat as _;
-use owo_colors::OwoColorize;
+use eyre::{ContextCompat as _, eyre};
 
 use crate::cli::CommandExecute;
 
@@ -24,6 +30,9 @@ pub(crate) const PHASE2_RECEIPT_LOCATION: &str = "/nix/uninstall-phase2.json";
 /// location), it will clean up everything but the Nix store and allow you to reinstall with a
 /// newer version. If you run `/nix/nix-installer uninstall /nix/uninstall-phase2.json`, then it
 /// will complete the uninstall by cleaning up the Nix store.
+///
+/// Alternatively, `--list` shows the actions in the receipt, and `--revert` undoes only those with
+/// the given tags (such as `configure_shell_profile`), removing them from the receipt.
 #[derive(Debug, Parser)]
 pub struct SplitReceipt {
     #[clap(
@@ -34,8 +43,14 @@ pub struct SplitReceipt {
         global = true
     )]
     pub no_confirm: bool,
-    #[clap(default_value = RECEIPT_LOCATION)]
-    pub receipt: PathBuf,
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+    /// The install receipt to split (default: found in `--state-dir` or a known location)
+    pub receipt: Option<PathBuf>,
     #[clap(long, default_value = PHASE1_RECEIPT_LOCATION)]
     pub phase1_output: PathBuf,
     #[clap(long, default_value = PHASE2_RECEIPT_LOCATION)]
@@ -46,6 +61,21 @@ pub struct SplitReceipt {
     // measure
     #[clap(long, hide = true)]
     pub force_naive_json_method: bool,
+    /// List the tag, state and description of each action in the receipt, then exit
+    #[clap(long, conflicts_with_all = ["revert", "force_naive_json_method"])]
+    pub list: bool,
+    /// Revert only the actions with this tag (as shown by `--list`, including the steps of `configure_nix`), and remove them from the receipt
+    #[clap(
+        long,
+        value_name = "TAG",
+        action(ArgAction::Append),
+        value_delimiter = ',',
+        conflicts_with = "force_naive_json_method"
+    )]
+    pub revert: Vec<String>,
+    /// The hostname of this machine, required to `--revert` actions of a Nix installed with `--protect`
+    #[clap(long, env = "NIX_INSTALLER_CONFIRM_HOSTNAME")]
+    pub confirm_hostname: Option<String>,
 }
 
 impl CommandExecute for SplitReceipt {
@@ -57,10 +87,33 @@ impl CommandExecute for SplitReceipt {
             .duration_since(SystemTime::UNIX_EPOCH)?
             .as_millis();
 
-        let original_receipt_location = PathBuf::from(RECEIPT_LOCATION);
+        let original_receipt_location = self
+            .receipt
+            .clone()
+            .or_else(|| find_receipt_at(self.receipt_path.as_deref(), self.state_dir.as_deref()))
+            .ok_or_else(|| eyre!("Could not find an install receipt to split"))?;
         let backed_up_receipt_location = original_receipt_location
             .with_file_name(format!(".original-receipt.{timestamp_millis}.json"));
 
+        if self.list || !self.revert.is_empty() {
+            let install_receipt_string =
+                std::fs::read_to_string(&original_receipt_location).wrap_err("Reading receipt")?;
+            let plan = serde_json::from_str::<InstallPlan>(&install_receipt_string)
+                .wrap_err("Parsing the receipt")?;
+            plan.check_compatible()?;
+
+            if self.list {
+                list_actions(&plan)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            return revert_selected(
+                &self,
+                plan,
+                &original_receipt_location,
+                &backed_up_receipt_location,
+            );
+        }
+
         let brief_summary = format!(
             "\n\
                This will split your existing receipt at {receipt} into two phases (phase 1: {phase1}, phase 2: {phase2}) \
@@ -71,7 +124,7 @@ impl CommandExecute for SplitReceipt {
                you do not need to run phase 2 of the uninstallation.\n\
                If you want a clean uninstallation, you should run phase 2 after phase 1.\
                ",
-            receipt = self.receipt.display().bold(),
+            receipt = original_receipt_location.display().bold(),
             phase1 = self.phase1_output.display().bold(),
             phase2 = self.phase2_output.display().bold(),
             backup_location = backed_up_receipt_location.display().bold(),
@@ -93,7 +146,7 @@ impl CommandExecute for SplitReceipt {
         }
 
         let install_receipt_string =
-            std::fs::read_to_string(&self.receipt).wrap_err("Reading receipt")?;
+            std::fs::read_to_string(&original_receipt_location).wrap_err("Reading receipt")?;
 
         if self.force_naive_json_method {
             two_phased_cannot_parse_receipt_perfectly(&self, &install_receipt_string)?;
@@ -145,6 +198,155 @@ impl CommandExecute for SplitReceipt {
     }
 }
 
+fn list_actions(plan: &InstallPlan) -> eyre::Result<()> {
+    for (idx, action) in plan.actions.iter().enumerate() {
+        println!(
+            "{idx:>3}  {tag:<36} {state:<12} {synopsis}",
+            tag = action.inner_typetag_name().bold(),
+            state = format!("{:?}", action.state),
+            synopsis = action.tracing_synopsis(),
+        );
+        if let Some(configure_nix) = as_configure_nix(action)? {
+            for tag in configure_nix.action.step_tags() {
+                println!("       {tag}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The optional steps of `configure_nix`, such as `configure_shell_profile`, can be reverted too
+fn as_configure_nix(
+    action: &StatefulAction<Box<dyn Action>>,
+) -> eyre::Result<Option<StatefulAction<ConfigureNix>>> {
+    if action.inner_typetag_name() != ConfigureNix::action_tag().0 {
+        return Ok(None);
+    }
+    roundtrip_to_extract_type::<ConfigureNix>(action).map(Some)
+}
+
+/// Revert the actions tagged with any of `uninstall_args.revert` (last first, as uninstalling
+/// would), then rewrite the receipt without them
+fn revert_selected(
+    uninstall_args: &SplitReceipt,
+    mut plan: InstallPlan,
+    receipt_location: &Path,
+    backed_up_receipt_location: &Path,
+) -> eyre::Result<ExitCode> {
+    let tags = &uninstall_args.revert;
+    let mut known_tags = Vec::new();
+    for action in &plan.actions {
+        known_tags.push(action.inner_typetag_name());
+        if let Some(configure_nix) = as_configure_nix(action)? {
+            known_tags.extend(configure_nix.action.step_tags());
+        }
+    }
+    let unknown = tags
+        .iter()
+        .filter(|tag| !known_tags.contains(&tag.as_str()))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(eyre!(
+            "The receipt has no actions tagged {}, see `--list` for those it has",
+            unknown
+                .iter()
+                .map(|tag| format!("`{tag}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    if !confirm_protected(
+        &plan,
+        uninstall_args.confirm_hostname.clone(),
+        uninstall_args.no_confirm,
+    )? {
+        return Ok(report_failure_message(
+            "\
+            This Nix install was installed with `--protect`, and the hostname of this machine was not confirmed.\n\
+            To revert its actions, pass `--confirm-hostname` with the hostname of this machine.\
+            ",
+            ErrorCode::Failure,
+        ));
+    }
+
+    let selected = |action: &StatefulAction<Box<dyn Action>>| {
+        tags.iter()
+            .any(|tag| tag.as_str() == action.inner_typetag_name())
+    };
+    if !uninstall_args.no_confirm {
+        let brief_summary = format!(
+            "Will revert the actions tagged {tags}, and remove them from {receipt}",
+            tags = tags
+                .iter()
+                .map(|tag| format!("`{tag}`"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            receipt = receipt_location.display().bold(),
+        );
+        loop {
+            match crate::cli::interaction::prompt(&brief_summary, PromptChoice::Yes, true)? {
+                PromptChoice::Yes => break,
+                PromptChoice::No => crate::cli::interaction::clean_exit_with_message(
+                    "Okay, didn't do anything! Bye!",
+                ),
+                PromptChoice::Explain => (),
+            }
+        }
+    }
+
+    // Stop at the first failure, keeping it and the actions not reached yet in the receipt
+    let mut failure = None;
+    for idx in (0..plan.actions.len()).rev() {
+        if let Some(mut configure_nix) = as_configure_nix(&plan.actions[idx])? {
+            for tag in tags.iter().rev() {
+                if !configure_nix.action.step_tags().contains(&tag.as_str()) {
+                    continue;
+                }
+                tracing::info!("Revert: {tag}");
+                if let Err(err) = configure_nix.action.revert_step(tag) {
+                    failure = Some(err);
+                    break;
+                }
+            }
+            plan.actions[idx] = configure_nix.boxed();
+            if failure.is_some() {
+                break;
+            }
+        }
+        if !selected(&plan.actions[idx]) {
+            continue;
+        }
+        tracing::info!("Revert: {}", plan.actions[idx].tracing_synopsis());
+        if let Err(err) = plan.actions[idx].try_revert() {
+            failure = Some(err);
+            break;
+        }
+        plan.actions.remove(idx);
+    }
+
+    std::fs::copy(receipt_location, backed_up_receipt_location)?;
+    tracing::info!(
+        "Backed up original, untouched receipt to {}",
+        backed_up_receipt_location.display()
+    );
+    crate::plan::write_receipt(&plan, receipt_location)?;
+
+    if let Some(err) = failure {
+        return Err(err).wrap_err("Reverting the selected actions");
+    }
+    println!(
+        "{}",
+        format!(
+            "Reverted the selected actions and updated {}",
+            receipt_location.display()
+        )
+        .green()
+        .bold()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
 /// If the receipt can be parsed by this version of the installer, then we can use the actual
 /// types as they will have the same fields.
 fn two_phased_can_parse_receipt_perfectly(
@@ -156,8 +358,12 @@ fn two_phased_can_parse_receipt_perfectly(
     let mut phase1_plan = plan;
     let mut phase2_plan = InstallPlan {
         version: phase1_plan.version.clone(),
+        extensions: phase1_plan.extensions.clone(),
         actions: Vec::new(),
         planner: phase1_plan.planner.clone(),
+        skip_interactive_self_test: false,
+        converging: false,
+        action_timeout: None,
     };
 
     for action in phase1_plan.actions.iter_mut() {
@@ -263,6 +469,8 @@ fn two_phased_cannot_parse_receipt_perfectly(
     #[derive(Debug, serde::Deserialize, serde::Serialize)]
     struct OpaquePlan {
         version: semver::Version,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        extensions: Vec<serde_json::Value>,
         actions: Vec<serde_json::Value>,
         planner: serde_json::Value,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -273,6 +481,7 @@ fn two_phased_cannot_parse_receipt_perfectly(
         serde_json::from_str(receipt_str).context("Receipt was not opaquely parseable")?;
     let mut phase2_plan = OpaquePlan {
         version: phase1_plan.version.clone(),
+        extensions: phase1_plan.extensions.clone(),
         actions: Vec::new(),
         planner: phase1_plan.planner.clone(),
         diagnostic_data: phase1_plan.diagnostic_data.clone(),
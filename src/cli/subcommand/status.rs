@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+use clap::{ArgAction, Parser};
+use target_lexicon::OperatingSystem;
+
+use crate::InstallPlan;
+use crate::action::base::create_directory::path_is_mountpoint;
+use crate::action::macos::DARWIN_LAUNCHD_DOMAIN;
+use crate::cli::CommandExecute;
+use crate::plan::find_receipt_at;
+use crate::settings::DEFAULT_DAEMON_SOCKET;
+use crate::style::Colorize;
+
+const NIX_STORE: &str = "/nix/store";
+const DEFAULT_PROFILE_NIX: &str = "/nix/var/nix/profiles/default/bin/nix";
+
+/// Show the state of the Nix install, from its receipt and the running system
+#[derive(Debug, Parser)]
+pub struct Status {
+    /// Output a JSON object instead of a human report, for support scripts
+    #[clap(long, action(ArgAction::SetTrue), default_value = "false")]
+    pub json: bool,
+
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Where the install receipt was written with `install --receipt-path`, instead of looking for it
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+}
+
+/// What `nix-installer status` reports
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct InstallStatus {
+    /// Where the install receipt was found, if anywhere
+    receipt: Option<PathBuf>,
+    /// The `nix-installer` version which wrote the receipt
+    receipt_version: Option<String>,
+    /// The version reported by the default profile's `nix`
+    nix_version: Option<String>,
+    planner: Option<String>,
+    /// The settings the install was planned with which differ from the defaults
+    settings: Option<HashMap<String, serde_json::Value>>,
+    /// The state of the Nix daemon's service, as the init system reports it
+    daemon: String,
+    daemon_socket: bool,
+    nix_store: bool,
+    /// If `/nix` is a mountpoint of its own, such as the macOS Nix Store volume
+    nix_mounted: bool,
+    /// Reasons this installer can't manage the install as-is
+    incompatibilities: Vec<String>,
+}
+
+impl InstallStatus {
+    pub(crate) fn current(receipt_path: Option<&Path>, state_dir: Option<&Path>) -> Self {
+        let mut incompatibilities = Vec::new();
+
+        let receipt = find_receipt_at(receipt_path, state_dir);
+        let plan = receipt.as_ref().and_then(|receipt| {
+            match std::fs::read_to_string(receipt)
+                .map_err(|e| e.to_string())
+                .and_then(|buf| {
+                    serde_json::from_str::<InstallPlan>(&buf).map_err(|e| e.to_string())
+                }) {
+                Ok(plan) => Some(plan),
+                Err(e) => {
+                    incompatibilities.push(format!(
+                        "Unable to parse the receipt `{}`, it may be from an incompatible version of `nix-installer`: {e}",
+                        receipt.display()
+                    ));
+                    None
+                },
+            }
+        });
+
+        let nix_store = Path::new(NIX_STORE).exists();
+        if let Some(plan) = &plan {
+            if let Err(e) = plan.check_compatible() {
+                incompatibilities.push(e.to_string());
+            }
+            if let Err(e) = plan.planner.platform_check() {
+                incompatibilities.push(e.to_string());
+            }
+            if !nix_store {
+                incompatibilities.push(format!(
+                    "The receipt exists, but `{NIX_STORE}` does not, the install may be partially removed"
+                ));
+            }
+        } else if receipt.is_none() && nix_store {
+            incompatibilities.push(format!(
+                "`{NIX_STORE}` exists without a receipt, it may be from another installer"
+            ));
+        }
+
+        let settings = plan.as_ref().and_then(|plan| {
+            plan.planner
+                .configured_settings()
+                .map_err(|e| tracing::debug!(%e, "Could not read the receipt's settings"))
+                .ok()
+        });
+
+        Self {
+            receipt,
+            receipt_version: plan.as_ref().map(|plan| plan.version.to_string()),
+            nix_version: nix_version(),
+            planner: plan
+                .as_ref()
+                .map(|plan| plan.planner.typetag_name().to_string()),
+            settings,
+            daemon: daemon_state(),
+            daemon_socket: Path::new(DEFAULT_DAEMON_SOCKET).exists(),
+            nix_store,
+            nix_mounted: Path::new("/nix").exists()
+                && path_is_mountpoint(Path::new("/nix")).unwrap_or(false),
+            incompatibilities,
+        }
+    }
+}
+
+/// The version from `nix --version`, such as `2.24.9` from `nix (Nix) 2.24.9`
+fn nix_version() -> Option<String> {
+    let output = Command::new(DEFAULT_PROFILE_NIX)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()?
+        .split_whitespace()
+        .last()
+        .map(ToString::to_string)
+}
+
+fn daemon_state() -> String {
+    match OperatingSystem::host() {
+        OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
+            let output = Command::new("launchctl")
+                .arg("print")
+                .arg(format!("{DARWIN_LAUNCHD_DOMAIN}/org.nixos.nix-daemon"))
+                .stdin(Stdio::null())
+                .output();
+            match output {
+                Ok(output) if output.status.success() => {
+                    if String::from_utf8_lossy(&output.stdout).contains("state = running") {
+                        "running".to_string()
+                    } else {
+                        "loaded".to_string()
+                    }
+                },
+                Ok(_) => "not loaded".to_string(),
+                Err(e) => format!("unknown ({e})"),
+            }
+        },
+        _ if Path::new("/run/systemd/system").exists() => {
+            // `is-active` exits non-zero for anything but active, but still prints the state
+            match Command::new("systemctl")
+                .args(["is-active", "nix-daemon.service"])
+                .stdin(Stdio::null())
+                .output()
+            {
+                Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+                Err(e) => format!("unknown ({e})"),
+            }
+        },
+        _ => "unknown (no supported init system is running)".to_string(),
+    }
+}
+
+impl CommandExecute for Status {
+    #[tracing::instrument(level = "debug", skip_all, fields())]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let status =
+            InstallStatus::current(self.receipt_path.as_deref(), self.state_dir.as_deref());
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&status)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let none = || "none".to_string();
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        println!(
+            "\
+            {receipt_heading}: {receipt}\n\
+            \x20 Written by: {receipt_version}\n\
+            \x20 Planner: {planner}\n\
+            {nix_heading}: {nix_version}\n\
+            \x20 Daemon: {daemon}\n\
+            \x20 Daemon socket: {daemon_socket}\n\
+            \x20 `/nix/store`: {nix_store}\n\
+            \x20 `/nix` is a mountpoint: {nix_mounted}\
+            ",
+            receipt_heading = "Receipt".bold(),
+            receipt = status
+                .receipt
+                .as_ref()
+                .map(|receipt| receipt.display().to_string())
+                .unwrap_or_else(none),
+            receipt_version = status
+                .receipt_version
+                .as_ref()
+                .map(|version| format!("nix-installer {version}"))
+                .unwrap_or_else(none),
+            planner = status.planner.clone().unwrap_or_else(none),
+            nix_heading = "Nix".bold(),
+            nix_version = status
+                .nix_version
+                .clone()
+                .unwrap_or_else(|| "not installed".to_string()),
+            daemon = status.daemon,
+            daemon_socket = yes_no(status.daemon_socket),
+            nix_store = yes_no(status.nix_store),
+            nix_mounted = yes_no(status.nix_mounted),
+        );
+
+        if let Some(settings) = &status.settings {
+            if !settings.is_empty() {
+                println!("{}:", "Settings".bold());
+                let mut settings = settings.iter().collect::<Vec<_>>();
+                settings.sort_by_key(|(key, _)| key.as_str());
+                for (key, value) in settings {
+                    println!("  {key}: {value}");
+                }
+            }
+        }
+
+        if !status.incompatibilities.is_empty() {
+            println!("{}:", "Incompatibilities".bold().red());
+            for incompatibility in &status.incompatibilities {
+                println!("  * {incompatibility}");
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use target_lexicon::OperatingSystem;
+
+const NIX_DARWIN_SYSTEM: &str = "/run/current-system";
+const NIX_DARWIN_ACTIVATION: &str = "/Library/LaunchDaemons/org.nixos.activate-system.plist";
+const CACHIX_AGENT_UNITS: &[&str] = &[
+    "/etc/systemd/system/cachix-agent.service",
+    "/Library/LaunchDaemons/org.nixos.cachix-agent.plist",
+];
+const DEFAULT_PROFILE_BIN: &str = "/nix/var/nix/profiles/default/bin";
+
+/// Something installed alongside Nix which stops working once Nix is uninstalled
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Dependent {
+    /// An allowed `.envrc` loading a flake or `shell.nix` with direnv
+    Direnv(PathBuf),
+    CachixAgent(PathBuf),
+    Devenv(PathBuf),
+    HomeManager(PathBuf),
+    NixDarwin(PathBuf),
+}
+
+impl Display for Dependent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dependent::Direnv(path) => write!(f, "direnv environment `{}`", path.display()),
+            Dependent::CachixAgent(path) => write!(f, "Cachix Deploy agent `{}`", path.display()),
+            Dependent::Devenv(path) => write!(f, "devenv `{}`", path.display()),
+            Dependent::HomeManager(path) => {
+                write!(f, "Home Manager configuration `{}`", path.display())
+            },
+            Dependent::NixDarwin(path) => write!(f, "nix-darwin system `{}`", path.display()),
+        }
+    }
+}
+
+/// Scan for tools which rely on the Nix about to be uninstalled
+#[tracing::instrument(level = "debug")]
+pub(crate) fn find_dependents() -> Vec<Dependent> {
+    let mut dependents = vec![];
+
+    for path in [NIX_DARWIN_SYSTEM, NIX_DARWIN_ACTIVATION].map(PathBuf::from) {
+        if path.exists() {
+            dependents.push(Dependent::NixDarwin(path));
+        }
+    }
+    for path in CACHIX_AGENT_UNITS.iter().map(PathBuf::from) {
+        if path.exists() {
+            dependents.push(Dependent::CachixAgent(path));
+        }
+    }
+    let devenv = Path::new(DEFAULT_PROFILE_BIN).join("devenv");
+    if devenv.exists() {
+        dependents.push(Dependent::Devenv(devenv));
+    }
+
+    for home in homes() {
+        dependents.extend(find_dependents_in_home(&home));
+    }
+
+    dependents
+}
+
+fn find_dependents_in_home(home: &Path) -> Vec<Dependent> {
+    let mut dependents = vec![];
+
+    for path in [
+        ".local/state/nix/profiles/home-manager",
+        ".local/state/home-manager",
+        ".config/home-manager",
+    ]
+    .map(|suffix| home.join(suffix))
+    {
+        if path.exists() || path.is_symlink() {
+            dependents.push(Dependent::HomeManager(path));
+            break;
+        }
+    }
+
+    let devenv = home.join(".nix-profile/bin/devenv");
+    if devenv.exists() {
+        dependents.push(Dependent::Devenv(devenv));
+    }
+
+    // direnv records each `.envrc` the user allowed in a file named after its hash, holding its path
+    let allow_dir = home.join(".local/share/direnv/allow");
+    if let Ok(entries) = std::fs::read_dir(allow_dir) {
+        for entry in entries.flatten() {
+            let Ok(envrc) = std::fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            let envrc = PathBuf::from(envrc.trim());
+            if std::fs::read_to_string(&envrc).is_ok_and(|contents| envrc_uses_nix(&contents)) {
+                dependents.push(Dependent::Direnv(envrc));
+            }
+        }
+    }
+
+    dependents
+}
+
+/// Whether an `.envrc` loads its environment from Nix, with direnv's stdlib or nix-direnv
+fn envrc_uses_nix(contents: &str) -> bool {
+    contents.lines().any(|line| {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("use") => matches!(words.next(), Some("flake" | "nix" | "devenv")),
+            Some("use_flake" | "use_nix" | "use_devenv") => true,
+            _ => false,
+        }
+    })
+}
+
+/// Home directories of the users on this machine
+fn homes() -> Vec<PathBuf> {
+    match OperatingSystem::host() {
+        OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => std::fs::read_dir("/Users")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|name| name != "Shared"))
+            .chain(std::iter::once(PathBuf::from("/var/root")))
+            .collect(),
+        _ => std::fs::read_to_string("/etc/passwd")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split(':').nth(5))
+            .map(PathBuf::from)
+            .filter(|home| home.starts_with("/home") || home == Path::new("/root"))
+            .filter(|home| home.is_dir())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dependent, envrc_uses_nix, find_dependents_in_home};
+
+    #[test]
+    fn envrc() {
+        assert!(envrc_uses_nix("use flake\n"));
+        assert!(envrc_uses_nix("dotenv\nuse flake .#dev --impure\n"));
+        assert!(envrc_uses_nix("use nix shell.nix\n"));
+        assert!(envrc_uses_nix("if has nix; then\n  use_flake\nfi\n"));
+        assert!(!envrc_uses_nix("export FOO=bar\n"));
+        assert!(!envrc_uses_nix("# use flake\n"));
+    }
+
+    #[test]
+    fn home_manager_and_direnv() -> eyre::Result<()> {
+        let home = tempfile::tempdir()?;
+        let home = home.path();
+
+        std::fs::create_dir_all(home.join(".config/home-manager"))?;
+        let project = home.join("project");
+        std::fs::create_dir_all(&project)?;
+        std::fs::write(project.join(".envrc"), "use flake\n")?;
+        let allow_dir = home.join(".local/share/direnv/allow");
+        std::fs::create_dir_all(&allow_dir)?;
+        std::fs::write(
+            allow_dir.join("0123abcd"),
+            format!("{}\n", project.join(".envrc").display()),
+        )?;
+
+        assert_eq!(
+            find_dependents_in_home(home),
+            vec![
+                Dependent::HomeManager(home.join(".config/home-manager")),
+                Dependent::Direnv(project.join(".envrc")),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::{Command, ExitCode},
+};
+
+use color_eyre::eyre::WrapErr;
+use nix::unistd::Group;
+use target_lexicon::OperatingSystem;
+
+use crate::style::Colorize;
+use crate::{
+    action::{
+        common::configure_upstream_init_service::DARWIN_NIX_DAEMON_DEST,
+        macos::NIX_VOLUME_MOUNTD_DEST,
+    },
+    cli::interaction::{self, PromptChoice},
+    execute_command,
+    os::users::user_backend,
+    planner::ShellProfileLocations,
+    settings::DEFAULT_NIX_BUILD_USER_GROUP_NAME,
+    util::OnMissing,
+};
+
+const LAUNCHD_SERVICES: &[&str] = &[
+    DARWIN_NIX_DAEMON_DEST,
+    NIX_VOLUME_MOUNTD_DEST,
+    "/Library/LaunchDaemons/systems.determinate.nix-installer.nix-hook.plist",
+];
+const SYSTEMD_UNITS: &[&str] = &[
+    "/etc/systemd/system/nix-daemon.service",
+    "/etc/systemd/system/nix-daemon.socket",
+];
+const SYNTHETIC_CONF: &str = "/etc/synthetic.conf";
+const FSTAB: &str = "/etc/fstab";
+
+/// Something an earlier install (by this installer, or the shell installer) left behind
+#[derive(Debug)]
+pub(crate) enum Leftover {
+    BuildUser(String),
+    BuildGroup(String),
+    LaunchdService(PathBuf),
+    SystemdUnit(PathBuf),
+    /// A file with Nix related lines which should be rewritten to `contents`
+    Lines {
+        path: PathBuf,
+        removed: Vec<String>,
+        contents: String,
+    },
+}
+
+impl Display for Leftover {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Leftover::BuildUser(name) => write!(f, "Build user `{name}`"),
+            Leftover::BuildGroup(name) => write!(f, "Build group `{name}`"),
+            Leftover::LaunchdService(path) => {
+                write!(f, "Launchd service `{}`", path.display())
+            },
+            Leftover::SystemdUnit(path) => write!(f, "Systemd unit `{}`", path.display()),
+            Leftover::Lines { path, removed, .. } => write!(
+                f,
+                "{} Nix related line(s) in `{}`",
+                removed.len(),
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Leftover {
+    fn remove(&self) -> eyre::Result<()> {
+        match self {
+            Leftover::BuildUser(name) => user_backend()?.delete_user(name)?,
+            Leftover::BuildGroup(name) => user_backend()?.delete_group(name)?,
+            Leftover::LaunchdService(path) => {
+                if let Some(label) = path.file_stem() {
+                    // The service may well not be loaded, that is fine
+                    let _ = Command::new("launchctl")
+                        .arg("bootout")
+                        .arg(format!("system/{}", label.to_string_lossy()))
+                        .output();
+                }
+                crate::util::remove_file(path, OnMissing::Ignore)?;
+            },
+            Leftover::SystemdUnit(path) => {
+                if let Some(unit) = path.file_name() {
+                    // The unit may well not be enabled, that is fine
+                    let _ = Command::new("systemctl")
+                        .arg("disable")
+                        .arg("--now")
+                        .arg(unit)
+                        .output();
+                }
+                crate::util::remove_file(path, OnMissing::Ignore)?;
+                let _ = Command::new("systemctl").arg("daemon-reload").output();
+            },
+            Leftover::Lines { path, contents, .. } => crate::util::write_atomic(path, contents)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Scan for leftovers of earlier installs
+#[tracing::instrument(level = "debug")]
+pub(crate) fn find_leftovers() -> eyre::Result<Vec<Leftover>> {
+    let mut leftovers = vec![];
+
+    for user in build_users()? {
+        leftovers.push(Leftover::BuildUser(user));
+    }
+    if Group::from_name(DEFAULT_NIX_BUILD_USER_GROUP_NAME)?.is_some() {
+        leftovers.push(Leftover::BuildGroup(
+            DEFAULT_NIX_BUILD_USER_GROUP_NAME.to_string(),
+        ));
+    }
+
+    for service in LAUNCHD_SERVICES.iter().map(PathBuf::from) {
+        if service.exists() {
+            leftovers.push(Leftover::LaunchdService(service));
+        }
+    }
+    for unit in SYSTEMD_UNITS.iter().map(PathBuf::from) {
+        if unit.exists() || unit.is_symlink() {
+            leftovers.push(Leftover::SystemdUnit(unit));
+        }
+    }
+
+    let mut line_leftovers = vec![];
+    line_leftovers.push(find_lines(Path::new(SYNTHETIC_CONF), |line| {
+        line.split_whitespace().next() == Some("nix")
+    })?);
+    line_leftovers.push(find_lines(Path::new(FSTAB), |line| {
+        !line.trim_start().starts_with('#') && line.split_whitespace().nth(1) == Some("/nix")
+    })?);
+
+    let locations = ShellProfileLocations::default();
+    let fish_profiles = locations
+        .fish
+        .confd_prefixes
+        .iter()
+        .map(|prefix| prefix.join(&locations.fish.confd_suffix))
+        .chain(
+            locations
+                .fish
+                .vendor_confd_prefixes
+                .iter()
+                .map(|prefix| prefix.join(&locations.fish.vendor_confd_suffix)),
+        );
+    let profiles = locations
+        .bash
+        .iter()
+        .chain(locations.zsh.iter())
+        .cloned()
+        .chain(std::iter::once(PathBuf::from("/etc/zshenv")))
+        .chain(fish_profiles);
+    for profile in profiles {
+        if profile.is_symlink() {
+            // Likely managed by something else (eg `nix-darwin`)
+            continue;
+        }
+        line_leftovers.push(find_shell_profile_snippets(&profile)?);
+    }
+
+    leftovers.extend(line_leftovers.into_iter().flatten());
+
+    Ok(leftovers)
+}
+
+/// List leftovers of earlier installs and, once confirmed, remove them
+pub(crate) fn remove_leftovers(no_confirm: bool) -> eyre::Result<ExitCode> {
+    let leftovers = find_leftovers()?;
+
+    if leftovers.is_empty() {
+        println!("No leftovers from earlier Nix installs were found");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let listing = leftovers
+        .iter()
+        .map(|leftover| format!("* {leftover}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let summary = format!(
+        "\
+        Found the following leftovers from earlier Nix installs, which will be removed:\n\
+        \n\
+        {listing}\n\
+        "
+    );
+
+    if !no_confirm {
+        match interaction::prompt(&summary, PromptChoice::Yes, true)? {
+            PromptChoice::Yes => (),
+            PromptChoice::No | PromptChoice::Explain => {
+                interaction::clean_exit_with_message("Okay, not removing any leftovers. Bye!")
+            },
+        }
+    } else {
+        tracing::info!("{summary}");
+    }
+
+    let mut failed = false;
+    for leftover in leftovers {
+        if let Err(err) = leftover
+            .remove()
+            .wrap_err_with(|| format!("Removing {leftover}"))
+        {
+            tracing::error!("{err:?}");
+            failed = true;
+        }
+    }
+
+    if failed {
+        eprintln!("{}", "Some leftovers could not be removed".red());
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("{}", "Leftovers were removed successfully!".green().bold());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn build_users() -> eyre::Result<Vec<String>> {
+    let names = match OperatingSystem::host() {
+        OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => {
+            let output =
+                execute_command(Command::new("/usr/bin/dscl").args([".", "-list", "/Users"]))?;
+            String::from_utf8(output.stdout)?
+                .lines()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        },
+        _ => std::fs::read_to_string("/etc/passwd")
+            .wrap_err("Reading `/etc/passwd`")?
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .map(ToString::to_string)
+            .collect(),
+    };
+
+    Ok(names
+        .into_iter()
+        .filter(|name| is_build_user(name))
+        .collect())
+}
+
+fn is_build_user(name: &str) -> bool {
+    let Some(number) = name
+        .strip_prefix("_nixbld")
+        .or_else(|| name.strip_prefix("nixbld"))
+    else {
+        return false;
+    };
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+fn find_lines(path: &Path, is_nix: impl Fn(&str) -> bool) -> eyre::Result<Option<Leftover>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let existing =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Reading `{}`", path.display()))?;
+
+    let (removed, kept): (Vec<&str>, Vec<&str>) = existing.lines().partition(|line| is_nix(line));
+    if removed.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Leftover::Lines {
+        path: path.to_path_buf(),
+        removed: removed.into_iter().map(ToString::to_string).collect(),
+        contents: kept.into_iter().map(|line| format!("{line}\n")).collect(),
+    }))
+}
+
+fn find_shell_profile_snippets(path: &Path) -> eyre::Result<Option<Leftover>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let existing =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Reading `{}`", path.display()))?;
+
+    Ok(
+        strip_shell_profile_snippets(&existing).map(|(removed, contents)| Leftover::Lines {
+            path: path.to_path_buf(),
+            removed,
+            contents,
+        }),
+    )
+}
+
+/// Strip the `# Nix` ... `# End Nix` blocks this installer and the shell installer add to shell profiles
+fn strip_shell_profile_snippets(existing: &str) -> Option<(Vec<String>, String)> {
+    let mut removed = vec![];
+    let mut contents = String::new();
+    let mut in_snippet = false;
+
+    for line in existing.lines() {
+        let trimmed = line.trim();
+        if !in_snippet && (trimmed == "# Nix" || trimmed == "# Set up Nix only on SSH connections")
+        {
+            in_snippet = true;
+        }
+
+        if in_snippet {
+            removed.push(line.to_string());
+            if trimmed == "# End Nix" {
+                in_snippet = false;
+            }
+        } else {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+    }
+
+    if in_snippet {
+        // An unterminated block may not be ours, leave the file alone
+        return None;
+    }
+
+    (!removed.is_empty()).then_some((removed, contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_build_user, strip_shell_profile_snippets};
+
+    #[test]
+    fn build_users() {
+        assert!(is_build_user("_nixbld1"));
+        assert!(is_build_user("nixbld32"));
+        assert!(!is_build_user("nixbld"));
+        assert!(!is_build_user("nixbldfoo"));
+        assert!(!is_build_user("nixuser1"));
+    }
+
+    #[test]
+    fn shell_profile_snippets() {
+        let existing = "\
+            export FOO=bar\n\
+            \n\
+            # Nix\n\
+            if [ -e '/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh' ]; then\n\
+            . '/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh'\n\
+            fi\n\
+            # End Nix\n\
+            alias ll='ls -l'\n\
+        ";
+        let (removed, contents) = strip_shell_profile_snippets(existing).unwrap();
+        assert_eq!(removed.len(), 5);
+        assert_eq!(contents, "export FOO=bar\n\nalias ll='ls -l'\n");
+
+        assert_eq!(strip_shell_profile_snippets("export FOO=bar\n"), None);
+        assert_eq!(
+            strip_shell_profile_snippets("# Nix\nexport FOO=bar\n"),
+            None
+        );
+    }
+}
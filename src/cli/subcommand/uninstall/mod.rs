@@ -0,0 +1,372 @@
+mod dependents;
+mod leftovers;
+
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use crate::style::Colorize;
+use crate::{
+    ErrorCode, InstallPlan, NixInstallerError,
+    cli::{
+        confirm_protected, ensure_root,
+        error::{report_failure, report_failure_message},
+        interaction::PromptChoice,
+        setup_signal_handler,
+    },
+    error::HasExpectedErrors,
+    plan::{KNOWN_STATE_DIRS, current_version, find_receipt_at},
+    util::OnMissing,
+};
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::{WrapErr, eyre};
+use rand::Rng;
+
+use crate::cli::{CommandExecute, interaction};
+
+/// Uninstall a previously `nix-installer` installed Nix
+#[derive(Debug, Parser)]
+pub struct Uninstall {
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_NO_CONFIRM",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub no_confirm: bool,
+
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_EXPLAIN",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub explain: bool,
+
+    /// The directory to look for the install receipt in, before the default locations
+    #[clap(long, env = "NIX_INSTALLER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// The hostname of this machine, required to uninstall a Nix installed with `--protect`
+    #[clap(long, env = "NIX_INSTALLER_CONFIRM_HOSTNAME")]
+    pub confirm_hostname: Option<String>,
+
+    /// After uninstalling, also scan for and remove leftovers of earlier installs (such as stale build users or shell profile snippets)
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_THOROUGH",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub thorough: bool,
+
+    /// Uninstall even if tools relying on Nix (such as direnv, Home Manager, or nix-darwin) are found
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_IGNORE_DEPENDENTS",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub ignore_dependents: bool,
+
+    /// Kill a step's commands and fail it if it is still running after this many seconds, rather than waiting forever for a command which hangs
+    #[clap(long, value_name = "SECONDS", env = "NIX_INSTALLER_ACTION_TIMEOUT")]
+    pub action_timeout: Option<u64>,
+
+    /// Before uninstalling, export every store path to a binary cache in this directory, which `install --restore-store` imports
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_BACKUP_STORE")]
+    pub backup_store: Option<PathBuf>,
+
+    /// Where the install receipt was written with `install --receipt-path`, which is removed after uninstalling
+    #[clap(long, value_name = "PATH", env = "NIX_INSTALLER_RECEIPT_PATH")]
+    pub receipt_path: Option<PathBuf>,
+
+    /// The install receipt to uninstall from (default: found in `--state-dir` or a known location)
+    pub receipt: Option<PathBuf>,
+}
+
+impl CommandExecute for Uninstall {
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn execute(self) -> eyre::Result<ExitCode> {
+        let Self {
+            no_confirm,
+            receipt,
+            state_dir,
+            confirm_hostname,
+            thorough,
+            explain,
+            ignore_dependents,
+            action_timeout,
+            backup_store,
+            receipt_path,
+        } = self;
+
+        if let Ok(current_dir) = std::env::current_dir() {
+            let mut components = current_dir.components();
+            let should_be_root = components.next();
+            let maybe_nix = components.next();
+            if should_be_root == Some(std::path::Component::RootDir)
+                && maybe_nix == Some(std::path::Component::Normal(std::ffi::OsStr::new("nix")))
+            {
+                tracing::debug!("Changing current directory to be outside of `/nix`");
+                std::env::set_current_dir("/").wrap_err("Uninstall process was run from `/nix` folder, but could not change directory away from `/nix`, please change the current directory and try again.")?;
+            }
+        }
+
+        // During install, `nix-installer` will store a copy of itself in `/nix/nix-installer`
+        // If the user opted to run that particular copy of `nix-installer` to do this uninstall,
+        // well, we have a problem, since the binary would delete itself.
+        // Instead, detect if we're in that location, if so, move the binary and `execv` it.
+        if let Ok(current_exe) = std::env::current_exe() {
+            if current_exe.as_path() == Path::new("/nix/nix-installer") {
+                tracing::debug!(
+                    "Detected uninstall from `/nix/nix-installer`, moving executable and re-executing"
+                );
+                let temp = std::env::temp_dir();
+                let random_trailer: String = {
+                    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                                        abcdefghijklmnopqrstuvwxyz\
+                                            0123456789";
+                    const PASSWORD_LEN: usize = 16;
+                    let mut rng = rand::rng();
+
+                    (0..PASSWORD_LEN)
+                        .map(|_| {
+                            let idx = rng.random_range(0..CHARSET.len());
+                            CHARSET[idx] as char
+                        })
+                        .collect()
+                };
+                let temp_exe = temp.join(format!("nix-installer-{random_trailer}"));
+                std::fs::copy(&current_exe, &temp_exe)
+                    .wrap_err("Copying nix-installer to tempdir")?;
+                let args = std::env::args();
+                let mut arg_vec_cstring = vec![];
+                for arg in args {
+                    arg_vec_cstring.push(CString::new(arg).wrap_err("Making arg into C string")?);
+                }
+                let temp_exe_cstring = CString::new(temp_exe.to_string_lossy().into_owned())
+                    .wrap_err("Making C string of executable path")?;
+
+                tracing::trace!("Execv'ing `{temp_exe_cstring:?} {arg_vec_cstring:?}`");
+                nix::unistd::execv(&temp_exe_cstring, &arg_vec_cstring)
+                    .wrap_err("Executing copied `nix-installer`")?;
+            }
+        }
+
+        let receipt_was_given = receipt.is_some();
+        let Some(receipt) =
+            receipt.or_else(|| find_receipt_at(receipt_path.as_deref(), state_dir.as_deref()))
+        else {
+            if thorough {
+                ensure_root()?;
+                tracing::info!("No receipt found, only removing leftovers of earlier installs");
+                return leftovers::remove_leftovers(no_confirm);
+            }
+            let searched = match &receipt_path {
+                Some(receipt_path) => receipt_path.display().to_string(),
+                None => state_dir
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .chain(KNOWN_STATE_DIRS.iter().map(|dir| dir.to_string()))
+                    .collect::<Vec<_>>()
+                    .join("`, `"),
+            };
+            return Ok(report_failure_message(
+                format!(
+                    "Could not find an install receipt in `{searched}`, pass the receipt path if it was moved"
+                ),
+                ErrorCode::NotInstalled,
+            ));
+        };
+        tracing::debug!("Using receipt at {}", receipt.display());
+
+        let install_receipt_string =
+            std::fs::read_to_string(&receipt).wrap_err("Reading receipt")?;
+
+        let mut plan: InstallPlan = match serde_json::from_str(&install_receipt_string) {
+            Ok(plan) => plan,
+            Err(plan_err) => {
+                #[derive(serde::Deserialize)]
+                struct MinimalPlan {
+                    version: semver::Version,
+                }
+                let minimal_plan: Result<MinimalPlan, _> =
+                    serde_json::from_str(&install_receipt_string);
+                match minimal_plan {
+                    Ok(minimal_plan) => {
+                        return Err(plan_err).wrap_err_with(|| {
+                            let plan_version = minimal_plan.version;
+                            let current_version = current_version().map(|v| v.to_string()).unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
+                            format!(
+                            "\
+                            Unable to parse plan, this plan was created by `nix-installer` version `{plan_version}`, this is `nix-installer` version `{current_version}`\n\
+                            To uninstall, either run  `/nix/nix-installer uninstall` or `curl --proto '=https' --tlsv1.2 -sSf -L https://artifacts.nixos.org/nix-installer | sh -s -- uninstall`\
+                            ").red().to_string()
+                        });
+                    },
+                    Err(_minimal_plan_err) => return Err(plan_err)?,
+                }
+            },
+        };
+
+        // Termux installs are the user's own, and there is no `root` to escalate to
+        if plan.planner.requires_root() {
+            ensure_root()?;
+        }
+
+        if let Err(e) = plan.check_compatible() {
+            return Ok(report_failure_message(
+                format!("\
+                    {e}\n\
+                    \n\
+                    Found existing plan in `{receipt}` which was created by a version incompatible `nix-installer`.\n\
+                    \n
+                    To uninstall, either run `/nix/nix-installer uninstall` or `curl --proto '=https' --tlsv1.2 -sSf -L https://artifacts.nixos.org/nix-installer | sh -s -- uninstall`\n\
+                    \n\
+                ", receipt = receipt.display()),
+                ErrorCode::IncompatibleVersion,
+            ));
+        }
+
+        if let Err(err) = plan.pre_uninstall_check() {
+            if let Some(expected) = err.expected() {
+                return Ok(report_failure(&*expected, err.error_code()));
+            }
+            Err(err)?
+        }
+
+        // Checked before anything is removed, an export into `/nix` would be removed along with it
+        let backup_store = match backup_store
+            .as_deref()
+            .map(crate::store_export::check_backup_dest)
+            .transpose()
+        {
+            Ok(backup_store) => backup_store,
+            Err(e) => {
+                return Ok(report_failure_message(
+                    e.to_string(),
+                    ErrorCode::InvalidPlan,
+                ));
+            },
+        };
+
+        if !confirm_protected(&plan, confirm_hostname, no_confirm)? {
+            return Ok(report_failure_message(
+                "\
+                This Nix install was installed with `--protect`, and the hostname of this machine was not confirmed.\n\
+                To uninstall it, pass `--confirm-hostname` with the hostname of this machine.\
+                ",
+                ErrorCode::Failure,
+            ));
+        }
+
+        if !ignore_dependents {
+            let dependents = dependents::find_dependents();
+            if !dependents.is_empty() {
+                let listing = dependents
+                    .iter()
+                    .map(|dependent| format!("* {dependent}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let warning = format!(
+                    "\
+                    Found the following tools relying on Nix, which will stop working once it is uninstalled:\n\
+                    \n\
+                    {listing}\n\
+                    "
+                );
+                if no_confirm {
+                    eprintln!("{}", warning.yellow());
+                    return Ok(report_failure_message(
+                        "Remove them first, or pass `--ignore-dependents` to uninstall anyway.",
+                        ErrorCode::Conflict,
+                    ));
+                }
+                match interaction::prompt(warning.yellow().to_string(), PromptChoice::No, true)? {
+                    PromptChoice::Yes => (),
+                    PromptChoice::No | PromptChoice::Explain => {
+                        interaction::clean_exit_with_message(
+                            "Okay, not continuing with the uninstallation. Bye!",
+                        )
+                    },
+                }
+            }
+        }
+
+        if !no_confirm {
+            let mut currently_explaining = explain;
+            loop {
+                match interaction::prompt(
+                    plan.describe_uninstall(currently_explaining)
+                        .map_err(|e| eyre!(e))?,
+                    PromptChoice::Yes,
+                    currently_explaining,
+                )? {
+                    PromptChoice::Yes => break,
+                    PromptChoice::Explain => currently_explaining = true,
+                    PromptChoice::No => interaction::clean_exit_with_message(
+                        "Okay, not continuing with the uninstallation. Bye!",
+                    ),
+                }
+            }
+        }
+
+        // Nothing is removed if the export fails, so the store is never lost
+        if let Some(backup_store) = &backup_store {
+            let count = crate::store_export::export_store(backup_store).wrap_err_with(|| {
+                format!(
+                    "Exporting the Nix store to `{}`, not uninstalling",
+                    backup_store.display()
+                )
+            })?;
+            tracing::info!(
+                "Exported {count} store paths to `{}`, pass `--restore-store {}` to `nix-installer install` to import them",
+                backup_store.display(),
+                backup_store.display()
+            );
+        }
+
+        let cancel_signal = setup_signal_handler();
+
+        plan.set_action_timeout(action_timeout.map(Duration::from_secs));
+        let res = plan.uninstall(Some(cancel_signal));
+        match res {
+            Err(err @ NixInstallerError::ActionRevert(_)) => {
+                tracing::error!("Uninstallation complete, some errors encountered");
+                return Err(err)?;
+            },
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    return Ok(report_failure(&*expected, err.error_code()));
+                }
+                return Err(err)?;
+            },
+            _ => (),
+        }
+
+        // Receipts kept in a state directory outside of `/nix` are not removed along with the store
+        if !receipt_was_given {
+            crate::util::remove_file(&receipt, OnMissing::Ignore)
+                .wrap_err_with(|| format!("Removing receipt at {}", receipt.display()))?;
+        }
+
+        println!(
+            "\
+            {success}\n\
+            ",
+            success = "Nix was uninstalled successfully!".green().bold(),
+        );
+
+        if thorough {
+            return leftovers::remove_leftovers(no_confirm);
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,425 @@
+/*! Templates for what the installer adds to the Nix daemon's service
+
+The systemd units and `launchd` plist themselves come from Nix, so what the installer changes is
+added on top: systemd drop-ins, rendered from a [`SystemdDropIn`], and keys merged into the plist
+with [`merge_launchd_plist`]. [`DaemonLimits`] are rendered into both. `--daemon-unit-override`
+goes through the same layer, as a [`DaemonUnitOverride`] applied after the installer's own
+changes so it wins over them. On macOS the daemon can also be run under `caffeinate`, with
+[`caffeinate_launchd_plist`].
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::action::ActionErrorKind;
+use crate::settings::DaemonIoClass;
+
+/// The first line of every file generated for the daemon's service
+const GENERATED_HEADER: &str = "# Generated by https://github.com/NixOS/nix-installer\n";
+
+/// A systemd drop-in of `[Section]`s holding `Key=Value` lines, in order and possibly repeated
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct SystemdDropIn {
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl SystemdDropIn {
+    /// Add `key=value` to `section`, after any lines already in it
+    pub(crate) fn entry(
+        mut self,
+        section: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        let section = section.into();
+        let entry = (key.into(), value.into());
+        match self.sections.iter_mut().find(|(name, _)| *name == section) {
+            Some((_, entries)) => entries.push(entry),
+            None => self.sections.push((section, vec![entry])),
+        }
+        self
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut content = String::from(GENERATED_HEADER);
+        for (index, (section, entries)) in self.sections.iter().enumerate() {
+            if index > 0 {
+                content.push('\n');
+            }
+            content.push_str(&format!("[{section}]\n"));
+            for (key, value) in entries {
+                content.push_str(&format!("{key}={value}\n"));
+            }
+        }
+        content
+    }
+}
+
+/// Quote `value` for a systemd `Environment=` line, escaping `%` from systemd's specifiers
+pub(crate) fn systemd_environment(key: &str, value: &str) -> String {
+    let value = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{key}={value}\"")
+}
+
+/// Merge `keys` into the plist at `dest`, key by key into dictionaries both have
+pub(crate) fn merge_launchd_plist(
+    dest: &Path,
+    keys: plist::Dictionary,
+) -> Result<(), ActionErrorKind> {
+    let mut plist = plist::Value::from_file(dest)?;
+    let Some(dict) = plist.as_dictionary_mut() else {
+        return Err(ActionErrorKind::Custom(
+            format!("`{}` is not a dictionary", dest.display()).into(),
+        ));
+    };
+    merge_dictionary(dict, keys);
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &plist)?;
+    crate::util::write_atomic(dest, buf)?;
+    Ok(())
+}
+
+fn merge_dictionary(dict: &mut plist::Dictionary, keys: plist::Dictionary) {
+    for (key, value) in keys {
+        match (dict.get_mut(&key), value) {
+            (Some(plist::Value::Dictionary(existing)), plist::Value::Dictionary(value)) => {
+                merge_dictionary(existing, value)
+            },
+            (_, value) => {
+                dict.insert(key, value);
+            },
+        }
+    }
+}
+
+/// Limits on the Nix daemon's resources, so large builds leave the rest of the machine responsive
+///
+/// `launchd` has no equivalent of `CPUWeight=` or `MemoryMax=`, so only the niceness and I/O
+/// class apply there.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DaemonLimits {
+    pub nice: Option<i8>,
+    pub cpu_weight: Option<u16>,
+    pub io_class: Option<DaemonIoClass>,
+    /// As systemd's `MemoryMax=` takes it, such as `8G` or `50%`
+    pub memory_max: Option<String>,
+}
+
+impl DaemonLimits {
+    pub(crate) fn systemd_drop_in(&self) -> SystemdDropIn {
+        let Self {
+            nice,
+            cpu_weight,
+            io_class,
+            memory_max,
+        } = self;
+        [
+            ("Nice", nice.map(|nice| nice.to_string())),
+            ("CPUWeight", cpu_weight.map(|weight| weight.to_string())),
+            ("IOSchedulingClass", io_class.map(|class| class.to_string())),
+            ("MemoryMax", memory_max.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .fold(SystemdDropIn::default(), |drop_in, (key, value)| {
+            drop_in.entry("Service", key, value)
+        })
+    }
+
+    /// The keys to merge into the `launchd` plist, warning about the limits it can't set
+    pub(crate) fn launchd_keys(&self) -> plist::Dictionary {
+        if self.cpu_weight.is_some() || self.memory_max.is_some() {
+            tracing::warn!(
+                "launchd can't weight the Nix daemon's CPU use or limit its memory, only its niceness and I/O class are set"
+            );
+        }
+        let mut keys = plist::Dictionary::new();
+        if let Some(nice) = self.nice {
+            keys.insert("Nice".into(), plist::Value::Integer(i64::from(nice).into()));
+        }
+        if let Some(io_class) = self.io_class {
+            keys.insert(
+                "LowPriorityIO".into(),
+                plist::Value::Boolean(io_class == DaemonIoClass::Idle),
+            );
+        }
+        keys
+    }
+}
+
+/// Check `value` is a size systemd's `MemoryMax=` takes: bytes (with a `K`, `M`, `G` or `T` suffix), a percentage, or `infinity`
+pub(crate) fn parse_memory_max(value: &str) -> Result<String, String> {
+    let digits = value
+        .strip_suffix(['K', 'M', 'G', 'T', '%'])
+        .unwrap_or(value);
+    let valid = value == "infinity"
+        || (!digits.is_empty()
+            && digits.bytes().all(|byte| byte.is_ascii_digit())
+            && (!value.ends_with('%') || digits.parse::<u8>().is_ok_and(|percent| percent <= 100)));
+    match valid {
+        true => Ok(value.to_string()),
+        false => Err(format!(
+            "`{value}` is not a size such as `8G`, a percentage of memory such as `50%`, or `infinity`"
+        )),
+    }
+}
+
+/// `caffeinate` holding assertions against idle sleep (`-i`) and, on AC power, system sleep (`-s`)
+const CAFFEINATE: &[&str] = &["/usr/bin/caffeinate", "-i", "-s"];
+
+/// Run the daemon in the plist at `dest` under `caffeinate`, so the Mac stays awake while it runs
+///
+/// The assertions are released when the daemon exits, and removing the plist removes them.
+pub(crate) fn caffeinate_launchd_plist(dest: &Path) -> Result<(), ActionErrorKind> {
+    let mut plist = plist::Value::from_file(dest)?;
+    let Some(dict) = plist.as_dictionary_mut() else {
+        return Err(ActionErrorKind::Custom(
+            format!("`{}` is not a dictionary", dest.display()).into(),
+        ));
+    };
+    caffeinate_program(dict);
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &plist)?;
+    crate::util::write_atomic(dest, buf)?;
+    Ok(())
+}
+
+fn caffeinate_program(dict: &mut plist::Dictionary) {
+    let mut arguments = dict
+        .get("ProgramArguments")
+        .and_then(plist::Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    if arguments.first().and_then(plist::Value::as_string) == Some(CAFFEINATE[0]) {
+        return;
+    }
+    // With `Program` set, the first of `ProgramArguments` is only the `argv[0]` it's run with
+    if let Some(program) = dict.remove("Program") {
+        match arguments.first_mut() {
+            Some(argv0) => *argv0 = program,
+            None => arguments.push(program),
+        }
+    }
+    let mut caffeinated = CAFFEINATE
+        .iter()
+        .map(|argument| plist::Value::String(argument.to_string()))
+        .collect::<Vec<_>>();
+    caffeinated.append(&mut arguments);
+    dict.insert("ProgramArguments".into(), plist::Value::Array(caffeinated));
+}
+
+/// The content of a `--daemon-unit-override` file, kept in the receipt
+///
+/// For systemd it is installed as a drop-in, for `launchd` it is a plist dictionary whose keys
+/// are merged into the daemon's plist.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DaemonUnitOverride {
+    /// Where the override was read from
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl DaemonUnitOverride {
+    pub(crate) fn read(path: &Path) -> Result<Self, ActionErrorKind> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            content,
+        })
+    }
+
+    /// Check the override is a systemd drop-in: `[Section]`s of `Key=Value` lines, and comments
+    pub(crate) fn check_systemd(&self) -> Result<(), DaemonUnitError> {
+        let mut in_section = false;
+        let mut continued = false;
+        for (index, line) in self.content.lines().enumerate() {
+            let line = line.trim();
+            let is_continuation = continued;
+            continued = line.ends_with('\\');
+            if is_continuation || line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                in_section = true;
+            } else if !in_section || !line.contains('=') {
+                return Err(DaemonUnitError::NotSystemd(self.path.clone(), index + 1));
+            }
+        }
+        match in_section {
+            true => Ok(()),
+            false => Err(DaemonUnitError::Empty(self.path.clone())),
+        }
+    }
+
+    /// The keys to merge into the `launchd` plist
+    pub(crate) fn launchd_keys(&self) -> Result<plist::Dictionary, DaemonUnitError> {
+        let value = plist::Value::from_reader_xml(self.content.as_bytes())
+            .map_err(|e| DaemonUnitError::Plist(self.path.clone(), e))?;
+        match value {
+            plist::Value::Dictionary(keys) => Ok(keys),
+            _ => Err(DaemonUnitError::NotDictionary(self.path.clone())),
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum DaemonUnitError {
+    #[error(
+        "`{}` line {} is not a `[Section]` or a `Key=Value` inside one, as a systemd drop-in needs",
+        .0.display(),
+        .1
+    )]
+    NotSystemd(PathBuf, usize),
+    #[error("`{}` has no `[Section]`, so would change nothing", .0.display())]
+    Empty(PathBuf),
+    #[error("`{}` is not an XML plist", .0.display())]
+    Plist(PathBuf, #[source] plist::Error),
+    #[error("`{}` is not a plist dictionary of keys to merge into the daemon's plist", .0.display())]
+    NotDictionary(PathBuf),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_drop_ins() {
+        let drop_in = SystemdDropIn::default()
+            .entry("Service", "Environment", systemd_environment("A", "100%"))
+            .entry("Unit", "After", "network-online.target")
+            .entry("Service", "Environment", systemd_environment("B", "\"b\""));
+        assert_eq!(
+            drop_in.render(),
+            "# Generated by https://github.com/NixOS/nix-installer\n\
+            [Service]\n\
+            Environment=\"A=100%%\"\n\
+            Environment=\"B=\\\"b\\\"\"\n\
+            \n\
+            [Unit]\n\
+            After=network-online.target\n"
+        );
+    }
+
+    #[test]
+    fn renders_limits() {
+        let limits = DaemonLimits {
+            nice: Some(10),
+            io_class: Some(DaemonIoClass::Idle),
+            memory_max: Some("50%".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.systemd_drop_in().render(),
+            "# Generated by https://github.com/NixOS/nix-installer\n\
+            [Service]\n\
+            Nice=10\n\
+            IOSchedulingClass=idle\n\
+            MemoryMax=50%\n"
+        );
+        let keys = limits.launchd_keys();
+        assert_eq!(keys.get("Nice"), Some(&plist::Value::Integer(10.into())));
+        assert_eq!(
+            keys.get("LowPriorityIO"),
+            Some(&plist::Value::Boolean(true))
+        );
+
+        for valid in ["8G", "1073741824", "50%", "infinity"] {
+            assert!(parse_memory_max(valid).is_ok(), "{valid}");
+        }
+        for invalid in ["", "G", "8GB", "-1G", "150%"] {
+            assert!(parse_memory_max(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn caffeinates_the_daemon_once() {
+        let mut dict = plist::Dictionary::new();
+        dict.insert(
+            "ProgramArguments".into(),
+            plist::Value::Array(vec![
+                "/bin/sh".into(),
+                "-c".into(),
+                "exec /nix/var/nix/profiles/default/bin/nix-daemon".into(),
+            ]),
+        );
+        caffeinate_program(&mut dict);
+        caffeinate_program(&mut dict);
+        let arguments = dict
+            .get("ProgramArguments")
+            .and_then(plist::Value::as_array)
+            .map(|arguments| {
+                arguments
+                    .iter()
+                    .filter_map(plist::Value::as_string)
+                    .collect::<Vec<_>>()
+            });
+        assert_eq!(
+            arguments,
+            Some(vec![
+                "/usr/bin/caffeinate",
+                "-i",
+                "-s",
+                "/bin/sh",
+                "-c",
+                "exec /nix/var/nix/profiles/default/bin/nix-daemon"
+            ])
+        );
+    }
+
+    #[test]
+    fn checks_systemd_overrides() {
+        let unit_override = |content: &str| DaemonUnitOverride {
+            path: PathBuf::from("override.conf"),
+            content: content.to_string(),
+        };
+        assert!(
+            unit_override(
+                "# Limits\n[Service]\nLimitNOFILE=65536\nExecStartPre=/bin/true \\\n  --flag\n"
+            )
+            .check_systemd()
+            .is_ok()
+        );
+        assert!(matches!(
+            unit_override("LimitNOFILE=65536\n").check_systemd(),
+            Err(DaemonUnitError::NotSystemd(_, 1))
+        ));
+        assert!(matches!(
+            unit_override("# Nothing\n").check_systemd(),
+            Err(DaemonUnitError::Empty(_))
+        ));
+    }
+
+    #[test]
+    fn merges_launchd_dictionaries() -> Result<(), Box<dyn std::error::Error>> {
+        let unit_override = DaemonUnitOverride {
+            path: PathBuf::from("override.plist"),
+            content: r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+  <key>EnvironmentVariables</key>
+  <dict><key>NIX_SSL_CERT_FILE</key><string>/etc/ssl/cert.pem</string></dict>
+  <key>SoftResourceLimits</key>
+  <dict><key>NumberOfFiles</key><integer>65536</integer></dict>
+</dict>
+</plist>"#
+                .to_string(),
+        };
+        let mut dict = plist::Dictionary::new();
+        let mut environment = plist::Dictionary::new();
+        environment.insert("OBJC_DISABLE_INITIALIZE_FORK_SAFETY".into(), "YES".into());
+        dict.insert("EnvironmentVariables".into(), environment.into());
+
+        merge_dictionary(&mut dict, unit_override.launchd_keys()?);
+        let environment = dict
+            .get("EnvironmentVariables")
+            .and_then(plist::Value::as_dictionary)
+            .ok_or("no EnvironmentVariables")?;
+        assert_eq!(environment.len(), 2);
+        assert!(dict.contains_key("SoftResourceLimits"));
+        Ok(())
+    }
+}
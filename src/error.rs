@@ -32,6 +32,18 @@ pub enum NixInstallerError {
         }
     }).collect::<Vec<_>>().join("\n"))]
     ActionRevert(Vec<ActionError>),
+    /// An [`Action`](crate::action::Action) failed, and the actions before it were reverted because of [`OnFailure::Rollback`](crate::OnFailure::Rollback)
+    #[error("Error executing action, the partial install was rolled back")]
+    RolledBack(#[source] ActionError),
+    /// An [`Action`](crate::action::Action) failed, and reverting the actions before it failed too
+    #[error("Error executing action, rolling back the partial install also failed\n{}", .1.iter().map(|err| {
+        if let Some(source) = err.source() {
+            format!("{err}\n{source}\n")
+        } else {
+            format!("{err}\n")
+        }
+    }).collect::<Vec<_>>().join("\n"))]
+    RollbackFailed(#[source] Box<ActionError>, Vec<ActionError>),
     /// An error while writing the [`InstallPlan`](crate::InstallPlan)
     #[error("Recording install receipt")]
     RecordingReceipt(PathBuf, #[source] std::io::Error),
@@ -82,9 +94,36 @@ pub enum NixInstallerError {
         "Could not parse `nix-installer`'s version `{0}` as a valid version according to Semantic Versioning, therefore the plan version compatibility cannot be checked"
     )]
     InvalidCurrentVersion(String, semver::Error),
+    /// Could not read a plan file
+    #[error("Reading plan `{}`", .0.display())]
+    ReadingPlan(PathBuf, #[source] std::io::Error),
+    /// A plan file is not valid JSON, or does not describe an [`InstallPlan`](crate::InstallPlan)
+    #[error("Could not parse plan `{}`: {}", .0.display(), .1)]
+    ParsingPlan(PathBuf, #[source] serde_json::Error),
+    /// A plan file has fields which this version of `nix-installer` does not know
+    #[error("Plan `{}` has fields this version of `nix-installer` does not know, they may be misspelled or from another version: {}", .0.display(), .1.join(", "))]
+    UnknownPlanFields(PathBuf, Vec<String>),
+    /// A `${env:...}` template in a plan file is unterminated, or does not name a valid environment variable
+    #[error("Invalid template `{0}` in plan, expected `${{env:NAME}}` or `${{env:NAME:-default}}`")]
+    InvalidPlanTemplate(String),
+    /// A `${env:NAME}` template in a plan file has no default and `NAME` is not set
+    #[error(
+        "Plan uses `${{env:{0}}}`, but the environment variable `{0}` is not set, set it or give a default with `${{env:{0}:-default}}`"
+    )]
+    UnsetPlanVariable(String),
     /// This version of `nix-installer` is not compatible with this plan's version
     #[error("`nix-installer` version `{}` is not compatible with this plan's version `{}`", .binary, .plan)]
     IncompatibleVersion { binary: Version, plan: Version },
+    /// The plan uses actions or a planner from an [`Extension`](crate::extension::Extension) this binary does not include
+    #[error(
+        "This plan was made with the `{name}` extension version `{version}`, which this `nix-installer` does not include (at a compatible version), use the `nix-installer` which made it"
+    )]
+    MissingExtension { name: String, version: Version },
+    /// An [`Extension`](crate::extension::Extension)'s version is not a valid Semantic Versioning version
+    #[error(
+        "Could not parse the version `{1}` of the `{0}` extension as a valid version according to Semantic Versioning"
+    )]
+    InvalidExtensionVersion(String, String, semver::Error),
 }
 
 pub(crate) trait HasExpectedErrors: std::error::Error + Sized + Send + Sync {
@@ -96,6 +135,8 @@ impl HasExpectedErrors for NixInstallerError {
         match self {
             NixInstallerError::Action(action_error) => action_error.kind().expected(),
             NixInstallerError::ActionRevert(_) => None,
+            NixInstallerError::RolledBack(action_error) => action_error.kind().expected(),
+            NixInstallerError::RollbackFailed(_, _) => None,
             this @ NixInstallerError::SelfTest(_) => Some(Box::new(this)),
             NixInstallerError::RecordingReceipt(_, _) => None,
             NixInstallerError::CopyingSelf(_) => None,
@@ -104,11 +145,179 @@ impl HasExpectedErrors for NixInstallerError {
             NixInstallerError::SemVer(_) => None,
             NixInstallerError::Planner(planner_error) => planner_error.expected(),
             NixInstallerError::InstallSettings(_) => None,
+            NixInstallerError::ReadingPlan(_, _) => None,
+            this @ NixInstallerError::ParsingPlan(_, _) => Some(Box::new(this)),
+            this @ NixInstallerError::UnknownPlanFields(_, _) => Some(Box::new(this)),
+            this @ NixInstallerError::InvalidPlanTemplate(_) => Some(Box::new(this)),
+            this @ NixInstallerError::UnsetPlanVariable(_) => Some(Box::new(this)),
             this @ NixInstallerError::InvalidVersionRequirement(_, _) => Some(Box::new(this)),
             this @ NixInstallerError::InvalidCurrentVersion(_, _) => Some(Box::new(this)),
             this @ NixInstallerError::IncompatibleVersion { binary: _, plan: _ } => {
                 Some(Box::new(this))
             },
+            this @ NixInstallerError::MissingExtension { .. } => Some(Box::new(this)),
+            NixInstallerError::InvalidExtensionVersion(_, _, _) => None,
         }
     }
 }
+
+/**
+A stable category of error, for programs wrapping `nix-installer` to act on without matching on
+error messages
+
+Each has a name (as in `--error-format json`) and a process exit code, which won't change between
+versions. Errors without a more specific category are [`ErrorCode::Failure`].
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Anything without a more specific code
+    Failure,
+    /// Cancelled, such as with Ctrl+C
+    Cancelled,
+    /// Not running as `root`, and escalating with `sudo` failed
+    NeedsRoot,
+    /// Nix, or another install by `nix-installer`, is already installed
+    AlreadyInstalled,
+    /// There is no install receipt to act on
+    NotInstalled,
+    /// The plan or receipt is from an incompatible version, or needs an extension this binary lacks
+    IncompatibleVersion,
+    /// The host's operating system, architecture or init isn't supported by the planner
+    UnsupportedPlatform,
+    /// A plan file or setting is invalid
+    InvalidPlan,
+    /// Existing files, users or groups conflict with what would be installed
+    Conflict,
+    /// A step of the install failed
+    ActionFailed,
+    /// Reverting failed, the system may have a partial install
+    RevertFailed,
+    /// A step failed and the partial install was rolled back
+    RolledBack,
+    /// Installed, but the installed Nix failed its self-test
+    SelfTestFailed,
+    /// A step's command hung, and was killed after `--action-timeout`
+    TimedOut,
+    /// There isn't enough free space to install, see `--min-free-space`
+    InsufficientSpace,
+}
+
+impl ErrorCode {
+    /// The exit code of `nix-installer` when it fails with this error
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCode::Failure => 1,
+            ErrorCode::Cancelled => 10,
+            ErrorCode::NeedsRoot => 11,
+            ErrorCode::AlreadyInstalled => 12,
+            ErrorCode::NotInstalled => 13,
+            ErrorCode::IncompatibleVersion => 14,
+            ErrorCode::UnsupportedPlatform => 15,
+            ErrorCode::InvalidPlan => 16,
+            ErrorCode::Conflict => 17,
+            ErrorCode::ActionFailed => 18,
+            ErrorCode::RevertFailed => 19,
+            ErrorCode::RolledBack => 20,
+            ErrorCode::SelfTestFailed => 21,
+            ErrorCode::TimedOut => 22,
+            ErrorCode::InsufficientSpace => 23,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::Failure => write!(f, "failure"),
+            ErrorCode::Cancelled => write!(f, "cancelled"),
+            ErrorCode::NeedsRoot => write!(f, "needs_root"),
+            ErrorCode::AlreadyInstalled => write!(f, "already_installed"),
+            ErrorCode::NotInstalled => write!(f, "not_installed"),
+            ErrorCode::IncompatibleVersion => write!(f, "incompatible_version"),
+            ErrorCode::UnsupportedPlatform => write!(f, "unsupported_platform"),
+            ErrorCode::InvalidPlan => write!(f, "invalid_plan"),
+            ErrorCode::Conflict => write!(f, "conflict"),
+            ErrorCode::ActionFailed => write!(f, "action_failed"),
+            ErrorCode::RevertFailed => write!(f, "revert_failed"),
+            ErrorCode::RolledBack => write!(f, "rolled_back"),
+            ErrorCode::SelfTestFailed => write!(f, "self_test_failed"),
+            ErrorCode::TimedOut => write!(f, "timed_out"),
+            ErrorCode::InsufficientSpace => write!(f, "insufficient_space"),
+        }
+    }
+}
+
+impl NixInstallerError {
+    /// The [`ErrorCode`] of this error
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            NixInstallerError::Action(action_error) => action_error
+                .kind()
+                .error_code()
+                .unwrap_or(ErrorCode::ActionFailed),
+            NixInstallerError::ActionRevert(_) | NixInstallerError::RollbackFailed(_, _) => {
+                ErrorCode::RevertFailed
+            },
+            NixInstallerError::RolledBack(_) => ErrorCode::RolledBack,
+            NixInstallerError::SelfTest(_) => ErrorCode::SelfTestFailed,
+            NixInstallerError::Cancelled => ErrorCode::Cancelled,
+            NixInstallerError::Planner(planner_error) => planner_error.error_code(),
+            NixInstallerError::InstallSettings(_)
+            | NixInstallerError::ReadingPlan(_, _)
+            | NixInstallerError::ParsingPlan(_, _)
+            | NixInstallerError::UnknownPlanFields(_, _)
+            | NixInstallerError::InvalidPlanTemplate(_)
+            | NixInstallerError::UnsetPlanVariable(_) => ErrorCode::InvalidPlan,
+            NixInstallerError::IncompatibleVersion { .. }
+            | NixInstallerError::MissingExtension { .. } => ErrorCode::IncompatibleVersion,
+            NixInstallerError::RecordingReceipt(_, _)
+            | NixInstallerError::CopyingSelf(_)
+            | NixInstallerError::SerializingReceipt(_)
+            | NixInstallerError::SemVer(_)
+            | NixInstallerError::InvalidVersionRequirement(_, _)
+            | NixInstallerError::InvalidCurrentVersion(_, _)
+            | NixInstallerError::InvalidExtensionVersion(_, _, _) => ErrorCode::Failure,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{ActionErrorKind, ActionTag};
+
+    #[test]
+    fn error_codes_are_stable() {
+        let nested = |kind| {
+            ActionError::new(
+                ActionTag("configure_nix"),
+                ActionErrorKind::Child(Box::new(ActionError::new(
+                    ActionTag("create_directory"),
+                    kind,
+                ))),
+            )
+        };
+
+        let cancelled = NixInstallerError::Action(nested(ActionErrorKind::Cancelled));
+        assert_eq!(cancelled.error_code(), ErrorCode::Cancelled);
+        assert_eq!(cancelled.error_code().exit_code(), 10);
+        assert_eq!(
+            serde_json::to_value(cancelled.error_code()).unwrap(),
+            serde_json::json!("cancelled")
+        );
+        assert_eq!(
+            NixInstallerError::Action(nested(ActionErrorKind::NoUser("nixbld1".into())))
+                .error_code(),
+            ErrorCode::ActionFailed
+        );
+        assert_eq!(
+            NixInstallerError::RolledBack(nested(ActionErrorKind::Cancelled)).error_code(),
+            ErrorCode::RolledBack
+        );
+        assert_eq!(
+            NixInstallerError::Planner(PlannerError::NixExists).error_code(),
+            ErrorCode::AlreadyInstalled
+        );
+    }
+}
@@ -0,0 +1,211 @@
+/*! Registering [`Action`](crate::action::Action)s and [`Planner`](crate::planner::Planner)s from other crates
+
+Custom actions and planners (see [`action`](crate::action) and [`planner`](crate::planner)) are
+`#[typetag::serde]` implementations, so any crate depending on `nix-installer` can add them, and
+plans using them round-trip through receipts as long as the binary reading the receipt links the
+crate too. A receipt read by a binary without them would fail with an opaque "unknown variant"
+error, part way through deserializing its actions.
+
+An [`Extension`] names the actions and planners a crate adds, along with its version. When an
+[`InstallPlan`](crate::InstallPlan) is planned, the extensions its actions or planner come from
+are recorded in it, and reading it back first checks each one is registered at a compatible
+version (the same `^version` requirement as the plan's own version), failing with
+[`NixInstallerError::MissingExtension`] otherwise.
+
+```rust,no_run
+nix_installer::register_extension!(nix_installer::extension::Extension {
+    name: "my-installer",
+    version: env!("CARGO_PKG_VERSION"),
+    actions: &["my_action"],
+    planners: &["my-planner"],
+});
+```
+
+The names are the `#[typetag::serde(name = ...)]` of each action and planner.
+*/
+
+use semver::{Version, VersionReq};
+
+use crate::NixInstallerError;
+
+#[doc(hidden)]
+pub use inventory;
+
+/// A crate adding [`Action`](crate::action::Action)s or [`Planner`](crate::planner::Planner)s, see [`register_extension!`](crate::register_extension)
+#[derive(Debug)]
+pub struct Extension {
+    pub name: &'static str,
+    /// A Semantic Versioning version, such as `env!("CARGO_PKG_VERSION")`
+    pub version: &'static str,
+    /// The typetag names of the actions it adds
+    pub actions: &'static [&'static str],
+    /// The typetag names of the planners it adds
+    pub planners: &'static [&'static str],
+}
+
+inventory::collect!(Extension);
+
+/// Register an [`Extension`], so plans using its actions or planners record that they need it
+#[macro_export]
+macro_rules! register_extension {
+    ($extension:expr $(,)?) => {
+        $crate::extension::inventory::submit! { $extension }
+    };
+}
+
+/// The registered [`Extension`]s
+pub fn extensions() -> impl Iterator<Item = &'static Extension> {
+    inventory::iter::<Extension>.into_iter()
+}
+
+/// An [`Extension`] a plan was made with, recorded in its receipt
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExtensionRequirement {
+    pub name: String,
+    pub version: Version,
+}
+
+/// The registered extensions adding any of the actions or the planner in `plan`, a serialized [`InstallPlan`](crate::InstallPlan)
+pub(crate) fn required(
+    plan: &serde_json::Value,
+) -> Result<Vec<ExtensionRequirement>, NixInstallerError> {
+    let planner = plan.pointer("/planner/planner").and_then(|v| v.as_str());
+    let mut action_names = vec![];
+    if let Some(actions) = plan.get("actions") {
+        collect_action_names(actions, &mut action_names);
+    }
+
+    let mut required = vec![];
+    for extension in extensions() {
+        let used = planner.is_some_and(|planner| extension.planners.contains(&planner))
+            || action_names
+                .iter()
+                .any(|name| extension.actions.contains(name));
+        if !used
+            || required
+                .iter()
+                .any(|r: &ExtensionRequirement| r.name == extension.name)
+        {
+            continue;
+        }
+        let version = Version::parse(extension.version).map_err(|e| {
+            NixInstallerError::InvalidExtensionVersion(
+                extension.name.to_string(),
+                extension.version.to_string(),
+                e,
+            )
+        })?;
+        required.push(ExtensionRequirement {
+            name: extension.name.to_string(),
+            version,
+        });
+    }
+    required.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(required)
+}
+
+/// The `action_name` of every action, including those nested in other actions
+fn collect_action_names<'a>(value: &'a serde_json::Value, names: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(name) = map.get("action_name").and_then(|v| v.as_str()) {
+                names.push(name);
+            }
+            map.values()
+                .for_each(|value| collect_action_names(value, names));
+        },
+        serde_json::Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_action_names(value, names)),
+        _ => (),
+    }
+}
+
+/// Check each of `requirements` is registered, at a compatible version
+pub fn check_available(requirements: &[ExtensionRequirement]) -> Result<(), NixInstallerError> {
+    for requirement in requirements {
+        let req = VersionReq::parse(&requirement.version.to_string()).map_err(|e| {
+            NixInstallerError::InvalidVersionRequirement(requirement.version.to_string(), e)
+        })?;
+        let available = extensions().any(|extension| {
+            extension.name == requirement.name
+                && Version::parse(extension.version).is_ok_and(|version| req.matches(&version))
+        });
+        if !available {
+            return Err(NixInstallerError::MissingExtension {
+                name: requirement.name.clone(),
+                version: requirement.version.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Deserialize the extensions recorded in a receipt, failing before its actions are read if any are missing
+pub(crate) fn deserialize_available<'de, D>(
+    deserializer: D,
+) -> Result<Vec<ExtensionRequirement>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let requirements: Vec<ExtensionRequirement> = serde::Deserialize::deserialize(deserializer)?;
+    check_available(&requirements).map_err(serde::de::Error::custom)?;
+    Ok(requirements)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    crate::register_extension!(Extension {
+        name: "test-extension",
+        version: "1.2.0",
+        actions: &["test_extension_action"],
+        planners: &[],
+    });
+
+    #[test]
+    fn records_and_checks_extensions() -> Result<(), NixInstallerError> {
+        let builtin = serde_json::json!({
+            "planner": { "planner": "linux" },
+            "actions": [{ "action": { "action_name": "create_directory" } }],
+        });
+        assert_eq!(required(&builtin)?, vec![]);
+
+        let nested = serde_json::json!({
+            "planner": { "planner": "linux" },
+            "actions": [{
+                "action": {
+                    "action_name": "create_nix_tree",
+                    "create_directories": [{ "action": { "action_name": "test_extension_action" } }],
+                },
+            }],
+        });
+        let requirements = required(&nested)?;
+        assert_eq!(
+            requirements,
+            vec![ExtensionRequirement {
+                name: "test-extension".into(),
+                version: Version::new(1, 2, 0),
+            }]
+        );
+        check_available(&requirements)?;
+
+        assert!(matches!(
+            check_available(&[ExtensionRequirement {
+                name: "test-extension".into(),
+                version: Version::new(2, 0, 0),
+            }]),
+            Err(NixInstallerError::MissingExtension { .. })
+        ));
+        assert!(matches!(
+            check_available(&[ExtensionRequirement {
+                name: "other-extension".into(),
+                version: Version::new(1, 0, 0),
+            }]),
+            Err(NixInstallerError::MissingExtension { .. })
+        ));
+        Ok(())
+    }
+}
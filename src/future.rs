@@ -0,0 +1,202 @@
+/*! Installing and uninstalling from async code
+
+With the `async` feature, [`InstallPlan::install_async`] and [`InstallPlan::uninstall_async`] run
+the plan on a thread of its own and return a [`PlanFuture`], so a GUI or other async program can
+await it without blocking its executor. It doesn't depend on any particular runtime.
+
+```rust,no_run
+use std::time::Duration;
+
+use nix_installer::{InstallPlan, OnFailure};
+
+# async fn async_install() -> color_eyre::Result<()> {
+let mut plan = InstallPlan::try_default()?;
+plan.set_action_timeout(Some(Duration::from_secs(15 * 60)));
+let (plan, result) = plan.install_async(None, None, OnFailure::Rollback).await;
+if let Err(e) = result {
+    tracing::error!("{e}");
+    let (_plan, result) = plan.uninstall_async().await;
+    result?;
+}
+#
+# Ok(())
+# }
+```
+
+Dropping the future before it completes cancels the plan, which stops before the next step (or
+part way through one which checks for cancellation) and records the receipt. A step which hangs
+isn't stopped by cancelling, give each step a timeout with [`InstallPlan::set_action_timeout`]
+before spawning the plan.
+*/
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, atomic::Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    InstallPlan, NixInstallerError, OnFailure,
+    plan::{CancelSignal, cancel_signal},
+    progress::ProgressReporter,
+    prompt::InteractionHandler,
+};
+
+/// The plan, and the result of installing or uninstalling it
+pub type PlanOutcome = (InstallPlan, Result<(), NixInstallerError>);
+
+impl InstallPlan {
+    /// Like [`InstallPlan::install_with_progress`], on a thread of its own
+    ///
+    /// The plan is handed back with the result, for uninstalling it or inspecting its actions.
+    pub fn install_async(
+        mut self,
+        interaction: Option<Arc<dyn InteractionHandler>>,
+        progress_reporter: Option<Arc<dyn ProgressReporter>>,
+        on_failure: OnFailure,
+    ) -> PlanFuture {
+        PlanFuture::spawn(move |cancel_signal| {
+            let result = self.install_with_progress(
+                Some(cancel_signal),
+                interaction,
+                progress_reporter,
+                on_failure,
+            );
+            (self, result)
+        })
+    }
+
+    /// Like [`InstallPlan::uninstall`], on a thread of its own
+    pub fn uninstall_async(mut self) -> PlanFuture {
+        PlanFuture::spawn(move |cancel_signal| {
+            let result = self.uninstall(Some(cancel_signal));
+            (self, result)
+        })
+    }
+}
+
+/// An install or uninstall running on a thread of its own, see [`future`](crate::future)
+#[derive(Debug)]
+pub struct PlanFuture {
+    shared: Arc<Mutex<Shared<PlanOutcome>>>,
+    cancel_signal: CancelSignal,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    outcome: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl PlanFuture {
+    fn spawn(run: impl FnOnce(CancelSignal) -> PlanOutcome + Send + 'static) -> Self {
+        let cancel_signal = cancel_signal();
+        let shared = spawn_blocking(cancel_signal.clone(), run);
+        Self {
+            shared,
+            cancel_signal,
+        }
+    }
+
+    /// Cancel the plan, it completes with [`NixInstallerError::Cancelled`] once the step being run stops
+    pub fn cancel(&self) {
+        self.cancel_signal.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Future for PlanFuture {
+    type Output = PlanOutcome;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_shared(&self.shared, cx)
+    }
+}
+
+impl Drop for PlanFuture {
+    fn drop(&mut self) {
+        let completed = self
+            .shared
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .outcome
+            .is_some();
+        if !completed {
+            self.cancel();
+        }
+    }
+}
+
+/// Run `run` on a thread of its own, storing its outcome and waking whoever is waiting for it
+fn spawn_blocking<T: Send + 'static>(
+    cancel_signal: CancelSignal,
+    run: impl FnOnce(CancelSignal) -> T + Send + 'static,
+) -> Arc<Mutex<Shared<T>>> {
+    let shared = Arc::new(Mutex::new(Shared {
+        outcome: None,
+        waker: None,
+    }));
+    let thread_shared = shared.clone();
+    std::thread::Builder::new()
+        .name("nix-installer-plan".into())
+        .spawn(move || {
+            let outcome = run(cancel_signal);
+            let mut shared = thread_shared.lock().unwrap_or_else(|e| e.into_inner());
+            shared.outcome = Some(outcome);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        })
+        .expect("Spawning a thread to run the plan on");
+    shared
+}
+
+fn poll_shared<T>(shared: &Mutex<Shared<T>>, cx: &mut Context<'_>) -> Poll<T> {
+    let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+    match shared.outcome.take() {
+        Some(outcome) => Poll::Ready(outcome),
+        None => {
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{task::Wake, thread::Thread, time::Duration};
+
+    use super::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    #[test]
+    fn completes_once_the_thread_finishes() {
+        let signal = cancel_signal();
+        let shared = spawn_blocking(signal.clone(), |signal: CancelSignal| {
+            while !signal.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            "cancelled"
+        });
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        assert!(poll_shared(&shared, &mut cx).is_pending());
+
+        signal.store(true, Ordering::Relaxed);
+        let outcome = loop {
+            if let Poll::Ready(outcome) = poll_shared(&shared, &mut cx) {
+                break outcome;
+            }
+            std::thread::park_timeout(Duration::from_secs(1));
+        };
+        assert_eq!(outcome, "cancelled");
+    }
+}
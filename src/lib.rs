@@ -1,5 +1,7 @@
 // .filter_map() predicates returns Some/None, which is more clear than .filter()'s -> bool predicates.
 #![allow(clippy::unnecessary_filter_map)]
+// Some internals are only used by the subcommands
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
 
 /*! The [Nix](https://github.com/NixOS/nix) installer
 
@@ -11,6 +13,7 @@
 * [`Planner`](planner::Planner): Something which can be used to plan out an [`InstallPlan`].
 
 It is possible to create custom [`Action`]s and [`Planner`](planner::Planner)s to suit the needs of your project, team, or organization.
+Registering them as an [`Extension`](extension::Extension) records them in the receipts of plans which use them.
 
 In the simplest case, `nix-installer` can be asked to determine a default plan for the platform and install
 it, uninstalling if anything goes wrong:
@@ -69,21 +72,38 @@ match plan.install(None) {
 */
 
 pub mod action;
+mod backup;
 #[cfg(feature = "cli")]
 pub mod cli;
+mod daemon_unit;
 mod error;
+pub mod extension;
+#[cfg(feature = "async")]
+pub mod future;
+pub mod locale;
+mod nix_conf;
 mod os;
 mod plan;
 pub mod planner;
 mod profile;
+pub mod progress;
+pub mod prompt;
+mod proxy;
 pub mod self_test;
+pub mod sensitive;
 pub mod settings;
+mod ssl_cert;
+mod store_export;
+mod style;
+mod timeout;
 mod util;
 
 use std::{ffi::OsStr, process::Output};
 
-pub use error::NixInstallerError;
-pub use plan::InstallPlan;
+pub use error::{ErrorCode, NixInstallerError};
+#[cfg(feature = "async")]
+pub use future::{PlanFuture, PlanOutcome};
+pub use plan::{ActionChange, InstallPlan, OnFailure, PlanDiff, SettingChange};
 use planner::BuiltinPlanner;
 
 use std::process::Command;
@@ -93,9 +113,12 @@ use crate::action::{Action, ActionErrorKind};
 #[tracing::instrument(level = "debug", skip_all, fields(command = %format!("{:?}", command)))]
 fn execute_command(command: &mut Command) -> Result<Output, ActionErrorKind> {
     tracing::trace!("Executing");
-    let output = command
-        .output()
-        .map_err(|e| ActionErrorKind::command(command, e))?;
+    let output = match timeout::deadline() {
+        Some(deadline) => timeout::output_before(command, deadline)?,
+        None => command
+            .output()
+            .map_err(|e| ActionErrorKind::command(command, e))?,
+    };
     match output.status.success() {
         true => {
             tracing::trace!(
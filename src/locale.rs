@@ -0,0 +1,286 @@
+/*! Translations of the messages `nix-installer` shows its users
+
+Plan descriptions, progress and prompts are written in English. A [`Catalog`] maps those messages
+to another language, and messages it has no translation for stay in English. The catalog is
+chosen by `NIX_INSTALLER_LANG` (such as `fr` or `pt_BR.UTF-8`, which also tries `pt`), read from
+`{lang}.json` in `NIX_INSTALLER_LOCALE_DIR` (by default [`DEFAULT_LOCALE_DIR`]), so distributions
+can ship translations alongside the installer.
+
+A catalog is a JSON object from each English message to its translation. Where a message has
+parts which vary, such as paths, the English message has a `{}` for each of them. The
+translation uses `{}` for them in the same order, or `{0}`, `{1}` and so on to reorder them:
+
+```json
+{
+  "Proceed?": "Continuer ?",
+  "Create directory `{}`": "Créer le répertoire `{}`",
+  "Move `{}` to `{}`": "Déplacer vers `{1}` depuis `{0}`"
+}
+```
+
+Embedders can instead provide a catalog themselves:
+
+```rust
+use nix_installer::locale::{self, Catalog};
+
+let mut catalog = Catalog::default();
+catalog.insert("Create directory `{}`", "Créer le répertoire `{}`");
+locale::set_catalog(Some(catalog));
+
+assert_eq!(locale::translate("Create directory `/nix`"), "Créer le répertoire `/nix`");
+```
+
+Logs and errors are not translated, so they can be searched for and reported as they are.
+*/
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::action::ActionDescription;
+
+/// Where catalogs are read from when `NIX_INSTALLER_LOCALE_DIR` isn't set
+///
+/// Distributions can set `NIX_INSTALLER_LOCALE_DIR` when building the installer to change it.
+pub const DEFAULT_LOCALE_DIR: &str = match option_env!("NIX_INSTALLER_LOCALE_DIR") {
+    Some(dir) => dir,
+    None => "/usr/share/nix-installer/locale",
+};
+
+/// Translations from English messages, see [the module documentation](self)
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    /// Messages without placeholders, looked up first
+    exact: HashMap<String, String>,
+    templates: Vec<Template>,
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    /// The English message, split at each `{}`
+    segments: Vec<String>,
+    translation: String,
+}
+
+impl Catalog {
+    pub fn from_json(buf: &str) -> Result<Self, serde_json::Error> {
+        let messages: HashMap<String, String> = serde_json::from_str(buf)?;
+        let mut catalog = Self::default();
+        for (message, translation) in messages {
+            catalog.insert(message, translation);
+        }
+        Ok(catalog)
+    }
+
+    /// Read `{lang}.json` from `dir`, or a catalog for the language alone (`pt` for `pt_BR`)
+    pub fn from_dir(dir: &Path, lang: &str) -> Result<Option<Self>, LocaleError> {
+        for candidate in language_candidates(lang) {
+            let path = dir.join(format!("{candidate}.json"));
+            match std::fs::read_to_string(&path) {
+                Ok(buf) => {
+                    return Self::from_json(&buf)
+                        .map(Some)
+                        .map_err(|e| LocaleError::Parse(path, e));
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(LocaleError::Read(path, e)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Translate `message` (where `{}` stands for a part which varies) to `translation`
+    pub fn insert(&mut self, message: impl Into<String>, translation: impl Into<String>) {
+        let message = message.into();
+        let translation = translation.into();
+        if message.contains("{}") {
+            self.templates.push(Template {
+                segments: message.split("{}").map(String::from).collect(),
+                translation,
+            });
+        } else {
+            self.exact.insert(message, translation);
+        }
+    }
+
+    /// The translation of `message`, or `message` itself if there is none
+    pub fn translate<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        if let Some(translation) = self.exact.get(message) {
+            return Cow::Owned(translation.clone());
+        }
+        for template in &self.templates {
+            if let Some(parts) = capture(&template.segments, message) {
+                return Cow::Owned(fill(&template.translation, &parts));
+            }
+        }
+        Cow::Borrowed(message)
+    }
+}
+
+/// The parts of `message` standing in for the `{}` between each of `segments`, if it matches them
+fn capture<'a>(segments: &[String], message: &'a str) -> Option<Vec<&'a str>> {
+    let (first, rest) = segments.split_first()?;
+    let mut remaining = message.strip_prefix(first.as_str())?;
+    let mut parts = Vec::with_capacity(rest.len());
+    for (index, segment) in rest.iter().enumerate() {
+        if index == rest.len() - 1 {
+            parts.push(remaining.strip_suffix(segment.as_str())?);
+        } else {
+            let at = remaining.find(segment.as_str())?;
+            parts.push(&remaining[..at]);
+            remaining = &remaining[at + segment.len()..];
+        }
+    }
+    Some(parts)
+}
+
+/// Substitute `parts` into the `{}` (in order) or `{N}` placeholders of `translation`
+fn fill(translation: &str, parts: &[&str]) -> String {
+    let mut buf = String::with_capacity(translation.len());
+    let mut next = 0;
+    let mut rest = translation;
+    while let Some(open) = rest.find('{') {
+        buf.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            rest = &rest[open..];
+            break;
+        };
+        let placeholder = &after[..close];
+        let index = match placeholder {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            },
+            _ => placeholder.parse::<usize>().ok(),
+        };
+        match index.and_then(|index| parts.get(index)) {
+            Some(part) => buf.push_str(part),
+            // Not a placeholder, such as `{prefix}` in a path
+            None => buf.push_str(&rest[open..open + close + 2]),
+        }
+        rest = &after[close + 1..];
+    }
+    buf.push_str(rest);
+    buf
+}
+
+/// `pt_BR.UTF-8` is looked for as `pt_BR`, then `pt`
+fn language_candidates(lang: &str) -> Vec<&str> {
+    let lang = lang.split(['.', '@']).next().unwrap_or_default();
+    let mut candidates = vec![lang];
+    if let Some((language, _territory)) = lang.split_once(['_', '-']) {
+        candidates.push(language);
+    }
+    candidates.retain(|candidate| !candidate.is_empty());
+    candidates
+}
+
+fn catalog() -> &'static RwLock<Option<Arc<Catalog>>> {
+    static CATALOG: OnceLock<RwLock<Option<Arc<Catalog>>>> = OnceLock::new();
+    CATALOG.get_or_init(|| RwLock::new(catalog_from_env().map(Arc::new)))
+}
+
+/// The catalog `NIX_INSTALLER_LANG` selects, English (no catalog) if it is unset or has none
+fn catalog_from_env() -> Option<Catalog> {
+    let lang = std::env::var("NIX_INSTALLER_LANG").ok()?;
+    if matches!(lang.as_str(), "" | "C" | "POSIX") || lang.starts_with("en") {
+        return None;
+    }
+    let dir = std::env::var_os("NIX_INSTALLER_LOCALE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_LOCALE_DIR));
+    match Catalog::from_dir(&dir, &lang) {
+        Ok(Some(catalog)) => Some(catalog),
+        Ok(None) => {
+            tracing::debug!(
+                "No catalog for `{lang}` in `{}`, using English",
+                dir.display()
+            );
+            None
+        },
+        Err(err) => {
+            tracing::warn!("{err}, using English");
+            None
+        },
+    }
+}
+
+/// Use `catalog` (or English, if `None`) rather than the one `NIX_INSTALLER_LANG` selects
+pub fn set_catalog(catalog: Option<Catalog>) {
+    *self::catalog().write().unwrap_or_else(|e| e.into_inner()) = catalog.map(Arc::new);
+}
+
+/// The translation of `message` in the current catalog, or `message` itself
+pub fn translate(message: &str) -> Cow<'_, str> {
+    let current = catalog().read().unwrap_or_else(|e| e.into_inner()).clone();
+    match current {
+        Some(catalog) => Cow::Owned(catalog.translate(message).into_owned()),
+        None => Cow::Borrowed(message),
+    }
+}
+
+/// [`translate`] the description and each line of the explanation
+pub(crate) fn translate_description(description: ActionDescription) -> ActionDescription {
+    let ActionDescription {
+        description,
+        explanation,
+    } = description;
+    ActionDescription::new(
+        translate(&description).into_owned(),
+        explanation
+            .iter()
+            .map(|line| translate(line).into_owned())
+            .collect(),
+    )
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum LocaleError {
+    #[error("Reading catalog `{0}`")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Parsing catalog `{0}`")]
+    Parse(PathBuf, #[source] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn translates_templates() {
+        let catalog = Catalog::from_json(
+            r#"{
+                "Proceed?": "Continuer ?",
+                "Create directory `{}`": "Créer le répertoire `{}`",
+                "Move `{}` to `{}`": "Déplacer vers `{1}` depuis `{0}`"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(catalog.translate("Proceed?"), "Continuer ?");
+        assert_eq!(
+            catalog.translate("Create directory `/nix/var`"),
+            "Créer le répertoire `/nix/var`"
+        );
+        assert_eq!(
+            catalog.translate("Move `/a` to `/b`"),
+            "Déplacer vers `/b` depuis `/a`"
+        );
+        assert_eq!(catalog.translate("Untranslated"), "Untranslated");
+        assert_eq!(
+            fill("`{prefix}/nix` from `{}`", &["/a"]),
+            "`{prefix}/nix` from `/a`"
+        );
+    }
+
+    #[test]
+    fn language_fallback() {
+        assert_eq!(language_candidates("pt_BR.UTF-8"), vec!["pt_BR", "pt"]);
+        assert_eq!(language_candidates("fr"), vec!["fr"]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Typed values for `nix.conf` settings, so they can be merged and compared
+//!
+//! [`nix_config_parser::NixConfig`] stores every value as a string. Whether two values for the same
+//! setting can be combined depends on the setting: lists (like `substituters`) are merged as sets,
+//! everything else must match exactly.
+
+use std::fmt;
+
+/// Settings whose values are whitespace separated lists, in addition to every `extra-*` setting
+const LIST_SETTINGS: &[&str] = &[
+    "allowed-uris",
+    "allowed-users",
+    "experimental-features",
+    "hashed-mirrors",
+    "nix-path",
+    "plugin-files",
+    "substituters",
+    "system-features",
+    "trusted-public-keys",
+    "trusted-substituters",
+    "trusted-users",
+];
+
+/// The value of a `nix.conf` setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum NixConfValue {
+    /// A single value (`max-jobs = auto`), which can't be merged with a different one
+    Scalar(String),
+    /// A set of values (`trusted-users = root @wheel`), kept in the order they were first seen
+    List(Vec<String>),
+}
+
+impl NixConfValue {
+    /// Parse the raw value of the setting `name`
+    pub(crate) fn parse(name: &str, value: &str) -> Self {
+        if is_list_setting(name) {
+            let mut list = Vec::new();
+            for item in value.split_whitespace() {
+                if !list.iter().any(|existing| existing == item) {
+                    list.push(item.to_string());
+                }
+            }
+            Self::List(list)
+        } else {
+            Self::Scalar(value.trim().to_string())
+        }
+    }
+
+    /// If every part of `other` is already present in `self`
+    pub(crate) fn contains(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::List(existing), Self::List(pending)) => {
+                pending.iter().all(|item| existing.contains(item))
+            },
+            (existing, pending) => existing == pending,
+        }
+    }
+
+    /// Combine `other` into `self`, or `None` if they conflict
+    pub(crate) fn merge(&self, other: &Self) -> Option<Self> {
+        match (self, other) {
+            (Self::List(existing), Self::List(pending)) => {
+                let mut merged = existing.clone();
+                for item in pending {
+                    if !merged.contains(item) {
+                        merged.push(item.clone());
+                    }
+                }
+                Some(Self::List(merged))
+            },
+            (existing, pending) if existing == pending => Some(existing.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NixConfValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar(value) => f.write_str(value),
+            Self::List(list) => f.write_str(&list.join(" ")),
+        }
+    }
+}
+
+/// Whether `name` takes a list of values
+pub(crate) fn is_list_setting(name: &str) -> bool {
+    name.starts_with("extra-") || LIST_SETTINGS.contains(&name)
+}
+
+/// Merge `value` into the setting `name` of `config`, or `Err` with the existing value if they conflict
+pub(crate) fn merge_into(
+    config: &mut nix_config_parser::NixConfig,
+    name: &str,
+    value: &str,
+) -> Result<(), String> {
+    let pending = NixConfValue::parse(name, value);
+    let settings = config.settings_mut();
+    let merged = match settings.get(name) {
+        Some(existing) => NixConfValue::parse(name, existing)
+            .merge(&pending)
+            .ok_or_else(|| existing.clone())?,
+        None => pending,
+    };
+    settings.insert(name.to_string(), merged.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NixConfValue;
+
+    #[test]
+    fn lists_merge_and_scalars_conflict() {
+        let existing = NixConfValue::parse("extra-substituters", "https://a https://b https://a");
+        assert_eq!(existing.to_string(), "https://a https://b");
+
+        let pending = NixConfValue::parse("extra-substituters", "https://b https://c");
+        assert!(!existing.contains(&pending));
+        assert_eq!(
+            existing.merge(&pending).map(|v| v.to_string()),
+            Some(String::from("https://a https://b https://c"))
+        );
+
+        let existing = NixConfValue::parse("max-jobs", "auto");
+        assert!(existing.contains(&NixConfValue::parse("max-jobs", " auto")));
+        assert_eq!(existing.merge(&NixConfValue::parse("max-jobs", "4")), None);
+    }
+}
@@ -45,6 +45,12 @@ pub struct DiskUtilApfsListOutput {
 #[derive(serde::Deserialize, Clone, Debug)]
 #[serde(rename_all = "PascalCase")]
 pub struct DiskUtilApfsContainer {
+    /// The container's disk, such as `disk3`
+    #[serde(default)]
+    pub container_reference: Option<String>,
+    /// Bytes not used by any of the container's volumes
+    #[serde(default)]
+    pub capacity_free: Option<u64>,
     pub volumes: Vec<DiskUtilApfsListVolume>,
 }
 
@@ -53,4 +59,7 @@ pub struct DiskUtilApfsContainer {
 pub struct DiskUtilApfsListVolume {
     pub name: Option<String>,
     pub file_vault: Option<bool>,
+    /// If an encrypted volume has not been unlocked yet
+    #[serde(default)]
+    pub locked: Option<bool>,
 }
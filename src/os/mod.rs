@@ -1 +1,3 @@
 pub mod darwin;
+pub(crate) mod probe;
+pub(crate) mod users;
@@ -0,0 +1,144 @@
+/*! Locale independent probes of the host
+
+Text meant for people, such as `systemctl status` or `groups`, is translated and reformatted between
+releases, so probes here rely on exit codes and machine readable output (`systemctl show`, the
+user and group databases) instead. Parsing is kept separate from running the commands so it can be
+tested against captured output.
+*/
+
+use std::{collections::HashMap, ffi::OsStr, process::Command};
+
+use nix::unistd::{Group, User};
+
+use crate::action::ActionErrorKind;
+
+/// A [`Command`] running with the `C` locale, for the rare output which must still be read as text
+pub(crate) fn command(program: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(program);
+    command
+        .env("LC_ALL", "C")
+        .stdin(std::process::Stdio::null());
+    command
+}
+
+/// Parse the `Key=Value` lines printed by `systemctl show`
+pub(crate) fn parse_properties(output: &str) -> HashMap<&str, &str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect()
+}
+
+/// The load and enablement state of a systemd unit, as reported by `systemctl show`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct UnitState {
+    pub(crate) active_state: String,
+    pub(crate) unit_file_state: String,
+    pub(crate) need_daemon_reload: bool,
+}
+
+impl UnitState {
+    const PROPERTIES: &'static str = "ActiveState,UnitFileState,NeedDaemonReload";
+
+    pub(crate) fn parse(output: &str) -> Self {
+        let properties = parse_properties(output);
+        let get = |key| properties.get(key).copied().unwrap_or_default().to_string();
+        Self {
+            active_state: get("ActiveState"),
+            unit_file_state: get("UnitFileState"),
+            need_daemon_reload: properties.get("NeedDaemonReload") == Some(&"yes"),
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active_state == "active"
+    }
+
+    /// Enabled, or linked into the unit search path, either permanently or until reboot
+    pub(crate) fn is_enabled(&self) -> bool {
+        matches!(
+            self.unit_file_state.as_str(),
+            "enabled" | "enabled-runtime" | "linked" | "linked-runtime"
+        )
+    }
+}
+
+/// Query the state of `unit`, units systemd does not know of are reported as inactive and disabled
+pub(crate) fn systemd_unit_state(unit: &str) -> Result<UnitState, ActionErrorKind> {
+    let mut command = command("systemctl");
+    command.arg("show");
+    command.arg(format!("--property={}", UnitState::PROPERTIES));
+    command.arg(unit);
+    let output = command
+        .output()
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    if !output.status.success() {
+        return Err(ActionErrorKind::command_output(&command, output));
+    }
+    let state = UnitState::parse(&String::from_utf8(output.stdout)?);
+    tracing::trace!(%unit, ?state, "Unit state");
+    Ok(state)
+}
+
+/// Whether FileVault is enabled on the boot volume, `fdesetup isactive` exits successfully if so
+pub(crate) fn filevault_is_active() -> Result<bool, ActionErrorKind> {
+    let mut command = command("/usr/bin/fdesetup");
+    command.arg("isactive");
+    command.stdout(std::process::Stdio::null());
+    command.stderr(std::process::Stdio::null());
+    let status = command
+        .status()
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    Ok(status.success())
+}
+
+/// Whether the user `name` is a member of `groupname`, through either its primary group or the
+/// group's member list
+///
+/// Returns `None` if the group does not exist.
+pub(crate) fn user_in_group(name: &str, groupname: &str) -> Result<Option<bool>, ActionErrorKind> {
+    let Some(group) = Group::from_name(groupname)
+        .map_err(|e| ActionErrorKind::GettingGroupId(groupname.to_string(), e))?
+    else {
+        return Ok(None);
+    };
+    if group.mem.iter().any(|member| member == name) {
+        return Ok(Some(true));
+    }
+    let user =
+        User::from_name(name).map_err(|e| ActionErrorKind::GettingUserId(name.to_string(), e))?;
+    Ok(Some(user.is_some_and(|user| user.gid == group.gid)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_systemctl_show() {
+        let output = "ActiveState=active\nUnitFileState=enabled-runtime\nNeedDaemonReload=no\n";
+        let state = UnitState::parse(output);
+        assert!(state.is_active());
+        assert!(state.is_enabled());
+        assert!(!state.need_daemon_reload);
+
+        let output = "ActiveState=inactive\nUnitFileState=\nNeedDaemonReload=yes\n";
+        let state = UnitState::parse(output);
+        assert!(!state.is_active());
+        assert!(!state.is_enabled());
+        assert!(state.need_daemon_reload);
+
+        // Values may contain `=`
+        assert_eq!(
+            parse_properties("Environment=A=1 B=2\n").get("Environment"),
+            Some(&"A=1 B=2")
+        );
+    }
+
+    #[test]
+    fn activating_is_not_active() {
+        let state = UnitState::parse("ActiveState=activating\nUnitFileState=linked\n");
+        assert!(!state.is_active());
+        assert!(state.is_enabled());
+    }
+}
@@ -0,0 +1,595 @@
+/*! Backends which create and delete users and groups
+
+Each platform (and each flavor of the Linux user tools) spells creating a system user differently,
+so the user and group actions go through a [`UserBackend`] found by [`user_backend`] rather than
+running the commands themselves. The backend is found again when executing or reverting, as it
+depends on the host rather than the plan.
+
+When users come from a network directory, like LDAP through `sssd`, and can't be created locally,
+the [`NetworkDirectory`] backend only checks that the users and groups exist in the directory.
+*/
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, Stdio};
+
+use target_lexicon::OperatingSystem;
+
+use crate::action::ActionErrorKind;
+use crate::execute_command;
+use crate::util::{is_busybox, which};
+
+const NSSWITCH_CONF: &str = "/etc/nsswitch.conf";
+
+/// `nsswitch.conf` sources which resolve users from a network directory
+const NETWORK_SOURCES: &[&str] = &["sss", "ldap", "winbind", "nis", "nisplus", "hesiod"];
+
+/// `nsswitch.conf` sources backed by files the local user tools edit
+const LOCAL_SOURCES: &[&str] = &["files", "compat", "db"];
+
+static WARNED_USER_HIDDEN: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+/// A user to create, in its primary group
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NewUser<'a> {
+    pub name: &'a str,
+    pub uid: u32,
+    pub groupname: &'a str,
+    pub gid: u32,
+    pub comment: &'a str,
+}
+
+/// Creates and deletes users and groups, and their memberships
+pub(crate) trait UserBackend: std::fmt::Debug {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind>;
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind>;
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind>;
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind>;
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind>;
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind>;
+
+    /// Fail if `entry`, like ``User `nixbld1` (UID 30001)``, can't be created, so must already exist
+    fn check_creatable(&self, _entry: &str) -> Result<(), ActionErrorKind> {
+        Ok(())
+    }
+}
+
+/// The backend for this host
+///
+/// Fails if none of the supported user tools are installed, unless users come from a network
+/// directory, in which case they are only checked.
+pub(crate) fn user_backend() -> Result<Box<dyn UserBackend>, ActionErrorKind> {
+    match OperatingSystem::host() {
+        OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => Ok(Box::new(Dscl)),
+        OperatingSystem::Freebsd => {
+            require(Pw::COMMANDS)?;
+            Ok(Box::new(Pw))
+        },
+        _ => {
+            let sources = std::fs::read_to_string(NSSWITCH_CONF)
+                .map(|nsswitch| passwd_sources(&nsswitch))
+                .unwrap_or_default();
+            let network = sources
+                .iter()
+                .any(|source| NETWORK_SOURCES.contains(&source.as_str()));
+            let local = sources.is_empty()
+                || sources
+                    .iter()
+                    .any(|source| LOCAL_SOURCES.contains(&source.as_str()));
+            let directory = || {
+                Box::new(NetworkDirectory {
+                    sources: sources.join(" "),
+                }) as Box<dyn UserBackend>
+            };
+
+            if network && !local {
+                return Ok(directory());
+            }
+            match linux_backend() {
+                Err(_) if network => Ok(directory()),
+                backend => backend,
+            }
+        },
+    }
+}
+
+/// The first of the Linux user tools which is installed
+fn linux_backend() -> Result<Box<dyn UserBackend>, ActionErrorKind> {
+    if which("useradd").is_some() {
+        require(Shadow::COMMANDS)?;
+        Ok(Box::new(Shadow))
+    } else if is_busybox("adduser") {
+        require(BusyBox::COMMANDS)?;
+        Ok(Box::new(BusyBox))
+    } else if which("adduser").is_some() {
+        require(Adduser::COMMANDS)?;
+        Ok(Box::new(Adduser))
+    } else {
+        Err(ActionErrorKind::MissingUserCreationCommand)
+    }
+}
+
+/// Fail with the error for the first of `commands` missing from `PATH`
+fn require(commands: &[&str]) -> Result<(), ActionErrorKind> {
+    match commands.iter().find(|command| which(command).is_none()) {
+        Some(&("userdel" | "deluser")) => Err(ActionErrorKind::MissingUserDeletionCommand),
+        Some(&("groupadd" | "addgroup")) => Err(ActionErrorKind::MissingGroupCreationCommand),
+        Some(&("groupdel" | "delgroup")) => Err(ActionErrorKind::MissingGroupDeletionCommand),
+        Some(&"gpasswd") => Err(ActionErrorKind::MissingAddUserToGroupCommand),
+        Some(_) => Err(ActionErrorKind::MissingUserCreationCommand),
+        None => Ok(()),
+    }
+}
+
+/// The sources of the `passwd` database in `nsswitch.conf`, without their `[STATUS=action]`s
+fn passwd_sources(nsswitch: &str) -> Vec<String> {
+    nsswitch
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .find_map(|line| line.trim().strip_prefix("passwd:"))
+        .map(|sources| {
+            sources
+                .split_whitespace()
+                .filter(|source| !source.starts_with('['))
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn run(command: &mut Command) -> Result<(), ActionErrorKind> {
+    execute_command(command.stdin(Stdio::null()))?;
+    Ok(())
+}
+
+/// `useradd`, `groupadd` and friends from `shadow`, as on most distributions
+#[derive(Debug)]
+pub(crate) struct Shadow;
+
+impl Shadow {
+    const COMMANDS: &'static [&'static str] =
+        &["useradd", "userdel", "groupadd", "groupdel", "gpasswd"];
+}
+
+impl UserBackend for Shadow {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        run(Command::new("groupadd").args(["-g", &gid.to_string(), "--system", name]))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("groupdel").arg(name))
+    }
+
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        run(Command::new("useradd").args([
+            "--home-dir",
+            "/var/empty",
+            "--comment",
+            user.comment,
+            "--gid",
+            &user.gid.to_string(),
+            "--groups",
+            &user.gid.to_string(),
+            "--no-user-group",
+            "--system",
+            "--shell",
+            "/sbin/nologin",
+            "--uid",
+            &user.uid.to_string(),
+            "--password",
+            "!",
+            user.name,
+        ]))
+    }
+
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("userdel").arg(name))
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("gpasswd").args(["-a", name, groupname]))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("gpasswd").args(["-d", name, groupname]))
+    }
+}
+
+/// BusyBox's `adduser` and `addgroup` applets, as on Alpine
+#[derive(Debug)]
+pub(crate) struct BusyBox;
+
+impl BusyBox {
+    const COMMANDS: &'static [&'static str] = &["adduser", "deluser", "addgroup", "delgroup"];
+}
+
+impl UserBackend for BusyBox {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        run(Command::new("addgroup").args(["-g", &gid.to_string(), "-S", name]))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("delgroup").arg(name))
+    }
+
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        run(Command::new("adduser").args([
+            "-h",
+            "/var/empty",
+            "-H", // Don't create a home.
+            "-g",
+            user.comment,
+            "-G",
+            user.groupname,
+            "-S",
+            "-s",
+            "/sbin/nologin",
+            "-u",
+            &user.uid.to_string(),
+            "-D", // Don't assign a password.
+            user.name,
+        ]))
+    }
+
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("deluser").arg(name))
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("addgroup").args([name, groupname]))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("delgroup").args([name, groupname]))
+    }
+}
+
+/// Debian's `adduser` scripts, without `shadow`'s tools
+#[derive(Debug)]
+pub(crate) struct Adduser;
+
+impl Adduser {
+    const COMMANDS: &'static [&'static str] = &["adduser", "deluser", "addgroup", "delgroup"];
+}
+
+impl UserBackend for Adduser {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        run(Command::new("addgroup").args(["-g", &gid.to_string(), "--system", name]))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("delgroup").arg(name))
+    }
+
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        run(Command::new("adduser").args([
+            "--home",
+            "/var/empty",
+            "-H", // Don't create a home.
+            "--gecos",
+            user.comment,
+            "--ingroup",
+            user.groupname,
+            "--system",
+            "--shell",
+            "/sbin/nologin",
+            "--uid",
+            &user.uid.to_string(),
+            "--disabled-password",
+            user.name,
+        ]))
+    }
+
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("deluser").arg(name))
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("adduser").args([name, groupname]))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("deluser").args([name, groupname]))
+    }
+}
+
+/// FreeBSD's `pw`
+#[derive(Debug)]
+pub(crate) struct Pw;
+
+impl Pw {
+    const COMMANDS: &'static [&'static str] = &["pw"];
+}
+
+impl UserBackend for Pw {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args(["groupadd", "-n", name, "-g", &gid.to_string()]))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args(["groupdel", "-n", name]))
+    }
+
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args([
+            "useradd",
+            "-n",
+            user.name,
+            "-u",
+            &user.uid.to_string(),
+            "-g",
+            &user.gid.to_string(),
+            "-c",
+            user.comment,
+            "-d",
+            "/var/empty",
+            "-s",
+            "/usr/sbin/nologin",
+            "-h",
+            "-", // Lock the password.
+        ]))
+    }
+
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args(["userdel", "-n", name]))
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args(["groupmod", "-n", groupname, "-m", name]))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("pw").args(["groupmod", "-n", groupname, "-d", name]))
+    }
+}
+
+/// macOS's `dscl` and `dseditgroup`, on the local directory node
+#[derive(Debug)]
+pub(crate) struct Dscl;
+
+impl UserBackend for Dscl {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        run(Command::new("/usr/sbin/dseditgroup").args([
+            "-o",
+            "create",
+            "-r",
+            "Nix build group for nix-daemon",
+            "-i",
+            &format!("{gid}"),
+            name,
+        ]))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("/usr/bin/dscl").args([".", "-delete", &format!("/Groups/{name}")]))
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        let NewUser { name, uid, gid, .. } = user;
+        execute_dscl_retry_on_specific_errors(&[".", "-create", &format!("/Users/{name}")])?;
+
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "UniqueID",
+            &format!("{uid}"),
+        ])?;
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "PrimaryGroupID",
+            &format!("{gid}"),
+        ])?;
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "NFSHomeDirectory",
+            "/var/empty",
+        ])?;
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "UserShell",
+            "/sbin/nologin",
+        ])?;
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "RealName",
+            name,
+        ])?;
+        execute_dscl_retry_on_specific_errors(&[
+            ".",
+            "-create",
+            &format!("/Users/{name}"),
+            "IsHidden",
+            "1",
+        ])
+
+        .or_else(|e| {
+            if let ActionErrorKind::CommandOutput { ref output, .. } = e {
+                if output.status.signal() == Some(9) {
+                    if !WARNED_USER_HIDDEN.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                        tracing::warn!("Failed to automatically mark nixbld users as hidden. See: https://dtr.mn/mark-user-hidden");
+                    }
+                    return Ok(())
+                }
+            }
+
+            Err(e)
+        })?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        // MacOS is a "Special" case
+        // It's only possible to delete users under certain conditions.
+        // Documentation on https://it.megocollector.com/macos/cant-delete-a-macos-user-with-dscl-resolution/ and http://www.aixperts.co.uk/?p=214 suggested it was a secure token
+        // That is correct, however it's a bit more nuanced. It appears to be that a user must be graphically logged in for some other user on the system to be deleted.
+        let mut command = Command::new("/usr/bin/dscl");
+        command.args([".", "-delete", &format!("/Users/{}", name)]);
+        command.stdin(std::process::Stdio::null());
+
+        let output = command
+            .output()
+            .map_err(|e| ActionErrorKind::command(&command, e))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match output.status.code() {
+            Some(0) => (),
+            Some(40) if stderr.contains("-14120") => {
+                // The user is on an ephemeral Mac, like detsys uses
+                // These Macs cannot always delete users, as sometimes there is no graphical login
+                tracing::warn!(
+                    "Encountered an exit code 40 with -14120 error while removing user, this is likely because the initial executing user did not have a secure token, or that there was no graphical login session. To delete the user, log in graphically, then run `/usr/bin/dscl . -delete /Users/{}`",
+                    name
+                );
+            },
+            Some(185) if stderr.contains("-14009 (eDSUnknownNodeName)") => {
+                // The user has already been deleted
+                tracing::debug!("User already deleted: /Users/{}", name);
+            },
+            _ => {
+                // Something went wrong
+                return Err(ActionErrorKind::command_output(&command, output));
+            },
+        }
+
+        Ok(())
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("/usr/bin/dscl")
+            .args([
+                ".",
+                "-append",
+                &format!("/Groups/{groupname}"),
+                "GroupMembership",
+            ])
+            .arg(name))?;
+        run(Command::new("/usr/sbin/dseditgroup")
+            .args(["-o", "edit"])
+            .arg("-a")
+            .arg(name)
+            .arg(groupname))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        run(Command::new("/usr/bin/dscl")
+            .args([".", "-delete", &format!("/Groups/{groupname}"), "users"])
+            .arg(name))
+    }
+}
+
+#[tracing::instrument]
+fn execute_dscl_retry_on_specific_errors(dscl_args: &[&str]) -> Result<(), ActionErrorKind> {
+    let mut retry_tokens: usize = 10;
+    loop {
+        let mut command = Command::new("/usr/bin/dscl");
+        command.args(dscl_args);
+        command.stdin(std::process::Stdio::null());
+        tracing::debug!(%retry_tokens, command = ?command, "Waiting for user create/update to succeed");
+
+        let output = command
+            .output()
+            .map_err(|e| ActionErrorKind::command(&command, e))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if output.status.success() {
+            tracing::trace!(
+                stderr = %stderr,
+                stdout = %String::from_utf8_lossy(&output.stdout),
+                "Command success"
+            );
+            break;
+        } else if retry_tokens == 0 {
+            return Err(ActionErrorKind::command_output(&command, output));
+        } else {
+            if output.status.code() == Some(140) && stderr.contains("-14988 (eNotYetImplemented)") {
+                // Retry due to buggy macOS user behavior?
+                // https://github.com/DeterminateSystems/nix-installer/issues/1300
+                // https://github.com/ansible/ansible/issues/73505
+            } else if output.status.signal() == Some(9) {
+                // If the command was SIGKILLed, let's retry and hope it doesn't happen again.
+            } else {
+                // If the command failed for a reason that we weren't "expecting", return that as an
+                // error.
+                return Err(ActionErrorKind::command_output(&command, output));
+            }
+
+            retry_tokens = retry_tokens.saturating_sub(1);
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+
+    Ok(())
+}
+
+/// Users and groups from a network directory (LDAP, Active Directory, NIS) which can't be created
+/// locally, so they must already exist
+///
+/// Deleting leaves the directory alone, as the installer did not create its entries.
+#[derive(Debug)]
+pub(crate) struct NetworkDirectory {
+    sources: String,
+}
+
+impl UserBackend for NetworkDirectory {
+    fn create_group(&self, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+        self.check_creatable(&format!("Group `{name}` (GID {gid})"))
+    }
+
+    fn delete_group(&self, name: &str) -> Result<(), ActionErrorKind> {
+        tracing::warn!("Leaving group `{name}` in the network directory");
+        Ok(())
+    }
+
+    fn create_user(&self, user: NewUser<'_>) -> Result<(), ActionErrorKind> {
+        self.check_creatable(&format!("User `{}` (UID {})", user.name, user.uid))
+    }
+
+    fn delete_user(&self, name: &str) -> Result<(), ActionErrorKind> {
+        tracing::warn!("Leaving user `{name}` in the network directory");
+        Ok(())
+    }
+
+    fn add_user_to_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        self.check_creatable(&format!("Membership of `{name}` in group `{groupname}`"))
+    }
+
+    fn remove_user_from_group(&self, name: &str, groupname: &str) -> Result<(), ActionErrorKind> {
+        tracing::warn!("Leaving `{name}` in group `{groupname}` in the network directory");
+        Ok(())
+    }
+
+    fn check_creatable(&self, entry: &str) -> Result<(), ActionErrorKind> {
+        Err(ActionErrorKind::NetworkDirectoryEntryMissing(
+            entry.to_string(),
+            self.sources.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_passwd_sources() {
+        let nsswitch = "\
+            # passwd: files\n\
+            group: files sss\n\
+            passwd:     sss [NOTFOUND=return] ldap # network only\n\
+            shadow: files\n";
+        assert_eq!(passwd_sources(nsswitch), vec!["sss", "ldap"]);
+        assert!(passwd_sources("hosts: files dns\n").is_empty());
+    }
+}
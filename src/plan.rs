@@ -1,21 +1,56 @@
 use std::{
+    cell::RefCell,
+    io::Write,
     path::{Path, PathBuf},
     str::FromStr,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::{Duration, Instant},
 };
 
+use crate::style::Colorize;
 use crate::{
     NixInstallerError,
-    action::{Action, ActionDescription, StatefulAction},
+    action::{Action, ActionDescription, ActionErrorKind, ActionEstimate, StatefulAction},
+    extension::ExtensionRequirement,
+    locale::translate,
     planner::{BuiltinPlanner, Planner},
+    progress::{PlanEvent, PlanObserver, ProgressGuard, ProgressReporter},
+    prompt::{InteractionHandler, PromptGuard, PromptSender},
+    timeout::TimeoutGuard,
 };
-use owo_colors::OwoColorize;
 use semver::{Version, VersionReq};
 
-pub const RECEIPT_LOCATION: &str = "/nix/receipt.json";
+pub const RECEIPT_FILE_NAME: &str = "receipt.json";
+
+/// State directories searched for an existing receipt, after any the user specified
+pub const KNOWN_STATE_DIRS: &[&str] =
+    &[crate::settings::DEFAULT_STATE_DIR, "/var/lib/nix-installer"];
+
+/// Locate an existing receipt, checking `state_dir` (if any) before the [`KNOWN_STATE_DIRS`]
+pub fn find_receipt(state_dir: Option<&Path>) -> Option<PathBuf> {
+    state_dir
+        .into_iter()
+        .map(Path::to_path_buf)
+        .chain(KNOWN_STATE_DIRS.iter().map(PathBuf::from))
+        // Termux has no `/nix`, its installs keep the receipt in the store root
+        .chain(
+            crate::planner::detect::in_termux()
+                .then(crate::planner::termux::default_store_root),
+        )
+        .map(|dir| dir.join(RECEIPT_FILE_NAME))
+        .find(|receipt| receipt.exists())
+}
+
+/// Locate an existing receipt at `receipt_path` (from `--receipt-path`) if given, otherwise with [`find_receipt`]
+pub fn find_receipt_at(receipt_path: Option<&Path>, state_dir: Option<&Path>) -> Option<PathBuf> {
+    match receipt_path {
+        Some(receipt_path) => receipt_path.exists().then(|| receipt_path.to_path_buf()),
+        None => find_receipt(state_dir),
+    }
+}
 
 /// A cancellation flag that can be shared across threads
 pub type CancelSignal = Arc<AtomicBool>;
@@ -25,6 +60,69 @@ pub fn cancel_signal() -> CancelSignal {
     Arc::new(AtomicBool::new(false))
 }
 
+// Each plan runs its actions on its own thread, and is cancelled by its own signal
+thread_local! {
+    static CANCEL_SIGNAL: RefCell<Option<CancelSignal>> = const { RefCell::new(None) };
+}
+
+/// Exposes `signal` to actions on this thread through [`check_cancelled`] until dropped
+pub(crate) struct CancelGuard {
+    previous: Option<CancelSignal>,
+}
+
+impl CancelGuard {
+    pub(crate) fn install(signal: Option<CancelSignal>) -> Self {
+        Self {
+            previous: CANCEL_SIGNAL.replace(signal),
+        }
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        CANCEL_SIGNAL.set(self.previous.take());
+    }
+}
+
+/// Fails with [`ActionErrorKind::Cancelled`] if the running plan has been cancelled
+///
+/// Actions made of several sub-actions call this between them, so a cancellation stops them part
+/// way, leaving them (and the receipt) in [`ActionState::Progress`](crate::action::ActionState::Progress).
+pub(crate) fn check_cancelled() -> Result<(), ActionErrorKind> {
+    let cancelled = CANCEL_SIGNAL.with_borrow(|signal| {
+        signal
+            .as_ref()
+            .is_some_and(|signal| signal.load(Ordering::Relaxed))
+    });
+    if cancelled {
+        return Err(ActionErrorKind::Cancelled);
+    }
+    Ok(())
+}
+
+/// What [`InstallPlan::install_with_on_failure`] does when an action fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OnFailure {
+    /// Stop, leaving completed actions in place so the install can be resumed or uninstalled
+    #[default]
+    Stop,
+    /// Revert every action which completed (or partially completed), as an uninstall would
+    Rollback,
+    /// Log the failure and carry on with the remaining actions
+    Ignore,
+}
+
+impl std::fmt::Display for OnFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnFailure::Stop => write!(f, "stop"),
+            OnFailure::Rollback => write!(f, "rollback"),
+            OnFailure::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
 /**
 A set of [`Action`]s, along with some metadata, which can be carried out to drive an install or
 revert
@@ -33,9 +131,29 @@ revert
 pub struct InstallPlan {
     pub(crate) version: Version,
 
+    /// The [`Extension`](crate::extension::Extension)s the actions or planner come from, checked before the actions are read
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "crate::extension::deserialize_available"
+    )]
+    pub(crate) extensions: Vec<ExtensionRequirement>,
+
     pub(crate) actions: Vec<StatefulAction<Box<dyn Action>>>,
 
     pub(crate) planner: Box<dyn Planner>,
+
+    /// Only self-test login shells after installing, see [`InstallPlan::set_skip_interactive_self_test`]
+    #[serde(skip)]
+    pub(crate) skip_interactive_self_test: bool,
+
+    /// Converging an existing install, see [`InstallPlan::set_converging`]
+    #[serde(skip)]
+    pub(crate) converging: bool,
+
+    /// How long each action may take, see [`InstallPlan::set_action_timeout`]
+    #[serde(skip)]
+    pub(crate) action_timeout: Option<Duration>,
 }
 
 impl InstallPlan {
@@ -46,9 +164,13 @@ impl InstallPlan {
         let actions = planner.plan()?;
 
         Ok(Self {
+            extensions: required_extensions(planner.as_ref(), &actions)?,
             planner,
             actions,
             version: current_version()?,
+            skip_interactive_self_test: false,
+            converging: false,
+            action_timeout: None,
         })
     }
 
@@ -62,22 +184,96 @@ impl InstallPlan {
         planner.pre_install_check()?;
 
         let actions = planner.plan()?;
+        let planner = planner.boxed();
         Ok(Self {
-            planner: planner.boxed(),
+            extensions: required_extensions(planner.as_ref(), &actions)?,
+            planner,
             actions,
             version: current_version()?,
+            skip_interactive_self_test: false,
+            converging: false,
+            action_timeout: None,
         })
     }
 
+    /// Skip self-testing interactive shells (see [`Shell::is_interactive`](crate::self_test::Shell::is_interactive))
+    /// after installing, as they read user configuration which is unreliable without a terminal
+    pub fn set_skip_interactive_self_test(&mut self, skip: bool) {
+        self.skip_interactive_self_test = skip;
+    }
+
+    /// Mark the plan as applying changes to an install made by `nix-installer`, so the planner's
+    /// checks that Nix isn't already installed are skipped
+    pub fn set_converging(&mut self, converging: bool) {
+        self.converging = converging;
+    }
+
+    /// Kill the commands of an action still running after `timeout`, failing the action, rather
+    /// than waiting forever for one which hangs
+    ///
+    /// Only commands run through the installer's command runner are killed, along with anything
+    /// they started in their process group.
+    pub fn set_action_timeout(&mut self, timeout: Option<Duration>) {
+        self.action_timeout = timeout;
+    }
+
     pub fn pre_uninstall_check(&self) -> Result<(), NixInstallerError> {
         self.planner.platform_check()?;
         self.planner.pre_uninstall_check()?;
         Ok(())
     }
 
+    /// Read a plan written by `nix-installer plan`, substituting `${env:NAME}` (or
+    /// `${env:NAME:-default}`) in its string values with environment variables
+    ///
+    /// Fields this version does not know (such as misspellings) are an error if `strict`, and
+    /// logged otherwise.
+    #[tracing::instrument(level = "debug")]
+    pub fn from_plan_file(path: &Path, strict: bool) -> Result<Self, NixInstallerError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| NixInstallerError::ReadingPlan(path.to_path_buf(), e))?;
+        let mut value: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| NixInstallerError::ParsingPlan(path.to_path_buf(), e))?;
+        substitute_env_values(&mut value, &|name| std::env::var(name).ok())?;
+
+        let plan: Self = serde_json::from_value(value.clone())
+            .map_err(|e| NixInstallerError::ParsingPlan(path.to_path_buf(), e))?;
+        let roundtrip = serde_json::to_value(&plan)?;
+        let mut unknown = vec![];
+        unknown_fields(&value, &roundtrip, "", &mut unknown);
+        if !unknown.is_empty() {
+            if strict {
+                return Err(NixInstallerError::UnknownPlanFields(
+                    path.to_path_buf(),
+                    unknown,
+                ));
+            }
+            tracing::warn!(
+                "Ignoring fields in plan `{}` which this version of `nix-installer` does not know: {}",
+                path.display(),
+                unknown.join(", ")
+            );
+        }
+
+        plan.check_compatible()?;
+        if let Some(interval) = plan
+            .planner
+            .settings()?
+            .get("config_refresh_interval")
+            .and_then(serde_json::Value::as_u64)
+        {
+            crate::settings::check_config_refresh_interval(
+                interval.try_into().unwrap_or(u32::MAX),
+            )?;
+        }
+        Ok(plan)
+    }
+
     pub fn pre_install_check(&self) -> Result<(), NixInstallerError> {
         self.planner.platform_check()?;
-        self.planner.pre_install_check()?;
+        if !self.converging {
+            self.planner.pre_install_check()?;
+        }
         Ok(())
     }
 
@@ -104,18 +300,29 @@ impl InstallPlan {
         // Stabilize output order
         plan_settings.sort();
 
+        let estimate: ActionEstimate = actions.iter().map(|v| v.estimate_execute()).sum();
+
         let buf = format!(
             "\
-            Nix install plan (v{version})\n\
-            Planner: {planner}{maybe_default_setting_note}\n\
+            {title}\n\
+            {planner_label} {planner}{maybe_default_setting_note}\n\
             \n\
             {maybe_plan_settings}\
-            Planned actions:\n\
+            {actions_label}\n\
             {actions}\n\
+            {maybe_estimate}\
         ",
+            title = translate(&format!("Nix install plan (v{version})")),
+            planner_label = translate("Planner:"),
             planner = planner.typetag_name(),
+            actions_label = translate("Planned actions:"),
+            maybe_estimate = if estimate.is_empty() {
+                String::new()
+            } else {
+                format!("\n{}\n", translate(&format!("Estimated cost: {estimate}")))
+            },
             maybe_default_setting_note = if plan_settings.is_empty() {
-                String::from(" (with default settings)")
+                format!(" {}", translate("(with default settings)"))
             } else {
                 String::new()
             },
@@ -124,10 +331,11 @@ impl InstallPlan {
             } else {
                 format!(
                     "\
-                    Configured settings:\n\
+                    {settings_label}\n\
                     {plan_settings}\n\
                     \n\
                 ",
+                    settings_label = translate("Configured settings:"),
                     plan_settings = plan_settings.join("\n")
                 )
             },
@@ -155,20 +363,112 @@ impl InstallPlan {
         Ok(buf)
     }
 
-    #[tracing::instrument(level = "debug", skip_all)]
     pub fn install(
         &mut self,
         cancel_signal: Option<CancelSignal>,
+    ) -> Result<(), NixInstallerError> {
+        self.install_with_prompts(cancel_signal, None)
+    }
+
+    /// Like [`InstallPlan::install`], routing decisions needed mid-execution to `prompt_sender`
+    ///
+    /// See [`prompt`](crate::prompt) for details.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn install_with_prompts(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        prompt_sender: Option<PromptSender>,
+    ) -> Result<(), NixInstallerError> {
+        let interaction =
+            prompt_sender.map(|sender| Arc::new(sender) as Arc<dyn InteractionHandler>);
+        self.install_with_progress(cancel_signal, interaction, None, OnFailure::Stop)
+    }
+
+    /// Like [`InstallPlan::install`], asking `interaction` to decide what is needed mid-execution
+    ///
+    /// See [`prompt`](crate::prompt) for details.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn install_with_interaction(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        interaction: Arc<dyn InteractionHandler>,
+    ) -> Result<(), NixInstallerError> {
+        self.install_with_progress(cancel_signal, Some(interaction), None, OnFailure::Stop)
+    }
+
+    /// Like [`InstallPlan::install`], handling a failed action according to `on_failure`
+    ///
+    /// A cancelled install always stops, whatever `on_failure` is.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn install_with_on_failure(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        on_failure: OnFailure,
+    ) -> Result<(), NixInstallerError> {
+        self.install_with_progress(cancel_signal, None, None, on_failure)
+    }
+
+    /// Like [`InstallPlan::install_with_interaction`], reporting the progress of each step to `progress_reporter`
+    ///
+    /// See [`progress`](crate::progress) for details.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn install_with_progress(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        interaction: Option<Arc<dyn InteractionHandler>>,
+        progress_reporter: Option<Arc<dyn ProgressReporter>>,
+        on_failure: OnFailure,
+    ) -> Result<(), NixInstallerError> {
+        self.execute_actions(
+            cancel_signal,
+            interaction,
+            progress_reporter,
+            None,
+            on_failure,
+        )
+    }
+
+    /// Like [`InstallPlan::install`], sending a [`PlanEvent`] to `observer` as each step starts, completes, or fails
+    ///
+    /// See [`progress`](crate::progress) for details.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn install_with_observer(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        mut observer: impl PlanObserver,
+    ) -> Result<(), NixInstallerError> {
+        self.execute_actions(
+            cancel_signal,
+            None,
+            None,
+            Some(&mut observer),
+            OnFailure::Stop,
+        )
+    }
+
+    fn execute_actions(
+        &mut self,
+        cancel_signal: Option<CancelSignal>,
+        interaction: Option<Arc<dyn InteractionHandler>>,
+        progress_reporter: Option<Arc<dyn ProgressReporter>>,
+        mut observer: Option<&mut dyn PlanObserver>,
+        on_failure: OnFailure,
     ) -> Result<(), NixInstallerError> {
         self.check_compatible()?;
+        let _prompt_guard = PromptGuard::install(interaction);
+        let _progress_guard = ProgressGuard::install(progress_reporter.clone());
+        let _cancel_guard = CancelGuard::install(cancel_signal.clone());
+        let _timeout_guard = TimeoutGuard::install(self.action_timeout);
         self.pre_install_check()?;
 
-        let Self { actions, .. } = self;
+        let total = self.actions.len();
+        let mut failed = 0;
 
         // This is **deliberately sequential**.
         // Actions which are parallelizable are represented by "group actions" like CreateUsers
         // The plan itself represents the concept of the sequence of stages.
-        for action in actions {
+        for index in 0..total {
+            let action = &mut self.actions[index];
             if let Some(ref signal) = cancel_signal {
                 if signal.load(Ordering::Relaxed) {
                     if let Err(err) = self.write_receipt() {
@@ -179,21 +479,109 @@ impl InstallPlan {
                 }
             }
 
-            tracing::info!("Step: {}", action.tracing_synopsis());
-            if let Err(err) = action.try_execute() {
+            let synopsis = action.tracing_synopsis();
+            let step = index + 1;
+            let started = Instant::now();
+            // Described up front, as completed actions have no description
+            let description = action
+                .describe_execute()
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| ActionDescription::new(synopsis.clone(), vec![]));
+            if let Some(observer) = observer.as_deref_mut() {
+                observer.observe(PlanEvent::ActionStarted {
+                    description: &description,
+                });
+            }
+            match &progress_reporter {
+                Some(reporter) => {
+                    // The reporter shows the step, logging it too would interrupt the reporter's output
+                    tracing::debug!("Step: {synopsis}");
+                    reporter.step_started(step, total, &translate(&synopsis));
+                },
+                None => tracing::info!("Step: {synopsis}"),
+            }
+            let res = action.try_execute();
+            if let Some(reporter) = &progress_reporter {
+                reporter.step_finished(
+                    step,
+                    total,
+                    &translate(&synopsis),
+                    started.elapsed(),
+                    res.is_ok(),
+                );
+            }
+            if let Some(observer) = observer.as_deref_mut() {
+                observer.observe(match &res {
+                    Ok(()) => PlanEvent::ActionCompleted {
+                        description: &description,
+                        elapsed: started.elapsed(),
+                    },
+                    Err(error) => PlanEvent::ActionFailed {
+                        description: &description,
+                        error,
+                    },
+                });
+            }
+            if let Err(err) = res {
                 if let Err(err) = self.write_receipt() {
                     tracing::error!("Error saving receipt: {:?}", err);
                 }
 
-                let err = NixInstallerError::Action(err);
+                if cancel_signal
+                    .as_ref()
+                    .is_some_and(|signal| signal.load(Ordering::Relaxed))
+                {
+                    tracing::debug!("Stopped part way through a step: {err}");
+                    return Err(NixInstallerError::Cancelled);
+                }
 
-                return Err(err);
+                match on_failure {
+                    OnFailure::Stop => return Err(NixInstallerError::Action(err)),
+                    OnFailure::Rollback => {
+                        tracing::error!("Step `{synopsis}` failed, rolling back: {err}");
+                        return match self.uninstall(cancel_signal) {
+                            Ok(()) => Err(NixInstallerError::RolledBack(err)),
+                            Err(NixInstallerError::ActionRevert(revert_errors)) => {
+                                if let Err(err) = self.write_receipt() {
+                                    tracing::error!("Error saving receipt: {:?}", err);
+                                }
+                                Err(NixInstallerError::RollbackFailed(
+                                    Box::new(err),
+                                    revert_errors,
+                                ))
+                            },
+                            Err(rollback_err) => Err(rollback_err),
+                        };
+                    },
+                    OnFailure::Ignore => {
+                        tracing::warn!("Ignoring failed step `{synopsis}`: {err}");
+                        failed += 1;
+                    },
+                }
             }
         }
 
         self.write_receipt()?;
 
-        if let Err(err) = crate::self_test::self_test().map_err(NixInstallerError::SelfTest) {
+        if failed != 0 {
+            tracing::warn!(
+                "{failed} step(s) failed and were ignored, the install may be only partially functional"
+            );
+        }
+
+        // Nix is only usable through proot when installed without `root`, which shells don't use
+        let mut shells = match self.planner.requires_root() {
+            true => crate::self_test::Shell::discover(),
+            false => vec![],
+        };
+        if self.skip_interactive_self_test {
+            shells.retain(|shell| !shell.is_interactive());
+        }
+        if let Err(err) =
+            crate::self_test::self_test_shells(&shells, Some(&self.planner.receipt_path()))
+                .map_err(NixInstallerError::SelfTest)
+        {
             tracing::warn!("{err:?}")
         }
 
@@ -225,17 +613,20 @@ impl InstallPlan {
 
         let buf = format!(
             "\
-            Nix uninstall plan (v{version})\n\
+            {title}\n\
             \n\
-            Planner: {planner}{maybe_default_setting_note}\n\
+            {planner_label} {planner}{maybe_default_setting_note}\n\
             \n\
             {maybe_plan_settings}\
-            Planned actions:\n\
+            {actions_label}\n\
             {actions}\n\
         ",
+            title = translate(&format!("Nix uninstall plan (v{version})")),
+            planner_label = translate("Planner:"),
             planner = planner.typetag_name(),
+            actions_label = translate("Planned actions:"),
             maybe_default_setting_note = if plan_settings.is_empty() {
-                String::from(" (with default settings)")
+                format!(" {}", translate("(with default settings)"))
             } else {
                 String::new()
             },
@@ -244,10 +635,11 @@ impl InstallPlan {
             } else {
                 format!(
                     "\
-                Configured settings:\n\
+                {settings_label}\n\
                 {plan_settings}\n\
                 \n\
             ",
+                    settings_label = translate("Configured settings:"),
                     plan_settings = plan_settings.join("\n")
                 )
             },
@@ -283,6 +675,7 @@ impl InstallPlan {
     ) -> Result<(), NixInstallerError> {
         self.check_compatible()?;
         self.pre_uninstall_check()?;
+        let _timeout_guard = TimeoutGuard::install(self.action_timeout);
 
         let Self { actions, .. } = self;
         let mut errors = vec![];
@@ -315,6 +708,46 @@ impl InstallPlan {
         }
     }
 
+    /// How `proposed` differs from this plan, usually the receipt of the current install
+    ///
+    /// Actions are matched by their type and position among actions of that type, and compared
+    /// without their state or what they recorded while executing.
+    pub fn diff(&self, proposed: &InstallPlan) -> Result<PlanDiff, NixInstallerError> {
+        let installed_planner = self.planner.typetag_name();
+        let proposed_planner = proposed.planner.typetag_name();
+        let planner = (installed_planner != proposed_planner)
+            .then(|| (installed_planner.to_string(), proposed_planner.to_string()));
+
+        let installed_settings = self.planner.settings()?;
+        let proposed_settings = proposed.planner.settings()?;
+        let settings = installed_settings
+            .keys()
+            .chain(proposed_settings.keys())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .filter_map(|name| {
+                let installed = installed_settings.get(name);
+                let proposed = proposed_settings.get(name);
+                (installed != proposed).then(|| SettingChange {
+                    name: name.clone(),
+                    installed: installed.cloned(),
+                    proposed: proposed.cloned(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let actions = diff_actions(
+            &action_entries(&self.actions)?,
+            &action_entries(&proposed.actions)?,
+        );
+
+        Ok(PlanDiff {
+            planner,
+            settings,
+            actions,
+        })
+    }
+
     pub fn check_compatible(&self) -> Result<(), NixInstallerError> {
         let self_version_string = self.version.to_string();
         let req = VersionReq::parse(&self_version_string)
@@ -331,13 +764,23 @@ impl InstallPlan {
     }
 
     pub(crate) fn write_receipt(&self) -> Result<(), NixInstallerError> {
-        let install_receipt_path = PathBuf::from(RECEIPT_LOCATION);
-        write_receipt(self, &install_receipt_path)?;
+        write_receipt(self, &self.planner.receipt_path())?;
 
         Ok(())
     }
 }
 
+/// The registered [`Extension`](crate::extension::Extension)s used by `planner` or `actions`
+pub(crate) fn required_extensions(
+    planner: &dyn Planner,
+    actions: &[StatefulAction<Box<dyn Action>>],
+) -> Result<Vec<ExtensionRequirement>, NixInstallerError> {
+    crate::extension::required(&serde_json::json!({
+        "planner": planner,
+        "actions": actions,
+    }))
+}
+
 pub(crate) fn write_receipt(
     plan: &impl serde::Serialize,
     install_receipt_path: &Path,
@@ -350,16 +793,270 @@ pub(crate) fn write_receipt(
     let self_json =
         serde_json::to_string_pretty(plan).map_err(NixInstallerError::SerializingReceipt)?;
 
-    std::fs::create_dir_all("/nix")
-        .map_err(|e| NixInstallerError::RecordingReceipt(PathBuf::from("/nix"), e))?;
-    std::fs::write(&install_receipt_path_tmp, format!("{self_json}\n"))
+    if let Some(parent) = install_receipt_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| NixInstallerError::RecordingReceipt(parent.to_path_buf(), e))?;
+    }
+    // Synced before and after the rename, so a power loss never leaves a truncated receipt behind
+    let mut receipt = std::fs::File::create(&install_receipt_path_tmp)
+        .map_err(|e| NixInstallerError::RecordingReceipt(install_receipt_path_tmp.clone(), e))?;
+    receipt
+        .write_all(format!("{self_json}\n").as_bytes())
+        .and_then(|()| receipt.sync_all())
         .map_err(|e| NixInstallerError::RecordingReceipt(install_receipt_path_tmp.clone(), e))?;
     std::fs::rename(&install_receipt_path_tmp, install_receipt_path)
         .map_err(|e| NixInstallerError::RecordingReceipt(install_receipt_path.to_path_buf(), e))?;
+    if let Some(parent) = install_receipt_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| NixInstallerError::RecordingReceipt(parent.to_path_buf(), e))?;
+    }
 
     Ok(())
 }
 
+/// Substitute `${env:NAME}` and `${env:NAME:-default}` in `template` using `lookup`
+pub(crate) fn substitute_env(
+    template: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<String, NixInstallerError> {
+    const START: &str = "${env:";
+
+    let mut substituted = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(START) {
+        substituted.push_str(&rest[..start]);
+        let after = &rest[start + START.len()..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| NixInstallerError::InvalidPlanTemplate(rest[start..].to_string()))?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(NixInstallerError::InvalidPlanTemplate(
+                rest[start..start + START.len() + end + 1].to_string(),
+            ));
+        }
+        match lookup(name).or_else(|| default.map(String::from)) {
+            Some(value) => substituted.push_str(&value),
+            None => return Err(NixInstallerError::UnsetPlanVariable(name.to_string())),
+        }
+        rest = &after[end + 1..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
+
+fn substitute_env_values(
+    value: &mut serde_json::Value,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<(), NixInstallerError> {
+    match value {
+        serde_json::Value::String(string) => *string = substitute_env(string, lookup)?,
+        serde_json::Value::Array(values) => {
+            for value in values {
+                substitute_env_values(value, lookup)?;
+            }
+        },
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                substitute_env_values(value, lookup)?;
+            }
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Collect the paths of fields in `input` which were dropped when deserializing it, by comparing
+/// it with the plan serialized again
+fn unknown_fields(
+    input: &serde_json::Value,
+    roundtrip: &serde_json::Value,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    match (input, roundtrip) {
+        (serde_json::Value::Object(input), serde_json::Value::Object(roundtrip)) => {
+            for (key, value) in input {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match roundtrip.get(key) {
+                    Some(roundtrip) => unknown_fields(value, roundtrip, &field, unknown),
+                    None if value.is_null() => (),
+                    None => unknown.push(field),
+                }
+            }
+        },
+        (serde_json::Value::Array(input), serde_json::Value::Array(roundtrip)) => {
+            for (index, (value, roundtrip)) in input.iter().zip(roundtrip).enumerate() {
+                unknown_fields(value, roundtrip, &format!("{path}[{index}]"), unknown);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// How a proposed plan differs from an installed one, see [`InstallPlan::diff`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlanDiff {
+    /// The installed and proposed planners, if they differ
+    pub planner: Option<(String, String)>,
+    pub settings: Vec<SettingChange>,
+    pub actions: Vec<ActionChange>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.planner.is_none() && self.settings.is_empty() && self.actions.is_empty()
+    }
+}
+
+/// A planner setting which differs, `None` where the planner has no such setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    pub name: String,
+    pub installed: Option<serde_json::Value>,
+    pub proposed: Option<serde_json::Value>,
+}
+
+/// An action which would be added, removed, or changed, with its synopsis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionChange {
+    Added(String),
+    Removed(String),
+    /// The paths of the fields which differ, like `create_users[3].action.uid`
+    Changed(String, Vec<String>),
+}
+
+/// Fields recorded while executing an action, rather than planning it
+const EXECUTION_FIELDS: &[&str] = &["state", "backup", "backups"];
+
+/// An action's type tag, synopsis, and serialized fields
+type ActionEntry = (&'static str, String, serde_json::Value);
+
+fn action_entries(
+    actions: &[StatefulAction<Box<dyn Action>>],
+) -> Result<Vec<ActionEntry>, NixInstallerError> {
+    actions
+        .iter()
+        .map(|action| {
+            let mut value = serde_json::to_value(&action.action)?;
+            strip_execution_fields(&mut value);
+            Ok((
+                action.inner_typetag_name(),
+                action.tracing_synopsis(),
+                value,
+            ))
+        })
+        .collect()
+}
+
+fn strip_execution_fields(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|key, _| !EXECUTION_FIELDS.contains(&key.as_str()));
+            map.values_mut().for_each(strip_execution_fields);
+        },
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_execution_fields),
+        _ => (),
+    }
+}
+
+/// Match up actions by their type and position among actions of that type, so inserting an
+/// action of one type doesn't shift the comparison of every other
+fn diff_actions(installed: &[ActionEntry], proposed: &[ActionEntry]) -> Vec<ActionChange> {
+    let installed = keyed(installed);
+    let proposed = keyed(proposed);
+
+    let mut changes = vec![];
+    for (key, (_, synopsis, value)) in &proposed {
+        match installed
+            .iter()
+            .find(|(installed_key, _)| installed_key == key)
+        {
+            Some((_, (_, _, installed_value))) => {
+                let mut fields = vec![];
+                changed_fields(installed_value, value, "", &mut fields);
+                if !fields.is_empty() {
+                    changes.push(ActionChange::Changed(synopsis.clone(), fields));
+                }
+            },
+            None => changes.push(ActionChange::Added(synopsis.clone())),
+        }
+    }
+    for (key, (_, synopsis, _)) in &installed {
+        if !proposed.iter().any(|(proposed_key, _)| proposed_key == key) {
+            changes.push(ActionChange::Removed(synopsis.clone()));
+        }
+    }
+    changes
+}
+
+/// Each entry keyed by its type tag and position among actions of that type
+fn keyed(entries: &[ActionEntry]) -> Vec<((&'static str, usize), &ActionEntry)> {
+    let mut seen = std::collections::HashMap::<&str, usize>::new();
+    entries
+        .iter()
+        .map(|entry| {
+            let occurrence = seen.entry(entry.0).or_default();
+            *occurrence += 1;
+            ((entry.0, *occurrence), entry)
+        })
+        .collect()
+}
+
+/// Collect the paths of the fields which differ between `installed` and `proposed`
+fn changed_fields(
+    installed: &serde_json::Value,
+    proposed: &serde_json::Value,
+    path: &str,
+    changed: &mut Vec<String>,
+) {
+    match (installed, proposed) {
+        (serde_json::Value::Object(installed), serde_json::Value::Object(proposed)) => {
+            let keys = installed
+                .keys()
+                .chain(proposed.keys())
+                .collect::<std::collections::BTreeSet<_>>();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match (installed.get(key), proposed.get(key)) {
+                    (Some(installed), Some(proposed)) => {
+                        changed_fields(installed, proposed, &field, changed)
+                    },
+                    _ => changed.push(field),
+                }
+            }
+        },
+        (serde_json::Value::Array(installed_values), serde_json::Value::Array(proposed_values))
+            if installed_values.len() == proposed_values.len() =>
+        {
+            for (index, (installed, proposed)) in
+                installed_values.iter().zip(proposed_values).enumerate()
+            {
+                changed_fields(installed, proposed, &format!("{path}[{index}]"), changed);
+            }
+        },
+        (installed, proposed) if installed != proposed => changed.push(path.to_string()),
+        _ => (),
+    }
+}
+
 pub fn current_version() -> Result<Version, NixInstallerError> {
     let nix_installer_version_str = env!("CARGO_PKG_VERSION");
     Version::from_str(nix_installer_version_str).map_err(|e| {
@@ -400,4 +1097,98 @@ mod test {
         assert!(maybe_plan.check_compatible().is_err());
         Ok(())
     }
+
+    #[test]
+    fn check_cancelled_follows_installed_signal() {
+        use super::{CancelGuard, cancel_signal, check_cancelled};
+        use std::sync::atomic::Ordering;
+
+        let signal = cancel_signal();
+        {
+            let _guard = CancelGuard::install(Some(signal.clone()));
+            assert!(check_cancelled().is_ok());
+            signal.store(true, Ordering::Relaxed);
+            assert!(check_cancelled().is_err());
+            // A plan on another thread isn't cancelled along with this one
+            assert!(std::thread::spawn(check_cancelled).join().unwrap().is_ok());
+        }
+        assert!(check_cancelled().is_ok());
+    }
+
+    #[test]
+    fn substitutes_env() -> Result<(), NixInstallerError> {
+        use super::substitute_env;
+
+        let lookup = |name: &str| (name == "SITE").then(|| "eu-west".to_string());
+        assert_eq!(
+            substitute_env("https://${env:SITE}.example.com/${env:CACHE:-nix}", &lookup)?,
+            "https://eu-west.example.com/nix"
+        );
+        assert_eq!(
+            substitute_env("${SHELL:-/bin/sh}", &lookup)?,
+            "${SHELL:-/bin/sh}"
+        );
+        assert!(matches!(
+            substitute_env("${env:CACHE}", &lookup),
+            Err(NixInstallerError::UnsetPlanVariable(name)) if name == "CACHE"
+        ));
+        assert!(matches!(
+            substitute_env("${env:SITE", &lookup),
+            Err(NixInstallerError::InvalidPlanTemplate(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn diffs_actions_by_type_and_position() {
+        use super::{ActionChange, diff_actions, strip_execution_fields};
+        use serde_json::json;
+
+        let entry = |tag, synopsis: &str, value| (tag, synopsis.to_string(), value);
+        let installed = vec![
+            entry(
+                "create_directory",
+                "Create `/nix`",
+                json!({ "path": "/nix" }),
+            ),
+            entry("create_user", "Create `nixbld1`", json!({ "uid": 30001 })),
+            entry("create_user", "Create `nixbld2`", json!({ "uid": 30002 })),
+        ];
+        let mut backed_up = json!({ "path": "/etc/nix", "backup": { "original": "/etc/nix" } });
+        strip_execution_fields(&mut backed_up);
+        let proposed = vec![
+            entry(
+                "create_directory",
+                "Create `/nix`",
+                json!({ "path": "/nix" }),
+            ),
+            entry("create_directory", "Create `/etc/nix`", backed_up),
+            entry("create_user", "Create `nixbld1`", json!({ "uid": 40001 })),
+        ];
+
+        assert_eq!(
+            diff_actions(&installed, &proposed),
+            vec![
+                ActionChange::Added("Create `/etc/nix`".into()),
+                ActionChange::Changed("Create `nixbld1`".into(), vec!["uid".into()]),
+                ActionChange::Removed("Create `nixbld2`".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_unknown_fields() {
+        let input = serde_json::json!({
+            "version": "1.0.0",
+            "actions": [{ "action": { "path": "/nix", "mdoe": 493 } }],
+            "removed": null,
+        });
+        let roundtrip = serde_json::json!({
+            "version": "1.0.0",
+            "actions": [{ "action": { "path": "/nix", "mode": 493 } }],
+        });
+        let mut unknown = vec![];
+        super::unknown_fields(&input, &roundtrip, "", &mut unknown);
+        assert_eq!(unknown, vec!["actions[0].action.mdoe".to_string()]);
+    }
 }
@@ -0,0 +1,275 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "cli")]
+use clap::ArgAction;
+
+use super::{
+    ShellProfileLocations,
+    linux::{check_nix_not_already_installed, check_not_nixos, check_not_wsl1, plan_sandbox},
+};
+use crate::{
+    Action, BuiltinPlanner,
+    action::{
+        StatefulAction,
+        base::{CreateDirectory, RemoveDirectory},
+        common::{
+            ConfigureAutoMaintenance, ConfigureConfigRefresh, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, LinkDaemonSockets, ProvisionNix,
+        },
+    },
+    error::HasExpectedErrors,
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_free_space, warn_if_low_memory},
+        with_hooks,
+    },
+    settings::{CommonSettings, InitSystem, InstallSettingsError, SandboxMode},
+    util::which,
+};
+
+/// A planner for Alpine Linux and other musl/BusyBox based systems using OpenRC
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Alpine {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    /// Which init system to configure (if `--init none` Nix will be root-only)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_parser,
+            long,
+            env = "NIX_INSTALLER_INIT",
+            default_value_t = InitSystem::Openrc
+        )
+    )]
+    pub init: InitSystem,
+    /// Start the daemon (if not `--init none`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_parser,
+            long,
+            action(ArgAction::SetFalse),
+            env = "NIX_INSTALLER_START_DAEMON",
+            default_value_t = true,
+            long = "no-start-daemon"
+        )
+    )]
+    pub start_daemon: bool,
+    /// Whether to keep Nix's build sandbox: `auto` drops it if the kernel doesn't support it, `require` fails instead
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            default_value_t = SandboxMode::Auto,
+            env = "NIX_INSTALLER_SANDBOX"
+        )
+    )]
+    #[serde(default)]
+    pub sandbox: SandboxMode,
+}
+
+#[typetag::serde(name = "alpine")]
+impl Planner for Alpine {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            init: InitSystem::Openrc,
+            start_daemon: true,
+            sandbox: SandboxMode::Auto,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let mut settings = self.settings.clone();
+        plan_sandbox(&mut settings, self.sandbox)?;
+
+        let mut plan = vec![
+            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ProvisionNix::plan(&settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateUsersAndGroups::plan(settings.clone())
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ConfigureNix::plan(ShellProfileLocations::default(), &settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+
+        if settings.registry_url.is_some() || settings.netrc_url.is_some() {
+            plan.push(
+                ConfigureConfigRefresh::plan(self.init, &settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if settings.enable_auto_gc.is_some() || settings.enable_auto_optimise.is_some() {
+            plan.push(
+                ConfigureAutoMaintenance::plan(self.init, &settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if !settings.extra_daemon_sockets.is_empty() {
+            plan.push(
+                LinkDaemonSockets::plan(settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.init == InitSystem::Systemd {
+            plan.push(
+                CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        plan.extend([
+            ConfigureUpstreamInitService::plan(self.init, self.start_daemon, false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ]);
+
+        with_hooks(self.typetag_name(), &settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            settings,
+            init,
+            start_daemon,
+            sandbox,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("init".into(), serde_json::to_value(init)?);
+        map.insert("start_daemon".into(), serde_json::to_value(start_daemon)?);
+        map.insert("sandbox".into(), serde_json::to_value(sandbox)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        check_not_wsl1()?;
+
+        if self.init == InitSystem::Openrc {
+            check_openrc_available()?;
+        }
+
+        Ok(())
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        check_not_nixos()?;
+
+        check_nix_not_already_installed()?;
+
+        check_not_wsl1()?;
+
+        check_free_space(&self.settings, Path::new("/nix"))?;
+        warn_if_low_memory();
+
+        if self.init == InitSystem::Openrc {
+            check_openrc_available()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Alpine> for BuiltinPlanner {
+    fn from(val: Alpine) -> Self {
+        BuiltinPlanner::Alpine(val)
+    }
+}
+
+pub(crate) fn check_openrc_available() -> Result<(), PlannerError> {
+    // `openrc-run` is needed to interpret the service script, `rc-update` to enable it
+    if !Path::new("/sbin/openrc-run").exists() && which("openrc-run").is_none() {
+        return Err(AlpineErrorKind::OpenrcNotFound.into());
+    }
+    if which("rc-update").is_none() {
+        return Err(AlpineErrorKind::OpenrcNotFound.into());
+    }
+
+    Ok(())
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum AlpineErrorKind {
+    #[error(
+        "\
+        OpenRC was not found.\n\
+        \n\
+        On Alpine Linux, install it with `apk add openrc`. In containers without an init system, consider passing `--init none`."
+    )]
+    OpenrcNotFound,
+}
+
+impl HasExpectedErrors for AlpineErrorKind {
+    fn expected<'a>(&'a self) -> Option<Box<dyn std::error::Error + 'a>> {
+        match self {
+            AlpineErrorKind::OpenrcNotFound => Some(Box::new(self)),
+        }
+    }
+}
+
+impl From<AlpineErrorKind> for PlannerError {
+    fn from(v: AlpineErrorKind) -> PlannerError {
+        PlannerError::Custom(Box::new(v))
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    ShellProfileLocations,
+    linux::{check_nix_not_already_installed, check_not_nixos, check_not_wsl1, plan_sandbox},
+};
+use crate::{
+    Action, BuiltinPlanner,
+    action::{
+        StatefulAction,
+        base::{CreateDirectory, CreateFile, RemoveDirectory},
+        common::{ConfigureNix, CreateUsersAndGroups, ProvisionNix},
+    },
+    planner::{Planner, PlannerError, free_space::check_free_space, with_hooks},
+    settings::{CommonSettings, InstallSettingsError, SandboxMode},
+};
+
+/// Where the entrypoint script is written, for `ENTRYPOINT ["/usr/local/bin/nix-entrypoint"]`
+pub const ENTRYPOINT_PATH: &str = "/usr/local/bin/nix-entrypoint";
+
+const ENTRYPOINT_CONTENT: &str = "\
+#!/bin/sh
+# Created by nix-installer: run a command (or a shell) with Nix available, as a container entrypoint
+if [ -e /nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh ]; then
+    . /nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh
+fi
+if [ \"$#\" -eq 0 ]; then
+    set -- \"${SHELL:-/bin/sh}\"
+fi
+exec \"$@\"
+";
+
+/// A planner for Docker, Podman and other containers without an init, such as when building images
+///
+/// Nix is installed for `root` without a daemon: no init service or `systemd-tmpfiles` are
+/// configured, and an entrypoint script makes Nix available to the container's command.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Container {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    /// Whether to keep Nix's build sandbox, which needs privileges most container engines do not grant (`--sandbox` alone is `require`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            num_args = 0..=1,
+            default_value_t = SandboxMode::Disable,
+            default_missing_value = "require",
+            env = "NIX_INSTALLER_SANDBOX"
+        )
+    )]
+    #[serde(default = "default_sandbox", deserialize_with = "deserialize_sandbox")]
+    pub sandbox: SandboxMode,
+}
+
+fn default_sandbox() -> SandboxMode {
+    SandboxMode::Disable
+}
+
+/// Receipts from before `--sandbox` took a [`SandboxMode`] record whether it was kept as a `bool`
+fn deserialize_sandbox<'de, D>(deserializer: D) -> Result<SandboxMode, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum SandboxSetting {
+        Kept(bool),
+        Mode(SandboxMode),
+    }
+
+    Ok(
+        match <SandboxSetting as serde::Deserialize>::deserialize(deserializer)? {
+            SandboxSetting::Kept(true) => SandboxMode::Require,
+            SandboxSetting::Kept(false) => SandboxMode::Disable,
+            SandboxSetting::Mode(mode) => mode,
+        },
+    )
+}
+
+#[typetag::serde(name = "container")]
+impl Planner for Container {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            sandbox: SandboxMode::Disable,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let mut settings = self.settings.clone();
+        plan_sandbox(&mut settings, self.sandbox)?;
+
+        let plan = vec![
+            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ProvisionNix::plan(&settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateUsersAndGroups::plan(settings.clone())
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ConfigureNix::plan(ShellProfileLocations::default(), &settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateFile::plan(
+                ENTRYPOINT_PATH,
+                None,
+                None,
+                0o0755,
+                ENTRYPOINT_CONTENT.to_string(),
+                false,
+            )
+            .map_err(PlannerError::Action)?
+            .boxed(),
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+
+        with_hooks(self.typetag_name(), &settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self { settings, sandbox } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("sandbox".into(), serde_json::to_value(sandbox)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        check_not_wsl1()?;
+
+        Ok(())
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        check_not_nixos()?;
+
+        check_nix_not_already_installed()?;
+
+        check_not_wsl1()?;
+
+        check_free_space(&self.settings, Path::new("/nix"))?;
+
+        Ok(())
+    }
+}
+
+impl From<Container> for BuiltinPlanner {
+    fn from(val: Container) -> Self {
+        BuiltinPlanner::Container(val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_sandbox_from_old_receipts() -> Result<(), Box<dyn std::error::Error>> {
+        let mut planner = serde_json::to_value(Container::try_default()?)?;
+        for (recorded, mode) in [
+            (serde_json::json!(true), SandboxMode::Require),
+            (serde_json::json!(false), SandboxMode::Disable),
+            (serde_json::json!("auto"), SandboxMode::Auto),
+        ] {
+            planner["sandbox"] = recorded;
+            let container: Container = serde_json::from_value(planner.clone())?;
+            assert_eq!(container.sandbox, mode);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,493 @@
+/*! Detection of the host's distribution and the context the installer runs in
+
+[`BuiltinPlanner::try_default()`](super::BuiltinPlanner::try_default) uses a [`Host`] to pick a
+planner, such as the `container` planner in containers without an init.
+Reading the host is kept separate from interpreting it so the choices can be tested.
+*/
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// The fields of `/etc/os-release` which matter when picking a planner
+///
+/// See <https://www.freedesktop.org/software/systemd/man/latest/os-release.html>
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsRelease {
+    pub id: String,
+    pub id_like: Vec<String>,
+    pub version_id: Option<String>,
+    pub variant_id: Option<String>,
+}
+
+impl OsRelease {
+    pub fn parse(content: &str) -> Self {
+        let mut os_release = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let value = unquote(value.trim()).to_string();
+            match key {
+                "ID" => os_release.id = value,
+                "ID_LIKE" => {
+                    os_release.id_like = value.split_whitespace().map(String::from).collect()
+                },
+                "VERSION_ID" => os_release.version_id = Some(value),
+                "VARIANT_ID" => os_release.variant_id = Some(value),
+                _ => (),
+            }
+        }
+        os_release
+    }
+
+    /// Read `/etc/os-release`, falling back to `/usr/lib/os-release` as the specification requires
+    pub fn read() -> Option<Self> {
+        ["/etc/os-release", "/usr/lib/os-release"]
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .map(|content| Self::parse(&content))
+    }
+
+    /// Whether the distribution is `id`, or derived from it
+    pub fn is_like(&self, id: &str) -> bool {
+        self.id == id || self.id_like.iter().any(|like| like == id)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// The container engine the installer is running under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Container {
+    Docker,
+    Podman,
+    /// Another engine, as named in `/run/systemd/container` or `$container` (eg. `lxc`)
+    Other(String),
+}
+
+impl Container {
+    pub fn detect() -> Option<Self> {
+        if Path::new("/.dockerenv").exists() {
+            return Some(Self::Docker);
+        }
+        if Path::new("/run/.containerenv").exists() {
+            return Some(Self::Podman);
+        }
+        let name = std::fs::read_to_string("/run/systemd/container")
+            .ok()
+            .or_else(|| std::env::var("container").ok())?;
+        match name.trim() {
+            "" => None,
+            "docker" => Some(Self::Docker),
+            "podman" => Some(Self::Podman),
+            other => Some(Self::Other(other.to_string())),
+        }
+    }
+}
+
+/// The version of the Windows Subsystem for Linux the installer is running under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wsl {
+    Wsl1,
+    Wsl2,
+}
+
+impl Wsl {
+    pub fn detect() -> Option<Self> {
+        // Detection strategies: https://patrickwu.space/wslconf/
+        if std::env::var("WSL_DISTRO_NAME").is_ok() {
+            return Some(if std::env::var("WSL_INTEROP").is_ok() {
+                Self::Wsl2
+            } else {
+                Self::Wsl1
+            });
+        }
+        let kernel_release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+        Self::from_kernel_release(&kernel_release)
+    }
+
+    /// WSL1 reports a kernel release such as `4.4.0-19041-Microsoft`, WSL2 one such as
+    /// `5.15.167.4-microsoft-standard-WSL2`
+    fn from_kernel_release(release: &str) -> Option<Self> {
+        if release.contains("Microsoft") {
+            Some(Self::Wsl1)
+        } else if release.contains("microsoft") {
+            Some(Self::Wsl2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether SELinux denies or only logs policy violations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelinuxMode {
+    Enforcing,
+    Permissive,
+}
+
+impl SelinuxMode {
+    /// `None` if SELinux is disabled or not built into the kernel
+    pub fn detect() -> Option<Self> {
+        match std::fs::read_to_string("/sys/fs/selinux/enforce")
+            .ok()?
+            .trim()
+        {
+            "1" => Some(Self::Enforcing),
+            _ => Some(Self::Permissive),
+        }
+    }
+}
+
+/// Whether AppArmor stops unprivileged processes creating user namespaces, as on Ubuntu 24.04
+///
+/// Nix's build sandbox needs them, so sandboxed builds outside the daemon fail in `clone()`.
+pub fn userns_restricted() -> bool {
+    std::fs::read_to_string("/proc/sys/kernel/apparmor_restrict_unprivileged_userns")
+        .is_ok_and(|value| value.trim() == "1")
+}
+
+/// Whether the installer runs in [Termux](https://termux.dev) on Android, without `root` or a `/nix`
+///
+/// Termux sets `TERMUX_VERSION` in its shells, and installs everything under its app's data.
+pub fn in_termux() -> bool {
+    std::env::var_os("TERMUX_VERSION").is_some()
+        || std::env::var("PREFIX").is_ok_and(|prefix| prefix.starts_with("/data/data/com.termux/"))
+}
+
+/// Which of the kernel features Nix's build sandbox uses are available, see [`SandboxMode`](crate::settings::SandboxMode)
+///
+/// Unprivileged containers (such as LXC without nesting, or OpenVZ) commonly lack them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxSupport {
+    /// Builds by the root daemon run without them, but sandboxed builds by other users need them
+    pub user_namespaces: bool,
+    /// New mount, PID, IPC, UTS and network namespaces, with `/proc` mounted in them
+    pub namespaces: bool,
+    /// Nix filters the syscalls of builds with seccomp, sandboxed or not
+    pub seccomp: bool,
+}
+
+impl SandboxSupport {
+    #[tracing::instrument(level = "debug")]
+    pub fn detect() -> Self {
+        let user_namespaces_enabled = std::fs::read_to_string("/proc/sys/user/max_user_namespaces")
+            .map(|value| value.trim() != "0")
+            .unwrap_or(true);
+        let support = Self {
+            user_namespaces: user_namespaces_enabled && unshare(&["--user"]),
+            namespaces: unshare(&[
+                "--mount",
+                "--pid",
+                "--ipc",
+                "--uts",
+                "--net",
+                "--fork",
+                "--mount-proc",
+            ]),
+            seccomp: std::fs::read_to_string("/proc/self/status")
+                .map(|status| status.lines().any(|line| line.starts_with("Seccomp:")))
+                .unwrap_or(true),
+        };
+        tracing::debug!(?support, "Detected sandbox support");
+        support
+    }
+
+    /// What the sandbox needs but is missing, empty if it can be kept
+    pub fn missing(&self) -> Vec<&'static str> {
+        let mut missing = vec![];
+        if !self.namespaces {
+            missing.push("mount, PID and network namespaces");
+        }
+        if !self.seccomp {
+            missing.push("seccomp");
+        }
+        missing
+    }
+}
+
+/// Whether `unshare` (from util-linux or BusyBox) can create the namespaces in `args` to run `true`
+///
+/// Without `unshare`, nothing can be told, so the namespaces are assumed to work.
+fn unshare(args: &[&str]) -> bool {
+    std::process::Command::new("unshare")
+        .args(args)
+        .arg("true")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or_else(|e| {
+            tracing::debug!("Could not run `unshare`, assuming namespaces work: {e}");
+            true
+        })
+}
+
+/// The IDs mapped into the user namespace the installer runs in, from `/proc/self/uid_map` or `/proc/self/gid_map`
+///
+/// Unprivileged containers map only some of the host's IDs, such as LXC's default of the 65536
+/// starting at 100000. Users or groups with IDs outside the map can't be created.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMap {
+    /// The first ID of each range inside the namespace, and how many IDs it has
+    pub ranges: Vec<(u32, u32)>,
+}
+
+impl IdMap {
+    pub fn parse(content: &str) -> Self {
+        let ranges = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().map(str::parse::<u32>);
+                let inside = fields.next()?.ok()?;
+                let _outside = fields.next()?.ok()?;
+                let count = fields.next()?.ok()?;
+                Some((inside, count))
+            })
+            .collect();
+        Self { ranges }
+    }
+
+    /// `None` outside Linux, or if it can't be read
+    pub fn read(path: impl AsRef<Path>) -> Option<Self> {
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|content| Self::parse(&content))
+    }
+
+    /// Whether this maps every ID, as outside of a user namespace
+    pub fn is_full(&self) -> bool {
+        self.ranges == [(0, u32::MAX)]
+    }
+
+    /// Whether all of `first..=last` are mapped
+    pub fn contains(&self, first: u32, last: u32) -> bool {
+        self.ranges.iter().any(|&(start, count)| {
+            first >= start && u64::from(last) < u64::from(start) + u64::from(count)
+        })
+    }
+
+    /// The range with the most IDs
+    pub fn largest(&self) -> Option<(u32, u32)> {
+        self.ranges.iter().copied().max_by_key(|&(_, count)| count)
+    }
+}
+
+/// Which builtin planner suits a [`Host`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectedPlanner {
+    Linux,
+    SteamDeck,
+    Ostree,
+    Alpine,
+    Container,
+    Lxc,
+    Nixos,
+    Termux,
+}
+
+/// What the installer knows about the Linux host it is running on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Host {
+    pub os_release: Option<OsRelease>,
+    pub container: Option<Container>,
+    /// Booted from an ostree deployment, such as on Fedora Silverblue
+    pub ostree: bool,
+    pub wsl: Option<Wsl>,
+    pub selinux: Option<SelinuxMode>,
+    /// See [`userns_restricted`]
+    #[serde(default)]
+    pub userns_restricted: bool,
+    /// Whether an init system is running as PID 1 which a planner could configure
+    pub init_running: bool,
+    /// See [`in_termux`]
+    #[serde(default)]
+    pub termux: bool,
+}
+
+impl Host {
+    #[tracing::instrument(level = "debug")]
+    pub fn detect() -> Self {
+        let host = Self {
+            os_release: OsRelease::read(),
+            container: Container::detect(),
+            ostree: Path::new("/run/ostree-booted").exists()
+                || std::process::Command::new("ostree")
+                    .arg("remote")
+                    .arg("list")
+                    .stdin(std::process::Stdio::null())
+                    .output()
+                    .is_ok_and(|output| output.status.success()),
+            wsl: Wsl::detect(),
+            selinux: SelinuxMode::detect(),
+            userns_restricted: userns_restricted(),
+            init_running: Path::new("/run/systemd/system").exists()
+                || Path::new("/run/openrc").exists(),
+            termux: in_termux(),
+        };
+        tracing::debug!(?host, "Detected host");
+        host
+    }
+
+    pub fn planner(&self) -> DetectedPlanner {
+        let is_like = |id| self.os_release.as_ref().is_some_and(|os| os.is_like(id));
+        if self.termux {
+            DetectedPlanner::Termux
+        } else if is_like("nixos") {
+            DetectedPlanner::Nixos
+        } else if is_like("steamos") {
+            DetectedPlanner::SteamDeck
+        } else if self.ostree {
+            DetectedPlanner::Ostree
+        } else if self.needs_init_none() {
+            DetectedPlanner::Container
+        } else if is_like("alpine") {
+            DetectedPlanner::Alpine
+        } else if self.container == Some(Container::Other("lxc".into())) {
+            DetectedPlanner::Lxc
+        } else {
+            DetectedPlanner::Linux
+        }
+    }
+
+    /// Containers usually run their entrypoint as PID 1, with no init to configure a daemon with
+    pub fn needs_init_none(&self) -> bool {
+        self.container.is_some() && !self.init_running
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_os_release() {
+        let os_release = OsRelease::parse(
+            "NAME=\"Fedora Linux\"\nID=fedora\nVERSION_ID=41\nVARIANT_ID='silverblue'\n",
+        );
+        assert_eq!(os_release.id, "fedora");
+        assert_eq!(os_release.version_id.as_deref(), Some("41"));
+        assert_eq!(os_release.variant_id.as_deref(), Some("silverblue"));
+
+        let os_release = OsRelease::parse("ID=\"postmarketos\"\nID_LIKE=\"alpine\"\n");
+        assert!(os_release.is_like("alpine"));
+        assert!(!os_release.is_like("debian"));
+    }
+
+    #[test]
+    fn sandbox_needs_namespaces_and_seccomp() {
+        let full = SandboxSupport {
+            user_namespaces: true,
+            namespaces: true,
+            seccomp: true,
+        };
+        assert!(full.missing().is_empty());
+        // The root daemon's builds don't need user namespaces
+        assert!(
+            SandboxSupport {
+                user_namespaces: false,
+                ..full
+            }
+            .missing()
+            .is_empty()
+        );
+        assert_eq!(
+            SandboxSupport {
+                namespaces: false,
+                seccomp: false,
+                ..full
+            }
+            .missing(),
+            vec!["mount, PID and network namespaces", "seccomp"]
+        );
+    }
+
+    #[test]
+    fn parses_id_maps() {
+        assert!(IdMap::parse("         0          0 4294967295\n").is_full());
+
+        let unprivileged = IdMap::parse("0 100000 65536\n");
+        assert!(!unprivileged.is_full());
+        assert!(unprivileged.contains(30001, 30032));
+        assert!(!unprivileged.contains(65000, 70000));
+        assert_eq!(unprivileged.largest(), Some((0, 65536)));
+    }
+
+    #[test]
+    fn picks_planner() {
+        let host = |os_release: &str, ostree| Host {
+            os_release: Some(OsRelease::parse(os_release)),
+            ostree,
+            ..Default::default()
+        };
+        assert_eq!(
+            host("ID=steamos\n", false).planner(),
+            DetectedPlanner::SteamDeck
+        );
+        assert_eq!(
+            host("ID=postmarketos\nID_LIKE=alpine\n", false).planner(),
+            DetectedPlanner::Alpine
+        );
+        assert_eq!(host("ID=fedora\n", true).planner(), DetectedPlanner::Ostree);
+        assert_eq!(host("ID=nixos\n", false).planner(), DetectedPlanner::Nixos);
+        assert_eq!(host("ID=debian\n", false).planner(), DetectedPlanner::Linux);
+        let lxc = Host {
+            container: Some(Container::Other("lxc".into())),
+            init_running: true,
+            ..host("ID=debian\n", false)
+        };
+        assert_eq!(lxc.planner(), DetectedPlanner::Lxc);
+        let termux = Host {
+            termux: true,
+            ..Default::default()
+        };
+        assert_eq!(termux.planner(), DetectedPlanner::Termux);
+        assert_eq!(Host::default().planner(), DetectedPlanner::Linux);
+    }
+
+    #[test]
+    fn containers_without_init() {
+        let mut host = Host {
+            container: Some(Container::Docker),
+            ..Default::default()
+        };
+        assert!(host.needs_init_none());
+        host.init_running = true;
+        assert!(!host.needs_init_none());
+        host.container = None;
+        host.init_running = false;
+        assert!(!host.needs_init_none());
+
+        let alpine_container = Host {
+            os_release: Some(OsRelease::parse("ID=alpine\n")),
+            container: Some(Container::Podman),
+            ..Default::default()
+        };
+        assert_eq!(alpine_container.planner(), DetectedPlanner::Container);
+    }
+
+    #[test]
+    fn wsl_kernel_release() {
+        assert_eq!(
+            Wsl::from_kernel_release("4.4.0-19041-Microsoft"),
+            Some(Wsl::Wsl1)
+        );
+        assert_eq!(
+            Wsl::from_kernel_release("5.15.167.4-microsoft-standard-WSL2"),
+            Some(Wsl::Wsl2)
+        );
+        assert_eq!(Wsl::from_kernel_release("6.8.0-45-generic"), None);
+    }
+}
@@ -0,0 +1,117 @@
+/*! Checking there is room to install, before anything is changed
+
+Running out of space part way through unpacking Nix leaves a half written store behind, and an
+`ENOSPC` from deep inside the unpack which doesn't say how much space was needed.
+*/
+
+use std::path::Path;
+
+use crate::{action::format_bytes, planner::PlannerError, settings::CommonSettings};
+
+/// Less memory than this is worth a warning, evaluating Nixpkgs commonly takes more
+const LOW_MEMORY_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Check the filesystem `path` will be created on has [`CommonSettings::required_free_space`] available
+///
+/// `path` need not exist yet, the nearest directory above it which does is checked instead.
+pub(crate) fn check_free_space(settings: &CommonSettings, path: &Path) -> Result<(), PlannerError> {
+    let Some(existing) = path.ancestors().find(|ancestor| ancestor.exists()) else {
+        return Ok(());
+    };
+    let stat = match nix::sys::statvfs::statvfs(existing) {
+        Ok(stat) => stat,
+        Err(e) => {
+            tracing::warn!(
+                "Could not tell how much space is free on `{}`, not checking it: {e}",
+                existing.display()
+            );
+            return Ok(());
+        },
+    };
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    let available = (stat.blocks_available() as u64).saturating_mul(stat.fragment_size() as u64);
+    check_available(
+        format!("the filesystem holding `{}`", existing.display()),
+        available,
+        settings.required_free_space(),
+    )
+}
+
+/// Fail with [`PlannerError::InsufficientSpace`] if `available` bytes is less than `required`
+pub(crate) fn check_available(
+    location: String,
+    available: u64,
+    required: u64,
+) -> Result<(), PlannerError> {
+    tracing::debug!(
+        location,
+        available = format_bytes(available),
+        required = format_bytes(required),
+        "Checking free space"
+    );
+    if available < required {
+        return Err(PlannerError::InsufficientSpace {
+            location,
+            available,
+            required,
+        });
+    }
+    Ok(())
+}
+
+/// Warn if the machine has little memory, which Nix itself installs with but builds may run out of
+pub(crate) fn warn_if_low_memory() {
+    // Only Linux (and the Linux emulation of some other systems) have `/proc/meminfo`
+    let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") else {
+        return;
+    };
+    let Some(total) = mem_total(&meminfo) else {
+        return;
+    };
+    if total < LOW_MEMORY_BYTES {
+        tracing::warn!(
+            "This machine has {} of memory, evaluating and building with Nix commonly needs more than {}",
+            format_bytes(total),
+            format_bytes(LOW_MEMORY_BYTES)
+        );
+    }
+}
+
+/// The `MemTotal` of `/proc/meminfo`, in bytes
+fn mem_total(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?;
+    let kib = line.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kib.saturating_mul(1024))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks_the_nearest_existing_directory() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("nix").join("store");
+        let mut settings = CommonSettings::try_default()?;
+
+        settings.min_free_space = 0;
+        check_free_space(&settings, &path)?;
+
+        // More than any filesystem has
+        settings.min_free_space = u64::MAX;
+        assert!(matches!(
+            check_free_space(&settings, &path),
+            Err(PlannerError::InsufficientSpace { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_total_memory() {
+        let meminfo = "MemTotal:        2030940 kB\nMemFree:          173356 kB\n";
+        assert_eq!(mem_total(meminfo), Some(2030940 * 1024));
+        assert_eq!(mem_total("MemFree: 1 kB\n"), None);
+    }
+}
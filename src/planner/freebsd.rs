@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "cli")]
+use clap::ArgAction;
+
+use super::{ShellProfileLocations, linux::check_nix_not_already_installed};
+use crate::{
+    Action, BuiltinPlanner,
+    action::{
+        StatefulAction,
+        base::{CreateDirectory, RemoveDirectory},
+        common::{
+            ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups, LinkDaemonSockets,
+            ProvisionNix,
+        },
+        linux::CreateZfsDataset,
+    },
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_free_space, warn_if_low_memory},
+        with_hooks,
+    },
+    settings::{CommonSettings, InitSystem, InstallSettingsError},
+};
+
+/// A planner for FreeBSD, with `/nix` on ZFS or UFS and the daemon run from `rc.d`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Freebsd {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    /// Which init system to configure (if `--init none` Nix will be root-only)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_parser,
+            long,
+            env = "NIX_INSTALLER_INIT",
+            default_value_t = InitSystem::RcD
+        )
+    )]
+    pub init: InitSystem,
+    /// Start the daemon (if not `--init none`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_parser,
+            long,
+            action(ArgAction::SetFalse),
+            env = "NIX_INSTALLER_START_DAEMON",
+            default_value_t = true,
+            long = "no-start-daemon"
+        )
+    )]
+    pub start_daemon: bool,
+    /// Create a ZFS dataset (such as `zroot/nix`) mounted at `/nix` to hold the Nix store, otherwise `/nix` is a directory on the root filesystem
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_ZFS_DATASET", value_name = "POOL/DATASET")
+    )]
+    pub zfs_dataset: Option<String>,
+}
+
+#[typetag::serde(name = "freebsd")]
+impl Planner for Freebsd {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            init: InitSystem::RcD,
+            start_daemon: true,
+            zfs_dataset: None,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let mut plan = vec![
+            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+
+        if let Some(zfs_dataset) = &self.zfs_dataset {
+            plan.push(
+                CreateZfsDataset::plan(zfs_dataset, "/nix")
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        plan.extend([
+            ProvisionNix::plan(&self.settings.clone())
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateUsersAndGroups::plan(self.settings.clone())
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ConfigureNix::plan(shell_profile_locations(), &self.settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ConfigureUpstreamInitService::plan(self.init, self.start_daemon, false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ]);
+
+        if !self.settings.extra_daemon_sockets.is_empty() {
+            plan.push(
+                LinkDaemonSockets::plan(self.settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        with_hooks(self.typetag_name(), &self.settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            settings,
+            init,
+            start_daemon,
+            zfs_dataset,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("init".into(), serde_json::to_value(init)?);
+        map.insert("start_daemon".into(), serde_json::to_value(start_daemon)?);
+        map.insert("zfs_dataset".into(), serde_json::to_value(zfs_dataset)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Freebsd => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        check_nix_not_already_installed()?;
+
+        check_free_space(&self.settings, Path::new("/nix"))?;
+        warn_if_low_memory();
+
+        Ok(())
+    }
+}
+
+impl From<Freebsd> for BuiltinPlanner {
+    fn from(val: Freebsd) -> Self {
+        BuiltinPlanner::Freebsd(val)
+    }
+}
+
+/// `zsh` from packages keeps its configuration in `/usr/local/etc`, while `sh` and `bash` login shells read `/etc/profile`
+fn shell_profile_locations() -> ShellProfileLocations {
+    ShellProfileLocations {
+        bash: vec!["/etc/profile".into()],
+        zsh: vec!["/usr/local/etc/zshrc".into()],
+        ..ShellProfileLocations::default()
+    }
+}
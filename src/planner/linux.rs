@@ -1,20 +1,44 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "cli")]
+use clap::ArgAction;
 
 use crate::util::which;
 use std::process::Command;
 
-use super::ShellProfileLocations;
+use super::{
+    ShellProfileLocations,
+    detect::{SandboxSupport, SelinuxMode, Wsl, userns_restricted},
+};
 use crate::{
     Action, BuiltinPlanner,
     action::{
         StatefulAction,
         base::{CreateDirectory, RemoveDirectory},
-        common::{ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionNix},
-        linux::{ProvisionSelinux, provision_selinux::SELINUX_POLICY_PP_CONTENT},
+        common::{
+            ConfigureAutoMaintenance, ConfigureConfigRefresh, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, LinkDaemonSockets, ProvisionNix,
+        },
+        linux::{
+            AllowUnprivilegedUserns, ConfigureSessionEnvironment, CreateNixBindMount,
+            CreateNixMountUnits, CreateSysusersBuildUsers, CreateZfsDataset, FormatNixDevice,
+            LinkNixToStoreRoot, ProvisionSelinux, create_sysusers_build_users::sysusers_available,
+            format_nix_device::NIX_DEVICE_LABEL, provision_selinux::SELINUX_POLICY_PP_CONTENT,
+        },
     },
     error::HasExpectedErrors,
-    planner::{Planner, PlannerError},
-    settings::{CommonSettings, InitSettings, InitSystem, InstallSettingsError},
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_free_space, warn_if_low_memory},
+        with_hooks,
+    },
+    settings::{
+        CommonSettings, Filesystem, InitSettings, InitSystem, InstallSettingsError, SandboxMode,
+        UrlOrPathOrString, UserManagement, UsernsWorkaround,
+    },
 };
 
 pub const FHS_SELINUX_POLICY_PATH: &str = "/usr/share/selinux/packages/nix.pp";
@@ -27,6 +51,108 @@ pub struct Linux {
     pub settings: CommonSettings,
     #[cfg_attr(feature = "cli", clap(flatten))]
     pub init: InitSettings,
+    /// Create a ZFS dataset (such as `rpool/nix`) mounted at `/nix` to hold the Nix store
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_ZFS_DATASET", value_name = "POOL/DATASET")
+    )]
+    pub zfs_dataset: Option<String>,
+    /// Keep the Nix store in this directory (such as `/data/nix`) on another filesystem, with `/nix` a symlink to it
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_STORE_ROOT",
+            value_name = "PATH",
+            conflicts_with = "zfs_dataset"
+        )
+    )]
+    #[serde(default)]
+    pub store_root: Option<PathBuf>,
+    /// Keep the Nix store in this directory (such as `/var/lib/nix`) on another filesystem, bind mounted on `/nix`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NIX_BACKING_DIR",
+            value_name = "PATH",
+            conflicts_with_all = ["zfs_dataset", "store_root"]
+        )
+    )]
+    #[serde(default)]
+    pub nix_backing_dir: Option<PathBuf>,
+    /// Format this blank device (such as `/dev/vdb1`) labeled `nix` and mount it on `/nix` with a systemd `nix.mount` unit
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NIX_DEVICE",
+            value_name = "DEVICE",
+            conflicts_with_all = ["zfs_dataset", "store_root", "nix_backing_dir"]
+        )
+    )]
+    #[serde(default)]
+    pub nix_device: Option<PathBuf>,
+    /// The filesystem to format `--nix-device` with
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            default_value_t = Filesystem::Ext4,
+            env = "NIX_INSTALLER_NIX_DEVICE_FILESYSTEM",
+            requires = "nix_device"
+        )
+    )]
+    #[serde(default)]
+    pub nix_device_filesystem: Filesystem,
+    /// Mount `--nix-device` on first use with a `nix.automount` unit, instead of at boot
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_NIX_DEVICE_AUTOMOUNT",
+            requires = "nix_device"
+        )
+    )]
+    #[serde(default)]
+    pub nix_device_automount: bool,
+    /// How to create the build users and group (`--use-sysusers` is `sysusers`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            alias = "use-sysusers",
+            num_args = 0..=1,
+            default_value_t = UserManagement::Auto,
+            default_missing_value = "sysusers",
+            env = "NIX_INSTALLER_USER_MANAGEMENT"
+        )
+    )]
+    #[serde(default)]
+    pub user_management: UserManagement,
+    /// Where AppArmor restricts unprivileged user namespaces (as on Ubuntu 24.04), how to let Nix's build sandbox create them
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, value_enum, env = "NIX_INSTALLER_USERNS_WORKAROUND")
+    )]
+    #[serde(default)]
+    pub userns_workaround: Option<UsernsWorkaround>,
+    /// Whether to keep Nix's build sandbox: `auto` drops it if the kernel doesn't support it, `require` fails instead
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            default_value_t = SandboxMode::Auto,
+            env = "NIX_INSTALLER_SANDBOX"
+        )
+    )]
+    #[serde(default)]
+    pub sandbox: SandboxMode,
 }
 
 #[typetag::serde(name = "linux")]
@@ -35,56 +161,64 @@ impl Planner for Linux {
         Ok(Self {
             settings: CommonSettings::try_default()?,
             init: InitSettings::try_default()?,
+            zfs_dataset: None,
+            store_root: None,
+            nix_backing_dir: None,
+            nix_device: None,
+            nix_device_filesystem: Filesystem::default(),
+            nix_device_automount: false,
+            user_management: UserManagement::default(),
+            userns_workaround: None,
+            sandbox: SandboxMode::Auto,
         })
     }
 
     fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
-        let has_selinux = detect_selinux()?;
-
-        let mut plan = vec![
-            CreateDirectory::plan("/nix", None, None, 0o0755, true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-            ProvisionNix::plan(&self.settings.clone())
-                .map_err(PlannerError::Action)?
-                .boxed(),
-            CreateUsersAndGroups::plan(self.settings.clone())
-                .map_err(PlannerError::Action)?
-                .boxed(),
-            ConfigureNix::plan(ShellProfileLocations::default(), &self.settings)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        ];
-
-        if has_selinux {
-            plan.push(
-                ProvisionSelinux::plan(FHS_SELINUX_POLICY_PATH.into(), SELINUX_POLICY_PP_CONTENT)
-                    .map_err(PlannerError::Action)?
-                    .boxed(),
-            );
-        }
-
-        plan.extend([
-            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-            ConfigureUpstreamInitService::plan(self.init.init, self.init.start_daemon)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        ]);
-
-        Ok(plan)
+        self.plan_for(self.typetag_name())
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
-        let Self { settings, init } = self;
+        let Self {
+            settings,
+            init,
+            zfs_dataset,
+            store_root,
+            nix_backing_dir,
+            nix_device,
+            nix_device_filesystem,
+            nix_device_automount,
+            user_management,
+            userns_workaround,
+            sandbox,
+        } = self;
         let mut map = HashMap::default();
 
         map.extend(settings.settings()?);
         map.extend(init.settings()?);
+        map.insert("zfs_dataset".into(), serde_json::to_value(zfs_dataset)?);
+        map.insert("store_root".into(), serde_json::to_value(store_root)?);
+        map.insert(
+            "nix_backing_dir".into(),
+            serde_json::to_value(nix_backing_dir)?,
+        );
+        map.insert("nix_device".into(), serde_json::to_value(nix_device)?);
+        map.insert(
+            "nix_device_filesystem".into(),
+            serde_json::to_value(nix_device_filesystem)?,
+        );
+        map.insert(
+            "nix_device_automount".into(),
+            serde_json::to_value(nix_device_automount)?,
+        );
+        map.insert(
+            "user_management".into(),
+            serde_json::to_value(user_management)?,
+        );
+        map.insert(
+            "userns_workaround".into(),
+            serde_json::to_value(userns_workaround)?,
+        );
+        map.insert("sandbox".into(), serde_json::to_value(sandbox)?);
 
         Ok(map)
     }
@@ -103,6 +237,18 @@ impl Planner for Linux {
         Ok(settings)
     }
 
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
     fn platform_check(&self) -> Result<(), PlannerError> {
         use target_lexicon::OperatingSystem;
         match target_lexicon::OperatingSystem::host() {
@@ -131,7 +277,19 @@ impl Planner for Linux {
 
         check_not_wsl1()?;
 
-        if self.init.init == InitSystem::Systemd && self.init.start_daemon {
+        // A `--nix-device` is formatted, so the space on it isn't known until then
+        if self.nix_device.is_none() {
+            let nix_dir = self.nix_backing_dir.as_deref().unwrap_or(Path::new("/nix"));
+            check_free_space(&self.settings, nix_dir)?;
+        }
+        warn_if_low_memory();
+
+        // The `nix.mount` unit for `--nix-backing-dir` or `--nix-device` is started right away, even if `--no-start-daemon`
+        if self.init.init == InitSystem::Systemd
+            && (self.init.start_daemon
+                || self.nix_backing_dir.is_some()
+                || self.nix_device.is_some())
+        {
             check_systemd_active()?;
         }
 
@@ -139,12 +297,238 @@ impl Planner for Linux {
     }
 }
 
+impl Linux {
+    /// Plan the install, as `planner` for its hooks (such as when a planner builds on this one)
+    pub(crate) fn plan_for(
+        &self,
+        planner: &str,
+    ) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        if self.nix_device.is_some() && self.init.init != InitSystem::Systemd {
+            return Err(LinuxErrorKind::NixDeviceRequiresSystemd.into());
+        }
+
+        let has_selinux = detect_selinux()?;
+
+        let mut settings = self.settings.clone();
+        plan_sandbox(&mut settings, self.sandbox)?;
+        let mut plan = vec![];
+        match &self.store_root {
+            Some(store_root) => {
+                plan.push(
+                    LinkNixToStoreRoot::plan(store_root)
+                        .map_err(PlannerError::Action)?
+                        .boxed(),
+                );
+                // Nix refuses a store path with symlinks in it otherwise
+                settings.extra_conf.push(UrlOrPathOrString::String(
+                    "allow-symlinked-store = true".into(),
+                ));
+            },
+            None => plan.push(
+                CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            ),
+        }
+
+        if let Some(zfs_dataset) = &self.zfs_dataset {
+            plan.push(
+                CreateZfsDataset::plan(zfs_dataset, "/nix")
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if let Some(nix_backing_dir) = &self.nix_backing_dir {
+            plan.push(
+                CreateNixBindMount::plan(nix_backing_dir, self.init.init)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if let Some(nix_device) = &self.nix_device {
+            plan.extend([
+                FormatNixDevice::plan(nix_device, self.nix_device_filesystem)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+                // Device names like `/dev/vdb1` can change between boots, the label doesn't
+                CreateNixMountUnits::plan(
+                    format!("/dev/disk/by-label/{NIX_DEVICE_LABEL}"),
+                    self.nix_device_filesystem,
+                    self.nix_device_automount,
+                )
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ]);
+        }
+
+        plan.extend([
+            ProvisionNix::plan(&settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            plan_build_users(&settings, self.user_management)?,
+            ConfigureNix::plan(ShellProfileLocations::default(), &settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ]);
+
+        if settings.modify_profile {
+            plan.push(
+                ConfigureSessionEnvironment::plan(self.init.init, settings.force)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if settings.registry_url.is_some() || settings.netrc_url.is_some() {
+            plan.push(
+                ConfigureConfigRefresh::plan(self.init.init, &settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if settings.enable_auto_gc.is_some() || settings.enable_auto_optimise.is_some() {
+            plan.push(
+                ConfigureAutoMaintenance::plan(self.init.init, &settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if !settings.extra_daemon_sockets.is_empty() {
+            plan.push(
+                LinkDaemonSockets::plan(settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if has_selinux {
+            plan.push(
+                ProvisionSelinux::plan(FHS_SELINUX_POLICY_PATH.into(), SELINUX_POLICY_PP_CONTENT)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        match self.userns_workaround {
+            Some(workaround) => plan.push(
+                AllowUnprivilegedUserns::plan(workaround)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            ),
+            None if userns_restricted() => tracing::warn!(
+                "AppArmor restricts unprivileged user namespaces, so sandboxed builds run outside the Nix daemon will fail, pass `--userns-workaround apparmor` (or `sysctl`) to allow them"
+            ),
+            None => (),
+        }
+
+        let mut configure_init_service = ConfigureUpstreamInitService::plan(
+            self.init.init,
+            self.init.start_daemon,
+            self.init.harden_daemon,
+        )
+        .map_err(PlannerError::Action)?;
+        if let Some(proxy) = settings.proxy_for_daemon() {
+            configure_init_service.action.use_proxy(proxy);
+        }
+        if let Some(limits) = settings.daemon_limits() {
+            configure_init_service.action.limit_daemon(limits);
+        }
+        if let Some(path) = &settings.daemon_unit_override {
+            configure_init_service
+                .action
+                .override_unit(path)
+                .map_err(PlannerError::Action)?;
+        }
+        plan.extend([
+            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            configure_init_service.boxed(),
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ]);
+
+        with_hooks(planner, &settings, plan)
+    }
+}
+
 impl From<Linux> for BuiltinPlanner {
     fn from(val: Linux) -> Self {
         BuiltinPlanner::Linux(val)
     }
 }
 
+/// Plan creating the build users and group, with `systemd-sysusers` if selected (or detected by [`UserManagement::Auto`])
+pub(crate) fn plan_build_users(
+    settings: &CommonSettings,
+    user_management: UserManagement,
+) -> Result<StatefulAction<Box<dyn Action>>, PlannerError> {
+    let use_sysusers = match user_management {
+        UserManagement::Auto => sysusers_available(),
+        UserManagement::Sysusers => true,
+        UserManagement::Useradd => false,
+    };
+    tracing::debug!(%user_management, use_sysusers, "Planning build users");
+
+    if use_sysusers {
+        Ok(CreateSysusersBuildUsers::plan(settings)
+            .map_err(PlannerError::Action)?
+            .boxed())
+    } else {
+        Ok(CreateUsersAndGroups::plan(settings.clone())
+            .map_err(PlannerError::Action)?
+            .boxed())
+    }
+}
+
+/// Check the kernel supports Nix's build sandbox as `mode` requires, setting `sandbox = false` in `settings` if it is dropped
+pub(crate) fn plan_sandbox(
+    settings: &mut CommonSettings,
+    mode: SandboxMode,
+) -> Result<(), PlannerError> {
+    if mode == SandboxMode::Disable {
+        settings
+            .extra_conf
+            .push(UrlOrPathOrString::String("sandbox = false".into()));
+        return Ok(());
+    }
+
+    let support = SandboxSupport::detect();
+    if !support.user_namespaces {
+        tracing::warn!(
+            "User namespaces are unavailable, so sandboxed builds run outside the Nix daemon will fail"
+        );
+    }
+    let missing = support.missing();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let missing = missing.join(" and ");
+    match mode {
+        SandboxMode::Require => Err(LinuxErrorKind::SandboxUnsupported(missing).into()),
+        SandboxMode::Auto | SandboxMode::Disable => {
+            tracing::warn!(
+                "The kernel does not provide {missing} to Nix's build sandbox, setting `sandbox = false` (pass `--sandbox require` to fail instead)"
+            );
+            settings
+                .extra_conf
+                .push(UrlOrPathOrString::String("sandbox = false".into()));
+            // Nix loads its seccomp filter for every build, not only sandboxed ones
+            if !support.seccomp {
+                settings
+                    .extra_conf
+                    .push(UrlOrPathOrString::String("filter-syscalls = false".into()));
+            }
+            Ok(())
+        },
+    }
+}
+
 // If on NixOS, running `nix_installer` is pointless
 pub(crate) fn check_not_nixos() -> Result<(), PlannerError> {
     // NixOS always sets up this file as part of setting up /etc itself: https://github.com/NixOS/nixpkgs/blob/bdd39e5757d858bd6ea58ed65b4a2e52c8ed11ca/nixos/modules/system/etc/setup-etc.pl#L145
@@ -155,15 +539,14 @@ pub(crate) fn check_not_nixos() -> Result<(), PlannerError> {
 }
 
 pub(crate) fn check_not_wsl1() -> Result<(), PlannerError> {
-    // Detection strategies: https://patrickwu.space/wslconf/
-    if std::env::var("WSL_DISTRO_NAME").is_ok() && std::env::var("WSL_INTEROP").is_err() {
+    if Wsl::detect() == Some(Wsl::Wsl1) {
         return Err(PlannerError::Wsl1);
     }
     Ok(())
 }
 
 pub(crate) fn detect_selinux() -> Result<bool, PlannerError> {
-    if Path::new("/sys/fs/selinux").exists() && which("sestatus").is_some() {
+    if SelinuxMode::detect().is_some() && which("sestatus").is_some() {
         // We expect systems with SELinux to have the normal SELinux tools.
         let has_semodule = which("semodule").is_some();
         let has_restorecon = which("restorecon").is_some();
@@ -226,6 +609,17 @@ pub enum LinuxErrorKind {
         To use a `root`-only Nix install, consider passing `--init none`."
     )]
     Wsl2SystemdNotActive,
+    #[error("`--nix-device` mounts the device with systemd units, so needs `--init systemd`")]
+    NixDeviceRequiresSystemd,
+    #[error(
+        "\
+        Nix's build sandbox needs {0}, which the kernel does not provide here.\n\
+        \n\
+        In an LXC container, enable nesting (`features: nesting=1` on Proxmox). OpenVZ containers and other unprivileged containers may not allow it at all.\n\
+        \n\
+        To install without the sandbox, pass `--sandbox disable` (or `auto`)."
+    )]
+    SandboxUnsupported(String),
 }
 
 impl HasExpectedErrors for LinuxErrorKind {
@@ -233,6 +627,8 @@ impl HasExpectedErrors for LinuxErrorKind {
         match self {
             LinuxErrorKind::SystemdNotActive => Some(Box::new(self)),
             LinuxErrorKind::Wsl2SystemdNotActive => Some(Box::new(self)),
+            LinuxErrorKind::NixDeviceRequiresSystemd => Some(Box::new(self)),
+            LinuxErrorKind::SandboxUnsupported(_) => Some(Box::new(self)),
         }
     }
 }
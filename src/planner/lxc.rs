@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    detect::IdMap,
+    linux::{Linux, check_not_wsl1, check_systemd_active},
+};
+use crate::{
+    Action, BuiltinPlanner,
+    action::StatefulAction,
+    error::HasExpectedErrors,
+    planner::{Planner, PlannerError},
+    settings::{CommonSettings, InitSettings, InitSystem, InstallSettingsError, SandboxMode},
+};
+
+/// `nobody` is usually 65534, so build users are kept below it
+const HIGHEST_BUILD_ID: u32 = 65533;
+
+/// A planner for LXC containers (such as on Proxmox) running an init, including unprivileged ones
+///
+/// It plans like the `linux` planner, but drops the sandbox if the container doesn't let Nix create
+/// namespaces, never hardens the daemon (unprivileged containers can't create the namespaces that
+/// needs either), and moves the build users into the IDs the container maps.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Lxc {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub init: InitSettings,
+    /// Whether to keep Nix's build sandbox: `auto` drops it if the container doesn't allow it (such as without nesting), `require` fails instead
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            default_value_t = SandboxMode::Auto,
+            env = "NIX_INSTALLER_SANDBOX"
+        )
+    )]
+    #[serde(default)]
+    pub sandbox: SandboxMode,
+}
+
+#[typetag::serde(name = "lxc")]
+impl Planner for Lxc {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            init: InitSettings::try_default()?,
+            sandbox: SandboxMode::Auto,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        self.linux()?.plan_for(self.typetag_name())
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            settings,
+            init,
+            sandbox,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.extend(init.settings()?);
+        map.insert("sandbox".into(), serde_json::to_value(sandbox)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        check_not_wsl1()?;
+
+        if self.init.init == InitSystem::Systemd && self.init.start_daemon {
+            check_systemd_active()?;
+        }
+
+        Ok(())
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        self.linux()?.pre_install_check()
+    }
+}
+
+impl Lxc {
+    /// The `linux` planner this one plans like, adjusted for the container
+    fn linux(&self) -> Result<Linux, PlannerError> {
+        let mut settings = self.settings.clone();
+        if let (Some(uid_map), Some(gid_map)) = (
+            IdMap::read("/proc/self/uid_map"),
+            IdMap::read("/proc/self/gid_map"),
+        ) {
+            fit_build_ids(&mut settings, &uid_map, &gid_map)?;
+        }
+
+        let mut init = self.init.clone();
+        if init.harden_daemon {
+            tracing::warn!(
+                "Not hardening the Nix daemon, LXC containers commonly can't create the namespaces it needs"
+            );
+            init.harden_daemon = false;
+        }
+
+        Ok(Linux {
+            settings,
+            init,
+            sandbox: self.sandbox,
+            ..Linux::try_default()?
+        })
+    }
+}
+
+/// Move the build users and group into the IDs mapped into the container, if they aren't already
+fn fit_build_ids(
+    settings: &mut CommonSettings,
+    uid_map: &IdMap,
+    gid_map: &IdMap,
+) -> Result<(), LxcError> {
+    let count = settings.nix_build_user_count;
+    let first_uid = settings.nix_build_user_id_base.saturating_add(1);
+    let last_uid = settings.nix_build_user_id_base.saturating_add(count);
+    if !uid_map.contains(first_uid, last_uid) {
+        let base = highest_base(uid_map, count)?;
+        tracing::warn!(
+            "The container doesn't map user IDs {first_uid}-{last_uid}, creating the build users from {} instead",
+            base + 1
+        );
+        settings.nix_build_user_id_base = base;
+    }
+
+    let gid = settings.nix_build_group_id;
+    if !gid_map.contains(gid, gid) {
+        // Like the default IDs, the group's ID is the build users' base
+        let gid = highest_base(gid_map, count)?;
+        tracing::warn!(
+            "The container doesn't map group ID {}, creating the build group as {gid} instead",
+            settings.nix_build_group_id
+        );
+        settings.nix_build_group_id = gid;
+    }
+
+    Ok(())
+}
+
+/// The highest ID in the largest range of `map` which leaves `count` IDs after it, below `nobody`
+fn highest_base(map: &IdMap, count: u32) -> Result<u32, LxcError> {
+    let needed = count + 1;
+    let (start, mapped) = map.largest().unwrap_or_default();
+    let end = start
+        .saturating_add(mapped.saturating_sub(1))
+        .min(HIGHEST_BUILD_ID);
+    match end.checked_sub(count) {
+        Some(base) if base >= start => Ok(base),
+        _ => Err(LxcError::IdMapTooSmall { mapped, needed }),
+    }
+}
+
+impl From<Lxc> for BuiltinPlanner {
+    fn from(val: Lxc) -> Self {
+        BuiltinPlanner::Lxc(val)
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum LxcError {
+    #[error(
+        "The container maps {mapped} IDs into it, too few for the {needed} Nix needs for its build users and group. Map more with `lxc.idmap` in the container's configuration, or pass a lower `--nix-build-user-count`"
+    )]
+    IdMapTooSmall { mapped: u32, needed: u32 },
+}
+
+impl HasExpectedErrors for LxcError {
+    fn expected<'a>(&'a self) -> Option<Box<dyn std::error::Error + 'a>> {
+        match self {
+            this @ LxcError::IdMapTooSmall { .. } => Some(Box::new(this)),
+        }
+    }
+}
+
+impl From<LxcError> for PlannerError {
+    fn from(v: LxcError) -> PlannerError {
+        PlannerError::Custom(Box::new(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fits_build_ids_into_the_id_map() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = CommonSettings::try_default()?;
+        settings.nix_build_user_id_base = 30000;
+        settings.nix_build_user_count = 32;
+        settings.nix_build_group_id = 30000;
+
+        // LXC's default map has room for the default IDs
+        let lxc_default = IdMap::parse("0 100000 65536\n");
+        fit_build_ids(&mut settings, &lxc_default, &lxc_default)?;
+        assert_eq!(settings.nix_build_user_id_base, 30000);
+        assert_eq!(settings.nix_build_group_id, 30000);
+
+        let small = IdMap::parse("0 100000 10000\n");
+        fit_build_ids(&mut settings, &small, &small)?;
+        assert_eq!(settings.nix_build_user_id_base, 9967);
+        assert_eq!(settings.nix_build_group_id, 9967);
+        assert!(small.contains(9968, 9999));
+
+        let tiny = IdMap::parse("0 100000 16\n");
+        assert!(matches!(
+            fit_build_ids(&mut settings, &tiny, &tiny),
+            Err(LxcError::IdMapTooSmall { mapped: 16, .. })
+        ));
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use std::{collections::HashMap, io::Cursor, path::PathBuf};
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
 
 use crate::util::which;
 #[cfg(feature = "cli")]
 use clap::ArgAction;
+use nix::unistd::{AccessFlags, access};
 use std::process::Command;
 
 use super::ShellProfileLocations;
@@ -16,15 +21,24 @@ use crate::{
     Action, BuiltinPlanner,
     action::{
         StatefulAction,
-        base::RemoveDirectory,
-        common::{ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionNix},
+        base::{CreateDirectory, RemoveDirectory},
+        common::{
+            ConfigureAutoMaintenance, ConfigureConfigRefresh, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, LinkDaemonSockets, ProvisionNix,
+        },
         macos::{
-            ConfigureRemoteBuilding, CreateNixHookService, CreateNixVolume, SetTmutilExclusions,
+            ApfsVolumeOptions, ConfigureRemoteBuilding, CopyNixDirectory, CreateNixHookService,
+            CreateNixVolume, DEFAULT_MOUNT_TIMEOUT_SECS, ExportKeychainCertificates,
+            MIGRATED_NIX_DIR, MoveAsideNixDirectory, SYSTEM_CA_BUNDLE_DEST, SetTmutilExclusions,
         },
     },
     execute_command,
-    os::darwin::DiskUtilInfoOutput,
-    planner::{Planner, PlannerError},
+    os::darwin::{DiskUtilApfsListOutput, DiskUtilInfoOutput},
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_available, check_free_space},
+        with_hooks,
+    },
     settings::InstallSettingsError,
     settings::{CommonSettings, InitSystem},
 };
@@ -67,6 +81,65 @@ pub struct Macos {
     /// The root disk of the target
     #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_ROOT_DISK"))]
     pub root_disk: Option<String>,
+    /// The APFS role(s) of the created volume, as passed to `diskutil apfs addVolume -role`
+    #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_VOLUME_ROLE"))]
+    #[serde(default)]
+    pub volume_role: Option<String>,
+    /// Space to reserve for the created volume within its container (eg. `20g`)
+    #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_VOLUME_RESERVE"))]
+    #[serde(default)]
+    pub volume_reserve: Option<String>,
+    /// The most space the created volume may consume within its container (eg. `100g`)
+    #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_VOLUME_QUOTA"))]
+    #[serde(default)]
+    pub volume_quota: Option<String>,
+    /// The label of the System keychain item holding the encrypted volume's password (default: `{root disk} encryption password`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_VOLUME_KEYCHAIN_LABEL")
+    )]
+    #[serde(default)]
+    pub volume_keychain_label: Option<String>,
+    /// Seconds to wait for the Nix Store volume to mount on `/nix`, before kickstarting its mount daemon and waiting as long again
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            default_value_t = DEFAULT_MOUNT_TIMEOUT_SECS,
+            env = "NIX_INSTALLER_MOUNT_TIMEOUT",
+            value_name = "SECONDS"
+        )
+    )]
+    #[serde(default = "default_mount_timeout_secs")]
+    pub mount_timeout: u64,
+    /// Run the Nix daemon under `caffeinate`, so the Mac doesn't sleep (even with the lid closed, on AC power) and kill long builds, such as on CI runners
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_PREVENT_SLEEP"
+        )
+    )]
+    #[serde(default)]
+    pub prevent_sleep: bool,
+    /// Move a `/nix` directory left by an install before the Nix Store volume into a new volume, keeping its store and profiles
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_MIGRATE_STORE"
+        )
+    )]
+    #[serde(default)]
+    pub migrate_store: bool,
+}
+
+fn default_mount_timeout_secs() -> u64 {
+    DEFAULT_MOUNT_TIMEOUT_SECS
 }
 
 fn default_root_disk() -> Result<String, PlannerError> {
@@ -82,19 +155,8 @@ fn default_root_disk() -> Result<String, PlannerError> {
     Ok(the_plist.parent_whole_disk)
 }
 
-#[typetag::serde(name = "macos")]
-impl Planner for Macos {
-    fn try_default() -> Result<Self, PlannerError> {
-        Ok(Self {
-            settings: CommonSettings::try_default()?,
-            root_disk: Some(default_root_disk()?),
-            case_sensitive: false,
-            encrypt: None,
-            volume_label: "Nix Store".into(),
-        })
-    }
-
-    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+impl Macos {
+    fn plan_nix_volume(&self) -> Result<StatefulAction<CreateNixVolume>, PlannerError> {
         let root_disk = match &self.root_disk {
             root_disk @ Some(_) => root_disk.clone(),
             None => Some(default_root_disk()?),
@@ -120,19 +182,8 @@ impl Planner for Macos {
                 }
             },
             None => {
-                let root_disk_is_encrypted = {
-                    let output = Command::new("/usr/bin/fdesetup")
-                        .arg("isactive")
-                        .stdout(std::process::Stdio::null())
-                        .stderr(std::process::Stdio::null())
-                        .output()
-                        .map_err(|e| PlannerError::Custom(Box::new(e)))?;
-
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stdout_trimmed = stdout.trim();
-
-                    stdout_trimmed == "true"
-                };
+                let root_disk_is_encrypted = crate::os::probe::filevault_is_active()
+                    .map_err(|e| PlannerError::Custom(Box::new(e)))?;
 
                 let existing_store_volume_is_encrypted = {
                     if let Some(diskutil_info) =
@@ -150,15 +201,198 @@ impl Planner for Macos {
             },
         };
 
-        let mut plan = vec![
-            CreateNixVolume::plan(
-                root_disk.unwrap(), /* We just ensured it was populated */
-                self.volume_label.clone(),
-                self.case_sensitive,
-                encrypt,
-            )
-            .map_err(PlannerError::Action)?
-            .boxed(),
+        CreateNixVolume::plan(
+            root_disk.unwrap(), /* We just ensured it was populated */
+            self.volume_label.clone(),
+            self.case_sensitive,
+            encrypt,
+            self.volume_keychain_label.clone(),
+            self.volume_options()?,
+            self.mount_timeout,
+        )
+        .map_err(PlannerError::Action)
+    }
+
+    /// Check the APFS container the Nix Store volume is added to has room for it, and its `--volume-reserve`
+    fn check_container_space(&self) -> Result<(), PlannerError> {
+        // An existing volume is reused, and its space is already allocated from the container
+        if crate::action::macos::get_disk_info_for_label(&self.volume_label)
+            .ok()
+            .flatten()
+            .is_some()
+        {
+            return Ok(());
+        }
+        let root_disk = match &self.root_disk {
+            Some(root_disk) => root_disk.clone(),
+            None => default_root_disk()?,
+        };
+        let output = execute_command(
+            Command::new("/usr/sbin/diskutil")
+                .args(["apfs", "list", "-plist"])
+                .stdin(std::process::Stdio::null()),
+        )
+        .map_err(|e| PlannerError::Custom(Box::new(e)))?;
+        let list: DiskUtilApfsListOutput = plist::from_bytes(&output.stdout)?;
+        let Some(available) = list
+            .containers
+            .into_iter()
+            .find(|container| container.container_reference.as_deref() == Some(&root_disk))
+            .and_then(|container| container.capacity_free)
+        else {
+            tracing::warn!(
+                "Could not tell how much space is free in the APFS container `{root_disk}`, not checking it"
+            );
+            return Ok(());
+        };
+
+        let reserve = match &self.volume_reserve {
+            Some(reserve) => {
+                volume_size_bytes(reserve).map_err(|e| PlannerError::Custom(Box::new(e)))?
+            },
+            None => 0,
+        };
+        // The store is unpacked into the reserved space, so the reserve only adds to what's needed if it's larger
+        let mut required = self.settings.required_free_space().max(reserve);
+        if self.migrate_store {
+            required += nix_directory_size()?;
+        }
+        check_available(
+            format!("the APFS container `{root_disk}`"),
+            available,
+            required,
+        )
+    }
+
+    fn volume_options(&self) -> Result<ApfsVolumeOptions, PlannerError> {
+        for size in [&self.volume_reserve, &self.volume_quota]
+            .into_iter()
+            .flatten()
+        {
+            volume_size_bytes(size).map_err(|e| PlannerError::Custom(Box::new(e)))?;
+        }
+
+        Ok(ApfsVolumeOptions {
+            role: self.volume_role.clone(),
+            reserve: self.volume_reserve.clone(),
+            quota: self.volume_quota.clone(),
+        })
+    }
+}
+
+/// The bytes in `size`, if it is understood by `diskutil`: a number with an optional `B`, `K`, `M`, `G`, `T` or `P` suffix
+///
+/// Like `diskutil`, the units are powers of 1000.
+fn volume_size_bytes(size: &str) -> Result<u64, MacosError> {
+    let lowercase = size.to_ascii_lowercase();
+    let number = lowercase.strip_suffix('b').unwrap_or(&lowercase);
+    let exponent = match number.chars().last() {
+        Some('k') => 1,
+        Some('m') => 2,
+        Some('g') => 3,
+        Some('t') => 4,
+        Some('p') => 5,
+        _ => 0,
+    };
+    let number = if exponent > 0 {
+        &number[..number.len() - 1]
+    } else {
+        number
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => {
+            Ok((value * 1000f64.powi(exponent)) as u64)
+        },
+        _ => Err(MacosError::InvalidVolumeSize(size.to_string())),
+    }
+}
+
+/// The bytes used by the `/nix` directory `--migrate-store` copies into the volume
+fn nix_directory_size() -> Result<u64, PlannerError> {
+    let output = execute_command(
+        Command::new("/usr/bin/du")
+            .args(["-sk", "/nix"])
+            .stdin(std::process::Stdio::null()),
+    )
+    .map_err(|e| PlannerError::Custom(Box::new(e)))?;
+    let kibibytes = String::from_utf8(output.stdout)?
+        .split_whitespace()
+        .next()
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    Ok(kibibytes * 1024)
+}
+
+/// Whether `/nix` can be created directly on the root filesystem, as on macOS before Catalina (10.15)
+fn root_is_writable() -> Result<bool, PlannerError> {
+    let output = execute_command(
+        Command::new("/usr/bin/sw_vers")
+            .arg("-productVersion")
+            .stdin(std::process::Stdio::null()),
+    )
+    .map_err(|e| PlannerError::Custom(Box::new(e)))?;
+    let product_version = String::from_utf8(output.stdout)?;
+    let mut components = product_version
+        .trim()
+        .split('.')
+        .map(|component| component.parse::<u32>().unwrap_or(0));
+    let major = components.next().unwrap_or(0);
+    let minor = components.next().unwrap_or(0);
+    if (major, minor) < (10, 15) {
+        tracing::debug!(%product_version, "Detected macOS before Catalina");
+        return Ok(true);
+    }
+
+    Ok(access("/", AccessFlags::W_OK).is_ok())
+}
+
+#[typetag::serde(name = "macos")]
+impl Planner for Macos {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            root_disk: Some(default_root_disk()?),
+            case_sensitive: false,
+            encrypt: None,
+            volume_label: "Nix Store".into(),
+            volume_role: None,
+            volume_reserve: None,
+            volume_quota: None,
+            volume_keychain_label: None,
+            mount_timeout: DEFAULT_MOUNT_TIMEOUT_SECS,
+            prevent_sleep: false,
+            migrate_store: false,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let mut plan = vec![];
+
+        if self.migrate_store {
+            // The earlier install's `/nix` is on the root filesystem, which is only writable before Catalina
+            plan.extend([
+                MoveAsideNixDirectory::plan(MIGRATED_NIX_DIR)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+                self.plan_nix_volume()?.boxed(),
+                CopyNixDirectory::plan(MIGRATED_NIX_DIR)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            ]);
+        } else if root_is_writable()? {
+            // Before Catalina the root filesystem is writable, so (like the legacy shell installer) `/nix` can live on it directly
+            tracing::debug!("Root filesystem is writable, creating `/nix` without an APFS volume");
+            plan.push(
+                CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        } else {
+            plan.push(self.plan_nix_volume()?.boxed());
+        }
+
+        plan.extend([
             ProvisionNix::plan(&self.settings)
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -173,13 +407,48 @@ impl Planner for Macos {
             ])
             .map_err(PlannerError::Action)?
             .boxed(),
+        ]);
+
+        if self.settings.use_system_ca && self.settings.ssl_cert_file.is_none() {
+            plan.push(
+                ExportKeychainCertificates::plan(SYSTEM_CA_BUNDLE_DEST)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        plan.extend([
             ConfigureNix::plan(ShellProfileLocations::default(), &self.settings)
                 .map_err(PlannerError::Action)?
                 .boxed(),
             ConfigureRemoteBuilding::plan()
                 .map_err(PlannerError::Action)?
                 .boxed(),
-        ];
+        ]);
+
+        if self.settings.registry_url.is_some() || self.settings.netrc_url.is_some() {
+            plan.push(
+                ConfigureConfigRefresh::plan(InitSystem::Launchd, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.settings.enable_auto_gc.is_some() || self.settings.enable_auto_optimise.is_some() {
+            plan.push(
+                ConfigureAutoMaintenance::plan(InitSystem::Launchd, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if !self.settings.extra_daemon_sockets.is_empty() {
+            plan.push(
+                LinkDaemonSockets::plan(self.settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
 
         if self.settings.modify_profile {
             plan.push(
@@ -189,16 +458,32 @@ impl Planner for Macos {
             );
         }
 
+        let mut configure_init_service =
+            ConfigureUpstreamInitService::plan(InitSystem::Launchd, true, false)
+                .map_err(PlannerError::Action)?;
+        if let Some(proxy) = self.settings.proxy_for_daemon() {
+            configure_init_service.action.use_proxy(proxy);
+        }
+        if let Some(limits) = self.settings.daemon_limits() {
+            configure_init_service.action.limit_daemon(limits);
+        }
+        if self.prevent_sleep {
+            configure_init_service.action.prevent_sleep();
+        }
+        if let Some(path) = &self.settings.daemon_unit_override {
+            configure_init_service
+                .action
+                .override_unit(path)
+                .map_err(PlannerError::Action)?;
+        }
         plan.extend([
-            ConfigureUpstreamInitService::plan(InitSystem::Launchd, true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
+            configure_init_service.boxed(),
             RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
                 .map_err(PlannerError::Action)?
                 .boxed(),
         ]);
 
-        Ok(plan)
+        with_hooks(self.typetag_name(), &self.settings, plan)
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
@@ -208,6 +493,13 @@ impl Planner for Macos {
             volume_label,
             case_sensitive,
             root_disk,
+            volume_role,
+            volume_reserve,
+            volume_quota,
+            volume_keychain_label,
+            mount_timeout,
+            prevent_sleep,
+            migrate_store,
         } = self;
         let mut map = HashMap::default();
 
@@ -219,6 +511,19 @@ impl Planner for Macos {
             "case_sensitive".into(),
             serde_json::to_value(case_sensitive)?,
         );
+        map.insert("volume_role".into(), serde_json::to_value(volume_role)?);
+        map.insert(
+            "volume_reserve".into(),
+            serde_json::to_value(volume_reserve)?,
+        );
+        map.insert("volume_quota".into(), serde_json::to_value(volume_quota)?);
+        map.insert(
+            "volume_keychain_label".into(),
+            serde_json::to_value(volume_keychain_label)?,
+        );
+        map.insert("mount_timeout".into(), serde_json::to_value(mount_timeout)?);
+        map.insert("prevent_sleep".into(), serde_json::to_value(prevent_sleep)?);
+        map.insert("migrate_store".into(), serde_json::to_value(migrate_store)?);
 
         Ok(map)
     }
@@ -237,6 +542,18 @@ impl Planner for Macos {
         Ok(settings)
     }
 
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
     fn platform_check(&self) -> Result<(), PlannerError> {
         use target_lexicon::OperatingSystem;
         match target_lexicon::OperatingSystem::host() {
@@ -255,7 +572,13 @@ impl Planner for Macos {
     }
 
     fn pre_install_check(&self) -> Result<(), PlannerError> {
-        check_suis()?;
+        // Media restriction policies only interfere with the APFS volume
+        if root_is_writable()? && !self.migrate_store {
+            check_free_space(&self.settings, Path::new("/nix"))?;
+        } else {
+            check_suis()?;
+            self.check_container_space()?;
+        }
         check_not_running_in_rosetta()?;
 
         Ok(())
@@ -356,6 +679,11 @@ pub enum MacosError {
 
     #[error("{0}")]
     BlockedBySystemUIServerPolicy(String),
+
+    #[error(
+        "`{0}` is not a valid volume size, use a number with an optional unit such as `500m` or `100g`"
+    )]
+    InvalidVolumeSize(String),
 }
 
 impl HasExpectedErrors for MacosError {
@@ -363,6 +691,28 @@ impl HasExpectedErrors for MacosError {
         match self {
             this @ MacosError::UninstallNixDarwin => Some(Box::new(this)),
             this @ MacosError::BlockedBySystemUIServerPolicy(_) => Some(Box::new(this)),
+            this @ MacosError::InvalidVolumeSize(_) => Some(Box::new(this)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::volume_size_bytes;
+
+    #[test]
+    fn volume_sizes() {
+        for valid in ["100g", "100G", "100GB", "1.5t", "512000000", "500mb"] {
+            assert!(volume_size_bytes(valid).is_ok(), "{valid} should be valid");
+        }
+        assert_eq!(volume_size_bytes("1.5t").unwrap(), 1_500_000_000_000);
+        assert_eq!(volume_size_bytes("500mb").unwrap(), 500_000_000);
+        assert_eq!(volume_size_bytes("512").unwrap(), 512);
+        for invalid in ["", "g", "100x", "-1g", "0", "100 g"] {
+            assert!(
+                volume_size_bytes(invalid).is_err(),
+                "{invalid} should be invalid"
+            );
         }
     }
 }
@@ -100,39 +100,36 @@ match plan.install(None) {
 ```
 
 */
+pub mod alpine;
+pub mod container;
+pub mod detect;
+mod free_space;
+pub mod freebsd;
 pub mod linux;
+pub mod lxc;
 pub mod macos;
+pub mod nixos;
+pub mod none;
 pub mod ostree;
 pub mod steam_deck;
+pub mod termux;
 
-use std::{collections::HashMap, path::PathBuf, string::FromUtf8Error};
-
-/// Parse the ID field from /etc/os-release
-fn get_os_release_id() -> Option<String> {
-    let content = std::fs::read_to_string("/etc/os-release").ok()?;
-    for line in content.lines() {
-        if let Some(value) = line.strip_prefix("ID=") {
-            let value = value.trim();
-            // Remove quotes if present (single or double)
-            let value = if (value.starts_with('"') && value.ends_with('"'))
-                || (value.starts_with('\'') && value.ends_with('\''))
-            {
-                &value[1..value.len() - 1]
-            } else {
-                value
-            };
-            return Some(value.to_string());
-        }
-    }
-    None
-}
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     Action, InstallPlan, NixInstallerError,
-    action::{ActionError, StatefulAction},
-    error::HasExpectedErrors,
+    action::{
+        ActionError, StatefulAction,
+        common::{HookStage, RestoreStore, RunHook},
+    },
+    error::{ErrorCode, HasExpectedErrors},
+    plan::RECEIPT_FILE_NAME,
     settings::{CommonSettings, InstallSettingsError},
 };
 
@@ -161,6 +158,26 @@ pub trait Planner: std::fmt::Debug + Send + Sync + dyn_clone::DynClone {
 
     fn platform_check(&self) -> Result<(), PlannerError>;
 
+    /// The directory the install receipt is written to
+    fn state_dir(&self) -> &Path {
+        Path::new(crate::settings::DEFAULT_STATE_DIR)
+    }
+
+    /// Where the install receipt is written, `receipt.json` in the [`state_dir`](Planner::state_dir) unless overridden
+    fn receipt_path(&self) -> PathBuf {
+        self.state_dir().join(RECEIPT_FILE_NAME)
+    }
+
+    /// If uninstalling requires confirming the hostname of the machine
+    fn protected(&self) -> bool {
+        false
+    }
+
+    /// If installing and uninstalling need `root`, which `nix-installer` escalates to with `sudo`
+    fn requires_root(&self) -> bool {
+        true
+    }
+
     fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
         Ok(())
     }
@@ -176,16 +193,38 @@ dyn_clone::clone_trait_object!(Planner);
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "cli", derive(clap::Subcommand))]
 pub enum BuiltinPlanner {
-    #[cfg_attr(not(target_os = "linux"), clap(hide = true))]
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
     /// A planner for traditional, mutable Linux systems like Debian, RHEL, or Arch
     Linux(linux::Linux),
-    #[cfg_attr(not(target_os = "linux"), clap(hide = true))]
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
     /// A planner for the Valve Steam Deck running SteamOS
     SteamDeck(steam_deck::SteamDeck),
-    #[cfg_attr(not(target_os = "linux"), clap(hide = true))]
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
     /// A planner suitable for immutable systems using ostree, such as Fedora Silverblue
     Ostree(ostree::Ostree),
-    #[cfg_attr(not(target_os = "macos"), clap(hide = true))]
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    /// A planner for Alpine Linux and other musl/BusyBox based systems using OpenRC
+    Alpine(alpine::Alpine),
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    /// A planner for Docker, Podman and other containers without an init, such as when building images
+    Container(container::Container),
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    /// A planner for LXC containers (such as on Proxmox) running an init, including unprivileged ones
+    Lxc(lxc::Lxc),
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    /// A planner for NixOS, which installs a secondary store under a prefix
+    Nixos(nixos::Nixos),
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    /// A planner for Termux on Android, installing for the current user without `root`, used through proot
+    Termux(termux::Termux),
+    #[cfg_attr(all(feature = "cli", not(target_os = "linux")), clap(hide = true))]
+    #[cfg_attr(feature = "cli", clap(name = "none"))]
+    /// A planner without an init or daemon, for building images and chroots (`--store-only` installs only the store and `nix.conf`)
+    NoInit(none::NoInit),
+    #[cfg_attr(all(feature = "cli", not(target_os = "freebsd")), clap(hide = true))]
+    /// A planner for FreeBSD systems, using `rc.d` to run the daemon
+    Freebsd(freebsd::Freebsd),
+    #[cfg_attr(all(feature = "cli", not(target_os = "macos")), clap(hide = true))]
     /// A planner for MacOS (Darwin) systems
     Macos(macos::Macos),
 }
@@ -195,12 +234,11 @@ impl BuiltinPlanner {
     pub fn try_default() -> Result<Self, PlannerError> {
         use target_lexicon::{Architecture, OperatingSystem};
         match (Architecture::host(), OperatingSystem::host()) {
-            (Architecture::X86_64, OperatingSystem::Linux) => Self::detect_linux_distro(),
-            (Architecture::X86_32(_), OperatingSystem::Linux) => {
-                Ok(Self::Linux(linux::Linux::try_default()?))
-            },
-            (Architecture::Aarch64(_), OperatingSystem::Linux) => {
-                Ok(Self::Linux(linux::Linux::try_default()?))
+            (Architecture::X86_64, OperatingSystem::Linux)
+            | (Architecture::X86_32(_), OperatingSystem::Linux)
+            | (Architecture::Aarch64(_), OperatingSystem::Linux)
+            | (Architecture::Arm(_), OperatingSystem::Linux) => {
+                Self::from_host(&detect::Host::detect())
             },
             (Architecture::X86_64, OperatingSystem::MacOSX(_))
             | (Architecture::X86_64, OperatingSystem::Darwin(_)) => {
@@ -210,28 +248,47 @@ impl BuiltinPlanner {
             | (Architecture::Aarch64(_), OperatingSystem::Darwin(_)) => {
                 Ok(Self::Macos(macos::Macos::try_default()?))
             },
+            (Architecture::X86_64, OperatingSystem::Freebsd)
+            | (Architecture::Aarch64(_), OperatingSystem::Freebsd) => {
+                Ok(Self::Freebsd(freebsd::Freebsd::try_default()?))
+            },
             _ => Err(PlannerError::UnsupportedArchitecture(target_lexicon::HOST)),
         }
     }
 
-    fn detect_linux_distro() -> Result<Self, PlannerError> {
-        let is_steam_deck = get_os_release_id()
-            .map(|id| id == "steamos")
-            .unwrap_or(false);
-        if is_steam_deck {
-            return Ok(Self::SteamDeck(steam_deck::SteamDeck::try_default()?));
-        }
-
-        let is_ostree = std::process::Command::new("ostree")
-            .arg("remote")
-            .arg("list")
-            .output()
-            .is_ok_and(|output| output.status.success());
-        if is_ostree {
-            return Ok(Self::Ostree(ostree::Ostree::try_default()?));
-        }
+    /// The default planner for a Linux [`detect::Host`]
+    pub fn from_host(host: &detect::Host) -> Result<Self, PlannerError> {
+        let planner = match host.planner() {
+            // SteamOS is only shipped for x86_64 devices
+            detect::DetectedPlanner::SteamDeck
+                if target_lexicon::Architecture::host() == target_lexicon::Architecture::X86_64 =>
+            {
+                Self::SteamDeck(steam_deck::SteamDeck::try_default()?)
+            },
+            detect::DetectedPlanner::Ostree => Self::Ostree(ostree::Ostree::try_default()?),
+            detect::DetectedPlanner::Container => {
+                tracing::info!(
+                    container = ?host.container,
+                    "Running in a container without an init system, using the `container` planner"
+                );
+                Self::Container(container::Container::try_default()?)
+            },
+            detect::DetectedPlanner::Alpine => Self::Alpine(alpine::Alpine::try_default()?),
+            detect::DetectedPlanner::Lxc => {
+                tracing::info!("Running in an LXC container, using the `lxc` planner");
+                Self::Lxc(lxc::Lxc::try_default()?)
+            },
+            detect::DetectedPlanner::Nixos => Self::Nixos(nixos::Nixos::try_default()?),
+            detect::DetectedPlanner::Termux => {
+                tracing::info!("Running in Termux, using the `termux` planner");
+                Self::Termux(termux::Termux::try_default()?)
+            },
+            detect::DetectedPlanner::SteamDeck | detect::DetectedPlanner::Linux => {
+                Self::Linux(linux::Linux::try_default()?)
+            },
+        };
 
-        Ok(Self::Linux(linux::Linux::try_default()?))
+        Ok(planner)
     }
 
     pub fn from_common_settings(settings: CommonSettings) -> Result<Self, PlannerError> {
@@ -240,6 +297,13 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(inner) => inner.settings = settings,
             BuiltinPlanner::SteamDeck(inner) => inner.settings = settings,
             BuiltinPlanner::Ostree(inner) => inner.settings = settings,
+            BuiltinPlanner::Alpine(inner) => inner.settings = settings,
+            BuiltinPlanner::Container(inner) => inner.settings = settings,
+            BuiltinPlanner::Lxc(inner) => inner.settings = settings,
+            BuiltinPlanner::Nixos(inner) => inner.settings = settings,
+            BuiltinPlanner::Termux(inner) => inner.settings = settings,
+            BuiltinPlanner::NoInit(inner) => inner.settings = settings,
+            BuiltinPlanner::Freebsd(inner) => inner.settings = settings,
             BuiltinPlanner::Macos(inner) => inner.settings = settings,
         }
         Ok(built)
@@ -250,6 +314,13 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(inner) => &inner.settings,
             BuiltinPlanner::SteamDeck(inner) => &inner.settings,
             BuiltinPlanner::Ostree(inner) => &inner.settings,
+            BuiltinPlanner::Alpine(inner) => &inner.settings,
+            BuiltinPlanner::Container(inner) => &inner.settings,
+            BuiltinPlanner::Lxc(inner) => &inner.settings,
+            BuiltinPlanner::Nixos(inner) => &inner.settings,
+            BuiltinPlanner::Termux(inner) => &inner.settings,
+            BuiltinPlanner::NoInit(inner) => &inner.settings,
+            BuiltinPlanner::Freebsd(inner) => &inner.settings,
             BuiltinPlanner::Macos(inner) => &inner.settings,
         }
     }
@@ -259,6 +330,13 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(inner) => &mut inner.settings,
             BuiltinPlanner::SteamDeck(inner) => &mut inner.settings,
             BuiltinPlanner::Ostree(inner) => &mut inner.settings,
+            BuiltinPlanner::Alpine(inner) => &mut inner.settings,
+            BuiltinPlanner::Container(inner) => &mut inner.settings,
+            BuiltinPlanner::Lxc(inner) => &mut inner.settings,
+            BuiltinPlanner::Nixos(inner) => &mut inner.settings,
+            BuiltinPlanner::Termux(inner) => &mut inner.settings,
+            BuiltinPlanner::NoInit(inner) => &mut inner.settings,
+            BuiltinPlanner::Freebsd(inner) => &mut inner.settings,
             BuiltinPlanner::Macos(inner) => &mut inner.settings,
         }
     }
@@ -268,15 +346,31 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(inner) => inner.configured_settings(),
             BuiltinPlanner::SteamDeck(inner) => inner.configured_settings(),
             BuiltinPlanner::Ostree(inner) => inner.configured_settings(),
+            BuiltinPlanner::Alpine(inner) => inner.configured_settings(),
+            BuiltinPlanner::Container(inner) => inner.configured_settings(),
+            BuiltinPlanner::Lxc(inner) => inner.configured_settings(),
+            BuiltinPlanner::Nixos(inner) => inner.configured_settings(),
+            BuiltinPlanner::Termux(inner) => inner.configured_settings(),
+            BuiltinPlanner::NoInit(inner) => inner.configured_settings(),
+            BuiltinPlanner::Freebsd(inner) => inner.configured_settings(),
             BuiltinPlanner::Macos(inner) => inner.configured_settings(),
         }
     }
 
     pub fn plan(self) -> Result<InstallPlan, NixInstallerError> {
+        self.common_settings().check_offline()?;
+        self.common_settings().check_config_refresh_interval()?;
         match self {
             BuiltinPlanner::Linux(planner) => InstallPlan::plan(planner),
             BuiltinPlanner::SteamDeck(planner) => InstallPlan::plan(planner),
             BuiltinPlanner::Ostree(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Alpine(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Container(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Lxc(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Nixos(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Termux(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::NoInit(planner) => InstallPlan::plan(planner),
+            BuiltinPlanner::Freebsd(planner) => InstallPlan::plan(planner),
             BuiltinPlanner::Macos(planner) => InstallPlan::plan(planner),
         }
     }
@@ -285,6 +379,13 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(i) => i.boxed(),
             BuiltinPlanner::SteamDeck(i) => i.boxed(),
             BuiltinPlanner::Ostree(i) => i.boxed(),
+            BuiltinPlanner::Alpine(i) => i.boxed(),
+            BuiltinPlanner::Container(i) => i.boxed(),
+            BuiltinPlanner::Lxc(i) => i.boxed(),
+            BuiltinPlanner::Nixos(i) => i.boxed(),
+            BuiltinPlanner::Termux(i) => i.boxed(),
+            BuiltinPlanner::NoInit(i) => i.boxed(),
+            BuiltinPlanner::Freebsd(i) => i.boxed(),
             BuiltinPlanner::Macos(i) => i.boxed(),
         }
     }
@@ -294,6 +395,13 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(i) => i.typetag_name(),
             BuiltinPlanner::SteamDeck(i) => i.typetag_name(),
             BuiltinPlanner::Ostree(i) => i.typetag_name(),
+            BuiltinPlanner::Alpine(i) => i.typetag_name(),
+            BuiltinPlanner::Container(i) => i.typetag_name(),
+            BuiltinPlanner::Lxc(i) => i.typetag_name(),
+            BuiltinPlanner::Nixos(i) => i.typetag_name(),
+            BuiltinPlanner::Termux(i) => i.typetag_name(),
+            BuiltinPlanner::NoInit(i) => i.typetag_name(),
+            BuiltinPlanner::Freebsd(i) => i.typetag_name(),
             BuiltinPlanner::Macos(i) => i.typetag_name(),
         }
     }
@@ -303,9 +411,33 @@ impl BuiltinPlanner {
             BuiltinPlanner::Linux(i) => i.settings(),
             BuiltinPlanner::SteamDeck(i) => i.settings(),
             BuiltinPlanner::Ostree(i) => i.settings(),
+            BuiltinPlanner::Alpine(i) => i.settings(),
+            BuiltinPlanner::Container(i) => i.settings(),
+            BuiltinPlanner::Lxc(i) => i.settings(),
+            BuiltinPlanner::Nixos(i) => i.settings(),
+            BuiltinPlanner::Termux(i) => i.settings(),
+            BuiltinPlanner::NoInit(i) => i.settings(),
+            BuiltinPlanner::Freebsd(i) => i.settings(),
             BuiltinPlanner::Macos(i) => i.settings(),
         }
     }
+
+    /// See [`Planner::requires_root`]
+    pub fn requires_root(&self) -> bool {
+        match self {
+            BuiltinPlanner::Linux(i) => i.requires_root(),
+            BuiltinPlanner::SteamDeck(i) => i.requires_root(),
+            BuiltinPlanner::Ostree(i) => i.requires_root(),
+            BuiltinPlanner::Alpine(i) => i.requires_root(),
+            BuiltinPlanner::Container(i) => i.requires_root(),
+            BuiltinPlanner::Lxc(i) => i.requires_root(),
+            BuiltinPlanner::Nixos(i) => i.requires_root(),
+            BuiltinPlanner::Termux(i) => i.requires_root(),
+            BuiltinPlanner::NoInit(i) => i.requires_root(),
+            BuiltinPlanner::Freebsd(i) => i.requires_root(),
+            BuiltinPlanner::Macos(i) => i.requires_root(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
@@ -313,6 +445,11 @@ pub struct ShellProfileLocations {
     pub fish: FishShellProfileLocations,
     pub bash: Vec<PathBuf>,
     pub zsh: Vec<PathBuf>,
+    /// Nushell can't source POSIX shell scripts, so gets its own `nix.nu`
+    #[serde(default = "ConfdShellProfileLocations::nushell")]
+    pub nushell: ConfdShellProfileLocations,
+    #[serde(default = "ConfdShellProfileLocations::xonsh")]
+    pub xonsh: ConfdShellProfileLocations,
 }
 
 impl Default for ShellProfileLocations {
@@ -329,6 +466,39 @@ impl Default for ShellProfileLocations {
                 "/etc/zshrc".into(),
                 "/etc/zsh/zshrc".into(),
             ],
+            nushell: ConfdShellProfileLocations::nushell(),
+            xonsh: ConfdShellProfileLocations::xonsh(),
+        }
+    }
+}
+
+/// A file to create under whichever of the `prefixes` exist, for shells which read a directory of snippets
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct ConfdShellProfileLocations {
+    pub prefixes: Vec<PathBuf>,
+    pub suffix: PathBuf,
+}
+
+impl ConfdShellProfileLocations {
+    /// Nushell's `$nu.vendor-autoload-dirs`
+    ///
+    /// More info: <https://www.nushell.sh/book/configuration.html#configuration-overview>
+    pub fn nushell() -> Self {
+        Self {
+            prefixes: vec![
+                "/usr/share/nushell".into(),
+                "/usr/local/share/nushell".into(),
+                "/opt/homebrew/share/nushell".into(), // homebrew
+            ],
+            suffix: "vendor/autoload/nix.nu".into(),
+        }
+    }
+
+    /// Xonsh's `$XONSHRC_DIR`
+    pub fn xonsh() -> Self {
+        Self {
+            prefixes: vec!["/etc/xonsh".into()],
+            suffix: "rc.d/nix.xsh".into(),
         }
     }
 }
@@ -370,6 +540,46 @@ impl Default for FishShellProfileLocations {
     }
 }
 
+/// Wrap a planned install in the hooks from `settings`
+///
+/// The post-uninstall hook goes first, so it is reverted (and run) last.
+pub(crate) fn with_hooks(
+    planner: &str,
+    settings: &CommonSettings,
+    plan: Vec<StatefulAction<Box<dyn Action>>>,
+) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+    let receipt_path = settings.receipt_location(&settings.state_dir);
+    let mut hooked = vec![];
+    for (stage, hook) in [
+        (HookStage::PostUninstall, &settings.post_uninstall_hook),
+        (HookStage::PreInstall, &settings.pre_install_hook),
+    ] {
+        if let Some(hook) = hook {
+            hooked.push(
+                RunHook::plan(stage, hook, planner, &settings.state_dir, &receipt_path)?.boxed(),
+            );
+        }
+    }
+    hooked.extend(plan);
+    if let Some(restore_store) = &settings.restore_store {
+        hooked.push(RestoreStore::plan(restore_store)?.boxed());
+    }
+    if let Some(hook) = &settings.post_install_hook {
+        hooked.push(
+            RunHook::plan(
+                HookStage::PostInstall,
+                hook,
+                planner,
+                &settings.state_dir,
+                &receipt_path,
+            )?
+            .boxed(),
+        );
+    }
+
+    Ok(hooked)
+}
+
 /// An error originating from a [`Planner`]
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug)]
@@ -417,7 +627,9 @@ pub enum PlannerError {
     /// Custom planner error
     #[error("Custom planner error")]
     Custom(#[source] Box<dyn std::error::Error + Send + Sync>),
-    #[error("NixOS already has Nix installed")]
+    #[error(
+        "NixOS already has Nix installed, configure it with `nix.settings` in the NixOS configuration instead. To install a secondary store (such as for a container), use the `nixos` planner with `--store-prefix`"
+    )]
     NixOs,
     #[error("`nix` is already a valid command, so it is installed")]
     NixExists,
@@ -428,6 +640,17 @@ pub enum PlannerError {
     /// Failed to execute command
     #[error("Failed to execute command `{0}`")]
     Command(String, #[source] std::io::Error),
+    /// Not enough free space to install, see `--min-free-space`
+    #[error(
+        "Installing needs {} free on {location}, but only {} is available. Free up some space, or lower `--min-free-space`",
+        crate::action::format_bytes(*required),
+        crate::action::format_bytes(*available)
+    )]
+    InsufficientSpace {
+        location: String,
+        available: u64,
+        required: u64,
+    },
 }
 
 impl HasExpectedErrors for PlannerError {
@@ -448,6 +671,14 @@ impl HasExpectedErrors for PlannerError {
                 if let Some(err) = _e.downcast_ref::<linux::LinuxErrorKind>() {
                     return err.expected();
                 }
+                #[cfg(target_os = "linux")]
+                if let Some(err) = _e.downcast_ref::<alpine::AlpineErrorKind>() {
+                    return err.expected();
+                }
+                #[cfg(target_os = "linux")]
+                if let Some(err) = _e.downcast_ref::<lxc::LxcError>() {
+                    return err.expected();
+                }
                 #[cfg(target_os = "macos")]
                 if let Some(err) = _e.downcast_ref::<macos::MacosError>() {
                     return err.expected();
@@ -458,6 +689,72 @@ impl HasExpectedErrors for PlannerError {
             this @ PlannerError::NixExists => Some(Box::new(this)),
             this @ PlannerError::Wsl1 => Some(Box::new(this)),
             PlannerError::Command(_, _) => None,
+            this @ PlannerError::InsufficientSpace { .. } => Some(Box::new(this)),
+        }
+    }
+}
+
+impl PlannerError {
+    /// The [`ErrorCode`] of this error
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            PlannerError::Action(action_error) => action_error
+                .kind()
+                .error_code()
+                .unwrap_or(ErrorCode::Failure),
+            PlannerError::InstallSettings(_) => ErrorCode::InvalidPlan,
+            PlannerError::NixExists => ErrorCode::AlreadyInstalled,
+            PlannerError::UnsupportedArchitecture(_)
+            | PlannerError::IncompatibleOperatingSystem { .. }
+            | PlannerError::RosettaDetected
+            | PlannerError::SelinuxRequirements
+            | PlannerError::NixOs
+            | PlannerError::Wsl1 => ErrorCode::UnsupportedPlatform,
+            PlannerError::InsufficientSpace { .. } => ErrorCode::InsufficientSpace,
+            PlannerError::Custom(_e) => {
+                #[cfg(target_os = "linux")]
+                if _e.is::<linux::LinuxErrorKind>()
+                    || _e.is::<alpine::AlpineErrorKind>()
+                    || _e.is::<lxc::LxcError>()
+                {
+                    return ErrorCode::UnsupportedPlatform;
+                }
+                ErrorCode::Failure
+            },
+            PlannerError::Plist(_)
+            | PlannerError::Sysctl(_)
+            | PlannerError::Utf8(_)
+            | PlannerError::Command(_, _) => ErrorCode::Failure,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::CommonSettings;
+
+    #[test]
+    fn hooks_wrap_the_plan() -> Result<(), Box<dyn std::error::Error>> {
+        let hook = tempfile::NamedTempFile::new()?;
+        let mut settings = CommonSettings::try_default()?;
+        settings.pre_install_hook = Some(hook.path().into());
+        settings.post_install_hook = Some(hook.path().into());
+        settings.post_uninstall_hook = Some(hook.path().into());
+
+        let plan = super::with_hooks("linux", &settings, vec![])?;
+        let synopses = plan
+            .iter()
+            .map(|action| action.tracing_synopsis())
+            .collect::<Vec<_>>();
+        let hook = hook.path().display();
+        assert_eq!(
+            synopses,
+            vec![
+                format!("Run the post-uninstall hook `{hook}`"),
+                format!("Run the pre-install hook `{hook}`"),
+                format!("Run the post-install hook `{hook}`"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Action, BuiltinPlanner,
+    action::{StatefulAction, common::ProvisionChrootStore},
+    planner::{Planner, PlannerError, free_space::check_free_space, with_hooks},
+    settings::{CommonSettings, InstallSettingsError},
+};
+
+/// A planner for NixOS, which already has Nix: it only installs a secondary store under a prefix
+///
+/// Such a store is useful as a chroot store (`nix --store {prefix}`) or as the root of a
+/// NixOS-hosted container under development. Without `--store-prefix` it explains how to
+/// configure the system's own Nix instead.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Nixos {
+    /// Install a secondary Nix store in `{prefix}/nix`, instead of changing the system's Nix
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NIXOS_STORE_PREFIX", value_name = "PREFIX")
+    )]
+    pub store_prefix: Option<PathBuf>,
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+}
+
+#[typetag::serde(name = "nixos")]
+impl Planner for Nixos {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            store_prefix: None,
+            settings: CommonSettings::try_default()?,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let Some(store_prefix) = &self.store_prefix else {
+            return Err(PlannerError::NixOs);
+        };
+
+        let plan = vec![
+            ProvisionChrootStore::plan(store_prefix, &self.settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+
+        with_hooks(self.typetag_name(), &self.settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            store_prefix,
+            settings,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("store_prefix".into(), serde_json::to_value(store_prefix)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        let Some(store_prefix) = &self.store_prefix else {
+            return Err(PlannerError::NixOs);
+        };
+        check_free_space(&self.settings, store_prefix)?;
+
+        Ok(())
+    }
+}
+
+impl From<Nixos> for BuiltinPlanner {
+    fn from(val: Nixos) -> Self {
+        BuiltinPlanner::Nixos(val)
+    }
+}
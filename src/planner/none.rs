@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    ShellProfileLocations,
+    linux::{check_nix_not_already_installed, check_not_nixos, check_not_wsl1},
+};
+use crate::{
+    Action, BuiltinPlanner,
+    action::{
+        StatefulAction,
+        base::{CreateDirectory, RemoveDirectory},
+        common::{ConfigureNix, CreateUsersAndGroups, ProvisionNix},
+    },
+    planner::{Planner, PlannerError, free_space::check_free_space, with_hooks},
+    settings::{CommonSettings, InstallSettingsError, UrlOrPathOrString},
+};
+
+/// A planner installing Nix without an init or a daemon, such as when building images and chroots
+///
+/// Nix is used by `root` alone, as with the `container` planner but without an entrypoint. With
+/// `--store-only` even less is planned: the store, `nix.conf` and the default profile, with no
+/// build users or shell profiles, for tools assembling a filesystem through the library.
+///
+/// ```rust,no_run
+/// use nix_installer::{InstallPlan, planner::{Planner, none::NoInit}};
+///
+/// # fn store_only() -> color_eyre::Result<()> {
+/// let planner = NoInit {
+///     store_only: true,
+///     ..NoInit::try_default()?
+/// };
+/// let plan = InstallPlan::plan(planner)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct NoInit {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    /// Only provision the store, `nix.conf` and the default profile, without build users or shell profiles
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, default_value_t = false, env = "NIX_INSTALLER_STORE_ONLY")
+    )]
+    #[serde(default)]
+    pub store_only: bool,
+}
+
+#[typetag::serde(name = "none")]
+impl Planner for NoInit {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            store_only: false,
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let settings = self.planned_settings();
+
+        let mut plan = vec![
+            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ProvisionNix::plan(&settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+        if !self.store_only {
+            plan.push(
+                CreateUsersAndGroups::plan(settings.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+        plan.push(
+            ConfigureNix::plan(ShellProfileLocations::default(), &settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+        plan.push(
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        with_hooks(self.typetag_name(), &settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            settings,
+            store_only,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("store_only".into(), serde_json::to_value(store_only)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        check_not_wsl1()?;
+
+        Ok(())
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        check_not_nixos()?;
+
+        check_nix_not_already_installed()?;
+
+        check_not_wsl1()?;
+
+        check_free_space(&self.settings, Path::new("/nix"))?;
+
+        Ok(())
+    }
+}
+
+impl NoInit {
+    /// The settings to plan with, which for `--store-only` leave out build users and shell profiles
+    fn planned_settings(&self) -> CommonSettings {
+        let mut settings = self.settings.clone();
+        if self.store_only {
+            // Builds run as `root`, and the store belongs to it rather than a build group
+            settings
+                .extra_conf
+                .push(UrlOrPathOrString::String("build-users-group =".into()));
+            settings.nix_build_group_id = 0;
+            settings.modify_profile = false;
+        }
+        settings
+    }
+}
+
+impl From<NoInit> for BuiltinPlanner {
+    fn from(val: NoInit) -> Self {
+        BuiltinPlanner::NoInit(val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_only_leaves_out_build_users() -> Result<(), Box<dyn std::error::Error>> {
+        let planner = NoInit {
+            store_only: true,
+            ..NoInit::try_default()?
+        };
+        let settings = planner.planned_settings();
+        assert!(!settings.modify_profile);
+        assert_eq!(settings.nix_build_group_id, 0);
+        assert!(settings.extra_conf.iter().any(
+            |conf| matches!(conf, UrlOrPathOrString::String(line) if line == "build-users-group =")
+        ));
+
+        let planner = NoInit::try_default()?;
+        assert!(planner.planned_settings().modify_profile);
+        Ok(())
+    }
+}
@@ -3,23 +3,33 @@ use crate::{
     action::{
         StatefulAction,
         base::{CreateDirectory, CreateFile, RemoveDirectory},
-        common::{ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionNix},
+        common::{
+            ConfigureAutoMaintenance, ConfigureConfigRefresh, ConfigureNix,
+            ConfigureUpstreamInitService, LinkDaemonSockets, ProvisionNix,
+        },
         linux::{
-            ProvisionSelinux, StartSystemdUnit, SystemctlDaemonReload,
-            provision_selinux::SELINUX_POLICY_PP_CONTENT,
+            ConfigureSessionEnvironment, PresetSystemdUnit, ProvisionSelinux, StartSystemdUnit,
+            SystemctlDaemonReload, provision_selinux::SELINUX_POLICY_PP_CONTENT,
         },
     },
     error::HasExpectedErrors,
-    planner::{Planner, PlannerError},
-    settings::{CommonSettings, InitSystem, InstallSettingsError},
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_free_space, warn_if_low_memory},
+        with_hooks,
+    },
+    settings::{CommonSettings, InitSettings, InitSystem, InstallSettingsError, UserManagement},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
 };
-use std::{collections::HashMap, path::PathBuf};
 
 use super::{
     ShellProfileLocations,
     linux::{
         check_nix_not_already_installed, check_not_nixos, check_not_wsl1, check_systemd_active,
-        detect_selinux,
+        detect_selinux, plan_build_users,
     },
 };
 
@@ -27,11 +37,29 @@ use super::{
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "cli", derive(clap::Parser))]
 pub struct Ostree {
-    /// Where `/nix` will be bind mounted to.
+    /// Where `/nix` will be bind mounted to (unless `--no-start-daemon`, such as when building an image, where `/nix` is created directly)
     #[cfg_attr(feature = "cli", clap(long, default_value = "/var/home/nix"))]
     persistence: PathBuf,
     #[cfg_attr(feature = "cli", clap(flatten))]
     pub settings: CommonSettings,
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    #[serde(default = "default_init")]
+    pub init: InitSettings,
+    /// How to create the build users and group (`--use-sysusers` is `sysusers`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            alias = "use-sysusers",
+            num_args = 0..=1,
+            default_value_t = UserManagement::Auto,
+            default_missing_value = "sysusers",
+            env = "NIX_INSTALLER_USER_MANAGEMENT"
+        )
+    )]
+    #[serde(default)]
+    pub user_management: UserManagement,
 }
 
 #[typetag::serde(name = "ostree")]
@@ -40,111 +68,25 @@ impl Planner for Ostree {
         Ok(Self {
             persistence: PathBuf::from("/var/home/nix"),
             settings: CommonSettings::try_default()?,
+            init: InitSettings::try_default()?,
+            user_management: UserManagement::default(),
         })
     }
 
     fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
         let has_selinux = detect_selinux()?;
-        let mut plan = vec![
-            // Primarily for uninstall
-            SystemctlDaemonReload::plan()
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        ];
-
-        plan.push(
-            CreateDirectory::plan(&self.persistence, None, None, 0o0755, true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
-
-        let nix_directory_buf = "\
-                [Unit]\n\
-                Description=Enable mount points in / for ostree\n\
-                ConditionPathExists=!/nix\n\
-                DefaultDependencies=no\n\
-                Requires=local-fs-pre.target\n\
-                After=local-fs-pre.target\n\
-                [Service]\n\
-                Type=oneshot\n\
-                ExecStartPre=chattr -i /\n\
-                ExecStart=mkdir -p /nix\n\
-                ExecStopPost=chattr +i /\n\
-            "
-        .to_string();
-        let nix_directory_unit = CreateFile::plan(
-            "/etc/systemd/system/nix-directory.service",
-            None,
-            None,
-            0o0644,
-            nix_directory_buf,
-            false,
-        )
-        .map_err(PlannerError::Action)?;
-        plan.push(nix_directory_unit.boxed());
-
-        let create_bind_mount_buf = format!(
-            "\
-                [Unit]\n\
-                Description=Mount `{persistence}` on `/nix`\n\
-                PropagatesStopTo=nix-daemon.service\n\
-                PropagatesStopTo=nix-directory.service\n\
-                After=nix-directory.service\n\
-                Requires=nix-directory.service\n\
-                ConditionPathIsDirectory=/nix\n\
-                DefaultDependencies=no\n\
-                \n\
-                [Mount]\n\
-                What={persistence}\n\
-                Where=/nix\n\
-                Type=none\n\
-                DirectoryMode=0755\n\
-                Options=bind\n\
-                \n\
-                [Install]\n\
-                RequiredBy=nix-daemon.service\n\
-                RequiredBy=nix-daemon.socket\n
-            ",
-            persistence = self.persistence.display(),
-        );
-        let create_bind_mount_unit = CreateFile::plan(
-            "/etc/systemd/system/nix.mount",
-            None,
-            None,
-            0o0644,
-            create_bind_mount_buf,
-            false,
-        )
-        .map_err(PlannerError::Action)?;
-        plan.push(create_bind_mount_unit.boxed());
+        let mut plan = vec![];
 
-        let ensure_symlinked_units_resolve_buf = "\
-        [Unit]\n\
-        Description=Ensure Nix related units which are symlinked resolve\n\
-        After=nix.mount\n\
-        Requires=nix.mount\n\
-        DefaultDependencies=no\n\
-        \n\
-        [Service]\n\
-        Type=oneshot\n\
-        RemainAfterExit=yes\n\
-        ExecStart=/usr/bin/systemctl daemon-reload\n\
-        ExecStart=/usr/bin/systemctl restart --no-block nix-daemon.socket\n\
-        \n\
-        [Install]\n\
-        WantedBy=sysinit.target\n\
-    "
-        .to_string();
-        let ensure_symlinked_units_resolve_unit = CreateFile::plan(
-            "/etc/systemd/system/ensure-symlinked-units-resolve.service",
-            None,
-            None,
-            0o0644,
-            ensure_symlinked_units_resolve_buf,
-            false,
-        )
-        .map_err(PlannerError::Action)?;
-        plan.push(ensure_symlinked_units_resolve_unit.boxed());
+        if self.init.start_daemon {
+            plan.extend(self.plan_bind_mount()?);
+        } else {
+            // Image builds have no running systemd to mount with, so the store goes into the image
+            plan.push(
+                CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
 
         // We need to remove this path since it's part of the read-only install.
         let mut shell_profile_locations = ShellProfileLocations::default();
@@ -160,27 +102,24 @@ impl Planner for Ostree {
                 .remove(index);
         }
 
-        plan.push(
-            StartSystemdUnit::plan("nix.mount", false)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
-
         plan.push(
             ProvisionNix::plan(&self.settings.clone())
                 .map_err(PlannerError::Action)?
                 .boxed(),
         );
-        plan.push(
-            CreateUsersAndGroups::plan(self.settings.clone())
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
+        plan.push(plan_build_users(&self.settings, self.user_management)?);
         plan.push(
             ConfigureNix::plan(shell_profile_locations, &self.settings)
                 .map_err(PlannerError::Action)?
                 .boxed(),
         );
+        if self.settings.modify_profile {
+            plan.push(
+                ConfigureSessionEnvironment::plan(self.init.init, self.settings.force)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
 
         if has_selinux {
             plan.push(
@@ -193,48 +132,106 @@ impl Planner for Ostree {
             );
         }
 
+        if self.settings.registry_url.is_some() || self.settings.netrc_url.is_some() {
+            plan.push(
+                ConfigureConfigRefresh::plan(self.init.init, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.settings.enable_auto_gc.is_some() || self.settings.enable_auto_optimise.is_some() {
+            plan.push(
+                ConfigureAutoMaintenance::plan(self.init.init, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if !self.settings.extra_daemon_sockets.is_empty() {
+            plan.push(
+                LinkDaemonSockets::plan(self.settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
         plan.push(
             CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
                 .map_err(PlannerError::Action)?
                 .boxed(),
         );
 
-        plan.push(
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
-        plan.push(
-            StartSystemdUnit::plan("ensure-symlinked-units-resolve.service", true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
+        let mut configure_init_service = ConfigureUpstreamInitService::plan(
+            self.init.init,
+            self.init.start_daemon,
+            self.init.harden_daemon,
+        )
+        .map_err(PlannerError::Action)?;
+        if let Some(proxy) = self.settings.proxy_for_daemon() {
+            configure_init_service.action.use_proxy(proxy);
+        }
+        if let Some(limits) = self.settings.daemon_limits() {
+            configure_init_service.action.limit_daemon(limits);
+        }
+        if let Some(path) = &self.settings.daemon_unit_override {
+            configure_init_service
+                .action
+                .override_unit(path)
+                .map_err(PlannerError::Action)?;
+        }
+        plan.push(configure_init_service.boxed());
+        if self.init.init == InitSystem::Systemd {
+            if self.init.start_daemon {
+                plan.push(
+                    StartSystemdUnit::plan("ensure-symlinked-units-resolve.service", true)
+                        .map_err(PlannerError::Action)?
+                        .boxed(),
+                );
+            } else {
+                // Building an image, so the daemon comes up on the first boot of the image instead
+                plan.push(
+                    PresetSystemdUnit::plan("nix-daemon.socket", "sockets.target")
+                        .map_err(PlannerError::Action)?
+                        .boxed(),
+                );
+            }
+        }
         plan.push(
             RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
                 .map_err(PlannerError::Action)?
                 .boxed(),
         );
-        plan.push(
-            SystemctlDaemonReload::plan()
-                .map_err(PlannerError::Action)?
-                .boxed(),
-        );
+        if self.init.start_daemon {
+            plan.push(
+                SystemctlDaemonReload::plan()
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
 
-        Ok(plan)
+        with_hooks(self.typetag_name(), &self.settings, plan)
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
         let Self {
             persistence,
             settings,
+            init,
+            user_management,
         } = self;
         let mut map = HashMap::default();
 
         map.extend(settings.settings()?);
+        map.extend(init.settings()?);
         map.insert(
             "persistence".to_string(),
             serde_json::to_value(persistence)?,
         );
+        map.insert(
+            "user_management".into(),
+            serde_json::to_value(user_management)?,
+        );
 
         Ok(map)
     }
@@ -253,6 +250,18 @@ impl Planner for Ostree {
         Ok(settings)
     }
 
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
     fn platform_check(&self) -> Result<(), PlannerError> {
         use target_lexicon::OperatingSystem;
         match target_lexicon::OperatingSystem::host() {
@@ -267,7 +276,9 @@ impl Planner for Ostree {
     fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
         check_not_wsl1()?;
 
-        check_systemd_active()?;
+        if self.init.start_daemon {
+            check_systemd_active()?;
+        }
 
         Ok(())
     }
@@ -279,12 +290,141 @@ impl Planner for Ostree {
 
         check_not_wsl1()?;
 
-        check_systemd_active()?;
+        check_free_space(&self.settings, &self.persistence)?;
+        warn_if_low_memory();
+
+        // The bind mount of `/nix` needs a running systemd, even if `--init none`
+        if self.init.start_daemon {
+            check_systemd_active()?;
+        }
 
         Ok(())
     }
 }
 
+impl Ostree {
+    /// Units creating `/nix` and bind mounting the persistent directory on it, then mounting it
+    fn plan_bind_mount(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let mut plan = vec![
+            // Primarily for uninstall
+            SystemctlDaemonReload::plan()
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateDirectory::plan(&self.persistence, None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        ];
+
+        let nix_directory_buf = "\
+                [Unit]\n\
+                Description=Enable mount points in / for ostree\n\
+                ConditionPathExists=!/nix\n\
+                DefaultDependencies=no\n\
+                Requires=local-fs-pre.target\n\
+                After=local-fs-pre.target\n\
+                [Service]\n\
+                Type=oneshot\n\
+                ExecStartPre=chattr -i /\n\
+                ExecStart=mkdir -p /nix\n\
+                ExecStopPost=chattr +i /\n\
+            "
+        .to_string();
+        let nix_directory_unit = CreateFile::plan(
+            "/etc/systemd/system/nix-directory.service",
+            None,
+            None,
+            0o0644,
+            nix_directory_buf,
+            false,
+        )
+        .map_err(PlannerError::Action)?;
+        plan.push(nix_directory_unit.boxed());
+
+        let create_bind_mount_buf = format!(
+            "\
+                [Unit]\n\
+                Description=Mount `{persistence}` on `/nix`\n\
+                PropagatesStopTo=nix-daemon.service\n\
+                PropagatesStopTo=nix-directory.service\n\
+                After=nix-directory.service\n\
+                Requires=nix-directory.service\n\
+                ConditionPathIsDirectory=/nix\n\
+                DefaultDependencies=no\n\
+                \n\
+                [Mount]\n\
+                What={persistence}\n\
+                Where=/nix\n\
+                Type=none\n\
+                DirectoryMode=0755\n\
+                Options=bind\n\
+                \n\
+                [Install]\n\
+                RequiredBy=nix-daemon.service\n\
+                RequiredBy=nix-daemon.socket\n
+            ",
+            persistence = self.persistence.display(),
+        );
+        let create_bind_mount_unit = CreateFile::plan(
+            "/etc/systemd/system/nix.mount",
+            None,
+            None,
+            0o0644,
+            create_bind_mount_buf,
+            false,
+        )
+        .map_err(PlannerError::Action)?;
+        plan.push(create_bind_mount_unit.boxed());
+
+        // Only needed to restart the daemon's socket once `/nix` is mounted
+        if self.init.init == InitSystem::Systemd {
+            let ensure_symlinked_units_resolve_buf = "\
+            [Unit]\n\
+            Description=Ensure Nix related units which are symlinked resolve\n\
+            After=nix.mount\n\
+            Requires=nix.mount\n\
+            DefaultDependencies=no\n\
+            \n\
+            [Service]\n\
+            Type=oneshot\n\
+            RemainAfterExit=yes\n\
+            ExecStart=/usr/bin/systemctl daemon-reload\n\
+            ExecStart=/usr/bin/systemctl restart --no-block nix-daemon.socket\n\
+            \n\
+            [Install]\n\
+            WantedBy=sysinit.target\n\
+        "
+            .to_string();
+            let ensure_symlinked_units_resolve_unit = CreateFile::plan(
+                "/etc/systemd/system/ensure-symlinked-units-resolve.service",
+                None,
+                None,
+                0o0644,
+                ensure_symlinked_units_resolve_buf,
+                false,
+            )
+            .map_err(PlannerError::Action)?;
+            plan.push(ensure_symlinked_units_resolve_unit.boxed());
+        }
+
+        plan.push(
+            StartSystemdUnit::plan("nix.mount", false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        Ok(plan)
+    }
+}
+
+/// Receipts from before the init was configurable always started the daemon with systemd
+fn default_init() -> InitSettings {
+    InitSettings {
+        init: InitSystem::Systemd,
+        start_daemon: true,
+        harden_daemon: false,
+    }
+}
+
 impl From<Ostree> for BuiltinPlanner {
     fn from(val: Ostree) -> Self {
         BuiltinPlanner::Ostree(val)
@@ -96,22 +96,31 @@ To test on a specific build id of the Steam Deck:
 6. Safely turn off the VM!
 
 */
-use std::{collections::HashMap, path::PathBuf, process::Output};
-
-use std::process::Command;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     BuiltinPlanner,
     action::{
         Action, StatefulAction,
         base::{CreateDirectory, CreateFile, RemoveDirectory},
-        common::{ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionNix},
+        common::{
+            ConfigureAutoMaintenance, ConfigureConfigRefresh, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, LinkDaemonSockets, ProvisionNix,
+        },
         linux::{
-            EnsureSteamosNixDirectory, RevertCleanSteamosNixOffload, StartSystemdUnit,
-            SystemctlDaemonReload,
+            CreateSteamosSysext, EnsureSteamosNixDirectory, RevertCleanSteamosNixOffload,
+            StartSystemdUnit, SystemctlDaemonReload,
+            create_steamos_sysext::{NIX_SYSEXT_UNIT, NIX_SYSEXT_UNIT_PATH},
         },
     },
-    planner::{Planner, PlannerError},
+    planner::{
+        Planner, PlannerError,
+        free_space::{check_free_space, warn_if_low_memory},
+        with_hooks,
+    },
     settings::{CommonSettings, InitSystem, InstallSettingsError},
 };
 
@@ -131,6 +140,18 @@ pub struct SteamDeck {
         )
     )]
     persistence: PathBuf,
+    /// Also package the `nix-daemon` units and fish profile into a systemd-sysext extension, merged on boot, so SteamOS updates don't drop them
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_STEAM_DECK_SYSEXT",
+            action(clap::ArgAction::SetTrue),
+            default_value = "false"
+        )
+    )]
+    #[serde(default)]
+    sysext: bool,
     #[cfg_attr(feature = "cli", clap(flatten))]
     pub settings: CommonSettings,
 }
@@ -140,6 +161,7 @@ impl Planner for SteamDeck {
     fn try_default() -> Result<Self, PlannerError> {
         Ok(Self {
             persistence: PathBuf::from("/home/nix"),
+            sysext: false,
             settings: CommonSettings::try_default()?,
         })
     }
@@ -155,13 +177,12 @@ impl Planner for SteamDeck {
                 .boxed(),
         ];
 
-        if let Ok(nix_mount_status) = systemctl_status("nix.mount") {
-            let nix_mount_status_stderr = String::from_utf8(nix_mount_status.stderr)?;
-            if nix_mount_status_stderr.contains("Warning: The unit file, source configuration file or drop-ins of nix.mount changed on disk. Run 'systemctl daemon-reload' to reload units.") {
-                return Err(PlannerError::Custom(Box::new(
-                    SteamDeckError::NixMountSystemctlDaemonReloadRequired,
-                )))
-            }
+        if let Ok(nix_mount_state) = crate::os::probe::systemd_unit_state("nix.mount")
+            && nix_mount_state.need_daemon_reload
+        {
+            return Err(PlannerError::Custom(Box::new(
+                SteamDeckError::NixMountSystemctlDaemonReloadRequired,
+            )));
         }
 
         if requires_nix_bind_mount {
@@ -260,19 +281,26 @@ impl Planner for SteamDeck {
         }
 
         if std::path::Path::new("/etc/atomic-update.conf.d").exists() {
-            let create_atomic_update_buf = "\
+            let mut create_atomic_update_buf = "\
                 /etc/fish/conf.d/nix.fish\n\
                 /etc/nix/**\n\
                 /etc/profile.d/nix.sh\n\
                 /etc/systemd/system/nix-daemon.socket\n\
                 /etc/tmpfiles.d/nix-daemon.conf\n\
-            ";
+            "
+            .to_string();
+            if self.sysext {
+                // The extension itself is in `/var`, but what merges it on boot is in `/etc`
+                create_atomic_update_buf.push_str(&format!(
+                    "{NIX_SYSEXT_UNIT_PATH}\n/etc/systemd/system/sysinit.target.wants/{NIX_SYSEXT_UNIT}\n"
+                ));
+            }
             let create_atomic_update_unit = CreateFile::plan(
                 "/etc/atomic-update.conf.d/nix-installer.conf",
                 None,
                 None,
                 0o0644,
-                create_atomic_update_buf.to_string(),
+                create_atomic_update_buf,
                 false,
             )
             .map_err(PlannerError::Action)?;
@@ -329,6 +357,22 @@ impl Planner for SteamDeck {
             )
         }
 
+        // Init is required for the steam-deck archetype to make the `/nix` mount
+        let mut configure_init_service =
+            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true, false)
+                .map_err(PlannerError::Action)?;
+        if let Some(proxy) = self.settings.proxy_for_daemon() {
+            configure_init_service.action.use_proxy(proxy);
+        }
+        if let Some(limits) = self.settings.daemon_limits() {
+            configure_init_service.action.limit_daemon(limits);
+        }
+        if let Some(path) = &self.settings.daemon_unit_override {
+            configure_init_service
+                .action
+                .override_unit(path)
+                .map_err(PlannerError::Action)?;
+        }
         actions.append(&mut vec![
             ProvisionNix::plan(&self.settings.clone())
                 .map_err(PlannerError::Action)?
@@ -339,10 +383,7 @@ impl Planner for SteamDeck {
             ConfigureNix::plan(shell_profile_locations, &self.settings)
                 .map_err(PlannerError::Action)?
                 .boxed(),
-            // Init is required for the steam-deck archetype to make the `/nix` mount
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
-                .map_err(PlannerError::Action)?
-                .boxed(),
+            configure_init_service.boxed(),
             StartSystemdUnit::plan("ensure-symlinked-units-resolve.service", true)
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -353,13 +394,47 @@ impl Planner for SteamDeck {
                 .map_err(PlannerError::Action)?
                 .boxed(),
         ]);
-        Ok(actions)
+
+        if self.sysext {
+            actions.push(
+                CreateSteamosSysext::plan()
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.settings.registry_url.is_some() || self.settings.netrc_url.is_some() {
+            actions.push(
+                ConfigureConfigRefresh::plan(InitSystem::Systemd, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if self.settings.enable_auto_gc.is_some() || self.settings.enable_auto_optimise.is_some() {
+            actions.push(
+                ConfigureAutoMaintenance::plan(InitSystem::Systemd, &self.settings)
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        if !self.settings.extra_daemon_sockets.is_empty() {
+            actions.push(
+                LinkDaemonSockets::plan(self.settings.extra_daemon_sockets.clone())
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        with_hooks(self.typetag_name(), &self.settings, actions)
     }
 
     fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
         let Self {
             settings,
             persistence,
+            sysext,
         } = self;
         let mut map = HashMap::default();
 
@@ -368,6 +443,7 @@ impl Planner for SteamDeck {
             "persistence".to_string(),
             serde_json::to_value(persistence)?,
         );
+        map.insert("sysext".to_string(), serde_json::to_value(sysext)?);
 
         Ok(map)
     }
@@ -386,6 +462,18 @@ impl Planner for SteamDeck {
         Ok(settings)
     }
 
+    fn state_dir(&self) -> &Path {
+        &self.settings.state_dir
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
     fn platform_check(&self) -> Result<(), PlannerError> {
         use target_lexicon::OperatingSystem;
         match target_lexicon::OperatingSystem::host() {
@@ -413,6 +501,9 @@ impl Planner for SteamDeck {
 
         super::linux::check_not_wsl1()?;
 
+        check_free_space(&self.settings, &self.persistence)?;
+        warn_if_low_memory();
+
         // Unlike the Linux planner, the steam deck planner requires systemd
         super::linux::check_systemd_active()?;
 
@@ -458,13 +549,3 @@ pub(crate) fn detect_requires_bind_mount() -> Result<bool, PlannerError> {
         None | Some(_) => Ok(true),
     }
 }
-
-fn systemctl_status(unit: &str) -> Result<Output, PlannerError> {
-    let mut command = Command::new("systemctl");
-    command.arg("status");
-    command.arg(unit);
-    let output = command
-        .output()
-        .map_err(|e| PlannerError::Command(format!("{:?}", command), e))?;
-    Ok(output)
-}
@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Action, BuiltinPlanner,
+    action::{
+        StatefulAction,
+        base::{CreateDirectory, CreateFile, CreateOrMergeNixConfig},
+        common::PlaceNixConfiguration,
+        linux::ProvisionProotStore,
+    },
+    planner::{Planner, PlannerError, free_space::check_free_space, with_hooks},
+    settings::{CommonSettings, DEFAULT_STATE_DIR, InstallSettingsError},
+};
+
+/// Where Termux installs its packages, which is `$PREFIX` in its shells
+const TERMUX_PREFIX: &str = "/data/data/com.termux/files/usr";
+
+/// The command [`Termux`] installs in `{prefix}/bin` to run things with Nix
+pub const ENTER_COMMAND: &str = "nix-enter";
+
+const NIX_CONFIG_HEADER: &str = "\
+# Generated by https://github.com/NixOS/nix-installer
+# Read through `nix-enter`, which sets `NIX_CONF_DIR=/nix/etc/nix`
+";
+
+/// A planner for Termux on Android (or other proot environments), installing for the current user without `root`
+///
+/// Android has neither `root` nor a writable `/nix`, so the store is kept in `--store-root` and
+/// bound on `/nix` with [proot](https://proot-me.github.io) by a `nix-enter` command installed in
+/// `--prefix`. There is no daemon or build users, and builds aren't sandboxed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Termux {
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    /// The user-writable prefix to install the `nix-enter` command into, as `{prefix}/bin/nix-enter`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_TERMUX_PREFIX",
+            value_name = "PATH",
+            default_value_os_t = default_prefix()
+        )
+    )]
+    #[serde(default = "default_prefix")]
+    pub prefix: PathBuf,
+    /// The user-writable directory to keep the Nix store in, bound on `/nix` by `nix-enter` (defaults to `nix` next to `--prefix`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_STORE_ROOT",
+            value_name = "PATH",
+            default_value_os_t = default_store_root()
+        )
+    )]
+    #[serde(default = "default_store_root")]
+    pub store_root: PathBuf,
+}
+
+fn default_prefix() -> PathBuf {
+    std::env::var_os("PREFIX")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(TERMUX_PREFIX))
+}
+
+/// Termux's `$PREFIX` is `usr` in the app's data, so the store goes beside it
+pub(crate) fn default_store_root() -> PathBuf {
+    let prefix = default_prefix();
+    prefix.parent().unwrap_or(&prefix).join("nix")
+}
+
+#[typetag::serde(name = "termux")]
+impl Planner for Termux {
+    fn try_default() -> Result<Self, PlannerError> {
+        Ok(Self {
+            settings: CommonSettings::try_default()?,
+            prefix: default_prefix(),
+            store_root: default_store_root(),
+        })
+    }
+
+    fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        let nix_conf_dir = self.store_root.join("etc/nix");
+
+        let plan = vec![
+            CreateDirectory::plan(&self.store_root, None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            ProvisionProotStore::plan(&self.store_root, &self.settings)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateDirectory::plan(&nix_conf_dir, None, None, 0o0755, true)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateOrMergeNixConfig::plan(
+                nix_conf_dir.join("nix.conf"),
+                self.nix_config()?,
+                NIX_CONFIG_HEADER.to_string(),
+                None,
+            )
+            .map_err(PlannerError::Action)?
+            .boxed(),
+            CreateDirectory::plan(self.prefix.join("bin"), None, None, 0o0755, false)
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            CreateFile::plan(
+                self.prefix.join("bin").join(ENTER_COMMAND),
+                None,
+                None,
+                0o0755,
+                self.enter_script(),
+                self.settings.force,
+            )
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        ];
+
+        with_hooks(self.typetag_name(), &self.settings, plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            settings,
+            prefix,
+            store_root,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.insert("prefix".into(), serde_json::to_value(prefix)?);
+        map.insert("store_root".into(), serde_json::to_value(store_root)?);
+
+        Ok(map)
+    }
+
+    fn configured_settings(&self) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::try_default()?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// The receipt is kept in the store root, which is `/nix` to this install
+    fn state_dir(&self) -> &Path {
+        if self.settings.state_dir == Path::new(DEFAULT_STATE_DIR) {
+            &self.store_root
+        } else {
+            &self.settings.state_dir
+        }
+    }
+
+    fn receipt_path(&self) -> PathBuf {
+        self.settings.receipt_location(self.state_dir())
+    }
+
+    fn protected(&self) -> bool {
+        self.settings.protect
+    }
+
+    fn requires_root(&self) -> bool {
+        false
+    }
+
+    fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => Ok(()),
+            host_os => Err(PlannerError::IncompatibleOperatingSystem {
+                planner: self.typetag_name(),
+                host_os,
+            }),
+        }
+    }
+
+    fn pre_install_check(&self) -> Result<(), PlannerError> {
+        if self.store_root.join("store").exists() {
+            return Err(PlannerError::NixExists);
+        }
+        check_free_space(&self.settings, &self.store_root)?;
+
+        Ok(())
+    }
+}
+
+impl Termux {
+    /// A single user `nix.conf`: builds run as the user, and proot can't sandbox them
+    fn nix_config(&self) -> Result<nix_config_parser::NixConfig, PlannerError> {
+        let mut nix_config =
+            PlaceNixConfiguration::parse_extra_conf(self.settings.extra_conf.clone())
+                .map_err(PlannerError::Action)?;
+        let settings = nix_config.settings_mut();
+        for (name, value) in [
+            ("build-users-group", ""),
+            ("sandbox", "false"),
+            ("extra-experimental-features", "nix-command flakes"),
+            ("extra-nix-path", "nixpkgs=flake:nixpkgs"),
+        ] {
+            settings
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        // Android keeps its certificates elsewhere, Termux's own bundle is readable through proot
+        let ssl_cert_file = match &self.settings.ssl_cert_file {
+            Some(ssl_cert_file) => ssl_cert_file.clone(),
+            None => self.prefix.join("etc/tls/cert.pem"),
+        };
+        settings.insert(
+            "ssl-cert-file".to_string(),
+            ssl_cert_file.display().to_string(),
+        );
+        Ok(nix_config)
+    }
+
+    /// `nix-enter`, which runs its arguments (or a shell) with the store bound on `/nix` and Nix on the `PATH`
+    fn enter_script(&self) -> String {
+        let shell = self.prefix.join("bin/sh");
+        format!(
+            "\
+#!{shell}
+# Created by nix-installer: run a command (or a shell) with Nix, its store bound on `/nix` with proot
+if [ \"$#\" -eq 0 ]; then
+    set -- \"${{SHELL:-{shell}}}\"
+fi
+export NIX_CONF_DIR=/nix/etc/nix
+exec proot -b '{store_root}:/nix' '{shell}' -c '. /nix/var/nix/profiles/default/etc/profile.d/nix.sh; exec \"$@\"' {ENTER_COMMAND} \"$@\"
+",
+            shell = shell.display(),
+            store_root = self.store_root.display(),
+        )
+    }
+}
+
+impl From<Termux> for BuiltinPlanner {
+    fn from(val: Termux) -> Self {
+        BuiltinPlanner::Termux(val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::UrlOrPathOrString;
+
+    #[test]
+    fn single_user_nix_config() -> Result<(), Box<dyn std::error::Error>> {
+        let mut planner = Termux {
+            prefix: PathBuf::from(TERMUX_PREFIX),
+            store_root: PathBuf::from("/data/data/com.termux/files/nix"),
+            ..Termux::try_default()?
+        };
+        planner.settings.ssl_cert_file = None;
+        planner
+            .settings
+            .extra_conf
+            .push(UrlOrPathOrString::String("max-jobs = 2".into()));
+
+        let nix_config = planner.nix_config()?;
+        let settings = nix_config.settings();
+        assert_eq!(
+            settings.get("build-users-group").map(String::as_str),
+            Some("")
+        );
+        assert_eq!(settings.get("sandbox").map(String::as_str), Some("false"));
+        assert_eq!(settings.get("max-jobs").map(String::as_str), Some("2"));
+        assert_eq!(
+            settings.get("ssl-cert-file").map(String::as_str),
+            Some("/data/data/com.termux/files/usr/etc/tls/cert.pem")
+        );
+
+        assert!(
+            planner
+                .enter_script()
+                .contains("proot -b '/data/data/com.termux/files/nix:/nix'")
+        );
+        Ok(())
+    }
+}
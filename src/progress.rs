@@ -0,0 +1,234 @@
+/*! Progress of an [`InstallPlan`](crate::InstallPlan) as it executes
+
+Some steps, such as unpacking Nix or changing the ownership of the Nix store, can take a while.
+Embedders can pass a [`ProgressReporter`] to
+[`InstallPlan::install_with_progress`](crate::InstallPlan::install_with_progress) to be told when
+each step starts and finishes, and how far along steps which process many bytes are:
+
+```rust,no_run
+use std::{sync::Arc, time::Duration};
+use nix_installer::{InstallPlan, OnFailure, progress::ProgressReporter};
+
+#[derive(Debug)]
+struct LogProgress;
+
+impl ProgressReporter for LogProgress {
+    fn step_started(&self, step: usize, total: usize, synopsis: &str) {
+        tracing::info!("[{step}/{total}] {synopsis}");
+    }
+
+    fn step_finished(&self, step: usize, total: usize, _synopsis: &str, elapsed: Duration, _success: bool) {
+        tracing::info!("[{step}/{total}] took {elapsed:?}");
+    }
+}
+
+# fn reporting_install() -> color_eyre::Result<()> {
+let mut plan = InstallPlan::try_default()?;
+plan.install_with_progress(None, None, Some(Arc::new(LogProgress)), OnFailure::Stop)?;
+#
+# Ok(())
+# }
+```
+
+Programs rendering their own interface, such as GUIs, can instead pass a [`PlanObserver`] to
+[`InstallPlan::install_with_observer`](crate::InstallPlan::install_with_observer), which receives a
+[`PlanEvent`] carrying the [`ActionDescription`] of each step. Closures are observers too:
+
+```rust,no_run
+use nix_installer::{InstallPlan, progress::PlanEvent};
+
+# fn observed_install() -> color_eyre::Result<()> {
+let mut plan = InstallPlan::try_default()?;
+plan.install_with_observer(None, |event: PlanEvent<'_>| match event {
+    PlanEvent::ActionStarted { description } => println!("Started: {}", description.description),
+    PlanEvent::ActionCompleted { description, elapsed } => {
+        println!("Completed in {elapsed:?}: {}", description.description)
+    },
+    PlanEvent::ActionFailed { description, error } => {
+        println!("Failed: {}: {error}", description.description)
+    },
+    _ => (),
+})?;
+#
+# Ok(())
+# }
+```
+*/
+
+use std::{cell::RefCell, io::Read, sync::Arc, time::Duration};
+
+use crate::action::{ActionDescription, ActionError};
+
+/// Something which happened while executing an [`InstallPlan`](crate::InstallPlan), see [`PlanObserver`]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy)]
+pub enum PlanEvent<'a> {
+    /// A step has started executing
+    ActionStarted { description: &'a ActionDescription },
+    /// A step has finished executing
+    ActionCompleted {
+        description: &'a ActionDescription,
+        elapsed: Duration,
+    },
+    /// A step has failed, the plan stops executing after this
+    ActionFailed {
+        description: &'a ActionDescription,
+        error: &'a ActionError,
+    },
+}
+
+/// Receives each [`PlanEvent`] of an [`InstallPlan`](crate::InstallPlan) as it executes
+pub trait PlanObserver {
+    fn observe(&mut self, event: PlanEvent<'_>);
+}
+
+impl<F> PlanObserver for F
+where
+    F: FnMut(PlanEvent<'_>),
+{
+    fn observe(&mut self, event: PlanEvent<'_>) {
+        self(event)
+    }
+}
+
+/// Receives the progress of each step of an [`InstallPlan`](crate::InstallPlan)
+///
+/// Steps are the top level actions of the plan, numbered from 1.
+pub trait ProgressReporter: Send + Sync + std::fmt::Debug {
+    /// A step has started executing
+    fn step_started(&self, step: usize, total: usize, synopsis: &str);
+    /// The current step has processed `done` of `total` bytes
+    fn step_bytes(&self, done: u64, total: u64) {
+        let _ = (done, total);
+    }
+    /// A step has finished executing, successfully or not
+    fn step_finished(
+        &self,
+        step: usize,
+        total: usize,
+        synopsis: &str,
+        elapsed: Duration,
+        success: bool,
+    );
+    /// Stop drawing to the terminal, such as while the user is asked a [`prompt`](crate::prompt)
+    fn suspend(&self) {}
+    /// Resume drawing to the terminal after [`suspend`][ProgressReporter::suspend]
+    fn resume(&self) {}
+}
+
+// Actions report progress on the thread executing their plan, to that plan's reporter
+thread_local! {
+    static PROGRESS_REPORTER: RefCell<Option<Arc<dyn ProgressReporter>>> = const { RefCell::new(None) };
+}
+
+/// Routes progress reported by actions on this thread to `reporter` until dropped
+pub(crate) struct ProgressGuard {
+    previous: Option<Arc<dyn ProgressReporter>>,
+}
+
+impl ProgressGuard {
+    pub(crate) fn install(reporter: Option<Arc<dyn ProgressReporter>>) -> Self {
+        Self {
+            previous: PROGRESS_REPORTER.replace(reporter),
+        }
+    }
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        PROGRESS_REPORTER.set(self.previous.take());
+    }
+}
+
+/// The reporter installed by the [`InstallPlan`](crate::InstallPlan) running on this thread, if any
+pub(crate) fn reporter() -> Option<Arc<dyn ProgressReporter>> {
+    PROGRESS_REPORTER.with_borrow(Clone::clone)
+}
+
+/// Suspends the installed reporter until dropped
+pub(crate) struct SuspendGuard {
+    reporter: Option<Arc<dyn ProgressReporter>>,
+}
+
+impl SuspendGuard {
+    pub(crate) fn suspend() -> Self {
+        let reporter = reporter();
+        if let Some(reporter) = &reporter {
+            reporter.suspend();
+        }
+        Self { reporter }
+    }
+}
+
+impl Drop for SuspendGuard {
+    fn drop(&mut self) {
+        if let Some(reporter) = &self.reporter {
+            reporter.resume();
+        }
+    }
+}
+
+/// A [`Read`] which reports how many of `total` bytes have been read to the installed reporter
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    done: u64,
+    total: u64,
+    reporter: Option<Arc<dyn ProgressReporter>>,
+}
+
+impl<R> ProgressReader<R> {
+    pub(crate) fn new(inner: R, total: u64) -> Self {
+        Self {
+            inner,
+            done: 0,
+            total,
+            reporter: reporter(),
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.done += read as u64;
+        if let Some(reporter) = &self.reporter {
+            reporter.step_bytes(self.done, self.total);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct RecordBytes(Mutex<Vec<(u64, u64)>>);
+
+    impl ProgressReporter for RecordBytes {
+        fn step_started(&self, _step: usize, _total: usize, _synopsis: &str) {}
+        fn step_bytes(&self, done: u64, total: u64) {
+            self.0.lock().unwrap().push((done, total));
+        }
+        fn step_finished(&self, _: usize, _: usize, _: &str, _: Duration, _: bool) {}
+    }
+
+    #[test]
+    fn progress_reader_reports_to_installed_reporter() {
+        let recorder = Arc::new(RecordBytes::default());
+        {
+            let _guard = ProgressGuard::install(Some(recorder.clone()));
+            assert!(std::thread::spawn(reporter).join().unwrap().is_none());
+            let mut reader = ProgressReader::new(std::io::Cursor::new(vec![0u8; 10]), 10);
+            let mut buf = [0u8; 4];
+            while reader.read(&mut buf).unwrap() != 0 {}
+        }
+        assert!(reporter().is_none());
+
+        let recorded = recorder.0.lock().unwrap();
+        assert_eq!(recorded.first(), Some(&(4, 10)));
+        assert_eq!(recorded.last(), Some(&(10, 10)));
+    }
+}
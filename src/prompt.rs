@@ -0,0 +1,391 @@
+/*! Decisions [`Action`](crate::action::Action)s may need from the user during execution
+
+Some situations can only be discovered once an [`InstallPlan`](crate::InstallPlan) is being
+carried out, such as an existing file which differs from what an action intends to write. Instead
+of failing outright, an action can raise a [`PromptRequest`] and wait for a [`PromptAnswer`].
+
+Embedders create a channel with [`prompt_channel`], pass the [`PromptSender`] to
+[`InstallPlan::install_with_prompts`](crate::InstallPlan::install_with_prompts), and answer each
+[`Prompt`] from the [`PromptReceiver`] on another thread:
+
+```rust,no_run
+use nix_installer::{InstallPlan, prompt::{self, PromptAnswer}};
+# fn headless_install() -> color_eyre::Result<()> {
+let mut plan = InstallPlan::try_default()?;
+let (sender, receiver) = prompt::prompt_channel();
+
+std::thread::spawn(move || {
+    for prompt in receiver {
+        tracing::info!("Answering: {}", prompt.request());
+        prompt.answer(PromptAnswer::Yes);
+    }
+});
+
+plan.install_with_prompts(None, Some(sender))?;
+#
+# Ok(())
+# }
+```
+
+Embedders which can answer on the executing thread can instead implement [`InteractionHandler`]
+and pass it to [`InstallPlan::install_with_interaction`](crate::InstallPlan::install_with_interaction).
+A handler which cannot answer a request falls back to its [`InteractionHandler::fallback`], and
+[`FallbackPolicy`] is a handler which only ever falls back, for unattended installs:
+
+```rust,no_run
+use std::sync::Arc;
+use nix_installer::{InstallPlan, prompt::FallbackPolicy};
+# fn unattended_install() -> color_eyre::Result<()> {
+let mut plan = InstallPlan::try_default()?;
+plan.install_with_interaction(None, Arc::new(FallbackPolicy::Yes))?;
+#
+# Ok(())
+# }
+```
+
+When no channel or handler is provided (as with `--no-confirm`), or the receiving side has gone
+away, each request resolves to its [`PromptRequest::predeclared_answer`].
+
+Unattended installs can pre-seed answers with [`PromptAnswers`], looked up by each request's
+[`PromptRequest::answer_keys`].
+*/
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender, channel},
+    },
+};
+
+/// A decision an [`Action`](crate::action::Action) needs during execution
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "prompt", rename_all = "snake_case")]
+pub enum PromptRequest {
+    /// An existing file conflicts with one the action intends to create, replacing it discards the existing file
+    ReplaceFile {
+        /// The conflicting file
+        path: PathBuf,
+        /// Why the existing file conflicts
+        reason: String,
+    },
+}
+
+impl PromptRequest {
+    /// The answer used when nobody is available to respond, such as with `--no-confirm`
+    ///
+    /// These always preserve the existing state of the system.
+    pub fn predeclared_answer(&self) -> PromptAnswer {
+        match self {
+            PromptRequest::ReplaceFile { .. } => PromptAnswer::No,
+        }
+    }
+
+    /// The keys a [`PromptAnswers`] may answer this request by, most specific first
+    ///
+    /// For example, `replace_file:/etc/systemd/system/nix-daemon.service` then `replace_file`.
+    pub fn answer_keys(&self) -> Vec<String> {
+        match self {
+            PromptRequest::ReplaceFile { path, .. } => vec![
+                format!("replace_file:{}", path.display()),
+                "replace_file".to_string(),
+            ],
+        }
+    }
+}
+
+impl std::fmt::Display for PromptRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptRequest::ReplaceFile { path, reason } => write!(
+                f,
+                "{reason}\nReplace `{path}` with the version `nix-installer` expects?",
+                path = path.display()
+            ),
+        }
+    }
+}
+
+/// The response to a [`PromptRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptAnswer {
+    Yes,
+    No,
+}
+
+impl FromStr for PromptAnswer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "yes" | "y" | "true" => Ok(PromptAnswer::Yes),
+            "no" | "n" | "false" => Ok(PromptAnswer::No),
+            _ => Err(format!("`{s}` is not an answer, expected `yes` or `no`")),
+        }
+    }
+}
+
+/// Answers decided ahead of time, keyed by [`PromptRequest::answer_keys`] or by the name of a
+/// decision the CLI makes (such as `install`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct PromptAnswers(HashMap<String, PromptAnswer>);
+
+impl PromptAnswers {
+    pub fn insert(&mut self, key: impl Into<String>, answer: PromptAnswer) {
+        self.0.insert(key.into(), answer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The answer seeded for `key`, if any
+    pub fn get(&self, key: &str) -> Option<PromptAnswer> {
+        self.0.get(key).copied()
+    }
+
+    /// The answer seeded for the most specific of the request's keys, if any
+    pub fn answer(&self, request: &PromptRequest) -> Option<PromptAnswer> {
+        request.answer_keys().iter().find_map(|key| self.get(key))
+    }
+
+    /// Parse a `KEY=ANSWER` pair, such as `replace_file=yes`
+    pub fn parse_pair(pair: &str) -> Result<(String, PromptAnswer), String> {
+        let (key, answer) = pair
+            .rsplit_once('=')
+            .ok_or_else(|| format!("`{pair}` is not of the form `KEY=yes` or `KEY=no`"))?;
+        Ok((key.to_string(), answer.parse()?))
+    }
+}
+
+/// A [`PromptRequest`] awaiting an answer
+#[derive(Debug)]
+pub struct Prompt {
+    request: PromptRequest,
+    reply: Sender<PromptAnswer>,
+}
+
+impl Prompt {
+    /// The decision being requested
+    pub fn request(&self) -> &PromptRequest {
+        &self.request
+    }
+
+    /// Answer the prompt, resuming the waiting action
+    pub fn answer(self, answer: PromptAnswer) {
+        if self.reply.send(answer).is_err() {
+            tracing::debug!("Prompt answered after the requesting action stopped waiting");
+        }
+    }
+}
+
+/// The sending half of a prompt channel, given to [`InstallPlan::install_with_prompts`](crate::InstallPlan::install_with_prompts)
+pub type PromptSender = Sender<Prompt>;
+/// The receiving half of a prompt channel, yielding each [`Prompt`] raised during execution
+pub type PromptReceiver = Receiver<Prompt>;
+
+/// Create a new prompt channel
+pub fn prompt_channel() -> (PromptSender, PromptReceiver) {
+    channel()
+}
+
+/// Answers the [`PromptRequest`]s raised by actions during execution
+///
+/// Handlers are called on the thread executing the plan, with any progress display suspended.
+pub trait InteractionHandler: Send + Sync {
+    /// Answer `request`, or `None` if nobody is available to decide it
+    fn interact(&self, request: &PromptRequest) -> Option<PromptAnswer>;
+
+    /// The answer used when [`InteractionHandler::interact`] cannot decide `request`
+    fn fallback(&self, request: &PromptRequest) -> PromptAnswer {
+        request.predeclared_answer()
+    }
+}
+
+impl InteractionHandler for PromptSender {
+    fn interact(&self, request: &PromptRequest) -> Option<PromptAnswer> {
+        let (reply, response) = channel();
+        let prompt = Prompt {
+            request: request.clone(),
+            reply,
+        };
+        if self.send(prompt).is_err() {
+            tracing::debug!("Prompt channel closed");
+            return None;
+        }
+        let answer = response.recv().ok();
+        if answer.is_none() {
+            tracing::debug!("Prompt dropped unanswered");
+        }
+        answer
+    }
+}
+
+/// How requests are answered when nobody is available to decide them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackPolicy {
+    /// Use each request's [`PromptRequest::predeclared_answer`], preserving the existing state
+    #[default]
+    Predeclared,
+    /// Answer every request with [`PromptAnswer::Yes`]
+    Yes,
+    /// Answer every request with [`PromptAnswer::No`]
+    No,
+}
+
+impl FallbackPolicy {
+    pub fn answer(&self, request: &PromptRequest) -> PromptAnswer {
+        match self {
+            FallbackPolicy::Predeclared => request.predeclared_answer(),
+            FallbackPolicy::Yes => PromptAnswer::Yes,
+            FallbackPolicy::No => PromptAnswer::No,
+        }
+    }
+}
+
+impl InteractionHandler for FallbackPolicy {
+    fn interact(&self, _request: &PromptRequest) -> Option<PromptAnswer> {
+        None
+    }
+
+    fn fallback(&self, request: &PromptRequest) -> PromptAnswer {
+        self.answer(request)
+    }
+}
+
+// Prompts are raised on the thread executing the plan, so plans (and tests) on other threads each
+// keep their own handler
+thread_local! {
+    static INTERACTION_HANDLER: RefCell<Option<Arc<dyn InteractionHandler>>> = const { RefCell::new(None) };
+}
+
+/// Routes prompts raised by actions on this thread to `handler` until dropped
+pub(crate) struct PromptGuard {
+    previous: Option<Arc<dyn InteractionHandler>>,
+}
+
+impl PromptGuard {
+    pub(crate) fn install(handler: Option<Arc<dyn InteractionHandler>>) -> Self {
+        Self {
+            previous: INTERACTION_HANDLER.replace(handler),
+        }
+    }
+}
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        INTERACTION_HANDLER.set(self.previous.take());
+    }
+}
+
+/// Raise a [`PromptRequest`], blocking until it is answered
+#[tracing::instrument(level = "debug", skip_all, fields(request = ?request))]
+pub(crate) fn ask(request: PromptRequest) -> PromptAnswer {
+    let handler = INTERACTION_HANDLER.with_borrow(Clone::clone);
+    let Some(handler) = handler else {
+        let predeclared = request.predeclared_answer();
+        tracing::trace!(answer = ?predeclared, "No interaction handler, using predeclared answer");
+        return predeclared;
+    };
+
+    let answer = {
+        let _suspended = crate::progress::SuspendGuard::suspend();
+        handler.interact(&request)
+    };
+    match answer {
+        Some(answer) => {
+            tracing::trace!(?answer, "Prompt answered");
+            answer
+        },
+        None => {
+            let answer = handler.fallback(&request);
+            tracing::debug!(?answer, "Prompt unanswered, using fallback answer");
+            answer
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ask_uses_channel_then_predeclared_answer() {
+        let request = PromptRequest::ReplaceFile {
+            path: PathBuf::from("/etc/systemd/system/nix-daemon.service"),
+            reason: "Different content".into(),
+        };
+
+        let (sender, receiver) = prompt_channel();
+        let responder = std::thread::spawn(move || {
+            for pending in receiver {
+                pending.answer(PromptAnswer::Yes);
+            }
+        });
+        {
+            let _guard = PromptGuard::install(Some(Arc::new(sender)));
+            assert_eq!(ask(request.clone()), PromptAnswer::Yes);
+            // Only the thread which installed the handler has its prompts routed to it
+            let elsewhere = request.clone();
+            assert_eq!(
+                std::thread::spawn(move || ask(elsewhere)).join().unwrap(),
+                PromptAnswer::No
+            );
+        }
+        responder.join().unwrap();
+
+        assert_eq!(ask(request), PromptAnswer::No);
+    }
+
+    #[test]
+    fn unanswered_requests_use_the_fallback_policy() {
+        let request = PromptRequest::ReplaceFile {
+            path: PathBuf::from("/etc/systemd/system/nix-daemon.service"),
+            reason: "Different content".into(),
+        };
+
+        let (sender, receiver) = prompt_channel();
+        drop(receiver);
+        assert_eq!(sender.interact(&request), None);
+        assert_eq!(sender.fallback(&request), PromptAnswer::No);
+
+        assert_eq!(FallbackPolicy::Yes.interact(&request), None);
+        assert_eq!(FallbackPolicy::Yes.fallback(&request), PromptAnswer::Yes);
+        assert_eq!(
+            FallbackPolicy::Predeclared.fallback(&request),
+            request.predeclared_answer()
+        );
+    }
+
+    #[test]
+    fn answers_prefer_the_most_specific_key() {
+        let request = PromptRequest::ReplaceFile {
+            path: PathBuf::from("/etc/systemd/system/nix-daemon.service"),
+            reason: "Different content".into(),
+        };
+
+        let mut answers = PromptAnswers::default();
+        assert_eq!(answers.answer(&request), None);
+
+        let (key, answer) = PromptAnswers::parse_pair("replace_file=yes").unwrap();
+        answers.insert(key, answer);
+        assert_eq!(answers.answer(&request), Some(PromptAnswer::Yes));
+
+        let (key, answer) =
+            PromptAnswers::parse_pair("replace_file:/etc/systemd/system/nix-daemon.service=no")
+                .unwrap();
+        answers.insert(key, answer);
+        assert_eq!(answers.answer(&request), Some(PromptAnswer::No));
+
+        assert!(PromptAnswers::parse_pair("replace_file").is_err());
+        assert!(PromptAnswers::parse_pair("replace_file=maybe").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+/*! Passing a proxy on to the commands the installer runs, and to the Nix daemon
+
+`curl` and Nix read their proxy from the environment (Nix has no `nix.conf` setting for it), so
+`--proxy` is set as `http_proxy`, `https_proxy` and `all_proxy` (in both cases) for the commands
+run during the install, and `--daemon-proxy` puts the same variables in the daemon's service.
+*/
+
+use url::Url;
+
+/// The variables holding a proxy, which are kept when escalating with `sudo`
+pub(crate) const PROXY_ENV_VARS: &[&str] = &[
+    "http_proxy",
+    "HTTP_PROXY",
+    "https_proxy",
+    "HTTPS_PROXY",
+    "all_proxy",
+    "ALL_PROXY",
+    "no_proxy",
+    "NO_PROXY",
+];
+
+/// A proxy, and the hosts it is not used for
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Proxy {
+    #[serde(serialize_with = "crate::sensitive::serialize_redacted_url")]
+    pub url: Url,
+    /// Comma separated hosts (or domains) to connect to directly, as in `no_proxy`
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+}
+
+impl Proxy {
+    /// `url`, skipped for the hosts in `no_proxy` (or `NO_PROXY`) if it is set
+    pub(crate) fn new(url: Url) -> Self {
+        Self {
+            url,
+            no_proxy: env_var(&["no_proxy", "NO_PROXY"]),
+        }
+    }
+
+    /// The proxy in `https_proxy`, `http_proxy` or `all_proxy` (or their uppercase versions), if any
+    pub(crate) fn from_env() -> Option<Self> {
+        let url = env_var(&[
+            "https_proxy",
+            "HTTPS_PROXY",
+            "http_proxy",
+            "HTTP_PROXY",
+            "all_proxy",
+            "ALL_PROXY",
+        ])?;
+        match Url::parse(&url) {
+            Ok(url) => Some(Self::new(url)),
+            Err(err) => {
+                tracing::warn!("Ignoring the proxy in the environment, it isn't a URL: {err}");
+                None
+            },
+        }
+    }
+
+    /// The variables `curl` and Nix read this proxy from
+    pub(crate) fn environment(&self) -> Vec<(&'static str, String)> {
+        let mut environment = [
+            "http_proxy",
+            "HTTP_PROXY",
+            "https_proxy",
+            "HTTPS_PROXY",
+            "all_proxy",
+            "ALL_PROXY",
+        ]
+        .into_iter()
+        .map(|key| (key, self.url.to_string()))
+        .collect::<Vec<_>>();
+        if let Some(no_proxy) = &self.no_proxy {
+            environment.push(("no_proxy", no_proxy.clone()));
+            environment.push(("NO_PROXY", no_proxy.clone()));
+        }
+        environment
+    }
+}
+
+fn env_var(keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn environment_sets_every_case() -> Result<(), Box<dyn std::error::Error>> {
+        let proxy = Proxy {
+            url: Url::parse("http://proxy.example.com:3128")?,
+            no_proxy: Some("localhost,.internal".into()),
+        };
+        let environment = proxy.environment();
+        assert!(environment.contains(&("https_proxy", "http://proxy.example.com:3128/".into())));
+        assert!(environment.contains(&("ALL_PROXY", "http://proxy.example.com:3128/".into())));
+        assert!(environment.contains(&("NO_PROXY", "localhost,.internal".into())));
+        Ok(())
+    }
+}
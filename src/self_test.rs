@@ -1,8 +1,28 @@
-use std::{process::Output, time::SystemTime};
+use std::{
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::Output,
+    time::SystemTime,
+};
 
+use crate::plan::find_receipt_at;
+use crate::settings::{DEFAULT_DAEMON_SOCKET, DEFAULT_PROFILE};
 use crate::util::which;
 use std::process::Command;
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYSTEM: &str = "x86_64-linux";
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const SYSTEM: &str = "aarch64-linux";
+#[cfg(all(target_os = "linux", target_arch = "arm", target_feature = "v7"))]
+const SYSTEM: &str = "armv7l-linux";
+#[cfg(all(target_os = "linux", target_arch = "arm", not(target_feature = "v7")))]
+const SYSTEM: &str = "armv6l-linux";
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+const SYSTEM: &str = "x86_64-darwin";
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const SYSTEM: &str = "aarch64-darwin";
+
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug)]
 pub enum SelfTestError {
@@ -22,6 +42,31 @@ pub enum SelfTestError {
         #[source]
         error: std::io::Error,
     },
+    #[error(
+        "Could not connect to the Nix daemon socket `{}`, check the daemon is running (such as with `systemctl status nix-daemon.socket` or `launchctl print system/org.nixos.nix-daemon`)",
+        .path.display()
+    )]
+    DaemonSocket {
+        path: PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("Self-test `{check}` failed with command `{command}`, {hint}, stderr:\n{}", String::from_utf8_lossy(&output.stderr), hint = check.hint())]
+    CheckFailed {
+        check: Check,
+        command: String,
+        output: Output,
+    },
+    /// Failed to execute command
+    #[error("Failed to execute command `{command}` for self-test `{check}`")]
+    CheckCommand {
+        check: Check,
+        command: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
     #[error(transparent)]
     SystemTime(#[from] std::time::SystemTimeError),
 }
@@ -44,6 +89,12 @@ impl Shell {
     pub fn all() -> &'static [Shell] {
         &[Shell::Sh, Shell::Bash, Shell::Fish, Shell::Zsh]
     }
+    /// Zsh and Fish are tested as interactive shells, reading the user's rc files, which often
+    /// misbehave without a terminal (such as in CI)
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, Shell::Zsh | Shell::Fish)
+    }
+
     pub fn executable(&self) -> &'static str {
         match &self {
             Shell::Sh => "sh",
@@ -70,15 +121,6 @@ impl Shell {
             },
         };
 
-        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-linux";
-        #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-linux";
-        #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-darwin";
-        #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-darwin";
-
         let timestamp_millis = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)?
             .as_millis();
@@ -126,10 +168,189 @@ impl Shell {
     }
 }
 
+/// A check of the Nix install which doesn't depend on a shell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// The daemon socket accepts connections
+    DaemonSocket,
+    /// `nix store ping --store daemon` completes the daemon handshake
+    StorePing,
+    /// A trivial derivation builds, in the sandbox on Linux
+    SandboxedBuild,
+    /// A flake without inputs evaluates
+    Flakes,
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Check::DaemonSocket => write!(f, "daemon-socket"),
+            Check::StorePing => write!(f, "store-ping"),
+            Check::SandboxedBuild => write!(f, "sandboxed-build"),
+            Check::Flakes => write!(f, "flakes"),
+        }
+    }
+}
+
+impl Check {
+    pub fn all() -> &'static [Check] {
+        &[
+            Check::DaemonSocket,
+            Check::StorePing,
+            Check::SandboxedBuild,
+            Check::Flakes,
+        ]
+    }
+
+    /// What to look into when the check fails
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Check::DaemonSocket => "check the daemon is running",
+            Check::StorePing => {
+                "the daemon refused the connection, check the user is in `allowed-users` in `/etc/nix/nix.conf`"
+            },
+            Check::SandboxedBuild => {
+                "if the build sandbox is unsupported here (such as in an unprivileged container), consider installing with `--sandbox disable`"
+            },
+            Check::Flakes => {
+                "check `extra-experimental-features` in `/etc/nix/nix.conf` includes `nix-command flakes`"
+            },
+        }
+    }
+
+    /// Run the check, reading the install receipt from `receipt_path` (from `--receipt-path`) if given
+    #[tracing::instrument(skip_all, fields(check = %self))]
+    pub fn run(&self, receipt_path: Option<&Path>) -> Result<(), SelfTestError> {
+        let nix = format!("{DEFAULT_PROFILE}/bin/nix");
+        let mut command = Command::new(&nix);
+        // Kept alive until the command has run
+        let mut _flake_dir = None;
+        match self {
+            Check::DaemonSocket => {
+                for path in daemon_sockets(receipt_path) {
+                    UnixStream::connect(&path)
+                        .map_err(|error| SelfTestError::DaemonSocket { path, error })?;
+                }
+                return Ok(());
+            },
+            Check::StorePing => {
+                command.args(["store", "ping", "--store", "daemon"]);
+            },
+            Check::SandboxedBuild => {
+                let timestamp_millis = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_millis();
+                command.args([
+                    "build",
+                    "--option",
+                    "substitute",
+                    "false",
+                    "--option",
+                    "post-build-hook",
+                    "",
+                    "--no-link",
+                    "--expr",
+                    &format!(
+                        r#"derivation {{ name = "self-test-sandbox-{timestamp_millis}"; system = "{SYSTEM}"; builder = "/bin/sh"; args = ["-c" "echo hello > $out"]; }}"#
+                    ),
+                ]);
+                // macOS defaults to no sandbox, which is expected to be kept
+                if cfg!(target_os = "linux") {
+                    command.args(["--option", "sandbox", "true"]);
+                }
+            },
+            Check::Flakes => {
+                let flake_dir = tempfile::tempdir()?;
+                std::fs::write(
+                    flake_dir.path().join("flake.nix"),
+                    r#"{ outputs = _: { selfTest = "ok"; }; }"#,
+                )?;
+                command.args(["eval", "--raw"]);
+                command.arg(format!("path:{}#selfTest", flake_dir.path().display()));
+                _flake_dir = Some(flake_dir);
+            },
+        }
+
+        let command_str = format!("{:?}", command);
+        tracing::debug!(command = command_str, "Running self-test `{self}`");
+        let output = command
+            .stdin(std::process::Stdio::null())
+            .env("NIX_REMOTE", "daemon")
+            .output()
+            .map_err(|error| SelfTestError::CheckCommand {
+                check: *self,
+                command: command_str.clone(),
+                error,
+            })?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(SelfTestError::CheckFailed {
+                check: *self,
+                command: command_str,
+                output,
+            })
+        }
+    }
+}
+
+/// The default daemon socket, and any `--extra-daemon-socket`s recorded in the install receipt
+fn daemon_sockets(receipt_path: Option<&Path>) -> Vec<PathBuf> {
+    let extra_daemon_sockets = find_receipt_at(receipt_path, None)
+        .and_then(|receipt| std::fs::read_to_string(receipt).ok())
+        .and_then(|buf| serde_json::from_str::<serde_json::Value>(&buf).ok())
+        .and_then(|receipt| {
+            serde_json::from_value::<Vec<PathBuf>>(
+                receipt["planner"]["settings"]["extra_daemon_sockets"].clone(),
+            )
+            .ok()
+        })
+        .unwrap_or_default();
+
+    std::iter::once(PathBuf::from(DEFAULT_DAEMON_SOCKET))
+        .chain(extra_daemon_sockets)
+        .collect()
+}
+
+/// The outcome of each self-test, for `nix-installer self-test --json`
+#[derive(Debug, serde::Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<SelfTestReportEntry>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SelfTestReportEntry {
+    /// Such as `shell:bash` or `store-ping`
+    pub name: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SelfTestReport {
+    fn push(&mut self, name: String, result: Result<(), SelfTestError>) {
+        self.passed &= result.is_ok();
+        self.checks.push(SelfTestReportEntry {
+            name,
+            passed: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        });
+    }
+}
+
 #[tracing::instrument(skip_all)]
-pub fn self_test() -> Result<(), Vec<SelfTestError>> {
-    let shells = Shell::discover();
+pub fn self_test(receipt_path: Option<&Path>) -> Result<(), Vec<SelfTestError>> {
+    self_test_shells(&Shell::discover(), receipt_path)
+}
 
+/// Like [`self_test`], only testing `shells`, along with every [`Check`]
+#[tracing::instrument(skip_all)]
+pub fn self_test_shells(
+    shells: &[Shell],
+    receipt_path: Option<&Path>,
+) -> Result<(), Vec<SelfTestError>> {
     let mut failures = vec![];
 
     for shell in shells {
@@ -139,9 +360,34 @@ pub fn self_test() -> Result<(), Vec<SelfTestError>> {
         }
     }
 
+    for check in Check::all() {
+        match check.run(receipt_path) {
+            Ok(()) => (),
+            Err(err) => failures.push(err),
+        }
+    }
+
     if failures.is_empty() {
         Ok(())
     } else {
         Err(failures)
     }
 }
+
+/// Run the same tests as [`self_test`], reporting each independently
+#[tracing::instrument(skip_all)]
+pub fn self_test_report(receipt_path: Option<&Path>) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        passed: true,
+        checks: vec![],
+    };
+
+    for shell in Shell::discover() {
+        report.push(format!("shell:{shell}"), shell.self_test());
+    }
+    for check in Check::all() {
+        report.push(check.to_string(), check.run(receipt_path));
+    }
+
+    report
+}
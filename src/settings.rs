@@ -1,7 +1,15 @@
 /*! Configurable knobs and their related errors
 */
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use crate::daemon_unit::DaemonLimits;
+use crate::plan::RECEIPT_FILE_NAME;
+use crate::proxy::Proxy;
+use crate::sensitive::SensitiveString;
 #[cfg(feature = "cli")]
 use clap::{
     ArgAction,
@@ -11,26 +19,63 @@ use url::Url;
 
 pub const SCRATCH_DIR: &str = "/nix/temp-install-dir";
 
+/// The directory the install receipt is kept in unless `--state-dir` says otherwise
+pub const DEFAULT_STATE_DIR: &str = "/nix";
+
+/// The default `--min-free-space`, room for a first few builds and downloads after installing
+pub const DEFAULT_MIN_FREE_SPACE_MIB: u64 = 1024;
+
 pub const DEFAULT_NIX_BUILD_USER_GROUP_NAME: &str = "nixbld";
 
 /// The embedded Nix tarball (zstd compressed)
 pub const EMBEDDED_NIX_TARBALL: &[u8] = include_bytes!(concat!(env!("NIX_TARBALL_PATH")));
 
+/// The size of the embedded Nix tarball once unpacked
+///
+/// Falls back to a typical compression ratio if the zstd frame does not record its size.
+pub fn embedded_nix_unpacked_size() -> u64 {
+    zstd::zstd_safe::get_frame_content_size(EMBEDDED_NIX_TARBALL)
+        .ok()
+        .flatten()
+        .unwrap_or(EMBEDDED_NIX_TARBALL.len() as u64 * 4)
+}
+
 /// The store path of the nix package in the embedded tarball
 pub const NIX_STORE_PATH: &str = env!("NIX_STORE_PATH");
 
 /// The store path of the nss-cacert package in the embedded tarball
 pub const NSS_CACERT_STORE_PATH: &str = env!("NSS_CACERT_STORE_PATH");
 
+/// The profile `SetupDefaultProfile` installs Nix and `nss-cacert` into, whichever tarball they came from
+pub const DEFAULT_PROFILE: &str = "/nix/var/nix/profiles/default";
+
+/// Where the Nix daemon listens, and where Nix clients connect by default
+pub const DEFAULT_DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+
 /// The version of Nix embedded in this installer
 pub const NIX_VERSION: &str = env!("NIX_VERSION");
 
+/// The SHA-256 of the embedded Nix tarball, in hex (empty if unknown when the installer was built)
+pub const NIX_TARBALL_SHA256: &str = env!("NIX_TARBALL_SHA256");
+
+/// The experimental features the embedded Nix supports, comma separated (empty if unknown when the
+/// installer was built)
+pub const NIX_EXPERIMENTAL_FEATURES: &str = env!("NIX_EXPERIMENTAL_FEATURES");
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum InitSystem {
     None,
     Systemd,
     Launchd,
+    Openrc,
+    #[cfg_attr(feature = "cli", value(name = "sysvinit"))]
+    SysVInit,
+    Runit,
+    S6,
+    /// FreeBSD's `rc.d`
+    #[cfg_attr(feature = "cli", value(name = "rcd"))]
+    RcD,
 }
 
 impl std::fmt::Display for InitSystem {
@@ -39,6 +84,202 @@ impl std::fmt::Display for InitSystem {
             InitSystem::None => write!(f, "none"),
             InitSystem::Systemd => write!(f, "systemd"),
             InitSystem::Launchd => write!(f, "launchd"),
+            InitSystem::Openrc => write!(f, "openrc"),
+            InitSystem::SysVInit => write!(f, "sysvinit"),
+            InitSystem::Runit => write!(f, "runit"),
+            InitSystem::S6 => write!(f, "s6"),
+            InitSystem::RcD => write!(f, "rcd"),
+        }
+    }
+}
+
+/// How build users and groups are created on Linux
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum UserManagement {
+    /// `systemd-sysusers` if systemd is running, otherwise `useradd`
+    #[default]
+    Auto,
+    /// A `sysusers.d` fragment applied with `systemd-sysusers`
+    Sysusers,
+    /// `useradd` (or `adduser`) and friends
+    Useradd,
+}
+
+impl std::fmt::Display for UserManagement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserManagement::Auto => write!(f, "auto"),
+            UserManagement::Sysusers => write!(f, "sysusers"),
+            UserManagement::Useradd => write!(f, "useradd"),
+        }
+    }
+}
+
+/// How to let unprivileged processes create the user namespaces Nix's build sandbox needs, where
+/// AppArmor restricts them (as on Ubuntu 24.04)
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum UsernsWorkaround {
+    /// A `sysctl.d` drop-in lifting the restriction for every program
+    Sysctl,
+    /// An AppArmor profile allowing only Nix to create user namespaces
+    Apparmor,
+}
+
+impl std::fmt::Display for UsernsWorkaround {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsernsWorkaround::Sysctl => write!(f, "sysctl"),
+            UsernsWorkaround::Apparmor => write!(f, "apparmor"),
+        }
+    }
+}
+
+/// Whether to keep Nix's build sandbox, which needs kernel features unprivileged containers (such as
+/// LXC or OpenVZ) often lack
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxMode {
+    /// Keep the sandbox if the kernel supports it, otherwise set `sandbox = false` with a warning
+    #[default]
+    Auto,
+    /// Keep the sandbox, failing if the kernel doesn't support it
+    #[cfg_attr(feature = "cli", value(alias = "true"))]
+    Require,
+    /// Set `sandbox = false`
+    #[cfg_attr(feature = "cli", value(alias = "false"))]
+    Disable,
+}
+
+impl std::fmt::Display for SandboxMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxMode::Auto => write!(f, "auto"),
+            SandboxMode::Require => write!(f, "require"),
+            SandboxMode::Disable => write!(f, "disable"),
+        }
+    }
+}
+
+/// The I/O scheduling class the Nix daemon (and so its builds) run in
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonIoClass {
+    /// Share I/O with everything else, as by default
+    BestEffort,
+    /// Only use the disk when nothing else is (with `launchd`, `LowPriorityIO`)
+    Idle,
+}
+
+impl std::fmt::Display for DaemonIoClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonIoClass::BestEffort => write!(f, "best-effort"),
+            DaemonIoClass::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+/// What to do when existing users or groups clash with the planned build users and group
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum IdConflict {
+    /// Fail, listing the clashes
+    #[default]
+    Error,
+    /// Keep existing build users and group with the IDs they already have
+    Adopt,
+    /// Move the build users to the first free UID range, and the group to a free GID
+    Shift,
+}
+
+impl std::fmt::Display for IdConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdConflict::Error => write!(f, "error"),
+            IdConflict::Adopt => write!(f, "adopt"),
+            IdConflict::Shift => write!(f, "shift"),
+        }
+    }
+}
+
+/// How often a periodic maintenance job runs
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceSchedule {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl std::fmt::Display for MaintenanceSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaintenanceSchedule::Daily => write!(f, "daily"),
+            MaintenanceSchedule::Weekly => write!(f, "weekly"),
+            MaintenanceSchedule::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// A filesystem to format a dedicated Nix store device with
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Filesystem {
+    #[default]
+    Ext4,
+    Xfs,
+    Btrfs,
+}
+
+impl std::fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filesystem::Ext4 => write!(f, "ext4"),
+            Filesystem::Xfs => write!(f, "xfs"),
+            Filesystem::Btrfs => write!(f, "btrfs"),
+        }
+    }
+}
+
+/// Whose shell profiles to configure to load Nix
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum ShellProfileScope {
+    /// The system-wide profiles, such as `/etc/bashrc`
+    #[default]
+    System,
+    /// The profiles in the home directory of the user running `sudo`, such as `~/.bashrc`
+    User,
+    /// Both the system-wide and user profiles
+    Both,
+}
+
+impl ShellProfileScope {
+    pub fn includes_system(&self) -> bool {
+        matches!(self, ShellProfileScope::System | ShellProfileScope::Both)
+    }
+
+    pub fn includes_user(&self) -> bool {
+        matches!(self, ShellProfileScope::User | ShellProfileScope::Both)
+    }
+}
+
+impl std::fmt::Display for ShellProfileScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellProfileScope::System => write!(f, "system"),
+            ShellProfileScope::User => write!(f, "user"),
+            ShellProfileScope::Both => write!(f, "both"),
         }
     }
 }
@@ -64,6 +305,20 @@ pub struct CommonSettings {
     )]
     pub modify_profile: bool,
 
+    /// Whose shell profiles to modify to load Nix: the `system`-wide ones, the `user` running `sudo`'s, or `both`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_enum,
+            long,
+            default_value_t = ShellProfileScope::System,
+            env = "NIX_INSTALLER_SHELL_PROFILE_SCOPE",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub shell_profile_scope: ShellProfileScope,
+
     /// The Nix build group name
     #[cfg_attr(
         feature = "cli",
@@ -97,52 +352,542 @@ pub struct CommonSettings {
         clap(default_value = "_nixbld")
     )]
     #[cfg_attr(
-        all(target_os = "linux", feature = "cli"),
-        clap(default_value = "nixbld")
+        all(target_os = "linux", feature = "cli"),
+        clap(default_value = "nixbld")
+    )]
+    pub nix_build_user_prefix: String,
+
+    /// The number of build users to create
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            alias = "daemon-user-count",
+            env = "NIX_INSTALLER_NIX_BUILD_USER_COUNT",
+            global = true
+        )
+    )]
+    #[cfg_attr(all(target_os = "macos", feature = "cli"), clap(default_value = "32"))]
+    #[cfg_attr(all(target_os = "linux", feature = "cli"), clap(default_value = "32"))]
+    pub nix_build_user_count: u32,
+
+    /// The Nix build user base UID (ascending)
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NIX_BUILD_USER_ID_BASE", global = true)
+    )]
+    #[cfg_attr(
+        all(target_os = "macos", feature = "cli"),
+        doc = "Service users on Mac should be between 200-400"
+    )]
+    #[cfg_attr(
+        all(feature = "cli"),
+        clap(default_value_t = default_nix_build_user_id_base())
+    )]
+    pub nix_build_user_id_base: u32,
+
+    /// If existing users clash with the planned build users, move the build users to the first free UID range instead of failing (the same as `--id-conflict shift`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            global = true,
+            env = "NIX_INSTALLER_RENUMBER_BUILD_USERS"
+        )
+    )]
+    #[serde(default)]
+    pub renumber_build_users: bool,
+
+    /// What to do when existing users or groups clash with the planned build users or group: `error`, `adopt` them with their existing IDs, or `shift` to free IDs
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            value_enum,
+            long,
+            default_value_t = IdConflict::Error,
+            global = true,
+            env = "NIX_INSTALLER_ID_CONFLICT"
+        )
+    )]
+    #[serde(default)]
+    pub id_conflict: IdConflict,
+
+    /// An SSL cert file to use; sets `ssl-cert-file` in `/etc/nix/nix.conf`
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_SSL_CERT_FILE", global = true)
+    )]
+    pub ssl_cert_file: Option<PathBuf>,
+
+    /// Set `ssl-cert-file` to the system's CA certificates: the distribution's bundle, or on macOS the System keychains exported to a bundle (for corporate TLS intercepting proxies)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_USE_SYSTEM_CA",
+            conflicts_with = "ssl_cert_file",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub use_system_ca: bool,
+
+    /// The proxy to fetch Nix and run the install's `nix` commands through (valid proxy bases are `https://$URL`, `http://$URL` and `socks5://$URL`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_PROXY", global = true)
+    )]
+    #[serde(
+        default,
+        serialize_with = "crate::sensitive::serialize_redacted_optional_url"
+    )]
+    pub proxy: Option<Url>,
+
+    /// Have the Nix daemon use `--proxy` (or else the proxy in the environment) too, through its service's environment
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_DAEMON_PROXY", global = true)
+    )]
+    #[serde(default)]
+    pub daemon_proxy: bool,
+
+    /// A systemd drop-in (or, with `launchd`, a plist dictionary of keys) to merge into the Nix daemon's service, kept in the receipt
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_name = "PATH",
+            env = "NIX_INSTALLER_DAEMON_UNIT_OVERRIDE",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub daemon_unit_override: Option<PathBuf>,
+
+    /// (systemd and `launchd` only) The niceness of the Nix daemon and its builds, from -20 to 19, so large builds don't freeze the desktop
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            allow_negative_numbers = true,
+            value_parser = clap::value_parser!(i8).range(-20..=19),
+            env = "NIX_INSTALLER_DAEMON_NICE",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub daemon_nice: Option<i8>,
+
+    /// (systemd only) The Nix daemon's `CPUWeight=`, from 1 to 10000 (100 is every other service's)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_parser = clap::value_parser!(u16).range(1..=10000),
+            env = "NIX_INSTALLER_DAEMON_CPU_WEIGHT",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub daemon_cpu_weight: Option<u16>,
+
+    /// (systemd and `launchd` only) The I/O scheduling class of the Nix daemon and its builds
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, value_enum, env = "NIX_INSTALLER_DAEMON_IO_CLASS", global = true)
+    )]
+    #[serde(default)]
+    pub daemon_io_class: Option<DaemonIoClass>,
+
+    /// (systemd only) The Nix daemon's `MemoryMax=`, such as `8G` or `50%`, beyond which its builds are killed
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_parser = crate::daemon_unit::parse_memory_max,
+            env = "NIX_INSTALLER_DAEMON_MEMORY_MAX",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub daemon_memory_max: Option<String>,
+
+    /// Extra configuration lines for `/etc/nix.conf`
+    #[cfg_attr(feature = "cli", clap(long, action = ArgAction::Append, num_args = 0.., env = "NIX_INSTALLER_EXTRA_CONF", global = true))]
+    pub extra_conf: Vec<UrlOrPathOrString>,
+
+    /// A binary cache to substitute from, in addition to `cache.nixos.org` (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "substituter",
+            action = ArgAction::Append,
+            value_name = "URL",
+            env = "NIX_INSTALLER_SUBSTITUTERS",
+            value_delimiter = ',',
+            global = true
+        )
+    )]
+    #[serde(default, serialize_with = "crate::sensitive::serialize_redacted_urls")]
+    pub substituters: Vec<Url>,
+
+    /// A public key to trust signatures from, such as `cache.example.com-1:...` (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "trusted-public-key",
+            action = ArgAction::Append,
+            value_name = "KEY",
+            env = "NIX_INSTALLER_TRUSTED_PUBLIC_KEYS",
+            value_delimiter = ',',
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub trusted_public_keys: Vec<String>,
+
+    /// Experimental features to enable in addition to `nix-command` and `flakes` (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "experimental-feature",
+            action = ArgAction::Append,
+            value_name = "FEATURE",
+            env = "NIX_INSTALLER_EXPERIMENTAL_FEATURES",
+            value_delimiter = ',',
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub experimental_features: Vec<String>,
+
+    /// The `max-jobs` to set in `/etc/nix/nix.conf`, a number or `auto`
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_MAX_JOBS", global = true)
+    )]
+    #[serde(default)]
+    pub max_jobs: Option<String>,
+
+    /// Users (or `@group`s) to add to `trusted-users`, alongside `root` (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "trusted-users",
+            action = ArgAction::Append,
+            value_name = "USER",
+            env = "NIX_INSTALLER_TRUSTED_USERS",
+            value_delimiter = ',',
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub trusted_users: Vec<String>,
+
+    /// Users (or `@group`s) allowed to connect to the Nix daemon, instead of everyone (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "allowed-users",
+            action = ArgAction::Append,
+            value_name = "USER",
+            env = "NIX_INSTALLER_ALLOWED_USERS",
+            value_delimiter = ',',
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub allowed_users: Vec<String>,
+
+    /// If `nix-installer` should forcibly recreate files it finds existing
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            global = true,
+            env = "NIX_INSTALLER_FORCE"
+        )
+    )]
+    pub force: bool,
+
+    /// If `nix-installer` should skip creating `/etc/nix/nix.conf`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            global = true,
+            env = "NIX_INSTALLER_SKIP_NIX_CONF",
+            conflicts_with_all = ["extra_conf", "github_token", "access_tokens", "netrc_file", "netrc_content", "substituters", "trusted_public_keys", "experimental_features", "max_jobs", "trusted_users", "allowed_users"],
+        )
+    )]
+    pub skip_nix_conf: bool,
+
+    /// Whether to setup system channels
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            global = true,
+            env = "NIX_INSTALLER_ADD_CHANNEL",
+            long("add-channel"),
+        )
+    )]
+    pub add_channel: bool,
+
+    /// Never use the network: don't update channels, and refuse settings which would need to fetch anything
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            global = true,
+            env = "NIX_INSTALLER_OFFLINE",
+            long = "offline",
+        )
+    )]
+    #[serde(default)]
+    pub offline: bool,
+
+    /// Free space (in MiB) to require on the filesystem `/nix` is created on, beyond what unpacking Nix takes
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            default_value_t = DEFAULT_MIN_FREE_SPACE_MIB,
+            global = true,
+            env = "NIX_INSTALLER_MIN_FREE_SPACE",
+            value_name = "MIB",
+        )
+    )]
+    #[serde(default = "default_min_free_space")]
+    pub min_free_space: u64,
+
+    /// A GitHub token for fetching flakes, written as `access-tokens = github.com=TOKEN` to the root-only `/etc/nix/nix.conf.d/tokens.conf`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_GITHUB_TOKEN",
+            hide_env_values = true,
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub github_token: Option<SensitiveString>,
+
+    /// Space separated `HOST=TOKEN` pairs for fetching flakes (as in Nix's `access-tokens`), written to the root-only `/etc/nix/nix.conf.d/tokens.conf`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_ACCESS_TOKENS",
+            hide_env_values = true,
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub access_tokens: Option<SensitiveString>,
+
+    /// A URL to periodically fetch an enterprise-managed `/etc/nix/registry.json` from
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_REGISTRY_URL", global = true)
+    )]
+    #[serde(
+        default,
+        serialize_with = "crate::sensitive::serialize_redacted_optional_url"
+    )]
+    pub registry_url: Option<Url>,
+
+    /// A URL to periodically fetch an enterprise-managed `/etc/nix/netrc` from
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NETRC_URL", global = true)
+    )]
+    #[serde(
+        default,
+        serialize_with = "crate::sensitive::serialize_redacted_optional_url"
+    )]
+    pub netrc_url: Option<Url>,
+
+    /// A netrc file with credentials for private binary caches, installed as `/etc/nix/netrc` and set as `netrc-file` in `/etc/nix/nix.custom.conf`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NETRC_FILE",
+            conflicts_with_all = ["netrc_content", "netrc_url"],
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub netrc_file: Option<PathBuf>,
+
+    /// Like `--netrc-file`, but the content of the netrc itself, which is redacted in the receipt
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NETRC_CONTENT",
+            hide_env_values = true,
+            conflicts_with = "netrc_url",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub netrc_content: Option<SensitiveString>,
+
+    /// How often (in seconds) to refresh the `--registry-url` and `--netrc-url` files
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            default_value_t = default_config_refresh_interval(),
+            value_parser = clap::value_parser!(u32).range(1..),
+            env = "NIX_INSTALLER_CONFIG_REFRESH_INTERVAL",
+            global = true
+        )
+    )]
+    #[serde(default = "default_config_refresh_interval")]
+    pub config_refresh_interval: u32,
+
+    /// A PEM client certificate (and key) presented when refreshing the `--registry-url` and `--netrc-url` files, for mTLS
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_CONFIG_REFRESH_CLIENT_CERT", global = true)
+    )]
+    #[serde(default)]
+    pub config_refresh_client_cert: Option<PathBuf>,
+
+    /// Collect garbage older than 30 days on this schedule, with a systemd timer or `launchd` job
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            env = "NIX_INSTALLER_ENABLE_AUTO_GC",
+            value_name = "SCHEDULE",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub enable_auto_gc: Option<MaintenanceSchedule>,
+
+    /// Optimise the Nix store (hard linking identical files) on this schedule, with a systemd timer or `launchd` job
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_enum,
+            env = "NIX_INSTALLER_ENABLE_AUTO_OPTIMISE",
+            value_name = "SCHEDULE",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub enable_auto_optimise: Option<MaintenanceSchedule>,
+
+    /// Another path for the Nix daemon's socket, linked to the default one, for clients (such as those of Nix forks) which connect elsewhere (can be repeated)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "extra-daemon-socket",
+            action = ArgAction::Append,
+            value_name = "PATH",
+            env = "NIX_INSTALLER_EXTRA_DAEMON_SOCKETS",
+            value_delimiter = ',',
+            global = true
+        )
     )]
-    pub nix_build_user_prefix: String,
+    #[serde(default)]
+    pub extra_daemon_sockets: Vec<PathBuf>,
 
-    /// The number of build users to create
+    /// An executable to run before installing, with `NIX_INSTALLER_*` variables describing the plan in its environment
     #[cfg_attr(
         feature = "cli",
         clap(
             long,
-            alias = "daemon-user-count",
-            env = "NIX_INSTALLER_NIX_BUILD_USER_COUNT",
+            env = "NIX_INSTALLER_PRE_INSTALL_HOOK",
+            value_name = "PATH",
             global = true
         )
     )]
-    #[cfg_attr(all(target_os = "macos", feature = "cli"), clap(default_value = "32"))]
-    #[cfg_attr(all(target_os = "linux", feature = "cli"), clap(default_value = "32"))]
-    pub nix_build_user_count: u32,
+    #[serde(default)]
+    pub pre_install_hook: Option<PathBuf>,
 
-    /// The Nix build user base UID (ascending)
+    /// An executable to run after installing, with `NIX_INSTALLER_*` variables describing the plan in its environment
     #[cfg_attr(
         feature = "cli",
-        clap(long, env = "NIX_INSTALLER_NIX_BUILD_USER_ID_BASE", global = true)
+        clap(
+            long,
+            env = "NIX_INSTALLER_POST_INSTALL_HOOK",
+            value_name = "PATH",
+            global = true
+        )
     )]
+    #[serde(default)]
+    pub post_install_hook: Option<PathBuf>,
+
+    /// An executable to run after uninstalling, with `NIX_INSTALLER_*` variables describing the plan in its environment
     #[cfg_attr(
-        all(target_os = "macos", feature = "cli"),
-        doc = "Service users on Mac should be between 200-400"
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_POST_UNINSTALL_HOOK",
+            value_name = "PATH",
+            global = true
+        )
     )]
+    #[serde(default)]
+    pub post_uninstall_hook: Option<PathBuf>,
+
+    /// A store exported with `nix-installer uninstall --backup-store` to import once Nix is installed
     #[cfg_attr(
-        all(feature = "cli"),
-        clap(default_value_t = default_nix_build_user_id_base())
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_RESTORE_STORE",
+            value_name = "PATH",
+            global = true
+        )
     )]
-    pub nix_build_user_id_base: u32,
+    #[serde(default)]
+    pub restore_store: Option<PathBuf>,
 
-    /// An SSL cert file to use; sets `ssl-cert-file` in `/etc/nix/nix.conf`
+    /// The directory `nix-installer` keeps its install receipt and other state in
     #[cfg_attr(
         feature = "cli",
-        clap(long, env = "NIX_INSTALLER_SSL_CERT_FILE", global = true)
+        clap(
+            long,
+            default_value = DEFAULT_STATE_DIR,
+            env = "NIX_INSTALLER_STATE_DIR",
+            global = true
+        )
     )]
-    pub ssl_cert_file: Option<PathBuf>,
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
 
-    /// Extra configuration lines for `/etc/nix.conf`
-    #[cfg_attr(feature = "cli", clap(long, action = ArgAction::Append, num_args = 0.., env = "NIX_INSTALLER_EXTRA_CONF", global = true))]
-    pub extra_conf: Vec<UrlOrPathOrString>,
+    /// Where to write the install receipt, instead of `receipt.json` in `--state-dir`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_RECEIPT_PATH",
+            value_name = "PATH",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub receipt_path: Option<PathBuf>,
 
-    /// If `nix-installer` should forcibly recreate files it finds existing
+    /// Require `uninstall --confirm-hostname` with the machine's hostname before uninstalling
     #[cfg_attr(
         feature = "cli",
         clap(
@@ -150,37 +895,93 @@ pub struct CommonSettings {
             action(ArgAction::SetTrue),
             default_value = "false",
             global = true,
-            env = "NIX_INSTALLER_FORCE"
+            env = "NIX_INSTALLER_PROTECT"
         )
     )]
-    pub force: bool,
+    #[serde(default)]
+    pub protect: bool,
 
-    /// If `nix-installer` should skip creating `/etc/nix/nix.conf`
+    /// (Expert) An additional Nix store path (eg. `/nix/store/...-nix-2.34.0`) to install into the `nix-next` profile, run with `nix-next`
     #[cfg_attr(
         feature = "cli",
         clap(
             long,
-            action(ArgAction::SetTrue),
-            default_value = "false",
-            global = true,
-            env = "NIX_INSTALLER_SKIP_NIX_CONF",
-            conflicts_with = "extra_conf",
+            value_name = "STORE_PATH",
+            env = "NIX_INSTALLER_NIX_NEXT",
+            global = true
         )
     )]
-    pub skip_nix_conf: bool,
+    #[serde(default)]
+    pub nix_next: Option<PathBuf>,
 
-    /// Whether to setup system channels
+    /// Which implementation of Nix to install, only `nix` is embedded so others require `--tarball-url`
     #[cfg_attr(
         feature = "cli",
         clap(
-            action(ArgAction::SetTrue),
-            default_value = "false",
-            global = true,
-            env = "NIX_INSTALLER_ADD_CHANNEL",
-            long("add-channel"),
+            long,
+            value_enum,
+            default_value_t = Distribution::Nix,
+            env = "NIX_INSTALLER_DISTRIBUTION",
+            global = true
         )
     )]
-    pub add_channel: bool,
+    #[serde(default)]
+    pub distribution: Distribution,
+
+    /// (Expert) A binary tarball (`.tar.zst`, `.tar.xz` or `.tar.gz`) to install instead of the embedded Nix, from an `https://` or `file://` URL
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_TARBALL_URL", global = true)
+    )]
+    #[serde(default)]
+    pub tarball_url: Option<Url>,
+
+    /// (Expert) A JSON file with the `nix` and `nss_cacert` store paths in `--tarball-url`, if they can't be found from the tarball's `.reginfo`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_STORE_PATHS_JSON",
+            requires = "tarball_url",
+            global = true
+        )
+    )]
+    #[serde(default)]
+    pub store_paths_json: Option<PathBuf>,
+}
+
+/// Where distributions keep their CA bundle, in the order `--use-system-ca` looks for them
+pub const SYSTEM_CA_BUNDLES: &[&str] = &[
+    // Debian, Ubuntu, Arch Linux, Alpine and Gentoo
+    "/etc/ssl/certs/ca-certificates.crt",
+    // Fedora, RHEL and derivatives
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+    // openSUSE
+    "/etc/ssl/ca-bundle.pem",
+    // FreeBSD
+    "/usr/local/share/certs/ca-root-nss.crt",
+    "/etc/ssl/cert.pem",
+];
+
+pub(crate) fn default_state_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_STATE_DIR)
+}
+
+pub(crate) fn default_min_free_space() -> u64 {
+    DEFAULT_MIN_FREE_SPACE_MIB
+}
+
+pub(crate) fn default_config_refresh_interval() -> u32 {
+    3600
+}
+
+/// A systemd timer or `launchd` job with an interval of 0 never runs
+pub(crate) fn check_config_refresh_interval(interval: u32) -> Result<(), InstallSettingsError> {
+    match interval {
+        0 => Err(InstallSettingsError::ZeroConfigRefreshInterval),
+        _ => Ok(()),
+    }
 }
 
 pub(crate) fn default_nix_build_user_id_base() -> u32 {
@@ -202,6 +1003,15 @@ pub(crate) fn default_nix_build_group_id() -> u32 {
 }
 
 impl CommonSettings {
+    /// How ID clashes are handled, counting `--renumber-build-users` as `--id-conflict shift`
+    pub fn id_conflict_strategy(&self) -> IdConflict {
+        if self.renumber_build_users {
+            IdConflict::Shift
+        } else {
+            self.id_conflict
+        }
+    }
+
     /// The default settings for the given Architecture & Operating System
     pub fn try_default() -> Result<Self, InstallSettingsError> {
         use target_lexicon::{Architecture, OperatingSystem};
@@ -209,10 +1019,13 @@ impl CommonSettings {
             (Architecture::X86_64, OperatingSystem::Linux) => "nixbld",
             (Architecture::X86_32(_), OperatingSystem::Linux) => "nixbld",
             (Architecture::Aarch64(_), OperatingSystem::Linux) => "nixbld",
+            (Architecture::Arm(_), OperatingSystem::Linux) => "nixbld",
             (Architecture::X86_64, OperatingSystem::MacOSX(_))
             | (Architecture::X86_64, OperatingSystem::Darwin(_)) => "_nixbld",
             (Architecture::Aarch64(_), OperatingSystem::MacOSX(_))
             | (Architecture::Aarch64(_), OperatingSystem::Darwin(_)) => "_nixbld",
+            (Architecture::X86_64, OperatingSystem::Freebsd)
+            | (Architecture::Aarch64(_), OperatingSystem::Freebsd) => "nixbld",
             _ => {
                 return Err(InstallSettingsError::UnsupportedArchitecture(
                     target_lexicon::HOST,
@@ -222,16 +1035,57 @@ impl CommonSettings {
 
         Ok(Self {
             modify_profile: true,
+            shell_profile_scope: ShellProfileScope::System,
             nix_build_group_name: String::from(crate::settings::DEFAULT_NIX_BUILD_USER_GROUP_NAME),
             nix_build_group_id: default_nix_build_group_id(),
             nix_build_user_id_base: default_nix_build_user_id_base(),
             nix_build_user_count: 32,
             nix_build_user_prefix: nix_build_user_prefix.to_string(),
+            renumber_build_users: false,
+            id_conflict: IdConflict::Error,
             ssl_cert_file: None,
+            use_system_ca: false,
+            proxy: None,
+            daemon_proxy: false,
+            daemon_unit_override: None,
+            daemon_nice: None,
+            daemon_cpu_weight: None,
+            daemon_io_class: None,
+            daemon_memory_max: None,
             extra_conf: Default::default(),
+            substituters: Default::default(),
+            trusted_public_keys: Default::default(),
+            experimental_features: Default::default(),
+            max_jobs: None,
+            trusted_users: Default::default(),
+            allowed_users: Default::default(),
             force: false,
             skip_nix_conf: false,
             add_channel: false,
+            offline: false,
+            min_free_space: DEFAULT_MIN_FREE_SPACE_MIB,
+            github_token: None,
+            access_tokens: None,
+            registry_url: None,
+            netrc_url: None,
+            netrc_file: None,
+            netrc_content: None,
+            config_refresh_interval: default_config_refresh_interval(),
+            config_refresh_client_cert: None,
+            enable_auto_gc: None,
+            enable_auto_optimise: None,
+            extra_daemon_sockets: Default::default(),
+            pre_install_hook: None,
+            post_install_hook: None,
+            post_uninstall_hook: None,
+            restore_store: None,
+            state_dir: default_state_dir(),
+            receipt_path: None,
+            protect: false,
+            nix_next: None,
+            distribution: Distribution::default(),
+            tarball_url: None,
+            store_paths_json: None,
         })
     }
 
@@ -239,16 +1093,57 @@ impl CommonSettings {
     pub fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
         let Self {
             modify_profile,
+            shell_profile_scope,
             nix_build_group_name,
             nix_build_group_id,
             nix_build_user_prefix,
             nix_build_user_id_base,
             nix_build_user_count,
+            renumber_build_users,
+            id_conflict,
             ssl_cert_file,
+            use_system_ca,
+            proxy,
+            daemon_proxy,
+            daemon_unit_override,
+            daemon_nice,
+            daemon_cpu_weight,
+            daemon_io_class,
+            daemon_memory_max,
             extra_conf,
+            substituters,
+            trusted_public_keys,
+            experimental_features,
+            max_jobs,
+            trusted_users,
+            allowed_users,
             force,
             skip_nix_conf,
             add_channel,
+            offline,
+            min_free_space,
+            github_token,
+            access_tokens,
+            registry_url,
+            netrc_url,
+            netrc_file,
+            netrc_content,
+            config_refresh_interval,
+            config_refresh_client_cert,
+            enable_auto_gc,
+            enable_auto_optimise,
+            extra_daemon_sockets,
+            pre_install_hook,
+            post_install_hook,
+            post_uninstall_hook,
+            restore_store,
+            state_dir,
+            receipt_path,
+            protect,
+            nix_next,
+            distribution,
+            tarball_url,
+            store_paths_json,
         } = self;
         let mut map = HashMap::default();
 
@@ -256,6 +1151,10 @@ impl CommonSettings {
             "modify_profile".into(),
             serde_json::to_value(modify_profile)?,
         );
+        map.insert(
+            "shell_profile_scope".into(),
+            serde_json::to_value(shell_profile_scope)?,
+        );
         map.insert(
             "nix_build_group_name".into(),
             serde_json::to_value(nix_build_group_name)?,
@@ -276,15 +1175,368 @@ impl CommonSettings {
             "nix_build_user_count".into(),
             serde_json::to_value(nix_build_user_count)?,
         );
+        map.insert(
+            "renumber_build_users".into(),
+            serde_json::to_value(renumber_build_users)?,
+        );
+        map.insert("id_conflict".into(), serde_json::to_value(id_conflict)?);
         map.insert("ssl_cert_file".into(), serde_json::to_value(ssl_cert_file)?);
+        map.insert("use_system_ca".into(), serde_json::to_value(use_system_ca)?);
+        map.insert(
+            "proxy".into(),
+            serde_json::to_value(proxy.as_ref().map(crate::sensitive::redact_url))?,
+        );
+        map.insert("daemon_proxy".into(), serde_json::to_value(daemon_proxy)?);
+        map.insert(
+            "daemon_unit_override".into(),
+            serde_json::to_value(daemon_unit_override)?,
+        );
+        map.insert("daemon_nice".into(), serde_json::to_value(daemon_nice)?);
+        map.insert(
+            "daemon_cpu_weight".into(),
+            serde_json::to_value(daemon_cpu_weight)?,
+        );
+        map.insert(
+            "daemon_io_class".into(),
+            serde_json::to_value(daemon_io_class)?,
+        );
+        map.insert(
+            "daemon_memory_max".into(),
+            serde_json::to_value(daemon_memory_max)?,
+        );
         map.insert("extra_conf".into(), serde_json::to_value(extra_conf)?);
+        map.insert(
+            "substituters".into(),
+            serde_json::to_value(
+                substituters
+                    .iter()
+                    .map(crate::sensitive::redact_url)
+                    .collect::<Vec<_>>(),
+            )?,
+        );
+        map.insert(
+            "trusted_public_keys".into(),
+            serde_json::to_value(trusted_public_keys)?,
+        );
+        map.insert(
+            "experimental_features".into(),
+            serde_json::to_value(experimental_features)?,
+        );
+        map.insert("max_jobs".into(), serde_json::to_value(max_jobs)?);
+        map.insert("trusted_users".into(), serde_json::to_value(trusted_users)?);
+        map.insert("allowed_users".into(), serde_json::to_value(allowed_users)?);
         map.insert("force".into(), serde_json::to_value(force)?);
         map.insert("skip_nix_conf".into(), serde_json::to_value(skip_nix_conf)?);
 
         map.insert("add_channel".into(), serde_json::to_value(add_channel)?);
+        map.insert("offline".into(), serde_json::to_value(offline)?);
+        map.insert(
+            "min_free_space".into(),
+            serde_json::to_value(min_free_space)?,
+        );
+        map.insert(
+            "registry_url".into(),
+            serde_json::to_value(registry_url.as_ref().map(crate::sensitive::redact_url))?,
+        );
+        map.insert("github_token".into(), serde_json::to_value(github_token)?);
+        map.insert("access_tokens".into(), serde_json::to_value(access_tokens)?);
+        map.insert(
+            "netrc_url".into(),
+            serde_json::to_value(netrc_url.as_ref().map(crate::sensitive::redact_url))?,
+        );
+        map.insert("netrc_file".into(), serde_json::to_value(netrc_file)?);
+        map.insert("netrc_content".into(), serde_json::to_value(netrc_content)?);
+        map.insert(
+            "config_refresh_interval".into(),
+            serde_json::to_value(config_refresh_interval)?,
+        );
+        map.insert(
+            "config_refresh_client_cert".into(),
+            serde_json::to_value(config_refresh_client_cert)?,
+        );
+        map.insert(
+            "enable_auto_gc".into(),
+            serde_json::to_value(enable_auto_gc)?,
+        );
+        map.insert(
+            "enable_auto_optimise".into(),
+            serde_json::to_value(enable_auto_optimise)?,
+        );
+        map.insert(
+            "extra_daemon_sockets".into(),
+            serde_json::to_value(extra_daemon_sockets)?,
+        );
+        map.insert(
+            "pre_install_hook".into(),
+            serde_json::to_value(pre_install_hook)?,
+        );
+        map.insert(
+            "post_install_hook".into(),
+            serde_json::to_value(post_install_hook)?,
+        );
+        map.insert(
+            "post_uninstall_hook".into(),
+            serde_json::to_value(post_uninstall_hook)?,
+        );
+        map.insert("restore_store".into(), serde_json::to_value(restore_store)?);
+        map.insert("state_dir".into(), serde_json::to_value(state_dir)?);
+        map.insert("receipt_path".into(), serde_json::to_value(receipt_path)?);
+        map.insert("protect".into(), serde_json::to_value(protect)?);
+        map.insert("nix_next".into(), serde_json::to_value(nix_next)?);
+        map.insert("distribution".into(), serde_json::to_value(distribution)?);
+        map.insert("tarball_url".into(), serde_json::to_value(tarball_url)?);
+        map.insert(
+            "store_paths_json".into(),
+            serde_json::to_value(store_paths_json)?,
+        );
 
         Ok(map)
     }
+
+    /// The CA bundle to set as `ssl-cert-file`: `--ssl-cert-file`, or with `--use-system-ca` the system's
+    ///
+    /// On macOS that is where [`ExportKeychainCertificates`](crate::action::macos::ExportKeychainCertificates)
+    /// exports the System keychains to, which doesn't exist until it executes.
+    pub fn ca_bundle(&self) -> Result<Option<PathBuf>, InstallSettingsError> {
+        use target_lexicon::OperatingSystem;
+        if let Some(ssl_cert_file) = &self.ssl_cert_file {
+            return Ok(Some(ssl_cert_file.clone()));
+        }
+        if !self.use_system_ca {
+            return Ok(None);
+        }
+        match OperatingSystem::host() {
+            OperatingSystem::MacOSX(_) | OperatingSystem::Darwin(_) => Ok(Some(PathBuf::from(
+                crate::action::macos::SYSTEM_CA_BUNDLE_DEST,
+            ))),
+            _ => SYSTEM_CA_BUNDLES
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.is_file())
+                .map(Some)
+                .ok_or(InstallSettingsError::NoSystemCaBundle),
+        }
+    }
+
+    /// The proxy for the commands run while installing, from `--proxy`
+    pub(crate) fn proxy_for_install(&self) -> Option<Proxy> {
+        self.proxy.clone().map(Proxy::new)
+    }
+
+    /// With `--daemon-proxy`, the proxy for the Nix daemon: `--proxy`, or else the one in the environment
+    pub(crate) fn proxy_for_daemon(&self) -> Option<Proxy> {
+        if !self.daemon_proxy {
+            return None;
+        }
+        let proxy = self.proxy_for_install().or_else(Proxy::from_env);
+        if proxy.is_none() {
+            tracing::warn!(
+                "`--daemon-proxy` was passed, but there is no proxy in `--proxy` or the environment, continuing without"
+            );
+        }
+        proxy
+    }
+
+    /// Where the install receipt is written: `--receipt-path`, or `receipt.json` in `state_dir`
+    pub fn receipt_location(&self, state_dir: &Path) -> PathBuf {
+        self.receipt_path
+            .clone()
+            .unwrap_or_else(|| state_dir.join(RECEIPT_FILE_NAME))
+    }
+
+    /// The limits on the Nix daemon's resources, if any were given
+    pub(crate) fn daemon_limits(&self) -> Option<DaemonLimits> {
+        let limits = DaemonLimits {
+            nice: self.daemon_nice,
+            cpu_weight: self.daemon_cpu_weight,
+            io_class: self.daemon_io_class,
+            memory_max: self.daemon_memory_max.clone(),
+        };
+        (limits != DaemonLimits::default()).then_some(limits)
+    }
+
+    /// Check `--config-refresh-interval` would schedule a refresh, which plan files bypassing `clap` may not
+    pub fn check_config_refresh_interval(&self) -> Result<(), InstallSettingsError> {
+        check_config_refresh_interval(self.config_refresh_interval)
+    }
+
+    /// With `--offline`, check nothing planned would need to fetch from the network
+    ///
+    /// This runs before planning, so air-gapped installs fail before changing anything.
+    pub fn check_offline(&self) -> Result<(), InstallSettingsError> {
+        if !self.offline {
+            return Ok(());
+        }
+        if let Some(url) = &self.tarball_url {
+            if url.scheme() != "file" {
+                return Err(InstallSettingsError::RequiresNetwork(format!(
+                    "--tarball-url {url}"
+                )));
+            }
+        }
+        if let Some(url) = &self.registry_url {
+            return Err(InstallSettingsError::RequiresNetwork(format!(
+                "--registry-url {}",
+                crate::sensitive::redact_url(url)
+            )));
+        }
+        if let Some(url) = &self.netrc_url {
+            return Err(InstallSettingsError::RequiresNetwork(format!(
+                "--netrc-url {}",
+                crate::sensitive::redact_url(url)
+            )));
+        }
+        // Anything not already in the store would be substituted
+        if let Some(store_path) = &self.nix_next {
+            if !store_path.exists() {
+                return Err(InstallSettingsError::RequiresNetwork(format!(
+                    "--nix-next {}",
+                    store_path.display()
+                )));
+            }
+        }
+        for extra_conf in &self.extra_conf {
+            if let UrlOrPathOrString::Url(url) = extra_conf {
+                if url.scheme() != "file" {
+                    return Err(InstallSettingsError::RequiresNetwork(format!(
+                        "--extra-conf {url}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The free space (in bytes) installing needs where `/nix` is created: the unpacked Nix, plus `--min-free-space`
+    ///
+    /// A tarball from `--tarball-url` isn't fetched until installing, so only `--min-free-space` is required for it.
+    pub fn required_free_space(&self) -> u64 {
+        let unpacked = match self.tarball_url {
+            None => embedded_nix_unpacked_size(),
+            Some(_) => 0,
+        };
+        unpacked.saturating_add(self.min_free_space.saturating_mul(1024 * 1024))
+    }
+
+    /// Where the Nix to install comes from, reading `--store-paths-json` if given
+    pub fn nix_source(&self) -> Result<NixSource, InstallSettingsError> {
+        let Some(url) = &self.tarball_url else {
+            if self.distribution != Distribution::Nix {
+                return Err(InstallSettingsError::DistributionNotEmbedded(
+                    self.distribution,
+                ));
+            }
+            return Ok(NixSource::Embedded);
+        };
+
+        let store_paths = match &self.store_paths_json {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|e| InstallSettingsError::ReadStorePaths(path.clone(), e))?;
+                Some(serde_json::from_str(&content)?)
+            },
+            None => None,
+        };
+
+        Ok(NixSource::Tarball {
+            url: url.clone(),
+            distribution: self.distribution,
+            store_paths,
+        })
+    }
+}
+
+/// An implementation of Nix which can be installed
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    /// Upstream Nix, embedded in the installer
+    #[default]
+    Nix,
+    /// Lix, from a `--tarball-url` such as one of <https://releases.lix.systems>
+    Lix,
+}
+
+impl std::fmt::Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Distribution::Nix => write!(f, "nix"),
+            Distribution::Lix => write!(f, "lix"),
+        }
+    }
+}
+
+/// The store paths of the Nix and `nss-cacert` packages in a binary tarball
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct StorePaths {
+    pub nix: PathBuf,
+    pub nss_cacert: PathBuf,
+}
+
+impl StorePaths {
+    /// The store paths of the tarball embedded in this installer
+    pub fn embedded() -> Self {
+        Self {
+            nix: PathBuf::from(NIX_STORE_PATH.trim()),
+            nss_cacert: PathBuf::from(NSS_CACERT_STORE_PATH.trim()),
+        }
+    }
+
+    /// Find the `{distribution}-{version}` and `nss-cacert` store paths in the `.reginfo` of a binary tarball
+    pub(crate) fn from_reginfo(
+        reginfo: &str,
+        distribution: Distribution,
+        version: &str,
+    ) -> Option<Self> {
+        let nix_name = format!("{distribution}-{version}");
+        let mut nix = None;
+        let mut nss_cacert = None;
+        for line in reginfo.lines() {
+            let Some(name) = line.strip_prefix("/nix/store/") else {
+                continue;
+            };
+            // Store path names are `{hash}-{name}`
+            let Some((_hash, name)) = name.split_once('-') else {
+                continue;
+            };
+            if name == nix_name {
+                nix = Some(PathBuf::from(line));
+            } else if name.starts_with("nss-cacert-") {
+                nss_cacert = Some(PathBuf::from(line));
+            }
+        }
+
+        Some(Self {
+            nix: nix?,
+            nss_cacert: nss_cacert?,
+        })
+    }
+}
+
+/// Where the Nix to install comes from
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NixSource {
+    /// The tarball embedded in this installer
+    #[default]
+    Embedded,
+    /// A binary tarball fetched when installing
+    Tarball {
+        url: Url,
+        distribution: Distribution,
+        /// Found from the tarball's `.reginfo` if not given
+        store_paths: Option<StorePaths>,
+    },
+}
+
+impl NixSource {
+    pub fn distribution(&self) -> Distribution {
+        match self {
+            NixSource::Embedded => Distribution::Nix,
+            NixSource::Tarball { distribution, .. } => *distribution,
+        }
+    }
 }
 
 fn linux_detect_systemd_started() -> bool {
@@ -320,6 +1572,10 @@ pub struct InitSettings {
         all(target_os = "linux", feature = "cli"),
         clap(default_value_t = InitSystem::Systemd)
     )]
+    #[cfg_attr(
+        all(target_os = "freebsd", feature = "cli"),
+        clap(default_value_t = InitSystem::RcD)
+    )]
     pub init: InitSystem,
 
     /// Start the daemon (if not `--init none`)
@@ -335,6 +1591,19 @@ pub struct InitSettings {
         )
     )]
     pub start_daemon: bool,
+
+    /// (systemd only) Sandbox the daemon with a drop-in setting `ProtectSystem=strict`, `ProtectHome=read-only`, `PrivateTmp=yes` and similar
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_HARDEN_DAEMON",
+        )
+    )]
+    #[serde(default)]
+    pub harden_daemon: bool,
 }
 
 impl InitSettings {
@@ -351,10 +1620,15 @@ impl InitSettings {
             (Architecture::Aarch64(_), OperatingSystem::Linux) => {
                 (InitSystem::Systemd, linux_detect_systemd_started())
             },
+            (Architecture::Arm(_), OperatingSystem::Linux) => {
+                (InitSystem::Systemd, linux_detect_systemd_started())
+            },
             (Architecture::X86_64, OperatingSystem::MacOSX(_))
             | (Architecture::X86_64, OperatingSystem::Darwin(_)) => (InitSystem::Launchd, true),
             (Architecture::Aarch64(_), OperatingSystem::MacOSX(_))
             | (Architecture::Aarch64(_), OperatingSystem::Darwin(_)) => (InitSystem::Launchd, true),
+            (Architecture::X86_64, OperatingSystem::Freebsd)
+            | (Architecture::Aarch64(_), OperatingSystem::Freebsd) => (InitSystem::RcD, true),
             _ => {
                 return Err(InstallSettingsError::UnsupportedArchitecture(
                     target_lexicon::HOST,
@@ -362,16 +1636,25 @@ impl InitSettings {
             },
         };
 
-        Ok(Self { init, start_daemon })
+        Ok(Self {
+            init,
+            start_daemon,
+            harden_daemon: false,
+        })
     }
 
     /// A listing of the settings, suitable for [`Planner::settings`](crate::planner::Planner::settings)
     pub fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
-        let Self { init, start_daemon } = self;
+        let Self {
+            init,
+            start_daemon,
+            harden_daemon,
+        } = self;
         let mut map = HashMap::default();
 
         map.insert("init".into(), serde_json::to_value(init)?);
         map.insert("start_daemon".into(), serde_json::to_value(start_daemon)?);
+        map.insert("harden_daemon".into(), serde_json::to_value(harden_daemon)?);
         Ok(map)
     }
 
@@ -411,15 +1694,49 @@ pub enum InstallSettingsError {
     ),
     #[error("No supported init system found")]
     InitNotSupported,
+    #[error("Only `nix` is embedded in this installer, pass a `--tarball-url` to install `{0}`")]
+    DistributionNotEmbedded(Distribution),
+    #[error("Reading store paths from `{0}`")]
+    ReadStorePaths(PathBuf, #[source] std::io::Error),
+    #[error("`{0}` needs network access, which `--offline` forbids")]
+    RequiresNetwork(String),
+    #[error(
+        "`--use-system-ca` found no CA bundle (looked for `{}`), pass its location with `--ssl-cert-file` instead",
+        SYSTEM_CA_BUNDLES.join("`, `")
+    )]
+    NoSystemCaBundle,
+    #[error(
+        "`config_refresh_interval` must be at least 1 second, a refresh every 0 seconds is never run"
+    )]
+    ZeroConfigRefreshInterval,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize, Clone)]
+/// Credentials in a URL and secret settings in a string are redacted when formatted or serialized
+#[derive(PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize, Clone)]
 pub enum UrlOrPathOrString {
+    #[serde(serialize_with = "crate::sensitive::serialize_redacted_url")]
     Url(Url),
     Path(PathBuf),
+    #[serde(serialize_with = "crate::sensitive::serialize_redacted_nix_conf")]
     String(String),
 }
 
+impl std::fmt::Debug for UrlOrPathOrString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrlOrPathOrString::Url(url) => f
+                .debug_tuple("Url")
+                .field(&crate::sensitive::redact_url(url))
+                .finish(),
+            UrlOrPathOrString::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            UrlOrPathOrString::String(string) => f
+                .debug_tuple("String")
+                .field(&crate::sensitive::redact_nix_conf(string))
+                .finish(),
+        }
+    }
+}
+
 impl FromStr for UrlOrPathOrString {
     type Err = url::ParseError;
 
@@ -478,7 +1795,10 @@ impl clap::builder::TypedValueParser for UrlOrPathOrString {
 
 #[cfg(test)]
 mod tests {
-    use super::{FromStr, PathBuf, Url, UrlOrPathOrString};
+    use super::{
+        CommonSettings, Distribution, FromStr, InstallSettingsError, PathBuf, StorePaths, Url,
+        UrlOrPathOrString,
+    };
 
     #[test]
     fn url_or_path_or_string_parses() -> Result<(), Box<dyn std::error::Error>> {
@@ -501,4 +1821,68 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn store_paths_from_reginfo() {
+        let reginfo = "\
+/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-lix-2.93.0
+0000000000000000000000000000000000000000000000000000000000000000
+0
+
+/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-lix-2.93.0-man
+0000000000000000000000000000000000000000000000000000000000000000
+0
+
+/nix/store/cccccccccccccccccccccccccccccccc-nss-cacert-3.107
+0000000000000000000000000000000000000000000000000000000000000000
+0
+";
+        assert_eq!(
+            StorePaths::from_reginfo(reginfo, Distribution::Lix, "2.93.0"),
+            Some(StorePaths {
+                nix: PathBuf::from("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-lix-2.93.0"),
+                nss_cacert: PathBuf::from(
+                    "/nix/store/cccccccccccccccccccccccccccccccc-nss-cacert-3.107"
+                ),
+            }),
+        );
+        assert_eq!(
+            StorePaths::from_reginfo(reginfo, Distribution::Nix, "2.93.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn offline_refuses_fetching() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = CommonSettings::try_default()?;
+        settings.registry_url = Some(Url::parse("https://example.com/registry.json")?);
+        assert!(
+            settings.check_offline().is_ok(),
+            "Only checked with `--offline`"
+        );
+
+        settings.offline = true;
+        assert!(matches!(
+            settings.check_offline(),
+            Err(InstallSettingsError::RequiresNetwork(_))
+        ));
+
+        settings.registry_url = None;
+        settings.tarball_url = Some(Url::parse("file:///tmp/lix.tar.xz")?);
+        assert!(settings.check_offline().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_a_zero_config_refresh_interval() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = CommonSettings::try_default()?;
+        assert!(settings.check_config_refresh_interval().is_ok());
+
+        settings.config_refresh_interval = 0;
+        assert!(matches!(
+            settings.check_config_refresh_interval(),
+            Err(InstallSettingsError::ZeroConfigRefreshInterval)
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,161 @@
+/*! Exporting the Nix store before uninstalling, and importing it into a new install
+
+`uninstall --backup-store PATH` copies every path in the store to a `file://` binary cache in
+`PATH`, with the list of those paths beside it (a binary cache can't list what it holds), and
+`install --restore-store PATH` copies them back once the new install is running. Only store
+paths are kept: profiles and GC roots are not, so restored paths are collected by the next
+`nix-collect-garbage` unless something refers to them again.
+*/
+
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::action::ActionErrorKind;
+use crate::execute_command;
+use crate::settings::DEFAULT_PROFILE;
+
+/// The list of exported store paths, one per line, in the export directory
+pub(crate) const STORE_PATHS_FILE: &str = "store-paths";
+
+/// The `nix` of the default profile, with the `nix` command enabled whatever `nix.conf` says
+pub(crate) fn nix_command() -> Command {
+    let mut command = Command::new(format!("{DEFAULT_PROFILE}/bin/nix"));
+    command.args(["--extra-experimental-features", "nix-command"]);
+    command
+}
+
+/// The `file://` binary cache in `dir`, compressed with zstd, which is much faster than the default xz
+pub(crate) fn binary_cache_url(dir: &Path) -> String {
+    format!("file://{}?compression=zstd", dir.display())
+}
+
+/// Check `dest` is somewhere the export outlives uninstalling, returning it as an absolute path
+///
+/// `dest` may not exist yet, so its nearest existing parent is what is resolved and checked.
+pub(crate) fn check_backup_dest(dest: &Path) -> Result<PathBuf, ActionErrorKind> {
+    let dest = std::path::absolute(dest)
+        .map_err(|e| ActionErrorKind::Canonicalize(dest.to_path_buf(), e))?;
+    let mut existing = dest.as_path();
+    let mut missing = vec![];
+    while !existing.exists() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            break;
+        };
+        missing.push(name);
+        existing = parent;
+    }
+    let existing = existing
+        .canonicalize()
+        .map_err(|e| ActionErrorKind::Canonicalize(existing.to_path_buf(), e))?;
+    let resolved = missing
+        .into_iter()
+        .rev()
+        .fold(existing.clone(), |path, name| path.join(name));
+
+    let removed = || {
+        ActionErrorKind::Custom(
+            format!(
+                "`{}` is removed along with Nix, pass a `--backup-store` outside of `/nix`",
+                resolved.display()
+            )
+            .into(),
+        )
+    };
+    if resolved.starts_with("/nix") {
+        return Err(removed());
+    }
+    // On macOS, and wherever else `/nix` is a volume of its own, anything mounted there goes with it
+    let device = |path: &Path| {
+        path.metadata()
+            .map(|metadata| metadata.dev())
+            .map_err(|e| ActionErrorKind::GettingMetadata(path.to_path_buf(), e))
+    };
+    let nix = Path::new("/nix");
+    if nix.exists() {
+        let nix_device = device(nix)?;
+        if nix_device != device(Path::new("/"))? && device(&existing)? == nix_device {
+            return Err(removed());
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Copy every path in the store to a binary cache in `dest`, returning how many were copied
+pub(crate) fn export_store(dest: &Path) -> Result<usize, ActionErrorKind> {
+    std::fs::create_dir_all(dest)
+        .map_err(|e| ActionErrorKind::CreateDirectory(dest.to_path_buf(), e))?;
+    // `nix copy` wants an absolute path in a `file://` URL
+    let dest = dest
+        .canonicalize()
+        .map_err(|e| ActionErrorKind::Canonicalize(dest.to_path_buf(), e))?;
+
+    let output = execute_command(
+        nix_command()
+            .args(["path-info", "--all"])
+            .stdin(std::process::Stdio::null()),
+    )?;
+    let store_paths = dest.join(STORE_PATHS_FILE);
+    crate::util::write_atomic(&store_paths, &output.stdout)?;
+    let count = String::from_utf8_lossy(&output.stdout).lines().count();
+
+    tracing::info!(
+        count,
+        dest = %dest.display(),
+        "Exporting the Nix store, which may take a while"
+    );
+    execute_command(
+        nix_command()
+            .args(["copy", "--stdin", "--to"])
+            .arg(binary_cache_url(&dest))
+            .stdin(open_store_paths(&dest)?),
+    )?;
+
+    Ok(count)
+}
+
+/// The list of store paths exported to `dir`, to give `nix copy --stdin`
+pub(crate) fn open_store_paths(dir: &Path) -> Result<File, ActionErrorKind> {
+    let store_paths = dir.join(STORE_PATHS_FILE);
+    File::open(&store_paths).map_err(|e| ActionErrorKind::Open(store_paths, e))
+}
+
+/// Check `dir` holds a store exported by [`export_store`]
+pub(crate) fn check_export(dir: &Path) -> Result<PathBuf, ActionErrorKind> {
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| ActionErrorKind::Canonicalize(dir.to_path_buf(), e))?;
+    for expected in [STORE_PATHS_FILE, "nix-cache-info"] {
+        if !dir.join(expected).is_file() {
+            return Err(ActionErrorKind::Custom(
+                format!(
+                    "`{}` has no `{expected}`, so it isn't a store exported with `nix-installer uninstall --backup-store`",
+                    dir.display()
+                )
+                .into(),
+            ));
+        }
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backup_dest_outside_nix() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(check_backup_dest(Path::new("/nix/store-backup")).is_err());
+        assert!(check_backup_dest(Path::new("/tmp/../nix/store-backup")).is_err());
+
+        let dir = tempfile::tempdir()?;
+        let dest = dir.path().join("not-yet").join("backup");
+        assert_eq!(
+            check_backup_dest(&dest)?,
+            dir.path().canonicalize()?.join("not-yet").join("backup")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+/*! Colors and emphasis in what `nix-installer` prints
+
+[`Colorize`] has the [`OwoColorize`](owo_colors::OwoColorize) styles `nix-installer` uses, but leaves them out once
+[`set_plain`] is called, as for `--output plain`, so screen readers and dumb terminals get the
+same text without escape codes.
+*/
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Print text without colors or emphasis from now on
+pub(crate) fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether output is plain, so no colors, spinners, or redrawn lines are printed
+pub(crate) fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+pub(crate) trait Colorize: Sized {
+    fn bold(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().bold())
+    }
+
+    fn dimmed(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().dimmed())
+    }
+
+    fn italic(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().italic())
+    }
+
+    fn red(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().red())
+    }
+
+    fn green(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().green())
+    }
+
+    fn yellow(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().yellow())
+    }
+
+    fn cyan(&self) -> Styled<'_, Self> {
+        Styled::new(self, owo_colors::Style::new().cyan())
+    }
+}
+
+impl<D> Colorize for D {}
+
+/// Displays as the styled value, or the value alone when output [`is_plain`]
+pub(crate) struct Styled<'a, T> {
+    inner: &'a T,
+    style: owo_colors::Style,
+}
+
+impl<'a, T> Styled<'a, T> {
+    fn new(inner: &'a T, style: owo_colors::Style) -> Self {
+        Self { inner, style }
+    }
+}
+
+impl<T: fmt::Display> Styled<'_, T> {
+    /// Write the value to `f`, styled unless `plain`
+    fn write_to(&self, f: &mut impl fmt::Write, plain: bool) -> fmt::Result {
+        match plain {
+            true => write!(f, "{}", self.inner),
+            false => write!(
+                f,
+                "{}",
+                owo_colors::OwoColorize::style(self.inner, self.style)
+            ),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Styled<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_to(f, is_plain())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_leaves_out_escape_codes() -> fmt::Result {
+        let styled = Colorize::bold(&"Proceed?");
+
+        let mut rich = String::new();
+        styled.write_to(&mut rich, false)?;
+        assert!(rich.contains('\x1b'));
+
+        let mut plain = String::new();
+        styled.write_to(&mut plain, true)?;
+        assert_eq!(plain, "Proceed?");
+        Ok(())
+    }
+}
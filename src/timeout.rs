@@ -0,0 +1,201 @@
+/*! Timing out actions whose commands hang
+
+Commands like `diskutil` or `systemctl` occasionally hang, which would otherwise leave the install
+waiting forever. With [`InstallPlan::set_action_timeout`](crate::InstallPlan::set_action_timeout),
+each top level action gets a deadline when it starts executing or reverting. Commands run through
+[`execute_command`](crate::execute_command) during the action then run in a process group of their
+own, which is killed if the deadline passes, failing the action with the command and whatever it
+had output so far.
+*/
+
+use std::{
+    cell::Cell,
+    io::Read,
+    os::unix::process::CommandExt,
+    process::{Child, Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use nix::{sys::signal::Signal, unistd::Pid};
+
+use crate::action::ActionErrorKind;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// When the running action must finish by, and the timeout it was given
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+// Actions run on the thread executing the plan, which may not be the only plan in the process
+thread_local! {
+    static ACTION_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+    static DEADLINE: Cell<Option<Deadline>> = const { Cell::new(None) };
+}
+
+/// Gives each action executed or reverted on this thread `timeout` to finish, until dropped
+pub(crate) struct TimeoutGuard {
+    previous: Option<Duration>,
+}
+
+impl TimeoutGuard {
+    pub(crate) fn install(timeout: Option<Duration>) -> Self {
+        Self {
+            previous: ACTION_TIMEOUT.replace(timeout),
+        }
+    }
+}
+
+impl Drop for TimeoutGuard {
+    fn drop(&mut self) {
+        ACTION_TIMEOUT.set(self.previous);
+    }
+}
+
+/// Starts the deadline of an action, unless it is part of an action which already has one
+pub(crate) struct DeadlineGuard {
+    started: bool,
+}
+
+impl DeadlineGuard {
+    pub(crate) fn start() -> Self {
+        let started = DEADLINE.get().is_none()
+            && ACTION_TIMEOUT.get().is_some_and(|timeout| {
+                DEADLINE.set(Some(Deadline {
+                    at: Instant::now() + timeout,
+                    timeout,
+                }));
+                true
+            });
+        Self { started }
+    }
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        if self.started {
+            DEADLINE.set(None);
+        }
+    }
+}
+
+pub(crate) fn deadline() -> Option<Deadline> {
+    DEADLINE.get()
+}
+
+/// Like [`Command::output`], killing the command (and anything it started) if `deadline` passes
+pub(crate) fn output_before(
+    command: &mut Command,
+    deadline: Deadline,
+) -> Result<Output, ActionErrorKind> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| ActionErrorKind::command(command, e))?;
+    let (stdout, stdout_reader) = capture(child.stdout.take());
+    let (stderr, stderr_reader) = capture(child.stderr.take());
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                // As with `Command::output`, wait for the output to be closed
+                let _ = stdout_reader.join();
+                let _ = stderr_reader.join();
+                return Ok(Output {
+                    status,
+                    stdout: take(&stdout),
+                    stderr: take(&stderr),
+                });
+            },
+            Ok(None) if Instant::now() >= deadline.at => {
+                kill_process_group(&mut child);
+                // Something which left the process group may still hold the output open, so it isn't waited for
+                return Err(ActionErrorKind::CommandTimedOut {
+                    command: format!("{:?}", command),
+                    timeout: deadline.timeout,
+                    stdout: String::from_utf8_lossy(&take(&stdout)).into_owned(),
+                    stderr: String::from_utf8_lossy(&take(&stderr)).into_owned(),
+                });
+            },
+            Ok(None) => std::thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(ActionErrorKind::command(command, e)),
+        }
+    }
+}
+
+type Captured = Arc<Mutex<Vec<u8>>>;
+
+/// Read `pipe` as it is written to, so what was output is known even if the command never exits
+fn capture(pipe: Option<impl Read + Send + 'static>) -> (Captured, JoinHandle<()>) {
+    let captured = Captured::default();
+    let buffer = captured.clone();
+    let reader = std::thread::spawn(move || {
+        let Some(mut pipe) = pipe else {
+            return;
+        };
+        let mut chunk = [0; 8192];
+        while let Ok(read @ 1..) = pipe.read(&mut chunk) {
+            buffer
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend_from_slice(&chunk[..read]);
+        }
+    });
+    (captured, reader)
+}
+
+fn take(captured: &Captured) -> Vec<u8> {
+    std::mem::take(&mut *captured.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+fn kill_process_group(child: &mut Child) {
+    // The child leads its process group, so the group has its pid
+    let group = Pid::from_raw(child.id() as i32);
+    if let Err(e) = nix::sys::signal::killpg(group, Signal::SIGKILL) {
+        tracing::debug!("Killing process group {group}: {e}");
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kills_commands_past_the_deadline() {
+        let _timeout = TimeoutGuard::install(Some(Duration::from_millis(500)));
+        {
+            let _outer = DeadlineGuard::start();
+            let outer = deadline().unwrap();
+            {
+                // Sub-actions share the deadline of the action they are part of
+                let _inner = DeadlineGuard::start();
+                assert_eq!(deadline().unwrap().at, outer.at);
+            }
+
+            let output =
+                crate::execute_command(Command::new("sh").args(["-c", "echo done"])).unwrap();
+            assert_eq!(output.stdout, b"done\n");
+
+            let started = Instant::now();
+            let err = crate::execute_command(
+                Command::new("sh").args(["-c", "echo partial; sleep 10 & wait"]),
+            )
+            .unwrap_err();
+            assert!(started.elapsed() < Duration::from_secs(5));
+            assert!(matches!(
+                err,
+                ActionErrorKind::CommandTimedOut { ref stdout, .. } if stdout == "partial\n"
+            ));
+        }
+        assert!(deadline().is_none());
+    }
+}
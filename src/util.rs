@@ -1,6 +1,11 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
 
-use nix::unistd::{AccessFlags, access};
+use nix::unistd::{AccessFlags, Gid, Uid, access, chown};
 
 use crate::action::ActionErrorKind;
 
@@ -30,6 +35,14 @@ pub fn which(executable: impl AsRef<Path>) -> Option<PathBuf> {
     None
 }
 
+/// Whether an executable in PATH is a BusyBox applet (as on Alpine Linux), whose flags differ
+/// from the shadow-utils and Debian tools of the same name.
+pub(crate) fn is_busybox(executable: impl AsRef<Path>) -> bool {
+    which(executable)
+        .and_then(|path| std::fs::canonicalize(path).ok())
+        .is_some_and(|path| path.file_name() == Some("busybox".as_ref()))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum OnMissing {
     Ignore,
@@ -64,15 +77,137 @@ pub(crate) fn remove_dir_all(path: &Path, on_missing: OnMissing) -> std::io::Res
     }
 }
 
-pub(crate) fn write_atomic(destination: &Path, body: &str) -> Result<(), ActionErrorKind> {
-    let temp = destination.with_extension("tmp");
+/// A temporary file beside a destination, which is only moved over it once durably written
+///
+/// Writing to a temporary file then renaming it means the destination is either its previous
+/// contents or the new contents, never truncated. Before the rename the temporary file is synced
+/// to disk, and after it the directory is, so neither the contents nor the rename are lost to a
+/// power failure. The temporary file is removed if it is dropped without being persisted.
+pub(crate) struct DurableFile {
+    file: File,
+    temp_path: PathBuf,
+    destination: PathBuf,
+    persisted: bool,
+}
+
+impl DurableFile {
+    /// Create the temporary file, only readable by its owner until its mode is changed
+    pub(crate) fn create(destination: &Path) -> Result<Self, ActionErrorKind> {
+        let parent = destination.parent().unwrap_or_else(|| Path::new("/"));
+        let temp_path = parent.join(format!("nix-installer-tmp.{}", rand::random::<u32>()));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            // If we ever create setuid executables, they should only become setuid once they are
+            // owned by the appropriate user
+            .mode(0o600)
+            .open(&temp_path)
+            .map_err(|e| ActionErrorKind::Open(temp_path.clone(), e))?;
+        Ok(Self {
+            file,
+            temp_path,
+            destination: destination.to_path_buf(),
+            persisted: false,
+        })
+    }
+
+    /// The path of the temporary file, for changing its ownership or mode before persisting it
+    pub(crate) fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    pub(crate) fn write_all(&mut self, buf: &[u8]) -> Result<(), ActionErrorKind> {
+        self.file
+            .write_all(buf)
+            .map_err(|e| ActionErrorKind::Write(self.temp_path.clone(), e))
+    }
+
+    pub(crate) fn copy_from(&mut self, reader: &mut impl Read) -> Result<(), ActionErrorKind> {
+        std::io::copy(reader, &mut self.file).map_err(|e| {
+            ActionErrorKind::Copy(self.destination.clone(), self.temp_path.clone(), e)
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn set_mode(&self, mode: u32) -> Result<(), ActionErrorKind> {
+        std::fs::set_permissions(&self.temp_path, PermissionsExt::from_mode(mode))
+            .map_err(|e| ActionErrorKind::SetPermissions(mode, self.destination.clone(), e))
+    }
+
+    /// Replace the destination with the written file
+    pub(crate) fn persist(mut self) -> Result<(), ActionErrorKind> {
+        self.sync()?;
+        std::fs::rename(&self.temp_path, &self.destination).map_err(|e| {
+            ActionErrorKind::Rename(self.temp_path.clone(), self.destination.clone(), e)
+        })?;
+        self.persisted = true;
+        sync_parent(&self.destination)
+    }
+
+    /// Place the written file at the destination, failing if something is already there
+    pub(crate) fn persist_new(mut self) -> Result<(), ActionErrorKind> {
+        self.sync()?;
+        // Unlike a rename, linking never replaces an existing file
+        std::fs::hard_link(&self.temp_path, &self.destination)
+            .map_err(|e| ActionErrorKind::Open(self.destination.clone(), e))?;
+        sync_parent(&self.destination)
+    }
+
+    fn sync(&mut self) -> Result<(), ActionErrorKind> {
+        self.file
+            .sync_all()
+            .map_err(|e| ActionErrorKind::Sync(self.temp_path.clone(), e))
+    }
+}
+
+impl Drop for DurableFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
 
-    std::fs::write(&temp, body).map_err(|e| ActionErrorKind::Write(temp.to_owned(), e))?;
+/// Sync the directory containing `path`, so a file created in or renamed into it is durable
+pub(crate) fn sync_parent(path: &Path) -> Result<(), ActionErrorKind> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+    File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| ActionErrorKind::Sync(parent.to_path_buf(), e))
+}
 
-    std::fs::rename(&temp, destination)
-        .map_err(|e| ActionErrorKind::Rename(temp, destination.into(), e))?;
+/// Durably replace the contents of `destination` with `body`, keeping its owner and mode if it
+/// exists
+pub(crate) fn write_atomic(
+    destination: &Path,
+    body: impl AsRef<[u8]>,
+) -> Result<(), ActionErrorKind> {
+    let existing = match std::fs::metadata(destination) {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(ActionErrorKind::GettingMetadata(
+                destination.to_path_buf(),
+                e,
+            ));
+        },
+    };
 
-    Ok(())
+    let mut file = DurableFile::create(destination)?;
+    file.write_all(body.as_ref())?;
+    match existing {
+        Some(metadata) => {
+            chown(
+                file.temp_path(),
+                Some(Uid::from_raw(metadata.uid())),
+                Some(Gid::from_raw(metadata.gid())),
+            )
+            .map_err(|e| ActionErrorKind::Chown(destination.to_path_buf(), e))?;
+            file.set_mode(metadata.permissions().mode())?;
+        },
+        None => file.set_mode(0o644)?,
+    }
+    file.persist()
 }
 
 #[cfg(test)]
@@ -88,6 +223,36 @@ mod tests {
         assert!(path.ends_with("ls"), "path should end with 'ls'");
     }
 
+    #[test]
+    fn write_atomic_replaces_and_keeps_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fstab");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, PermissionsExt::from_mode(0o640)).unwrap();
+
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        // No temporary files are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn persist_new_keeps_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unit");
+        std::fs::write(&path, "existing").unwrap();
+
+        let mut file = DurableFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        assert!(file.persist_new().is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "existing");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
     #[test]
     fn test_which_nonexistent() {
         let result = which("this-command-definitely-does-not-exist-12345");